@@ -1,5 +1,45 @@
 use shadow_rs::SdResult;
 
 fn main() -> SdResult<()> {
+    #[cfg(feature = "grpc")]
+    tonic_build::configure()
+        .build_client(false)
+        .compile(&["proto/conmon.proto"], &["proto"])
+        .expect("compile gRPC API");
+    #[cfg(feature = "ttrpc")]
+    compile_ttrpc();
     shadow_rs::new()
 }
+
+#[cfg(feature = "ttrpc")]
+fn compile_ttrpc() {
+    use std::{env, fs, path::PathBuf};
+
+    // Keep the output apart from the gRPC one, which uses the same file names.
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR")).join("ttrpc");
+    fs::create_dir_all(&out_dir).expect("create ttrpc output directory");
+    ttrpc_codegen::Codegen::new()
+        .out_dir(&out_dir)
+        .inputs(&["proto/conmon.proto"])
+        .include("proto")
+        .rust_protobuf()
+        .customize(ttrpc_codegen::Customize {
+            async_server: true,
+            ..Default::default()
+        })
+        .run()
+        .expect("compile ttrpc API");
+
+    // The generated files start with inner attributes, which are not allowed in included
+    // modules.
+    for entry in fs::read_dir(&out_dir).expect("read ttrpc output directory") {
+        let path = entry.expect("read ttrpc output entry").path();
+        let content = fs::read_to_string(&path).expect("read generated ttrpc file");
+        let content = content
+            .lines()
+            .filter(|line| !line.starts_with("#!["))
+            .collect::<Vec<_>>()
+            .join("\n");
+        fs::write(&path, content).expect("write generated ttrpc file");
+    }
+}
@@ -0,0 +1,135 @@
+//! Admission control of the RPCs invoking the runtime, which queues them once their configured
+//! concurrency limits are reached.
+
+use crate::{method_filter, metrics::METRICS};
+use anyhow::{bail, Context, Result};
+use std::{collections::HashMap, sync::Arc};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+
+#[derive(Debug, Default)]
+/// The concurrency limits of all RPCs and of single methods.
+pub struct Admission {
+    global: Option<Arc<Semaphore>>,
+    methods: HashMap<String, Arc<Semaphore>>,
+}
+
+#[derive(Debug)]
+/// The permit of an admitted RPC, which frees its slots once dropped.
+pub struct Permit {
+    _global: Option<OwnedSemaphorePermit>,
+    _method: Option<OwnedSemaphorePermit>,
+}
+
+/// Accounts a waiting RPC in the queue depth until it gets dropped.
+struct Queued(&'static str);
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        METRICS.record_dequeued(self.0);
+    }
+}
+
+impl Admission {
+    /// Create the admission for at most `max_concurrent` RPCs at once, where 0 disables the
+    /// limit, and the `METHOD=COUNT` limits of single methods.
+    pub fn new<T: AsRef<str>>(max_concurrent: usize, method_limits: &[T]) -> Result<Self> {
+        let mut methods = HashMap::new();
+        for method_limit in method_limits.iter().map(AsRef::as_ref) {
+            let (method, limit) = method_limit.split_once('=').context(format!(
+                "method limit '{}' is not METHOD=COUNT",
+                method_limit
+            ))?;
+            method_filter::method_ids(&[method])?;
+            let limit = limit
+                .parse::<usize>()
+                .context(format!("parse limit of method {}", method))?;
+            if limit == 0 {
+                bail!("limit of method {} has to be greater than 0", method)
+            }
+            methods.insert(method.to_string(), Arc::new(Semaphore::new(limit)));
+        }
+        Ok(Self {
+            global: (max_concurrent > 0).then(|| Arc::new(Semaphore::new(max_concurrent))),
+            methods,
+        })
+    }
+
+    /// Wait until the RPC of the method is admitted. The returned permit has to be kept until
+    /// the RPC is done.
+    pub async fn acquire(&self, method: &'static str) -> Result<Permit> {
+        let method_semaphore = self.methods.get(method);
+        if self.global.is_none() && method_semaphore.is_none() {
+            return Ok(Permit {
+                _global: None,
+                _method: None,
+            });
+        }
+
+        METRICS.record_queued(method);
+        let queued = Queued(method);
+        let start = Instant::now();
+        let method_permit = match method_semaphore {
+            Some(semaphore) => Some(semaphore.clone().acquire_owned().await?),
+            None => None,
+        };
+        let global_permit = match &self.global {
+            Some(semaphore) => Some(semaphore.clone().acquire_owned().await?),
+            None => None,
+        };
+        drop(queued);
+        METRICS.record_admission_wait(method, start.elapsed());
+
+        Ok(Permit {
+            _global: global_permit,
+            _method: method_permit,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time;
+
+    #[test]
+    fn new() -> Result<()> {
+        let sut = Admission::new(2, &["createContainer=4"])?;
+        assert!(sut.global.is_some());
+        assert_eq!(sut.methods["createContainer"].available_permits(), 4);
+
+        assert!(Admission::new::<&str>(0, &[])?.global.is_none());
+        assert!(Admission::new(0, &["createContainer"]).is_err());
+        assert!(Admission::new(0, &["unknown=1"]).is_err());
+        assert!(Admission::new(0, &["createContainer=0"]).is_err());
+        assert!(Admission::new(0, &["createContainer=x"]).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn acquire() -> Result<()> {
+        let sut = Admission::new(2, &["createContainer=1"])?;
+
+        let first = sut.acquire("createContainer").await?;
+        assert!(
+            time::timeout(Duration::from_millis(10), sut.acquire("createContainer"))
+                .await
+                .is_err()
+        );
+        let exec = sut.acquire("execSyncContainer").await?;
+        assert!(
+            time::timeout(Duration::from_millis(10), sut.acquire("execSyncContainer"))
+                .await
+                .is_err()
+        );
+
+        drop(first);
+        let _second = sut.acquire("createContainer").await?;
+        drop(exec);
+        sut.acquire("version").await?;
+        Ok(())
+    }
+}
@@ -1,118 +1,982 @@
 use crate::{
-    container_io::Pipe,
+    child_reaper::kill_grandchild,
+    container_io::{Message, Pipe},
+    events::{EventKind, EVENTS},
     listener::{DefaultListener, Listener},
+    output_buffer::OutputBuffer,
+    recorder::Recorder,
 };
-use anyhow::{bail, Context, Result};
+use anyhow::{bail, format_err, Context, Result};
+use bytes::Bytes;
+use conmon_common::conmon_capnp::conmon::attach_request::{self, OverflowPolicy};
+use futures::{SinkExt, StreamExt};
+use getset::{CopyGetters, Getters, Setters};
 use nix::{
     errno::Errno,
-    sys::socket::{bind, listen, socket, AddressFamily, SockFlag, SockType, UnixAddr},
+    sys::{
+        signal::Signal,
+        socket::{bind, listen, socket, AddressFamily, SockFlag, SockType, UnixAddr},
+    },
+    unistd::{chown, Gid, Uid},
 };
 use std::{
-    convert::From,
+    convert::{From, TryFrom},
+    fs, io,
     os::unix::{
         fs::PermissionsExt,
         io::{FromRawFd, RawFd},
         net,
     },
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, ErrorKind},
-    net::{
-        unix::{OwnedReadHalf, OwnedWriteHalf},
-        UnixListener,
-    },
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ErrorKind},
+    net::{unix::UCred, UnixListener, UnixStream},
     select,
-    sync::broadcast::{self, Receiver, Sender},
-    task,
+    sync::{
+        broadcast::{self, error::RecvError, Receiver, Sender},
+        mpsc::{self, error::TrySendError},
+        Notify,
+    },
+    task, time,
 };
+use tokio_tungstenite::tungstenite::Message as WebSocketMessage;
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, debug_span, error, Instrument};
+use tokio_vsock::VsockListener;
+use tracing::{debug, debug_span, error, warn, Instrument};
+use uuid::Uuid;
+
+/// A single chunk of container output destined for attach clients, which shares its buffer
+/// between all clients.
+type Packet = (Pipe, Bytes);
+
+/// All clients connected to any attach endpoint of a container.
+type AttachClients = Arc<Mutex<Vec<AttachClient>>>;
+
+macro_rules! lock {
+    ($x:expr) => {
+        $x.lock().map_err(|e| format_err!("{:#}", e))?
+    };
+}
 
 #[derive(Debug)]
 /// A shared container attach abstraction.
 pub struct SharedContainerAttach {
-    read_half_rx: Receiver<Vec<u8>>,
-    read_half_tx: Sender<Vec<u8>>,
-    write_half_tx: Sender<(Pipe, Vec<u8>)>,
+    read_half_rx: Receiver<Message>,
+    state: AttachState,
+}
+
+#[derive(Clone, Debug)]
+/// The state shared between all attach endpoints of a container.
+struct AttachState {
+    read_half_tx: Sender<Message>,
+    clients: AttachClients,
+    stdin_clients: Arc<AtomicUsize>,
+    replay: Option<Arc<Mutex<OutputBuffer>>>,
+    metrics: Arc<AttachMetrics>,
+
+    /// The last known terminal width and height.
+    window_size: Arc<Mutex<Option<(u16, u16)>>>,
+
+    /// The paths of the listening attach sockets.
+    socket_paths: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+#[derive(Debug, Default)]
+/// The usage counters of all attach endpoints of a container.
+struct AttachMetrics {
+    /// The ID of the container, which gets published along with connect and disconnect events.
+    container_id: String,
+    active_clients: AtomicUsize,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    dropped_messages: AtomicU64,
+    disconnects: AtomicU64,
+}
+
+impl AttachMetrics {
+    fn add(counter: &AtomicU64, value: usize) {
+        counter.fetch_add(value as u64, Ordering::Relaxed);
+    }
+
+    /// Count a newly connected client.
+    fn connected(&self) {
+        self.active_clients.fetch_add(1, Ordering::Relaxed);
+        EVENTS.publish(&self.container_id, EventKind::AttachConnected);
+    }
+
+    /// Count a disconnected client.
+    fn disconnected(&self) {
+        self.active_clients.fetch_sub(1, Ordering::Relaxed);
+        Self::add(&self.disconnects, 1);
+        EVENTS.publish(&self.container_id, EventKind::AttachDisconnected);
+    }
+
+    /// Take a snapshot of the current counters.
+    fn stats(&self) -> AttachStats {
+        AttachStats {
+            active_clients: self.active_clients.load(Ordering::Relaxed) as u64,
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            dropped_messages: self.dropped_messages.load(Ordering::Relaxed),
+            disconnects: self.disconnects.load(Ordering::Relaxed),
+            queued_messages: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, CopyGetters, Debug, Default, Eq, PartialEq)]
+#[getset(get_copy = "pub")]
+/// A snapshot of the attach usage of a container.
+pub struct AttachStats {
+    /// The amount of currently connected clients.
+    active_clients: u64,
+
+    /// The amount of input bytes forwarded from clients to the container.
+    bytes_in: u64,
+
+    /// The amount of output bytes written to clients.
+    bytes_out: u64,
+
+    /// The amount of output messages dropped for slow clients.
+    dropped_messages: u64,
+
+    /// The amount of clients which disconnected.
+    disconnects: u64,
+
+    /// The amount of output messages queued for all connected clients.
+    queued_messages: u64,
 }
 
 impl Default for SharedContainerAttach {
     fn default() -> Self {
-        let (read_half_tx, read_half_rx) = broadcast::channel(1000);
-        let (write_half_tx, _) = broadcast::channel(1000);
-        Self {
-            read_half_rx,
-            read_half_tx,
-            write_half_tx,
-        }
+        Self::new(0)
     }
 }
 
 impl Clone for SharedContainerAttach {
     fn clone(&self) -> Self {
         Self {
-            read_half_rx: self.read_half_tx.subscribe(),
-            read_half_tx: self.read_half_tx.clone(),
-            write_half_tx: self.write_half_tx.clone(),
+            read_half_rx: self.state.read_half_tx.subscribe(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+#[derive(Clone, CopyGetters, Debug, Getters, Setters)]
+/// The options of a single attach endpoint.
+pub struct AttachOptions {
+    #[getset(get_copy = "pub", set = "pub")]
+    /// The policy applied if the output queue of a client is full.
+    overflow_policy: OverflowPolicy,
+
+    #[getset(get = "pub", set = "pub")]
+    /// The key sequence which detaches a client, disabled if empty.
+    detach_keys: Vec<u8>,
+
+    #[getset(get_copy = "pub", set = "pub")]
+    /// Whether the client input should be discarded instead of being forwarded to the container.
+    read_only: bool,
+
+    #[getset(get_copy = "pub", set = "pub")]
+    /// The duration without any IO after which a client gets disconnected.
+    idle_timeout: Option<Duration>,
+
+    #[getset(get_copy = "pub", set = "pub")]
+    /// The maximum amount of concurrent clients of the container.
+    max_clients: Option<usize>,
+
+    #[getset(get_copy = "pub", set = "pub")]
+    /// The maximum size of the attach packets, used for clients negotiating it.
+    packet_size: usize,
+
+    #[getset(get_copy = "pub", set = "pub")]
+    /// The size of the buffer for reading the client input, which is at least the packet size.
+    stdin_buffer_size: usize,
+
+    #[getset(get_copy = "pub", set = "pub")]
+    /// The permission bits of the attach socket.
+    socket_mode: u32,
+
+    #[getset(get_copy = "pub", set = "pub")]
+    /// The user owning the attach socket, unchanged if not set.
+    socket_uid: Option<u32>,
+
+    #[getset(get_copy = "pub", set = "pub")]
+    /// The group owning the attach socket, unchanged if not set.
+    socket_gid: Option<u32>,
+
+    #[getset(get = "pub", set = "pub")]
+    /// The peers allowed to connect, any peer is allowed if empty.
+    allowed_peers: Vec<AttachPeer>,
+
+    #[getset(get_copy = "pub", set = "pub")]
+    /// Whether to use a stream socket with length-prefixed frames instead of a seqpacket socket.
+    stream_socket: bool,
+
+    #[getset(get_copy = "pub", set = "pub")]
+    /// The process receiving the signals sent by clients, which get rejected if not set.
+    signal_pid: Option<u32>,
+
+    #[getset(get = "pub", set = "pub")]
+    /// The directory for recording every client session into an asciicast file, if set.
+    recording_dir: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+/// The credentials of a peer allowed to connect to an attach endpoint.
+pub struct AttachPeer {
+    /// The user ID of the peer, any if not set.
+    pub uid: Option<u32>,
+
+    /// The group ID of the peer, any if not set.
+    pub gid: Option<u32>,
+
+    /// The process ID of the peer, any if not set.
+    pub pid: Option<i32>,
+}
+
+impl AttachPeer {
+    /// Create a new attach peer from a capnp peer.
+    fn from(peer: attach_request::peer::Reader) -> Self {
+        Self {
+            uid: match peer.get_uid() {
+                u32::MAX => None,
+                x => Some(x),
+            },
+            gid: match peer.get_gid() {
+                u32::MAX => None,
+                x => Some(x),
+            },
+            pid: match peer.get_pid() {
+                0 => None,
+                x => Some(x as i32),
+            },
+        }
+    }
+
+    /// Check if the credentials of a connected client match the peer.
+    fn matches(&self, cred: &UCred) -> bool {
+        self.uid.map_or(true, |x| x == cred.uid())
+            && self.gid.map_or(true, |x| x == cred.gid())
+            && self.pid.map_or(true, |x| Some(x) == cred.pid())
+    }
+}
+
+impl Default for AttachOptions {
+    fn default() -> Self {
+        Self {
+            overflow_policy: OverflowPolicy::DropOldest,
+            detach_keys: vec![],
+            read_only: false,
+            idle_timeout: None,
+            max_clients: None,
+            packet_size: Attach::PACKET_BUF_SIZE,
+            stdin_buffer_size: Attach::PACKET_BUF_SIZE,
+            socket_mode: Self::DEFAULT_SOCKET_MODE,
+            socket_uid: None,
+            socket_gid: None,
+            allowed_peers: vec![],
+            stream_socket: false,
+            signal_pid: None,
+            recording_dir: None,
+        }
+    }
+}
+
+impl AttachOptions {
+    /// The minimum supported attach packet size.
+    pub const MIN_PACKET_SIZE: usize = 64;
+
+    /// The maximum supported attach packet size.
+    pub const MAX_PACKET_SIZE: usize = 128 * 1024;
+
+    /// The permission bits of the attach socket, if not specified otherwise.
+    const DEFAULT_SOCKET_MODE: u32 = 0o700;
+
+    /// Create new attach options from a capnp attach request.
+    pub fn from(req: attach_request::Reader) -> Result<Self> {
+        let packet_size = match req.get_packet_size() as usize {
+            0 => Attach::PACKET_BUF_SIZE,
+            x => Self::validate_packet_size(x)?,
+        };
+        let socket_mode = match req.get_socket_mode() {
+            0 => Self::DEFAULT_SOCKET_MODE,
+            x if x & !0o7777 != 0 => bail!("invalid attach socket mode {:o}", x),
+            x => x,
+        };
+        Ok(Self {
+            overflow_policy: req.get_overflow_policy()?,
+            detach_keys: req.get_detach_keys()?.to_vec(),
+            read_only: req.get_read_only(),
+            idle_timeout: match req.get_idle_timeout_sec() {
+                0 => None,
+                x => Some(Duration::from_secs(x)),
+            },
+            max_clients: match req.get_max_clients() {
+                0 => None,
+                x => Some(x as usize),
+            },
+            packet_size,
+            stdin_buffer_size: Attach::PACKET_BUF_SIZE,
+            socket_mode,
+            socket_uid: match req.get_socket_uid() {
+                u32::MAX => None,
+                x => Some(x),
+            },
+            socket_gid: match req.get_socket_gid() {
+                u32::MAX => None,
+                x => Some(x),
+            },
+            allowed_peers: req
+                .get_allowed_peers()?
+                .iter()
+                .map(AttachPeer::from)
+                .collect(),
+            stream_socket: req.get_stream_socket(),
+            signal_pid: None,
+            recording_dir: match req.get_recording_dir()? {
+                "" => None,
+                x => Some(x.into()),
+            },
+        })
+    }
+
+    /// The type of the attach socket.
+    fn sock_type(&self) -> SockType {
+        if self.stream_socket {
+            SockType::Stream
+        } else {
+            SockType::SeqPacket
+        }
+    }
+
+    /// The initial framing of the attach protocol.
+    fn framing(&self) -> Framing {
+        if self.stream_socket {
+            Framing::Stream
+        } else {
+            Framing::Packet(Attach::PACKET_BUF_SIZE)
         }
     }
+
+    /// Check if a client is allowed to connect by its peer credentials.
+    fn is_peer_allowed(&self, cred: &io::Result<UCred>) -> bool {
+        if self.allowed_peers.is_empty() {
+            return true;
+        }
+        match cred {
+            Ok(cred) => self.allowed_peers.iter().any(|x| x.matches(cred)),
+            Err(e) => {
+                warn!("Unable to get attach client peer credentials: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Verify that the packet size is supported.
+    pub fn validate_packet_size(packet_size: usize) -> Result<usize> {
+        if !(Self::MIN_PACKET_SIZE..=Self::MAX_PACKET_SIZE).contains(&packet_size) {
+            bail!(
+                "attach packet size {} is not between {} and {}",
+                packet_size,
+                Self::MIN_PACKET_SIZE,
+                Self::MAX_PACKET_SIZE
+            )
+        }
+        Ok(packet_size)
+    }
 }
 
 impl SharedContainerAttach {
+    /// Create a new shared container attach instance. A non-zero replay size keeps the recent
+    /// output of the container, which gets replayed to newly connected clients.
+    pub fn new(replay_size: usize) -> Self {
+        let (read_half_tx, read_half_rx) = broadcast::channel(1000);
+        Self {
+            read_half_rx,
+            state: AttachState {
+                read_half_tx,
+                clients: Default::default(),
+                stdin_clients: Default::default(),
+                replay: if replay_size > 0 {
+                    Some(Arc::new(Mutex::new(OutputBuffer::new(replay_size))))
+                } else {
+                    None
+                },
+                metrics: Default::default(),
+                window_size: Default::default(),
+                socket_paths: Default::default(),
+            },
+        }
+    }
+
+    /// Publish the connect and disconnect events of the clients for the container ID.
+    pub fn with_container_id(mut self, container_id: &str) -> Self {
+        self.state.metrics = Arc::new(AttachMetrics {
+            container_id: container_id.into(),
+            ..Default::default()
+        });
+        self
+    }
+
     /// Add a new attach endpoint to this shared container attach instance.
-    pub async fn add<T>(&mut self, socket_path: T, token: CancellationToken) -> Result<()>
+    pub async fn add<T>(
+        &mut self,
+        socket_path: T,
+        options: AttachOptions,
+        token: CancellationToken,
+    ) -> Result<()>
     where
         T: AsRef<Path>,
         PathBuf: From<T>,
     {
-        Attach::create(
-            socket_path,
-            self.read_half_tx.clone(),
-            self.write_half_tx.clone(),
-            token,
-        )
-        .context("create attach endpoint")
+        let path = PathBuf::from(socket_path);
+        Attach::create(&path, self.state.clone(), options, token)
+            .context("create attach endpoint")?;
+        lock!(self.state.socket_paths).push(path);
+        Ok(())
+    }
+
+    /// Add a new attach endpoint listening on the provided name in the abstract unix socket
+    /// namespace.
+    pub async fn add_abstract(
+        &mut self,
+        name: &str,
+        options: AttachOptions,
+        token: CancellationToken,
+    ) -> Result<()> {
+        Attach::create_abstract(name, self.state.clone(), options, token)
+            .context("create abstract attach endpoint")
+    }
+
+    /// Add a new attach endpoint serving WebSocket connections on the provided unix socket path.
+    pub async fn add_websocket(
+        &mut self,
+        socket_path: &Path,
+        options: AttachOptions,
+        token: CancellationToken,
+    ) -> Result<()> {
+        Attach::create_websocket(socket_path, self.state.clone(), options, token)
+            .context("create WebSocket attach endpoint")?;
+        lock!(self.state.socket_paths).push(socket_path.into());
+        Ok(())
+    }
+
+    /// Add a new attach endpoint listening on the provided vsock port for any CID.
+    pub async fn add_vsock(
+        &mut self,
+        port: u32,
+        options: AttachOptions,
+        token: CancellationToken,
+    ) -> Result<()> {
+        Attach::create_vsock(port, self.state.clone(), options, token)
+            .context("create vsock attach endpoint")
     }
 
     /// Read from all attach endpoints standard input and return the first result.
-    pub async fn read(&mut self) -> Result<Vec<u8>> {
-        self.read_half_rx
+    /// Returns `Message::Done` if the last client stopped writing.
+    pub async fn read(&mut self) -> Result<Message> {
+        let message = self
+            .read_half_rx
             .recv()
             .await
-            .context("receive attach message")
+            .context("receive attach message")?;
+        if let Message::Data(data) = &message {
+            AttachMetrics::add(&self.state.metrics.bytes_in, data.len());
+        }
+        Ok(message)
+    }
+
+    /// Propagate a resize of the container terminal to all recorded client sessions.
+    pub fn resize(&self, width: u16, height: u16) -> Result<()> {
+        let clients = lock!(self.state.clients);
+        *lock!(self.state.window_size) = Some((width, height));
+        for recorder in clients.iter().filter_map(|x| x.recorder.as_ref()) {
+            recorder.resize(width, height);
+        }
+        Ok(())
+    }
+
+    /// The paths of all listening attach sockets, which excludes abstract and vsock endpoints.
+    pub fn socket_paths(&self) -> Result<Vec<PathBuf>> {
+        Ok(lock!(self.state.socket_paths).clone())
+    }
+
+    /// Retrieve the usage statistics of all attach endpoints.
+    pub fn stats(&self) -> Result<AttachStats> {
+        let mut stats = self.state.metrics.stats();
+        stats.queued_messages = lock!(self.state.clients)
+            .iter()
+            .filter(|x| !x.token.is_cancelled())
+            .map(|x| x.queued() as u64)
+            .sum();
+        Ok(stats)
+    }
+
+    /// Returns whether writes would neither reach a client nor get recorded for replay, which
+    /// allows the container output to bypass the attach endpoints.
+    pub fn is_idle(&self) -> Result<bool> {
+        let clients = lock!(self.state.clients);
+        Ok(self.state.replay.is_none() && clients.iter().all(|x| x.token.is_cancelled()))
     }
 
     /// Write a buffer to all attach endpoints.
+    ///
+    /// Every client owns its own bounded queue, which means that a slow client only affects
+    /// itself, depending on its overflow policy.
     pub async fn write<T>(&mut self, pipe: Pipe, buf: T) -> Result<()>
     where
-        T: AsRef<[u8]>,
+        T: Into<Bytes>,
     {
-        if self.write_half_tx.receiver_count() > 0 {
-            self.write_half_tx
-                .send((pipe, buf.as_ref().into()))
-                .context("send data message to attach clients")?;
+        let buf = buf.into();
+        let clients = {
+            // The replay buffer is updated while holding the clients lock, which means that new
+            // clients either get the buffer replayed or the live data, but never both.
+            let clients = lock!(self.state.clients);
+            if let Some(replay) = &self.state.replay {
+                lock!(replay).push(pipe, &buf);
+            }
+            clients.clone()
+        };
+        if clients.is_empty() {
+            return Ok(());
+        }
+
+        for client in clients.iter() {
+            client.send((pipe, buf.clone())).await;
         }
+
+        lock!(self.state.clients).retain(|x| !x.token.is_cancelled());
         Ok(())
     }
 }
 
+#[derive(Clone, Debug)]
+/// The sending side of a single attach client output queue.
+struct AttachClient {
+    sender: ClientSender,
+    token: CancellationToken,
+    metrics: Arc<AttachMetrics>,
+
+    /// The amount of packets currently queued, shared with the receiving side.
+    queued: Arc<AtomicUsize>,
+
+    /// The recording of the client session, shared with the receiving side.
+    recorder: Option<Arc<Recorder>>,
+}
+
+#[derive(Clone, Debug)]
+enum ClientSender {
+    /// A bounded queue used by the block and disconnect overflow policies.
+    Queue(mpsc::Sender<Packet>, OverflowPolicy),
+
+    /// A ring buffer which overwrites the oldest packets on overflow.
+    Ring(broadcast::Sender<Packet>),
+}
+
+#[derive(Debug)]
+/// The receiving side of a single attach client output queue.
+struct ClientReceiver {
+    queue: ClientQueue,
+    metrics: Arc<AttachMetrics>,
+
+    /// The amount of packets currently queued, shared with the sending side.
+    queued: Arc<AtomicUsize>,
+
+    /// The recording of the client session, which gets all received output.
+    recorder: Option<Arc<Recorder>>,
+}
+
+#[derive(Debug)]
+enum ClientQueue {
+    Queue(mpsc::Receiver<Packet>),
+    Ring(broadcast::Receiver<Packet>),
+}
+
+impl AttachClient {
+    /// The maximum amount of packets queued per client. Has to be a power of two, because the
+    /// broadcast channel would round it up otherwise.
+    const QUEUE_SIZE: usize = 1024;
+
+    /// Register a new client and return the receiving side of its output queue. Returns `None`
+    /// if the maximum amount of clients is already connected.
+    fn register(
+        state: &AttachState,
+        options: &AttachOptions,
+        token: CancellationToken,
+    ) -> Result<Option<ClientReceiver>> {
+        let mut clients = lock!(state.clients);
+        if let Some(max_clients) = options.max_clients() {
+            if clients.iter().filter(|x| !x.token.is_cancelled()).count() >= max_clients {
+                return Ok(None);
+            }
+        }
+
+        let (sender, queue) = match options.overflow_policy() {
+            OverflowPolicy::DropOldest => {
+                let (tx, rx) = broadcast::channel(Self::QUEUE_SIZE);
+                (ClientSender::Ring(tx), ClientQueue::Ring(rx))
+            }
+            overflow_policy @ (OverflowPolicy::Block | OverflowPolicy::Disconnect) => {
+                let (tx, rx) = mpsc::channel(Self::QUEUE_SIZE);
+                (
+                    ClientSender::Queue(tx, overflow_policy),
+                    ClientQueue::Queue(rx),
+                )
+            }
+        };
+        let recorder = match options.recording_dir() {
+            Some(dir) => {
+                let path = dir.join(format!("{}.cast", Uuid::new_v4()));
+                debug!("Recording attach session to {}", path.display());
+                let window_size = *lock!(state.window_size);
+                Some(Arc::new(
+                    Recorder::new(&path, window_size).context("create session recorder")?,
+                ))
+            }
+            None => None,
+        };
+        let client = Self {
+            sender,
+            token,
+            metrics: state.metrics.clone(),
+            queued: Default::default(),
+            recorder,
+        };
+        let receiver = ClientReceiver {
+            queue,
+            metrics: state.metrics.clone(),
+            queued: client.queued.clone(),
+            recorder: client.recorder.clone(),
+        };
+
+        if let Some(replay) = &state.replay {
+            for (pipe, data) in lock!(replay).chunks() {
+                if !client.try_send((*pipe, Bytes::copy_from_slice(data))) {
+                    warn!("Unable to replay the whole output to attach client");
+                    break;
+                }
+            }
+        }
+
+        clients.push(client);
+        state.metrics.connected();
+        Ok(Some(receiver))
+    }
+
+    /// The amount of packets currently queued for the client.
+    fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Queue a packet without waiting, returns `false` if it could not be queued.
+    fn try_send(&self, packet: Packet) -> bool {
+        // The counter is increased upfront, because the receiver may get the packet immediately.
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        let queued = match &self.sender {
+            ClientSender::Ring(tx) => tx.send(packet).is_ok(),
+            ClientSender::Queue(tx, _) => tx.try_send(packet).is_ok(),
+        };
+        if !queued {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+        }
+        queued
+    }
+
+    /// Queue a packet for the client according to its overflow policy. Clients which are gone
+    /// or have to be disconnected get their token cancelled.
+    async fn send(&self, packet: Packet) {
+        if self.token.is_cancelled() {
+            return;
+        }
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        let delivered = match &self.sender {
+            ClientSender::Ring(tx) => tx.send(packet).is_ok(),
+            // Waiting for the queue applies backpressure to the container output.
+            ClientSender::Queue(tx, OverflowPolicy::Block) => select! {
+                res = tx.send(packet) => res.is_ok(),
+                _ = self.token.cancelled() => false,
+            },
+            ClientSender::Queue(tx, _) => match tx.try_send(packet) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("Disconnecting attach client because its output queue is full");
+                    AttachMetrics::add(&self.metrics.dropped_messages, 1);
+                    false
+                }
+                Err(TrySendError::Closed(_)) => false,
+            },
+        };
+        if !delivered {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            self.token.cancel();
+        }
+    }
+}
+
+impl ClientReceiver {
+    /// Receive the next packet, or `None` if the queue got closed.
+    async fn recv(&mut self) -> Option<Packet> {
+        let packet = match &mut self.queue {
+            ClientQueue::Queue(rx) => rx.recv().await,
+            ClientQueue::Ring(rx) => loop {
+                match rx.recv().await {
+                    Ok(packet) => break Some(packet),
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("Dropped {} packets for slow attach client", n);
+                        self.queued.fetch_sub(n as usize, Ordering::Relaxed);
+                        AttachMetrics::add(&self.metrics.dropped_messages, n as usize);
+                    }
+                    Err(RecvError::Closed) => break None,
+                }
+            },
+        };
+        if let Some((_, data)) = &packet {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            if let Some(recorder) = &self.recorder {
+                recorder.output(data);
+            }
+        }
+        packet
+    }
+}
+
+#[derive(Debug)]
+/// Scans the input of a client for its detach key sequence.
+struct DetachKeysScanner<'a> {
+    keys: &'a [u8],
+    matched: usize,
+
+    /// The length of the longest proper prefix of the keys, which is also a suffix of the keys
+    /// up to the index. Allows to continue with a shorter match if the next byte does not match,
+    /// like for keys overlapping their own prefix.
+    fallbacks: Vec<usize>,
+}
+
+impl<'a> DetachKeysScanner<'a> {
+    fn new(keys: &'a [u8]) -> Self {
+        let mut fallbacks = vec![0; keys.len()];
+        let mut len = 0;
+        for i in 1..keys.len() {
+            while len > 0 && keys[i] != keys[len] {
+                len = fallbacks[len - 1];
+            }
+            if keys[i] == keys[len] {
+                len += 1;
+            }
+            fallbacks[i] = len;
+        }
+        Self {
+            keys,
+            matched: 0,
+            fallbacks,
+        }
+    }
+
+    /// Filter the provided input and return the data to be forwarded, as well as if the client
+    /// requested to detach. A partially matched key sequence is held back until it either
+    /// matches or not, which means the detach keys never get forwarded to the container.
+    fn scan(&mut self, input: &[u8]) -> (Vec<u8>, bool) {
+        if self.keys.is_empty() {
+            return (input.to_vec(), false);
+        }
+
+        let mut data = Vec::with_capacity(input.len());
+        for &b in input {
+            // Forward the held back bytes which cannot be part of a match anymore.
+            while self.matched > 0 && self.keys[self.matched] != b {
+                let fallback = self.fallbacks[self.matched - 1];
+                data.extend_from_slice(&self.keys[..self.matched - fallback]);
+                self.matched = fallback;
+            }
+
+            if self.keys[self.matched] == b {
+                self.matched += 1;
+                if self.matched == self.keys.len() {
+                    return (data, true);
+                }
+            } else {
+                data.push(b);
+            }
+        }
+        (data, false)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// The optional protocol handshake of an attach client.
+///
+/// Clients initiate the handshake by sending a packet consisting of a NUL byte, the magic, the
+/// protocol version (u16) and the feature bits (u32), both big endian. Legacy clients never send
+/// such a packet, because a leading NUL byte means no stdin data for them. The server replies with
+/// a handshake packet containing the negotiated version and features, while all output written
+/// before the reply uses the legacy protocol.
+///
+/// Feature specific fields follow the feature bits in the order of the features.
+struct Handshake {
+    version: u16,
+    features: u32,
+
+    /// The maximum packet size, part of the handshake if `FEATURE_PACKET_SIZE` is set.
+    packet_size: u32,
+}
+
+impl Handshake {
+    /// The magic identifying a handshake packet.
+    const MAGIC: &'static [u8] = b"CONMONRS";
+
+    /// The latest protocol version supported by the server.
+    const VERSION: u16 = 1;
+
+    /// Negotiate the size of the attach packets in both directions.
+    const FEATURE_PACKET_SIZE: u32 = 1;
+
+    /// Clients are able to send signal packets.
+    const FEATURE_SIGNALS: u32 = 1 << 1;
+
+    /// The protocol features supported by the server.
+    const FEATURES: u32 = Self::FEATURE_PACKET_SIZE | Self::FEATURE_SIGNALS;
+
+    /// Parse a client handshake packet, returns `None` if the packet is not a handshake.
+    fn parse(packet: &[u8]) -> Option<Self> {
+        let data = packet.strip_prefix(&[0])?.strip_prefix(Self::MAGIC)?;
+        if data.len() < 6 {
+            return None;
+        }
+        let mut handshake = Self {
+            version: u16::from_be_bytes([data[0], data[1]]),
+            features: u32::from_be_bytes([data[2], data[3], data[4], data[5]]),
+            packet_size: 0,
+        };
+        if handshake.features & Self::FEATURE_PACKET_SIZE != 0 {
+            let size = data.get(6..10)?;
+            handshake.packet_size = u32::from_be_bytes([size[0], size[1], size[2], size[3]]);
+        }
+        Some(handshake)
+    }
+
+    /// Negotiate the handshake of a client with the capabilities of the server.
+    fn negotiate(self, packet_size: usize) -> Self {
+        let features = self.features & Self::FEATURES;
+        Self {
+            version: self.version.min(Self::VERSION),
+            features,
+            packet_size: if features & Self::FEATURE_PACKET_SIZE != 0 {
+                (self.packet_size as usize).clamp(AttachOptions::MIN_PACKET_SIZE, packet_size)
+                    as u32
+            } else {
+                0
+            },
+        }
+    }
+
+    /// Serialize the handshake into the provided packet type and its payload.
+    fn to_packet(self, packet_type: u8) -> Vec<u8> {
+        let mut packet = vec![packet_type];
+        packet.extend_from_slice(Self::MAGIC);
+        packet.extend_from_slice(&self.version.to_be_bytes());
+        packet.extend_from_slice(&self.features.to_be_bytes());
+        if self.features & Self::FEATURE_PACKET_SIZE != 0 {
+            packet.extend_from_slice(&self.packet_size.to_be_bytes());
+        }
+        packet
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// The framing of the attach protocol on the wire.
+enum Framing {
+    /// Zero padded packets of a fixed size on seqpacket sockets.
+    Packet(usize),
+
+    /// Frames prefixed by their packet type and big endian payload length on stream sockets.
+    Stream,
+}
+
+impl Framing {
+    /// The length of the packet type and payload length of a stream frame.
+    const HEADER_LEN: usize = 5;
+
+    /// The maximum payload length of a single packet or frame.
+    fn max_payload(self) -> usize {
+        match self {
+            Self::Packet(size) => size - 1,
+            Self::Stream => AttachOptions::MAX_PACKET_SIZE,
+        }
+    }
+
+    /// Encode the payload, which must not exceed the maximum payload length.
+    fn encode(self, packet_type: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![];
+        self.encode_into(packet_type, payload, &mut packet);
+        packet
+    }
+
+    /// Encode the payload into the provided buffer, which gets cleared before. Reusing the buffer
+    /// avoids an allocation per written packet.
+    fn encode_into(self, packet_type: u8, payload: &[u8], buf: &mut Vec<u8>) {
+        buf.clear();
+        buf.push(packet_type);
+        match self {
+            Self::Packet(size) => {
+                buf.extend_from_slice(payload);
+                buf.resize(size, 0);
+            }
+            Self::Stream => {
+                buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+                buf.extend_from_slice(payload);
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 /// Attach handles the attach socket IO of a container.
 struct Attach;
 
 impl Attach {
-    /// The size of an attach packet.
+    /// The size of an attach packet, if not negotiated otherwise.
     const PACKET_BUF_SIZE: usize = 8192;
 
-    /// The packet indicating that we're done writing.
-    const DONE_PACKET: &'static [u8; Self::PACKET_BUF_SIZE] = &[0; Self::PACKET_BUF_SIZE];
+    /// The packet type indicating that we're done writing.
+    const DONE_PACKET_TYPE: u8 = 0;
+
+    /// The packet type of client input, only used by stream sockets.
+    const STDIN_PACKET_TYPE: u8 = 1;
+
+    /// The packet type of a protocol error, followed by a message.
+    const ERROR_PACKET_TYPE: u8 = 4;
+
+    /// The packet type of the handshake reply, followed by the negotiated handshake.
+    const HANDSHAKE_PACKET_TYPE: u8 = 5;
+
+    /// The packet type of a client signal, followed by the big endian signal number (u32).
+    /// Seqpacket clients have to prefix it by a NUL byte, which legacy clients never send.
+    const SIGNAL_PACKET_TYPE: u8 = 6;
+
+    /// The WebSocket channel of the container stdin.
+    const WEBSOCKET_STDIN_CHANNEL: u8 = 0;
+
+    /// The WebSocket channel of the container stdout.
+    const WEBSOCKET_STDOUT_CHANNEL: u8 = 1;
+
+    /// The WebSocket channel of the container stderr.
+    const WEBSOCKET_STDERR_CHANNEL: u8 = 2;
+
+    /// The WebSocket channel of protocol errors.
+    const WEBSOCKET_ERROR_CHANNEL: u8 = 3;
 
     /// Create a new attach instance.
     fn create<T>(
         socket_path: T,
-        read_half_tx: Sender<Vec<u8>>,
-        write_half_tx: Sender<(Pipe, Vec<u8>)>,
+        state: AttachState,
+        options: AttachOptions,
         token: CancellationToken,
     ) -> Result<()>
     where
@@ -128,7 +992,7 @@ impl Attach {
 
         let fd = socket(
             AddressFamily::Unix,
-            SockType::SeqPacket,
+            options.sock_type(),
             SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
             None,
         )
@@ -139,18 +1003,311 @@ impl Attach {
             Listener::<DefaultListener>::default().shorten_socket_path(path)?;
         let addr = UnixAddr::new(&shortened_path).context("create socket addr")?;
         bind(fd, &addr).context("bind socket fd")?;
+        Self::set_socket_permissions(path, &options)?;
 
-        let metadata = path.metadata()?;
-        let mut permissions = metadata.permissions();
-        permissions.set_mode(0o700);
+        Self::listen(fd, Some(path.into()), state, options, token)
+    }
 
-        listen(fd, 10).context("listen on socket fd")?;
+    /// Apply the configured mode and owner to the attach socket.
+    fn set_socket_permissions(path: &Path, options: &AttachOptions) -> Result<()> {
+        fs::set_permissions(path, fs::Permissions::from_mode(options.socket_mode()))
+            .context("set attach socket permissions")?;
+        if options.socket_uid().is_some() || options.socket_gid().is_some() {
+            chown(
+                path,
+                options.socket_uid().map(Uid::from_raw),
+                options.socket_gid().map(Gid::from_raw),
+            )
+            .context("change attach socket owner")?;
+        }
+        Ok(())
+    }
 
-        task::spawn(
-            async move {
-                if let Err(e) = Self::start(fd, read_half_tx, write_half_tx, token).await {
-                    error!("Attach failure: {:#}", e);
-                }
+    /// Remove the socket path of a closed listener.
+    fn remove_socket(path: &Path, state: &AttachState) {
+        match fs::remove_file(path) {
+            Ok(()) => debug!("Removed attach socket: {}", path.display()),
+            Err(e) => warn!("Unable to remove attach socket {}: {}", path.display(), e),
+        }
+        if let Ok(mut socket_paths) = state.socket_paths.lock() {
+            socket_paths.retain(|x| x != path);
+        }
+    }
+
+    /// Create a new attach instance serving WebSocket connections on a unix stream socket.
+    fn create_websocket(
+        path: &Path,
+        state: AttachState,
+        options: AttachOptions,
+        token: CancellationToken,
+    ) -> Result<()> {
+        debug!("Creating WebSocket attach socket: {}", path.display());
+
+        if path.exists() {
+            bail!("Attach socket path already exists: {}", path.display())
+        }
+
+        let listener = Listener::<DefaultListener>::default().bind_long_path(path)?;
+        Self::set_socket_permissions(path, &options)?;
+        let socket_path = path.to_path_buf();
+
+        task::spawn(
+            async move {
+                Self::start_websocket(listener, &socket_path, state, options, token).await;
+            }
+            .instrument(debug_span!("attach_websocket")),
+        );
+
+        Ok(())
+    }
+
+    /// Accept WebSocket connections until the token gets cancelled.
+    async fn start_websocket(
+        listener: UnixListener,
+        socket_path: &Path,
+        state: AttachState,
+        options: AttachOptions,
+        token: CancellationToken,
+    ) {
+        debug!("Start listening on WebSocket attach socket");
+        loop {
+            select! {
+                res = listener.accept() => match res {
+                    Ok((stream, _)) => {
+                        debug!("Got new WebSocket attach connection");
+                        let cred = stream.peer_cred();
+                        if !options.is_peer_allowed(&cred) {
+                            warn!("Rejecting unauthorized WebSocket attach client: {:?}", cred);
+                            continue;
+                        }
+                        let state = state.clone();
+                        let options = options.clone();
+                        let client_token = token.child_token();
+                        task::spawn(
+                            async move {
+                                if let Err(e) =
+                                    Self::serve_websocket(stream, &state, &options, client_token)
+                                        .await
+                                {
+                                    error!("WebSocket attach failure: {:#}", e);
+                                }
+                            }
+                            .instrument(debug_span!("websocket_client")),
+                        );
+                    }
+                    Err(e) => error!("Unable to accept WebSocket attach connection: {}", e),
+                },
+                _ = token.cancelled() => {
+                    debug!("Exiting because token cancelled");
+                    drop(listener);
+                    Self::remove_socket(socket_path, &state);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Bridge a single WebSocket client to the container. Every binary message is prefixed by its
+    /// channel, which are the same as used by the Kubernetes streaming protocol.
+    async fn serve_websocket(
+        stream: UnixStream,
+        state: &AttachState,
+        options: &AttachOptions,
+        token: CancellationToken,
+    ) -> Result<()> {
+        let mut ws = tokio_tungstenite::accept_async(stream)
+            .await
+            .context("accept WebSocket connection")?;
+
+        let mut rx = match AttachClient::register(state, options, token.clone())? {
+            Some(rx) => rx,
+            None => {
+                warn!("Rejecting WebSocket attach client because of too many clients");
+                let mut message = vec![Self::WEBSOCKET_ERROR_CHANNEL];
+                message.extend_from_slice(b"too many attach clients");
+                ws.send(WebSocketMessage::Binary(message))
+                    .await
+                    .context("send error message")?;
+                return ws.close(None).await.context("close WebSocket connection");
+            }
+        };
+
+        let activity = Arc::new(Notify::new());
+        if let Some(timeout) = options.idle_timeout() {
+            task::spawn(
+                Self::idle_watchdog(timeout, activity.clone(), token.clone())
+                    .instrument(debug_span!("idle_watchdog")),
+            );
+        }
+
+        // Read-only clients do not count as stdin writers.
+        let stdin_tx = if options.read_only() {
+            None
+        } else {
+            state.stdin_clients.fetch_add(1, Ordering::SeqCst);
+            Some(&state.read_half_tx)
+        };
+
+        let mut scanner = DetachKeysScanner::new(options.detach_keys());
+        let mut detached = false;
+        let res = loop {
+            select! {
+                message = ws.next() => {
+                    let data = match message {
+                        Some(Ok(WebSocketMessage::Binary(data))) => data,
+                        Some(Ok(WebSocketMessage::Close(_))) | None => {
+                            debug!("Stopping because WebSocket client closed the connection");
+                            break Ok(());
+                        }
+                        Some(Ok(_)) => continue,
+                        Some(Err(e)) => break Err(e).context("read WebSocket message"),
+                    };
+                    activity.notify_one();
+                    match data.split_first() {
+                        Some((&Self::WEBSOCKET_STDIN_CHANNEL, input)) => {
+                            let (input, detach) = scanner.scan(input);
+                            match stdin_tx {
+                                Some(tx) if !input.is_empty() => {
+                                    if let Some(recorder) = &rx.recorder {
+                                        recorder.input(&input);
+                                    }
+                                    if let Err(e) = tx.send(Message::Data(input.into())) {
+                                        break Err(e).context("send data message");
+                                    }
+                                }
+                                Some(_) => {}
+                                None => debug!("Discarding input of read-only client"),
+                            }
+                            if detach {
+                                debug!("Detaching client because of detach keys");
+                                detached = true;
+                                break Ok(());
+                            }
+                        }
+                        _ => debug!("Ignoring WebSocket message of unknown channel"),
+                    }
+                }
+                packet = rx.recv() => {
+                    let (pipe, buf) = match packet {
+                        Some(packet) => packet,
+                        None => {
+                            debug!("Exiting because output queue closed");
+                            break Ok(());
+                        }
+                    };
+                    let mut message = Vec::with_capacity(buf.len() + 1);
+                    message.push(match pipe {
+                        Pipe::StdOut => Self::WEBSOCKET_STDOUT_CHANNEL,
+                        Pipe::StdErr => Self::WEBSOCKET_STDERR_CHANNEL,
+                    });
+                    message.extend_from_slice(&buf);
+                    if let Err(e) = ws.send(WebSocketMessage::Binary(message)).await {
+                        break Err(e).context("send WebSocket message");
+                    }
+                    debug!("Wrote {} message to WebSocket client", pipe);
+                    activity.notify_one();
+                    AttachMetrics::add(&state.metrics.bytes_out, buf.len());
+                }
+                _ = token.cancelled() => {
+                    debug!("Exiting because token cancelled");
+                    break Ok(());
+                }
+            }
+        };
+
+        // Cancelling the client token removes it from the shared clients.
+        token.cancel();
+        state.metrics.disconnected();
+
+        if let Some(tx) = stdin_tx {
+            // The container stdin gets closed if the last client stops writing, except it
+            // intentionally detached.
+            if state.stdin_clients.fetch_sub(1, Ordering::SeqCst) == 1 && !detached {
+                debug!("Last attach client stopped writing");
+                // The send fails if nobody is reading stdin any more.
+                let _ = tx.send(Message::Done);
+            }
+        }
+
+        if let Err(e) = ws.close(None).await {
+            debug!("Unable to close WebSocket connection: {}", e);
+        }
+        res
+    }
+
+    /// Create a new attach instance listening on an abstract unix socket.
+    fn create_abstract(
+        name: &str,
+        state: AttachState,
+        options: AttachOptions,
+        token: CancellationToken,
+    ) -> Result<()> {
+        debug!("Creating abstract attach socket: {}", name);
+
+        if name.is_empty() {
+            bail!("Abstract attach socket name is empty")
+        }
+
+        let fd = socket(
+            AddressFamily::Unix,
+            options.sock_type(),
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            None,
+        )
+        .context("create socket")?;
+
+        let addr =
+            UnixAddr::new_abstract(name.as_bytes()).context("create abstract socket addr")?;
+        bind(fd, &addr).context("bind abstract socket fd")?;
+
+        Self::listen(fd, None, state, options, token)
+    }
+
+    /// Create a new attach instance listening on a vsock port. Vsock endpoints always use the
+    /// framing of stream sockets and cannot restrict clients by their peer credentials.
+    fn create_vsock(
+        port: u32,
+        state: AttachState,
+        mut options: AttachOptions,
+        token: CancellationToken,
+    ) -> Result<()> {
+        debug!("Creating attach vsock on port {}", port);
+
+        if !options.allowed_peers.is_empty() {
+            bail!("vsock attach endpoints do not support allowed peers")
+        }
+        options.set_stream_socket(true);
+
+        let listener = VsockListener::bind(libc::VMADDR_CID_ANY, port)
+            .context(format!("bind vsock to port {}", port))?;
+
+        task::spawn(
+            async move {
+                if let Err(e) = Self::start_vsock(listener, state, options, token).await {
+                    error!("Attach failure: {:#}", e);
+                }
+            }
+            .instrument(debug_span!("attach")),
+        );
+
+        Ok(())
+    }
+
+    /// Listen on the bound socket file descriptor and spawn the connection handling.
+    fn listen(
+        fd: RawFd,
+        socket_path: Option<PathBuf>,
+        state: AttachState,
+        options: AttachOptions,
+        token: CancellationToken,
+    ) -> Result<()> {
+        listen(fd, 10).context("listen on socket fd")?;
+
+        task::spawn(
+            async move {
+                if let Err(e) = Self::start(fd, socket_path, state, options, token).await {
+                    error!("Attach failure: {:#}", e);
+                }
             }
             .instrument(debug_span!("attach")),
         );
@@ -158,72 +1315,250 @@ impl Attach {
         Ok(())
     }
 
+    /// Accept connections until the token gets cancelled, which closes the listener and removes
+    /// the socket path, if any.
     async fn start(
         fd: RawFd,
-        read_half_tx: Sender<Vec<u8>>,
-        write_half_tx: Sender<(Pipe, Vec<u8>)>,
+        socket_path: Option<PathBuf>,
+        state: AttachState,
+        options: AttachOptions,
         token: CancellationToken,
     ) -> Result<()> {
         debug!("Start listening on attach socket");
         let listener = UnixListener::from_std(unsafe { net::UnixListener::from_raw_fd(fd) })?;
         loop {
-            match listener.accept().await {
+            let res = select! {
+                res = listener.accept() => res,
+                _ = token.cancelled() => {
+                    debug!("Stop listening because token cancelled");
+                    // Connected clients get their done packet from their write loop, because
+                    // their tokens are children of the cancelled one.
+                    drop(listener);
+                    if let Some(path) = &socket_path {
+                        Self::remove_socket(path, &state);
+                    }
+                    return Ok(());
+                }
+            };
+            match res {
                 Ok((stream, _)) => {
                     debug!("Got new attach stream connection");
+                    let cred = stream.peer_cred();
                     let (read, write) = stream.into_split();
 
-                    let read_half_tx_clone = read_half_tx.clone();
-                    let token_clone = token.clone();
-                    task::spawn(
-                        async move {
-                            if let Err(e) =
-                                Self::read_loop(read, read_half_tx_clone, token_clone).await
-                            {
-                                error!("Attach read loop failure: {:#}", e);
-                            }
-                        }
-                        .instrument(debug_span!("read_loop")),
-                    );
-
-                    let write_half_rx = write_half_tx.subscribe();
-                    let token_clone = token.clone();
-                    task::spawn(
-                        async move {
-                            if let Err(e) =
-                                Self::write_loop(write, write_half_rx, token_clone).await
-                            {
-                                error!("Attach write loop failure: {:#}", e);
-                            }
-                        }
-                        .instrument(debug_span!("write_loop")),
-                    );
+                    if !options.is_peer_allowed(&cred) {
+                        warn!("Rejecting unauthorized attach client: {:?}", cred);
+                        Self::reject(write, options.framing(), "attach client not authorized")
+                            .await;
+                        continue;
+                    }
+                    // A failing client must not stop serving the other ones.
+                    if let Err(e) = Self::serve(read, write, &state, &options, &token).await {
+                        error!("Unable to serve attach client: {:#}", e);
+                    }
                 }
                 Err(e) => error!("Unable to accept attach stream: {}", e),
             }
         }
     }
 
-    async fn read_loop(
-        mut read_half: OwnedReadHalf,
-        tx: Sender<Vec<u8>>,
+    /// Accept vsock connections until the token gets cancelled, which closes the listener.
+    async fn start_vsock(
+        mut listener: VsockListener,
+        state: AttachState,
+        options: AttachOptions,
         token: CancellationToken,
     ) -> Result<()> {
+        debug!("Start listening on attach vsock");
         loop {
-            let mut buf = vec![0; Self::PACKET_BUF_SIZE];
+            let res = select! {
+                res = listener.accept() => res,
+                _ = token.cancelled() => {
+                    debug!("Stop listening because token cancelled");
+                    return Ok(());
+                }
+            };
+            match res {
+                Ok((stream, addr)) => {
+                    debug!("Got new attach vsock connection from {:?}", addr);
+                    let (read, write) = stream.split();
+                    if let Err(e) = Self::serve(read, write, &state, &options, &token).await {
+                        error!("Unable to serve attach vsock client: {:#}", e);
+                    }
+                }
+                Err(e) => error!("Unable to accept attach vsock: {}", e),
+            }
+        }
+    }
+
+    /// Register the accepted client and spawn its read and write loops.
+    async fn serve<R, W>(
+        read: R,
+        write: W,
+        state: &AttachState,
+        options: &AttachOptions,
+        token: &CancellationToken,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        // The client token is used to disconnect a single client.
+        let client_token = token.child_token();
+        let client_rx = match AttachClient::register(state, options, client_token.clone())? {
+            Some(rx) => rx,
+            None => {
+                warn!("Rejecting attach client because of too many clients");
+                Self::reject(write, options.framing(), "too many attach clients").await;
+                return Ok(());
+            }
+        };
+
+        // Every read and write of the client resets its idle timer.
+        let activity = Arc::new(Notify::new());
+        if let Some(timeout) = options.idle_timeout() {
+            task::spawn(
+                Self::idle_watchdog(timeout, activity.clone(), client_token.clone())
+                    .instrument(debug_span!("idle_watchdog")),
+            );
+        }
+
+        // Read-only clients do not count as stdin writers.
+        let stdin_tx = if options.read_only() {
+            None
+        } else {
+            state.stdin_clients.fetch_add(1, Ordering::SeqCst);
+            Some(state.read_half_tx.clone())
+        };
+        let token_clone = client_token.clone();
+        let stdin_clients_clone = state.stdin_clients.clone();
+        let options_clone = options.clone();
+        let activity_clone = activity.clone();
+        let recorder = client_rx.recorder.clone();
+        let (handshake_tx, handshake_rx) = mpsc::channel(1);
+        task::spawn(
+            async move {
+                let res = if options_clone.stream_socket() {
+                    Self::stream_read_loop(
+                        read,
+                        stdin_tx.as_ref(),
+                        &options_clone,
+                        recorder.as_deref(),
+                        &activity_clone,
+                        token_clone,
+                    )
+                    .await
+                } else {
+                    Self::read_loop(
+                        read,
+                        stdin_tx.as_ref(),
+                        handshake_tx,
+                        &options_clone,
+                        recorder.as_deref(),
+                        &activity_clone,
+                        token_clone,
+                    )
+                    .await
+                };
+                let detached = res.unwrap_or_else(|e| {
+                    error!("Attach read loop failure: {:#}", e);
+                    false
+                });
+                let tx = match stdin_tx {
+                    Some(tx) => tx,
+                    None => return,
+                };
+                // The container stdin gets closed if the last client stops writing,
+                // except it intentionally detached.
+                if stdin_clients_clone.fetch_sub(1, Ordering::SeqCst) == 1 && !detached {
+                    debug!("Last attach client stopped writing");
+                    // The send fails if nobody is reading stdin any more.
+                    let _ = tx.send(Message::Done);
+                }
+            }
+            .instrument(debug_span!("read_loop")),
+        );
+
+        let metrics = state.metrics.clone();
+        let framing = options.framing();
+        task::spawn(
+            async move {
+                if let Err(e) = Self::write_loop(
+                    write,
+                    client_rx,
+                    handshake_rx,
+                    framing,
+                    &activity,
+                    &metrics,
+                    client_token,
+                )
+                .await
+                {
+                    error!("Attach write loop failure: {:#}", e);
+                }
+                metrics.disconnected();
+            }
+            .instrument(debug_span!("write_loop")),
+        );
+        Ok(())
+    }
+
+    /// Forward the client input until it stops writing. The input gets discarded if no sender is
+    /// provided. Returns `true` if the client detached by using the detach keys.
+    async fn read_loop<R: AsyncRead + Unpin>(
+        mut read_half: R,
+        tx: Option<&Sender<Message>>,
+        handshake_tx: mpsc::Sender<Handshake>,
+        options: &AttachOptions,
+        recorder: Option<&Recorder>,
+        activity: &Notify,
+        token: CancellationToken,
+    ) -> Result<bool> {
+        let packet_size = options.packet_size();
+        let mut scanner = DetachKeysScanner::new(options.detach_keys());
+        let mut handshake_tx = Some(handshake_tx);
+        // Legacy clients always use the default packet size, which gets truncated by smaller
+        // stdin buffers.
+        let buf_size = packet_size.max(options.stdin_buffer_size());
+        loop {
+            let mut buf = vec![0; buf_size];
             select! {
                 n = read_half.read(&mut buf) => {
                     match n {
                         Ok(n) if n > 0 => {
+                            if let Some(handshake) = Handshake::parse(&buf[..n]) {
+                                activity.notify_one();
+                                // Only the first handshake of a client is considered.
+                                if let Some(handshake_tx) = handshake_tx.take() {
+                                    debug!("Got attach handshake: {:?}", handshake);
+                                    let _ = handshake_tx.send(handshake.negotiate(packet_size)).await;
+                                }
+                                continue;
+                            }
+                            if let Some(payload) =
+                                buf[..n].strip_prefix(&[0, Self::SIGNAL_PACKET_TYPE])
+                            {
+                                activity.notify_one();
+                                Self::forward_signal(payload, tx.is_some(), options);
+                                continue;
+                            }
                             if let Some(first_zero_idx) = buf.iter().position(|&x| x == 0) {
                                 buf.resize(first_zero_idx, 0);
                             }
                             debug!("Read {} stdin bytes from client", buf.len());
-                            tx.send(buf).context("send data message")?;
+                            activity.notify_one();
+                            if Self::forward_input(&mut scanner, tx, recorder, &buf, &token)? {
+                                return Ok(true);
+                            }
+                        }
+                        Ok(_) => {
+                            debug!("Stopping read loop because client closed stdin");
+                            return Ok(false);
                         }
                         Err(e) => match Errno::from_i32(e.raw_os_error().context("get OS error")?) {
                             Errno::EIO => {
                                 debug!("Stopping read loop because of IO error");
-                                return Ok(());
+                                return Ok(false);
                             }
                             Errno::EBADF => {
                                 return Err(Errno::EBADFD.into());
@@ -237,55 +1572,231 @@ impl Attach {
                                 e.raw_os_error().context("get OS error")?
                             ),
                         },
-                        _ => {}
                     }
                 }
                 _ = token.cancelled() => {
                     debug!("Exiting because token cancelled");
-                    return Ok(());
+                    return Ok(false);
                 }
             }
         }
     }
 
-    async fn write_loop(
-        mut write_half: OwnedWriteHalf,
-        mut rx: Receiver<(Pipe, Vec<u8>)>,
+    /// Forward the input of a stream socket client until it stops writing, like `read_loop`.
+    async fn stream_read_loop<R: AsyncRead + Unpin>(
+        mut read_half: R,
+        tx: Option<&Sender<Message>>,
+        options: &AttachOptions,
+        recorder: Option<&Recorder>,
+        activity: &Notify,
+        token: CancellationToken,
+    ) -> Result<bool> {
+        let mut scanner = DetachKeysScanner::new(options.detach_keys());
+        loop {
+            select! {
+                frame = Self::read_frame(&mut read_half) => {
+                    let data = match frame? {
+                        Some((Self::STDIN_PACKET_TYPE, data)) => data,
+                        Some((Self::SIGNAL_PACKET_TYPE, payload)) => {
+                            activity.notify_one();
+                            Self::forward_signal(&payload, tx.is_some(), options);
+                            continue;
+                        }
+                        Some((packet_type, _)) => {
+                            debug!("Ignoring frame of unknown packet type {}", packet_type);
+                            continue;
+                        }
+                        None => {
+                            debug!("Stopping read loop because client closed stdin");
+                            return Ok(false);
+                        }
+                    };
+                    debug!("Read {} stdin bytes from client", data.len());
+                    activity.notify_one();
+                    if Self::forward_input(&mut scanner, tx, recorder, &data, &token)? {
+                        return Ok(true);
+                    }
+                }
+                _ = token.cancelled() => {
+                    debug!("Exiting because token cancelled");
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    /// Read a single frame from a stream socket, returns `None` if the client closed the
+    /// connection.
+    async fn read_frame<R: AsyncRead + Unpin>(read_half: &mut R) -> Result<Option<(u8, Vec<u8>)>> {
+        let mut header = [0; Framing::HEADER_LEN];
+        match read_half.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e).context("read frame header"),
+        }
+        let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        if len > Framing::Stream.max_payload() {
+            bail!(
+                "frame length {} exceeds the maximum of {}",
+                len,
+                Framing::Stream.max_payload()
+            )
+        }
+        let mut data = vec![0; len];
+        read_half
+            .read_exact(&mut data)
+            .await
+            .context("read frame payload")?;
+        Ok(Some((header[0], data)))
+    }
+
+    /// Forward the client input to the container. Returns `true` if the client detached, which
+    /// cancels the client token.
+    fn forward_input(
+        scanner: &mut DetachKeysScanner,
+        tx: Option<&Sender<Message>>,
+        recorder: Option<&Recorder>,
+        input: &[u8],
+        token: &CancellationToken,
+    ) -> Result<bool> {
+        let (data, detached) = scanner.scan(input);
+        match tx {
+            Some(tx) if !data.is_empty() => {
+                if let Some(recorder) = recorder {
+                    recorder.input(&data);
+                }
+                tx.send(Message::Data(data.into()))
+                    .context("send data message")?;
+            }
+            Some(_) => {}
+            None => debug!("Discarding input of read-only client"),
+        }
+        if detached {
+            debug!("Detaching client because of detach keys");
+            // Cancelling the client token disconnects the write loop, too.
+            token.cancel();
+        }
+        Ok(detached)
+    }
+
+    /// Send the signal of a client to the container process. Signals of read-only clients or
+    /// endpoints without a signal process are discarded.
+    fn forward_signal(payload: &[u8], writable: bool, options: &AttachOptions) {
+        let pid = match options.signal_pid() {
+            Some(pid) if writable => pid,
+            _ => {
+                warn!("Discarding signal of attach client which is not allowed to send signals");
+                return;
+            }
+        };
+        let signal = match payload.get(..4) {
+            Some(x) => i32::from_be_bytes([x[0], x[1], x[2], x[3]]),
+            None => {
+                warn!("Discarding truncated signal packet of attach client");
+                return;
+            }
+        };
+        match Signal::try_from(signal) {
+            Ok(signal) => {
+                debug!("Forwarding signal {} of attach client to {}", signal, pid);
+                kill_grandchild(pid, signal);
+            }
+            Err(e) => warn!(
+                "Discarding invalid signal {} of attach client: {}",
+                signal, e
+            ),
+        }
+    }
+
+    /// Send an error packet to a client and disconnect it afterwards.
+    async fn reject<W: AsyncWrite + Unpin>(mut write_half: W, framing: Framing, message: &str) {
+        let packet = framing.encode(Self::ERROR_PACKET_TYPE, message.as_bytes());
+        if let Err(e) = write_half.write_all(&packet).await {
+            debug!("Unable to write error packet to client: {:#}", e);
+        }
+    }
+
+    /// Disconnect the client if no activity got reported within the timeout.
+    async fn idle_watchdog(timeout: Duration, activity: Arc<Notify>, token: CancellationToken) {
+        loop {
+            select! {
+                _ = activity.notified() => {}
+                _ = time::sleep(timeout) => {
+                    warn!("Disconnecting attach client after being idle for {:?}", timeout);
+                    token.cancel();
+                    return;
+                }
+                _ = token.cancelled() => return,
+            }
+        }
+    }
+
+    async fn write_loop<W: AsyncWrite + Unpin>(
+        mut write_half: W,
+        mut rx: ClientReceiver,
+        mut handshake_rx: mpsc::Receiver<Handshake>,
+        mut framing: Framing,
+        activity: &Notify,
+        metrics: &AttachMetrics,
         token: CancellationToken,
     ) -> Result<()> {
+        let mut frame = vec![];
         loop {
             select! {
+                // Handshakes are only supported by seqpacket sockets.
+                Some(handshake) = handshake_rx.recv() => {
+                    let mut packet = handshake.to_packet(Self::HANDSHAKE_PACKET_TYPE);
+                    if let Framing::Packet(packet_size) = framing {
+                        packet.resize(packet_size, 0);
+                    }
+                    write_half
+                        .write_all(&packet)
+                        .await
+                        .context("write handshake packet")?;
+                    debug!("Wrote handshake packet to client");
+                    activity.notify_one();
+
+                    if handshake.features & Handshake::FEATURE_PACKET_SIZE != 0 {
+                        framing = Framing::Packet(handshake.packet_size as usize);
+                        debug!("Using negotiated packet size {}", handshake.packet_size);
+                    }
+                }
                 res = rx.recv() => {
-                    let (pipe, buf) = res?;
-                    let packets = buf
-                        .chunks(Self::PACKET_BUF_SIZE - 1)
-                        .map(|x| {
-                            let mut y = x.to_vec();
-                            let p = match pipe {
-                                Pipe::StdOut => 2,
-                                Pipe::StdErr => 3,
-                            };
-                            y.insert(0, p);
-                            y.resize(Self::PACKET_BUF_SIZE, 0);
-                            y
-                        })
-                        .collect::<Vec<_>>();
-
-                    let len = packets.len() - 1;
-                    for (idx, packet) in packets.iter().enumerate() {
-                        match write_half.write(packet).await {
+                    let (pipe, buf) = match res {
+                        Some(packet) => packet,
+                        None => {
+                            debug!("Exiting because output queue closed");
+                            return Ok(());
+                        }
+                    };
+                    let p = match pipe {
+                        Pipe::StdOut => 2,
+                        Pipe::StdErr => 3,
+                    };
+                    let chunks = buf.chunks(framing.max_payload());
+
+                    let len = chunks.len().saturating_sub(1);
+                    for (idx, chunk) in chunks.enumerate() {
+                        framing.encode_into(p, chunk, &mut frame);
+                        match write_half.write_all(&frame).await {
                             Ok(_) => {
-                                debug!("Wrote {} packet {}/{} to client", pipe, idx, len)
+                                debug!("Wrote {} packet {}/{} to client", pipe, idx, len);
+                                activity.notify_one();
+                                AttachMetrics::add(&metrics.bytes_out, chunk.len());
+                            }
+                            Err(ref e) if e.kind() == ErrorKind::BrokenPipe => {
+                                debug!("Exiting because client closed the connection");
+                                token.cancel();
+                                return Ok(());
                             }
-                            Err(ref e) if e.kind() == ErrorKind::WouldBlock => continue,
-                            Err(ref e) if e.kind() == ErrorKind::BrokenPipe => break,
                             Err(e) => bail!("unable to write packet {}/{}: {:#}", idx, len, e),
                         }
                     }
                 }
                 _ = token.cancelled() => {
                     debug!("Exiting because token cancelled");
-                    match write_half.write(Self::DONE_PACKET).await {
+                    let packet = framing.encode(Self::DONE_PACKET_TYPE, &[]);
+                    match write_half.write_all(&packet).await {
                         Ok(_) => {
                             debug!("Wrote done packet to client")
                         }
@@ -298,3 +1809,909 @@ impl Attach {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::socket::connect;
+    use tempfile::tempdir;
+    use tokio::net::UnixStream;
+
+    fn connect_client(path: &Path) -> Result<UnixStream> {
+        connect_addr(&UnixAddr::new(path)?)
+    }
+
+    fn connect_addr(addr: &UnixAddr) -> Result<UnixStream> {
+        let fd = socket(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        connect(fd, addr)?;
+        Ok(UnixStream::from_std(unsafe {
+            net::UnixStream::from_raw_fd(fd)
+        })?)
+    }
+
+    fn new_client(
+        sut: &SharedContainerAttach,
+        overflow_policy: OverflowPolicy,
+    ) -> Result<(ClientReceiver, CancellationToken)> {
+        let token = CancellationToken::new();
+        let mut options = AttachOptions::default();
+        options.set_overflow_policy(overflow_policy);
+        let rx = AttachClient::register(&sut.state, &options, token.clone())?
+            .context("client not registered")?;
+        Ok((rx, token))
+    }
+
+    async fn fill_queue(sut: &mut SharedContainerAttach) -> Result<()> {
+        for i in 0..AttachClient::QUEUE_SIZE {
+            sut.write(Pipe::StdOut, i.to_string()).await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_drop_oldest() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let (mut rx, token) = new_client(&sut, OverflowPolicy::DropOldest)?;
+
+        fill_queue(&mut sut).await?;
+        sut.write(Pipe::StdErr, "new").await?;
+
+        let (pipe, data) = rx.recv().await.context("no packet")?;
+        assert!(matches!(pipe, Pipe::StdOut));
+        assert_eq!(&data[..], b"1");
+        assert!(!token.is_cancelled());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_disconnect() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let (mut slow_rx, slow_token) = new_client(&sut, OverflowPolicy::Disconnect)?;
+        let (mut rx, token) = new_client(&sut, OverflowPolicy::DropOldest)?;
+
+        fill_queue(&mut sut).await?;
+        assert!(!slow_token.is_cancelled());
+
+        sut.write(Pipe::StdOut, "new").await?;
+        assert!(slow_token.is_cancelled());
+        assert!(!token.is_cancelled());
+        assert_eq!(sut.state.clients.lock().unwrap().len(), 1);
+
+        // Already queued data is still available for the disconnected client
+        let (_, data) = slow_rx.recv().await.context("no packet")?;
+        assert_eq!(&data[..], b"0");
+
+        let (_, data) = rx.recv().await.context("no packet")?;
+        assert_eq!(&data[..], b"1");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_block() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let (mut rx, token) = new_client(&sut, OverflowPolicy::Block)?;
+
+        fill_queue(&mut sut).await?;
+
+        let mut sut_clone = sut.clone();
+        let write = task::spawn(async move { sut_clone.write(Pipe::StdOut, "new").await });
+        task::yield_now().await;
+        assert!(!write.is_finished());
+
+        let (_, data) = rx.recv().await.context("no packet")?;
+        assert_eq!(&data[..], b"0");
+        write.await??;
+        assert!(!token.is_cancelled());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_removes_closed_clients() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let (rx, _) = new_client(&sut, OverflowPolicy::Block)?;
+        drop(rx);
+
+        sut.write(Pipe::StdOut, "data").await?;
+        assert!(sut.state.clients.lock().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn is_idle() -> Result<()> {
+        let sut = SharedContainerAttach::default();
+        assert!(sut.is_idle()?);
+
+        let (_rx, token) = new_client(&sut, OverflowPolicy::Block)?;
+        assert!(!sut.is_idle()?);
+
+        token.cancel();
+        assert!(sut.is_idle()?);
+
+        assert!(!SharedContainerAttach::new(1024).is_idle()?);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn read_done_after_last_client_closed() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let path = dir.path().join("attach");
+        sut.add(&path, AttachOptions::default(), token.clone())
+            .await?;
+
+        let mut first = connect_client(&path)?;
+        let mut second = connect_client(&path)?;
+
+        first.write_all(b"hello").await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"hello"))
+        );
+
+        second.write_all(b"world").await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"world"))
+        );
+
+        first.shutdown().await?;
+        second.shutdown().await?;
+        assert_eq!(sut.read().await?, Message::Done);
+
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn socket_paths() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let path = dir.path().join("attach");
+        sut.add(&path, AttachOptions::default(), token.clone())
+            .await?;
+        assert!(sut
+            .add(&path, AttachOptions::default(), token.clone())
+            .await
+            .is_err());
+        assert_eq!(sut.socket_paths()?, vec![path.clone()]);
+
+        token.cancel();
+        while !sut.socket_paths()?.is_empty() {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn read_detach_keys() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let path = dir.path().join("attach");
+        let mut options = AttachOptions::default();
+        options.set_detach_keys(vec![16, 17]);
+        sut.add(&path, options, token.clone()).await?;
+
+        let mut first = connect_client(&path)?;
+        let mut second = connect_client(&path)?;
+
+        first.write_all(b"hello\x10").await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"hello"))
+        );
+        first.write_all(b"\x11").await?;
+
+        // The detached client gets disconnected
+        let mut buf = vec![0; 32];
+        loop {
+            if first.read(&mut buf).await? == 0 {
+                break;
+            }
+        }
+
+        // The detach keys are not forwarded and the container stdin stays open
+        second.write_all(b"world").await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"world"))
+        );
+
+        token.cancel();
+        Ok(())
+    }
+
+    #[test]
+    fn detach_keys_scanner() {
+        let mut sut = DetachKeysScanner::new(&[16, 17]);
+
+        assert_eq!(sut.scan(b"abc"), (b"abc".to_vec(), false));
+        assert_eq!(sut.scan(b"a\x10"), (b"a".to_vec(), false));
+        assert_eq!(sut.scan(b"b"), (b"\x10b".to_vec(), false));
+        assert_eq!(sut.scan(b"\x10\x10"), (b"\x10".to_vec(), false));
+        assert_eq!(sut.scan(b"\x11c"), (vec![], true));
+    }
+
+    #[test]
+    fn detach_keys_scanner_overlapping() {
+        let mut sut = DetachKeysScanner::new(b"aab");
+        assert_eq!(sut.scan(b"aaab"), (b"a".to_vec(), true));
+
+        let mut sut = DetachKeysScanner::new(b"abab");
+        assert_eq!(sut.scan(b"xabab"), (b"x".to_vec(), true));
+
+        let mut sut = DetachKeysScanner::new(b"abab");
+        assert_eq!(sut.scan(b"aba"), (vec![], false));
+        assert_eq!(sut.scan(b"cabab"), (b"abac".to_vec(), true));
+
+        let mut sut = DetachKeysScanner::new(b"aab");
+        assert_eq!(sut.scan(b"aa"), (vec![], false));
+        assert_eq!(sut.scan(b"ac"), (b"aaac".to_vec(), false));
+    }
+
+    #[test]
+    fn detach_keys_scanner_disabled() {
+        let mut sut = DetachKeysScanner::new(&[]);
+        assert_eq!(sut.scan(b"\x10\x11"), (b"\x10\x11".to_vec(), false));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn read_only() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let read_only_path = dir.path().join("attach-ro");
+        let path = dir.path().join("attach");
+        let mut options = AttachOptions::default();
+        options.set_read_only(true);
+        sut.add(&read_only_path, options, token.clone()).await?;
+        sut.add(&path, AttachOptions::default(), token.clone())
+            .await?;
+
+        let mut read_only = connect_client(&read_only_path)?;
+        let mut writer = connect_client(&path)?;
+
+        read_only.write_all(b"ignored").await?;
+        writer.write_all(b"hello").await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"hello"))
+        );
+
+        // Output is still streamed to the read-only client
+        sut.write(Pipe::StdOut, "out").await?;
+        let mut buf = vec![0; Attach::PACKET_BUF_SIZE];
+        read_only.read_exact(&mut buf).await?;
+        assert_eq!(&buf[..4], b"\x02out");
+
+        // Closing the read-only client does not close the container stdin
+        read_only.shutdown().await?;
+        writer.shutdown().await?;
+        assert_eq!(sut.read().await?, Message::Done);
+
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn idle_timeout() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let path = dir.path().join("attach");
+        let mut options = AttachOptions::default();
+        options.set_idle_timeout(Some(Duration::from_millis(100)));
+        sut.add(&path, options, token.clone()).await?;
+
+        let mut client = connect_client(&path)?;
+        client.write_all(b"hello").await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"hello"))
+        );
+
+        // The idle client gets disconnected, which closes the container stdin
+        assert_eq!(sut.read().await?, Message::Done);
+        assert!(sut
+            .state
+            .clients
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|x| x.token.is_cancelled()));
+
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn max_clients() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let path = dir.path().join("attach");
+        let mut options = AttachOptions::default();
+        options.set_max_clients(Some(1));
+        sut.add(&path, options, token.clone()).await?;
+
+        let mut first = connect_client(&path)?;
+        first.write_all(b"hello").await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"hello"))
+        );
+
+        let mut second = connect_client(&path)?;
+        let mut buf = vec![0; Attach::PACKET_BUF_SIZE];
+        second.read_exact(&mut buf).await?;
+        assert_eq!(buf[0], Attach::ERROR_PACKET_TYPE);
+        assert!(buf[1..].starts_with(b"too many attach clients"));
+        assert_eq!(second.read(&mut buf).await?, 0);
+
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn socket_permissions() -> Result<()> {
+        use nix::unistd::{getgid, getuid};
+        use std::os::unix::fs::MetadataExt;
+
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+
+        let path = dir.path().join("default");
+        sut.add(&path, AttachOptions::default(), token.clone())
+            .await?;
+        assert_eq!(path.metadata()?.mode() & 0o7777, 0o700);
+
+        let path = dir.path().join("custom");
+        let mut options = AttachOptions::default();
+        options.set_socket_mode(0o660);
+        options.set_socket_uid(Some(getuid().as_raw()));
+        options.set_socket_gid(Some(getgid().as_raw()));
+        sut.add(&path, options, token.clone()).await?;
+        let metadata = path.metadata()?;
+        assert_eq!(metadata.mode() & 0o7777, 0o660);
+        assert_eq!(metadata.uid(), getuid().as_raw());
+        assert_eq!(metadata.gid(), getgid().as_raw());
+
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn abstract_socket() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let name = format!("conmonrs-test-{}", uuid::Uuid::new_v4());
+        sut.add_abstract(&name, AttachOptions::default(), token.clone())
+            .await?;
+
+        let mut client = connect_addr(&UnixAddr::new_abstract(name.as_bytes())?)?;
+        client.write_all(b"hello").await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"hello"))
+        );
+
+        assert!(sut
+            .add_abstract("", AttachOptions::default(), token.clone())
+            .await
+            .is_err());
+
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn allowed_peers() -> Result<()> {
+        use nix::unistd::getuid;
+
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+
+        let path = dir.path().join("allowed");
+        let mut options = AttachOptions::default();
+        options.set_allowed_peers(vec![AttachPeer {
+            uid: Some(getuid().as_raw()),
+            ..Default::default()
+        }]);
+        sut.add(&path, options, token.clone()).await?;
+        let mut client = connect_client(&path)?;
+        client.write_all(b"hello").await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"hello"))
+        );
+
+        let path = dir.path().join("denied");
+        let mut options = AttachOptions::default();
+        options.set_allowed_peers(vec![AttachPeer {
+            uid: Some(getuid().as_raw() + 1),
+            ..Default::default()
+        }]);
+        sut.add(&path, options, token.clone()).await?;
+        let mut client = connect_client(&path)?;
+        let mut buf = vec![0; Attach::PACKET_BUF_SIZE];
+        client.read_exact(&mut buf).await?;
+        assert_eq!(buf[0], Attach::ERROR_PACKET_TYPE);
+        assert!(buf[1..].starts_with(b"attach client not authorized"));
+        assert_eq!(client.read(&mut buf).await?, 0);
+
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stats() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let path = dir.path().join("attach");
+        sut.add(&path, AttachOptions::default(), token.clone())
+            .await?;
+
+        let mut client = connect_client(&path)?;
+        client.write_all(b"hello").await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"hello"))
+        );
+
+        sut.write(Pipe::StdOut, "world").await?;
+        let mut buf = vec![0; Attach::PACKET_BUF_SIZE];
+        client.read_exact(&mut buf).await?;
+        assert_eq!(&buf[..6], b"\x02world");
+
+        let stats = sut.stats()?;
+        assert_eq!(stats.active_clients(), 1);
+        assert_eq!(stats.bytes_in(), 5);
+        assert_eq!(stats.bytes_out(), 5);
+        assert_eq!(stats.dropped_messages(), 0);
+        assert_eq!(stats.disconnects(), 0);
+
+        let (_slow_rx, _) = new_client(&sut, OverflowPolicy::Disconnect)?;
+        fill_queue(&mut sut).await?;
+        sut.write(Pipe::StdOut, "new").await?;
+        assert_eq!(sut.stats()?.dropped_messages(), 1);
+
+        token.cancel();
+        while sut.stats()?.disconnects() == 0 {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn websocket() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let path = dir.path().join("attach");
+        sut.add_websocket(&path, AttachOptions::default(), token.clone())
+            .await?;
+
+        let stream = UnixStream::connect(&path).await?;
+        let (mut ws, _) = tokio_tungstenite::client_async("ws://localhost/", stream).await?;
+
+        ws.send(WebSocketMessage::Binary(b"\0hello".to_vec()))
+            .await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"hello"))
+        );
+
+        sut.write(Pipe::StdErr, "world").await?;
+        assert_eq!(
+            ws.next().await.context("no message")??,
+            WebSocketMessage::Binary(b"\x02world".to_vec())
+        );
+
+        ws.close(None).await?;
+        assert_eq!(sut.read().await?, Message::Done);
+
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stream_socket() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let path = dir.path().join("attach");
+        let mut options = AttachOptions::default();
+        options.set_stream_socket(true);
+        sut.add(&path, options, token.clone()).await?;
+
+        let mut client = UnixStream::connect(&path).await?;
+        client
+            .write_all(&Framing::Stream.encode(Attach::STDIN_PACKET_TYPE, b"hello"))
+            .await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"hello"))
+        );
+
+        let data = vec![b'a'; Framing::Stream.max_payload() + 1];
+        sut.write(Pipe::StdErr, data).await?;
+        for expected in [Framing::Stream.max_payload(), 1] {
+            let mut header = [0; Framing::HEADER_LEN];
+            client.read_exact(&mut header).await?;
+            assert_eq!(header[0], 3);
+            let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+            assert_eq!(len as usize, expected);
+            let mut payload = vec![0; expected];
+            client.read_exact(&mut payload).await?;
+            assert!(payload.iter().all(|&x| x == b'a'));
+        }
+
+        client.shutdown().await?;
+        assert_eq!(sut.read().await?, Message::Done);
+
+        token.cancel();
+        let mut header = [0; Framing::HEADER_LEN];
+        client.read_exact(&mut header).await?;
+        assert_eq!(header, [Attach::DONE_PACKET_TYPE, 0, 0, 0, 0]);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn vsock() -> Result<()> {
+        const PORT: u32 = 47_123;
+        let mut sut = SharedContainerAttach::default();
+
+        let mut options = AttachOptions::default();
+        options.set_allowed_peers(vec![AttachPeer {
+            uid: Some(0),
+            ..Default::default()
+        }]);
+        assert!(sut
+            .add_vsock(PORT, options, CancellationToken::new())
+            .await
+            .is_err());
+
+        let token = CancellationToken::new();
+        match sut
+            .add_vsock(PORT, AttachOptions::default(), token.clone())
+            .await
+        {
+            // The kernel does not support vsock.
+            Err(e)
+                if e.root_cause()
+                    .downcast_ref::<io::Error>()
+                    .and_then(io::Error::raw_os_error)
+                    == Some(libc::EAFNOSUPPORT) =>
+            {
+                return Ok(())
+            }
+            res => res?,
+        }
+
+        // The port stays bound until the token gets cancelled.
+        assert!(sut
+            .add_vsock(PORT, AttachOptions::default(), CancellationToken::new())
+            .await
+            .is_err());
+        token.cancel();
+        time::sleep(Duration::from_millis(100)).await;
+
+        let token = CancellationToken::new();
+        sut.add_vsock(PORT, AttachOptions::default(), token.clone())
+            .await?;
+        token.cancel();
+        Ok(())
+    }
+
+    #[test]
+    fn framing_encode() {
+        let packet = Framing::Packet(8).encode(2, b"foo");
+        assert_eq!(packet, b"\x02foo\0\0\0\0");
+        let frame = Framing::Stream.encode(2, b"foo");
+        assert_eq!(frame, b"\x02\0\0\0\x03foo");
+
+        // Reused buffers contain only the latest frame
+        let mut buf = frame;
+        Framing::Stream.encode_into(3, b"a", &mut buf);
+        assert_eq!(buf, b"\x03\0\0\0\x01a");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn signal() -> Result<()> {
+        use std::os::unix::process::{CommandExt, ExitStatusExt};
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .process_group(0)
+            .spawn()?;
+
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let path = dir.path().join("attach");
+        let mut options = AttachOptions::default();
+        options.set_signal_pid(Some(child.id()));
+        sut.add(&path, options, token.clone()).await?;
+
+        let mut client = connect_client(&path)?;
+        let mut packet = vec![0, Attach::SIGNAL_PACKET_TYPE];
+        packet.extend_from_slice(&(Signal::SIGTERM as i32).to_be_bytes());
+        client.write_all(&packet).await?;
+
+        let status = task::spawn_blocking(move || child.wait()).await??;
+        assert_eq!(status.signal(), Some(Signal::SIGTERM as i32));
+
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn teardown() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let path = dir.path().join("attach");
+        sut.add(&path, AttachOptions::default(), token.clone())
+            .await?;
+
+        let mut client = connect_client(&path)?;
+        client.write_all(b"hello").await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"hello"))
+        );
+
+        token.cancel();
+
+        let mut buf = vec![0; Attach::PACKET_BUF_SIZE];
+        client.read_exact(&mut buf).await?;
+        assert!(buf.iter().all(|&x| x == Attach::DONE_PACKET_TYPE));
+        assert_eq!(client.read(&mut buf).await?, 0);
+
+        while path.exists() {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(connect_client(&path).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn queued_messages() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let (mut rx, _token) = new_client(&sut, OverflowPolicy::DropOldest)?;
+
+        sut.write(Pipe::StdOut, "foo").await?;
+        sut.write(Pipe::StdOut, "bar").await?;
+        assert_eq!(sut.stats()?.queued_messages(), 2);
+
+        rx.recv().await.context("no packet")?;
+        assert_eq!(sut.stats()?.queued_messages(), 1);
+        rx.recv().await.context("no packet")?;
+
+        // Overwritten packets are no longer queued
+        fill_queue(&mut sut).await?;
+        sut.write(Pipe::StdOut, "new").await?;
+        assert_eq!(
+            sut.stats()?.queued_messages(),
+            AttachClient::QUEUE_SIZE as u64 + 1
+        );
+        rx.recv().await.context("no packet")?;
+        assert_eq!(
+            sut.stats()?.queued_messages(),
+            AttachClient::QUEUE_SIZE as u64 - 1
+        );
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn recording() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let path = dir.path().join("attach");
+        let recording_dir = dir.path().join("recordings");
+        fs::create_dir(&recording_dir)?;
+        let mut options = AttachOptions::default();
+        options.set_recording_dir(Some(recording_dir.clone()));
+        sut.resize(100, 50)?;
+        sut.add(&path, options, token.clone()).await?;
+
+        let mut client = connect_client(&path)?;
+        client.write_all(b"ls\r").await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"ls\r"))
+        );
+
+        sut.write(Pipe::StdOut, "foo").await?;
+        let mut buf = vec![0; Attach::PACKET_BUF_SIZE];
+        client.read_exact(&mut buf).await?;
+        sut.resize(120, 40)?;
+
+        let recording = fs::read_dir(&recording_dir)?
+            .next()
+            .context("no recording")??
+            .path();
+        let content = fs::read_to_string(recording)?;
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].contains(r#""width": 100, "height": 50"#));
+        assert!(lines[1].ends_with(r#""i", "ls\r"]"#));
+        assert!(lines[2].ends_with(r#""o", "foo"]"#));
+        assert!(lines[3].ends_with(r#""r", "120x40"]"#));
+
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn recording_failure() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let path = dir.path().join("attach");
+        let recording_dir = dir.path().join("recordings");
+        let mut options = AttachOptions::default();
+        options.set_recording_dir(Some(recording_dir.clone()));
+        sut.add(&path, options, token.clone()).await?;
+
+        // The missing recording directory only drops the connection of the client.
+        let mut client = connect_client(&path)?;
+        let mut buf = vec![0; Attach::PACKET_BUF_SIZE];
+        assert_eq!(client.read(&mut buf).await?, 0);
+
+        fs::create_dir(&recording_dir)?;
+        let mut client = connect_client(&path)?;
+        client.write_all(b"ls\r").await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"ls\r"))
+        );
+
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_replay() -> Result<()> {
+        let mut sut = SharedContainerAttach::new(1024);
+        sut.write(Pipe::StdOut, "before").await?;
+
+        let (mut rx, _) = new_client(&sut, OverflowPolicy::Block)?;
+        sut.write(Pipe::StdOut, "after").await?;
+
+        let (_, data) = rx.recv().await.context("no packet")?;
+        assert_eq!(&data[..], b"before");
+        let (_, data) = rx.recv().await.context("no packet")?;
+        assert_eq!(&data[..], b"after");
+        Ok(())
+    }
+
+    #[test]
+    fn handshake_parse() {
+        let handshake = Handshake {
+            version: 2,
+            features: 0xff,
+            packet_size: 1024,
+        };
+        let packet = handshake.to_packet(0);
+        assert_eq!(Handshake::parse(&packet), Some(handshake));
+        assert_eq!(Handshake::parse(&packet[..packet.len() - 1]), None);
+
+        let handshake = Handshake {
+            version: 1,
+            features: 0,
+            packet_size: 0,
+        };
+        assert_eq!(Handshake::parse(&handshake.to_packet(0)), Some(handshake));
+
+        assert_eq!(Handshake::parse(b"hello"), None);
+        assert_eq!(Handshake::parse(b"\0CONMONRS\0"), None);
+        assert_eq!(Handshake::parse(&packet[1..]), None);
+    }
+
+    #[test]
+    fn handshake_negotiate() {
+        let handshake = Handshake {
+            version: Handshake::VERSION + 1,
+            features: u32::MAX,
+            packet_size: u32::MAX,
+        }
+        .negotiate(1024);
+        assert_eq!(handshake.version, Handshake::VERSION);
+        assert_eq!(handshake.features, Handshake::FEATURES);
+        assert_eq!(handshake.packet_size, 1024);
+
+        let handshake = Handshake {
+            version: 1,
+            features: Handshake::FEATURE_PACKET_SIZE,
+            packet_size: 1,
+        }
+        .negotiate(1024);
+        assert_eq!(handshake.packet_size, AttachOptions::MIN_PACKET_SIZE as u32);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn handshake() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let path = dir.path().join("attach");
+        sut.add(&path, AttachOptions::default(), token.clone())
+            .await?;
+
+        let mut client = connect_client(&path)?;
+        let hello = Handshake {
+            version: 1,
+            features: 0,
+            packet_size: 0,
+        };
+        client.write_all(&hello.to_packet(0)).await?;
+
+        let mut buf = vec![0; Attach::PACKET_BUF_SIZE];
+        client.read_exact(&mut buf).await?;
+        let reply = hello.to_packet(Attach::HANDSHAKE_PACKET_TYPE);
+        assert_eq!(&buf[..reply.len()], reply);
+
+        // The handshake is not forwarded to the container
+        client.write_all(b"hello").await?;
+        assert_eq!(
+            sut.read().await?,
+            Message::Data(Bytes::from_static(b"hello"))
+        );
+
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn handshake_packet_size() -> Result<()> {
+        let mut sut = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let path = dir.path().join("attach");
+        let mut options = AttachOptions::default();
+        options.set_packet_size(1024);
+        sut.add(&path, options, token.clone()).await?;
+
+        let mut client = connect_client(&path)?;
+        let hello = Handshake {
+            version: 1,
+            features: Handshake::FEATURE_PACKET_SIZE,
+            packet_size: 2048,
+        };
+        client.write_all(&hello.to_packet(0)).await?;
+
+        // The handshake reply still uses the default packet size
+        let mut buf = vec![0; Attach::PACKET_BUF_SIZE];
+        client.read_exact(&mut buf).await?;
+        let reply = Handshake {
+            packet_size: 1024,
+            ..hello
+        }
+        .to_packet(Attach::HANDSHAKE_PACKET_TYPE);
+        assert_eq!(&buf[..reply.len()], reply);
+
+        sut.write(Pipe::StdOut, vec![b'a'; 2000]).await?;
+        for expected in [1023, 977] {
+            let n = client.read(&mut buf).await?;
+            assert_eq!(n, 1024);
+            assert_eq!(buf[0], 2);
+            assert_eq!(buf[1..].iter().filter(|&&x| x == b'a').count(), expected);
+        }
+
+        token.cancel();
+        Ok(())
+    }
+}
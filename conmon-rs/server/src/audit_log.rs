@@ -0,0 +1,467 @@
+//! The optional append-only audit log, which records every RPC along with its caller, the
+//! container it operates on, a summary of its parameters, its result and duration.
+
+use crate::{
+    journald_logger::JournaldLogger,
+    method_filter::{self, METHODS},
+};
+use anyhow::{bail, format_err, Context, Result};
+use capnp::{
+    any_pointer,
+    capability::{self, FromServer, Params, Promise, Results},
+    Error,
+};
+use capnp_rpc::pry;
+use conmon_common::conmon_capnp::conmon;
+use getset::Setters;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    ops::{Deref, DerefMut},
+    os::unix::{fs::OpenOptionsExt, net::UnixDatagram},
+    path::Path,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::error;
+
+macro_rules! lock {
+    ($x:expr) => {
+        $x.lock().map_err(|e| format_err!("{:#}", e))?
+    };
+}
+
+#[derive(Clone, Debug, Default, Serialize, Setters)]
+#[serde(rename_all = "camelCase")]
+/// The identity of a RPC caller, as far as it is known by the transport of the connection.
+pub struct Caller {
+    /// The transport of the connection, like `unix` or `tls`.
+    transport: &'static str,
+
+    #[getset(set = "pub")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The credentials of the peer process of unix domain sockets.
+    uid: Option<u32>,
+
+    #[getset(set = "pub")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gid: Option<u32>,
+
+    #[getset(set = "pub")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<i32>,
+
+    #[getset(set = "pub")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The remote address of network connections.
+    address: Option<String>,
+
+    #[getset(set = "pub")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The name of the token the connection authenticated with.
+    token: Option<String>,
+}
+
+impl Caller {
+    /// Create a new caller of the transport, whose identity is unknown yet.
+    pub fn new(transport: &'static str) -> Self {
+        Self {
+            transport,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+/// A single record of the audit log.
+struct Entry<'a> {
+    time_unix_nano: i64,
+    caller: &'a Caller,
+    method: &'a str,
+
+    #[serde(skip_serializing_if = "str::is_empty")]
+    container_id: &'a str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    params: Option<&'a BTreeMap<&'static str, String>>,
+
+    result: &'static str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+
+    duration_us: u64,
+}
+
+#[derive(Debug)]
+/// The destination of the audit records.
+enum Sink {
+    /// A file receiving one JSON object per line.
+    File(File),
+
+    /// The systemd journal, where every record is a message with its JSON as `MESSAGE`.
+    Journald(UnixDatagram),
+}
+
+#[derive(Clone, Debug)]
+/// The audit log shared by all connections.
+pub struct AuditLog {
+    sink: Arc<Mutex<Sink>>,
+}
+
+impl AuditLog {
+    /// The value of `--audit-log` which selects the systemd journal.
+    pub const JOURNALD: &'static str = "journald";
+
+    /// The syslog identifier of the journal messages.
+    const SYSLOG_IDENTIFIER: &'static [u8] = b"conmonrs-audit";
+
+    /// Validate the destination, which is either `journald` or an absolute file path.
+    pub fn validate(destination: &str) -> Result<()> {
+        if destination != Self::JOURNALD && !Path::new(destination).is_absolute() {
+            bail!(
+                "audit log {} is neither {} nor an absolute path",
+                destination,
+                Self::JOURNALD
+            )
+        }
+        Ok(())
+    }
+
+    /// Open the audit log, where files get appended to and are only accessible by the owner.
+    pub fn open(destination: &str) -> Result<Self> {
+        Self::validate(destination)?;
+        let sink = if destination == Self::JOURNALD {
+            Self::open_journald(Path::new(JournaldLogger::SOCKET_PATH))?
+        } else {
+            Sink::File(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .mode(0o600)
+                    .open(destination)
+                    .context(format!("open audit log {}", destination))?,
+            )
+        };
+        Ok(Self {
+            sink: Arc::new(Mutex::new(sink)),
+        })
+    }
+
+    fn open_journald(path: &Path) -> Result<Sink> {
+        let socket = UnixDatagram::unbound().context("create journal socket")?;
+        socket
+            .connect(path)
+            .context(format!("connect to journal socket {}", path.display()))?;
+        Ok(Sink::Journald(socket))
+    }
+
+    /// Wrap the client, so that every call of the caller gets recorded.
+    pub fn audit(&self, client: conmon::Client, caller: Caller) -> conmon::Client {
+        capnp_rpc::new_client(AuditedClient {
+            client,
+            log: self.clone(),
+            caller: Rc::new(caller),
+        })
+    }
+
+    /// Write a single record.
+    fn record(&self, entry: &Entry<'_>) -> Result<()> {
+        let json = serde_json::to_vec(entry).context("serialize audit record")?;
+        match &mut *lock!(self.sink) {
+            Sink::File(file) => {
+                let mut line = json;
+                line.push(b'\n');
+                file.write_all(&line).context("write audit record")?;
+            }
+            Sink::Journald(socket) => {
+                let mut message = Vec::with_capacity(json.len() + 256);
+                JournaldLogger::append_field(&mut message, "MESSAGE", &json);
+                JournaldLogger::append_field(
+                    &mut message,
+                    "SYSLOG_IDENTIFIER",
+                    Self::SYSLOG_IDENTIFIER,
+                );
+                JournaldLogger::append_field(&mut message, "AUDIT_METHOD", entry.method.as_bytes());
+                JournaldLogger::append_field(&mut message, "AUDIT_RESULT", entry.result.as_bytes());
+                if !entry.container_id.is_empty() {
+                    JournaldLogger::append_field(
+                        &mut message,
+                        "CONTAINER_ID_FULL",
+                        entry.container_id.as_bytes(),
+                    );
+                }
+                socket.send(&message).context("send audit record")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The parameters of the method worth recording, which leave out large or sensitive ones
+    /// like stdin data or log driver options.
+    fn summary(
+        method: &str,
+        params: any_pointer::Reader<'_>,
+    ) -> capnp::Result<BTreeMap<&'static str, String>> {
+        let mut summary = BTreeMap::new();
+        match method {
+            "createContainer" => {
+                let req = params
+                    .get_as::<conmon::create_container_params::Reader>()?
+                    .get_request()?;
+                summary.insert("bundlePath", req.get_bundle_path()?.to_string());
+                summary.insert("terminal", req.get_terminal().to_string());
+            }
+            "createContainers" => {
+                let req = params
+                    .get_as::<conmon::create_containers_params::Reader>()?
+                    .get_request()?;
+                let mut ids = vec![];
+                for request in req.get_requests()?.iter() {
+                    ids.push(request.get_id()?.to_string());
+                }
+                summary.insert("ids", ids.join(","));
+            }
+            "execSyncContainer" => {
+                let req = params
+                    .get_as::<conmon::exec_sync_container_params::Reader>()?
+                    .get_request()?;
+                summary.insert("command", Self::command(req.get_command()?)?);
+                summary.insert("execSessionId", req.get_exec_session_id()?.to_string());
+                summary.insert("terminal", req.get_terminal().to_string());
+            }
+            "execStreamContainer" => {
+                let req = params
+                    .get_as::<conmon::exec_stream_container_params::Reader>()?
+                    .get_request()?;
+                summary.insert("command", Self::command(req.get_command()?)?);
+                summary.insert("execSessionId", req.get_exec_session_id()?.to_string());
+                summary.insert("terminal", req.get_terminal().to_string());
+            }
+            "killExecSession" => {
+                let req = params
+                    .get_as::<conmon::kill_exec_session_params::Reader>()?
+                    .get_request()?;
+                summary.insert("execSessionId", req.get_exec_session_id()?.to_string());
+                summary.insert("signal", req.get_signal().to_string());
+            }
+            "removeContainer" => {
+                let req = params
+                    .get_as::<conmon::remove_container_params::Reader>()?
+                    .get_request()?;
+                summary.insert("force", req.get_force().to_string());
+            }
+            "stopContainer" => {
+                let req = params
+                    .get_as::<conmon::stop_container_params::Reader>()?
+                    .get_request()?;
+                summary.insert("signal", req.get_signal().to_string());
+                summary.insert("timeoutSec", req.get_timeout_sec().to_string());
+            }
+            "setLogFilter" => {
+                let req = params
+                    .get_as::<conmon::set_log_filter_params::Reader>()?
+                    .get_request()?;
+                summary.insert("filter", req.get_filter()?.to_string());
+            }
+            "shutdown" => {
+                let req = params
+                    .get_as::<conmon::shutdown_params::Reader>()?
+                    .get_request()?;
+                summary.insert("timeoutSec", req.get_timeout_sec().to_string());
+            }
+            _ => {}
+        }
+        summary.retain(|_, value| !value.is_empty());
+        Ok(summary)
+    }
+
+    fn command(command: capnp::text_list::Reader<'_>) -> capnp::Result<String> {
+        let mut args = vec![];
+        for arg in command.iter() {
+            args.push(arg?);
+        }
+        Ok(args.join(" "))
+    }
+}
+
+/// A server recording the calls of a single caller before forwarding them to the client.
+pub struct AuditedClient {
+    client: conmon::Client,
+    log: AuditLog,
+    caller: Rc<Caller>,
+}
+
+impl AuditedClient {
+    fn record(
+        &self,
+        method: &str,
+        container_id: &str,
+        params: &BTreeMap<&'static str, String>,
+        started: Instant,
+        error: Option<&str>,
+    ) {
+        let entry = Entry {
+            time_unix_nano: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |x| x.as_nanos() as i64),
+            caller: &self.caller,
+            method,
+            container_id,
+            params: (!params.is_empty()).then(|| params),
+            result: if error.is_some() { "error" } else { "ok" },
+            error,
+            duration_us: started.elapsed().as_micros() as u64,
+        };
+        if let Err(e) = self.log.record(&entry) {
+            error!("Unable to write audit record: {:#}", e);
+        }
+    }
+}
+
+impl capability::Server for AuditedClient {
+    fn dispatch_call(
+        &mut self,
+        interface_id: u64,
+        method_id: u16,
+        params: Params<any_pointer::Owned>,
+        mut results: Results<any_pointer::Owned>,
+    ) -> Promise<(), Error> {
+        let started = Instant::now();
+        let method = match METHODS.get(method_id as usize) {
+            Some(method) if interface_id == conmon::_private::TYPE_ID => *method,
+            _ => "unknown",
+        };
+        let reader = pry!(params.get());
+        // Malformed params still get recorded, the server rejects them anyways.
+        let container_id = method_filter::container_id(method, reader)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        let summary = AuditLog::summary(method, reader).unwrap_or_default();
+
+        let mut request = self
+            .client
+            .client
+            .new_call::<any_pointer::Owned, any_pointer::Owned>(interface_id, method_id, None);
+        if let Err(e) = request.get().set_as(reader) {
+            self.record(
+                method,
+                &container_id,
+                &summary,
+                started,
+                Some(&e.description),
+            );
+            return Promise::err(e);
+        }
+
+        let audited = Self {
+            client: self.client.clone(),
+            log: self.log.clone(),
+            caller: self.caller.clone(),
+        };
+        Promise::from_future(async move {
+            let result = match request.send().promise.await {
+                Ok(response) => response.get().and_then(|x| results.get().set_as(x)),
+                Err(e) => Err(e),
+            };
+            audited.record(
+                method,
+                &container_id,
+                &summary,
+                started,
+                result.as_ref().err().map(|e| e.description.as_str()),
+            );
+            result
+        })
+    }
+}
+
+/// The dispatch of the audited client, which is required to create a typed `Conmon` client
+/// from it.
+pub struct AuditedClientDispatch(AuditedClient);
+
+impl Deref for AuditedClientDispatch {
+    type Target = AuditedClient;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for AuditedClientDispatch {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl capability::Server for AuditedClientDispatch {
+    fn dispatch_call(
+        &mut self,
+        interface_id: u64,
+        method_id: u16,
+        params: Params<any_pointer::Owned>,
+        results: Results<any_pointer::Owned>,
+    ) -> Promise<(), Error> {
+        self.0
+            .dispatch_call(interface_id, method_id, params, results)
+    }
+}
+
+impl FromServer<AuditedClient> for conmon::Client {
+    type Dispatch = AuditedClientDispatch;
+
+    fn from_server(s: AuditedClient) -> Self::Dispatch {
+        AuditedClientDispatch(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn record_json_lines() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("audit.log");
+        let sut = AuditLog::open(&path.to_string_lossy())?;
+
+        let mut caller = Caller::new("unix");
+        caller.set_uid(Some(0)).set_gid(Some(0));
+        let params = BTreeMap::from([("force", "true".to_string())]);
+        for error in [None, Some("container not found")] {
+            sut.record(&Entry {
+                time_unix_nano: 1,
+                caller: &caller,
+                method: "removeContainer",
+                container_id: "id",
+                params: Some(&params),
+                result: if error.is_some() { "error" } else { "ok" },
+                error,
+                duration_us: 2,
+            })?;
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(
+            lines[0],
+            r#"{"timeUnixNano":1,"caller":{"transport":"unix","uid":0,"gid":0},"method":"removeContainer","containerId":"id","params":{"force":"true"},"result":"ok","durationUs":2}"#
+        );
+        assert!(lines[1].contains(r#""result":"error","error":"container not found""#));
+        Ok(())
+    }
+
+    #[test]
+    fn validate() {
+        assert!(AuditLog::validate("journald").is_ok());
+        assert!(AuditLog::validate("/var/log/conmonrs-audit.log").is_ok());
+        assert!(AuditLog::validate("audit.log").is_err());
+    }
+}
@@ -0,0 +1,268 @@
+//! Authentication of RPC connections by tokens and peer credentials.
+//!
+//! If a token file is configured, clients have to send one of its tokens terminated by a newline
+//! right after connecting, before the Cap'n Proto messages start. Unix domain socket peers can be
+//! restricted additionally to a set of user and group IDs. The HTTP, gRPC and ttrpc APIs send the
+//! token as `Bearer TOKEN` of the authorization header or metadata of every call instead.
+
+use anyhow::{bail, format_err, Context, Result};
+use std::{fs, path::Path, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt},
+    net::unix::UCred,
+    time,
+};
+
+#[derive(Debug, Default)]
+/// The accepted tokens along with the names of their callers.
+pub struct Authenticator {
+    keys: Vec<(String, Vec<u8>)>,
+}
+
+impl Authenticator {
+    /// The maximum time a client can take to send its token.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// The maximum length of a token including its newline.
+    const MAX_TOKEN_LEN: u64 = 1024;
+
+    /// The caller name of tokens without an explicit one.
+    const SHARED: &'static str = "shared";
+
+    /// Load the token file, which contains one `TOKEN` or `NAME TOKEN` per line. Empty lines and
+    /// lines starting with `#` are ignored.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content =
+            fs::read_to_string(path).context(format!("read token file '{}'", path.display()))?;
+        let mut keys = vec![];
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let (name, token) = match fields.as_slice() {
+                [token] => (Self::SHARED, *token),
+                [name, token] => (*name, *token),
+                _ => bail!("invalid line in token file '{}'", path.display()),
+            };
+            if token.len() as u64 >= Self::MAX_TOKEN_LEN {
+                bail!("token of caller {} exceeds the maximum length", name)
+            }
+            keys.push((name.into(), token.as_bytes().to_vec()));
+        }
+        if keys.is_empty() {
+            bail!("no tokens found in '{}'", path.display())
+        }
+        Ok(Self { keys })
+    }
+
+    /// Read the token of the client from the stream, returning the name of its caller. The
+    /// stream is left at the first byte after the token.
+    pub async fn authenticate<T>(&self, stream: &mut T) -> Result<&str>
+    where
+        T: AsyncBufRead + Unpin,
+    {
+        let mut line = vec![];
+        time::timeout(
+            Self::TIMEOUT,
+            stream
+                .take(Self::MAX_TOKEN_LEN)
+                .read_until(b'\n', &mut line),
+        )
+        .await
+        .context("timed out waiting for token")?
+        .context("read token")?;
+        if line.pop() != Some(b'\n') {
+            bail!("token not terminated by a newline")
+        }
+
+        self.verify(&line).context("invalid token")
+    }
+
+    /// One of the accepted tokens, which the server uses to connect to its own workers.
+    pub fn token(&self) -> Option<&[u8]> {
+        self.keys.first().map(|(_, key)| key.as_slice())
+    }
+
+    /// Returns the name of the caller of the token, or `None` if the token is not accepted.
+    pub fn verify(&self, token: &[u8]) -> Option<&str> {
+        self.keys
+            .iter()
+            .find(|(_, key)| constant_time_eq(key, token))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// Compare both values in a time only depending on their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Whether the peer is allowed to connect, which requires its user or group ID to be allowed.
+/// All peers are allowed if neither user nor group IDs are configured.
+pub fn peer_allowed(cred: &UCred, uids: &[u32], gids: &[u32]) -> bool {
+    ids_allowed(cred.uid(), cred.gid(), uids, gids)
+}
+
+/// Whether the user or group ID is allowed, where all are allowed if none are configured.
+fn ids_allowed(uid: u32, gid: u32, uids: &[u32], gids: &[u32]) -> bool {
+    (uids.is_empty() && gids.is_empty()) || uids.contains(&uid) || gids.contains(&gid)
+}
+
+#[derive(Clone, Debug, Default)]
+/// The access control of the HTTP, gRPC and ttrpc APIs, which applies the same token and peer
+/// credential checks as the Cap'n Proto socket.
+pub struct Access {
+    /// Requires a `Bearer TOKEN` authorization of every call if set.
+    authenticator: Option<Arc<Authenticator>>,
+
+    /// The user IDs of the peers allowed to connect to the unix domain socket.
+    allowed_uids: Vec<u32>,
+
+    /// The group IDs of the peers allowed to connect to the unix domain socket.
+    allowed_gids: Vec<u32>,
+}
+
+impl Access {
+    /// Create a new access control for the authenticator and the allowed peer IDs.
+    pub fn new(
+        authenticator: Option<Arc<Authenticator>>,
+        allowed_uids: Vec<u32>,
+        allowed_gids: Vec<u32>,
+    ) -> Self {
+        Self {
+            authenticator,
+            allowed_uids,
+            allowed_gids,
+        }
+    }
+
+    /// Whether calls have to be authenticated by a token.
+    pub fn requires_token(&self) -> bool {
+        self.authenticator.is_some()
+    }
+
+    /// Whether the peer with the provided user and group ID is allowed to connect.
+    pub fn peer_allowed(&self, uid: u32, gid: u32) -> bool {
+        ids_allowed(uid, gid, &self.allowed_uids, &self.allowed_gids)
+    }
+
+    /// Verify the `Bearer TOKEN` authorization of a call if a token file is configured, which
+    /// returns the name of the authenticated caller.
+    pub fn authorize(&self, authorization: Option<&str>) -> Result<Option<&str>> {
+        let authenticator = match &self.authenticator {
+            Some(authenticator) => authenticator,
+            None => return Ok(None),
+        };
+        let token = authorization
+            .and_then(|x| x.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        authenticator
+            .verify(token.as_bytes())
+            .map(Some)
+            .ok_or_else(|| format_err!("invalid token"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::unistd::{getgid, getuid};
+    use tempfile::NamedTempFile;
+    use tokio::{io::BufReader, net::UnixStream};
+
+    fn authenticator(content: &str) -> Result<Authenticator> {
+        let file = NamedTempFile::new()?;
+        fs::write(file.path(), content)?;
+        Authenticator::load(file.path())
+    }
+
+    #[test]
+    fn load() -> Result<()> {
+        let sut = authenticator("# comment\n\nsecret\ncrio other\n")?;
+        assert_eq!(
+            sut.keys,
+            vec![
+                ("shared".into(), b"secret".to_vec()),
+                ("crio".into(), b"other".to_vec())
+            ]
+        );
+        assert_eq!(sut.token(), Some(&b"secret"[..]));
+
+        assert!(authenticator("").is_err());
+        assert!(authenticator("a b c").is_err());
+        assert!(authenticator(&"a".repeat(1024)).is_err());
+        assert!(Authenticator::load(Path::new("/none")).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn authenticate() -> Result<()> {
+        let sut = authenticator("secret\ncrio other\n")?;
+
+        let mut stream = BufReader::new(&b"other\nrpc"[..]);
+        assert_eq!(sut.authenticate(&mut stream).await?, "crio");
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).await?;
+        assert_eq!(rest, "rpc");
+
+        for input in [&b"secret"[..], b"wrong\n", b"\n", b"secret2\n"] {
+            assert!(sut.authenticate(&mut BufReader::new(input)).await.is_err());
+        }
+        let long = [b'a'; 2048];
+        assert!(sut
+            .authenticate(&mut BufReader::new(&long[..]))
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn constant_time_eq_values() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[tokio::test]
+    async fn peer_allowed_by_ids() -> Result<()> {
+        let (stream, _) = UnixStream::pair()?;
+        let cred = stream.peer_cred()?;
+        let (uid, gid) = (getuid().as_raw(), getgid().as_raw());
+
+        assert!(peer_allowed(&cred, &[], &[]));
+        assert!(peer_allowed(&cred, &[uid], &[]));
+        assert!(peer_allowed(&cred, &[uid + 1], &[gid]));
+        assert!(!peer_allowed(&cred, &[uid + 1], &[]));
+        assert!(!peer_allowed(&cred, &[], &[gid + 1]));
+        Ok(())
+    }
+
+    #[test]
+    fn access() -> Result<()> {
+        let sut = Access::default();
+        assert!(!sut.requires_token());
+        assert_eq!(sut.authorize(None)?, None);
+        assert!(sut.peer_allowed(1, 2));
+
+        let sut = Access::new(
+            Some(Arc::new(authenticator("crio secret\n")?)),
+            vec![1],
+            vec![],
+        );
+        assert!(sut.requires_token());
+        assert_eq!(sut.authorize(Some("Bearer secret"))?, Some("crio"));
+        for authorization in [
+            None,
+            Some("secret"),
+            Some("Bearer other"),
+            Some("Basic secret"),
+        ] {
+            assert!(sut.authorize(authorization).is_err());
+        }
+        assert!(sut.peer_allowed(1, 2));
+        assert!(!sut.peer_allowed(2, 2));
+        Ok(())
+    }
+}
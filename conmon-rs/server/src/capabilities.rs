@@ -0,0 +1,128 @@
+//! The capabilities of the server binary reported by the capabilities RPC, which allow clients to
+//! detect supported features without relying on version numbers.
+
+use crate::{config::Config, method_filter::METHODS};
+use capnp::traits::FromU16;
+use conmon_common::conmon_capnp::conmon::{capabilities_response::Transport, log_driver};
+use getset::{CopyGetters, Getters};
+
+#[derive(CopyGetters, Debug, Getters)]
+/// The supported features, log drivers, transports and methods of the server.
+pub struct Capabilities {
+    #[getset(get_copy = "pub")]
+    /// The version of the RPC protocol.
+    protocol_version: u32,
+
+    #[getset(get = "pub")]
+    /// The optional features the binary got built with.
+    features: Vec<&'static str>,
+
+    #[getset(get = "pub")]
+    /// The supported log driver types.
+    log_drivers: Vec<log_driver::Type>,
+
+    #[getset(get = "pub")]
+    /// The transports the API is currently served on.
+    transports: Vec<Transport>,
+
+    #[getset(get_copy = "pub")]
+    /// The names of the supported methods of the `Conmon` interface.
+    methods: &'static [&'static str],
+}
+
+impl Capabilities {
+    /// The version of the RPC protocol, which gets increased on incompatible changes.
+    pub const PROTOCOL_VERSION: u32 = 1;
+
+    /// The oldest version of the RPC protocol the server still speaks.
+    pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+    /// Collect the capabilities of the binary running with the configuration.
+    pub fn new(config: &Config) -> Self {
+        let mut transports = vec![Transport::Unix];
+        if config.tls_address().is_some() {
+            transports.push(Transport::Tls);
+        }
+        if config.grpc_socket().is_some() {
+            transports.push(Transport::Grpc);
+        }
+        if config.ttrpc_socket().is_some() {
+            transports.push(Transport::Ttrpc);
+        }
+        if config.vsock_port().is_some() {
+            transports.push(Transport::Vsock);
+        }
+        if config.http_address().is_some() {
+            transports.push(Transport::Http);
+        }
+
+        Self {
+            protocol_version: Self::PROTOCOL_VERSION,
+            features: Self::enabled_features(),
+            log_drivers: Self::supported_log_drivers(),
+            transports,
+            methods: METHODS,
+        }
+    }
+
+    /// The optional cargo features the binary got built with.
+    pub fn enabled_features() -> Vec<&'static str> {
+        [
+            ("grpc", cfg!(feature = "grpc")),
+            ("io-uring", cfg!(feature = "io-uring")),
+            ("ttrpc", cfg!(feature = "ttrpc")),
+        ]
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(feature, _)| *feature)
+        .collect()
+    }
+
+    /// The log driver types known to the binary.
+    pub fn supported_log_drivers() -> Vec<log_driver::Type> {
+        (0..)
+            .map(log_driver::Type::from_u16)
+            .take_while(Result::is_ok)
+            .filter_map(Result::ok)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn capabilities() {
+        let sut = Capabilities::new(&Config::parse_from(["conmonrs"]));
+        assert_eq!(sut.protocol_version(), 1);
+        assert_eq!(sut.features().contains(&"grpc"), cfg!(feature = "grpc"));
+        assert_eq!(sut.log_drivers().len(), 12);
+        assert_eq!(
+            sut.log_drivers().last(),
+            Some(&log_driver::Type::Passthrough)
+        );
+        assert_eq!(sut.transports(), &[Transport::Unix]);
+        assert!(sut.methods().contains(&"capabilities"));
+    }
+
+    #[test]
+    fn capabilities_transports() {
+        let sut = Capabilities::new(&Config::parse_from([
+            "conmonrs",
+            "--tls-address=127.0.0.1:0",
+            "--ttrpc-socket=/run/ttrpc.sock",
+            "--vsock-port=1024",
+        ]));
+        assert_eq!(
+            sut.transports(),
+            &[
+                Transport::Unix,
+                Transport::Tls,
+                Transport::Ttrpc,
+                Transport::Vsock
+            ]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! The limit of running containers, which rejects further creates before they exhaust the file
+//! descriptors and memory of the server.
+
+use crate::metrics::METRICS;
+use getset::CopyGetters;
+use std::{
+    error::Error,
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+#[derive(Clone, CopyGetters, Debug, Default)]
+/// The capacity of the server for running containers.
+pub struct ContainerCapacity {
+    #[getset(get_copy = "pub")]
+    /// The maximum amount of running containers, where 0 disables the limit.
+    max: usize,
+
+    /// The creates which passed the check, but whose container is not running yet.
+    pending: Arc<AtomicUsize>,
+}
+
+#[derive(Debug)]
+/// A slot for a container being created, which gets released on drop.
+pub struct Reservation(Option<Arc<AtomicUsize>>);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// The rejection of a create, because the server runs the maximum amount of containers.
+pub struct CapacityExceeded {
+    max: usize,
+}
+
+impl ContainerCapacity {
+    /// Create a new capacity for the maximum amount of containers, where 0 disables the limit.
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            pending: Default::default(),
+        }
+    }
+
+    /// Reserve a slot for a new container, in addition to the already running ones.
+    pub fn reserve(&self, running: usize) -> Result<Reservation, CapacityExceeded> {
+        if self.max == 0 {
+            return Ok(Reservation(None));
+        }
+        if running + self.pending.load(Ordering::SeqCst) >= self.max {
+            METRICS.record_capacity_rejection();
+            return Err(CapacityExceeded { max: self.max });
+        }
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        Ok(Reservation(Some(self.pending.clone())))
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(pending) = &self.0 {
+            pending.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl CapacityExceeded {
+    /// The start of the error message, which identifies the rejection on every transport.
+    pub const PREFIX: &'static str = "capacity exceeded";
+
+    /// Whether the error description belongs to a rejected create.
+    pub fn matches(description: &str) -> bool {
+        description.starts_with(Self::PREFIX)
+    }
+}
+
+impl fmt::Display for CapacityExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: the server already runs the maximum of {} containers",
+            Self::PREFIX,
+            self.max
+        )
+    }
+}
+
+impl Error for CapacityExceeded {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserve() {
+        let sut = ContainerCapacity::new(2);
+        let first = sut.reserve(0).unwrap();
+        let err = sut.reserve(1).unwrap_err();
+        assert!(CapacityExceeded::matches(&err.to_string()));
+
+        drop(first);
+        assert!(sut.reserve(1).is_ok());
+        assert!(sut.reserve(2).is_err());
+    }
+
+    #[test]
+    fn reserve_unlimited() {
+        let sut = ContainerCapacity::new(0);
+        assert!(sut.reserve(usize::MAX).is_ok());
+    }
+}
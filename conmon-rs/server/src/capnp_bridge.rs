@@ -0,0 +1,63 @@
+//! The bridge of the alternative RPC front ends to the Cap'n Proto service.
+//!
+//! The gRPC, ttrpc and HTTP front ends do not implement the methods themselves, but translate
+//! every call into a request of an in-process Cap'n Proto client, so that all APIs share the same
+//! implementation. The Cap'n Proto client is bound to the local set of the RPC backend, which is
+//! why the calls get forwarded to it through a channel.
+
+use capnp::Error;
+use conmon_common::conmon_capnp::conmon;
+use std::{future::Future, pin::Pin};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task,
+};
+
+/// A call of the Cap'n Proto client, which gets run on the local set of the RPC backend.
+pub type Call = Box<dyn FnOnce(conmon::Client) -> Pin<Box<dyn Future<Output = ()>>> + Send>;
+
+#[derive(Clone, Debug)]
+/// The sending side of the bridge, which can be shared by any amount of front ends.
+pub struct CapnpBridge {
+    calls: mpsc::UnboundedSender<Call>,
+}
+
+impl CapnpBridge {
+    /// Create a new bridge along with the receiver of its calls, which have to be run by
+    /// `run_calls`.
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<Call>) {
+        let (calls, calls_rx) = mpsc::unbounded_channel();
+        (Self { calls }, calls_rx)
+    }
+
+    /// Run the forwarded calls on the local set until all bridges got dropped.
+    pub async fn run_calls(client: conmon::Client, mut calls: mpsc::UnboundedReceiver<Call>) {
+        while let Some(call) = calls.recv().await {
+            task::spawn_local(call(client.clone()));
+        }
+    }
+
+    /// Forward the call to the Cap'n Proto client and wait for its result, which fails as
+    /// disconnected if the RPC backend is gone.
+    pub async fn call<F, R, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(conmon::Client) -> R + Send + 'static,
+        R: Future<Output = Result<T, Error>> + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.calls
+            .send(Box::new(move |client| {
+                Box::pin(async move {
+                    // The caller may be gone already.
+                    let _ = tx.send(f(client).await);
+                }) as Pin<Box<dyn Future<Output = ()>>>
+            }))
+            .map_err(|_| Self::disconnected())?;
+        rx.await.map_err(|_| Self::disconnected())?
+    }
+
+    fn disconnected() -> Error {
+        Error::disconnected("server is shutting down".into())
+    }
+}
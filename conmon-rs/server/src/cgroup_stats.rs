@@ -0,0 +1,315 @@
+//! Collection of container resource usage statistics from the cgroup v1 and v2 hierarchies.
+
+use crate::oom_watcher::{OOMWatcher, IS_CGROUP_V2};
+use anyhow::{Context, Result};
+use getset::CopyGetters;
+use std::{io::ErrorKind, path::Path};
+use tokio::fs;
+
+#[derive(Clone, Copy, CopyGetters, Debug, Default, Eq, PartialEq)]
+#[getset(get_copy = "pub")]
+/// A snapshot of the resource usage of a cgroup. Counters of controllers which are not enabled
+/// for the cgroup are zero, as well as unlimited limits.
+pub struct CgroupStats {
+    /// The total CPU time in microseconds.
+    cpu_usage_usec: u64,
+
+    /// The CPU time spent in user mode in microseconds.
+    cpu_user_usec: u64,
+
+    /// The CPU time spent in kernel mode in microseconds.
+    cpu_system_usec: u64,
+
+    /// The current memory usage in bytes.
+    memory_usage_bytes: u64,
+
+    /// The memory limit in bytes.
+    memory_limit_bytes: u64,
+
+    /// The current amount of processes.
+    pids_current: u64,
+
+    /// The limit of processes.
+    pids_limit: u64,
+
+    /// The amount of bytes read from block devices.
+    io_read_bytes: u64,
+
+    /// The amount of bytes written to block devices.
+    io_write_bytes: u64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// The groups of statistics, which get read from separate cgroup controllers.
+pub enum StatsGroup {
+    /// The CPU times.
+    Cpu,
+
+    /// The memory usage and limit.
+    Memory,
+
+    /// The amount of processes and their limit.
+    Pids,
+
+    /// The bytes read from and written to block devices.
+    Io,
+}
+
+impl StatsGroup {
+    /// All available groups.
+    pub const ALL: [Self; 4] = [Self::Cpu, Self::Memory, Self::Pids, Self::Io];
+
+    /// The cgroup v1 controller of the group.
+    fn v1_subsystem(self) -> &'static str {
+        match self {
+            Self::Cpu => "cpuacct",
+            Self::Memory => "memory",
+            Self::Pids => "pids",
+            Self::Io => "blkio",
+        }
+    }
+}
+
+impl CgroupStats {
+    /// Values from this one on are reported by cgroup v1 for unlimited limits, which is
+    /// `i64::MAX` rounded down to the page size.
+    const V1_UNLIMITED: u64 = 0x7fff_ffff_ffff_f000;
+
+    /// Read the groups of statistics of the cgroup the process with the provided PID belongs
+    /// to. The counters of other groups are zero.
+    pub async fn read(pid: u32, groups: &[StatsGroup]) -> Result<Self> {
+        let mut stats = Self::default();
+        if *IS_CGROUP_V2 {
+            let path = OOMWatcher::process_cgroup_subsystem_path_cgroup_v2(pid)
+                .await
+                .context("get cgroup path")?
+                .context("process not found")?;
+            for group in groups {
+                stats.read_v2(*group, &path).await?;
+            }
+        } else {
+            for group in groups {
+                let subsystem = group.v1_subsystem();
+                let path = OOMWatcher::process_cgroup_subsystem_path_cgroup_v1(pid, subsystem)
+                    .await
+                    .context(format!("get cgroup {} path", subsystem))?
+                    .context("process not found")?;
+                stats.read_v1(*group, &path).await?;
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Read the group of statistics of the cgroup v2 at the provided path.
+    async fn read_v2(&mut self, group: StatsGroup, path: &Path) -> Result<()> {
+        match group {
+            StatsGroup::Cpu => {
+                if let Some(cpu) = Self::read_file(&path.join("cpu.stat")).await? {
+                    self.cpu_usage_usec = Self::keyed_value(&cpu, "usage_usec")?;
+                    self.cpu_user_usec = Self::keyed_value(&cpu, "user_usec")?;
+                    self.cpu_system_usec = Self::keyed_value(&cpu, "system_usec")?;
+                }
+            }
+            StatsGroup::Memory => {
+                self.memory_usage_bytes = Self::read_value(&path.join("memory.current")).await?;
+                self.memory_limit_bytes = Self::read_value(&path.join("memory.max")).await?;
+            }
+            StatsGroup::Pids => {
+                self.pids_current = Self::read_value(&path.join("pids.current")).await?;
+                self.pids_limit = Self::read_value(&path.join("pids.max")).await?;
+            }
+            StatsGroup::Io => {
+                // Every line contains the counters of a single device, for example
+                // `8:0 rbytes=1 wbytes=2 rios=3 wios=4 dbytes=0 dios=0`.
+                if let Some(io) = Self::read_file(&path.join("io.stat")).await? {
+                    for field in io.split_whitespace() {
+                        match field.split_once('=') {
+                            Some(("rbytes", value)) => self.io_read_bytes += Self::parse(value)?,
+                            Some(("wbytes", value)) => self.io_write_bytes += Self::parse(value)?,
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the group of statistics of the cgroup v1 controller at the provided path.
+    async fn read_v1(&mut self, group: StatsGroup, path: &Path) -> Result<()> {
+        let nsec_to_usec = |x: u64| x / 1000;
+        let limit = |x: u64| if x >= Self::V1_UNLIMITED { 0 } else { x };
+
+        match group {
+            StatsGroup::Cpu => {
+                self.cpu_usage_usec =
+                    nsec_to_usec(Self::read_value(&path.join("cpuacct.usage")).await?);
+                self.cpu_user_usec =
+                    nsec_to_usec(Self::read_value(&path.join("cpuacct.usage_user")).await?);
+                self.cpu_system_usec =
+                    nsec_to_usec(Self::read_value(&path.join("cpuacct.usage_sys")).await?);
+            }
+            StatsGroup::Memory => {
+                self.memory_usage_bytes =
+                    Self::read_value(&path.join("memory.usage_in_bytes")).await?;
+                self.memory_limit_bytes =
+                    limit(Self::read_value(&path.join("memory.limit_in_bytes")).await?);
+            }
+            StatsGroup::Pids => {
+                self.pids_current = Self::read_value(&path.join("pids.current")).await?;
+                self.pids_limit = Self::read_value(&path.join("pids.max")).await?;
+            }
+            StatsGroup::Io => {
+                // Every line contains a single counter of a device, for example `8:0 Read 1`,
+                // followed by a line with the total of all devices.
+                let io_service_bytes = path.join("blkio.throttle.io_service_bytes");
+                if let Some(io) = Self::read_file(&io_service_bytes).await? {
+                    for line in io.lines() {
+                        match line.split_whitespace().collect::<Vec<_>>()[..] {
+                            [_, "Read", value] => self.io_read_bytes += Self::parse(value)?,
+                            [_, "Write", value] => self.io_write_bytes += Self::parse(value)?,
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the content of the file at the provided path, which is `None` if it does not exist.
+    async fn read_file(path: &Path) -> Result<Option<String>> {
+        match fs::read_to_string(path).await {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context(format!("read {}", path.display())),
+        }
+    }
+
+    /// Read the single value file at the provided path, which is zero if it does not exist.
+    async fn read_value(path: &Path) -> Result<u64> {
+        match Self::read_file(path).await? {
+            Some(content) => {
+                Self::parse(content.trim()).context(format!("parse {}", path.display()))
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Returns the value of the key in a flat keyed file, which is zero if the key is missing.
+    fn keyed_value(content: &str, key: &str) -> Result<u64> {
+        content
+            .lines()
+            .find_map(|line| match line.split_once(' ') {
+                Some((k, value)) if k == key => Some(Self::parse(value)),
+                _ => None,
+            })
+            .unwrap_or(Ok(0))
+    }
+
+    /// Parse a single value, where `max` means unlimited and results in zero.
+    fn parse(value: &str) -> Result<u64> {
+        if value == "max" {
+            return Ok(0);
+        }
+        value
+            .parse()
+            .context(format!("parse cgroup value '{}'", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn read_v2() -> Result<()> {
+        let dir = tempdir()?;
+        for (name, content) in [
+            (
+                "cpu.stat",
+                "usage_usec 300\nuser_usec 200\nsystem_usec 100\n",
+            ),
+            ("memory.current", "4096\n"),
+            ("memory.max", "max\n"),
+            ("pids.current", "3\n"),
+            ("pids.max", "100\n"),
+            (
+                "io.stat",
+                "8:0 rbytes=10 wbytes=20 rios=1 wios=2 dbytes=0 dios=0\n\
+                 8:16 rbytes=1 wbytes=2 rios=1 wios=1 dbytes=0 dios=0\n",
+            ),
+        ] {
+            std::fs::write(dir.path().join(name), content)?;
+        }
+
+        let mut stats = CgroupStats::default();
+        for group in StatsGroup::ALL {
+            stats.read_v2(group, dir.path()).await?;
+        }
+        assert_eq!(stats.cpu_usage_usec(), 300);
+        assert_eq!(stats.cpu_user_usec(), 200);
+        assert_eq!(stats.cpu_system_usec(), 100);
+        assert_eq!(stats.memory_usage_bytes(), 4096);
+        assert_eq!(stats.memory_limit_bytes(), 0);
+        assert_eq!(stats.pids_current(), 3);
+        assert_eq!(stats.pids_limit(), 100);
+        assert_eq!(stats.io_read_bytes(), 11);
+        assert_eq!(stats.io_write_bytes(), 22);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_v1() -> Result<()> {
+        let dir = tempdir()?;
+        for (name, content) in [
+            ("cpuacct.usage", "3000000\n"),
+            ("cpuacct.usage_user", "2000000\n"),
+            ("cpuacct.usage_sys", "1000000\n"),
+            ("memory.usage_in_bytes", "4096\n"),
+            ("memory.limit_in_bytes", "9223372036854771712\n"),
+            ("pids.current", "3\n"),
+            (
+                "blkio.throttle.io_service_bytes",
+                "8:0 Read 10\n8:0 Write 20\n8:0 Sync 30\n8:0 Total 30\nTotal 30\n",
+            ),
+        ] {
+            std::fs::write(dir.path().join(name), content)?;
+        }
+
+        let mut stats = CgroupStats::default();
+        for group in StatsGroup::ALL {
+            stats.read_v1(group, dir.path()).await?;
+        }
+        assert_eq!(stats.cpu_usage_usec(), 3000);
+        assert_eq!(stats.cpu_user_usec(), 2000);
+        assert_eq!(stats.cpu_system_usec(), 1000);
+        assert_eq!(stats.memory_usage_bytes(), 4096);
+        assert_eq!(stats.memory_limit_bytes(), 0);
+        assert_eq!(stats.pids_current(), 3);
+        assert_eq!(stats.pids_limit(), 0);
+        assert_eq!(stats.io_read_bytes(), 10);
+        assert_eq!(stats.io_write_bytes(), 20);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_single_group() -> Result<()> {
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("memory.current"), "4096\n")?;
+        std::fs::write(dir.path().join("pids.current"), "invalid\n")?;
+
+        let mut stats = CgroupStats::default();
+        stats.read_v2(StatsGroup::Memory, dir.path()).await?;
+        assert_eq!(
+            stats,
+            CgroupStats {
+                memory_usage_bytes: 4096,
+                ..Default::default()
+            }
+        );
+        assert!(stats.read_v2(StatsGroup::Pids, dir.path()).await.is_err());
+        Ok(())
+    }
+}
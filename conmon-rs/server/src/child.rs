@@ -1,10 +1,11 @@
-use crate::container_io::SharedContainerIO;
-use getset::{CopyGetters, Getters};
-use std::path::PathBuf;
+use crate::{container_io::SharedContainerIO, container_labels::ContainerLabels};
+use getset::{CopyGetters, Getters, Setters};
+use nix::sys::signal::Signal;
+use std::{path::PathBuf, sync::Arc};
 use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 
-#[derive(Debug, CopyGetters, Getters)]
+#[derive(Debug, CopyGetters, Getters, Setters)]
 pub struct Child {
     #[getset(get = "pub")]
     id: String,
@@ -29,6 +30,26 @@ pub struct Child {
 
     #[getset(get = "pub")]
     token: CancellationToken,
+
+    #[getset(get = "pub", set = "pub")]
+    /// The ID of the exec session, if the child is one.
+    exec_session_id: Option<String>,
+
+    #[getset(get = "pub", set = "pub")]
+    /// The command executed by the exec session, which is empty for containers.
+    command: Vec<String>,
+
+    #[getset(get = "pub", set = "pub")]
+    /// The labels and annotations of the container, which exec sessions inherit.
+    labels: Arc<ContainerLabels>,
+
+    #[getset(get_copy = "pub", set = "pub")]
+    /// The amount of times the container got restarted by its restart policy.
+    restarts: u32,
+
+    #[getset(get_copy = "pub", set = "pub")]
+    /// The signal sent to the container when stopping it.
+    stop_signal: Signal,
 }
 
 impl Child {
@@ -52,6 +73,11 @@ impl Child {
             io,
             cleanup_cmd,
             token,
+            exec_session_id: None,
+            command: vec![],
+            labels: Default::default(),
+            restarts: 0,
+            stop_signal: Signal::SIGTERM,
         }
     }
 }
@@ -2,6 +2,11 @@
 use crate::{
     child::Child,
     container_io::{ContainerIO, ContainerIOType, SharedContainerIO},
+    container_labels::ContainerLabels,
+    events::{EventKind, EVENTS},
+    extra_fd::{ExtraFd, ExtraFdPipe},
+    lifecycle_event::LifecycleEvent,
+    metrics::METRICS,
     oom_watcher::OOMWatcher,
 };
 use anyhow::{bail, format_err, Context, Result};
@@ -14,15 +19,21 @@ use nix::{
         signal::{kill, Signal},
         wait::{waitpid, WaitStatus},
     },
-    unistd::{getpgid, Pid},
+    unistd::{dup2, getpgid, Pid},
 };
 use std::{
     ffi::OsStr,
     fmt::Write,
+    io::ErrorKind,
+    os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
     process::Stdio,
     str,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
 };
 use tokio::{
     fs::{self, File},
@@ -56,6 +67,67 @@ impl ChildReaper {
         Ok(r)
     }
 
+    /// Retrieve all tracked containers and exec sessions along with their container IDs.
+    pub fn list(&self) -> Result<Vec<(String, ReapableChild)>> {
+        let locked_grandchildren = &self.grandchildren().clone();
+        let lock = lock!(locked_grandchildren);
+        Ok(lock
+            .iter_all()
+            .flat_map(|(id, children)| children.iter().map(move |x| (id.clone(), x.clone())))
+            .collect())
+    }
+
+    /// The amount of tracked containers, without their exec sessions.
+    pub fn containers(&self) -> Result<usize> {
+        let lock = lock!(self.grandchildren);
+        Ok(lock
+            .iter_all()
+            .filter(|(_, children)| children.iter().any(|x| x.exec_session_id().is_none()))
+            .count())
+    }
+
+    /// The amount of running containers, without the exited and stopped ones as well as exec
+    /// sessions.
+    pub fn running_containers(&self) -> Result<usize> {
+        let lock = lock!(self.grandchildren);
+        let mut running = 0;
+        for child in lock.iter_all().flat_map(|(_, children)| children) {
+            if child.exec_session_id().is_none() && !child.stopped() && child.exit()?.is_none() {
+                running += 1;
+            }
+        }
+        Ok(running)
+    }
+
+    /// Retrieve the exec session of the container with the provided IDs.
+    pub fn get_exec_session(&self, id: &str, exec_session_id: &str) -> Result<ReapableChild> {
+        let locked_grandchildren = &self.grandchildren().clone();
+        let lock = lock!(locked_grandchildren);
+        let r = lock
+            .get_vec(id)
+            .and_then(|children| {
+                children
+                    .iter()
+                    .find(|x| x.exec_session_id().as_deref() == Some(exec_session_id))
+            })
+            .context("exec session not available")?
+            .clone();
+        drop(lock);
+        Ok(r)
+    }
+
+    /// Retrieve the exec sessions of the container in the order they got started.
+    pub fn exec_sessions(&self, id: &str) -> Result<Vec<ReapableChild>> {
+        let lock = lock!(self.grandchildren);
+        Ok(lock
+            .get_vec(id)
+            .context("container not available")?
+            .iter()
+            .filter(|x| x.exec_session_id().is_some())
+            .cloned()
+            .collect())
+    }
+
     pub async fn create_child<P, I, S>(
         &self,
         cmd: P,
@@ -68,16 +140,74 @@ impl ChildReaper {
         I: IntoIterator<Item = S>,
         S: AsRef<OsStr>,
     {
+        let streams = matches!(container_io.typ(), ContainerIOType::Streams(_));
         let mut cmd = Command::new(cmd);
-        cmd.args(args);
-        let mut child = cmd
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .context("spawn child process: {}")?;
+        // Kill the runtime if the request gets cancelled before it exited.
+        cmd.args(args).kill_on_drop(true);
+        match container_io.take_stdin_file() {
+            Some(file) if streams => cmd.stdin(file),
+            _ if streams && !container_io.stdin() => cmd.stdin(Stdio::null()),
+            _ => cmd.stdin(Stdio::piped()),
+        };
+        match container_io.take_stdout_file() {
+            Some(file) if streams => cmd.stdout(file),
+            _ => cmd.stdout(Stdio::piped()),
+        };
+        let stderr_file = container_io.take_stderr_file();
+        if container_io.merge_stderr() && streams {
+            cmd.stderr(Stdio::null());
+            // SAFETY: dup2 is async-signal-safe and the closure does not allocate.
+            unsafe {
+                cmd.pre_exec(|| {
+                    dup2(libc::STDOUT_FILENO, libc::STDERR_FILENO)?;
+                    Ok(())
+                });
+            }
+        } else if let Some(file) = stderr_file.filter(|_| streams) {
+            cmd.stderr(file);
+        } else {
+            cmd.stderr(Stdio::piped());
+        }
+
+        // The write ends of the extra fds get numbers above all extra fds, which ensures that
+        // duplicating them does not overwrite the write end of another one.
+        let min_fd = container_io
+            .extra_fds()
+            .iter()
+            .map(ExtraFd::fd)
+            .max()
+            .unwrap_or_default()
+            + 1;
+        let mut extra_fd_pipes = vec![];
+        for extra_fd in container_io.extra_fds() {
+            extra_fd_pipes.push(
+                extra_fd
+                    .open(min_fd)
+                    .await
+                    .context(format!("open extra fd {}", extra_fd.fd()))?,
+            );
+        }
+        if !extra_fd_pipes.is_empty() {
+            let targets = extra_fd_pipes
+                .iter()
+                .map(ExtraFdPipe::dup_target)
+                .collect::<Vec<_>>();
+            // SAFETY: dup2 is async-signal-safe and the closure does not allocate.
+            unsafe {
+                cmd.pre_exec(move || {
+                    for (fd, target) in &targets {
+                        dup2(*fd, *target)?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+        let mut child = cmd.spawn().context("spawn child process: {}")?;
 
         let token = CancellationToken::new();
+        for pipe in extra_fd_pipes {
+            pipe.spawn(container_io.metrics().clone(), token.clone())?;
+        }
 
         match container_io.typ_mut() {
             ContainerIOType::Terminal(ref mut terminal) => {
@@ -135,16 +265,39 @@ impl ChildReaper {
         let (exit_tx, exit_rx) = reapable_grandchild.watch()?;
 
         map.insert(child.id().clone(), reapable_grandchild);
-        let cleanup_grandchildren = locked_grandchildren.clone();
-        let pid = child.pid();
 
-        task::spawn(
-            async move {
-                exit_tx.subscribe().recv().await?;
-                Self::forget_grandchild(&cleanup_grandchildren, pid)
-            }
-            .instrument(debug_span!("watch_grandchild", pid)),
-        );
+        // Containers are kept after they exited until they get removed, whereas exec sessions
+        // are forgotten right away.
+        if child.exec_session_id().is_some() {
+            let cleanup_grandchildren = locked_grandchildren.clone();
+            let pid = child.pid();
+            task::spawn(
+                async move {
+                    exit_tx.subscribe().recv().await?;
+                    Self::forget_grandchild(&cleanup_grandchildren, pid)
+                }
+                .instrument(debug_span!("watch_grandchild", pid)),
+            );
+        }
+        Ok(exit_rx)
+    }
+
+    /// Track the restarted container in place of its previous process, which keeps the exec
+    /// sessions and the stop signal of the container. Fails if the container got removed or
+    /// stopped meanwhile.
+    pub fn watch_restarted(&self, child: Child) -> Result<Receiver<ExitChannelData>> {
+        let mut map = lock!(self.grandchildren);
+        let previous = map
+            .get_vec_mut(child.id())
+            .and_then(|children| children.iter_mut().find(|x| x.exec_session_id().is_none()))
+            .context(format!("container {} not available", child.id()))?;
+        if previous.stopped() {
+            bail!("container {} got stopped", child.id())
+        }
+        let mut reapable_grandchild = ReapableChild::from_child(&child);
+        reapable_grandchild.stop_signal = previous.stop_signal;
+        let (_, exit_rx) = reapable_grandchild.watch()?;
+        *previous = reapable_grandchild;
         Ok(exit_rx)
     }
 
@@ -153,32 +306,183 @@ impl ChildReaper {
         grandchild_pid: u32,
     ) -> Result<()> {
         let mut map = lock!(locked_grandchildren);
-        map.retain(|_, v| v.pid == grandchild_pid);
+        map.retain(|_, v| v.pid != grandchild_pid);
         Ok(())
     }
 
-    pub fn kill_grandchildren(&self, s: Signal) -> Result<()> {
-        debug!("Killing grandchildren");
-        let grandchildren = lock!(self.grandchildren);
-        let grandchildren_iter = grandchildren.iter();
-        for (_, grandchild) in grandchildren_iter {
-            let span = debug_span!("kill_grandchild", pid = grandchild.pid);
-            let _enter = span.enter();
-            debug!("Killing single grandchild");
-            kill_grandchild(grandchild.pid, s);
-            futures::executor::block_on(
-                async {
-                    if let Err(e) = grandchild.close().await {
-                        error!("Unable to close grandchild: {:#}", e)
+    /// Remove the container along with its exec sessions, which stops all of their tasks, removes
+    /// their attach sockets, flushes their logs and frees the ID for reuse. Running processes get
+    /// killed if `force` is set, otherwise the removal fails. Returns the amount of killed
+    /// processes.
+    pub async fn remove(&self, id: &str, force: bool) -> Result<usize> {
+        let children = {
+            let mut map = lock!(self.grandchildren);
+            let running = map
+                .get_vec(id)
+                .context("container not available")?
+                .iter()
+                .any(|child| !matches!(child.exit_code(), Ok(Some(_))));
+            if running && !force {
+                bail!("container {} is still running", id)
+            }
+            map.remove(id).unwrap_or_default()
+        };
+
+        let mut killed = 0;
+        for child in &children {
+            if !matches!(child.exit_code(), Ok(Some(_))) {
+                debug!(pid = child.pid, "Killing running grandchild");
+                kill_grandchild(child.pid, Signal::SIGKILL);
+                killed += 1;
+            }
+            if let Err(e) = child.close().await {
+                error!(pid = child.pid, "Unable to close grandchild: {:#}", e)
+            }
+            child.token().cancel();
+
+            let logger = child.io().logger().await;
+            if let Err(e) = logger.write().await.flush().await {
+                error!(pid = child.pid, "Unable to flush log: {:#}", e)
+            }
+            for path in child.io().attach().await.socket_paths()? {
+                match fs::remove_file(&path).await {
+                    Err(e) if e.kind() != ErrorKind::NotFound => {
+                        warn!("Unable to remove attach socket {}: {}", path.display(), e)
                     }
+                    _ => {}
                 }
-                .instrument(debug_span!("close", signal = s.as_str())),
-            );
-            debug!("Done killing single grandchild");
+            }
+        }
+        debug!("Removed container {} with {} killed processes", id, killed);
+        Ok(killed)
+    }
+
+    /// The maximum time to wait for a container to exit after killing it.
+    const KILL_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Stop the container by sending the signal to its process group and killing it using
+    /// `SIGKILL` if it did not exit within the timeout. Returns the exit of the container and
+    /// whether it got killed.
+    pub async fn stop(&self, id: &str, s: Signal, timeout: Duration) -> Result<(ChildExit, bool)> {
+        let child = self.get(id)?;
+        child.stopped.store(true, Ordering::SeqCst);
+        if let Some(exit) = child.exit()? {
+            debug!(pid = child.pid, "Container exited already");
+            return Ok((exit, false));
+        }
+
+        debug!(pid = child.pid, "Stopping container using {}", s);
+        kill_grandchild(child.pid, s);
+        if let Some(exit) = child.wait_exit(Instant::now() + timeout).await? {
+            return Ok((exit, false));
+        }
+
+        debug!(pid = child.pid, "Killing container after stop timeout");
+        kill_grandchild(child.pid, Signal::SIGKILL);
+        let exit = child
+            .wait_exit(Instant::now() + Self::KILL_TIMEOUT)
+            .await?
+            .context(format!("container {} did not exit after being killed", id))?;
+        Ok((exit, true))
+    }
+
+    /// Free the ID of an exited container for a new one by removing the exited container along
+    /// with its exec sessions. Fails if the container is still running.
+    pub async fn replace(&self, id: &str) -> Result<()> {
+        self.ensure_replaceable(id)?;
+        if self.get(id).is_ok() {
+            debug!("Replacing exited container {}", id);
+            self.remove(id, true).await?;
         }
-        debug!("Done killing all grandchildren");
         Ok(())
     }
+
+    /// Ensure that no running container uses the ID.
+    pub fn ensure_replaceable(&self, id: &str) -> Result<()> {
+        match self.get(id) {
+            Ok(child) if child.exit()?.is_none() => bail!("container {} already exists", id),
+            _ => Ok(()),
+        }
+    }
+
+    /// Remove the exited container with the provided PID after the time to live, unless it got
+    /// restarted, replaced or removed meanwhile.
+    pub async fn expire(&self, id: &str, pid: u32, ttl: Duration) {
+        time::sleep(ttl).await;
+        match self.get(id) {
+            Ok(child) if child.pid() == pid && matches!(child.exit(), Ok(Some(_))) => {}
+            _ => return,
+        }
+        debug!("Removing container {} after it expired", id);
+        if let Err(e) = self.remove(id, true).await {
+            error!("Unable to remove expired container {}: {:#}", id, e)
+        }
+    }
+
+    /// The interval for checking whether all grandchildren exited while draining.
+    const DRAIN_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Send the provided signal to all running grandchildren, wait up to the timeout for them to
+    /// exit, kill the remaining ones using `SIGKILL` and flush the logs of all of them afterwards.
+    pub async fn drain(&self, timeout: Duration, s: Signal) -> Result<DrainSummary> {
+        debug!("Draining grandchildren using {}", s);
+        let deadline = Instant::now() + timeout;
+        let running = |grandchildren: &[(String, ReapableChild)]| -> Vec<ReapableChild> {
+            grandchildren
+                .iter()
+                .map(|(_, child)| child.clone())
+                .filter(|child| !matches!(child.exit_code(), Ok(Some(_))))
+                .collect()
+        };
+        for grandchild in running(&self.list()?) {
+            debug!(pid = grandchild.pid, "Signaling running grandchild");
+            kill_grandchild(grandchild.pid, s);
+        }
+
+        let (grandchildren, running) = loop {
+            let grandchildren = self.list()?;
+            let running = running(&grandchildren);
+            let now = Instant::now();
+            if running.is_empty() || now >= deadline {
+                break (grandchildren, running);
+            }
+            time::sleep_until(deadline.min(now + Self::DRAIN_INTERVAL)).await;
+        };
+
+        for grandchild in &running {
+            debug!(pid = grandchild.pid, "Killing remaining grandchild");
+            kill_grandchild(grandchild.pid, Signal::SIGKILL);
+            if let Err(e) = grandchild.close().await {
+                error!(pid = grandchild.pid, "Unable to close grandchild: {:#}", e)
+            }
+        }
+
+        for (_, grandchild) in &grandchildren {
+            let logger = grandchild.io().logger().await;
+            let mut locked_logger = logger.write().await;
+            if let Err(e) = locked_logger.flush().await {
+                error!(pid = grandchild.pid, "Unable to flush log: {:#}", e)
+            }
+        }
+
+        let summary = DrainSummary {
+            exited: grandchildren.len().saturating_sub(running.len()),
+            killed: running.len(),
+        };
+        debug!("Done draining grandchildren: {:?}", summary);
+        Ok(summary)
+    }
+}
+
+#[derive(Clone, Copy, CopyGetters, Debug, Default, Eq, PartialEq)]
+#[getset(get_copy = "pub")]
+/// The outcome of draining all grandchildren.
+pub struct DrainSummary {
+    /// The amount of grandchildren which exited within the timeout.
+    exited: usize,
+
+    /// The amount of grandchildren which got killed after the deadline.
+    killed: usize,
 }
 
 pub fn kill_grandchild(raw_pid: u32, s: Signal) {
@@ -205,13 +509,17 @@ type TaskHandle = Arc<Mutex<Option<Vec<JoinHandle<()>>>>>;
 
 #[derive(Clone, CopyGetters, Debug, Getters, Setters)]
 pub struct ReapableChild {
+    #[getset(get = "pub")]
+    /// The ID of the container.
+    id: String,
+
     #[getset(get)]
     exit_paths: Vec<PathBuf>,
 
     #[getset(get)]
     oom_exit_paths: Vec<PathBuf>,
 
-    #[getset(get_copy)]
+    #[getset(get_copy = "pub")]
     pid: u32,
 
     #[getset(get = "pub")]
@@ -227,6 +535,72 @@ pub struct ReapableChild {
 
     #[getset(get = "pub")]
     cleanup_cmd: Vec<String>,
+
+    #[getset(get = "pub")]
+    exec_session_id: Option<String>,
+
+    #[getset(get = "pub")]
+    /// The command executed by the exec session.
+    command: Vec<String>,
+
+    #[getset(get = "pub")]
+    /// The labels and annotations of the container.
+    labels: Arc<ContainerLabels>,
+
+    #[getset(get_copy = "pub")]
+    /// The amount of times the container got restarted by its restart policy.
+    restarts: u32,
+
+    #[getset(get_copy = "pub")]
+    /// The signal sent to the container when stopping it.
+    stop_signal: Signal,
+
+    /// Whether the container got stopped, which prevents restarting it.
+    stopped: Arc<AtomicBool>,
+
+    #[getset(get_copy = "pub")]
+    /// The time at which the reaper started tracking the child.
+    created: SystemTime,
+
+    /// The time at which the child got first observed running, which is set right away for exec
+    /// sessions.
+    started: Arc<Mutex<Option<SystemTime>>>,
+
+    /// The exit of the child, which is set once it exited.
+    exit: Arc<Mutex<Option<ChildExit>>>,
+
+    /// The channel notified after the exit got set, available once the child is watched.
+    exit_tx: Option<Sender<ExitChannelData>>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// The lifecycle state of a tracked child.
+pub enum ChildState {
+    /// The runtime created the container, which waits to get started.
+    Created,
+
+    /// The container or exec session is running.
+    Running,
+
+    /// The container or exec session exited.
+    Exited,
+}
+
+#[derive(Clone, Copy, CopyGetters, Debug)]
+#[getset(get_copy = "pub")]
+/// The exit of a tracked child.
+pub struct ChildExit {
+    /// The exit code, which is 128 plus the signal number if a signal terminated the child.
+    code: i32,
+
+    /// The signal which terminated the child.
+    signal: Option<Signal>,
+
+    /// Whether the child got killed because it ran out of memory.
+    oom_killed: bool,
+
+    /// The time at which the child exited.
+    finished: SystemTime,
 }
 
 #[derive(Clone, CopyGetters, Debug, Getters, Setters)]
@@ -244,6 +618,7 @@ pub struct ExitChannelData {
 impl ReapableChild {
     pub fn from_child(child: &Child) -> Self {
         Self {
+            id: child.id().clone(),
             exit_paths: child.exit_paths().clone(),
             oom_exit_paths: child.oom_exit_paths().clone(),
             pid: child.pid(),
@@ -252,9 +627,87 @@ impl ReapableChild {
             token: child.token().clone(),
             task: None,
             cleanup_cmd: child.cleanup_cmd().to_vec(),
+            exec_session_id: child.exec_session_id().clone(),
+            command: child.command().clone(),
+            labels: child.labels().clone(),
+            restarts: child.restarts(),
+            stop_signal: child.stop_signal(),
+            stopped: Default::default(),
+            created: SystemTime::now(),
+            started: Arc::new(Mutex::new(
+                child.exec_session_id().is_some().then(SystemTime::now),
+            )),
+            exit: Default::default(),
+            exit_tx: None,
         }
     }
 
+    /// Returns the exit code of the child, or `None` if it is still running.
+    pub fn exit_code(&self) -> Result<Option<i32>> {
+        Ok(lock!(self.exit).map(|x| x.code()))
+    }
+
+    /// Returns the exit of the child, or `None` if it is still running.
+    pub fn exit(&self) -> Result<Option<ChildExit>> {
+        Ok(*lock!(self.exit))
+    }
+
+    /// Wait until the child exited or the deadline elapsed and returns its exit, or `None` if
+    /// it is still running.
+    async fn wait_exit(&self, deadline: Instant) -> Result<Option<ChildExit>> {
+        // Subscribe before checking the exit, which gets set before the notification is sent.
+        let mut exit_rx = self
+            .exit_tx
+            .as_ref()
+            .context("child is not watched")?
+            .subscribe();
+        if let Some(exit) = self.exit()? {
+            return Ok(Some(exit));
+        }
+        if time::timeout_at(deadline, exit_rx.recv()).await.is_err() {
+            return Ok(None);
+        }
+        self.exit()
+    }
+
+    /// Returns whether the container got stopped.
+    pub fn stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Returns the time at which the child got first observed running.
+    pub fn started(&self) -> Result<Option<SystemTime>> {
+        Ok(*lock!(self.started))
+    }
+
+    /// Returns the lifecycle state of the child. A container counts as created as long as its
+    /// process is still the one of the `runtime`, which waits for the container to get started.
+    pub fn state(&self, runtime: &Path) -> Result<ChildState> {
+        if self.exit()?.is_some() {
+            return Ok(ChildState::Exited);
+        }
+        let mut started = lock!(self.started);
+        if started.is_some() {
+            return Ok(ChildState::Running);
+        }
+
+        let comm = match std::fs::read(format!("/proc/{}/comm", self.pid)) {
+            Ok(comm) => comm,
+            // The process exited, but did not get reaped yet.
+            Err(_) => return Ok(ChildState::Running),
+        };
+        let runtime_name = runtime.file_name().map(OsStrExt::as_bytes);
+        if let Some(runtime_name) = runtime_name.filter(|x| !x.is_empty()) {
+            // The kernel truncates the command name to 15 bytes, while runtimes like runc set
+            // names like `runc:[2:INIT]` for the waiting process.
+            if comm.starts_with(&runtime_name[..runtime_name.len().min(15)]) {
+                return Ok(ChildState::Created);
+            }
+        }
+        *started = Some(SystemTime::now());
+        Ok(ChildState::Running)
+    }
+
     pub async fn close(&self) -> Result<()> {
         debug!("Waiting for tasks to close");
         if let Some(t) = self.task.clone() {
@@ -281,11 +734,16 @@ impl ReapableChild {
         let timeout = *self.timeout();
         let stop_token = self.token().clone();
         let mut cleanup_cmd_raw = self.cleanup_cmd().clone();
+        let io = self.io().clone();
+        let exit_state = self.exit.clone();
+        let id = self.id().clone();
+        let exec_session_id = self.exec_session_id().clone();
 
         let task = task::spawn(
             async move {
                 debug!("Running task");
                 let mut exit_code: i32 = -1;
+                let mut signal = None;
                 let mut oomed = false;
                 let mut timed_out = false;
                 let (oom_tx, mut oom_rx) = tokio::sync::mpsc::channel(1);
@@ -299,8 +757,9 @@ impl ReapableChild {
 
                 let closure = async {
                     let (code, oom) = tokio::join!(wait_for_exit_code, oom_rx.recv());
-                    if let Ok(code) = code {
+                    if let Ok((code, sig)) = code {
                         exit_code = code;
+                        signal = sig;
                     }
                     if let Some(event) = oom {
                         oomed = event.oom;
@@ -310,12 +769,24 @@ impl ReapableChild {
                     if time::timeout_at(timeout, closure).await.is_err() {
                         timed_out = true;
                         exit_code = -3;
+                        signal = Some(Signal::SIGKILL);
                         kill_grandchild(pid, Signal::SIGKILL);
                     }
                 } else {
                     closure.await;
                 }
                 oom_watcher.stop().await;
+                METRICS.record_reaped(oomed);
+                Self::publish_exit_events(&id, exec_session_id.as_deref(), exit_code, oomed);
+                if let Ok(mut state) = exit_state.lock() {
+                    *state = Some(ChildExit {
+                        code: exit_code,
+                        signal,
+                        oom_killed: oomed,
+                        finished: SystemTime::now(),
+                    });
+                }
+                Self::write_exit_events(&io, exit_code, oomed).await;
 
                 let exit_channel_data = ExitChannelData {
                     exit_code,
@@ -353,11 +824,39 @@ impl ReapableChild {
             .context("no tasks available")?
             .push(task);
         self.task = Some(tasks);
+        self.exit_tx = Some(exit_tx.clone());
 
         Ok((exit_tx, exit_rx))
     }
 
-    async fn spawn_cleanup_process(raw_cmd: &mut Vec<String>) {
+    /// Publish the OOM and exit events to the subscribed RPC clients.
+    fn publish_exit_events(id: &str, exec_session_id: Option<&str>, exit_code: i32, oomed: bool) {
+        let exec_session_id = exec_session_id.unwrap_or_default();
+        if oomed {
+            EVENTS.publish_exec(id, exec_session_id, EventKind::OomKilled);
+        }
+        EVENTS.publish_exec(id, exec_session_id, EventKind::Exited(exit_code));
+    }
+
+    /// Write the OOM and exit lifecycle events into the container log.
+    async fn write_exit_events(io: &SharedContainerIO, exit_code: i32, oomed: bool) {
+        let logger = io.logger().await;
+        let mut locked_logger = logger.write().await;
+        if oomed {
+            if let Err(e) = locked_logger.write_event(LifecycleEvent::OomKilled).await {
+                error!("Unable to write OOM event: {:#}", e);
+            }
+        }
+        if let Err(e) = locked_logger
+            .write_event(LifecycleEvent::Exited(exit_code))
+            .await
+        {
+            error!("Unable to write exit event: {:#}", e);
+        }
+    }
+
+    /// Run the cleanup command in the background without waiting for it.
+    pub async fn spawn_cleanup_process(raw_cmd: &mut Vec<String>) {
         let mut cleanup_cmd = Command::new(raw_cmd.remove(0));
 
         raw_cmd.iter().for_each(|arg| {
@@ -379,7 +878,8 @@ impl ReapableChild {
         });
     }
 
-    fn wait_for_exit_code(token: &CancellationToken, pid: u32) -> i32 {
+    /// Wait for the child to exit, returning its exit code and the signal which terminated it.
+    fn wait_for_exit_code(token: &CancellationToken, pid: u32) -> (i32, Option<Signal>) {
         debug!("Waiting for exit code");
         const FAILED_EXIT_CODE: i32 = -3;
         loop {
@@ -387,12 +887,12 @@ impl ReapableChild {
                 Ok(WaitStatus::Exited(_, exit_code)) => {
                     debug!("Exited {}", exit_code);
                     token.cancel();
-                    return exit_code;
+                    return (exit_code, None);
                 }
                 Ok(WaitStatus::Signaled(_, sig, _)) => {
                     debug!("Signaled");
                     token.cancel();
-                    return (sig as i32) + 128;
+                    return ((sig as i32) + 128, Some(sig));
                 }
                 Ok(_) => {
                     continue;
@@ -404,13 +904,14 @@ impl ReapableChild {
                 Err(err) => {
                     error!("Unable to waitpid on {:#}", err);
                     token.cancel();
-                    return FAILED_EXIT_CODE;
+                    return (FAILED_EXIT_CODE, None);
                 }
             };
         }
     }
 
-    async fn write_to_exit_paths(code: i32, paths: &[PathBuf]) -> Result<()> {
+    /// Write the exit code into all exit paths.
+    pub async fn write_to_exit_paths(code: i32, paths: &[PathBuf]) -> Result<()> {
         let paths = paths.to_owned();
         let tasks: Vec<_> = paths
             .into_iter()
@@ -444,3 +945,515 @@ impl ReapableChild {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        attach::{AttachOptions, SharedContainerAttach},
+        container_io::{BufferSizes, Message},
+        container_log::ContainerLog,
+        extra_fd::ExtraFdSink,
+    };
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn create_child_merge_stderr() -> Result<()> {
+        let dir = tempdir()?;
+        let pidfile = dir.path().join("pidfile");
+        let mut container_io = ContainerIO::new(
+            false,
+            ContainerLog::new(),
+            SharedContainerAttach::default(),
+            BufferSizes::default(),
+        )?;
+        container_io.set_merge_stderr(true);
+
+        let script = format!(
+            "echo out; echo err >&2; echo out; echo 42 > {}",
+            pidfile.display()
+        );
+        let (pid, token) = ChildReaper::default()
+            .create_child("sh", ["-c", &script], &mut container_io, &pidfile)
+            .await?;
+        assert_eq!(pid, 42);
+
+        let streams = match container_io.typ_mut() {
+            ContainerIOType::Streams(streams) => streams,
+            ContainerIOType::Terminal(_) => bail!("no streams"),
+        };
+        let mut stdout = vec![];
+        while stdout != b"out\nerr\nout\n" {
+            match time::timeout(Duration::from_secs(5), streams.message_rx_stdout.recv()).await? {
+                Some(Message::Data(data)) => stdout.extend_from_slice(&data),
+                message => bail!("unexpected message {:?}", message),
+            }
+        }
+        assert!(streams.message_rx_stderr.try_recv().is_err());
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn create_child_extra_fds() -> Result<()> {
+        let dir = tempdir()?;
+        let pidfile = dir.path().join("pidfile");
+        let (first, second) = (dir.path().join("first"), dir.path().join("second"));
+        let mut container_io = ContainerIO::new(
+            false,
+            ContainerLog::new(),
+            SharedContainerAttach::default(),
+            BufferSizes::default(),
+        )?;
+        container_io.set_extra_fds(vec![
+            ExtraFd::new(3, ExtraFdSink::File(first.clone()))?,
+            ExtraFd::new(4, ExtraFdSink::File(second.clone()))?,
+        ]);
+
+        let script = format!(
+            "printf a >&3; printf bc >&4; echo 42 > {}",
+            pidfile.display()
+        );
+        let (pid, token) = ChildReaper::default()
+            .create_child("sh", ["-c", &script], &mut container_io, &pidfile)
+            .await?;
+        assert_eq!(pid, 42);
+
+        let metrics = container_io.metrics();
+        for _ in 0..100 {
+            if metrics.stats()?.extra_fds() == &[(3, 1), (4, 2)] {
+                break;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(metrics.stats()?.extra_fds(), &[(3, 1), (4, 2)]);
+        assert_eq!(std::fs::read_to_string(first)?, "a");
+        assert_eq!(std::fs::read_to_string(second)?, "bc");
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn create_child_stdin_file() -> Result<()> {
+        let dir = tempdir()?;
+        let pidfile = dir.path().join("pidfile");
+        let stdin = dir.path().join("stdin");
+        std::fs::write(&stdin, "hello\n")?;
+        let mut container_io = ContainerIO::new(
+            false,
+            ContainerLog::new(),
+            SharedContainerAttach::default(),
+            BufferSizes::default(),
+        )?;
+        container_io.set_stdin_file(Some(std::fs::File::open(&stdin)?));
+
+        let script = format!("echo 42 > {}; cat", pidfile.display());
+        let (pid, token) = ChildReaper::default()
+            .create_child("sh", ["-c", &script], &mut container_io, &pidfile)
+            .await?;
+        assert_eq!(pid, 42);
+        assert!(container_io.take_stdin_file().is_none());
+
+        let streams = match container_io.typ_mut() {
+            ContainerIOType::Streams(streams) => streams,
+            ContainerIOType::Terminal(_) => bail!("no streams"),
+        };
+        let mut stdout = vec![];
+        loop {
+            match time::timeout(Duration::from_secs(5), streams.message_rx_stdout.recv()).await? {
+                Some(Message::Data(data)) => stdout.extend_from_slice(&data),
+                Some(Message::Done) => break,
+                message => bail!("unexpected message {:?}", message),
+            }
+        }
+        assert_eq!(stdout, b"hello\n");
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn create_child_stdout_stderr_files() -> Result<()> {
+        let dir = tempdir()?;
+        let pidfile = dir.path().join("pidfile");
+        let (stdout, stderr) = (dir.path().join("stdout"), dir.path().join("stderr"));
+        let mut container_io = ContainerIO::new(
+            false,
+            ContainerLog::new(),
+            SharedContainerAttach::default(),
+            BufferSizes::default(),
+        )?;
+        container_io.set_stdout_file(Some(std::fs::File::create(&stdout)?));
+        container_io.set_stderr_file(Some(std::fs::File::create(&stderr)?));
+
+        let script = format!("echo out; echo err >&2; echo 42 > {}", pidfile.display());
+        let (pid, token) = ChildReaper::default()
+            .create_child("sh", ["-c", &script], &mut container_io, &pidfile)
+            .await?;
+        assert_eq!(pid, 42);
+        assert!(container_io.take_stdout_file().is_none());
+        assert_eq!(std::fs::read_to_string(stdout)?, "out\n");
+        assert_eq!(std::fs::read_to_string(stderr)?, "err\n");
+
+        let streams = match container_io.typ_mut() {
+            ContainerIOType::Streams(streams) => streams,
+            ContainerIOType::Terminal(_) => bail!("no streams"),
+        };
+        assert!(streams.message_rx_stdout.try_recv().is_err());
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn create_child_without_stdin() -> Result<()> {
+        let dir = tempdir()?;
+        let pidfile = dir.path().join("pidfile");
+        let mut container_io = ContainerIO::new(
+            false,
+            ContainerLog::new(),
+            SharedContainerAttach::default(),
+            BufferSizes::default(),
+        )?;
+        container_io.set_stdin(false);
+
+        let script = format!("echo 42 > {}; cat; echo done", pidfile.display());
+        let (_, token) = ChildReaper::default()
+            .create_child("sh", ["-c", &script], &mut container_io, &pidfile)
+            .await?;
+
+        let streams = match container_io.typ_mut() {
+            ContainerIOType::Streams(streams) => streams,
+            ContainerIOType::Terminal(_) => bail!("no streams"),
+        };
+        let message =
+            time::timeout(Duration::from_secs(5), streams.message_rx_stdout.recv()).await?;
+        assert_eq!(message, Some(Message::Data(b"done\n".to_vec().into())));
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_exec_session() -> Result<()> {
+        let sut = ChildReaper::default();
+        for (pid, exec_session_id) in [(1, None), (2, Some("exec")), (3, Some("exec2"))] {
+            let io = ContainerIO::new(
+                false,
+                ContainerLog::new(),
+                SharedContainerAttach::default(),
+                BufferSizes::default(),
+            )?;
+            let mut child = Child::new(
+                "id".into(),
+                pid,
+                vec![],
+                vec![],
+                None,
+                SharedContainerIO::new(io),
+                vec![],
+                CancellationToken::new(),
+            );
+            child.set_exec_session_id(exec_session_id.map(Into::into));
+            lock!(sut.grandchildren).insert("id".into(), ReapableChild::from_child(&child));
+        }
+
+        assert_eq!(sut.get("id")?.pid(), 1);
+        assert_eq!(sut.containers()?, 1);
+        assert_eq!(sut.running_containers()?, 1);
+        assert_eq!(sut.get_exec_session("id", "exec")?.pid(), 2);
+        assert!(sut.get_exec_session("id", "other").is_err());
+        assert!(sut.get_exec_session("other", "exec").is_err());
+        assert_eq!(
+            sut.exec_sessions("id")?
+                .iter()
+                .map(|x| x.pid())
+                .collect::<Vec<_>>(),
+            [2, 3]
+        );
+        assert!(sut.exec_sessions("other").is_err());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn remove() -> Result<()> {
+        use std::os::unix::process::CommandExt;
+
+        let sut = ChildReaper::default();
+        let process = std::process::Command::new("sleep")
+            .arg("10")
+            .process_group(0)
+            .spawn()?;
+        let io = ContainerIO::new(
+            false,
+            ContainerLog::new(),
+            SharedContainerAttach::default(),
+            BufferSizes::default(),
+        )?;
+        let mut exit_rx = sut.watch_grandchild(Child::new(
+            "id".into(),
+            process.id(),
+            vec![],
+            vec![],
+            None,
+            SharedContainerIO::new(io),
+            vec![],
+            CancellationToken::new(),
+        ))?;
+
+        assert!(sut.remove("id", false).await.is_err());
+        assert!(sut.get("id").is_ok());
+        assert_eq!(sut.remove("id", true).await?, 1);
+        assert_eq!(
+            exit_rx.recv().await?.exit_code,
+            128 + Signal::SIGKILL as i32
+        );
+        assert!(sut.get("id").is_err());
+        assert!(sut.remove("id", true).await.is_err());
+        Ok(())
+    }
+
+    /// Watch a shell running the script as container with the provided ID.
+    fn watch_script(
+        sut: &ChildReaper,
+        id: &str,
+        script: &str,
+    ) -> Result<(u32, Receiver<ExitChannelData>)> {
+        use std::os::unix::process::CommandExt;
+
+        let process = std::process::Command::new("sh")
+            .args(["-c", script])
+            .process_group(0)
+            .spawn()?;
+        let io = ContainerIO::new(
+            false,
+            ContainerLog::new(),
+            SharedContainerAttach::default(),
+            BufferSizes::default(),
+        )?;
+        let exit_rx = sut.watch_grandchild(Child::new(
+            id.into(),
+            process.id(),
+            vec![],
+            vec![],
+            None,
+            SharedContainerIO::new(io),
+            vec![],
+            CancellationToken::new(),
+        ))?;
+        Ok((process.id(), exit_rx))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn replace() -> Result<()> {
+        let dir = tempdir()?;
+        let sut = ChildReaper::default();
+        sut.replace("id").await?;
+        let (_, mut exit_rx) = watch_script(&sut, "id", "exit 1")?;
+        exit_rx.recv().await?;
+        assert_eq!(sut.containers()?, 1);
+        assert_eq!(sut.running_containers()?, 0);
+
+        // The exited container gets replaced by the new one, which can be attached to.
+        sut.replace("id").await?;
+        let (pid, _) = watch_script(&sut, "id", "exec sleep 10")?;
+        let child = sut.get("id")?;
+        assert_eq!(child.pid(), pid);
+        assert_eq!(child.exit()?.map(|x| x.code()), None);
+        assert_eq!(sut.running_containers()?, 1);
+        let socket_path = dir.path().join("attach");
+        child
+            .io()
+            .attach()
+            .await
+            .add(
+                &socket_path,
+                AttachOptions::default(),
+                CancellationToken::new(),
+            )
+            .await?;
+        assert!(socket_path.exists());
+
+        // Running containers can not be replaced.
+        assert!(sut.ensure_replaceable("id").is_err());
+        assert!(sut.replace("id").await.is_err());
+        assert_eq!(sut.get("id")?.pid(), pid);
+
+        assert_eq!(sut.remove("id", true).await?, 1);
+        assert!(!socket_path.exists());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn expire() -> Result<()> {
+        let sut = ChildReaper::default();
+        let (pid, mut exit_rx) = watch_script(&sut, "id", "exit 1")?;
+        exit_rx.recv().await?;
+
+        // Containers restarted or replaced meanwhile are kept.
+        sut.expire("id", pid + 1, Duration::ZERO).await;
+        assert!(sut.get("id").is_ok());
+
+        sut.expire("id", pid, Duration::from_millis(10)).await;
+        assert!(sut.get("id").is_err());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stop() -> Result<()> {
+        use std::os::unix::process::CommandExt;
+
+        let sut = ChildReaper::default();
+        for (id, script) in [
+            ("graceful", "exec sleep 10"),
+            ("killed", "trap '' TERM; sleep 10"),
+        ] {
+            let process = std::process::Command::new("sh")
+                .args(["-c", script])
+                .process_group(0)
+                .spawn()?;
+            let io = ContainerIO::new(
+                false,
+                ContainerLog::new(),
+                SharedContainerAttach::default(),
+                BufferSizes::default(),
+            )?;
+            sut.watch_grandchild(Child::new(
+                id.into(),
+                process.id(),
+                vec![],
+                vec![],
+                None,
+                SharedContainerIO::new(io),
+                vec![],
+                CancellationToken::new(),
+            ))?;
+        }
+        // Give the shell time to set up the trap.
+        time::sleep(Duration::from_millis(100)).await;
+
+        let (exit, killed) = sut
+            .stop("graceful", Signal::SIGTERM, Duration::from_secs(5))
+            .await?;
+        assert_eq!(exit.signal(), Some(Signal::SIGTERM));
+        assert!(!killed);
+
+        let (exit, killed) = sut
+            .stop("killed", Signal::SIGTERM, Duration::from_millis(200))
+            .await?;
+        assert_eq!(exit.code(), 128 + Signal::SIGKILL as i32);
+        assert!(killed);
+        assert!(sut.get("killed")?.stopped());
+
+        // Stopping an exited container returns its previous exit.
+        let (exit, killed) = sut
+            .stop("killed", Signal::SIGTERM, Duration::from_secs(5))
+            .await?;
+        assert_eq!(exit.signal(), Some(Signal::SIGKILL));
+        assert!(!killed);
+        assert!(sut
+            .stop("other", Signal::SIGTERM, Duration::ZERO)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn state() -> Result<()> {
+        use std::os::unix::process::CommandExt;
+
+        let sut = ChildReaper::default();
+        let process = std::process::Command::new("sleep")
+            .arg("10")
+            .process_group(0)
+            .spawn()?;
+        let io = ContainerIO::new(
+            false,
+            ContainerLog::new(),
+            SharedContainerAttach::default(),
+            BufferSizes::default(),
+        )?;
+        let mut exit_rx = sut.watch_grandchild(Child::new(
+            "id".into(),
+            process.id(),
+            vec![],
+            vec![],
+            None,
+            SharedContainerIO::new(io),
+            vec![],
+            CancellationToken::new(),
+        ))?;
+        let child = sut.get("id")?;
+
+        assert_eq!(child.state(Path::new("/bin/sleep"))?, ChildState::Created);
+        assert!(child.started()?.is_none());
+        assert_eq!(child.state(Path::new("/bin/runc"))?, ChildState::Running);
+        assert!(child.started()?.is_some());
+        assert_eq!(child.state(Path::new("/bin/sleep"))?, ChildState::Running);
+
+        kill_grandchild(process.id(), Signal::SIGKILL);
+        exit_rx.recv().await?;
+        assert_eq!(child.state(Path::new("/bin/sleep"))?, ChildState::Exited);
+        let exit = child.exit()?.context("no exit")?;
+        assert_eq!(exit.code(), 128 + Signal::SIGKILL as i32);
+        assert_eq!(exit.signal(), Some(Signal::SIGKILL));
+        assert!(!exit.oom_killed());
+        assert!(exit.finished() >= child.created());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn drain() -> Result<()> {
+        use std::os::unix::process::CommandExt;
+
+        let dir = tempdir()?;
+        let sut = ChildReaper::default();
+        let watch = |id: &str, script: &str| -> Result<Receiver<ExitChannelData>> {
+            // Killing the grandchildren signals their process group as well.
+            let process = std::process::Command::new("sh")
+                .args(["-c", script])
+                .process_group(0)
+                .spawn()?;
+            let io = ContainerIO::new(
+                false,
+                ContainerLog::new(),
+                SharedContainerAttach::default(),
+                BufferSizes::default(),
+            )?;
+            sut.watch_grandchild(Child::new(
+                id.into(),
+                process.id(),
+                vec![dir.path().join(id)],
+                vec![],
+                None,
+                SharedContainerIO::new(io),
+                vec![],
+                CancellationToken::new(),
+            ))
+        };
+
+        let mut exited = watch("exited", "exit 1")?;
+        assert_eq!(exited.recv().await?.exit_code, 1);
+        let mut terminated = watch("terminated", "sleep 10")?;
+        let mut killed = watch("killed", "trap '' TERM; sleep 10")?;
+
+        let summary = sut
+            .drain(Duration::from_millis(500), Signal::SIGTERM)
+            .await?;
+        assert_eq!(
+            summary,
+            DrainSummary {
+                exited: 2,
+                killed: 1
+            }
+        );
+        assert_eq!(
+            terminated.recv().await?.exit_code,
+            128 + Signal::SIGTERM as i32
+        );
+        assert_eq!(killed.recv().await?.exit_code, 128 + Signal::SIGKILL as i32);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("killed"))?,
+            (128 + Signal::SIGKILL as i32).to_string()
+        );
+        Ok(())
+    }
+}
@@ -1,9 +1,17 @@
 //! Configuration related structures
-use anyhow::{bail, Result};
+use crate::{
+    admission::Admission, audit_log::AuditLog, auth::Authenticator, container_io::BufferSizes,
+    http_api::HttpApi, method_filter, metrics,
+};
+use anyhow::{bail, Context, Result};
 use clap::{AppSettings, Parser};
 use getset::{CopyGetters, Getters, Setters};
 use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf};
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
 use strum::{EnumIter, EnumString, IntoEnumIterator, IntoStaticStr};
 
 macro_rules! prefix {
@@ -12,7 +20,9 @@ macro_rules! prefix {
     };
 }
 
-#[derive(CopyGetters, Debug, Deserialize, Eq, Getters, Parser, PartialEq, Serialize, Setters)]
+#[derive(
+    Clone, CopyGetters, Debug, Deserialize, Eq, Getters, Parser, PartialEq, Serialize, Setters,
+)]
 #[serde(rename_all = "kebab-case")]
 #[clap(
     after_help("More info at: https://github.com/containers/conmon-rs"),
@@ -100,6 +110,378 @@ pub struct Config {
     )]
     /// Select the cgroup manager to be used
     cgroup_manager: CgroupManager,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env(concat!(prefix!(), "ATTACH_IDLE_TIMEOUT")),
+        long("attach-idle-timeout"),
+        value_name("SECONDS")
+    )]
+    /// Close attach connections after the amount of seconds without any IO, 0 means never.
+    attach_idle_timeout: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env(concat!(prefix!(), "ATTACH_MAX_CLIENTS")),
+        long("attach-max-clients"),
+        value_name("CLIENTS")
+    )]
+    /// The maximum amount of concurrent attach clients per container, 0 means unlimited.
+    attach_max_clients: u32,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("8192"),
+        env(concat!(prefix!(), "ATTACH_PACKET_SIZE")),
+        long("attach-packet-size"),
+        value_name("BYTES")
+    )]
+    /// The maximum attach packet size for clients negotiating it.
+    attach_packet_size: usize,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("1048576"),
+        env(concat!(prefix!(), "MAX_ATTACH_REPLAY_SIZE")),
+        long("max-attach-replay-size"),
+        value_name("BYTES")
+    )]
+    /// The maximum attach replay size of containers, larger ones get rejected on create.
+    max_attach_replay_size: usize,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("16777216"),
+        env(concat!(prefix!(), "MAX_RECENT_OUTPUT_SIZE")),
+        long("max-recent-output-size"),
+        value_name("BYTES")
+    )]
+    /// The maximum recent output buffer size of containers, larger ones get rejected on create.
+    max_recent_output_size: usize,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("8192"),
+        env(concat!(prefix!(), "STDIN_BUFFER_SIZE")),
+        long("stdin-buffer-size"),
+        value_name("BYTES")
+    )]
+    /// The size of the buffer for reading the input of attach clients.
+    stdin_buffer_size: usize,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("1024"),
+        env(concat!(prefix!(), "OUTPUT_BUFFER_SIZE")),
+        long("output-buffer-size"),
+        value_name("BYTES")
+    )]
+    /// The size of the buffer for reading the stdout and stderr of containers.
+    output_buffer_size: usize,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env(concat!(prefix!(), "LOG_QUOTA")),
+        long("log-quota"),
+        value_name("BYTES")
+    )]
+    /// The maximum disk usage of all managed container log files, 0 means unlimited.
+    log_quota: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value(LogQuotaPolicy::Rotate.into()),
+        env(concat!(prefix!(), "LOG_QUOTA_POLICY")),
+        long("log-quota-policy"),
+        possible_values(LogQuotaPolicy::iter().map(|x| x.into()).collect::<Vec<&str>>()),
+        value_name("POLICY")
+    )]
+    /// The action taken on container logs if the log quota is exceeded.
+    log_quota_policy: LogQuotaPolicy,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env(concat!(prefix!(), "SHUTDOWN_TIMEOUT")),
+        long("shutdown-timeout"),
+        value_name("SECONDS")
+    )]
+    /// Wait the amount of seconds for running containers to exit after signaling them on shutdown,
+    /// before killing them using `SIGKILL`.
+    shutdown_timeout: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env(concat!(prefix!(), "RPC_TIMEOUT")),
+        long("rpc-timeout"),
+        value_name("SECONDS")
+    )]
+    /// Cancel RPC requests after the amount of seconds, unless they set their own timeout. The
+    /// timeout covers the whole command of exec requests. 0 disables the timeout.
+    rpc_timeout: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("4"),
+        env(concat!(prefix!(), "CREATE_PARALLELISM")),
+        long("create-parallelism"),
+        value_name("COUNT")
+    )]
+    /// The maximum amount of containers of a batch create request, which get created
+    /// concurrently, unless the request sets its own limit.
+    create_parallelism: usize,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("300"),
+        env(concat!(prefix!(), "IDEMPOTENCY_WINDOW")),
+        long("idempotency-window"),
+        value_name("SECONDS")
+    )]
+    /// Replay the result of a create to retries using the same idempotency key for the amount of
+    /// seconds after it finished. 0 ignores idempotency keys.
+    idempotency_window: u64,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env(concat!(prefix!(), "MAX_CONCURRENT_RPCS")),
+        long("max-concurrent-rpcs"),
+        value_name("COUNT")
+    )]
+    /// The maximum amount of concurrently processed RPCs invoking the runtime, where further
+    /// ones wait in a queue. 0 disables the limit.
+    max_concurrent_rpcs: usize,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("0"),
+        env(concat!(prefix!(), "MAX_CONTAINERS")),
+        long("max-containers"),
+        value_name("COUNT")
+    )]
+    /// The maximum amount of running containers, without exited and stopped ones as well as exec
+    /// sessions, after which creates get rejected with a `capacity exceeded` error. Applies to
+    /// every worker in isolation mode. 0 disables the limit.
+    max_containers: usize,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("300"),
+        env(concat!(prefix!(), "EXITED_CONTAINER_TTL")),
+        long("exited-container-ttl"),
+        value_name("SECONDS")
+    )]
+    /// Remove exited containers along with their attach sockets and buffers after the amount of
+    /// seconds, unless they got removed before. Containers with a restart policy expire once
+    /// they do not get restarted anymore.
+    exited_container_ttl: u64,
+
+    #[get = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "RPC_METHOD_LIMITS")),
+        long("rpc-method-limits"),
+        use_value_delimiter(true),
+        value_name("LIMITS")
+    )]
+    /// Comma separated concurrency limits of single RPC methods invoking the runtime, like
+    /// `createContainer=4,execSyncContainer=16`.
+    rpc_method_limits: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "METRICS_ADDRESS")),
+        long("metrics-address"),
+        value_name("ADDRESS")
+    )]
+    /// Serve Prometheus metrics on the TCP address, like `127.0.0.1:9090`, or unix domain socket
+    /// path, like `/run/conmonrs/metrics.sock`, at `/metrics`.
+    metrics_address: Option<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "HTTP_ADDRESS")),
+        long("http-address"),
+        value_name("ADDRESS")
+    )]
+    /// Serve a JSON over HTTP API for scripts and debugging tools on the loopback TCP address,
+    /// like `127.0.0.1:7080`, which requires an auth token file, or unix domain socket path, like
+    /// `/run/conmonrs/http.sock`.
+    http_address: Option<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "GRPC_SOCKET")),
+        long("grpc-socket"),
+        value_name("PATH")
+    )]
+    /// Serve the gRPC API on the unix domain socket path, which requires the `grpc` feature.
+    grpc_socket: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "TTRPC_SOCKET")),
+        long("ttrpc-socket"),
+        value_name("PATH")
+    )]
+    /// Serve the ttrpc API on the unix domain socket path, which requires the `ttrpc` feature.
+    ttrpc_socket: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "ALLOWED_METHODS")),
+        long("allowed-methods"),
+        use_value_delimiter(true),
+        value_name("METHODS")
+    )]
+    /// Comma separated RPC methods, like `version,containerStats`, which can be called over the
+    /// unix domain socket as well as the HTTP, gRPC and ttrpc APIs. All methods are allowed if
+    /// empty.
+    allowed_methods: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "ALLOWED_UIDS")),
+        long("allowed-uids"),
+        use_value_delimiter(true),
+        value_name("UIDS")
+    )]
+    /// Comma separated user IDs of the peers allowed to connect to the unix domain socket as well
+    /// as the HTTP, gRPC and ttrpc sockets. All peers are allowed if neither user nor group IDs
+    /// are configured.
+    allowed_uids: Vec<u32>,
+
+    #[get = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "ALLOWED_GIDS")),
+        long("allowed-gids"),
+        use_value_delimiter(true),
+        value_name("GIDS")
+    )]
+    /// Comma separated group IDs of the peers allowed to connect to the unix domain socket as
+    /// well as the HTTP, gRPC and ttrpc sockets.
+    allowed_gids: Vec<u32>,
+
+    #[get = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "AUTH_TOKEN_FILE")),
+        long("auth-token-file"),
+        value_name("PATH")
+    )]
+    /// File of tokens, one `TOKEN` or `NAME TOKEN` per line, where RPC clients have to send one
+    /// of them terminated by a newline before any Cap'n Proto message. Calls of the HTTP, gRPC
+    /// and ttrpc APIs have to send an `authorization: Bearer TOKEN` header or metadata.
+    auth_token_file: Option<PathBuf>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "TLS_ADDRESS")),
+        long("tls-address"),
+        value_name("ADDRESS")
+    )]
+    /// Serve the RPC API additionally on the TCP address, like `0.0.0.0:7070`, which requires
+    /// TLS with client certificates.
+    tls_address: Option<SocketAddr>,
+
+    #[get = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "TLS_CERT")),
+        long("tls-cert"),
+        value_name("PATH")
+    )]
+    /// PEM encoded certificate chain of the TLS listener.
+    tls_cert: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(env(concat!(prefix!(), "TLS_KEY")), long("tls-key"), value_name("PATH"))]
+    /// PEM encoded private key of the TLS listener.
+    tls_key: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "TLS_CLIENT_CA")),
+        long("tls-client-ca"),
+        value_name("PATH")
+    )]
+    /// PEM encoded CA certificates, which have to issue the certificates of TLS clients.
+    tls_client_ca: Option<PathBuf>,
+
+    #[get = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "TLS_ALLOWED_METHODS")),
+        long("tls-allowed-methods"),
+        use_value_delimiter(true),
+        value_name("METHODS")
+    )]
+    /// Comma separated RPC methods, which can be called over the TLS listener. All methods are
+    /// allowed if empty.
+    tls_allowed_methods: Vec<String>,
+
+    #[get_copy = "pub"]
+    #[clap(env(concat!(prefix!(), "VSOCK_PORT")), long("vsock-port"), value_name("PORT"))]
+    /// Serve the RPC API additionally on the vsock port, which allows reaching a server inside of
+    /// a virtual machine from its host. Clients have to negotiate the protocol version first.
+    vsock_port: Option<u32>,
+
+    #[get = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "VSOCK_ALLOWED_METHODS")),
+        long("vsock-allowed-methods"),
+        use_value_delimiter(true),
+        value_name("METHODS")
+    )]
+    /// Comma separated RPC methods, which can be called over the vsock listener. All methods are
+    /// allowed if empty.
+    vsock_allowed_methods: Vec<String>,
+
+    #[get = "pub"]
+    #[clap(env(concat!(prefix!(), "AUDIT_LOG")), long("audit-log"), value_name("PATH"))]
+    /// Record every RPC with its caller, container ID, parameter summary, result and duration
+    /// as JSON lines appended to the absolute file path, or in the systemd journal if set to
+    /// `journald`. Calls of the HTTP, gRPC and ttrpc APIs only record their transport as caller.
+    audit_log: Option<String>,
+
+    #[get_copy = "pub"]
+    #[clap(
+        default_value(Isolation::None.into()),
+        env(concat!(prefix!(), "ISOLATION")),
+        long("isolation"),
+        possible_values(Isolation::iter().map(|x| x.into()).collect::<Vec<&str>>()),
+        value_name("ISOLATION")
+    )]
+    /// Fork a dedicated worker server per pod or container, which the RPCs get forwarded to, so
+    /// that a crashing worker only loses the monitoring of its own containers. Containers without
+    /// `PodID` metadata get their own worker in pod isolation.
+    isolation: Isolation,
+
+    #[get = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "CONFIG_FILE")),
+        long("config-file"),
+        value_name("PATH")
+    )]
+    /// JSON file overriding the log level, buffer sizes, timeouts and attach defaults, which gets
+    /// re-read on SIGHUP. The log level, shutdown and RPC timeouts apply to the running server,
+    /// the other settings to new containers.
+    config_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+/// The settings of the config file, where missing ones keep their current value.
+struct ConfigOverrides {
+    log_level: Option<String>,
+    attach_idle_timeout: Option<u64>,
+    attach_max_clients: Option<u32>,
+    attach_packet_size: Option<usize>,
+    stdin_buffer_size: Option<usize>,
+    output_buffer_size: Option<usize>,
+    shutdown_timeout: Option<u64>,
+    rpc_timeout: Option<u64>,
 }
 
 #[derive(
@@ -148,6 +530,55 @@ pub enum CgroupManager {
     Cgroupfs,
 }
 
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    EnumIter,
+    EnumString,
+    Eq,
+    IntoStaticStr,
+    Hash,
+    PartialEq,
+    Serialize,
+)]
+#[strum(serialize_all = "lowercase")]
+/// Available actions if the log quota is exceeded.
+pub enum LogQuotaPolicy {
+    /// Drop the output written to file based container logs
+    Drop,
+
+    /// Truncate file based container logs and remove their rotated files
+    Rotate,
+}
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    EnumIter,
+    EnumString,
+    Eq,
+    IntoStaticStr,
+    Hash,
+    PartialEq,
+    Serialize,
+)]
+#[strum(serialize_all = "lowercase")]
+/// Available isolation modes of the containers.
+pub enum Isolation {
+    /// Monitor all containers in the main server
+    None,
+
+    /// Fork a worker server per pod
+    Pod,
+
+    /// Fork a worker server per container
+    Container,
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self::parse()
@@ -156,6 +587,7 @@ impl Default for Config {
 
 // Sync with `pkg/client/client.go`
 const SOCKET: &str = "conmon.sock";
+const FD_SOCKET: &str = "conmon-fd.sock";
 const PIDFILE: &str = "pidfile";
 
 impl Config {
@@ -177,16 +609,220 @@ impl Config {
             }
         }
 
+        self.buffer_sizes()?;
+
+        if self.create_parallelism() == 0 {
+            bail!("create parallelism has to be greater than 0")
+        }
+
         if self.socket().exists() {
             fs::remove_file(self.socket())?;
         }
 
+        if self.fd_socket().exists() {
+            fs::remove_file(self.fd_socket())?;
+        }
+
+        if let Some(grpc_socket) = self.grpc_socket() {
+            if !cfg!(feature = "grpc") {
+                bail!("gRPC socket requires conmonrs to be built with the grpc feature")
+            }
+            if grpc_socket.exists() {
+                fs::remove_file(grpc_socket)?;
+            }
+        }
+
+        if let Some(ttrpc_socket) = self.ttrpc_socket() {
+            if !cfg!(feature = "ttrpc") {
+                bail!("ttrpc socket requires conmonrs to be built with the ttrpc feature")
+            }
+            if ttrpc_socket.exists() {
+                fs::remove_file(ttrpc_socket)?;
+            }
+        }
+
+        if let Some(path) = self.auth_token_file() {
+            Authenticator::load(path)?;
+        }
+
+        Admission::new(self.max_concurrent_rpcs(), self.rpc_method_limits())
+            .context("validate RPC method limits")?;
+
+        method_filter::method_ids(self.allowed_methods()).context("validate allowed methods")?;
+        method_filter::method_ids(self.tls_allowed_methods())
+            .context("validate TLS allowed methods")?;
+        method_filter::method_ids(self.vsock_allowed_methods())
+            .context("validate vsock allowed methods")?;
+
+        if let Some(destination) = self.audit_log() {
+            AuditLog::validate(destination)?;
+        }
+
+        if self.tls_address().is_some() {
+            for (name, path) in [
+                ("certificate", self.tls_cert()),
+                ("key", self.tls_key()),
+                ("client CA", self.tls_client_ca()),
+            ] {
+                match path {
+                    Some(path) if !path.exists() => {
+                        bail!("TLS {} '{}' does not exist", name, path.display())
+                    }
+                    Some(_) => {}
+                    None => bail!("TLS address requires a {} to be configured", name),
+                }
+            }
+        }
+
+        if let Some(address) = self.metrics_address() {
+            if metrics::is_socket_path(address) {
+                if Path::new(address).exists() {
+                    fs::remove_file(address)?;
+                }
+            } else {
+                address
+                    .parse::<SocketAddr>()
+                    .context(format!("parse metrics address '{}'", address))?;
+            }
+        }
+
+        if let Some(address) = self.http_address() {
+            HttpApi::validate_address(address, self.auth_token_file().is_some())?;
+            if metrics::is_socket_path(address) && Path::new(address).exists() {
+                fs::remove_file(address)?;
+            }
+        }
+
         Ok(())
     }
+
+    /// Returns a copy of the configuration with the settings of the config file applied, if
+    /// one is configured.
+    pub fn reloaded(&self) -> Result<Self> {
+        let mut config = self.clone();
+        let path = match self.config_file() {
+            Some(path) => path,
+            None => return Ok(config),
+        };
+        let content = fs::read(path).context(format!("read config file '{}'", path.display()))?;
+        let overrides: ConfigOverrides = serde_json::from_slice(&content)
+            .context(format!("parse config file '{}'", path.display()))?;
+
+        if let Some(x) = overrides.log_level {
+            config.log_level = x;
+        }
+        if let Some(x) = overrides.attach_idle_timeout {
+            config.attach_idle_timeout = x;
+        }
+        if let Some(x) = overrides.attach_max_clients {
+            config.attach_max_clients = x;
+        }
+        if let Some(x) = overrides.attach_packet_size {
+            config.attach_packet_size = x;
+        }
+        if let Some(x) = overrides.stdin_buffer_size {
+            config.stdin_buffer_size = x;
+        }
+        if let Some(x) = overrides.output_buffer_size {
+            config.output_buffer_size = x;
+        }
+        if let Some(x) = overrides.shutdown_timeout {
+            config.shutdown_timeout = x;
+        }
+        if let Some(x) = overrides.rpc_timeout {
+            config.rpc_timeout = x;
+        }
+        config.buffer_sizes().context("validate buffer sizes")?;
+        Ok(config)
+    }
+
+    /// The default buffer sizes of the container IO.
+    pub fn buffer_sizes(&self) -> Result<BufferSizes> {
+        BufferSizes::new(
+            self.stdin_buffer_size(),
+            self.output_buffer_size(),
+            self.attach_packet_size(),
+        )
+    }
     pub fn socket(&self) -> PathBuf {
         self.runtime_dir().join(SOCKET)
     }
+    pub fn fd_socket(&self) -> PathBuf {
+        self.runtime_dir().join(FD_SOCKET)
+    }
     pub fn conmon_pidfile(&self) -> PathBuf {
         self.runtime_dir().join(PIDFILE)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn reloaded() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let config_file = format!("--config-file={}", file.path().display());
+        let sut = Config::parse_from([
+            "conmonrs",
+            "--runtime=/bin/true",
+            "--runtime-dir=/tmp",
+            "--stdin-buffer-size=4096",
+            config_file.as_str(),
+        ]);
+
+        fs::write(
+            file.path(),
+            r#"{"log-level": "debug", "output-buffer-size": 2048}"#,
+        )?;
+        let config = sut.reloaded()?;
+        assert_eq!(config.log_level(), "debug");
+        assert_eq!(config.stdin_buffer_size(), 4096);
+        assert_eq!(config.output_buffer_size(), 2048);
+
+        fs::write(file.path(), r#"{"output-buffer-size": 0}"#)?;
+        assert!(sut.reloaded().is_err());
+        fs::write(file.path(), r#"{"runtime": "/bin/false"}"#)?;
+        assert!(sut.reloaded().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_tls() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let runtime_dir = format!("--runtime-dir={}", dir.path().display());
+        let cert = NamedTempFile::new()?;
+        let tls_files = [
+            format!("--tls-cert={}", cert.path().display()),
+            format!("--tls-key={}", cert.path().display()),
+        ];
+        let parse = |args: &[&str]| {
+            Config::parse_from(
+                ["conmonrs", "--runtime=/bin/true", runtime_dir.as_str()]
+                    .iter()
+                    .chain(args),
+            )
+        };
+
+        let sut = parse(&["--tls-allowed-methods=version,containerStats"]);
+        assert_eq!(sut.tls_allowed_methods(), &["version", "containerStats"]);
+        assert!(sut.validate().is_ok());
+        assert!(parse(&["--allowed-methods=version,unknown"])
+            .validate()
+            .is_err());
+
+        let sut = parse(&["--tls-address=127.0.0.1:7070", &tls_files[0], &tls_files[1]]);
+        assert!(sut.validate().is_err());
+
+        let client_ca = format!("--tls-client-ca={}", cert.path().display());
+        let sut = parse(&[
+            "--tls-address=127.0.0.1:7070",
+            &tls_files[0],
+            &tls_files[1],
+            &client_ca,
+        ]);
+        assert!(sut.validate().is_ok());
+        Ok(())
+    }
+}
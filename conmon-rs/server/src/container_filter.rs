@@ -0,0 +1,115 @@
+//! Filters selecting the containers and exec sessions returned by the list RPC.
+
+use conmon_common::conmon_capnp::conmon::ContainerState;
+use getset::Setters;
+use std::collections::HashMap;
+
+#[derive(Debug, Default, Setters)]
+#[getset(set = "pub")]
+/// A filter of containers, where only the set predicates have to match.
+pub struct ContainerFilter {
+    /// The matching container IDs, where empty matches all.
+    ids: Vec<String>,
+
+    /// The matching `PodID` metadata, where empty matches all.
+    pod_id: String,
+
+    /// The matching states, where empty matches all.
+    states: Vec<ContainerState>,
+
+    /// The entries which have to be part of the container labels or metadata, where labels take
+    /// precedence over metadata of the same key.
+    labels: HashMap<String, String>,
+
+    /// Whether exec sessions never match.
+    exclude_exec_sessions: bool,
+}
+
+impl ContainerFilter {
+    /// The metadata key of the pod ID.
+    pub const POD_ID: &'static str = "PodID";
+
+    /// Whether the container or exec session matches the filter.
+    pub fn matches(
+        &self,
+        id: &str,
+        exec_session: bool,
+        state: ContainerState,
+        metadata: &HashMap<String, String>,
+        labels: &HashMap<String, String>,
+    ) -> bool {
+        (self.ids.is_empty() || self.ids.iter().any(|x| x == id))
+            && (self.pod_id.is_empty() || metadata.get(Self::POD_ID) == Some(&self.pod_id))
+            && (self.states.is_empty() || self.states.contains(&state))
+            && self
+                .labels
+                .iter()
+                .all(|(key, value)| labels.get(key).or_else(|| metadata.get(key)) == Some(value))
+            && !(self.exclude_exec_sessions && exec_session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn match_all() {
+        let sut = ContainerFilter::default();
+        let empty = HashMap::new();
+        assert!(sut.matches("id", false, ContainerState::Running, &empty, &empty));
+        assert!(sut.matches("id", true, ContainerState::Exited, &empty, &empty));
+    }
+
+    #[test]
+    fn match_predicates() {
+        let mut sut = ContainerFilter::default();
+        sut.set_ids(vec!["a".into(), "b".into()])
+            .set_pod_id("pod".into())
+            .set_states(vec![ContainerState::Running])
+            .set_labels(metadata(&[("app", "web")]))
+            .set_exclude_exec_sessions(true);
+        let matching = metadata(&[("PodID", "pod"), ("app", "web"), ("tier", "front")]);
+        let empty = HashMap::new();
+
+        assert!(sut.matches("b", false, ContainerState::Running, &matching, &empty));
+        assert!(!sut.matches("c", false, ContainerState::Running, &matching, &empty));
+        assert!(!sut.matches("b", true, ContainerState::Running, &matching, &empty));
+        assert!(!sut.matches("b", false, ContainerState::Exited, &matching, &empty));
+        assert!(!sut.matches(
+            "b",
+            false,
+            ContainerState::Running,
+            &metadata(&[("PodID", "other"), ("app", "web")]),
+            &empty
+        ));
+        assert!(!sut.matches(
+            "b",
+            false,
+            ContainerState::Running,
+            &metadata(&[("PodID", "pod"), ("app", "db")]),
+            &empty
+        ));
+        assert!(sut.matches(
+            "b",
+            false,
+            ContainerState::Running,
+            &metadata(&[("PodID", "pod"), ("app", "db")]),
+            &metadata(&[("app", "web")])
+        ));
+        assert!(!sut.matches(
+            "b",
+            false,
+            ContainerState::Running,
+            &matching,
+            &metadata(&[("app", "db")])
+        ));
+    }
+}
@@ -1,31 +1,56 @@
 use crate::{
-    attach::SharedContainerAttach, container_log::SharedContainerLog, streams::Streams,
+    attach::{AttachOptions, SharedContainerAttach},
+    container_log::SharedContainerLog,
+    extra_fd::ExtraFd,
+    flush_policy::{FlushBuffer, FlushPolicy},
+    metrics::METRICS,
+    streams::Streams,
     terminal::Terminal,
+    terminal_mode::{TerminalMode, TerminalModeChange},
+};
+use anyhow::{bail, format_err, Context, Result};
+use bytes::{Bytes, BytesMut};
+use futures::future;
+use getset::{CopyGetters, Getters, MutGetters, Setters};
+use memchr::memchr_iter;
+use nix::{
+    errno::Errno,
+    fcntl::{self, FcntlArg, OFlag, SpliceFFlags},
+    sys::stat::{self, SFlag},
+    unistd,
 };
-use anyhow::{bail, Context, Result};
-use getset::{Getters, MutGetters};
-use nix::errno::Errno;
 use std::{
+    collections::BTreeMap,
     fmt,
+    fs::File,
     marker::Unpin,
-    os::unix::io::{FromRawFd, RawFd},
+    mem,
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
 };
 use strum::AsRefStr;
 use tempfile::Builder;
 use tokio::{
-    fs::File,
-    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    io::{unix::AsyncFd, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     select,
     sync::{
-        mpsc::{UnboundedReceiver, UnboundedSender},
-        RwLock,
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        watch, RwLock,
     },
     time::{self, Instant},
 };
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
+
+macro_rules! lock {
+    ($x:expr) => {
+        $x.lock().map_err(|e| format_err!("{:#}", e))?
+    };
+}
 
 /// A shared container IO abstraction.
 #[derive(Debug, Clone)]
@@ -37,23 +62,61 @@ impl SharedContainerIO {
         Self(Arc::new(RwLock::new(io)))
     }
 
+    /// Take the output of the container IO for receiving it chunk by chunk, which keeps the
+    /// container IO available while waiting.
+    pub async fn output_receiver(&self) -> Result<OutputReceiver> {
+        let (stdout_rx, stderr_rx) = self.0.write().await.take_output_rx()?;
+        Ok(OutputReceiver {
+            stdout_rx: Some(stdout_rx),
+            stderr_rx,
+        })
+    }
+
+    /// Read the whole output until it ended, the timeout got reached or the token got
+    /// cancelled. The receivers get taken out of the container IO upfront, which keeps it
+    /// available while waiting, for example for resizing the terminal of exec sessions.
     pub async fn read_all_with_timeout(
         &self,
         timeout: Option<Instant>,
         token: CancellationToken,
     ) -> Result<(Vec<u8>, Vec<u8>, bool)> {
-        self.0
-            .write()
-            .await
-            .read_all_with_timeout(timeout, token)
-            .await
+        let (mut stdout_rx, mut stderr_rx) = self.0.write().await.take_output_rx()?;
+        Ok(ContainerIO::read_receivers_with_timeout(
+            timeout,
+            &mut stdout_rx,
+            stderr_rx.as_mut(),
+            token,
+        )
+        .await)
     }
 
     /// Resize the shared container IO to the provided with and height.
     /// Errors in case of no terminal containers.
     pub async fn resize(&self, width: u16, height: u16) -> Result<()> {
+        let io = self.0.read().await;
+        match io.typ() {
+            ContainerIOType::Terminal(t) => t.resize(width, height).context("resize terminal")?,
+            ContainerIOType::Streams(_) => bail!("container has no terminal"),
+        }
+        io.attach()
+            .resize(width, height)
+            .context("resize attach recordings")
+    }
+
+    /// Send the terminal master of the shared container IO to the unix socket at the provided
+    /// path. Errors in case of no terminal containers.
+    pub async fn send_terminal_master(&self, path: &Path) -> Result<()> {
+        match self.0.read().await.typ() {
+            ContainerIOType::Terminal(t) => t.send_master(path).await,
+            ContainerIOType::Streams(_) => bail!("container has no terminal"),
+        }
+    }
+
+    /// Change the terminal mode of the shared container IO and return the resulting one.
+    /// Errors in case of no terminal containers.
+    pub async fn set_terminal_mode(&self, change: TerminalModeChange) -> Result<TerminalMode> {
         match self.0.read().await.typ() {
-            ContainerIOType::Terminal(t) => t.resize(width, height).context("resize terminal"),
+            ContainerIOType::Terminal(t) => t.set_mode(&change).context("set terminal mode"),
             ContainerIOType::Streams(_) => bail!("container has no terminal"),
         }
     }
@@ -67,9 +130,29 @@ impl SharedContainerIO {
     pub async fn attach(&self) -> SharedContainerAttach {
         self.0.read().await.attach().clone()
     }
+
+    /// Retrieve the buffer sizes of the container IO.
+    pub async fn buffer_sizes(&self) -> BufferSizes {
+        self.0.read().await.buffer_sizes()
+    }
+
+    /// Returns whether the container has stdin.
+    pub async fn stdin(&self) -> bool {
+        self.0.read().await.stdin()
+    }
+
+    /// Take a snapshot of the IO throughput of the container.
+    pub async fn stats(&self) -> Result<IoStats> {
+        self.0.read().await.metrics().stats()
+    }
+
+    /// Pause or resume consuming the container output and return whether it was paused before.
+    pub async fn set_output_paused(&self, paused: bool) -> bool {
+        self.0.read().await.set_output_paused(paused)
+    }
 }
 
-#[derive(Debug, Getters, MutGetters)]
+#[derive(CopyGetters, Debug, Getters, MutGetters, Setters)]
 pub struct ContainerIO {
     #[getset(get = "pub", get_mut = "pub")]
     typ: ContainerIOType,
@@ -79,6 +162,229 @@ pub struct ContainerIO {
 
     #[getset(get = "pub")]
     attach: SharedContainerAttach,
+
+    #[getset(get_copy = "pub")]
+    buffer_sizes: BufferSizes,
+
+    #[getset(get_copy = "pub", set = "pub")]
+    /// Whether stderr gets written into the stdout pipe, which keeps both streams in their
+    /// original order and makes them appear as stdout. Terminals always merge them.
+    merge_stderr: bool,
+
+    #[getset(set = "pub")]
+    /// The file the container reads its stdin from instead of the attach clients, which gets
+    /// closed by its end. Terminals always read their stdin from the attach clients.
+    stdin_file: Option<File>,
+
+    #[getset(set = "pub")]
+    /// The file the container writes its stdout to instead of the log drivers and attach
+    /// clients. Terminals always write their output to the log drivers and attach clients.
+    stdout_file: Option<File>,
+
+    #[getset(set = "pub")]
+    /// The file the container writes its stderr to instead of the log drivers and attach
+    /// clients, which has no effect if stderr gets merged into stdout.
+    stderr_file: Option<File>,
+
+    #[getset(get_copy = "pub")]
+    /// Whether the container has stdin.
+    stdin: bool,
+
+    #[getset(get = "pub")]
+    /// The IO counters shared with the read loops.
+    metrics: Arc<IoMetrics>,
+
+    #[getset(get = "pub", set = "pub")]
+    /// The additional file descriptors passed to the container, whose data gets forwarded.
+    extra_fds: Vec<ExtraFd>,
+
+    /// Whether the read loops stop consuming the output, which makes the container block on
+    /// writing once the pipes are full.
+    output_paused: watch::Sender<bool>,
+}
+
+#[derive(Clone, Copy, CopyGetters, Debug, Eq, PartialEq)]
+/// The sizes of the buffers used for the container IO.
+pub struct BufferSizes {
+    #[getset(get_copy = "pub")]
+    /// The size of the buffer for reading the input of attach clients.
+    stdin: usize,
+
+    #[getset(get_copy = "pub")]
+    /// The size of the buffer for reading the stdout and stderr of the container.
+    output: usize,
+
+    #[getset(get_copy = "pub")]
+    /// The maximum attach packet size for clients negotiating it.
+    attach_packet: usize,
+}
+
+impl Default for BufferSizes {
+    fn default() -> Self {
+        Self {
+            stdin: 8192,
+            output: 1024,
+            attach_packet: 8192,
+        }
+    }
+}
+
+impl BufferSizes {
+    /// The minimum supported buffer size.
+    pub const MIN_SIZE: usize = 64;
+
+    /// The maximum supported buffer size.
+    pub const MAX_SIZE: usize = 1024 * 1024;
+
+    /// Create new buffer sizes, where the attach packet size has to be supported by the attach
+    /// protocol.
+    pub fn new(stdin: usize, output: usize, attach_packet: usize) -> Result<Self> {
+        Ok(Self {
+            stdin: Self::validate_size("stdin", stdin)?,
+            output: Self::validate_size("output", output)?,
+            attach_packet: AttachOptions::validate_packet_size(attach_packet)?,
+        })
+    }
+
+    /// Verify that the buffer size is supported.
+    fn validate_size(name: &str, size: usize) -> Result<usize> {
+        if !(Self::MIN_SIZE..=Self::MAX_SIZE).contains(&size) {
+            bail!(
+                "{} buffer size {} is not within {} and {}",
+                name,
+                size,
+                Self::MIN_SIZE,
+                Self::MAX_SIZE
+            )
+        }
+        Ok(size)
+    }
+}
+
+#[derive(Debug, Default)]
+/// The IO counters of a container, which are shared between its read loops.
+pub struct IoMetrics {
+    stdin_bytes: AtomicU64,
+    stdout: StreamMetrics,
+    stderr: StreamMetrics,
+    extra_fds: Mutex<BTreeMap<RawFd, u64>>,
+}
+
+#[derive(Debug, Default)]
+/// The counters of a single output stream.
+struct StreamMetrics {
+    bytes: AtomicU64,
+    lines: AtomicU64,
+    partial_lines: AtomicU64,
+    dropped_bytes: AtomicU64,
+}
+
+impl IoMetrics {
+    fn add(counter: &AtomicU64, value: usize) {
+        counter.fetch_add(value as u64, Ordering::Relaxed);
+    }
+
+    fn stream(&self, pipe: Pipe) -> &StreamMetrics {
+        match pipe {
+            Pipe::StdOut => &self.stdout,
+            Pipe::StdErr => &self.stderr,
+        }
+    }
+
+    /// Account output read from the container, which has been dropped by the log rate limit if
+    /// it did not get logged.
+    fn record_output(&self, pipe: Pipe, data: &[u8], logged: bool) {
+        METRICS.record_output(pipe, data.len());
+        let stream = self.stream(pipe);
+        Self::add(&stream.bytes, data.len());
+        if !logged {
+            Self::add(&stream.dropped_bytes, data.len());
+            return;
+        }
+        Self::add(&stream.lines, memchr_iter(b'\n', data).count());
+        if data.last() != Some(&b'\n') {
+            Self::add(&stream.partial_lines, 1);
+        }
+    }
+
+    /// Account output spliced into the log, whose lines are unknown.
+    fn record_spliced(&self, pipe: Pipe, len: usize) {
+        METRICS.record_output(pipe, len);
+        Self::add(&self.stream(pipe).bytes, len);
+    }
+
+    /// Account input written to the container.
+    fn record_input(&self, len: usize) {
+        METRICS.record_input(len);
+        Self::add(&self.stdin_bytes, len);
+    }
+
+    /// Account data forwarded from an extra file descriptor of the container.
+    pub fn record_extra_fd(&self, fd: RawFd, len: usize) -> Result<()> {
+        *lock!(self.extra_fds).entry(fd).or_default() += len as u64;
+        Ok(())
+    }
+
+    /// Take a snapshot of the current counters.
+    pub fn stats(&self) -> Result<IoStats> {
+        Ok(IoStats {
+            stdin_bytes: self.stdin_bytes.load(Ordering::Relaxed),
+            stdout: self.stdout.stats(),
+            stderr: self.stderr.stats(),
+            extra_fds: lock!(self.extra_fds)
+                .iter()
+                .map(|(fd, bytes)| (*fd, *bytes))
+                .collect(),
+        })
+    }
+}
+
+impl StreamMetrics {
+    fn stats(&self) -> StreamStats {
+        StreamStats {
+            bytes: self.bytes.load(Ordering::Relaxed),
+            lines: self.lines.load(Ordering::Relaxed),
+            partial_lines: self.partial_lines.load(Ordering::Relaxed),
+            dropped_bytes: self.dropped_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Clone, CopyGetters, Debug, Default, Eq, Getters, PartialEq)]
+/// A snapshot of the IO throughput of a container.
+pub struct IoStats {
+    #[getset(get_copy = "pub")]
+    /// The amount of input bytes written to the container.
+    stdin_bytes: u64,
+
+    #[getset(get_copy = "pub")]
+    /// The stdout counters, which include the stderr output for terminals.
+    stdout: StreamStats,
+
+    #[getset(get_copy = "pub")]
+    /// The stderr counters.
+    stderr: StreamStats,
+
+    #[getset(get = "pub")]
+    /// The amount of bytes forwarded per extra file descriptor, ordered by their number.
+    extra_fds: Vec<(RawFd, u64)>,
+}
+
+#[derive(Clone, Copy, CopyGetters, Debug, Default, Eq, PartialEq)]
+#[getset(get_copy = "pub")]
+/// A snapshot of the throughput of a single output stream.
+pub struct StreamStats {
+    /// The amount of bytes read from the container.
+    bytes: u64,
+
+    /// The amount of logged lines, where spliced output is not inspected.
+    lines: u64,
+
+    /// The amount of logged chunks ending without newline.
+    partial_lines: u64,
+
+    /// The amount of bytes dropped by the log rate limit.
+    dropped_bytes: u64,
 }
 
 #[derive(Debug)]
@@ -88,14 +394,55 @@ pub enum ContainerIOType {
     Streams(Streams),
 }
 
+#[derive(Debug)]
+/// The output of a container IO, received independently of it.
+pub struct OutputReceiver {
+    /// The stdout receiver, which is `None` once done.
+    stdout_rx: Option<UnboundedReceiver<Message>>,
+
+    /// The stderr receiver, which is `None` once done or for terminals.
+    stderr_rx: Option<UnboundedReceiver<Message>>,
+}
+
+impl OutputReceiver {
+    /// Receive the next output chunk of any pipe in their arrival order, or `None` once the
+    /// output of all pipes is done.
+    pub async fn recv(&mut self) -> Option<(Pipe, Bytes)> {
+        loop {
+            let (pipe, message) = select! {
+                m = Self::recv_pipe(&mut self.stdout_rx), if self.stdout_rx.is_some() => {
+                    (Pipe::StdOut, m)
+                }
+                m = Self::recv_pipe(&mut self.stderr_rx), if self.stderr_rx.is_some() => {
+                    (Pipe::StdErr, m)
+                }
+                else => return None,
+            };
+            match message {
+                Message::Data(data) => return Some((pipe, data)),
+                Message::Done if pipe == Pipe::StdOut => self.stdout_rx = None,
+                Message::Done => self.stderr_rx = None,
+            }
+        }
+    }
+
+    /// Receive the next message of the pipe, where closed pipes are done.
+    async fn recv_pipe(rx: &mut Option<UnboundedReceiver<Message>>) -> Message {
+        match rx {
+            Some(rx) => rx.recv().await.unwrap_or(Message::Done),
+            None => future::pending().await,
+        }
+    }
+}
+
 /// A message to be sent through the ContainerIO.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Message {
-    Data(Vec<u8>),
+    Data(Bytes),
     Done,
 }
 
-#[derive(AsRefStr, Clone, Copy, Debug)]
+#[derive(AsRefStr, Clone, Copy, Debug, Eq, PartialEq)]
 #[strum(serialize_all = "lowercase")]
 /// Available pipe types.
 pub enum Pipe {
@@ -127,27 +474,112 @@ impl From<Streams> for ContainerIOType {
 impl ContainerIO {
     const MAX_STDIO_STREAM_SIZE: usize = 16 * 1024 * 1024;
 
+    /// The maximum amount of bytes spliced at once, which is the default pipe capacity.
+    const SPLICE_LEN: usize = 64 * 1024;
+
     /// Create a new container IO instance.
-    pub fn new(terminal: bool, logger: SharedContainerLog) -> Result<Self> {
+    pub fn new(
+        terminal: bool,
+        logger: SharedContainerLog,
+        attach: SharedContainerAttach,
+        buffer_sizes: BufferSizes,
+    ) -> Result<Self> {
         let logger_clone = logger.clone();
-        let attach = SharedContainerAttach::default();
         let attach_clone = attach.clone();
+        let metrics = Arc::new(IoMetrics::default());
+        let metrics_clone = metrics.clone();
+        let (output_paused, paused_rx) = watch::channel(false);
         let typ = if terminal {
-            Terminal::new(logger_clone, attach_clone)
-                .context("create new terminal")?
-                .into()
+            Terminal::new(
+                logger_clone,
+                attach_clone,
+                metrics_clone,
+                paused_rx,
+                buffer_sizes.output(),
+            )
+            .context("create new terminal")?
+            .into()
         } else {
-            Streams::new(logger_clone, attach_clone)
-                .context("create new streams")?
-                .into()
+            Streams::new(
+                logger_clone,
+                attach_clone,
+                metrics_clone,
+                paused_rx,
+                buffer_sizes.output(),
+            )
+            .context("create new streams")?
+            .into()
         };
         Ok(Self {
             typ,
             logger,
             attach,
+            buffer_sizes,
+            merge_stderr: false,
+            stdin_file: None,
+            stdout_file: None,
+            stderr_file: None,
+            stdin: true,
+            metrics,
+            extra_fds: vec![],
+            output_paused,
         })
     }
 
+    /// Set the window size applied to the terminal before the workload starts, which is ignored
+    /// for containers without terminal.
+    pub fn set_initial_window_size(&mut self, width: u16, height: u16) -> Result<()> {
+        if let ContainerIOType::Terminal(terminal) = &mut self.typ {
+            terminal.set_initial_window_size(Some((width, height)));
+            self.attach
+                .resize(width, height)
+                .context("resize attach recordings")?;
+        }
+        Ok(())
+    }
+
+    /// Set whether the container has stdin. Without stdin, streams get /dev/null as stdin and
+    /// terminals get no input forwarded.
+    pub fn set_stdin(&mut self, stdin: bool) {
+        self.stdin = stdin;
+        if let ContainerIOType::Terminal(t) = &mut self.typ {
+            t.set_stdin(stdin);
+        }
+    }
+
+    /// Set the policy of forwarding the read output to the loggers and attach endpoints.
+    pub fn set_flush_policy(&mut self, flush_policy: FlushPolicy) {
+        match &mut self.typ {
+            ContainerIOType::Terminal(t) => {
+                t.set_flush_policy(flush_policy);
+            }
+            ContainerIOType::Streams(s) => {
+                s.set_flush_policy(flush_policy);
+            }
+        }
+    }
+
+    /// Pause or resume consuming the container output and return whether it was paused before.
+    /// Output already read gets still forwarded when pausing.
+    pub fn set_output_paused(&self, paused: bool) -> bool {
+        self.output_paused.send_replace(paused)
+    }
+
+    /// Take the file the container reads its stdin from, if set.
+    pub fn take_stdin_file(&mut self) -> Option<File> {
+        self.stdin_file.take()
+    }
+
+    /// Take the file the container writes its stdout to, if set.
+    pub fn take_stdout_file(&mut self) -> Option<File> {
+        self.stdout_file.take()
+    }
+
+    /// Take the file the container writes its stderr to, if set.
+    pub fn take_stderr_file(&mut self) -> Option<File> {
+        self.stderr_file.take()
+    }
+
     /// Generate a the temp file name without creating the file.
     pub fn temp_file_name(directory: Option<&Path>, prefix: &str, suffix: &str) -> Result<PathBuf> {
         let mut file = Builder::new();
@@ -171,22 +603,67 @@ impl ContainerIO {
         match self.typ_mut() {
             ContainerIOType::Terminal(t) => {
                 if let Some(message_rx) = t.message_rx_mut() {
-                    let (stdout, timed_out) =
-                        Self::read_stream_with_timeout(time_to_timeout, message_rx, token).await;
-                    Ok((stdout, vec![], timed_out))
+                    Ok(
+                        Self::read_receivers_with_timeout(time_to_timeout, message_rx, None, token)
+                            .await,
+                    )
                 } else {
                     bail!("read_all_with_timeout called before message_rx was registered");
                 }
             }
-            ContainerIOType::Streams(s) => {
-                let stdout_rx = &mut s.message_rx_stdout;
-                let stderr_rx = &mut s.message_rx_stderr;
+            ContainerIOType::Streams(s) => Ok(Self::read_receivers_with_timeout(
+                time_to_timeout,
+                &mut s.message_rx_stdout,
+                Some(&mut s.message_rx_stderr),
+                token,
+            )
+            .await),
+        }
+    }
+
+    /// Take the receivers of the stdout and stderr messages, where terminals have no separate
+    /// stderr. Streams keep closed receivers afterwards.
+    pub fn take_output_rx(
+        &mut self,
+    ) -> Result<(
+        UnboundedReceiver<Message>,
+        Option<UnboundedReceiver<Message>>,
+    )> {
+        match self.typ_mut() {
+            ContainerIOType::Terminal(t) => Ok((
+                t.message_rx_mut()
+                    .take()
+                    .context("terminal output not registered or already taken")?,
+                None,
+            )),
+            ContainerIOType::Streams(s) => Ok((
+                mem::replace(&mut s.message_rx_stdout, mpsc::unbounded_channel().1),
+                Some(mem::replace(
+                    &mut s.message_rx_stderr,
+                    mpsc::unbounded_channel().1,
+                )),
+            )),
+        }
+    }
+
+    async fn read_receivers_with_timeout(
+        time_to_timeout: Option<Instant>,
+        stdout_rx: &mut UnboundedReceiver<Message>,
+        stderr_rx: Option<&mut UnboundedReceiver<Message>>,
+        token: CancellationToken,
+    ) -> (Vec<u8>, Vec<u8>, bool) {
+        match stderr_rx {
+            None => {
+                let (stdout, timed_out) =
+                    Self::read_stream_with_timeout(time_to_timeout, stdout_rx, token).await;
+                (stdout, vec![], timed_out)
+            }
+            Some(stderr_rx) => {
                 let (stdout, stderr) = tokio::join!(
                     Self::read_stream_with_timeout(time_to_timeout, stdout_rx, token.clone()),
                     Self::read_stream_with_timeout(time_to_timeout, stderr_rx, token),
                 );
-                let timed_out = stdout.1 || stderr.1;
-                Ok((stdout.0, stderr.0, timed_out))
+                (stdout.0, stderr.0, stdout.1 || stderr.1)
             }
         }
     }
@@ -208,7 +685,7 @@ impl ContainerIO {
                                 timed_out = true;
                                 Message::Done
                             }
-                            Ok(None) => unreachable!(),
+                            Ok(None) => Message::Done,
                         }
                     }
                 } else {
@@ -226,7 +703,7 @@ impl ContainerIO {
                         Message::Data(data) => {
                             if let Some(future_len) = stdio.len().checked_add(data.len()) {
                                 if future_len < Self::MAX_STDIO_STREAM_SIZE {
-                                    stdio.extend(data)
+                                    stdio.extend_from_slice(&data)
                                 } else {
                                     break;
                                 }
@@ -245,50 +722,122 @@ impl ContainerIO {
         (stdio, timed_out)
     }
 
+    /// Forward the output of the reader to the logger and attach endpoints until the output
+    /// ends or the token got cancelled, where the flush policy decides when read output gets
+    /// forwarded. Pipes get spliced into the log destination without copying their output into
+    /// userspace, as long as the output is forwarded immediately and neither the logger nor any
+    /// attach endpoint has to inspect it. The output is not consumed while being paused.
+    #[allow(clippy::too_many_arguments)]
     pub async fn read_loop<T>(
         mut reader: T,
+        buf_size: usize,
         pipe: Pipe,
         logger: SharedContainerLog,
         message_tx: UnboundedSender<Message>,
         mut attach: SharedContainerAttach,
+        metrics: Arc<IoMetrics>,
+        mut paused: watch::Receiver<bool>,
+        flush_policy: FlushPolicy,
         token: CancellationToken,
     ) -> Result<()>
     where
-        T: AsyncRead + Unpin,
+        T: AsyncRead + AsRawFd + Unpin,
     {
-        let mut buf = vec![0; 1024];
+        let mut buf = BytesMut::with_capacity(buf_size);
+        let mut flush_buffer = FlushBuffer::new(flush_policy);
+
+        // Spliced output would overtake the output held back by the flush buffer.
+        let mut splice_source = match flush_policy {
+            FlushPolicy::Immediate => Self::splice_source(&reader),
+            _ => None,
+        };
 
         loop {
+            if *paused.borrow() {
+                // Pending output would otherwise be held back until resuming.
+                if let Some(data) = flush_buffer.take() {
+                    Self::forward(pipe, data, &logger, &mut attach, &message_tx, &metrics).await?;
+                }
+                debug!("Pausing output");
+                if !Self::wait_resumed(&mut paused, &token).await {
+                    debug!("Sending done because token cancelled");
+                    return Self::stop(&logger, &message_tx).await;
+                }
+                debug!("Resuming output");
+            }
+
+            if let Some(source) = &splice_source {
+                let spliced = select! {
+                    guard = source.readable() => {
+                        let mut guard = guard.context("wait for readable pipe")?;
+                        let locked_logger = logger.read().await;
+                        match locked_logger.splice_target(pipe) {
+                            Some(target) if attach.is_idle()? => {
+                                let spliced = fcntl::splice(
+                                    source.as_raw_fd(),
+                                    None,
+                                    target,
+                                    None,
+                                    Self::SPLICE_LEN,
+                                    SpliceFFlags::SPLICE_F_MOVE,
+                                );
+                                if spliced == Err(Errno::EAGAIN) {
+                                    guard.clear_ready();
+                                }
+                                Some(spliced)
+                            }
+                            _ => None,
+                        }
+                    }
+                    _ = token.cancelled() => {
+                        debug!("Sending done because token cancelled");
+                        return Self::stop(&logger, &message_tx).await;
+                    }
+                };
+                match spliced {
+                    Some(Ok(0)) => {
+                        debug!("Stopping splice because the pipe got closed");
+                        splice_source = None;
+                    }
+                    Some(Ok(n)) => {
+                        debug!("Spliced {} bytes", n);
+                        metrics.record_spliced(pipe, n);
+                        continue;
+                    }
+                    Some(Err(Errno::EAGAIN)) => continue,
+                    Some(Err(e)) => {
+                        warn!("Unable to splice {}, falling back to reading: {}", pipe, e);
+                        splice_source = None;
+                    }
+                    None => {}
+                }
+            }
+
+            // The allocation gets reclaimed once all receivers dropped the previous data.
+            buf.reserve(buf_size);
+            let deadline = flush_buffer.deadline();
             select! {
-                n = reader.read(&mut buf) => {
+                n = reader.read_buf(&mut buf) => {
                     match n {
                         Ok(n) if n > 0 => {
                             debug!("Read {} bytes", n);
-                            let data = &buf[..n];
-
-                            let mut locked_logger = logger.write().await;
-                            locked_logger
-                                .write(pipe, data)
-                                .await
-                                .context("write to log file")?;
-
-                            attach
-                                .write(pipe, data)
-                                .await
-                                .context("write to attach endpoints")?;
-
-                            message_tx
-                                .send(Message::Data(data.into()))
-                                .context("send data message")?;
+                            if let Some(data) = flush_buffer.push(buf.split().freeze()) {
+                                Self::forward(
+                                    pipe, data, &logger, &mut attach, &message_tx, &metrics,
+                                )
+                                .await?;
+                            }
                         }
                         Err(e) => match Errno::from_i32(e.raw_os_error().context("get OS error")?) {
                             Errno::EIO => {
                                 debug!("Stopping read loop");
-
-                                message_tx
-                                    .send(Message::Done)
-                                    .context("send done message")?;
-                                return Ok(());
+                                if let Some(data) = flush_buffer.take() {
+                                    Self::forward(
+                                        pipe, data, &logger, &mut attach, &message_tx, &metrics,
+                                    )
+                                    .await?;
+                                }
+                                return Self::stop(&logger, &message_tx).await;
                             }
                             Errno::EBADF => {
                                 return Err(Errno::EBADFD.into());
@@ -305,32 +854,146 @@ impl ContainerIO {
                         _ => {}
                     }
                 }
+                _ = time::sleep_until(deadline.unwrap_or_else(Instant::now)),
+                    if deadline.is_some() =>
+                {
+                    if let Some(data) = flush_buffer.take() {
+                        debug!("Forwarding {} bytes after the flush deadline", data.len());
+                        Self::forward(pipe, data, &logger, &mut attach, &message_tx, &metrics)
+                            .await?;
+                    }
+                }
                 _ = token.cancelled() => {
                     debug!("Sending done because token cancelled");
-                    message_tx
-                        .send(Message::Done)
-                        .context("send done message")?;
-                    return Ok(());
+                    if let Some(data) = flush_buffer.take() {
+                        Self::forward(pipe, data, &logger, &mut attach, &message_tx, &metrics)
+                            .await?;
+                    }
+                    return Self::stop(&logger, &message_tx).await;
                 }
             }
         }
     }
 
-    pub async fn read_loop_stdin(
-        fd: RawFd,
-        mut attach: SharedContainerAttach,
-        token: CancellationToken,
+    /// Wait until the output got resumed. Returns false if the token got cancelled meanwhile.
+    async fn wait_resumed(paused: &mut watch::Receiver<bool>, token: &CancellationToken) -> bool {
+        while *paused.borrow_and_update() {
+            select! {
+                changed = paused.changed() => {
+                    // The container IO is gone, which leaves nobody to resume the output.
+                    if changed.is_err() {
+                        return true;
+                    }
+                }
+                _ = token.cancelled() => return false,
+            }
+        }
+        true
+    }
+
+    /// Forward the output to the logger, the attach endpoints and the output receivers.
+    async fn forward(
+        pipe: Pipe,
+        data: Bytes,
+        logger: &SharedContainerLog,
+        attach: &mut SharedContainerAttach,
+        message_tx: &UnboundedSender<Message>,
+        metrics: &IoMetrics,
+    ) -> Result<()> {
+        let mut locked_logger = logger.write().await;
+        let logged = locked_logger
+            .write(pipe, &data)
+            .await
+            .context("write to log file")?;
+        metrics.record_output(pipe, &data, logged);
+
+        attach
+            .write(pipe, data.clone())
+            .await
+            .context("write to attach endpoints")?;
+
+        message_tx
+            .send(Message::Data(data))
+            .context("send data message")
+    }
+
+    /// Create the readiness source for splicing if the reader is a pipe. The file descriptor gets
+    /// duplicated, because the reader itself is already registered at the runtime.
+    fn splice_source<T>(reader: &T) -> Option<AsyncFd<File>>
+    where
+        T: AsRawFd,
+    {
+        let fd = reader.as_raw_fd();
+        let mode = SFlag::from_bits_truncate(stat::fstat(fd).ok()?.st_mode);
+        if mode & SFlag::S_IFMT != SFlag::S_IFIFO {
+            return None;
+        }
+        // Blocking pipes never return EAGAIN, which is required to reset their readiness.
+        let flags = OFlag::from_bits_truncate(fcntl::fcntl(fd, FcntlArg::F_GETFL).ok()?);
+        if !flags.contains(OFlag::O_NONBLOCK) {
+            return None;
+        }
+        let fd = match unistd::dup(fd) {
+            Ok(fd) => fd,
+            Err(e) => {
+                warn!("Unable to duplicate pipe for splicing: {}", e);
+                return None;
+            }
+        };
+        // SAFETY: the file descriptor got duplicated and is owned exclusively.
+        match AsyncFd::new(unsafe { File::from_raw_fd(fd) }) {
+            Ok(source) => Some(source),
+            Err(e) => {
+                warn!("Unable to register pipe for splicing: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Flush the log and notify the receivers that the container output ended.
+    async fn stop(
+        logger: &SharedContainerLog,
+        message_tx: &UnboundedSender<Message>,
     ) -> Result<()> {
-        let mut writer = unsafe { File::from_raw_fd(fd) };
+        Self::flush_log(logger).await;
+        message_tx
+            .send(Message::Done)
+            .context("send done message")?;
+        Ok(())
+    }
+
+    /// Flush the buffered log entries after the container output ended.
+    async fn flush_log(logger: &SharedContainerLog) {
+        if let Err(e) = logger.write().await.flush().await {
+            error!("Unable to flush container log: {:#}", e);
+        }
+    }
+
+    /// Forward the attach clients input to the provided writer. Returns if the token got
+    /// cancelled or if all attach clients are done writing.
+    pub async fn read_loop_stdin<T>(
+        mut writer: T,
+        attach: &mut SharedContainerAttach,
+        metrics: &IoMetrics,
+        token: CancellationToken,
+    ) -> Result<()>
+    where
+        T: AsyncWrite + Unpin,
+    {
         loop {
             select! {
                 res = attach.read() => {
                     match res {
-                        Ok(data) => {
+                        Ok(Message::Data(data)) => {
                             writer
                                 .write_all(&data)
                                 .await
                                 .context("write attach stdin to stream")?;
+                            metrics.record_input(data.len());
+                        }
+                        Ok(Message::Done) => {
+                            debug!("Stopping stdin loop because attach clients are done");
+                            return Ok(());
                         }
                         Err(e) => {
                             return Err(e).context("read from stdin attach endpoints");
@@ -345,3 +1008,123 @@ impl ContainerIO {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container_log::ContainerLog;
+    use std::time::Duration;
+
+    #[test]
+    fn io_metrics() -> Result<()> {
+        let sut = IoMetrics::default();
+        sut.record_output(Pipe::StdOut, b"a\nb\nc", true);
+        sut.record_output(Pipe::StdOut, b"d\n", true);
+        sut.record_output(Pipe::StdErr, b"e\n", false);
+        sut.record_spliced(Pipe::StdErr, 4);
+        sut.record_input(3);
+        sut.record_extra_fd(4, 5)?;
+        sut.record_extra_fd(3, 0)?;
+        sut.record_extra_fd(4, 1)?;
+
+        let stats = sut.stats()?;
+        assert_eq!(stats.stdin_bytes(), 3);
+        assert_eq!(stats.stdout().bytes(), 7);
+        assert_eq!(stats.stdout().lines(), 3);
+        assert_eq!(stats.stdout().partial_lines(), 1);
+        assert_eq!(stats.stdout().dropped_bytes(), 0);
+        assert_eq!(stats.stderr().bytes(), 6);
+        assert_eq!(stats.stderr().lines(), 0);
+        assert_eq!(stats.stderr().dropped_bytes(), 2);
+        assert_eq!(stats.extra_fds(), &[(3, 0), (4, 6)]);
+        Ok(())
+    }
+
+    #[test]
+    fn buffer_sizes() -> Result<()> {
+        let sut = BufferSizes::new(256, 64 * 1024, 4096)?;
+        assert_eq!(sut.stdin(), 256);
+        assert_eq!(sut.output(), 64 * 1024);
+        assert_eq!(sut.attach_packet(), 4096);
+
+        assert!(BufferSizes::new(BufferSizes::MIN_SIZE - 1, 1024, 4096).is_err());
+        assert!(BufferSizes::new(1024, BufferSizes::MAX_SIZE + 1, 4096).is_err());
+        assert!(BufferSizes::new(1024, 1024, AttachOptions::MAX_PACKET_SIZE + 1).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn output_receiver() -> Result<()> {
+        let io = ContainerIO::new(
+            false,
+            ContainerLog::new(),
+            SharedContainerAttach::default(),
+            BufferSizes::default(),
+        )?;
+        let (stdout_tx, stderr_tx) = match io.typ() {
+            ContainerIOType::Streams(s) => {
+                (s.message_tx_stdout().clone(), s.message_tx_stderr().clone())
+            }
+            ContainerIOType::Terminal(_) => bail!("no streams"),
+        };
+        let mut sut = SharedContainerIO::new(io).output_receiver().await?;
+
+        stdout_tx.send(Message::Data(Bytes::from_static(b"out")))?;
+        assert_eq!(
+            sut.recv().await,
+            Some((Pipe::StdOut, Bytes::from_static(b"out")))
+        );
+        stderr_tx.send(Message::Data(Bytes::from_static(b"err")))?;
+        stderr_tx.send(Message::Done)?;
+        assert_eq!(
+            sut.recv().await,
+            Some((Pipe::StdErr, Bytes::from_static(b"err")))
+        );
+
+        // The remaining pipe is still received after the other one is done
+        stdout_tx.send(Message::Data(Bytes::from_static(b"more")))?;
+        assert_eq!(
+            sut.recv().await,
+            Some((Pipe::StdOut, Bytes::from_static(b"more")))
+        );
+        drop(stdout_tx);
+        assert_eq!(sut.recv().await, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shared_read_all_with_timeout() -> Result<()> {
+        let io = ContainerIO::new(
+            false,
+            ContainerLog::new(),
+            SharedContainerAttach::default(),
+            BufferSizes::default(),
+        )?;
+        let (stdout_tx, stderr_tx) = match io.typ() {
+            ContainerIOType::Streams(s) => {
+                (s.message_tx_stdout().clone(), s.message_tx_stderr().clone())
+            }
+            ContainerIOType::Terminal(_) => bail!("no streams"),
+        };
+        let sut = SharedContainerIO::new(io);
+        let sut_clone = sut.clone();
+        let read = tokio::spawn(async move {
+            sut_clone
+                .read_all_with_timeout(None, CancellationToken::new())
+                .await
+        });
+
+        // The container IO is not locked while waiting for the output
+        stdout_tx.send(Message::Data(Bytes::from_static(b"out")))?;
+        time::timeout(Duration::from_secs(5), sut.buffer_sizes()).await?;
+        stderr_tx.send(Message::Data(Bytes::from_static(b"err")))?;
+        stdout_tx.send(Message::Done)?;
+        stderr_tx.send(Message::Done)?;
+
+        let (stdout, stderr, timed_out) = read.await??;
+        assert_eq!(stdout, b"out");
+        assert_eq!(stderr, b"err");
+        assert!(!timed_out);
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+//! Labels and annotations of containers, which get stored by the server so that clients do not
+//! have to keep their own mapping of container IDs.
+
+use capnp::struct_list::{Builder, Reader};
+use conmon_common::conmon_capnp::conmon::metadata;
+use getset::Getters;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default, Eq, Getters, PartialEq)]
+#[getset(get = "pub")]
+/// The labels and annotations a container got created with.
+pub struct ContainerLabels {
+    /// Identifying key value pairs, which can be selected by container filters.
+    labels: HashMap<String, String>,
+
+    /// Non-identifying key value pairs.
+    annotations: HashMap<String, String>,
+}
+
+impl ContainerLabels {
+    /// The prefix of tag template fields referencing a label.
+    const LABELS_PREFIX: &'static str = "Labels.";
+
+    /// The prefix of tag template fields referencing an annotation.
+    const ANNOTATIONS_PREFIX: &'static str = "Annotations.";
+
+    /// Create new container labels from the provided key value pairs.
+    pub fn new(labels: HashMap<String, String>, annotations: HashMap<String, String>) -> Self {
+        Self {
+            labels,
+            annotations,
+        }
+    }
+
+    /// Read the labels and annotations from their Cap'n Proto lists.
+    pub fn from_lists(
+        labels: Reader<'_, metadata::Owned>,
+        annotations: Reader<'_, metadata::Owned>,
+    ) -> capnp::Result<Self> {
+        Ok(Self::new(
+            Self::entries(labels)?,
+            Self::entries(annotations)?,
+        ))
+    }
+
+    /// The value of a tag template field like `Labels.app` or `Annotations.example.com/owner`,
+    /// where the key is everything after the first dot.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        if let Some(key) = name.strip_prefix(Self::LABELS_PREFIX) {
+            self.labels.get(key)
+        } else if let Some(key) = name.strip_prefix(Self::ANNOTATIONS_PREFIX) {
+            self.annotations.get(key)
+        } else {
+            None
+        }
+        .map(String::as_str)
+    }
+
+    /// Write the key value pairs sorted by key into the initialized list.
+    pub fn set_entries(mut list: Builder<'_, metadata::Owned>, entries: &HashMap<String, String>) {
+        let mut sorted: Vec<_> = entries.iter().collect();
+        sorted.sort();
+        for (i, (key, value)) in sorted.into_iter().enumerate() {
+            let mut entry = list.reborrow().get(i as u32);
+            entry.set_key(key);
+            entry.set_value(value);
+        }
+    }
+
+    /// Convert the Cap'n Proto list into key value pairs.
+    fn entries(list: Reader<'_, metadata::Owned>) -> capnp::Result<HashMap<String, String>> {
+        list.iter()
+            .map(|x| Ok((x.get_key()?.to_string(), x.get_value()?.to_string())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use conmon_common::conmon_capnp::conmon;
+
+    fn entries(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn field() {
+        let sut = ContainerLabels::new(
+            entries(&[("app", "web")]),
+            entries(&[("example.com/owner", "team")]),
+        );
+        assert_eq!(sut.field("Labels.app"), Some("web"));
+        assert_eq!(sut.field("Annotations.example.com/owner"), Some("team"));
+        assert_eq!(sut.field("Labels.owner"), None);
+        assert_eq!(sut.field("app"), None);
+    }
+
+    #[test]
+    fn lists_round_trip() -> capnp::Result<()> {
+        let sut = ContainerLabels::new(
+            entries(&[("tier", "front"), ("app", "web")]),
+            entries(&[("owner", "team")]),
+        );
+
+        let mut message = capnp::message::Builder::new_default();
+        let mut req = message.init_root::<conmon::create_container_request::Builder>();
+        ContainerLabels::set_entries(
+            req.reborrow().init_labels(sut.labels().len() as u32),
+            sut.labels(),
+        );
+        ContainerLabels::set_entries(
+            req.reborrow()
+                .init_annotations(sut.annotations().len() as u32),
+            sut.annotations(),
+        );
+
+        let req = req.into_reader();
+        assert_eq!(req.get_labels()?.get(0).get_key()?, "app");
+        assert_eq!(
+            ContainerLabels::from_lists(req.get_labels()?, req.get_annotations()?)?,
+            sut
+        );
+        Ok(())
+    }
+}
@@ -1,21 +1,134 @@
-use crate::{container_io::Pipe, cri_logger::CriLogger};
-use anyhow::Result;
+use crate::{
+    config::LogQuotaPolicy,
+    container_io::Pipe,
+    container_labels::ContainerLabels,
+    cri_logger::{CriLogger, PartialLineMode},
+    events::{EventKind, EVENTS},
+    fd_socket::FdSocket,
+    file_ownership::FileOwnership,
+    gelf_logger::{GelfCompression, GelfLogger},
+    journald_logger::JournaldLogger,
+    json_file_logger::JsonFileLogger,
+    kafka_logger::KafkaLogger,
+    lifecycle_event::LifecycleEvent,
+    log_compression::{Codec, LogCompression},
+    log_quota::SharedLogQuota,
+    log_sync::SyncPolicy,
+    log_timestamp::TimestampFormat,
+    loki_logger::LokiLogger,
+    null_logger::NullLogger,
+    output_buffer::OutputBuffer,
+    passthrough_logger::PassthroughLogger,
+    plugin_logger::PluginLogger,
+    rate_limiter::RateLimiter,
+    redaction::Redactor,
+    remote_logger::RemoteLogger,
+    rotation_schedule::RotationSchedule,
+    splunk_logger::SplunkLogger,
+    syslog_logger::SyslogLogger,
+    tag_template::TagTemplate,
+};
+use anyhow::{bail, Context, Result};
 use capnp::struct_list::Reader;
-use conmon_common::conmon_capnp::conmon::log_driver::{Owned, Type};
-use futures::future::join_all;
-use std::sync::Arc;
-use tokio::{io::AsyncBufRead, sync::RwLock};
+use conmon_common::conmon_capnp::conmon::{
+    log_driver::{self, Owned, Type},
+    metadata,
+};
+use futures::future::{join_all, ready};
+use notify::{
+    event::{Event, EventKind as NotifyEventKind, ModifyKind},
+    RecommendedWatcher, RecursiveMode, Watcher,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    future::Future,
+    io::ErrorKind,
+    mem,
+    os::unix::io::RawFd,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+use tokio::{
+    fs,
+    sync::{mpsc, RwLock},
+    task::{self, JoinHandle},
+    time,
+};
+use tokio_util::sync::{CancellationToken, DropGuard};
+use tracing::{debug, error, info, warn};
 
 pub type SharedContainerLog = Arc<RwLock<ContainerLog>>;
 
+type LogFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
 #[derive(Debug, Default)]
 pub struct ContainerLog {
+    /// The ID of the container.
+    id: String,
+
+    /// The container metadata referenced by tag templates.
+    metadata: HashMap<String, String>,
+
+    /// The container labels and annotations referenced by tag templates.
+    labels: Arc<ContainerLabels>,
+
     drivers: Vec<LogDriver>,
+
+    /// Incremented whenever the drivers get replaced, which stops outdated scheduled rotations.
+    generation: u64,
+
+    /// Whether failures of the driver at the same index get ignored instead of returned.
+    ignore_failures: Vec<bool>,
+
+    /// The global log quota, which accounts the file based drivers.
+    quota: Option<SharedLogQuota>,
+
+    /// The quota generation of the last truncation because of the exceeded quota.
+    quota_generation: u64,
+
+    /// The optional rate limit of the written output.
+    rate_limiter: Option<RateLimiter>,
+
+    /// The redaction rules applied to the written output.
+    redactor: Redactor,
+
+    /// The format of the timestamps written by the file based drivers.
+    timestamp_format: TimestampFormat,
+
+    /// Whether lifecycle events get written as marker lines into the log.
+    lifecycle_events: bool,
+
+    /// Stops watching the log files of the current drivers for removals when dropped.
+    watch_guard: Option<DropGuard>,
+
+    /// The optional copy of the redacted output to caller provided file descriptors, which
+    /// bypasses the rate limit and gets removed once writing to it fails.
+    tee: Option<PassthroughLogger>,
+
+    /// The optional buffer of the most recent redacted output, which stays available even if
+    /// the drivers failed or the log files got rotated away.
+    recent_output: Option<OutputBuffer>,
 }
 
 #[derive(Debug)]
 enum LogDriver {
     ContainerRuntimeInterface(CriLogger),
+    Gelf(GelfLogger),
+    Journald(JournaldLogger),
+    JsonFile(JsonFileLogger),
+    Kafka(KafkaLogger),
+    Loki(LokiLogger),
+    Null(NullLogger),
+    Passthrough(PassthroughLogger),
+    Plugin(PluginLogger),
+    Remote(RemoteLogger),
+    Splunk(SplunkLogger),
+    /// CRI log next to a combined one, which receives only the stderr output.
+    Stderr(CriLogger),
+    Syslog(SyslogLogger),
 }
 
 impl ContainerLog {
@@ -24,78 +137,1241 @@ impl ContainerLog {
         Arc::new(RwLock::new(Self::default()))
     }
 
-    /// Create a new SharedContainerLog from an capnp owned reader.
-    pub fn from(reader: Reader<Owned>) -> Result<SharedContainerLog> {
-        let drivers = reader
-            .iter()
-            .flat_map(|x| -> Result<_> {
-                Ok(match x.get_type()? {
-                    Type::ContainerRuntimeInterface => {
-                        LogDriver::ContainerRuntimeInterface(CriLogger::new(
-                            x.get_path()?,
-                            if x.get_max_size() > 0 {
-                                Some(x.get_max_size() as usize)
-                            } else {
-                                None
-                            },
-                        )?)
+    /// Create a new SharedContainerLog from an capnp owned reader for the provided container ID.
+    /// The file based drivers get registered at the provided quota, while passthrough drivers
+    /// take their file descriptors from the fd socket.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from(
+        id: &str,
+        reader: Reader<Owned>,
+        quota: SharedLogQuota,
+        rate_limiter: Option<RateLimiter>,
+        redactor: Redactor,
+        metadata: &HashMap<String, String>,
+        labels: &Arc<ContainerLabels>,
+        timestamp_format: TimestampFormat,
+        fd_socket: &FdSocket,
+    ) -> Result<SharedContainerLog> {
+        let (drivers, ignore_failures, schedules) =
+            Self::drivers(id, metadata, labels, timestamp_format, fd_socket, reader)?;
+        let mut container_log = Self {
+            id: id.into(),
+            metadata: metadata.clone(),
+            labels: labels.clone(),
+            drivers,
+            generation: 0,
+            ignore_failures,
+            quota: Some(quota),
+            quota_generation: 0,
+            rate_limiter,
+            redactor,
+            timestamp_format,
+            lifecycle_events: false,
+            watch_guard: None,
+            tee: None,
+            recent_output: None,
+        };
+        container_log.register_quota()?;
+        let flush_intervals = container_log.flush_intervals();
+        let watch_paths = container_log.paths();
+        let token = CancellationToken::new();
+        container_log.watch_guard = Some(token.clone().drop_guard());
+
+        let container_log = Arc::new(RwLock::new(container_log));
+        Self::spawn_schedules(&container_log, schedules, 0);
+        Self::spawn_flushes(&container_log, flush_intervals, 0);
+        Self::spawn_watch(&container_log, watch_paths, token);
+        Ok(container_log)
+    }
+
+    /// Replace all log drivers of a running container by the ones of the provided capnp owned
+    /// reader. The new drivers get initialized while holding the lock, where the existing drivers
+    /// get restored if the initialization fails.
+    pub async fn update(
+        container_log: &SharedContainerLog,
+        reader: Reader<Owned>,
+        fd_socket: &FdSocket,
+    ) -> Result<()> {
+        let mut locked = container_log.write().await;
+        let (drivers, ignore_failures, schedules) = Self::drivers(
+            &locked.id,
+            &locked.metadata,
+            &locked.labels,
+            locked.timestamp_format,
+            fd_socket,
+            reader,
+        )?;
+
+        // Flush the existing drivers first, because the new ones may reopen the same files.
+        if let Err(e) = locked.flush().await {
+            warn!("Unable to flush replaced log drivers: {:#}", e);
+        }
+        locked.unregister_quota();
+        let previous_drivers = mem::replace(&mut locked.drivers, drivers);
+        let previous_ignore_failures = mem::replace(&mut locked.ignore_failures, ignore_failures);
+        if let Err(e) = locked.init().await {
+            locked.drivers = previous_drivers;
+            locked.ignore_failures = previous_ignore_failures;
+            locked.register_quota()?;
+            return Err(e.context("initialize updated log drivers"));
+        }
+        locked.register_quota()?;
+
+        locked.generation += 1;
+        Self::spawn_schedules(container_log, schedules, locked.generation);
+        Self::spawn_flushes(container_log, locked.flush_intervals(), locked.generation);
+        let token = CancellationToken::new();
+        locked.watch_guard = Some(token.clone().drop_guard());
+        Self::spawn_watch(container_log, locked.paths(), token);
+        info!(
+            "Updated container log to {} drivers in generation {}",
+            locked.drivers.len(),
+            locked.generation
+        );
+        Ok(())
+    }
+
+    /// Create all log drivers of the capnp owned reader, including whether their failures get
+    /// ignored and their optional rotation schedules.
+    #[allow(clippy::type_complexity)]
+    fn drivers(
+        id: &str,
+        metadata: &HashMap<String, String>,
+        labels: &ContainerLabels,
+        timestamp_format: TimestampFormat,
+        fd_socket: &FdSocket,
+        reader: Reader<Owned>,
+    ) -> Result<(Vec<LogDriver>, Vec<bool>, Vec<Option<RotationSchedule>>)> {
+        let mut drivers = vec![];
+        let mut ignore_failures = vec![];
+        let mut schedules = vec![];
+        for x in reader.iter() {
+            let ignore = x.get_failure_policy()? == log_driver::FailurePolicy::Ignore;
+            match Self::driver(id, metadata, labels, timestamp_format, fd_socket, x) {
+                Ok((driver, schedule)) => {
+                    for driver in driver {
+                        drivers.push(driver);
+                        ignore_failures.push(ignore);
+                        schedules.push(schedule.clone());
                     }
-                })
+                }
+                Err(e) if ignore => warn!("Skipping failed log driver: {:#}", e),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((drivers, ignore_failures, schedules))
+    }
+
+    /// Spawn the scheduled rotations for the drivers of the provided generation.
+    fn spawn_schedules(
+        container_log: &SharedContainerLog,
+        schedules: Vec<Option<RotationSchedule>>,
+        generation: u64,
+    ) {
+        for (index, schedule) in schedules.into_iter().enumerate() {
+            if let Some(schedule) = schedule {
+                task::spawn(Self::rotate_on_schedule(
+                    Arc::downgrade(container_log),
+                    generation,
+                    index,
+                    schedule,
+                ));
+            }
+        }
+    }
+
+    /// Spawn the interval based flushes for the drivers of the provided generation.
+    fn spawn_flushes(
+        container_log: &SharedContainerLog,
+        intervals: Vec<Option<Duration>>,
+        generation: u64,
+    ) {
+        for (index, interval) in intervals.into_iter().enumerate() {
+            if let Some(interval) = interval {
+                task::spawn(Self::flush_on_interval(
+                    Arc::downgrade(container_log),
+                    generation,
+                    index,
+                    interval,
+                ));
+            }
+        }
+    }
+
+    /// Spawn the watch of the provided log file paths, which recreates them if they get removed
+    /// until the token gets cancelled.
+    fn spawn_watch(
+        container_log: &SharedContainerLog,
+        paths: Vec<PathBuf>,
+        token: CancellationToken,
+    ) {
+        if !paths.is_empty() {
+            task::spawn(Self::recreate_on_removal(
+                Arc::downgrade(container_log),
+                paths,
+                token,
+            ));
+        }
+    }
+
+    /// The container metadata referenced by tag templates.
+    pub fn metadata(&self) -> &HashMap<String, String> {
+        &self.metadata
+    }
+
+    /// The paths of all file based logs.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.file_paths()
+            .map(|(path, _)| path.to_path_buf())
+            .collect()
+    }
+
+    /// The flush intervals of all drivers, where only buffering CRI drivers have one.
+    fn flush_intervals(&self) -> Vec<Option<Duration>> {
+        self.drivers
+            .iter()
+            .map(|driver| match driver {
+                LogDriver::ContainerRuntimeInterface(cri_logger)
+                | LogDriver::Stderr(cri_logger) => cri_logger.flush_interval(),
+                _ => None,
             })
-            .collect();
-        Ok(Arc::new(RwLock::new(Self { drivers })))
+            .collect()
+    }
+
+    /// Create a single log driver and its optional rotation schedule. CRI drivers with a separate
+    /// stderr file result in an additional driver for the stderr output.
+    fn driver(
+        id: &str,
+        metadata: &HashMap<String, String>,
+        labels: &ContainerLabels,
+        timestamp_format: TimestampFormat,
+        fd_socket: &FdSocket,
+        x: log_driver::Reader,
+    ) -> Result<(Vec<LogDriver>, Option<RotationSchedule>)> {
+        let schedule = match x.get_rotate_schedule()? {
+            "" => None,
+            s => Some(s.parse::<RotationSchedule>()?),
+        };
+        let compression = match x.get_compression()? {
+            log_driver::Compression::None => None,
+            log_driver::Compression::Gzip => Some(Codec::Gzip),
+            log_driver::Compression::Zstd => Some(Codec::Zstd),
+            log_driver::Compression::Zlib if x.get_type()? == Type::Gelf => None,
+            log_driver::Compression::Zlib => bail!("zlib compression is only supported by GELF"),
+        }
+        .map(|codec| LogCompression::new(codec, x.get_compression_level()));
+        let max_size = if x.get_max_size() > 0 {
+            Some(x.get_max_size() as usize)
+        } else {
+            None
+        };
+        let tag = TagTemplate::render(x.get_tag_template()?, id, metadata, labels)?;
+        let driver = match x.get_type()? {
+            Type::ContainerRuntimeInterface => {
+                let path = x.get_path()?;
+                let cri_logger =
+                    Self::cri_logger(path, x, max_size, compression, timestamp_format)?;
+                if x.get_separate_stderr() {
+                    let stderr_logger = Self::cri_logger(
+                        Self::stderr_path(path),
+                        x,
+                        max_size,
+                        compression,
+                        timestamp_format,
+                    )?;
+                    return Ok((
+                        vec![
+                            LogDriver::ContainerRuntimeInterface(cri_logger),
+                            LogDriver::Stderr(stderr_logger),
+                        ],
+                        schedule,
+                    ));
+                }
+                LogDriver::ContainerRuntimeInterface(cri_logger)
+            }
+            Type::Gelf => LogDriver::Gelf(GelfLogger::new(
+                id,
+                x.get_name()?,
+                x.get_address()?,
+                x.get_hostname()?,
+                &tag,
+                match x.get_compression()? {
+                    log_driver::Compression::None => GelfCompression::None,
+                    log_driver::Compression::Gzip => GelfCompression::Gzip,
+                    log_driver::Compression::Zlib => GelfCompression::Zlib,
+                    log_driver::Compression::Zstd => {
+                        bail!("zstd compression is not supported by GELF")
+                    }
+                },
+                &Self::key_values(x.get_fields()?)?,
+            )?),
+            Type::Journald => {
+                LogDriver::Journald(JournaldLogger::new(id, x.get_name()?, tag.as_str())?)
+            }
+            Type::JsonFile => {
+                let mut json_file_logger = JsonFileLogger::new(
+                    x.get_path()?,
+                    max_size,
+                    x.get_max_files() as usize,
+                    compression,
+                )?;
+                json_file_logger.set_timestamp_format(timestamp_format);
+                json_file_logger.set_sync_policy(Self::sync_policy(x)?);
+                json_file_logger.set_ownership(Self::ownership(x)?);
+                LogDriver::JsonFile(json_file_logger)
+            }
+            Type::Kafka => LogDriver::Kafka(KafkaLogger::new(
+                id,
+                x.get_address()?,
+                &Self::key_values(x.get_options()?)?,
+            )?),
+            Type::Loki => LogDriver::Loki(LokiLogger::new(
+                x.get_name()?,
+                x.get_address()?,
+                &tag,
+                metadata,
+                &Self::key_values(x.get_options()?)?,
+            )?),
+            Type::None => LogDriver::Null(NullLogger::new()),
+            Type::Passthrough => LogDriver::Passthrough(PassthroughLogger::new(
+                fd_socket
+                    .take(x.get_stdout_fd())
+                    .context("get passthrough stdout")?,
+                match x.get_stderr_fd() {
+                    0 => None,
+                    slot => Some(fd_socket.take(slot).context("get passthrough stderr")?),
+                },
+            )),
+            Type::Plugin => LogDriver::Plugin(PluginLogger::new(
+                x.get_path()?,
+                &Self::key_values(x.get_options()?)?,
+            )?),
+            Type::Remote => LogDriver::Remote(RemoteLogger::new(
+                id,
+                x.get_name()?,
+                x.get_address()?,
+                &tag,
+                &Self::key_values(x.get_options()?)?,
+            )?),
+            Type::Splunk => LogDriver::Splunk(SplunkLogger::new(
+                id,
+                x.get_name()?,
+                x.get_address()?,
+                x.get_hostname()?,
+                &tag,
+                &Self::key_values(x.get_options()?)?,
+            )?),
+            Type::Syslog => LogDriver::Syslog(SyslogLogger::new(
+                id,
+                x.get_address()?,
+                x.get_facility()?,
+                if tag.is_empty() {
+                    x.get_tag()?
+                } else {
+                    tag.as_str()
+                },
+                x.get_hostname()?,
+            )?),
+        };
+        Ok((vec![driver], schedule))
+    }
+
+    /// Create a CRI logger for the provided path from the options of the capnp log driver.
+    fn cri_logger<T: AsRef<Path>>(
+        path: T,
+        x: log_driver::Reader,
+        max_size: Option<usize>,
+        compression: Option<LogCompression>,
+        timestamp_format: TimestampFormat,
+    ) -> Result<CriLogger> {
+        let mut cri_logger =
+            CriLogger::new(path, max_size, x.get_max_files() as usize, compression)?;
+        cri_logger.set_timestamp_format(timestamp_format);
+        cri_logger.set_sync_policy(Self::sync_policy(x)?);
+        cri_logger.set_ownership(Self::ownership(x)?);
+        if x.get_max_line_size() > 0 {
+            cri_logger.set_max_line_size(x.get_max_line_size() as usize);
+        }
+        cri_logger.set_partial_line_mode(match x.get_partial_line_mode()? {
+            log_driver::PartialLineMode::Split => PartialLineMode::Split,
+            log_driver::PartialLineMode::Buffer => PartialLineMode::Buffer,
+        });
+        if x.get_flush_interval_ms() > 0 {
+            cri_logger.set_flush_interval(Some(Duration::from_millis(x.get_flush_interval_ms())));
+        }
+        if x.get_flush_threshold() > 0 {
+            cri_logger.set_flush_threshold(x.get_flush_threshold() as usize);
+        }
+        Ok(cri_logger)
+    }
+
+    /// The sync policy of a file based capnp log driver.
+    fn sync_policy(x: log_driver::Reader) -> Result<SyncPolicy> {
+        Ok(match x.get_sync_policy()? {
+            log_driver::SyncPolicy::Never => SyncPolicy::Never,
+            log_driver::SyncPolicy::Periodic => SyncPolicy::Periodic {
+                bytes: x.get_sync_bytes() as usize,
+                interval: Duration::from_millis(x.get_sync_interval_ms()),
+            },
+            log_driver::SyncPolicy::Line => SyncPolicy::Line,
+        })
+    }
+
+    /// The owner and SELinux file context of a file based capnp log driver.
+    fn ownership(x: log_driver::Reader) -> Result<FileOwnership> {
+        Ok(FileOwnership::new(
+            match x.get_file_uid() {
+                u32::MAX => None,
+                uid => Some(uid),
+            },
+            match x.get_file_gid() {
+                u32::MAX => None,
+                gid => Some(gid),
+            },
+            match x.get_selinux_context()? {
+                "" => None,
+                context => Some(context.into()),
+            },
+        ))
+    }
+
+    /// The path of the separate stderr log, which inserts `.err` before the extension of the
+    /// provided path, for example `0.log` becomes `0.err.log`.
+    fn stderr_path<T: AsRef<Path>>(path: T) -> PathBuf {
+        let path = path.as_ref();
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let file_name = match path.extension() {
+            Some(extension) => format!("{}.err.{}", stem, extension.to_string_lossy()),
+            None => format!("{}.err", stem),
+        };
+        path.with_file_name(file_name)
+    }
+
+    /// Convert a list of metadata into key value pairs.
+    fn key_values(list: Reader<metadata::Owned>) -> Result<Vec<(String, String)>> {
+        let mut key_values = vec![];
+        for x in list.iter() {
+            key_values.push((x.get_key()?.to_string(), x.get_value()?.to_string()));
+        }
+        Ok(key_values)
+    }
+
+    /// Rotate the log driver at `index` on the provided schedule until the container log gets
+    /// dropped or its drivers got replaced by a newer generation.
+    async fn rotate_on_schedule(
+        container_log: Weak<RwLock<ContainerLog>>,
+        generation: u64,
+        index: usize,
+        schedule: RotationSchedule,
+    ) {
+        loop {
+            let delay = match schedule.next_delay() {
+                Ok(delay) => delay,
+                Err(e) => {
+                    error!("Unable to schedule log rotation: {:#}", e);
+                    return;
+                }
+            };
+            debug!("Next scheduled log rotation in {:?}", delay);
+            time::sleep(delay).await;
+
+            let container_log = match container_log.upgrade() {
+                Some(container_log) => container_log,
+                None => return,
+            };
+            let mut locked = container_log.write().await;
+            if locked.generation != generation {
+                return;
+            }
+            if let Err(e) = locked.rotate(index).await {
+                error!("Unable to rotate container log: {:#}", e);
+                continue;
+            }
+            EVENTS.publish(&locked.id, EventKind::LogRotated);
+            if let Err(e) = locked.write_event(LifecycleEvent::Rotated).await {
+                error!("Unable to write rotation event: {:#}", e);
+            }
+        }
+    }
+
+    /// Flush the buffered entries of the CRI driver at `index` in the provided interval until the
+    /// container log gets dropped or its drivers got replaced by a newer generation.
+    async fn flush_on_interval(
+        container_log: Weak<RwLock<ContainerLog>>,
+        generation: u64,
+        index: usize,
+        interval: Duration,
+    ) {
+        loop {
+            time::sleep(interval).await;
+
+            let container_log = match container_log.upgrade() {
+                Some(container_log) => container_log,
+                None => return,
+            };
+            let mut locked = container_log.write().await;
+            if locked.generation != generation {
+                return;
+            }
+            if let Some(
+                LogDriver::ContainerRuntimeInterface(cri_logger) | LogDriver::Stderr(cri_logger),
+            ) = locked.drivers.get_mut(index)
+            {
+                if cri_logger.unflushed() > 0 {
+                    if let Err(e) = cri_logger.flush().await {
+                        error!("Unable to flush container log: {:#}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Watch the parent directories of the provided log file paths and recreate the files once
+    /// they get removed or renamed, until the container log gets dropped or the token gets
+    /// cancelled.
+    async fn recreate_on_removal(
+        container_log: Weak<RwLock<ContainerLog>>,
+        paths: Vec<PathBuf>,
+        token: CancellationToken,
+    ) {
+        let (mut watcher, mut rx) = match Self::removal_watcher(&paths) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                error!("Unable to create log file watcher: {:#}", e);
+                return;
+            }
+        };
+        let dirs = paths
+            .iter()
+            .filter_map(|path| path.parent())
+            .collect::<HashSet<_>>();
+        for dir in dirs {
+            if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+                error!("Unable to watch log directory {}: {:#}", dir.display(), e);
+                return;
+            }
+        }
+
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => return,
+                Some(()) = rx.recv() => {}
+            }
+            let container_log = match container_log.upgrade() {
+                Some(container_log) => container_log,
+                None => return,
+            };
+            let mut locked = container_log.write().await;
+            if let Err(e) = locked.recreate_removed().await {
+                error!("Unable to recreate removed container log: {:#}", e);
+            }
+        }
+    }
+
+    /// Create a watcher, which notifies the receiver if one of the provided paths got removed or
+    /// renamed. Multiple notifications get coalesced while the receiver is busy.
+    fn removal_watcher(paths: &[PathBuf]) -> Result<(RecommendedWatcher, mpsc::Receiver<()>)> {
+        let (tx, rx) = mpsc::channel(1);
+        let names = paths
+            .iter()
+            .filter_map(|path| path.file_name().map(OsString::from))
+            .collect::<HashSet<_>>();
+        let watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+            Ok(event)
+                if matches!(
+                    event.kind,
+                    NotifyEventKind::Remove(_) | NotifyEventKind::Modify(ModifyKind::Name(_))
+                ) && event
+                    .paths
+                    .iter()
+                    .filter_map(|path| path.file_name())
+                    .any(|name| names.contains(name)) =>
+            {
+                let _ = tx.try_send(());
+            }
+            Ok(_) => {}
+            Err(e) => error!("Unable to watch log files: {:#}", e),
+        })
+        .context("get recommended watcher")?;
+        Ok((watcher, rx))
+    }
+
+    /// Recreate the removed files of all file based drivers, which gets recorded by a lifecycle
+    /// event.
+    async fn recreate_removed(&mut self) -> Result<()> {
+        let mut recreated = false;
+        for driver in self.drivers.iter_mut() {
+            recreated |= match driver {
+                LogDriver::ContainerRuntimeInterface(cri_logger)
+                | LogDriver::Stderr(cri_logger) => cri_logger.recreate_removed().await?,
+                LogDriver::JsonFile(json_file_logger) => {
+                    json_file_logger.recreate_removed().await?
+                }
+                _ => false,
+            };
+        }
+        if recreated {
+            self.write_event(LifecycleEvent::LogRecreated).await?;
+        }
+        Ok(())
+    }
+
+    /// Rotate the log driver at `index`, if it is file based and not empty.
+    async fn rotate(&mut self, index: usize) -> Result<()> {
+        match self.drivers.get_mut(index) {
+            Some(
+                LogDriver::ContainerRuntimeInterface(cri_logger) | LogDriver::Stderr(cri_logger),
+            ) if cri_logger.bytes_written() > 0 => cri_logger.rotate().await,
+            Some(LogDriver::JsonFile(json_file_logger)) if json_file_logger.bytes_written() > 0 => {
+                json_file_logger.rotate().await
+            }
+            _ => Ok(()),
+        }
     }
 
     /// Asynchronously initialize all loggers.
     pub async fn init(&mut self) -> Result<()> {
-        join_all(
+        let results = join_all(
             self.drivers
                 .iter_mut()
                 .map(|x| match x {
-                    LogDriver::ContainerRuntimeInterface(ref mut cri_logger) => cri_logger.init(),
+                    LogDriver::ContainerRuntimeInterface(ref mut cri_logger)
+                    | LogDriver::Stderr(ref mut cri_logger) => {
+                        Box::pin(cri_logger.init()) as LogFuture
+                    }
+                    LogDriver::Gelf(ref mut gelf_logger) => Box::pin(gelf_logger.init()),
+                    LogDriver::Journald(ref mut journald_logger) => {
+                        Box::pin(journald_logger.init())
+                    }
+                    LogDriver::JsonFile(ref mut json_file_logger) => {
+                        Box::pin(json_file_logger.init())
+                    }
+                    LogDriver::Kafka(ref mut kafka_logger) => Box::pin(kafka_logger.init()),
+                    LogDriver::Loki(ref mut loki_logger) => Box::pin(loki_logger.init()),
+                    LogDriver::Null(ref mut null_logger) => Box::pin(null_logger.init()),
+                    LogDriver::Passthrough(ref mut passthrough_logger) => {
+                        Box::pin(passthrough_logger.init())
+                    }
+                    LogDriver::Plugin(ref mut plugin_logger) => Box::pin(plugin_logger.init()),
+                    LogDriver::Remote(ref mut remote_logger) => Box::pin(remote_logger.init()),
+                    LogDriver::Splunk(ref mut splunk_logger) => Box::pin(splunk_logger.init()),
+                    LogDriver::Syslog(ref mut syslog_logger) => Box::pin(syslog_logger.init()),
                 })
                 .collect::<Vec<_>>(),
         )
-        .await
-        .into_iter()
-        .collect::<Result<Vec<_>>>()?;
-        Ok(())
+        .await;
+        self.check_results(results, "init")
     }
 
     /// Reopen the container logs.
     pub async fn reopen(&mut self) -> Result<()> {
-        join_all(
+        let results = join_all(
             self.drivers
                 .iter_mut()
                 .map(|x| match x {
-                    LogDriver::ContainerRuntimeInterface(ref mut cri_logger) => cri_logger.reopen(),
+                    LogDriver::ContainerRuntimeInterface(ref mut cri_logger)
+                    | LogDriver::Stderr(ref mut cri_logger) => {
+                        Box::pin(cri_logger.reopen()) as LogFuture
+                    }
+                    LogDriver::Gelf(ref mut gelf_logger) => Box::pin(gelf_logger.reopen()),
+                    LogDriver::Journald(ref mut journald_logger) => {
+                        Box::pin(journald_logger.reopen())
+                    }
+                    LogDriver::JsonFile(ref mut json_file_logger) => {
+                        Box::pin(json_file_logger.reopen())
+                    }
+                    LogDriver::Kafka(ref mut kafka_logger) => Box::pin(kafka_logger.reopen()),
+                    LogDriver::Loki(ref mut loki_logger) => Box::pin(loki_logger.reopen()),
+                    LogDriver::Null(ref mut null_logger) => Box::pin(null_logger.reopen()),
+                    LogDriver::Passthrough(ref mut passthrough_logger) => {
+                        Box::pin(passthrough_logger.reopen())
+                    }
+                    LogDriver::Plugin(ref mut plugin_logger) => Box::pin(plugin_logger.reopen()),
+                    LogDriver::Remote(ref mut remote_logger) => Box::pin(remote_logger.reopen()),
+                    LogDriver::Splunk(ref mut splunk_logger) => Box::pin(splunk_logger.reopen()),
+                    LogDriver::Syslog(ref mut syslog_logger) => Box::pin(syslog_logger.reopen()),
                 })
                 .collect::<Vec<_>>(),
         )
-        .await
-        .into_iter()
-        .collect::<Result<Vec<_>>>()?;
-        Ok(())
+        .await;
+        self.check_results(results, "reopen")
     }
 
-    /// Write the contents of the provided reader into all loggers.
-    pub async fn write<T>(&mut self, pipe: Pipe, bytes: T) -> Result<()>
-    where
-        T: AsyncBufRead + Unpin + Copy,
-    {
-        join_all(
+    /// Write the provided bytes into all loggers after applying the redaction rules. If a rate
+    /// limit is set, then exceeding output gets either throttled or dropped, where dropped
+    /// messages are reported periodically by a marker line. Returns whether the bytes got
+    /// written.
+    pub async fn write(&mut self, pipe: Pipe, bytes: &[u8]) -> Result<bool> {
+        let bytes = self.redactor.redact(bytes);
+        if let Some(recent_output) = self.recent_output.as_mut() {
+            recent_output.push(pipe, &bytes);
+        }
+        self.write_tee(pipe, &bytes).await;
+        if let Some(rate_limiter) = self.rate_limiter.as_mut() {
+            if !rate_limiter.acquire(bytes.len()).await {
+                return Ok(false);
+            }
+            if let Some(suppressed) = rate_limiter.take_suppressed() {
+                let marker = format!("{} messages suppressed\n", suppressed);
+                self.write_drivers(pipe, marker.as_bytes()).await?;
+            }
+        }
+        let rotations = self.rotations();
+        self.write_drivers(pipe, &bytes).await?;
+        if self.rotations() != rotations {
+            EVENTS.publish(&self.id, EventKind::LogRotated);
+            self.write_event(LifecycleEvent::Rotated).await?;
+        }
+        Ok(true)
+    }
+
+    /// Set the additional copy of the output to caller provided file descriptors.
+    pub fn set_tee(&mut self, tee: Option<PassthroughLogger>) {
+        self.tee = tee;
+    }
+
+    /// Copy the provided bytes to the tee, which gets removed if the consumer went away.
+    async fn write_tee(&mut self, pipe: Pipe, bytes: &[u8]) {
+        if let Some(tee) = self.tee.as_mut() {
+            if let Err(e) = tee.write(pipe, bytes).await {
+                warn!("Removing output tee: {:#}", e);
+                self.tee = None;
+            }
+        }
+    }
+
+    /// Set the size of the buffer of the most recent output in bytes, where zero disables it.
+    pub fn set_recent_output_size(&mut self, size: usize) {
+        self.recent_output = if size > 0 {
+            Some(OutputBuffer::new(size))
+        } else {
+            None
+        };
+    }
+
+    /// Returns the most recent output chunks, oldest first.
+    pub fn recent_output(&self) -> Vec<(Pipe, Vec<u8>)> {
+        self.recent_output
+            .iter()
+            .flat_map(OutputBuffer::chunks)
+            .cloned()
+            .collect()
+    }
+
+    /// Set whether lifecycle events get written as marker lines into the log.
+    pub fn set_lifecycle_events(&mut self, lifecycle_events: bool) {
+        self.lifecycle_events = lifecycle_events;
+    }
+
+    /// Write the marker line of the provided lifecycle event into all loggers as stdout, if
+    /// lifecycle events are enabled. Markers bypass the rate limit and redaction rules.
+    pub async fn write_event(&mut self, event: LifecycleEvent) -> Result<()> {
+        if !self.lifecycle_events {
+            return Ok(());
+        }
+        debug!("Writing lifecycle event: {}", event);
+        let marker = format!("{}\n", event);
+        self.write_drivers(Pipe::StdOut, marker.as_bytes()).await
+    }
+
+    /// The total amount of rotations of all file based logs.
+    fn rotations(&self) -> u64 {
+        self.drivers
+            .iter()
+            .map(|driver| match driver {
+                LogDriver::ContainerRuntimeInterface(cri_logger)
+                | LogDriver::Stderr(cri_logger) => cri_logger.rotations(),
+                LogDriver::JsonFile(json_file_logger) => json_file_logger.rotations(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Returns the total amount of bytes discarded by null drivers.
+    pub fn discarded_bytes(&self) -> u64 {
+        self.drivers
+            .iter()
+            .map(|driver| match driver {
+                LogDriver::Null(null_logger) => null_logger.discarded_bytes(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Returns the file descriptor the output of the pipe can be spliced into without being
+    /// inspected, which requires a sole passthrough driver without rate limit, redaction, tee
+    /// and recent output buffer.
+    pub fn splice_target(&self, pipe: Pipe) -> Option<RawFd> {
+        if self.rate_limiter.is_some()
+            || !self.redactor.rules().is_empty()
+            || self.tee.is_some()
+            || self.recent_output.is_some()
+        {
+            return None;
+        }
+        match self.drivers.as_slice() {
+            [LogDriver::Passthrough(passthrough_logger)] => Some(passthrough_logger.fd(pipe)),
+            _ => None,
+        }
+    }
+
+    /// Returns the last `lines` lines of the first file based log, or all lines if `lines` is
+    /// zero. The rotated files are read as well if `follow_rotations` is set and the current file
+    /// contains less lines.
+    pub async fn tail(&mut self, lines: usize, follow_rotations: bool) -> Result<Vec<Vec<u8>>> {
+        self.flush().await?;
+        let (path, max_files) = self
+            .drivers
+            .iter()
+            .find_map(|driver| match driver {
+                LogDriver::ContainerRuntimeInterface(cri_logger) => {
+                    Some((cri_logger.path().clone(), cri_logger.max_files()))
+                }
+                LogDriver::JsonFile(json_file_logger) => Some((
+                    json_file_logger.path().clone(),
+                    json_file_logger.max_files(),
+                )),
+                _ => None,
+            })
+            .context("no file based log driver")?;
+        let max_files = if follow_rotations { max_files } else { 1 };
+        task::spawn_blocking(move || Self::tail_files(&path, max_files, lines))
+            .await
+            .context("wait for reading log files")?
+    }
+
+    /// Returns the redaction rules including their hit counters.
+    pub fn redactor(&self) -> &Redactor {
+        &self.redactor
+    }
+
+    /// Write the provided bytes into all loggers. If the global log quota is exceeded, then the
+    /// file based drivers either drop the contents or get truncated once per quota update,
+    /// depending on the quota policy.
+    async fn write_drivers(&mut self, pipe: Pipe, bytes: &[u8]) -> Result<()> {
+        let mut drop_files = false;
+        if let Some(quota) = self.quota.clone().filter(|x| x.exceeded()) {
+            match quota.policy() {
+                LogQuotaPolicy::Drop => {
+                    quota.record_dropped();
+                    drop_files = true;
+                }
+                LogQuotaPolicy::Rotate if self.quota_generation != quota.generation() => {
+                    self.quota_generation = quota.generation();
+                    self.truncate().await?;
+                }
+                LogQuotaPolicy::Rotate => {}
+            }
+        }
+
+        let results = join_all(
             self.drivers
                 .iter_mut()
                 .map(|x| match x {
-                    LogDriver::ContainerRuntimeInterface(ref mut cri_logger) => {
-                        cri_logger.write(pipe, bytes)
+                    LogDriver::ContainerRuntimeInterface(_)
+                    | LogDriver::JsonFile(_)
+                    | LogDriver::Stderr(_)
+                        if drop_files =>
+                    {
+                        Box::pin(ready(Ok(()))) as LogFuture
+                    }
+                    LogDriver::Stderr(_) if pipe != Pipe::StdErr => Box::pin(ready(Ok(()))),
+                    LogDriver::ContainerRuntimeInterface(ref mut cri_logger)
+                    | LogDriver::Stderr(ref mut cri_logger) => {
+                        Box::pin(cri_logger.write(pipe, bytes))
+                    }
+                    LogDriver::Gelf(ref mut gelf_logger) => {
+                        Box::pin(gelf_logger.write(pipe, bytes))
+                    }
+                    LogDriver::Journald(ref mut journald_logger) => {
+                        Box::pin(journald_logger.write(pipe, bytes))
+                    }
+                    LogDriver::JsonFile(ref mut json_file_logger) => {
+                        Box::pin(json_file_logger.write(pipe, bytes))
+                    }
+                    LogDriver::Kafka(ref mut kafka_logger) => {
+                        Box::pin(kafka_logger.write(pipe, bytes))
+                    }
+                    LogDriver::Loki(ref mut loki_logger) => {
+                        Box::pin(loki_logger.write(pipe, bytes))
+                    }
+                    LogDriver::Null(ref mut null_logger) => {
+                        Box::pin(null_logger.write(pipe, bytes))
+                    }
+                    LogDriver::Passthrough(ref mut passthrough_logger) => {
+                        Box::pin(passthrough_logger.write(pipe, bytes))
+                    }
+                    LogDriver::Plugin(ref mut plugin_logger) => {
+                        Box::pin(plugin_logger.write(pipe, bytes))
+                    }
+                    LogDriver::Remote(ref mut remote_logger) => {
+                        Box::pin(remote_logger.write(pipe, bytes))
+                    }
+                    LogDriver::Splunk(ref mut splunk_logger) => {
+                        Box::pin(splunk_logger.write(pipe, bytes))
+                    }
+                    LogDriver::Syslog(ref mut syslog_logger) => {
+                        Box::pin(syslog_logger.write(pipe, bytes))
                     }
                 })
                 .collect::<Vec<_>>(),
         )
-        .await
-        .into_iter()
-        .collect::<Result<Vec<_>>>()?;
+        .await;
+        self.check_results(results, "write")
+    }
+
+    /// Evaluate the results of all drivers, where the failures of drivers ignoring them get
+    /// logged instead of returned.
+    fn check_results(&self, results: Vec<Result<()>>, action: &str) -> Result<()> {
+        for (result, ignore) in results.into_iter().zip(&self.ignore_failures) {
+            match result {
+                Err(e) if *ignore => warn!("Ignoring failed log driver {}: {:#}", action, e),
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush the buffered contents of all file based logs, for example when the container exits.
+    pub async fn flush(&mut self) -> Result<()> {
+        for driver in self.drivers.iter_mut() {
+            match driver {
+                LogDriver::ContainerRuntimeInterface(cri_logger)
+                | LogDriver::Stderr(cri_logger) => cri_logger.flush().await?,
+                LogDriver::JsonFile(json_file_logger) => json_file_logger.flush().await?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Truncate all file based logs and remove their rotated files.
+    async fn truncate(&mut self) -> Result<()> {
+        for driver in self.drivers.iter_mut() {
+            match driver {
+                LogDriver::ContainerRuntimeInterface(cri_logger)
+                | LogDriver::Stderr(cri_logger) => cri_logger.truncate().await?,
+                LogDriver::JsonFile(json_file_logger) => json_file_logger.truncate().await?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Rotate the log files by shifting `path.N-1` to `path.N` down to `path` to `path.1`, where
+    /// `N` is `max_files - 1` and the oldest file gets dropped. Compressed variants of the rotated
+    /// files are shifted as well. If a `compression` is provided, then `path.1` gets compressed in
+    /// the background, where the `compression_task` is awaited on the next rotation to not race
+    /// against it. The caller is responsible for reopening `path` afterwards.
+    pub async fn rotate_files(
+        path: &Path,
+        max_files: usize,
+        compression: Option<LogCompression>,
+        compression_task: &mut Option<JoinHandle<()>>,
+    ) -> Result<()> {
+        if let Some(task) = compression_task.take() {
+            task.await.context("wait for log compression")?;
+        }
+
+        for i in (1..max_files).rev() {
+            let dst = Self::rotated_path(path, i);
+            for variant in Self::file_variants(&dst) {
+                if fs::metadata(&variant).await.is_ok() {
+                    fs::remove_file(&variant)
+                        .await
+                        .context(format!("remove log file '{}'", variant.display()))?;
+                }
+            }
+
+            let moves = if i == 1 {
+                vec![(path.into(), dst)]
+            } else {
+                Self::file_variants(&Self::rotated_path(path, i - 1))
+                    .into_iter()
+                    .zip(Self::file_variants(&dst))
+                    .collect()
+            };
+            for (src, dst) in moves {
+                if fs::metadata(&src).await.is_ok() {
+                    fs::rename(&src, &dst).await.context(format!(
+                        "rename log file '{}' to '{}'",
+                        src.display(),
+                        dst.display()
+                    ))?;
+                }
+            }
+        }
+        info!(
+            "Rotated container log {} keeping {} files",
+            path.display(),
+            max_files
+        );
+
+        if let Some(compression) = compression {
+            if max_files > 1 {
+                *compression_task = Some(compression.spawn(Self::rotated_path(path, 1)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove all rotated files of `path` including their compressed variants, which is done
+    /// after the `compression_task` finished.
+    pub async fn remove_rotated_files(
+        path: &Path,
+        max_files: usize,
+        compression_task: &mut Option<JoinHandle<()>>,
+    ) -> Result<()> {
+        if let Some(task) = compression_task.take() {
+            task.await.context("wait for log compression")?;
+        }
+        for i in 1..max_files {
+            for variant in Self::file_variants(&Self::rotated_path(path, i)) {
+                if fs::metadata(&variant).await.is_ok() {
+                    fs::remove_file(&variant)
+                        .await
+                        .context(format!("remove log file '{}'", variant.display()))?;
+                }
+            }
+        }
+        warn!(
+            "Truncated container log {} because of the exceeded log quota",
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Returns the paths and maximum amount of files of all file based logs.
+    fn file_paths(&self) -> impl Iterator<Item = (&Path, usize)> {
+        self.drivers.iter().filter_map(|driver| match driver {
+            LogDriver::ContainerRuntimeInterface(cri_logger) | LogDriver::Stderr(cri_logger) => {
+                Some((cri_logger.path().as_path(), cri_logger.max_files()))
+            }
+            LogDriver::JsonFile(json_file_logger) => Some((
+                json_file_logger.path().as_path(),
+                json_file_logger.max_files(),
+            )),
+            _ => None,
+        })
+    }
+
+    /// Register all file based logs at the quota.
+    fn register_quota(&self) -> Result<()> {
+        if let Some(quota) = &self.quota {
+            for (path, max_files) in self.file_paths() {
+                quota.register(path, max_files)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Unregister all file based logs from the quota.
+    fn unregister_quota(&self) {
+        if let Some(quota) = &self.quota {
+            for (path, _) in self.file_paths() {
+                if let Err(e) = quota.unregister(path) {
+                    error!("Unable to unregister log from quota: {:#}", e);
+                }
+            }
+        }
+    }
+
+    /// Returns the plain and all compressed variants of the provided path.
+    pub fn file_variants(path: &Path) -> Vec<PathBuf> {
+        let mut variants = vec![path.to_path_buf()];
+        variants.extend(
+            Codec::ALL
+                .iter()
+                .map(|codec| LogCompression::compressed_path(path, *codec)),
+        );
+        variants
+    }
+
+    /// Returns the last `lines` lines of `path` and its rotated files up to `max_files`, where the
+    /// rotated files are only read if the newer ones contain less lines.
+    fn tail_files(path: &Path, max_files: usize, lines: usize) -> Result<Vec<Vec<u8>>> {
+        let mut tail = vec![];
+        for index in 0..max_files.max(1) {
+            let file = if index == 0 {
+                path.to_path_buf()
+            } else {
+                Self::rotated_path(path, index)
+            };
+            let content = match Self::read_file_variant(&file)? {
+                Some(content) => content,
+                None => break,
+            };
+            let mut file_lines = content
+                .split_inclusive(|x| *x == b'\n')
+                .map(|line| line.strip_suffix(b"\n").unwrap_or(line).to_vec())
+                .collect::<Vec<_>>();
+            file_lines.append(&mut tail);
+            tail = file_lines;
+            if lines > 0 && tail.len() >= lines {
+                break;
+            }
+        }
+        if lines > 0 && tail.len() > lines {
+            tail.drain(..tail.len() - lines);
+        }
+        Ok(tail)
+    }
+
+    /// Read the plain or the first existing compressed variant of the provided path, which
+    /// returns `None` if neither exists.
+    fn read_file_variant(path: &Path) -> Result<Option<Vec<u8>>> {
+        match std::fs::read(path) {
+            Ok(content) => return Ok(Some(content)),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context(format!("read log file '{}'", path.display())),
+        }
+        for codec in Codec::ALL {
+            let compressed = LogCompression::compressed_path(path, *codec);
+            if compressed.exists() {
+                return LogCompression::decompress(&compressed, *codec).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the path of the rotated log file with the provided index.
+    pub fn rotated_path(path: &Path, index: usize) -> PathBuf {
+        let mut path = path.as_os_str().to_owned();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+}
+
+impl Drop for ContainerLog {
+    fn drop(&mut self) {
+        self.unregister_quota();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redaction::RedactionRule;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use tempfile::{tempdir, NamedTempFile};
+
+    fn lines(content: &[&str]) -> Vec<Vec<u8>> {
+        content.iter().map(|x| x.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn tail_files() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log");
+        std::fs::write(&path, "d\ne\npartial")?;
+        std::fs::write(ContainerLog::rotated_path(&path, 1), "b\nc\n")?;
+        std::fs::write(ContainerLog::rotated_path(&path, 2), "a\n")?;
+
+        assert_eq!(
+            ContainerLog::tail_files(&path, 1, 2)?,
+            lines(&["e", "partial"])
+        );
+        assert_eq!(
+            ContainerLog::tail_files(&path, 1, 5)?,
+            lines(&["d", "e", "partial"])
+        );
+        assert_eq!(
+            ContainerLog::tail_files(&path, 3, 5)?,
+            lines(&["b", "c", "d", "e", "partial"])
+        );
+        assert_eq!(
+            ContainerLog::tail_files(&path, 3, 0)?,
+            lines(&["a", "b", "c", "d", "e", "partial"])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn splice_target() -> Result<()> {
+        let stdout = tempfile::tempfile()?;
+        let stderr = tempfile::tempfile()?;
+        let (stdout_fd, stderr_fd) = (stdout.as_raw_fd(), stderr.as_raw_fd());
+        let mut sut = ContainerLog::default();
+        sut.drivers = vec![LogDriver::Passthrough(PassthroughLogger::new(
+            stdout,
+            Some(stderr),
+        ))];
+        assert_eq!(sut.splice_target(Pipe::StdOut), Some(stdout_fd));
+        assert_eq!(sut.splice_target(Pipe::StdErr), Some(stderr_fd));
+
+        // Redaction requires the output to be inspected
+        sut.redactor = Redactor::new(vec![RedactionRule::new("secret", "***")?]);
+        assert!(sut.splice_target(Pipe::StdOut).is_none());
+
+        sut.redactor = Redactor::default();
+        sut.drivers.push(LogDriver::Null(NullLogger::new()));
+        assert!(sut.splice_target(Pipe::StdOut).is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_tee() -> Result<()> {
+        let tee = NamedTempFile::new()?;
+        let mut sut = ContainerLog::default();
+        sut.drivers = vec![LogDriver::Null(NullLogger::new())];
+        sut.redactor = Redactor::new(vec![RedactionRule::new("secret", "***")?]);
+        sut.set_tee(Some(PassthroughLogger::new(tee.reopen()?, None)));
+        sut.write(Pipe::StdOut, b"a secret\n").await?;
+        sut.write(Pipe::StdErr, b"b\n").await?;
+        assert_eq!(std::fs::read_to_string(tee.path())?, "a ***\nb\n");
+        assert_eq!(sut.discarded_bytes(), 8);
+
+        // A gone consumer removes the tee without failing the write
+        let (read_fd, write_fd) = nix::unistd::pipe()?;
+        drop(unsafe { std::fs::File::from_raw_fd(read_fd) });
+        sut.set_tee(Some(PassthroughLogger::new(
+            unsafe { std::fs::File::from_raw_fd(write_fd) },
+            None,
+        )));
+        sut.write(Pipe::StdOut, b"c\n").await?;
+        assert!(sut.tee.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn recent_output() -> Result<()> {
+        let mut sut = ContainerLog::default();
+        sut.drivers = vec![LogDriver::Null(NullLogger::new())];
+        sut.redactor = Redactor::new(vec![RedactionRule::new("secret", "***")?]);
+        sut.write(Pipe::StdOut, b"a\n").await?;
+        assert!(sut.recent_output().is_empty());
+
+        sut.set_recent_output_size(8);
+        sut.write(Pipe::StdOut, b"b secret\n").await?;
+        sut.write(Pipe::StdErr, b"c\n").await?;
+        assert_eq!(
+            sut.recent_output(),
+            vec![
+                (Pipe::StdOut, b"b ***\n".to_vec()),
+                (Pipe::StdErr, b"c\n".to_vec())
+            ]
+        );
+        assert!(sut.splice_target(Pipe::StdOut).is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tail_files_compressed() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log");
+        std::fs::write(&path, "b\n")?;
+        let rotated = ContainerLog::rotated_path(&path, 1);
+        std::fs::write(&rotated, "a\n")?;
+        LogCompression::new(Codec::Zstd, 0).spawn(rotated).await?;
+
+        assert_eq!(ContainerLog::tail_files(&path, 2, 2)?, lines(&["a", "b"]));
+        Ok(())
+    }
+
+    #[test]
+    fn tail_files_missing() -> Result<()> {
+        let dir = tempdir()?;
+        assert!(ContainerLog::tail_files(&dir.path().join("log"), 3, 1)?.is_empty());
         Ok(())
     }
 }
@@ -1,24 +1,34 @@
 //! File logging functionalities.
 
-use crate::container_io::Pipe;
+use crate::{
+    container_io::Pipe,
+    container_log::ContainerLog,
+    file_ownership::FileOwnership,
+    log_compression::LogCompression,
+    log_sync::{LogSync, SyncPolicy},
+    log_timestamp::TimestampFormat,
+    utf8_boundary,
+};
 use anyhow::{Context, Result};
 use getset::{CopyGetters, Getters, Setters};
 use memchr::memchr;
 use std::{
+    io::ErrorKind,
     marker::Unpin,
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 use tokio::{
-    fs::{File, OpenOptions},
-    io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter},
+    fs::{self, File, OpenOptions},
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufWriter},
+    task::JoinHandle,
 };
-use tracing::{debug, trace};
-use tz::{DateTime, TimeZone};
+use tracing::{debug, trace, warn};
 
 #[derive(Debug, CopyGetters, Getters, Setters)]
 /// The main structure used for container log handling.
 pub struct CriLogger {
-    #[getset(get)]
+    #[getset(get = "pub")]
     /// Path to the file on disk.
     path: PathBuf,
 
@@ -30,131 +40,342 @@ pub struct CriLogger {
     /// Maximum allowed log size in bytes.
     max_log_size: Option<usize>,
 
-    #[getset(get_copy, set)]
+    #[getset(get_copy = "pub")]
+    /// Maximum number of log files including the current one, used on rotation. The log is
+    /// truncated instead of rotated if set to one.
+    max_files: usize,
+
+    #[getset(get_copy)]
+    /// Compression applied to rotated log files.
+    compression: Option<LogCompression>,
+
+    /// Background compression of the last rotated log file.
+    compression_task: Option<JoinHandle<()>>,
+
+    #[getset(get_copy = "pub", set)]
     /// Current bytes written to the log file.
     bytes_written: usize,
+
+    #[getset(get_copy = "pub")]
+    /// Amount of rotations of the log file.
+    rotations: u64,
+
+    #[getset(get_copy, set = "pub")]
+    /// Maximum length of a single log entry, longer lines are split into partial entries.
+    max_line_size: usize,
+
+    #[getset(get_copy, set = "pub")]
+    /// The handling of lines which are incomplete at the end of a write.
+    partial_line_mode: PartialLineMode,
+
+    /// Buffered partial line of stdout, which keeps its allocation for the following lines.
+    pending_stdout: Vec<u8>,
+
+    /// Buffered partial line of stderr, which keeps its allocation for the following lines.
+    pending_stderr: Vec<u8>,
+
+    #[getset(get_copy, set = "pub")]
+    /// Format of the entry timestamps.
+    timestamp_format: TimestampFormat,
+
+    #[getset(get_copy, set = "pub")]
+    /// Interval in which buffered entries get flushed to the file, `None` flushes after every
+    /// write.
+    flush_interval: Option<Duration>,
+
+    #[getset(get_copy, set = "pub")]
+    /// Amount of buffered bytes which triggers a flush before the interval elapsed.
+    flush_threshold: usize,
+
+    #[getset(get_copy = "pub")]
+    /// Amount of bytes written since the last flush.
+    unflushed: usize,
+
+    /// Time of the last flush.
+    last_flush: Instant,
+
+    /// Sync state of the written entries.
+    log_sync: LogSync,
+
+    #[getset(get, set = "pub")]
+    /// Owner and SELinux file context applied to the log file on creation.
+    ownership: FileOwnership,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Available modes of handling incomplete lines.
+pub enum PartialLineMode {
+    /// Write incomplete lines immediately as partial entries.
+    Split,
+
+    /// Buffer incomplete lines until their newline arrives, where lines exceeding the maximum
+    /// line size are still split.
+    Buffer,
 }
 
 impl CriLogger {
     const ERR_UNINITIALIZED: &'static str = "logger not initialized";
 
-    /// Create a new file logger instance.
-    pub fn new<T: AsRef<Path>>(path: T, max_log_size: Option<usize>) -> Result<CriLogger> {
+    /// The default maximum line size, longer lines are split into partial entries.
+    pub const DEFAULT_MAX_LINE_SIZE: usize = 8192;
+
+    /// The default amount of buffered bytes which triggers a flush.
+    pub const DEFAULT_FLUSH_THRESHOLD: usize = 64 * 1024;
+
+    /// Create a new file logger instance. A `max_files` of zero is treated like one.
+    pub fn new<T: AsRef<Path>>(
+        path: T,
+        max_log_size: Option<usize>,
+        max_files: usize,
+        compression: Option<LogCompression>,
+    ) -> Result<CriLogger> {
         Ok(Self {
             path: path.as_ref().into(),
             file: None,
             max_log_size,
+            max_files: max_files.max(1),
+            compression,
+            compression_task: None,
             bytes_written: 0,
+            rotations: 0,
+            max_line_size: Self::DEFAULT_MAX_LINE_SIZE,
+            partial_line_mode: PartialLineMode::Split,
+            pending_stdout: vec![],
+            pending_stderr: vec![],
+            timestamp_format: TimestampFormat::default(),
+            flush_interval: None,
+            flush_threshold: Self::DEFAULT_FLUSH_THRESHOLD,
+            unflushed: 0,
+            last_flush: Instant::now(),
+            log_sync: LogSync::default(),
+            ownership: FileOwnership::default(),
         })
     }
 
+    /// Set the policy of syncing written entries to disk.
+    pub fn set_sync_policy(&mut self, policy: SyncPolicy) {
+        self.log_sync = LogSync::new(policy);
+    }
+
     /// Asynchronously initialize the CRI logger.
     pub async fn init(&mut self) -> Result<()> {
         debug!("Initializing CRI logger in path {}", self.path().display());
-        self.set_file(Self::open(self.path()).await?.into());
+        let file = Self::open(self.path(), self.flush_threshold()).await?;
+        self.ownership().apply(file.get_ref())?;
+        self.set_file(file.into());
         Ok(())
     }
 
-    /// Write the contents of the provided reader into the file logger.
-    pub async fn write<T>(&mut self, pipe: Pipe, bytes: T) -> Result<()>
+    /// Write the contents of the provided reader into the file logger. Lines exceeding the
+    /// maximum line size are split into partial (`P`) entries. Incomplete lines at the end of the
+    /// reader are written as partial entries too, unless the partial line mode buffers them
+    /// until their newline arrives.
+    pub async fn write<T>(&mut self, pipe: Pipe, mut bytes: T) -> Result<()>
     where
         T: AsyncBufRead + Unpin,
     {
-        let mut reader = BufReader::new(bytes);
-
-        // Get the RFC3339 timestmap
-        let local_tz = TimeZone::local().context("get local timezone")?;
-        let timestamp = DateTime::now(local_tz.as_ref())
-            .context("get local datetime")?
-            .to_string();
-        let min_log_len = timestamp
-            .len()
-            .checked_add(10) // len of " stdout " + "P "
-            .context("min log line len exceeds usize")?;
+        // Get the timestamp, which may be omitted
+        let timestamp = self.timestamp_format().local()?;
 
         loop {
-            // Read the line
-            let mut line_buf = Vec::with_capacity(min_log_len);
-            let (read, partial) = Self::read_line(&mut reader, &mut line_buf).await?;
+            // Read the line, which continues a buffered partial line. Split partial lines get
+            // written immediately, which means that the pending buffer only contains the
+            // remainder of a code point split by them.
+            let mut line_buf = std::mem::take(self.pending_mut(pipe));
+            let (read, partial) =
+                Self::read_line(&mut bytes, &mut line_buf, self.max_line_size()).await?;
 
             if read == 0 {
+                *self.pending_mut(pipe) = line_buf;
                 break;
             }
 
-            let mut bytes_to_be_written = read + min_log_len;
-            if partial {
-                bytes_to_be_written += 1; // the added newline
+            if partial
+                && self.partial_line_mode() == PartialLineMode::Buffer
+                && line_buf.len() < self.max_line_size()
+            {
+                trace!("Buffering partial line of length {}", line_buf.len());
+                *self.pending_mut(pipe) = line_buf;
+                continue;
             }
 
-            let mut new_bytes_written = match self.bytes_written().checked_add(bytes_to_be_written)
-            {
-                Some(x) => x,
-                None => {
-                    self.reopen()
-                        .await
-                        .context("reopen logs because of overflowing bytes_written")?;
-                    0
+            // Partial lines end on a code point boundary, where the remainder of a split code
+            // point starts the next entry. A sole incomplete code point is kept until more
+            // output arrives, unless it already exceeds the maximum line size.
+            let len = match (partial, utf8_boundary::complete_len(&line_buf)) {
+                (false, _) => line_buf.len(),
+                (true, 0) if line_buf.len() < self.max_line_size() => {
+                    *self.pending_mut(pipe) = line_buf;
+                    continue;
                 }
+                (true, 0) => line_buf.len(),
+                (true, len) => len,
             };
 
-            if let Some(max_log_size) = self.max_log_size() {
-                trace!(
-                    "Verifying log size: max_log_size = {}, bytes_written = {},  bytes_to_be_written = {}, new_bytes_written = {}", 
-                    max_log_size, self.bytes_written(),  bytes_to_be_written, new_bytes_written,
-                );
-
-                if new_bytes_written > max_log_size {
-                    new_bytes_written = 0;
-                    self.reopen()
-                        .await
-                        .context("reopen logs because of exceeded size")?;
-                }
+            self.write_entry(pipe, timestamp.as_deref(), &line_buf[..len], partial)
+                .await?;
+            line_buf.drain(..len);
+            *self.pending_mut(pipe) = line_buf;
+        }
+
+        match self.flush_interval() {
+            Some(interval)
+                if self.unflushed() < self.flush_threshold()
+                    && self.last_flush.elapsed() < interval =>
+            {
+                Ok(())
             }
+            _ => self.flush().await,
+        }
+    }
 
-            // Write the timestmap
-            let file = self.file.as_mut().context(Self::ERR_UNINITIALIZED)?;
-            file.write_all(timestamp.as_bytes()).await?;
+    /// Write a single log entry, which rotates the log if the maximum log size is exceeded.
+    async fn write_entry(
+        &mut self,
+        pipe: Pipe,
+        timestamp: Option<&str>,
+        line: &[u8],
+        partial: bool,
+    ) -> Result<()> {
+        let min_log_len = timestamp
+            .map_or(0, |x| x.len() + 1) // len of timestamp + " "
+            .checked_add(9) // len of "stdout " + "P "
+            .context("min log line len exceeds usize")?;
+        let mut bytes_to_be_written = line.len() + min_log_len;
+        if partial {
+            bytes_to_be_written += 1; // the added newline
+        }
 
-            // Add the pipe name
-            match pipe {
-                Pipe::StdOut => file.write_all(b" stdout ").await,
-                Pipe::StdErr => file.write_all(b" stderr ").await,
-            }?;
-
-            // Output log tag for partial or newline
-            if partial {
-                file.write_all(b"P ").await?;
-            } else {
-                file.write_all(b"F ").await?;
+        let mut new_bytes_written = match self.bytes_written().checked_add(bytes_to_be_written) {
+            Some(x) => x,
+            None => {
+                self.reopen()
+                    .await
+                    .context("reopen logs because of overflowing bytes_written")?;
+                0
             }
+        };
 
-            // Output the actual contents
-            file.write_all(&line_buf).await?;
-
-            // Output a newline for partial
-            if partial {
-                file.write_all(b"\n").await?;
+        if let Some(max_log_size) = self.max_log_size() {
+            trace!(
+                "Verifying log size: max_log_size = {}, bytes_written = {},  bytes_to_be_written = {}, new_bytes_written = {}", 
+                max_log_size, self.bytes_written(),  bytes_to_be_written, new_bytes_written,
+            );
+
+            if new_bytes_written > max_log_size {
+                new_bytes_written = 0;
+                self.rotate()
+                    .await
+                    .context("rotate logs because of exceeded size")?;
             }
+        }
 
-            self.set_bytes_written(new_bytes_written);
-            trace!("Wrote log line of length {}", bytes_to_be_written);
+        // Write the timestmap
+        let file = self.file.as_mut().context(Self::ERR_UNINITIALIZED)?;
+        if let Some(timestamp) = timestamp {
+            file.write_all(timestamp.as_bytes()).await?;
+            file.write_all(b" ").await?;
+        }
+
+        // Add the pipe name
+        match pipe {
+            Pipe::StdOut => file.write_all(b"stdout ").await,
+            Pipe::StdErr => file.write_all(b"stderr ").await,
+        }?;
+
+        // Output log tag for partial or newline
+        if partial {
+            file.write_all(b"P ").await?;
+        } else {
+            file.write_all(b"F ").await?;
+        }
+
+        // Output the actual contents
+        file.write_all(line).await?;
+
+        // Output a newline for partial
+        if partial {
+            file.write_all(b"\n").await?;
         }
 
-        self.flush().await
+        self.set_bytes_written(new_bytes_written);
+        self.unflushed += bytes_to_be_written;
+        trace!("Wrote log line of length {}", bytes_to_be_written);
+
+        if self.log_sync.record(bytes_to_be_written) {
+            self.flush().await?;
+            self.sync().await?;
+        }
+        Ok(())
     }
 
-    /// Reopen the container log file.
-    pub async fn reopen(&mut self) -> Result<()> {
-        debug!("Reopen container log {}", self.path().display());
+    /// Returns the buffered partial line of the provided pipe.
+    fn pending_mut(&mut self, pipe: Pipe) -> &mut Vec<u8> {
+        match pipe {
+            Pipe::StdOut => &mut self.pending_stdout,
+            Pipe::StdErr => &mut self.pending_stderr,
+        }
+    }
+
+    /// Recreate the log file if it got removed while being open, which returns whether it got
+    /// recreated. Buffered entries are written to the recreated file instead of the removed one.
+    pub async fn recreate_removed(&mut self) -> Result<bool> {
+        match fs::metadata(self.path()).await {
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            _ => return Ok(false),
+        }
+        warn!("Recreating removed container log {}", self.path().display());
+        let buffered = self
+            .file
+            .as_ref()
+            .map(|file| file.buffer().to_vec())
+            .unwrap_or_default();
+        self.init().await?;
+        self.set_bytes_written(buffered.len());
         self.file
             .as_mut()
             .context(Self::ERR_UNINITIALIZED)?
-            .get_ref()
-            .sync_all()
-            .await?;
+            .write_all(&buffered)
+            .await
+            .context("write buffered entries")?;
+        Ok(true)
+    }
+
+    /// Reopen the container log file.
+    pub async fn reopen(&mut self) -> Result<()> {
+        debug!("Reopen container log {}", self.path().display());
+        self.flush().await?;
+        self.sync().await?;
         self.init().await
     }
 
+    /// Rotate the container log file, which keeps up to `max_files - 1` old logs.
+    pub async fn rotate(&mut self) -> Result<()> {
+        self.rotations += 1;
+        if self.max_files() > 1 {
+            self.flush().await?;
+            ContainerLog::rotate_files(
+                &self.path,
+                self.max_files(),
+                self.compression(),
+                &mut self.compression_task,
+            )
+            .await?;
+        }
+        self.reopen().await
+    }
+
+    /// Truncate the container log file and remove all rotated files.
+    pub async fn truncate(&mut self) -> Result<()> {
+        ContainerLog::remove_rotated_files(
+            &self.path,
+            self.max_files(),
+            &mut self.compression_task,
+        )
+        .await?;
+        self.reopen().await
+    }
+
     /// Ensures that all content is written to disk.
     pub async fn flush(&mut self) -> Result<()> {
         self.file
@@ -162,12 +383,30 @@ impl CriLogger {
             .context(Self::ERR_UNINITIALIZED)?
             .flush()
             .await
-            .context("flush file writer")
+            .context("flush file writer")?;
+        self.unflushed = 0;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+
+    /// Sync the contents of the log file to disk.
+    async fn sync(&mut self) -> Result<()> {
+        self.file
+            .as_mut()
+            .context(Self::ERR_UNINITIALIZED)?
+            .get_ref()
+            .sync_all()
+            .await
+            .context("sync log file")?;
+        self.log_sync.synced();
+        Ok(())
     }
 
-    /// Open the provided path with the default options.
-    async fn open<T: AsRef<Path>>(path: T) -> Result<BufWriter<File>> {
-        Ok(BufWriter::new(
+    /// Open the provided path with the default options, where the writer buffers at least
+    /// `capacity` bytes.
+    async fn open<T: AsRef<Path>>(path: T, capacity: usize) -> Result<BufWriter<File>> {
+        Ok(BufWriter::with_capacity(
+            capacity,
             OpenOptions::new()
                 .create(true)
                 .read(true)
@@ -180,25 +419,38 @@ impl CriLogger {
         ))
     }
 
-    async fn read_line<T>(r: &mut BufReader<T>, buf: &mut Vec<u8>) -> Result<(usize, bool)>
+    /// Read until the next newline, the end of the reader or until `buf` contains `max_len`
+    /// bytes. Returns the amount of read bytes and whether the line is partial.
+    async fn read_line<T>(r: &mut T, buf: &mut Vec<u8>, max_len: usize) -> Result<(usize, bool)>
     where
         T: AsyncBufRead + Unpin,
     {
-        let (partial, read) = {
-            let available = r.fill_buf().await?;
-            match memchr(b'\n', available) {
-                Some(i) => {
-                    buf.extend_from_slice(&available[..=i]);
-                    (false, i + 1)
+        let mut total = 0;
+        loop {
+            let (done, read) = {
+                let available = r.fill_buf().await?;
+                if available.is_empty() {
+                    return Ok((total, true));
                 }
-                None => {
-                    buf.extend_from_slice(available);
-                    (true, available.len())
+                let limit = max_len.saturating_sub(buf.len());
+                match memchr(b'\n', &available[..available.len().min(limit + 1)]) {
+                    Some(i) => {
+                        buf.extend_from_slice(&available[..=i]);
+                        (true, i + 1)
+                    }
+                    None => {
+                        let read = available.len().min(limit);
+                        buf.extend_from_slice(&available[..read]);
+                        (read == limit, read)
+                    }
                 }
+            };
+            r.consume(read);
+            total += read;
+            if done {
+                return Ok((total, buf.last() != Some(&b'\n')));
             }
-        };
-        r.consume(read);
-        Ok((read, partial))
+        }
     }
 }
 
@@ -206,7 +458,7 @@ impl CriLogger {
 mod tests {
     use super::*;
     use std::fs;
-    use tempfile::NamedTempFile;
+    use tempfile::{tempdir, NamedTempFile};
     use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
     #[tokio::test]
@@ -216,7 +468,7 @@ mod tests {
 
         let file = NamedTempFile::new()?;
         let path = file.path();
-        let mut sut = CriLogger::new(path, None)?;
+        let mut sut = CriLogger::new(path, None, 1, None)?;
         sut.init().await?;
 
         sut.write(Pipe::StdOut, bytes).await?;
@@ -230,6 +482,67 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn write_timestamp_formats() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let path = file.path();
+        let mut sut = CriLogger::new(path, None, 1, None)?;
+        sut.set_timestamp_format(TimestampFormat::None);
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, "a\n".as_bytes()).await?;
+        sut.set_timestamp_format(TimestampFormat::UnixNano);
+        sut.write(Pipe::StdErr, "b\n".as_bytes()).await?;
+
+        let res = fs::read_to_string(path)?;
+        let lines = res.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "stdout F a");
+        let (timestamp, entry) = lines[1].split_once(' ').context("no timestamp")?;
+        assert!(timestamp.parse::<u128>()? > 0);
+        assert_eq!(entry, "stderr F b");
+        assert_eq!(sut.bytes_written(), res.len());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_sync_line() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let path = file.path();
+        let mut sut = CriLogger::new(path, None, 1, None)?;
+        sut.set_flush_interval(Some(Duration::from_secs(60)));
+        sut.set_sync_policy(SyncPolicy::Line);
+        sut.init().await?;
+
+        // Syncing every line bypasses the buffering
+        sut.write(Pipe::StdOut, "a\n".as_bytes()).await?;
+        assert_eq!(sut.unflushed(), 0);
+        assert!(fs::read_to_string(path)?.ends_with(" stdout F a\n"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn recreate_removed() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("0.log");
+        let mut sut = CriLogger::new(&path, None, 1, None)?;
+        sut.set_flush_interval(Some(Duration::from_secs(60)));
+        sut.init().await?;
+        assert!(!sut.recreate_removed().await?);
+
+        // Buffered entries end up in the recreated file
+        sut.write(Pipe::StdOut, "a\n".as_bytes()).await?;
+        fs::remove_file(&path)?;
+        assert!(sut.recreate_removed().await?);
+        sut.write(Pipe::StdOut, "b\n".as_bytes()).await?;
+        sut.flush().await?;
+
+        let res = fs::read_to_string(&path)?;
+        assert!(res.contains(" stdout F a\n"));
+        assert!(res.ends_with(" stdout F b\n"));
+        assert_eq!(sut.bytes_written(), res.len());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn write_stdout_stderr_success() -> Result<()> {
         let buffer = "a\nb\nc\n";
@@ -238,7 +551,7 @@ mod tests {
 
         let file = NamedTempFile::new()?;
         let path = file.path();
-        let mut sut = CriLogger::new(path, None)?;
+        let mut sut = CriLogger::new(path, None, 1, None)?;
         sut.init().await?;
 
         sut.write(Pipe::StdOut, bytes1).await?;
@@ -261,7 +574,7 @@ mod tests {
 
         let file = NamedTempFile::new()?;
         let path = file.path();
-        let mut sut = CriLogger::new(path, Some(150))?;
+        let mut sut = CriLogger::new(path, Some(150), 1, None)?;
         sut.init().await?;
 
         sut.write(Pipe::StdOut, bytes).await?;
@@ -280,7 +593,7 @@ mod tests {
     async fn write_multi_reopen() -> Result<()> {
         let file = NamedTempFile::new()?;
         let path = file.path();
-        let mut sut = CriLogger::new(path, Some(150))?;
+        let mut sut = CriLogger::new(path, Some(150), 1, None)?;
         sut.init().await?;
 
         sut.write(Pipe::StdOut, "abcd\nabcd\nabcd\n".as_bytes())
@@ -295,9 +608,133 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn write_rotate() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log");
+        let mut sut = CriLogger::new(&path, Some(150), 3, None)?;
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, "a\nb\nc\nd\ne\nf\ng\nh\ni\n".as_bytes())
+            .await?;
+
+        let res = fs::read_to_string(&path)?;
+        assert!(res.contains(" stdout F h"));
+        assert!(res.contains(" stdout F i"));
+
+        let res = fs::read_to_string(ContainerLog::rotated_path(&path, 1))?;
+        assert!(res.contains(" stdout F d"));
+        assert!(res.contains(" stdout F g"));
+
+        let res = fs::read_to_string(ContainerLog::rotated_path(&path, 2))?;
+        assert!(res.contains(" stdout F a"));
+        assert!(res.contains(" stdout F c"));
+
+        assert!(!ContainerLog::rotated_path(&path, 3).exists());
+        assert_eq!(sut.rotations(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_max_line_size() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let path = file.path();
+        let mut sut = CriLogger::new(path, None, 1, None)?;
+        sut.set_max_line_size(4);
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, "abcdefghij\nabcd\nab".as_bytes())
+            .await?;
+
+        let res = fs::read_to_string(path)?;
+        let entries = res
+            .lines()
+            .map(|x| x.splitn(3, ' ').nth(2).unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(entries, vec!["P abcd", "P efgh", "F ij", "F abcd", "P ab"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_split_code_points() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let path = file.path();
+        let mut sut = CriLogger::new(path, None, 1, None)?;
+        sut.set_max_line_size(4);
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, "abc€d\n".as_bytes()).await?;
+        sut.write(Pipe::StdOut, &b"x\xc3"[..]).await?;
+        sut.write(Pipe::StdOut, &b"\xa4\n"[..]).await?;
+
+        let res = fs::read_to_string(path)?;
+        let entries = res
+            .lines()
+            .map(|x| x.splitn(3, ' ').nth(2).unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(entries, vec!["P abc", "F €d", "P x", "F ä"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_buffer_partial_lines() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let path = file.path();
+        let mut sut = CriLogger::new(path, None, 1, None)?;
+        sut.set_max_line_size(8);
+        sut.set_partial_line_mode(PartialLineMode::Buffer);
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, "a\nbc".as_bytes()).await?;
+        sut.write(Pipe::StdErr, "x".as_bytes()).await?;
+        sut.write(Pipe::StdOut, "d\nefgh".as_bytes()).await?;
+        sut.write(Pipe::StdOut, "ijklmn".as_bytes()).await?;
+        sut.write(Pipe::StdErr, "y\n".as_bytes()).await?;
+
+        let res = fs::read_to_string(path)?;
+        let entries = res
+            .lines()
+            .filter_map(|x| x.split_once(' ').map(|(_, entry)| entry))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                "stdout F a",
+                "stdout F bcd",
+                "stdout P efghijkl",
+                "stderr F xy"
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_flush_interval() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let path = file.path();
+        let mut sut = CriLogger::new(path, None, 1, None)?;
+        sut.set_flush_interval(Some(Duration::from_secs(60)));
+        sut.set_flush_threshold(100);
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, "a\n".as_bytes()).await?;
+        assert!(fs::read_to_string(path)?.is_empty());
+        assert!(sut.unflushed() > 0);
+
+        // Exceeding the threshold flushes all buffered entries
+        sut.write(Pipe::StdOut, "b".repeat(100).as_bytes()).await?;
+        assert_eq!(sut.unflushed(), 0);
+        assert_eq!(fs::read_to_string(path)?.lines().count(), 2);
+
+        sut.write(Pipe::StdErr, "c\n".as_bytes()).await?;
+        sut.reopen().await?;
+        assert_eq!(sut.unflushed(), 0);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn init_failure() -> Result<()> {
-        let mut sut = CriLogger::new("/file/does/not/exist", None)?;
+        let mut sut = CriLogger::new("/file/does/not/exist", None, 1, None)?;
         assert!(sut.init().await.is_err());
         Ok(())
     }
@@ -0,0 +1,134 @@
+//! Structured events of the containers, which get pushed to the subscribed RPC clients.
+
+use getset::{CopyGetters, Getters};
+use lazy_static::lazy_static;
+use std::time::SystemTime;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+lazy_static! {
+    /// The event bus of the server process.
+    pub static ref EVENTS: EventBus = EventBus::default();
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The kinds of events which can happen to a container.
+pub enum EventKind {
+    /// The container or exec session exited with the contained code.
+    Exited(i32),
+
+    /// The container got killed because it ran out of memory.
+    OomKilled,
+
+    /// The log file of the container got rotated.
+    LogRotated,
+
+    /// An attach client connected to the container.
+    AttachConnected,
+
+    /// An attach client disconnected from the container.
+    AttachDisconnected,
+
+    /// The container got restarted by its restart policy for the contained amount of times.
+    Restarted(u32),
+}
+
+#[derive(Clone, CopyGetters, Debug, Getters)]
+/// A single event of a container.
+pub struct Event {
+    #[getset(get = "pub")]
+    /// The ID of the container.
+    container_id: String,
+
+    #[getset(get = "pub")]
+    /// The ID of the exec session, which is empty for the container itself.
+    exec_session_id: String,
+
+    #[getset(get_copy = "pub")]
+    /// What happened to the container.
+    kind: EventKind,
+
+    #[getset(get_copy = "pub")]
+    /// The time at which the event happened.
+    timestamp: SystemTime,
+}
+
+#[derive(Debug)]
+/// The broadcast channel distributing the events to all subscribers.
+pub struct EventBus {
+    sender: Sender<Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(Self::CAPACITY);
+        Self { sender }
+    }
+}
+
+impl EventBus {
+    /// The number of events buffered for each subscriber before it starts lagging behind.
+    const CAPACITY: usize = 1024;
+
+    /// Publish an event of the container, which gets dropped if nobody is subscribed.
+    pub fn publish(&self, container_id: &str, kind: EventKind) {
+        self.publish_exec(container_id, "", kind)
+    }
+
+    /// Publish an event of an exec session of the container.
+    pub fn publish_exec(&self, container_id: &str, exec_session_id: &str, kind: EventKind) {
+        if container_id.is_empty() {
+            return;
+        }
+        let _ = self.sender.send(Event {
+            container_id: container_id.into(),
+            exec_session_id: exec_session_id.into(),
+            kind,
+            timestamp: SystemTime::now(),
+        });
+    }
+
+    /// Subscribe to all events published from now on.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    #[test]
+    fn publish_and_subscribe() -> Result<()> {
+        let sut = EventBus::default();
+        sut.publish("dropped", EventKind::LogRotated);
+
+        let mut rx = sut.subscribe();
+        sut.publish("id", EventKind::Exited(1));
+        sut.publish_exec("id", "exec", EventKind::OomKilled);
+        sut.publish("", EventKind::AttachConnected);
+
+        let event = rx.try_recv()?;
+        assert_eq!(event.container_id(), "id");
+        assert!(event.exec_session_id().is_empty());
+        assert_eq!(event.kind(), EventKind::Exited(1));
+
+        let event = rx.try_recv()?;
+        assert_eq!(event.exec_session_id(), "exec");
+        assert_eq!(event.kind(), EventKind::OomKilled);
+
+        assert!(matches!(rx.try_recv(), Err(TryRecvError::Empty)));
+        Ok(())
+    }
+
+    #[test]
+    fn lagging_subscriber() {
+        let sut = EventBus::default();
+        let mut rx = sut.subscribe();
+        for _ in 0..EventBus::CAPACITY + 2 {
+            sut.publish("id", EventKind::AttachDisconnected);
+        }
+        assert!(matches!(rx.try_recv(), Err(TryRecvError::Lagged(2))));
+    }
+}
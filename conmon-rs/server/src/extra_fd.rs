@@ -0,0 +1,208 @@
+//! Forwarding of additional file descriptors of the container into files or sockets.
+
+use crate::container_io::IoMetrics;
+use anyhow::{bail, Context, Result};
+use getset::{CopyGetters, Getters};
+use nix::{
+    fcntl::{self, FcntlArg, OFlag},
+    unistd,
+};
+use std::{
+    fs::File,
+    io::Read,
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    path::PathBuf,
+    sync::Arc,
+};
+use tokio::{
+    fs::OpenOptions,
+    io::{unix::AsyncFd, AsyncWrite, AsyncWriteExt},
+    net::UnixStream,
+    select, task,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, debug_span, error, Instrument};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// The available destinations of the data written to an extra file descriptor.
+pub enum ExtraFdSink {
+    /// Append the data to the file at the path, which gets created if it does not exist.
+    File(PathBuf),
+
+    /// Write the data into the unix stream socket at the path.
+    Socket(PathBuf),
+}
+
+#[derive(Clone, CopyGetters, Debug, Eq, Getters, PartialEq)]
+/// An additional file descriptor of the container, whose data gets forwarded into a sink.
+pub struct ExtraFd {
+    #[getset(get_copy = "pub")]
+    /// The number of the file descriptor in the container process.
+    fd: RawFd,
+
+    #[getset(get = "pub")]
+    /// The destination of the written data.
+    sink: ExtraFdSink,
+}
+
+impl ExtraFd {
+    /// The lowest number of an extra file descriptor, lower ones are the standard streams.
+    pub const MIN_FD: RawFd = 3;
+
+    /// Create a new extra file descriptor.
+    pub fn new(fd: RawFd, sink: ExtraFdSink) -> Result<Self> {
+        if fd < Self::MIN_FD {
+            bail!("extra fd {} is lower than {}", fd, Self::MIN_FD)
+        }
+        Ok(Self { fd, sink })
+    }
+
+    /// Open the sink and create the pipe, whose write end gets a number of at least `min_fd` to
+    /// not collide with the numbers of the other extra file descriptors.
+    pub async fn open(&self, min_fd: RawFd) -> Result<ExtraFdPipe> {
+        let sink: Box<dyn AsyncWrite + Send + Unpin> = match &self.sink {
+            ExtraFdSink::File(path) => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .mode(0o600)
+                    .open(path)
+                    .await
+                    .context(format!("open extra fd file '{}'", path.display()))?,
+            ),
+            ExtraFdSink::Socket(path) => Box::new(
+                UnixStream::connect(path)
+                    .await
+                    .context(format!("connect extra fd socket '{}'", path.display()))?,
+            ),
+        };
+
+        let (read_fd, write_fd) = unistd::pipe2(OFlag::O_CLOEXEC).context("create pipe")?;
+        // SAFETY: the file descriptors got just created and are owned exclusively.
+        let (reader, write_end) =
+            unsafe { (File::from_raw_fd(read_fd), File::from_raw_fd(write_fd)) };
+        fcntl::fcntl(read_fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
+            .context("set pipe non-blocking")?;
+        let writer = fcntl::fcntl(write_end.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(min_fd))
+            .context("move pipe write end")?;
+
+        Ok(ExtraFdPipe {
+            fd: self.fd,
+            reader,
+            // SAFETY: the file descriptor got just duplicated and is owned exclusively.
+            writer: unsafe { File::from_raw_fd(writer) },
+            sink,
+        })
+    }
+}
+
+/// The pipe of an extra file descriptor, whose write end gets passed to the container.
+pub struct ExtraFdPipe {
+    fd: RawFd,
+    reader: File,
+    writer: File,
+    sink: Box<dyn AsyncWrite + Send + Unpin>,
+}
+
+impl ExtraFdPipe {
+    const BUF_SIZE: usize = 8192;
+
+    /// Returns the write end and the number it has to be duplicated to in the container process.
+    pub fn dup_target(&self) -> (RawFd, RawFd) {
+        (self.writer.as_raw_fd(), self.fd)
+    }
+
+    /// Close the write end and forward the data of the pipe into the sink until all writers are
+    /// gone or the token got cancelled.
+    pub fn spawn(self, metrics: Arc<IoMetrics>, token: CancellationToken) -> Result<()> {
+        let Self {
+            fd,
+            reader,
+            writer,
+            sink,
+        } = self;
+        drop(writer);
+        let reader = AsyncFd::new(reader).context("register pipe")?;
+        metrics.record_extra_fd(fd, 0)?;
+
+        task::spawn(
+            async move {
+                if let Err(e) = Self::forward(fd, reader, sink, &metrics, token).await {
+                    error!("Unable to forward extra fd {}: {:#}", fd, e);
+                }
+            }
+            .instrument(debug_span!("extra_fd")),
+        );
+        Ok(())
+    }
+
+    async fn forward(
+        fd: RawFd,
+        reader: AsyncFd<File>,
+        mut sink: Box<dyn AsyncWrite + Send + Unpin>,
+        metrics: &IoMetrics,
+        token: CancellationToken,
+    ) -> Result<()> {
+        let mut buf = vec![0; Self::BUF_SIZE];
+        loop {
+            let n = select! {
+                guard = reader.readable() => {
+                    let mut guard = guard.context("wait for readable pipe")?;
+                    match guard.try_io(|r| r.get_ref().read(&mut buf)) {
+                        Ok(n) => n.context("read pipe")?,
+                        Err(_would_block) => continue,
+                    }
+                }
+                _ = token.cancelled() => {
+                    debug!("Stopping extra fd {} because token cancelled", fd);
+                    return Ok(());
+                }
+            };
+            if n == 0 {
+                debug!("Stopping extra fd {} because all writers are gone", fd);
+                return Ok(());
+            }
+            sink.write_all(&buf[..n]).await.context("write to sink")?;
+            sink.flush().await.context("flush sink")?;
+            metrics.record_extra_fd(fd, n)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn forward_file() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("fd");
+        let metrics = Arc::new(IoMetrics::default());
+        let token = CancellationToken::new();
+
+        assert!(ExtraFd::new(2, ExtraFdSink::File(path.clone())).is_err());
+        let sut = ExtraFd::new(5, ExtraFdSink::File(path.clone()))?;
+        let pipe = sut.open(100).await?;
+        let (writer, fd) = pipe.dup_target();
+        assert!(writer >= 100);
+        assert_eq!(fd, 5);
+
+        let mut writer = pipe.writer.try_clone()?;
+        pipe.spawn(metrics.clone(), token.clone())?;
+        writer.write_all(b"hello")?;
+        drop(writer);
+
+        for _ in 0..100 {
+            if metrics.stats()?.extra_fds() == &[(5, 5)] {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(metrics.stats()?.extra_fds(), &[(5, 5)]);
+        assert_eq!(std::fs::read_to_string(&path)?, "hello");
+        token.cancel();
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! Receiving of file descriptors from clients via `SCM_RIGHTS`.
+//!
+//! The RPC connection cannot transfer file descriptors, which is why clients send them to a
+//! separate socket next to the RPC socket. Every message consists of a big endian `u64` request
+//! ID and the attached file descriptors. The server replies with the request ID followed by one
+//! big endian `u64` slot per received file descriptor, where slots are never zero. RPC requests
+//! reference the file descriptors by their slots, which are valid until the file descriptor got
+//! used once or the client closed the connection to the socket.
+
+use anyhow::{bail, format_err, Context, Result};
+use sendfd::RecvWithFd;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::ErrorKind,
+    os::unix::io::{FromRawFd, RawFd},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncWriteExt, Interest},
+    net::{UnixListener, UnixStream},
+    task,
+};
+use tracing::{debug, debug_span, error, Instrument};
+
+#[derive(Debug, Default)]
+/// The received file descriptors of all client connections.
+pub struct FdSocket {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The last assigned slot.
+    last_slot: u64,
+
+    /// The received file descriptors which are not yet used.
+    slots: HashMap<u64, File>,
+}
+
+impl FdSocket {
+    /// The maximum amount of file descriptors per message.
+    const MAX_FDS: usize = 16;
+
+    /// The length of the request ID and the slots.
+    const ID_LEN: usize = 8;
+
+    /// Serve the clients connecting to the provided listener.
+    pub async fn serve(self: Arc<Self>, listener: UnixListener) -> Result<()> {
+        loop {
+            let stream = listener.accept().await.context("accept fd socket")?.0;
+            let fd_socket = self.clone();
+            task::spawn(
+                async move {
+                    if let Err(e) = fd_socket.handle(stream).await {
+                        error!("Unable to receive file descriptors: {:#}", e);
+                    }
+                }
+                .instrument(debug_span!("fd_socket_connection")),
+            );
+        }
+    }
+
+    /// Take the file descriptor of the provided slot, which can be taken only once.
+    pub fn take(&self, slot: u64) -> Result<File> {
+        self.state
+            .lock()
+            .map_err(|e| format_err!("lock fd socket state: {}", e))?
+            .slots
+            .remove(&slot)
+            .context(format!("no file descriptor in slot {}", slot))
+    }
+
+    /// Receive the file descriptors of a single client connection, where the unused ones get
+    /// closed once the connection is closed.
+    async fn handle(&self, mut stream: UnixStream) -> Result<()> {
+        let mut assigned = vec![];
+        let result = self.receive(&mut stream, &mut assigned).await;
+        if let Ok(mut state) = self.state.lock() {
+            for slot in &assigned {
+                state.slots.remove(slot);
+            }
+        }
+        result
+    }
+
+    async fn receive(&self, stream: &mut UnixStream, assigned: &mut Vec<u64>) -> Result<()> {
+        loop {
+            stream.readable().await?;
+            let mut data = [0; Self::ID_LEN];
+            let mut fds: [RawFd; Self::MAX_FDS] = [-1; Self::MAX_FDS];
+            let (read, fd_read) = match stream.try_io(Interest::READABLE, || {
+                stream.recv_with_fd(&mut data, &mut fds)
+            }) {
+                Ok(x) => x,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e).context("receive file descriptors"),
+            };
+
+            // SAFETY: the file descriptors got received and are owned exclusively.
+            let files = fds[..fd_read]
+                .iter()
+                .map(|fd| unsafe { File::from_raw_fd(*fd) })
+                .collect::<Vec<_>>();
+            if read == 0 && files.is_empty() {
+                debug!("Client closed fd socket connection");
+                return Ok(());
+            }
+            if read != Self::ID_LEN {
+                bail!("invalid request ID length {}", read)
+            }
+
+            let slots = self.insert(files)?;
+            debug!("Received {} file descriptors", slots.len());
+            assigned.extend_from_slice(&slots);
+
+            let mut response = data.to_vec();
+            for slot in slots {
+                response.extend_from_slice(&slot.to_be_bytes());
+            }
+            stream
+                .write_all(&response)
+                .await
+                .context("write fd socket response")?;
+        }
+    }
+
+    /// Assign a new slot to every file.
+    fn insert(&self, files: Vec<File>) -> Result<Vec<u64>> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|e| format_err!("lock fd socket state: {}", e))?;
+        let mut slots = vec![];
+        for file in files {
+            state.last_slot += 1;
+            let slot = state.last_slot;
+            state.slots.insert(slot, file);
+            slots.push(slot);
+        }
+        Ok(slots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::unistd::pipe;
+    use sendfd::SendWithFd;
+    use std::{
+        convert::TryInto,
+        io::{Read, Write},
+        os::unix::io::AsRawFd,
+    };
+    use tempfile::tempdir;
+    use tokio::io::AsyncReadExt;
+
+    fn parse_response(response: &[u8]) -> Result<(u64, Vec<u64>)> {
+        let mut chunks = response.chunks_exact(8);
+        let id = u64::from_be_bytes(chunks.next().context("no request ID")?.try_into()?);
+        let slots = chunks
+            .map(|x| Ok(u64::from_be_bytes(x.try_into()?)))
+            .collect::<Result<_>>()?;
+        Ok((id, slots))
+    }
+
+    #[tokio::test]
+    async fn receive() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("fd.sock");
+        let sut = Arc::new(FdSocket::default());
+        task::spawn(sut.clone().serve(UnixListener::bind(&path)?));
+
+        let (read_fd, write_fd) = pipe()?;
+        let mut reader = unsafe { File::from_raw_fd(read_fd) };
+        let writer = unsafe { File::from_raw_fd(write_fd) };
+        let other = tempfile::tempfile()?;
+
+        let mut stream = UnixStream::connect(&path).await?;
+        stream.writable().await?;
+        stream.send_with_fd(
+            &42u64.to_be_bytes(),
+            &[writer.as_raw_fd(), other.as_raw_fd()],
+        )?;
+        drop(writer);
+
+        let mut response = [0; 24];
+        stream.read_exact(&mut response).await?;
+        let (id, slots) = parse_response(&response)?;
+        assert_eq!(id, 42);
+        assert_eq!(slots.len(), 2);
+
+        // The received file descriptor refers to the same pipe
+        let mut file = sut.take(slots[0])?;
+        file.write_all(b"hello")?;
+        drop(file);
+        let mut buf = String::new();
+        reader.read_to_string(&mut buf)?;
+        assert_eq!(buf, "hello");
+
+        // Slots can be taken only once and are released on disconnect
+        assert!(sut.take(slots[0]).is_err());
+        drop(stream);
+        for _ in 0..100 {
+            if sut.state.lock().unwrap().slots.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(sut.take(slots[1]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+//! Ownership and SELinux labeling of log files.
+
+use anyhow::{Context, Result};
+use getset::{CopyGetters, Getters};
+use nix::{
+    errno::Errno,
+    unistd::{fchown, Gid, Uid},
+};
+use std::{
+    ffi::CString,
+    fs::File,
+    os::unix::{
+        fs::MetadataExt,
+        io::{AsRawFd, RawFd},
+    },
+};
+
+#[derive(Clone, Debug, Default, CopyGetters, Getters, PartialEq, Eq)]
+/// The owner and SELinux file context applied to created log files.
+pub struct FileOwnership {
+    #[getset(get_copy = "pub")]
+    /// The user owning the file, unchanged if `None`.
+    uid: Option<u32>,
+
+    #[getset(get_copy = "pub")]
+    /// The group owning the file, unchanged if `None`.
+    gid: Option<u32>,
+
+    #[getset(get = "pub")]
+    /// The SELinux file context, the default label is kept if `None`.
+    selinux_context: Option<String>,
+}
+
+impl FileOwnership {
+    /// The extended attribute containing the SELinux file context.
+    const SELINUX_XATTR: &'static [u8] = b"security.selinux\0";
+
+    /// Create a new file ownership.
+    pub fn new(uid: Option<u32>, gid: Option<u32>, selinux_context: Option<String>) -> Self {
+        Self {
+            uid,
+            gid,
+            selinux_context,
+        }
+    }
+
+    /// The ownership of the provided file including its SELinux file context, if set.
+    pub fn of(file: &File) -> Result<Self> {
+        let metadata = file.metadata().context("get file metadata")?;
+        Ok(Self::new(
+            Some(metadata.uid()),
+            Some(metadata.gid()),
+            Self::get_selinux_context(file.as_raw_fd())?,
+        ))
+    }
+
+    /// Copy the ownership of `src` to `dst`, where only differing attributes get applied.
+    pub fn copy(src: &File, dst: &File) -> Result<()> {
+        let src_ownership = Self::of(src)?;
+        let dst_ownership = Self::of(dst)?;
+        Self::new(
+            src_ownership
+                .uid()
+                .filter(|x| Some(*x) != dst_ownership.uid()),
+            src_ownership
+                .gid()
+                .filter(|x| Some(*x) != dst_ownership.gid()),
+            src_ownership
+                .selinux_context
+                .filter(|x| Some(x) != dst_ownership.selinux_context().as_ref()),
+        )
+        .apply(dst)
+    }
+
+    /// Apply the ownership to the provided file.
+    pub fn apply<T: AsRawFd>(&self, file: &T) -> Result<()> {
+        if self.uid().is_some() || self.gid().is_some() {
+            fchown(
+                file.as_raw_fd(),
+                self.uid().map(Uid::from_raw),
+                self.gid().map(Gid::from_raw),
+            )
+            .context("change log file owner")?;
+        }
+        if let Some(context) = self.selinux_context() {
+            Self::set_selinux_context(file.as_raw_fd(), context)?;
+        }
+        Ok(())
+    }
+
+    /// Set the SELinux file context of the provided file.
+    fn set_selinux_context(fd: RawFd, context: &str) -> Result<()> {
+        let value = CString::new(context).context("convert SELinux context")?;
+        let value = value.as_bytes_with_nul();
+        // SAFETY: the name and value are valid for their provided lengths.
+        let res = unsafe {
+            libc::fsetxattr(
+                fd,
+                Self::SELINUX_XATTR.as_ptr().cast(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        Errno::result(res).context(format!("set SELinux context '{}'", context))?;
+        Ok(())
+    }
+
+    /// Get the SELinux file context of the provided file, `None` if it is not labeled.
+    fn get_selinux_context(fd: RawFd) -> Result<Option<String>> {
+        let mut value = vec![0u8; 256];
+        // SAFETY: the name and value are valid for their provided lengths.
+        let res = unsafe {
+            libc::fgetxattr(
+                fd,
+                Self::SELINUX_XATTR.as_ptr().cast(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        match Errno::result(res) {
+            Ok(len) => {
+                value.truncate(len as usize);
+                if value.last() == Some(&0) {
+                    value.pop();
+                }
+                Ok(Some(String::from_utf8_lossy(&value).into()))
+            }
+            Err(Errno::ENODATA) | Err(Errno::ENOTSUP) => Ok(None),
+            Err(e) => Err(e).context("get SELinux context"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::unistd::{getgid, getuid};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn apply_owner() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let sut = FileOwnership::new(Some(getuid().as_raw()), Some(getgid().as_raw()), None);
+        sut.apply(file.as_file())?;
+
+        let ownership = FileOwnership::of(file.as_file())?;
+        assert_eq!(ownership.uid(), sut.uid());
+        assert_eq!(ownership.gid(), sut.gid());
+        Ok(())
+    }
+
+    #[test]
+    fn apply_default() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        FileOwnership::default().apply(file.as_file())?;
+        Ok(())
+    }
+
+    #[test]
+    fn copy() -> Result<()> {
+        let src = NamedTempFile::new()?;
+        let dst = NamedTempFile::new()?;
+        FileOwnership::copy(src.as_file(), dst.as_file())?;
+        assert_eq!(
+            FileOwnership::of(src.as_file())?,
+            FileOwnership::of(dst.as_file())?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn apply_invalid_selinux_context() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let sut = FileOwnership::new(None, None, Some("invalid\0context".into()));
+        assert!(sut.apply(file.as_file()).is_err());
+        Ok(())
+    }
+}
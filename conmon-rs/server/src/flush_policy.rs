@@ -0,0 +1,142 @@
+//! Buffering of the container output before it gets forwarded to the loggers and attach clients.
+
+use bytes::{Bytes, BytesMut};
+use memchr::memrchr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+/// The available policies of forwarding the read container output.
+pub enum FlushPolicy {
+    #[default]
+    /// Forward every read chunk immediately, which results in the lowest latency.
+    Immediate,
+
+    /// Forward complete lines and hold back the trailing partial line until its newline arrives,
+    /// `size` bytes are pending or `max_delay` elapsed. A zero `max_delay` disables the time cap.
+    Line { size: usize, max_delay: Duration },
+
+    /// Forward the output once `size` bytes are pending or `max_delay` elapsed. A zero
+    /// `max_delay` disables the time cap.
+    Block { size: usize, max_delay: Duration },
+}
+
+impl FlushPolicy {
+    /// The default amount of pending bytes which get forwarded by the buffered policies.
+    pub const DEFAULT_SIZE: usize = 64 * 1024;
+}
+
+#[derive(Debug, Default)]
+/// The output of a single pipe held back by a flush policy.
+pub struct FlushBuffer {
+    policy: FlushPolicy,
+    pending: BytesMut,
+    deadline: Option<Instant>,
+}
+
+impl FlushBuffer {
+    /// Create a new flush buffer for the provided policy.
+    pub fn new(policy: FlushPolicy) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    /// Add the read data and return the output due to be forwarded, if any.
+    pub fn push(&mut self, data: Bytes) -> Option<Bytes> {
+        let size = match self.policy {
+            FlushPolicy::Immediate => return Some(data),
+            FlushPolicy::Line { size, .. } | FlushPolicy::Block { size, .. } => size,
+        };
+        if self.pending.is_empty() {
+            self.arm();
+        }
+        self.pending.extend_from_slice(&data);
+
+        if self.pending.len() >= size {
+            return self.take();
+        }
+        if let FlushPolicy::Line { .. } = self.policy {
+            let end = memrchr(b'\n', &self.pending)? + 1;
+            let lines = self.pending.split_to(end).freeze();
+            self.deadline = None;
+            if !self.pending.is_empty() {
+                self.arm();
+            }
+            return Some(lines);
+        }
+        None
+    }
+
+    /// Returns the time at which the pending output has to be forwarded.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Take all pending output, which is done once the deadline elapsed or the output ended.
+    pub fn take(&mut self) -> Option<Bytes> {
+        self.deadline = None;
+        if self.pending.is_empty() {
+            return None;
+        }
+        Some(self.pending.split().freeze())
+    }
+
+    /// Start the time cap of newly pending output.
+    fn arm(&mut self) {
+        if let FlushPolicy::Line { max_delay, .. } | FlushPolicy::Block { max_delay, .. } =
+            self.policy
+        {
+            if !max_delay.is_zero() {
+                self.deadline = Some(Instant::now() + max_delay);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(sut: &mut FlushBuffer, data: &'static str) -> Option<Bytes> {
+        sut.push(Bytes::from_static(data.as_bytes()))
+    }
+
+    #[test]
+    fn immediate() {
+        let mut sut = FlushBuffer::new(FlushPolicy::Immediate);
+        assert_eq!(push(&mut sut, "a").as_deref(), Some(&b"a"[..]));
+        assert!(sut.deadline().is_none());
+        assert!(sut.take().is_none());
+    }
+
+    #[test]
+    fn line() {
+        let mut sut = FlushBuffer::new(FlushPolicy::Line {
+            size: 8,
+            max_delay: Duration::from_secs(1),
+        });
+        assert!(push(&mut sut, "a").is_none());
+        assert!(sut.deadline().is_some());
+        assert_eq!(push(&mut sut, "b\nc\nd").as_deref(), Some(&b"ab\nc\n"[..]));
+        assert!(sut.deadline().is_some());
+        assert_eq!(push(&mut sut, "efghijk").as_deref(), Some(&b"defghijk"[..]));
+        assert!(sut.deadline().is_none());
+        assert!(push(&mut sut, "l").is_none());
+        assert_eq!(sut.take().as_deref(), Some(&b"l"[..]));
+        assert!(sut.deadline().is_none());
+    }
+
+    #[test]
+    fn block() {
+        let mut sut = FlushBuffer::new(FlushPolicy::Block {
+            size: 4,
+            max_delay: Duration::ZERO,
+        });
+        assert!(push(&mut sut, "a\n").is_none());
+        assert!(sut.deadline().is_none());
+        assert_eq!(push(&mut sut, "bc").as_deref(), Some(&b"a\nbc"[..]));
+        assert!(sut.take().is_none());
+    }
+}
@@ -0,0 +1,493 @@
+//! Graylog Extended Log Format (GELF) logging functionalities.
+
+use crate::{container_io::Pipe, log_transport::Transport};
+use anyhow::{bail, Context, Result};
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use getset::{CopyGetters, Getters};
+use nix::unistd::gethostname;
+use serde_json::{Map, Value};
+use std::{io::Write, marker::Unpin};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpStream, UdpSocket},
+};
+use tracing::{debug, trace};
+use tz::UtcDateTime;
+use uuid::Uuid;
+
+#[derive(Debug, CopyGetters, Getters)]
+/// The structure used for logging container output to a Graylog server.
+pub struct GelfLogger {
+    #[getset(get)]
+    /// The transport used to reach the Graylog server.
+    transport: Transport,
+
+    /// Connection to the Graylog server.
+    connection: Option<Connection>,
+
+    #[getset(get_copy)]
+    /// The compression of UDP messages.
+    compression: GelfCompression,
+
+    #[getset(get)]
+    /// The fields added to every message, including the container specific ones.
+    fields: Map<String, Value>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Available compressions of GELF messages, which are only supported via UDP.
+pub enum GelfCompression {
+    None,
+    Gzip,
+    Zlib,
+}
+
+#[derive(Debug)]
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+impl GelfLogger {
+    const ERR_UNINITIALIZED: &'static str = "logger not initialized";
+
+    /// The port used if the address does not contain one.
+    const DEFAULT_PORT: u16 = 12201;
+
+    /// The maximum size of a single UDP datagram, larger messages get chunked.
+    const CHUNK_SIZE: usize = 1420;
+
+    /// The magic bytes prefixing every chunk.
+    const CHUNK_MAGIC: [u8; 2] = [0x1e, 0x0f];
+
+    /// The size of the chunk header, consisting of the magic bytes, the message ID, the sequence
+    /// number and the sequence count.
+    const CHUNK_HEADER_LEN: usize = 12;
+
+    /// The maximum amount of chunks per message as defined by the GELF specification.
+    const MAX_CHUNKS: usize = 128;
+
+    /// The length of the shortened container identifier.
+    const SHORT_ID_LEN: usize = 12;
+
+    /// Level of stdout messages (informational).
+    const LEVEL_STDOUT: u8 = 6;
+
+    /// Level of stderr messages (error).
+    const LEVEL_STDERR: u8 = 3;
+
+    /// Create a new GELF logger instance. The hostname defaults to the one of the system, while
+    /// the additional fields get prefixed by an underscore if required.
+    pub fn new(
+        container_id: &str,
+        container_name: &str,
+        address: &str,
+        hostname: &str,
+        tag: &str,
+        compression: GelfCompression,
+        additional_fields: &[(String, String)],
+    ) -> Result<GelfLogger> {
+        let transport = Self::parse_address(address)?;
+        if matches!(transport, Transport::Tcp(_)) && compression != GelfCompression::None {
+            bail!("GELF compression is not supported via TCP")
+        }
+
+        let hostname = if hostname.is_empty() {
+            gethostname()
+                .context("get hostname")?
+                .to_string_lossy()
+                .into_owned()
+        } else {
+            hostname.into()
+        };
+
+        let mut fields = Map::new();
+        fields.insert("version".into(), "1.1".into());
+        fields.insert("host".into(), hostname.into());
+        for (key, value) in additional_fields {
+            let key = if key.starts_with('_') {
+                key.clone()
+            } else {
+                format!("_{}", key)
+            };
+            Self::validate_field(&key)?;
+            fields.insert(key, value.as_str().into());
+        }
+        fields.insert(
+            "_container_id".into(),
+            container_id
+                .get(..Self::SHORT_ID_LEN)
+                .unwrap_or(container_id)
+                .into(),
+        );
+        fields.insert("_container_id_full".into(), container_id.into());
+        if !container_name.is_empty() {
+            fields.insert("_container_name".into(), container_name.into());
+        }
+        if !tag.is_empty() {
+            fields.insert("_tag".into(), tag.into());
+        }
+
+        Ok(Self {
+            transport,
+            connection: None,
+            compression,
+            fields,
+        })
+    }
+
+    /// Asynchronously initialize the GELF logger.
+    pub async fn init(&mut self) -> Result<()> {
+        debug!("Initializing GELF logger for {:?}", self.transport());
+        self.connection = Some(match self.transport() {
+            Transport::Udp(address) => {
+                let socket = UdpSocket::bind(if address.starts_with('[') {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                })
+                .await
+                .context("bind UDP socket")?;
+                socket
+                    .connect(address)
+                    .await
+                    .context(format!("connect to GELF address {}", address))?;
+                Connection::Udp(socket)
+            }
+            Transport::Tcp(address) => Connection::Tcp(
+                TcpStream::connect(address)
+                    .await
+                    .context(format!("connect to GELF address {}", address))?,
+            ),
+            Transport::UnixDatagram(path) => {
+                bail!("unsupported GELF socket: {}", path.display())
+            }
+        });
+        Ok(())
+    }
+
+    /// Write the contents of the provided reader to the Graylog server, one message per line.
+    pub async fn write<T>(&mut self, pipe: Pipe, bytes: T) -> Result<()>
+    where
+        T: AsyncBufRead + Unpin,
+    {
+        let mut reader = BufReader::new(bytes);
+        loop {
+            let mut line = vec![];
+            let read = reader
+                .read_until(b'\n', &mut line)
+                .await
+                .context("read log line")?;
+            if read == 0 {
+                break;
+            }
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+
+            let message = self.message(pipe, &line)?;
+            let len = message.len();
+            match self.connection.as_mut().context(Self::ERR_UNINITIALIZED)? {
+                Connection::Udp(socket) => {
+                    let message = Self::compress(self.compression, message)?;
+                    for datagram in Self::chunks(&message, Uuid::new_v4().as_u64_pair().0)? {
+                        socket.send(&datagram).await?;
+                    }
+                }
+                Connection::Tcp(stream) => {
+                    stream.write_all(&message).await?;
+                    stream.write_all(b"\0").await?;
+                }
+            }
+            trace!("Wrote GELF message of length {}", len);
+        }
+        Ok(())
+    }
+
+    /// Reconnect to the Graylog server.
+    pub async fn reopen(&mut self) -> Result<()> {
+        debug!("Reopen GELF logger");
+        self.init().await
+    }
+
+    /// Serialize a log line into a GELF JSON message.
+    fn message(&self, pipe: Pipe, line: &[u8]) -> Result<Vec<u8>> {
+        let now = UtcDateTime::now().context("get UTC datetime")?;
+        let mut message = self.fields().clone();
+        message.insert(
+            "short_message".into(),
+            String::from_utf8_lossy(line).into_owned().into(),
+        );
+        message.insert(
+            "timestamp".into(),
+            (now.unix_time() as f64 + f64::from(now.nanoseconds()) / 1e9).into(),
+        );
+        let (level, stream) = match pipe {
+            Pipe::StdOut => (Self::LEVEL_STDOUT, "stdout"),
+            Pipe::StdErr => (Self::LEVEL_STDERR, "stderr"),
+        };
+        message.insert("level".into(), level.into());
+        message.insert("_stream".into(), stream.into());
+        serde_json::to_vec(&message).context("serialize GELF message")
+    }
+
+    /// Compress the message for UDP transport.
+    fn compress(compression: GelfCompression, message: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(match compression {
+            GelfCompression::None => message,
+            GelfCompression::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], Compression::default());
+                encoder.write_all(&message)?;
+                encoder.finish().context("gzip GELF message")?
+            }
+            GelfCompression::Zlib => {
+                let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+                encoder.write_all(&message)?;
+                encoder.finish().context("zlib compress GELF message")?
+            }
+        })
+    }
+
+    /// Split the message into UDP datagrams, where messages exceeding the chunk size get
+    /// prefixed by chunk headers with the provided message ID.
+    fn chunks(message: &[u8], id: u64) -> Result<Vec<Vec<u8>>> {
+        if message.len() <= Self::CHUNK_SIZE {
+            return Ok(vec![message.to_vec()]);
+        }
+
+        let payload_len = Self::CHUNK_SIZE - Self::CHUNK_HEADER_LEN;
+        let count = message.chunks(payload_len).len();
+        if count > Self::MAX_CHUNKS {
+            bail!(
+                "GELF message of length {} exceeds {} chunks",
+                message.len(),
+                Self::MAX_CHUNKS
+            )
+        }
+
+        Ok(message
+            .chunks(payload_len)
+            .enumerate()
+            .map(|(sequence, payload)| {
+                let mut chunk = Vec::with_capacity(Self::CHUNK_HEADER_LEN + payload.len());
+                chunk.extend_from_slice(&Self::CHUNK_MAGIC);
+                chunk.extend_from_slice(&id.to_be_bytes());
+                chunk.push(sequence as u8);
+                chunk.push(count as u8);
+                chunk.extend_from_slice(payload);
+                chunk
+            })
+            .collect())
+    }
+
+    /// Parse the address into a transport. Supported are `udp://` addresses, where large
+    /// messages get chunked, and `tcp://` addresses, which use null byte delimited frames.
+    /// Addresses without scheme default to UDP.
+    fn parse_address(address: &str) -> Result<Transport> {
+        if address.is_empty() {
+            bail!("no GELF address provided")
+        }
+        let address = if address.contains("://") {
+            address.into()
+        } else {
+            format!("udp://{}", address)
+        };
+        Transport::parse(&address, &["udp", "tcp"], Self::DEFAULT_PORT)
+            .context("invalid GELF address")
+    }
+
+    /// Validate an additional field name as required by the GELF specification.
+    fn validate_field(key: &str) -> Result<()> {
+        if key == "_id"
+            || key.len() < 2
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        {
+            bail!("invalid GELF additional field name: {}", key)
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use std::io::Read;
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    const ID: &str = "0123456789abcdef";
+
+    fn fields() -> Vec<(String, String)> {
+        vec![("env".into(), "prod".into()), ("_team".into(), "a".into())]
+    }
+
+    fn assert_message(message: &[u8], level: u8, stream: &str, msg: &str) -> Result<()> {
+        let message: Map<String, Value> = serde_json::from_slice(message)?;
+        assert_eq!(message["version"], "1.1");
+        assert_eq!(message["host"], "host");
+        assert_eq!(message["short_message"], msg);
+        assert!(message["timestamp"].as_f64().context("no timestamp")? > 0.);
+        assert_eq!(message["level"], level);
+        assert_eq!(message["_stream"], stream);
+        assert_eq!(message["_container_id"], "0123456789ab");
+        assert_eq!(message["_container_id_full"], ID);
+        assert_eq!(message["_container_name"], "name");
+        assert_eq!(message["_tag"], "tag");
+        assert_eq!(message["_env"], "prod");
+        assert_eq!(message["_team"], "a");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_udp() -> Result<()> {
+        for compression in &[
+            GelfCompression::None,
+            GelfCompression::Gzip,
+            GelfCompression::Zlib,
+        ] {
+            let server = UdpSocket::bind("127.0.0.1:0").await?;
+            let address = format!("udp://{}", server.local_addr()?);
+            let mut sut =
+                GelfLogger::new(ID, "name", &address, "host", "tag", *compression, &fields())?;
+            sut.init().await?;
+
+            sut.write(Pipe::StdErr, "hello world\n".as_bytes()).await?;
+
+            let mut buf = vec![0; 2048];
+            let n = server.recv(&mut buf).await?;
+            let mut message = vec![];
+            match compression {
+                GelfCompression::None => message.extend_from_slice(&buf[..n]),
+                GelfCompression::Gzip => {
+                    GzDecoder::new(&buf[..n]).read_to_end(&mut message)?;
+                }
+                GelfCompression::Zlib => {
+                    ZlibDecoder::new(&buf[..n]).read_to_end(&mut message)?;
+                }
+            }
+            assert_message(&message, 3, "stderr", "hello world")?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_udp_chunked() -> Result<()> {
+        let server = UdpSocket::bind("127.0.0.1:0").await?;
+        let address = server.local_addr()?.to_string();
+        let mut sut = GelfLogger::new(
+            ID,
+            "name",
+            &address,
+            "host",
+            "tag",
+            GelfCompression::None,
+            &fields(),
+        )?;
+        sut.init().await?;
+
+        let line = "a".repeat(3000);
+        sut.write(Pipe::StdOut, format!("{}\n", line).as_bytes())
+            .await?;
+
+        let mut message = vec![];
+        let mut buf = vec![0; 2048];
+        for i in 0..3 {
+            let n = server.recv(&mut buf).await?;
+            assert_eq!(&buf[..2], &GelfLogger::CHUNK_MAGIC);
+            assert_eq!(buf[10], i);
+            assert_eq!(buf[11], 3);
+            message.extend_from_slice(&buf[GelfLogger::CHUNK_HEADER_LEN..n]);
+        }
+        assert_message(&message, 6, "stdout", &line)
+    }
+
+    #[tokio::test]
+    async fn write_tcp() -> Result<()> {
+        let server = TcpListener::bind("127.0.0.1:0").await?;
+        let address = format!("tcp://{}", server.local_addr()?);
+        let mut sut = GelfLogger::new(
+            ID,
+            "name",
+            &address,
+            "host",
+            "tag",
+            GelfCompression::None,
+            &fields(),
+        )?;
+        sut.init().await?;
+        let (mut stream, _) = server.accept().await?;
+
+        sut.write(Pipe::StdOut, "a\nb\n".as_bytes()).await?;
+        drop(sut);
+
+        let mut res = vec![];
+        stream.read_to_end(&mut res).await?;
+        let frames = res.split(|x| *x == 0).collect::<Vec<_>>();
+        assert_eq!(frames.len(), 3);
+        assert_message(frames[0], 6, "stdout", "a")?;
+        assert_message(frames[1], 6, "stdout", "b")?;
+        assert!(frames[2].is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn chunks() -> Result<()> {
+        let message = vec![1; GelfLogger::CHUNK_SIZE];
+        assert_eq!(GelfLogger::chunks(&message, 0)?, vec![message]);
+
+        let message = vec![1; GelfLogger::CHUNK_SIZE + 1];
+        let chunks = GelfLogger::chunks(&message, 0x0102030405060708)?;
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(
+            &chunks[0][..12],
+            &[0x1e, 0x0f, 1, 2, 3, 4, 5, 6, 7, 8, 0, 2]
+        );
+        assert_eq!(&chunks[1][10..12], &[1, 2]);
+        assert_eq!(chunks[0].len(), GelfLogger::CHUNK_SIZE);
+
+        let message = vec![1; 128 * GelfLogger::CHUNK_SIZE];
+        assert!(GelfLogger::chunks(&message, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_address() -> Result<()> {
+        assert_eq!(
+            GelfLogger::parse_address("localhost")?,
+            Transport::Udp("localhost:12201".into())
+        );
+        assert_eq!(
+            GelfLogger::parse_address("tcp://[::1]")?,
+            Transport::Tcp("[::1]:12201".into())
+        );
+        assert_eq!(
+            GelfLogger::parse_address("udp://host:1234")?,
+            Transport::Udp("host:1234".into())
+        );
+        assert!(GelfLogger::parse_address("").is_err());
+        assert!(GelfLogger::parse_address("http://localhost").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(GelfLogger::new(ID, "", "tcp://host", "", "", GelfCompression::Gzip, &[]).is_err());
+        for key in &["id", "_", "a b", "ä"] {
+            assert!(GelfLogger::new(
+                ID,
+                "",
+                "host",
+                "",
+                "",
+                GelfCompression::None,
+                &[(key.to_string(), "".into())]
+            )
+            .is_err());
+        }
+    }
+}
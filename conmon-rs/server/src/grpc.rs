@@ -0,0 +1,475 @@
+//! The gRPC API of the server, which is enabled by the `grpc` feature.
+//!
+//! The gRPC service forwards every call through the `CapnpBridge` to the Cap'n Proto service.
+
+use crate::{
+    auth::Access, capacity::CapacityExceeded, capnp_bridge::CapnpBridge,
+    container_labels::ContainerLabels, rpc::DEADLINE_EXCEEDED,
+};
+use anyhow::{Context, Result};
+use capnp::{text_list, ErrorKind};
+use conmon_common::conmon_capnp::conmon;
+use futures::{future, StreamExt};
+use std::{convert::TryFrom, future::Future};
+use tokio::net::UnixListener;
+use tokio_stream::wrappers::UnixListenerStream;
+use tonic::{transport, Request, Response, Status};
+use tracing::{debug, error, warn};
+
+#[allow(clippy::all)]
+mod proto {
+    tonic::include_proto!("conmon");
+}
+
+use proto::{
+    conmon_server::{Conmon, ConmonServer},
+    log_driver, AttachRequest, AttachResponse, ContainerStatsRequest, ContainerStatsResponse,
+    CreateContainerRequest, CreateContainerResponse, ExecSyncContainerRequest,
+    ExecSyncContainerResponse, ExtraFdStats, IoStatsRequest, IoStatsResponse, IoStreamStats,
+    ReopenLogRequest, ReopenLogResponse, SetOutputPausedRequest, SetOutputPausedResponse,
+    SetWindowSizeRequest, SetWindowSizeResponse, VersionRequest, VersionResponse,
+};
+
+#[derive(Clone, Debug)]
+/// The gRPC service, which forwards all calls to the Cap'n Proto service.
+pub struct GrpcService {
+    bridge: CapnpBridge,
+
+    /// The token and peer checks, where the token is sent by the `authorization` metadata.
+    access: Access,
+}
+
+impl GrpcService {
+    /// Create a new service forwarding its calls through the bridge.
+    pub fn new(bridge: CapnpBridge, access: Access) -> Self {
+        Self { bridge, access }
+    }
+
+    /// Serve the gRPC API on the unix domain socket listener, which drops connections of peers
+    /// which are not allowed and rejects calls without a valid token.
+    pub async fn serve(self, listener: UnixListener) -> Result<()> {
+        debug!("Serving gRPC API");
+        let access = self.access.clone();
+        let incoming = UnixListenerStream::new(listener).filter_map(move |stream| {
+            future::ready(
+                match stream.and_then(|x| x.peer_cred().map(|cred| (x, cred))) {
+                    Ok((stream, cred)) if access.peer_allowed(cred.uid(), cred.gid()) => {
+                        Some(Ok(stream))
+                    }
+                    Ok((_, cred)) => {
+                        warn!(
+                            "Rejecting gRPC connection of peer with UID {} and GID {}",
+                            cred.uid(),
+                            cred.gid()
+                        );
+                        None
+                    }
+                    Err(e) => {
+                        error!("Unable to accept gRPC connection: {:#}", e);
+                        None
+                    }
+                },
+            )
+        });
+
+        let access = self.access.clone();
+        let authorize = move |request: Request<()>| {
+            let authorization = request
+                .metadata()
+                .get("authorization")
+                .and_then(|x| x.to_str().ok());
+            match access.authorize(authorization) {
+                Ok(Some(name)) => debug!("Authenticated gRPC caller {}", name),
+                Ok(None) => {}
+                Err(e) => return Err(Status::unauthenticated(e.to_string())),
+            }
+            Ok(request)
+        };
+        transport::Server::builder()
+            .add_service(ConmonServer::with_interceptor(self, authorize))
+            .serve_with_incoming(incoming)
+            .await
+            .context("serve gRPC API")
+    }
+
+    /// Forward the call to the Cap'n Proto service and wait for its response.
+    async fn call<F, R, T>(&self, f: F) -> Result<Response<T>, Status>
+    where
+        F: FnOnce(conmon::Client) -> R + Send + 'static,
+        R: Future<Output = Result<T, capnp::Error>> + 'static,
+        T: Send + 'static,
+    {
+        self.bridge.call(f).await.map(Response::new).map_err(status)
+    }
+}
+
+/// Convert a Cap'n Proto error into the gRPC status of the same meaning.
+fn status(e: capnp::Error) -> Status {
+    match e.kind {
+        _ if CapacityExceeded::matches(&e.description) => Status::resource_exhausted(e.description),
+        ErrorKind::Overloaded if e.description.ends_with(DEADLINE_EXCEEDED) => {
+            Status::deadline_exceeded(e.description)
+        }
+        ErrorKind::Overloaded => Status::resource_exhausted(e.description),
+        ErrorKind::Disconnected => Status::unavailable(e.description),
+        _ => Status::internal(e.description),
+    }
+}
+
+/// Convert the type of a gRPC log driver into its Cap'n Proto counterpart.
+fn log_driver_type(typ: i32) -> Result<conmon::log_driver::Type, Status> {
+    use conmon::log_driver::Type;
+    match log_driver::Type::from_i32(typ) {
+        Some(log_driver::Type::ContainerRuntimeInterface) => Ok(Type::ContainerRuntimeInterface),
+        Some(log_driver::Type::Journald) => Ok(Type::Journald),
+        Some(log_driver::Type::JsonFile) => Ok(Type::JsonFile),
+        Some(log_driver::Type::None) => Ok(Type::None),
+        None => Err(Status::invalid_argument(format!(
+            "unknown log driver type {}",
+            typ
+        ))),
+    }
+}
+
+/// Convert a terminal dimension, which is limited to 16 bits.
+fn window_size(value: u32) -> Result<u16, Status> {
+    u16::try_from(value)
+        .map_err(|_| Status::invalid_argument(format!("window size {} out of range", value)))
+}
+
+fn set_texts(mut builder: text_list::Builder<'_>, values: &[String]) {
+    for (i, value) in values.iter().enumerate() {
+        builder.set(i as u32, value);
+    }
+}
+
+fn stream_stats(reader: conmon::io_stream_stats::Reader<'_>) -> IoStreamStats {
+    IoStreamStats {
+        bytes: reader.get_bytes(),
+        lines: reader.get_lines(),
+        partial_lines: reader.get_partial_lines(),
+        dropped_bytes: reader.get_dropped_bytes(),
+    }
+}
+
+#[tonic::async_trait]
+impl Conmon for GrpcService {
+    async fn version(
+        &self,
+        _: Request<VersionRequest>,
+    ) -> Result<Response<VersionResponse>, Status> {
+        self.call(|client| async move {
+            let response = client.version_request().send().promise.await?;
+            let response = response.get()?.get_response()?;
+            Ok(VersionResponse {
+                version: response.get_version()?.into(),
+                tag: response.get_tag()?.into(),
+                commit: response.get_commit()?.into(),
+                build_date: response.get_build_date()?.into(),
+                rust_version: response.get_rust_version()?.into(),
+                process_id: response.get_process_id(),
+                build_target: response.get_build_target()?.into(),
+                build_profile: response.get_build_profile()?.into(),
+                features: response
+                    .get_features()?
+                    .iter()
+                    .map(|x| x.map(String::from))
+                    .collect::<Result<_, _>>()?,
+                protocol_version: response.get_protocol_version(),
+                min_protocol_version: response.get_min_protocol_version(),
+            })
+        })
+        .await
+    }
+
+    async fn create_container(
+        &self,
+        request: Request<CreateContainerRequest>,
+    ) -> Result<Response<CreateContainerResponse>, Status> {
+        let request = request.into_inner();
+        let log_driver_types = request
+            .log_drivers
+            .iter()
+            .map(|x| log_driver_type(x.r#type))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.call(move |client| async move {
+            let mut call = client.create_container_request();
+            let mut req = call.get().init_request();
+            req.set_id(&request.id);
+            req.set_bundle_path(&request.bundle_path);
+            req.set_terminal(request.terminal);
+            req.set_stdin(request.stdin);
+            req.set_restart_policy(&request.restart_policy);
+            req.set_stop_signal(request.stop_signal);
+            set_texts(
+                req.reborrow()
+                    .init_exit_paths(request.exit_paths.len() as u32),
+                &request.exit_paths,
+            );
+            set_texts(
+                req.reborrow()
+                    .init_oom_exit_paths(request.oom_exit_paths.len() as u32),
+                &request.oom_exit_paths,
+            );
+            set_texts(
+                req.reborrow()
+                    .init_cleanup_cmd(request.cleanup_cmd.len() as u32),
+                &request.cleanup_cmd,
+            );
+            set_texts(
+                req.reborrow()
+                    .init_global_args(request.global_args.len() as u32),
+                &request.global_args,
+            );
+            set_texts(
+                req.reborrow()
+                    .init_command_args(request.command_args.len() as u32),
+                &request.command_args,
+            );
+
+            let mut log_drivers = req
+                .reborrow()
+                .init_log_drivers(request.log_drivers.len() as u32);
+            for (i, (driver, typ)) in request.log_drivers.iter().zip(log_driver_types).enumerate() {
+                let mut log_driver = log_drivers.reborrow().get(i as u32);
+                log_driver.set_type(typ);
+                log_driver.set_path(&driver.path);
+                log_driver.set_max_size(driver.max_size);
+                log_driver.set_max_files(driver.max_files);
+            }
+
+            ContainerLabels::set_entries(
+                req.reborrow().init_metadata(request.metadata.len() as u32),
+                &request.metadata,
+            );
+            ContainerLabels::set_entries(
+                req.reborrow().init_labels(request.labels.len() as u32),
+                &request.labels,
+            );
+            ContainerLabels::set_entries(
+                req.init_annotations(request.annotations.len() as u32),
+                &request.annotations,
+            );
+
+            let response = call.send().promise.await?;
+            Ok(CreateContainerResponse {
+                container_pid: response.get()?.get_response()?.get_container_pid(),
+            })
+        })
+        .await
+    }
+
+    async fn exec_sync_container(
+        &self,
+        request: Request<ExecSyncContainerRequest>,
+    ) -> Result<Response<ExecSyncContainerResponse>, Status> {
+        let request = request.into_inner();
+        self.call(move |client| async move {
+            let mut call = client.exec_sync_container_request();
+            let mut req = call.get().init_request();
+            req.set_id(&request.id);
+            req.set_timeout_sec(request.timeout_sec);
+            req.set_terminal(request.terminal);
+            req.set_exec_session_id(&request.exec_session_id);
+            set_texts(
+                req.init_command(request.command.len() as u32),
+                &request.command,
+            );
+
+            let response = call.send().promise.await?;
+            let response = response.get()?.get_response()?;
+            Ok(ExecSyncContainerResponse {
+                exit_code: response.get_exit_code(),
+                stdout: response.get_stdout()?.to_vec(),
+                stderr: response.get_stderr()?.to_vec(),
+                timed_out: response.get_timed_out(),
+                exec_session_id: response.get_exec_session_id()?.into(),
+            })
+        })
+        .await
+    }
+
+    async fn set_window_size_container(
+        &self,
+        request: Request<SetWindowSizeRequest>,
+    ) -> Result<Response<SetWindowSizeResponse>, Status> {
+        let request = request.into_inner();
+        let (width, height) = (window_size(request.width)?, window_size(request.height)?);
+        self.call(move |client| async move {
+            let mut call = client.set_window_size_container_request();
+            let mut req = call.get().init_request();
+            req.set_id(&request.id);
+            req.set_width(width);
+            req.set_height(height);
+            call.send().promise.await?;
+            Ok(SetWindowSizeResponse {})
+        })
+        .await
+    }
+
+    async fn set_output_paused_container(
+        &self,
+        request: Request<SetOutputPausedRequest>,
+    ) -> Result<Response<SetOutputPausedResponse>, Status> {
+        let request = request.into_inner();
+        self.call(move |client| async move {
+            let mut call = client.set_output_paused_container_request();
+            let mut req = call.get().init_request();
+            req.set_id(&request.id);
+            req.set_paused(request.paused);
+
+            let response = call.send().promise.await?;
+            Ok(SetOutputPausedResponse {
+                was_paused: response.get()?.get_response()?.get_was_paused(),
+            })
+        })
+        .await
+    }
+
+    async fn attach_container(
+        &self,
+        request: Request<AttachRequest>,
+    ) -> Result<Response<AttachResponse>, Status> {
+        let request = request.into_inner();
+        self.call(move |client| async move {
+            let mut call = client.attach_container_request();
+            let mut req = call.get().init_request();
+            req.set_id(&request.id);
+            req.set_socket_path(&request.socket_path);
+            req.set_exec_session_id(&request.exec_session_id);
+            req.set_read_only(request.read_only);
+            req.set_idle_timeout_sec(request.idle_timeout_sec);
+            req.set_max_clients(request.max_clients);
+            req.set_socket_mode(request.socket_mode);
+            req.set_stream_socket(request.stream_socket);
+
+            call.send().promise.await?;
+            Ok(AttachResponse {})
+        })
+        .await
+    }
+
+    async fn reopen_log_container(
+        &self,
+        request: Request<ReopenLogRequest>,
+    ) -> Result<Response<ReopenLogResponse>, Status> {
+        let request = request.into_inner();
+        self.call(move |client| async move {
+            let mut call = client.reopen_log_container_request();
+            call.get().init_request().set_id(&request.id);
+
+            call.send().promise.await?;
+            Ok(ReopenLogResponse {})
+        })
+        .await
+    }
+
+    async fn io_stats_container(
+        &self,
+        request: Request<IoStatsRequest>,
+    ) -> Result<Response<IoStatsResponse>, Status> {
+        let request = request.into_inner();
+        self.call(move |client| async move {
+            let mut call = client.io_stats_container_request();
+            call.get().init_request().set_id(&request.id);
+
+            let response = call.send().promise.await?;
+            let response = response.get()?.get_response()?;
+            Ok(IoStatsResponse {
+                stdin_bytes: response.get_stdin_bytes(),
+                stdout: Some(stream_stats(response.get_stdout()?)),
+                stderr: Some(stream_stats(response.get_stderr()?)),
+                extra_fds: response
+                    .get_extra_fds()?
+                    .iter()
+                    .map(|x| ExtraFdStats {
+                        fd: x.get_fd(),
+                        bytes: x.get_bytes(),
+                    })
+                    .collect(),
+            })
+        })
+        .await
+    }
+
+    async fn container_stats(
+        &self,
+        request: Request<ContainerStatsRequest>,
+    ) -> Result<Response<ContainerStatsResponse>, Status> {
+        let request = request.into_inner();
+        self.call(move |client| async move {
+            let mut call = client.container_stats_request();
+            call.get().init_request().set_id(&request.id);
+
+            let response = call.send().promise.await?;
+            let response = response.get()?.get_response()?;
+            Ok(ContainerStatsResponse {
+                cpu_usage_usec: response.get_cpu_usage_usec(),
+                cpu_user_usec: response.get_cpu_user_usec(),
+                cpu_system_usec: response.get_cpu_system_usec(),
+                memory_usage_bytes: response.get_memory_usage_bytes(),
+                memory_limit_bytes: response.get_memory_limit_bytes(),
+                pids_current: response.get_pids_current(),
+                pids_limit: response.get_pids_limit(),
+                io_read_bytes: response.get_io_read_bytes(),
+                io_write_bytes: response.get_io_write_bytes(),
+            })
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_log_driver_type() -> Result<(), Status> {
+        assert_eq!(
+            log_driver_type(log_driver::Type::JsonFile as i32)?,
+            conmon::log_driver::Type::JsonFile
+        );
+        assert_eq!(
+            log_driver_type(42).map_err(|e| e.code()),
+            Err(tonic::Code::InvalidArgument)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn convert_window_size() -> Result<(), Status> {
+        assert_eq!(window_size(80)?, 80);
+        assert!(window_size(1 << 16).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn convert_status() {
+        for (error, code) in [
+            (
+                capnp::Error::overloaded("capacity exceeded: limit".into()),
+                tonic::Code::ResourceExhausted,
+            ),
+            (
+                capnp::Error::overloaded(DEADLINE_EXCEEDED.into()),
+                tonic::Code::DeadlineExceeded,
+            ),
+            (
+                capnp::Error::overloaded("remote exception: deadline exceeded".into()),
+                tonic::Code::DeadlineExceeded,
+            ),
+            (
+                capnp::Error::overloaded("too many requests".into()),
+                tonic::Code::ResourceExhausted,
+            ),
+            (
+                capnp::Error::disconnected("gone".into()),
+                tonic::Code::Unavailable,
+            ),
+            (capnp::Error::failed("failed".into()), tonic::Code::Internal),
+        ] {
+            assert_eq!(status(error).code(), code);
+        }
+    }
+}
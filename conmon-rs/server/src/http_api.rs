@@ -0,0 +1,644 @@
+//! The optional JSON over HTTP API of the server, which allows scripts and debugging tools to
+//! call the common methods with plain HTTP clients like curl.
+//!
+//! Like the gRPC and ttrpc front ends, every request gets forwarded through the `CapnpBridge` to
+//! the Cap'n Proto service. The routes map to its methods as follows:
+//!
+//! - `GET /v1/version`: `version`
+//! - `GET /v1/status`: `status`
+//! - `GET /v1/containers`: `listContainers`
+//! - `GET /v1/containers/{id}`: `containerStatus`
+//! - `GET /v1/containers/{id}/stats`: `containerStats`
+//! - `POST /v1/containers/{id}/exec`: `execSyncContainer` of the JSON body
+//!   `{"command": ["ls"], "timeoutSec": 10}`
+//! - `POST /v1/containers/{id}/reopen-log`: `reopenLogContainer`
+//! - `DELETE /v1/containers/{id}`: `removeContainer`, which kills running processes if the query
+//!   is `?force=true`
+
+use crate::{
+    auth::Access,
+    capacity::CapacityExceeded,
+    capnp_bridge::CapnpBridge,
+    listener::{DefaultListener, Listener},
+    metrics,
+};
+use anyhow::{bail, Context, Result};
+use capnp::ErrorKind;
+use conmon_common::conmon_capnp::conmon::{self, log_driver, ContainerState};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::{future::Future, net::SocketAddr, time::Duration};
+use tokio::{
+    io::{
+        AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+        BufReader,
+    },
+    net::TcpListener,
+    task, time,
+};
+use tracing::{debug, debug_span, error, warn, Instrument};
+
+/// The maximum size of the request line and headers.
+const MAX_HEAD_SIZE: u64 = 8192;
+
+/// The maximum size of a request body.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// The time a client has to send the request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug)]
+/// The HTTP front end, which forwards all requests to the Cap'n Proto service.
+pub struct HttpApi {
+    bridge: CapnpBridge,
+
+    /// The token and peer checks, where the token is sent by the `Authorization` header.
+    access: Access,
+}
+
+#[derive(Debug, Default, PartialEq)]
+/// A parsed HTTP request.
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// The JSON body of an exec request.
+struct ExecBody {
+    command: Vec<String>,
+
+    #[serde(default)]
+    timeout_sec: u64,
+}
+
+/// A failed request along with its HTTP status.
+type Failure = (&'static str, String);
+
+impl HttpApi {
+    /// Create a new API forwarding its requests through the bridge.
+    pub fn new(bridge: CapnpBridge, access: Access) -> Self {
+        Self { bridge, access }
+    }
+
+    /// Validate the address to serve on, which is either the path of a unix domain socket or a
+    /// loopback TCP socket address. TCP peers cannot be verified, which is why they require
+    /// token authentication.
+    pub fn validate_address(address: &str, requires_token: bool) -> Result<()> {
+        if metrics::is_socket_path(address) {
+            return Ok(());
+        }
+        let address: SocketAddr = address
+            .parse()
+            .context(format!("parse HTTP API address '{}'", address))?;
+        if !address.ip().is_loopback() {
+            bail!("HTTP API address {} is not a loopback address", address)
+        }
+        if !requires_token {
+            bail!("HTTP API address {} requires an auth token file", address)
+        }
+        Ok(())
+    }
+
+    /// Serve the API on the address, which is either the path of a unix domain socket or a
+    /// loopback TCP socket address.
+    pub async fn serve(self, address: &str) -> Result<()> {
+        Self::validate_address(address, self.access.requires_token())?;
+        if metrics::is_socket_path(address) {
+            let listener = Listener::<DefaultListener>::default()
+                .bind_long_path(address)
+                .context("bind HTTP API socket")?;
+            debug!("Serving HTTP API on {}", address);
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        error!("Unable to accept HTTP API connection: {:#}", e);
+                        continue;
+                    }
+                };
+                let cred = match stream.peer_cred() {
+                    Ok(cred) => cred,
+                    Err(e) => {
+                        error!("Unable to get HTTP API peer credentials: {:#}", e);
+                        continue;
+                    }
+                };
+                if !self.access.peer_allowed(cred.uid(), cred.gid()) {
+                    warn!(
+                        "Rejecting HTTP API connection of peer with UID {} and GID {}",
+                        cred.uid(),
+                        cred.gid()
+                    );
+                    continue;
+                }
+                self.spawn_handler(stream);
+            }
+        }
+
+        let listener = TcpListener::bind(address)
+            .await
+            .context("bind HTTP API address")?;
+        debug!("Serving HTTP API on {}", address);
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    error!("Unable to accept HTTP API connection: {:#}", e);
+                    continue;
+                }
+            };
+            self.spawn_handler(stream);
+        }
+    }
+
+    fn spawn_handler<T>(&self, stream: T)
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let api = self.clone();
+        task::spawn(
+            async move {
+                if let Err(e) = api.handle(stream).await {
+                    error!("Unable to serve HTTP API request: {:#}", e);
+                }
+            }
+            .instrument(debug_span!("http_api")),
+        );
+    }
+
+    async fn handle<T>(&self, stream: T) -> Result<()>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut reader = BufReader::new(stream);
+        let (status, body) = match time::timeout(REQUEST_TIMEOUT, read_request(&mut reader)).await {
+            Err(_) => ("408 Request Timeout", error_body("request timed out")),
+            Ok(Err(e)) => ("400 Bad Request", error_body(format!("{:#}", e))),
+            Ok(Ok(request)) => match self.respond(request).await {
+                Ok(body) => ("200 OK", body),
+                Err((status, message)) => (status, error_body(message)),
+            },
+        };
+
+        let body = body.to_string();
+        let stream = reader.get_mut();
+        stream
+            .write_all(
+                format!(
+                    "HTTP/1.1 {}\r\n\
+                    Content-Type: application/json\r\n\
+                    Content-Length: {}\r\n\
+                    Connection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            )
+            .await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    /// Authenticate and route the request to the method of the Cap'n Proto service.
+    async fn respond(&self, request: Request) -> Result<Value, Failure> {
+        match self.access.authorize(request.authorization.as_deref()) {
+            Ok(Some(name)) => debug!("Authenticated HTTP API caller {}", name),
+            Ok(None) => {}
+            Err(e) => return Err(("401 Unauthorized", e.to_string())),
+        }
+
+        let segments = request
+            .path
+            .strip_prefix("/v1/")
+            .map(|x| x.split('/').collect::<Vec<_>>())
+            .unwrap_or_default();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["version"]) => self.version().await,
+            ("GET", ["status"]) => self.status().await,
+            ("GET", ["containers"]) => self.list_containers().await,
+            ("GET", ["containers", id]) => self.container_status(id).await,
+            ("GET", ["containers", id, "stats"]) => self.container_stats(id).await,
+            ("POST", ["containers", id, "exec"]) => {
+                let body: ExecBody = serde_json::from_slice(&request.body)
+                    .map_err(|e| ("400 Bad Request", format!("invalid exec request: {}", e)))?;
+                self.exec_sync(id, body).await
+            }
+            ("POST", ["containers", id, "reopen-log"]) => self.reopen_log(id).await,
+            ("DELETE", ["containers", id]) => {
+                let force = request.query.split('&').any(|x| x == "force=true");
+                self.remove_container(id, force).await
+            }
+            (
+                _,
+                ["version"]
+                | ["status"]
+                | ["containers"]
+                | ["containers", _]
+                | ["containers", _, "stats" | "exec" | "reopen-log"],
+            ) => Err(("405 Method Not Allowed", "method not allowed".into())),
+            _ => Err(("404 Not Found", "not found".into())),
+        }
+    }
+
+    /// Forward the call to the Cap'n Proto service and wait for its JSON response.
+    async fn call<F, R>(&self, f: F) -> Result<Value, Failure>
+    where
+        F: FnOnce(conmon::Client) -> R + Send + 'static,
+        R: Future<Output = Result<Value, capnp::Error>> + 'static,
+    {
+        self.bridge.call(f).await.map_err(|e| match e.kind {
+            _ if CapacityExceeded::matches(&e.description) => {
+                ("429 Too Many Requests", e.description)
+            }
+            ErrorKind::Disconnected => ("503 Service Unavailable", e.description),
+            ErrorKind::Overloaded => ("504 Gateway Timeout", e.description),
+            _ => ("500 Internal Server Error", e.description),
+        })
+    }
+
+    async fn version(&self) -> Result<Value, Failure> {
+        self.call(|client| async move {
+            let response = client.version_request().send().promise.await?;
+            let response = response.get()?.get_response()?;
+            Ok(json!({
+                "version": response.get_version()?,
+                "tag": response.get_tag()?,
+                "commit": response.get_commit()?,
+                "buildDate": response.get_build_date()?,
+                "rustVersion": response.get_rust_version()?,
+                "processId": response.get_process_id(),
+                "buildTarget": response.get_build_target()?,
+                "buildProfile": response.get_build_profile()?,
+                "features": texts(response.get_features()?)?,
+                "logDrivers": response
+                    .get_log_drivers()?
+                    .iter()
+                    .map(|x| x.map(log_driver_name))
+                    .collect::<Result<Vec<_>, _>>()?,
+                "protocolVersion": response.get_protocol_version(),
+                "minProtocolVersion": response.get_min_protocol_version(),
+            }))
+        })
+        .await
+    }
+
+    async fn status(&self) -> Result<Value, Failure> {
+        self.call(|client| async move {
+            let response = client.status_request().send().promise.await?;
+            let response = response.get()?.get_response()?;
+            let mut errors = vec![];
+            for error in response.get_errors()?.iter() {
+                errors.push(json!({
+                    "unixNano": error.get_unix_nano(),
+                    "target": error.get_target()?,
+                    "message": error.get_message()?,
+                }));
+            }
+            Ok(json!({
+                "uptimeMs": response.get_uptime_ms(),
+                "containers": response.get_containers(),
+                "attachClients": response.get_attach_clients(),
+                "tasks": response.get_tasks(),
+                "openFds": response.get_open_fds(),
+                "maxFds": response.get_max_fds(),
+                "trackedContainers": response.get_tracked_containers(),
+                "maxContainers": response.get_max_containers(),
+                "errors": errors,
+            }))
+        })
+        .await
+    }
+
+    async fn list_containers(&self) -> Result<Value, Failure> {
+        self.call(|client| async move {
+            let response = client.list_containers_request().send().promise.await?;
+            let mut containers = vec![];
+            for container in response.get()?.get_response()?.get_containers()?.iter() {
+                containers.push(json!({
+                    "id": container.get_id()?,
+                    "execSessionId": container.get_exec_session_id()?,
+                    "pid": container.get_pid(),
+                    "state": state_name(container.get_state()?),
+                    "exitCode": container.get_exit_code(),
+                    "createdUnixNano": container.get_created_unix_nano(),
+                    "logPaths": texts(container.get_log_paths()?)?,
+                    "attachSocketPaths": texts(container.get_attach_socket_paths()?)?,
+                    "labels": entries(container.get_labels()?)?,
+                    "annotations": entries(container.get_annotations()?)?,
+                }));
+            }
+            Ok(json!({ "containers": containers }))
+        })
+        .await
+    }
+
+    async fn container_status(&self, id: &str) -> Result<Value, Failure> {
+        let id = id.to_string();
+        self.call(move |client| async move {
+            let mut call = client.container_status_request();
+            call.get().init_request().set_id(&id);
+            let response = call.send().promise.await?;
+            let response = response.get()?.get_response()?;
+            Ok(json!({
+                "state": state_name(response.get_state()?),
+                "pid": response.get_pid(),
+                "exitCode": response.get_exit_code(),
+                "signal": response.get_signal(),
+                "oomKilled": response.get_oom_killed(),
+                "createdUnixNano": response.get_created_unix_nano(),
+                "startedUnixNano": response.get_started_unix_nano(),
+                "finishedUnixNano": response.get_finished_unix_nano(),
+                "labels": entries(response.get_labels()?)?,
+                "annotations": entries(response.get_annotations()?)?,
+                "restartCount": response.get_restart_count(),
+            }))
+        })
+        .await
+    }
+
+    async fn container_stats(&self, id: &str) -> Result<Value, Failure> {
+        let id = id.to_string();
+        self.call(move |client| async move {
+            let mut call = client.container_stats_request();
+            call.get().init_request().set_id(&id);
+            let response = call.send().promise.await?;
+            let response = response.get()?.get_response()?;
+            Ok(json!({
+                "cpuUsageUsec": response.get_cpu_usage_usec(),
+                "cpuUserUsec": response.get_cpu_user_usec(),
+                "cpuSystemUsec": response.get_cpu_system_usec(),
+                "memoryUsageBytes": response.get_memory_usage_bytes(),
+                "memoryLimitBytes": response.get_memory_limit_bytes(),
+                "pidsCurrent": response.get_pids_current(),
+                "pidsLimit": response.get_pids_limit(),
+                "ioReadBytes": response.get_io_read_bytes(),
+                "ioWriteBytes": response.get_io_write_bytes(),
+            }))
+        })
+        .await
+    }
+
+    async fn exec_sync(&self, id: &str, body: ExecBody) -> Result<Value, Failure> {
+        let id = id.to_string();
+        self.call(move |client| async move {
+            let mut call = client.exec_sync_container_request();
+            let mut req = call.get().init_request();
+            req.set_id(&id);
+            req.set_timeout_sec(body.timeout_sec);
+            let mut command = req.init_command(body.command.len() as u32);
+            for (i, arg) in body.command.iter().enumerate() {
+                command.set(i as u32, arg);
+            }
+            let response = call.send().promise.await?;
+            let response = response.get()?.get_response()?;
+            Ok(json!({
+                "exitCode": response.get_exit_code(),
+                "stdout": String::from_utf8_lossy(response.get_stdout()?),
+                "stderr": String::from_utf8_lossy(response.get_stderr()?),
+                "timedOut": response.get_timed_out(),
+                "execSessionId": response.get_exec_session_id()?,
+            }))
+        })
+        .await
+    }
+
+    async fn reopen_log(&self, id: &str) -> Result<Value, Failure> {
+        let id = id.to_string();
+        self.call(move |client| async move {
+            let mut call = client.reopen_log_container_request();
+            call.get().init_request().set_id(&id);
+            call.send().promise.await?;
+            Ok(json!({}))
+        })
+        .await
+    }
+
+    async fn remove_container(&self, id: &str, force: bool) -> Result<Value, Failure> {
+        let id = id.to_string();
+        self.call(move |client| async move {
+            let mut call = client.remove_container_request();
+            let mut req = call.get().init_request();
+            req.set_id(&id);
+            req.set_force(force);
+            let response = call.send().promise.await?;
+            Ok(json!({
+                "killedProcesses": response.get()?.get_response()?.get_killed_processes(),
+            }))
+        })
+        .await
+    }
+}
+
+/// Read the request line, the relevant headers and the body of a request.
+async fn read_request<T>(reader: &mut T) -> Result<Request>
+where
+    T: AsyncBufRead + Unpin,
+{
+    let mut head = (&mut *reader).take(MAX_HEAD_SIZE);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => bail!("invalid request line"),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method: method.into(),
+        path: path.into(),
+        query: query.into(),
+        ..Default::default()
+    };
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if head.read_line(&mut header).await? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().context("parse content length")?;
+            } else if name.eq_ignore_ascii_case("authorization") {
+                request.authorization = Some(value.into());
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_SIZE {
+        bail!("request body exceeds {} bytes", MAX_BODY_SIZE)
+    }
+    request.body = vec![0; content_length];
+    reader
+        .read_exact(&mut request.body)
+        .await
+        .context("read request body")?;
+    Ok(request)
+}
+
+fn error_body<T: Into<String>>(message: T) -> Value {
+    json!({ "error": message.into() })
+}
+
+fn state_name(state: ContainerState) -> &'static str {
+    match state {
+        ContainerState::Created => "created",
+        ContainerState::Running => "running",
+        ContainerState::Exited => "exited",
+    }
+}
+
+fn log_driver_name(typ: log_driver::Type) -> &'static str {
+    match typ {
+        log_driver::Type::ContainerRuntimeInterface => "containerRuntimeInterface",
+        log_driver::Type::Journald => "journald",
+        log_driver::Type::JsonFile => "jsonFile",
+        log_driver::Type::Syslog => "syslog",
+        log_driver::Type::None => "none",
+        log_driver::Type::Gelf => "gelf",
+        log_driver::Type::Splunk => "splunk",
+        log_driver::Type::Remote => "remote",
+        log_driver::Type::Plugin => "plugin",
+        log_driver::Type::Loki => "loki",
+        log_driver::Type::Kafka => "kafka",
+        log_driver::Type::Passthrough => "passthrough",
+    }
+}
+
+fn texts(reader: capnp::text_list::Reader<'_>) -> capnp::Result<Vec<String>> {
+    reader.iter().map(|x| x.map(String::from)).collect()
+}
+
+fn entries(
+    reader: capnp::struct_list::Reader<'_, conmon::metadata::Owned>,
+) -> capnp::Result<Map<String, Value>> {
+    reader
+        .iter()
+        .map(|x| Ok((x.get_key()?.to_string(), x.get_value()?.into())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Authenticator;
+    use std::sync::Arc;
+    use tokio::io::duplex;
+
+    async fn request(sut: &HttpApi, request: &str) -> Result<String> {
+        let (mut client, server) = duplex(64 * 1024);
+        client.write_all(request.as_bytes()).await?;
+        sut.handle(server).await?;
+        let mut response = String::new();
+        client.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    fn api(authenticator: Option<Authenticator>) -> HttpApi {
+        // Dropping the receiving side lets all forwarded calls fail as disconnected.
+        let (bridge, _) = CapnpBridge::new();
+        HttpApi::new(
+            bridge,
+            Access::new(authenticator.map(Arc::new), vec![], vec![]),
+        )
+    }
+
+    #[tokio::test]
+    async fn read_request_parts() -> Result<()> {
+        let mut reader = BufReader::new(
+            &b"POST /v1/containers/id/exec?x=y HTTP/1.1\r\n\
+            Authorization: Bearer token\r\n\
+            content-length: 4\r\n\r\nbodyrest"[..],
+        );
+        assert_eq!(
+            read_request(&mut reader).await?,
+            Request {
+                method: "POST".into(),
+                path: "/v1/containers/id/exec".into(),
+                query: "x=y".into(),
+                authorization: Some("Bearer token".into()),
+                body: b"body".to_vec(),
+            }
+        );
+
+        let mut reader = BufReader::new(&b"\r\n"[..]);
+        assert!(read_request(&mut reader).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handle_routes() -> Result<()> {
+        let sut = api(None);
+        for (request, status) in [
+            (
+                "GET /v1/version HTTP/1.1\r\n\r\n",
+                "503 Service Unavailable",
+            ),
+            (
+                "GET /v1/containers/id/stats HTTP/1.1\r\n\r\n",
+                "503 Service Unavailable",
+            ),
+            (
+                "DELETE /v1/containers/id?force=true HTTP/1.1\r\n\r\n",
+                "503 Service Unavailable",
+            ),
+            (
+                "PUT /v1/containers HTTP/1.1\r\n\r\n",
+                "405 Method Not Allowed",
+            ),
+            ("GET /v1/unknown HTTP/1.1\r\n\r\n", "404 Not Found"),
+            ("GET /metrics HTTP/1.1\r\n\r\n", "404 Not Found"),
+            (
+                "POST /v1/containers/id/exec HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}",
+                "400 Bad Request",
+            ),
+            ("\r\n", "400 Bad Request"),
+        ] {
+            let response = request(&sut, request).await?;
+            assert!(
+                response.starts_with(&format!("HTTP/1.1 {}\r\n", status)),
+                "{}: {}",
+                request,
+                response
+            );
+            assert!(response.contains("Content-Type: application/json\r\n"));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handle_authentication() -> Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        std::fs::write(file.path(), "secret\n")?;
+        let sut = api(Some(Authenticator::load(file.path())?));
+
+        let response = request(&sut, "GET /v1/version HTTP/1.1\r\n\r\n").await?;
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        assert!(response.ends_with(r#"{"error":"invalid token"}"#));
+
+        let response = request(
+            &sut,
+            "GET /v1/version HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n",
+        )
+        .await?;
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn validate_address() {
+        assert!(HttpApi::validate_address("/run/conmonrs/http.sock", false).is_ok());
+        assert!(HttpApi::validate_address("127.0.0.1:8080", true).is_ok());
+        assert!(HttpApi::validate_address("[::1]:8080", true).is_ok());
+        assert!(HttpApi::validate_address("127.0.0.1:8080", false).is_err());
+        assert!(HttpApi::validate_address("0.0.0.0:8080", true).is_err());
+        assert!(HttpApi::validate_address("localhost", true).is_err());
+    }
+}
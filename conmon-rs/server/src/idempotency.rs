@@ -0,0 +1,192 @@
+//! Deduplication of create requests by client supplied idempotency keys, which replays the result
+//! of the original create to retries instead of invoking the runtime again.
+
+use anyhow::{bail, format_err, Result};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::watch::{self, Receiver},
+    task,
+    time::Instant,
+};
+
+/// The result of a create, where failures keep only their message.
+type Outcome = Option<Result<u32, String>>;
+
+macro_rules! lock {
+    ($x:expr) => {
+        $x.lock().map_err(|e| format_err!("{:#}", e))?
+    };
+}
+
+#[derive(Clone, Debug, Default)]
+/// The creates of the recently used idempotency keys.
+pub struct IdempotencyCache {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// The ID of the container created for the key.
+    id: String,
+
+    /// Resolves once the create finished.
+    outcome: Receiver<Outcome>,
+
+    /// The time the key gets forgotten, which is unset while the create is running.
+    expires: Option<Instant>,
+}
+
+#[derive(Debug)]
+/// A running or finished create of an idempotency key.
+pub struct PendingCreate(Receiver<Outcome>);
+
+impl IdempotencyCache {
+    /// Start the create of the container `id` for the idempotency key, or replay the create which
+    /// used the key within the window. New creates run in the background, so that they finish
+    /// even if the request got cancelled, and their result is kept for the window afterwards.
+    pub fn claim<F, Fut>(
+        &self,
+        key: &str,
+        id: &str,
+        window: Duration,
+        create: F,
+    ) -> Result<PendingCreate>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<u32, String>> + 'static,
+    {
+        let mut entries = lock!(self.entries);
+        let now = Instant::now();
+        entries.retain(|_, entry| !matches!(entry.expires, Some(x) if x <= now));
+
+        if let Some(entry) = entries.get(key) {
+            if entry.id != id {
+                bail!(
+                    "idempotency key {} already used for container {}",
+                    key,
+                    entry.id
+                )
+            }
+            return Ok(PendingCreate(entry.outcome.clone()));
+        }
+
+        let (sender, receiver) = watch::channel(None);
+        entries.insert(
+            key.into(),
+            Entry {
+                id: id.into(),
+                outcome: receiver.clone(),
+                expires: None,
+            },
+        );
+
+        let create = create();
+        let cache = self.clone();
+        let key = key.to_string();
+        task::spawn_local(async move {
+            let _ = sender.send(Some(create.await));
+            if let Ok(mut entries) = cache.entries.lock() {
+                if let Some(entry) = entries.get_mut(&key) {
+                    entry.expires = Some(Instant::now() + window);
+                }
+            }
+        });
+
+        Ok(PendingCreate(receiver))
+    }
+}
+
+impl PendingCreate {
+    /// Wait for the create to finish, resolving to the PID of the container.
+    pub async fn result(mut self) -> Result<u32> {
+        loop {
+            if let Some(outcome) = self.0.borrow().as_ref() {
+                return outcome.clone().map_err(|e| format_err!("{}", e));
+            }
+            self.0
+                .changed()
+                .await
+                .map_err(|_| format_err!("create got dropped"))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, rc::Rc};
+    use tokio::{task::LocalSet, time};
+
+    #[tokio::test]
+    async fn claim_replays_result() -> Result<()> {
+        LocalSet::new()
+            .run_until(async {
+                let sut = IdempotencyCache::default();
+                let runs = Rc::new(Cell::new(0));
+                let create = || {
+                    runs.set(runs.get() + 1);
+                    async {
+                        time::sleep(Duration::from_millis(10)).await;
+                        Ok(42)
+                    }
+                };
+
+                let first = sut.claim("key", "id", Duration::from_secs(60), create)?;
+                let second = sut.claim("key", "id", Duration::from_secs(60), create)?;
+                assert_eq!(first.result().await?, 42);
+                assert_eq!(second.result().await?, 42);
+
+                let replay = sut.claim("key", "id", Duration::from_secs(60), create)?;
+                assert_eq!(replay.result().await?, 42);
+                assert_eq!(runs.get(), 1);
+
+                assert!(sut
+                    .claim("key", "other", Duration::from_secs(60), create)
+                    .is_err());
+                Ok(())
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn claim_replays_error() -> Result<()> {
+        LocalSet::new()
+            .run_until(async {
+                let sut = IdempotencyCache::default();
+                let create = || async { Err("runtime failed".to_string()) };
+
+                let first = sut.claim("key", "id", Duration::from_secs(60), create)?;
+                assert!(first.result().await.is_err());
+                let replay = sut.claim("key", "id", Duration::from_secs(60), || async { Ok(1) })?;
+                assert_eq!(
+                    replay.result().await.unwrap_err().to_string(),
+                    "runtime failed"
+                );
+                Ok(())
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn claim_expires() -> Result<()> {
+        LocalSet::new()
+            .run_until(async {
+                let sut = IdempotencyCache::default();
+                let window = Duration::from_millis(10);
+
+                let first = sut.claim("key", "id", window, || async { Ok(1) })?;
+                assert_eq!(first.result().await?, 1);
+                time::sleep(Duration::from_millis(20)).await;
+
+                let second = sut.claim("key", "other", window, || async { Ok(2) })?;
+                assert_eq!(second.result().await?, 2);
+                Ok(())
+            })
+            .await
+    }
+}
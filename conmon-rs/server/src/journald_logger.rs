@@ -0,0 +1,259 @@
+//! Journald logging functionalities.
+
+use crate::container_io::Pipe;
+use anyhow::{Context, Result};
+use getset::{Getters, Setters};
+use std::{
+    marker::Unpin,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, BufReader},
+    net::UnixDatagram,
+};
+use tracing::{debug, trace};
+
+#[derive(Debug, Getters, Setters)]
+/// The structure used for logging container output into the systemd journal.
+pub struct JournaldLogger {
+    #[getset(get, set)]
+    /// Path to the native protocol socket of journald.
+    socket_path: PathBuf,
+
+    /// Connected socket of the `socket_path`.
+    socket: Option<UnixDatagram>,
+
+    #[getset(get)]
+    /// The full container identifier.
+    container_id: String,
+
+    #[getset(get)]
+    /// The container name, omitted if empty.
+    container_name: String,
+
+    #[getset(get)]
+    /// The tag used as syslog identifier, omitted if empty.
+    tag: String,
+}
+
+impl JournaldLogger {
+    const ERR_UNINITIALIZED: &'static str = "logger not initialized";
+
+    /// The default path of the native journal protocol socket.
+    pub const SOCKET_PATH: &'static str = "/run/systemd/journal/socket";
+
+    /// The length of the shortened container identifier.
+    const SHORT_ID_LEN: usize = 12;
+
+    /// The maximum length of a single message, longer lines get split into partial messages.
+    const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+    /// Priority of stdout messages (LOG_INFO).
+    const PRIORITY_STDOUT: &'static [u8] = b"6";
+
+    /// Priority of stderr messages (LOG_ERR).
+    const PRIORITY_STDERR: &'static [u8] = b"3";
+
+    /// Create a new journald logger instance.
+    pub fn new<T: AsRef<str>>(
+        container_id: T,
+        container_name: T,
+        tag: T,
+    ) -> Result<JournaldLogger> {
+        Ok(Self {
+            socket_path: Self::SOCKET_PATH.into(),
+            socket: None,
+            container_id: container_id.as_ref().into(),
+            container_name: container_name.as_ref().into(),
+            tag: tag.as_ref().into(),
+        })
+    }
+
+    /// Asynchronously initialize the journald logger.
+    pub async fn init(&mut self) -> Result<()> {
+        debug!(
+            "Initializing journald logger for socket {}",
+            self.socket_path().display()
+        );
+        self.socket = Self::connect(self.socket_path())?.into();
+        Ok(())
+    }
+
+    /// Write the contents of the provided reader into the journal, one message per line.
+    pub async fn write<T>(&mut self, pipe: Pipe, bytes: T) -> Result<()>
+    where
+        T: AsyncBufRead + Unpin,
+    {
+        let mut reader = BufReader::new(bytes);
+        loop {
+            let mut line = vec![];
+            let read = reader
+                .read_until(b'\n', &mut line)
+                .await
+                .context("read log line")?;
+            if read == 0 {
+                break;
+            }
+
+            let partial = line.last() != Some(&b'\n');
+            if !partial {
+                line.pop();
+            }
+
+            let chunks = line.chunks(Self::MAX_MESSAGE_LEN).collect::<Vec<_>>();
+            for (idx, chunk) in chunks.iter().enumerate() {
+                let message = self.message(pipe, chunk, partial || idx + 1 < chunks.len());
+                self.socket
+                    .as_ref()
+                    .context(Self::ERR_UNINITIALIZED)?
+                    .send(&message)
+                    .await
+                    .context("send journal message")?;
+                trace!("Wrote journal message of length {}", chunk.len());
+            }
+        }
+        Ok(())
+    }
+
+    /// Reconnect to the journal, which is required if journald got restarted.
+    pub async fn reopen(&mut self) -> Result<()> {
+        debug!("Reopen journald logger");
+        self.init().await
+    }
+
+    /// Connect an unbound datagram socket to the provided path.
+    fn connect<T: AsRef<Path>>(path: T) -> Result<UnixDatagram> {
+        let socket = UnixDatagram::unbound().context("create journal socket")?;
+        socket.connect(&path).context(format!(
+            "connect to journal socket '{}'",
+            path.as_ref().display()
+        ))?;
+        Ok(socket)
+    }
+
+    /// Serialize a single log line into a message of the native journal protocol.
+    fn message(&self, pipe: Pipe, line: &[u8], partial: bool) -> Vec<u8> {
+        let mut message = Vec::with_capacity(line.len() + 256);
+        Self::append_field(&mut message, "MESSAGE", line);
+        Self::append_field(
+            &mut message,
+            "PRIORITY",
+            match pipe {
+                Pipe::StdOut => Self::PRIORITY_STDOUT,
+                Pipe::StdErr => Self::PRIORITY_STDERR,
+            },
+        );
+
+        let id = self.container_id();
+        let short_id = id.get(..Self::SHORT_ID_LEN).unwrap_or(id);
+        Self::append_field(&mut message, "CONTAINER_ID", short_id.as_bytes());
+        Self::append_field(&mut message, "CONTAINER_ID_FULL", id.as_bytes());
+        if !self.container_name().is_empty() {
+            Self::append_field(
+                &mut message,
+                "CONTAINER_NAME",
+                self.container_name().as_bytes(),
+            );
+        }
+        if !self.tag().is_empty() {
+            Self::append_field(&mut message, "CONTAINER_TAG", self.tag().as_bytes());
+            Self::append_field(&mut message, "SYSLOG_IDENTIFIER", self.tag().as_bytes());
+        }
+        if partial {
+            Self::append_field(&mut message, "CONTAINER_PARTIAL_MESSAGE", b"true");
+        }
+        message
+    }
+
+    /// Append a field to the message. Values containing newlines use the binary safe format,
+    /// which prefixes them by their little endian 64 bit length.
+    pub fn append_field(message: &mut Vec<u8>, key: &str, value: &[u8]) {
+        message.extend_from_slice(key.as_bytes());
+        if value.contains(&b'\n') {
+            message.push(b'\n');
+            message.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            message.push(b'=');
+        }
+        message.extend_from_slice(value);
+        message.push(b'\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    async fn new_sut(journal: &Path) -> Result<JournaldLogger> {
+        let mut sut = JournaldLogger::new("0123456789abcdef", "name", "")?;
+        sut.set_socket_path(journal.into());
+        sut.init().await?;
+        Ok(sut)
+    }
+
+    async fn recv(journal: &UnixDatagram) -> Result<Vec<u8>> {
+        let mut buf = vec![0; 1024];
+        let n = journal.recv(&mut buf).await?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    #[tokio::test]
+    async fn write_stdout_stderr_success() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("journal");
+        let journal = UnixDatagram::bind(&path)?;
+        let mut sut = new_sut(&path).await?;
+
+        sut.write(Pipe::StdOut, "a\n".as_bytes()).await?;
+        sut.write(Pipe::StdErr, "b".as_bytes()).await?;
+
+        assert_eq!(
+            recv(&journal).await?,
+            b"MESSAGE=a\nPRIORITY=6\nCONTAINER_ID=0123456789ab\n\
+              CONTAINER_ID_FULL=0123456789abcdef\nCONTAINER_NAME=name\n"
+        );
+        assert_eq!(
+            recv(&journal).await?,
+            b"MESSAGE=b\nPRIORITY=3\nCONTAINER_ID=0123456789ab\n\
+              CONTAINER_ID_FULL=0123456789abcdef\nCONTAINER_NAME=name\n\
+              CONTAINER_PARTIAL_MESSAGE=true\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_tag() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("journal");
+        let journal = UnixDatagram::bind(&path)?;
+        let mut sut = JournaldLogger::new("id", "", "pod/ctr")?;
+        sut.set_socket_path(path);
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, "a\n".as_bytes()).await?;
+
+        assert_eq!(
+            recv(&journal).await?,
+            b"MESSAGE=a\nPRIORITY=6\nCONTAINER_ID=id\nCONTAINER_ID_FULL=id\n\
+              CONTAINER_TAG=pod/ctr\nSYSLOG_IDENTIFIER=pod/ctr\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn append_field_binary() {
+        let mut message = vec![];
+        JournaldLogger::append_field(&mut message, "MESSAGE", b"a\nb");
+        assert_eq!(message, b"MESSAGE\n\x03\0\0\0\0\0\0\0a\nb\n");
+    }
+
+    #[tokio::test]
+    async fn init_failure() -> Result<()> {
+        let mut sut = JournaldLogger::new("id", "", "")?;
+        sut.set_socket_path("/file/does/not/exist".into());
+        assert!(sut.init().await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,480 @@
+//! Docker compatible json-file logging functionalities.
+
+use crate::{
+    container_io::Pipe,
+    container_log::ContainerLog,
+    file_ownership::FileOwnership,
+    log_compression::LogCompression,
+    log_sync::{LogSync, SyncPolicy},
+    log_timestamp::TimestampFormat,
+    utf8_boundary,
+};
+use anyhow::{Context, Result};
+use getset::{CopyGetters, Getters, Setters};
+use memchr::memchr;
+use serde::Serialize;
+use std::{
+    io::ErrorKind,
+    marker::Unpin,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufWriter},
+    task::JoinHandle,
+};
+use tracing::{debug, trace, warn};
+
+#[derive(Debug, CopyGetters, Getters, Setters)]
+/// The structure used for writing container logs in the json-file format of Docker.
+pub struct JsonFileLogger {
+    #[getset(get = "pub")]
+    /// Path to the file on disk.
+    path: PathBuf,
+
+    #[getset(set)]
+    /// Open file handle of the `path`.
+    file: Option<BufWriter<File>>,
+
+    #[getset(get_copy)]
+    /// Maximum allowed log size in bytes before the file gets rotated (`max-size`).
+    max_log_size: Option<usize>,
+
+    #[getset(get_copy = "pub")]
+    /// Maximum number of log files, including the currently written one (`max-file`).
+    max_files: usize,
+
+    #[getset(get_copy)]
+    /// Compression applied to rotated log files.
+    compression: Option<LogCompression>,
+
+    /// Background compression of the last rotated log file.
+    compression_task: Option<JoinHandle<()>>,
+
+    #[getset(get_copy = "pub", set)]
+    /// Current bytes written to the log file.
+    bytes_written: usize,
+
+    #[getset(get_copy = "pub")]
+    /// Amount of rotations of the log file.
+    rotations: u64,
+
+    /// Incomplete code point at the end of the last stdout write.
+    pending_stdout: Vec<u8>,
+
+    /// Incomplete code point at the end of the last stderr write.
+    pending_stderr: Vec<u8>,
+
+    #[getset(get_copy, set = "pub")]
+    /// Format of the entry timestamps.
+    timestamp_format: TimestampFormat,
+
+    /// Sync state of the written entries.
+    log_sync: LogSync,
+
+    #[getset(get, set = "pub")]
+    /// Owner and SELinux file context applied to the log file on creation.
+    ownership: FileOwnership,
+}
+
+#[derive(Debug, Serialize)]
+/// A single line of the json-file log.
+struct Entry<'a> {
+    log: &'a str,
+    stream: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<&'a str>,
+}
+
+impl JsonFileLogger {
+    const ERR_UNINITIALIZED: &'static str = "logger not initialized";
+
+    /// Create a new json-file logger instance. A `max_files` of zero is treated like one, which
+    /// means that the log gets truncated instead of rotated.
+    pub fn new<T: AsRef<Path>>(
+        path: T,
+        max_log_size: Option<usize>,
+        max_files: usize,
+        compression: Option<LogCompression>,
+    ) -> Result<JsonFileLogger> {
+        Ok(Self {
+            path: path.as_ref().into(),
+            file: None,
+            max_log_size,
+            max_files: max_files.max(1),
+            compression,
+            compression_task: None,
+            bytes_written: 0,
+            rotations: 0,
+            pending_stdout: vec![],
+            pending_stderr: vec![],
+            timestamp_format: TimestampFormat::default(),
+            log_sync: LogSync::default(),
+            ownership: FileOwnership::default(),
+        })
+    }
+
+    /// Set the policy of syncing written entries to disk.
+    pub fn set_sync_policy(&mut self, policy: SyncPolicy) {
+        self.log_sync = LogSync::new(policy);
+    }
+
+    /// Asynchronously initialize the json-file logger.
+    pub async fn init(&mut self) -> Result<()> {
+        debug!(
+            "Initializing json-file logger in path {}",
+            self.path().display()
+        );
+        let file = Self::open(self.path()).await?;
+        self.ownership().apply(file.get_ref())?;
+        self.set_file(file.into());
+        self.set_bytes_written(0);
+        Ok(())
+    }
+
+    /// Write the contents of the provided reader into the json-file logger. An incomplete code
+    /// point at the end of the reader is kept until the next write of the pipe.
+    pub async fn write<T>(&mut self, pipe: Pipe, mut bytes: T) -> Result<()>
+    where
+        T: AsyncBufRead + Unpin,
+    {
+        let time = self.timestamp_format().utc()?;
+        let stream = match pipe {
+            Pipe::StdOut => "stdout",
+            Pipe::StdErr => "stderr",
+        };
+
+        // The buffers are reused for all lines of the write.
+        let mut line_buf = std::mem::take(self.pending_mut(pipe));
+        let mut entry = vec![];
+        loop {
+            let read = Self::read_line(&mut bytes, &mut line_buf).await?;
+            if read == 0 {
+                break;
+            }
+
+            let len = match line_buf.last() {
+                Some(b'\n') => line_buf.len(),
+                _ => utf8_boundary::complete_len(&line_buf),
+            };
+            if len == 0 {
+                continue;
+            }
+
+            entry.clear();
+            serde_json::to_writer(
+                &mut entry,
+                &Entry {
+                    log: &String::from_utf8_lossy(&line_buf[..len]),
+                    stream,
+                    time: time.as_deref(),
+                },
+            )
+            .context("serialize log entry")?;
+            entry.push(b'\n');
+
+            let new_bytes_written = self.bytes_written().saturating_add(entry.len());
+            if let Some(max_log_size) = self.max_log_size() {
+                trace!(
+                    "Verifying log size: max_log_size = {}, bytes_written = {}, entry_len = {}",
+                    max_log_size,
+                    self.bytes_written(),
+                    entry.len(),
+                );
+
+                if new_bytes_written > max_log_size && self.bytes_written() > 0 {
+                    self.rotate()
+                        .await
+                        .context("rotate logs because of exceeded size")?;
+                }
+            }
+
+            self.file
+                .as_mut()
+                .context(Self::ERR_UNINITIALIZED)?
+                .write_all(&entry)
+                .await?;
+            self.set_bytes_written(self.bytes_written() + entry.len());
+            trace!("Wrote log line of length {}", entry.len());
+
+            if self.log_sync.record(entry.len()) {
+                self.flush().await?;
+                self.sync().await?;
+            }
+            line_buf.drain(..len);
+        }
+
+        *self.pending_mut(pipe) = line_buf;
+        self.flush().await
+    }
+
+    /// Returns the incomplete code point of the provided pipe.
+    fn pending_mut(&mut self, pipe: Pipe) -> &mut Vec<u8> {
+        match pipe {
+            Pipe::StdOut => &mut self.pending_stdout,
+            Pipe::StdErr => &mut self.pending_stderr,
+        }
+    }
+
+    /// Recreate the log file if it got removed while being open, which returns whether it got
+    /// recreated. Buffered entries are written to the recreated file instead of the removed one.
+    pub async fn recreate_removed(&mut self) -> Result<bool> {
+        match fs::metadata(self.path()).await {
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            _ => return Ok(false),
+        }
+        warn!("Recreating removed container log {}", self.path().display());
+        let buffered = self
+            .file
+            .as_ref()
+            .map(|file| file.buffer().to_vec())
+            .unwrap_or_default();
+        self.init().await?;
+        self.set_bytes_written(buffered.len());
+        self.file
+            .as_mut()
+            .context(Self::ERR_UNINITIALIZED)?
+            .write_all(&buffered)
+            .await
+            .context("write buffered entries")?;
+        Ok(true)
+    }
+
+    /// Reopen the container log file.
+    pub async fn reopen(&mut self) -> Result<()> {
+        debug!("Reopen container log {}", self.path().display());
+        self.sync().await?;
+        self.init().await
+    }
+
+    /// Truncate the container log file and remove all rotated files.
+    pub async fn truncate(&mut self) -> Result<()> {
+        ContainerLog::remove_rotated_files(
+            &self.path,
+            self.max_files(),
+            &mut self.compression_task,
+        )
+        .await?;
+        self.reopen().await
+    }
+
+    /// Ensures that all content is written to disk.
+    pub async fn flush(&mut self) -> Result<()> {
+        self.file
+            .as_mut()
+            .context(Self::ERR_UNINITIALIZED)?
+            .flush()
+            .await
+            .context("flush file writer")
+    }
+
+    /// Rotate the log files by shifting `path.N-1` to `path.N` down to `path` to `path.1`, where
+    /// the oldest file gets dropped. The log is only truncated if `max_files` is one.
+    pub async fn rotate(&mut self) -> Result<()> {
+        debug!("Rotate container log {}", self.path().display());
+        self.rotations += 1;
+        self.flush().await?;
+        self.sync().await?;
+
+        ContainerLog::rotate_files(
+            &self.path,
+            self.max_files(),
+            self.compression(),
+            &mut self.compression_task,
+        )
+        .await?;
+        self.init().await
+    }
+
+    /// Sync the contents of the log file to disk.
+    async fn sync(&mut self) -> Result<()> {
+        self.file
+            .as_mut()
+            .context(Self::ERR_UNINITIALIZED)?
+            .get_ref()
+            .sync_all()
+            .await
+            .context("sync log file")?;
+        self.log_sync.synced();
+        Ok(())
+    }
+
+    /// Open the provided path with the default options.
+    async fn open<T: AsRef<Path>>(path: T) -> Result<BufWriter<File>> {
+        Ok(BufWriter::new(
+            OpenOptions::new()
+                .create(true)
+                .read(true)
+                .truncate(true)
+                .write(true)
+                .mode(0o600)
+                .open(&path)
+                .await
+                .context(format!("open log file path '{}'", path.as_ref().display()))?,
+        ))
+    }
+
+    async fn read_line<T>(r: &mut T, buf: &mut Vec<u8>) -> Result<usize>
+    where
+        T: AsyncBufRead + Unpin,
+    {
+        let read = {
+            let available = r.fill_buf().await?;
+            match memchr(b'\n', available) {
+                Some(i) => {
+                    buf.extend_from_slice(&available[..=i]);
+                    i + 1
+                }
+                None => {
+                    buf.extend_from_slice(available);
+                    available.len()
+                }
+            }
+        };
+        r.consume(read);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_compression::Codec;
+    use std::fs;
+    use tempfile::{tempdir, NamedTempFile};
+    use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+    #[tokio::test]
+    async fn write_stdout_stderr_success() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let path = file.path();
+        let mut sut = JsonFileLogger::new(path, None, 1, None)?;
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, "a \"line\"\n".as_bytes()).await?;
+        sut.write(Pipe::StdErr, "partial".as_bytes()).await?;
+
+        let res = fs::read_to_string(path)?;
+        let lines = res.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(r#"{"log":"a \"line\"\n","stream":"stdout","time":""#));
+        assert!(lines[1].starts_with(r#"{"log":"partial","stream":"stderr","time":""#));
+
+        let timestamp = lines[0].rsplit('"').nth(1).context("no timestamp")?;
+        assert!(timestamp.ends_with('Z'));
+        OffsetDateTime::parse(timestamp, &Rfc3339).context("unable to parse timestamp")?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_split_code_point() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let path = file.path();
+        let mut sut = JsonFileLogger::new(path, None, 1, None)?;
+        sut.set_timestamp_format(TimestampFormat::None);
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, &b"a\xc3"[..]).await?;
+        sut.write(Pipe::StdErr, &b"\xe2"[..]).await?;
+        sut.write(Pipe::StdOut, &b"\xa4\n"[..]).await?;
+
+        let res = fs::read_to_string(path)?;
+        assert_eq!(
+            res.lines().collect::<Vec<_>>(),
+            vec![
+                r#"{"log":"a","stream":"stdout"}"#,
+                r#"{"log":"ä\n","stream":"stdout"}"#
+            ]
+        );
+        assert_eq!(sut.pending_stderr, b"\xe2");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_without_timestamp() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let path = file.path();
+        let mut sut = JsonFileLogger::new(path, None, 1, None)?;
+        sut.set_timestamp_format(TimestampFormat::None);
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, "a\n".as_bytes()).await?;
+
+        assert_eq!(
+            fs::read_to_string(path)?,
+            "{\"log\":\"a\\n\",\"stream\":\"stdout\"}\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_rotate() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log");
+        let mut sut = JsonFileLogger::new(&path, Some(100), 3, None)?;
+        sut.init().await?;
+
+        for line in &["a\n", "b\n", "c\n", "d\n"] {
+            sut.write(Pipe::StdOut, line.as_bytes()).await?;
+        }
+
+        let current = fs::read_to_string(&path)?;
+        let first = fs::read_to_string(ContainerLog::rotated_path(&path, 1))?;
+        let second = fs::read_to_string(ContainerLog::rotated_path(&path, 2))?;
+        assert!(current.contains(r#""log":"d\n""#));
+        assert!(first.contains(r#""log":"c\n""#));
+        assert!(second.contains(r#""log":"b\n""#));
+        assert!(!ContainerLog::rotated_path(&path, 3).exists());
+        assert_eq!(sut.rotations(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_rotate_compressed() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log");
+        let compression = LogCompression::new(Codec::Gzip, 0);
+        let mut sut = JsonFileLogger::new(&path, Some(100), 3, Some(compression))?;
+        sut.init().await?;
+
+        for line in &["a\n", "b\n", "c\n", "d\n"] {
+            sut.write(Pipe::StdOut, line.as_bytes()).await?;
+        }
+        sut.compression_task
+            .take()
+            .context("no compression task")?
+            .await?;
+
+        let first = ContainerLog::rotated_path(&path, 1);
+        let second = ContainerLog::rotated_path(&path, 2);
+        assert!(!first.exists());
+        assert!(!second.exists());
+        assert!(LogCompression::compressed_path(&first, Codec::Gzip).exists());
+        assert!(LogCompression::compressed_path(&second, Codec::Gzip).exists());
+        assert!(!ContainerLog::rotated_path(&path, 3).exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_truncate() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log");
+        let mut sut = JsonFileLogger::new(&path, Some(100), 1, None)?;
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, "a\nb\n".as_bytes()).await?;
+
+        let res = fs::read_to_string(&path)?;
+        assert!(!res.contains(r#""log":"a\n""#));
+        assert!(res.contains(r#""log":"b\n""#));
+        assert!(!ContainerLog::rotated_path(&path, 1).exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn init_failure() -> Result<()> {
+        let mut sut = JsonFileLogger::new("/file/does/not/exist", None, 1, None)?;
+        assert!(sut.init().await.is_err());
+        Ok(())
+    }
+}
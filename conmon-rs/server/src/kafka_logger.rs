@@ -0,0 +1,1257 @@
+//! Logging to Apache Kafka topics.
+//!
+//! The logger implements the minimal subset of the Kafka protocol required by a producer: it
+//! looks up the leader of the container partition via a metadata request and publishes record
+//! batches of the buffered container output to it from a batching task. Every record is keyed by
+//! the container ID and carries the stream as `stream` header, while the partition is selected
+//! like the Kafka default partitioner does for keyed records.
+
+use crate::{container_io::Pipe, remote_logger::Backoff};
+use anyhow::{anyhow, bail, Context, Result};
+use getset::{CopyGetters, Getters};
+use std::{
+    convert::{TryFrom, TryInto},
+    fs::File,
+    io::BufReader as StdBufReader,
+    marker::Unpin,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{
+        AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+        BufReader,
+    },
+    net::TcpStream,
+    sync::mpsc::{self, error::TrySendError},
+    task::{self, JoinHandle},
+    time,
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{self, Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName},
+    TlsConnector,
+};
+use tracing::{debug, error, trace, warn};
+
+#[derive(Debug, CopyGetters)]
+/// The structure used for publishing container output to a Kafka topic.
+pub struct KafkaLogger {
+    /// The producer, as long as it is not owned by the batching task.
+    producer: Option<Producer>,
+
+    /// Sender of records to the batching task.
+    sender: Option<mpsc::Sender<Record>>,
+
+    /// The batching task publishing the records, which returns the producer once stopped.
+    task: Option<JoinHandle<Producer>>,
+
+    #[getset(get_copy)]
+    /// The amount of records dropped because the buffer was full.
+    dropped: u64,
+}
+
+#[derive(Debug, CopyGetters, Getters)]
+/// The connection to the leader of the container partition, which publishes the record batches.
+struct Producer {
+    #[getset(get)]
+    /// The `host:port` addresses of the bootstrap brokers.
+    brokers: Vec<String>,
+
+    #[getset(get)]
+    /// The topic the records get published to.
+    topic: String,
+
+    #[getset(get)]
+    /// The full container identifier, which is the key of every record.
+    container_id: String,
+
+    #[getset(get)]
+    /// The client ID sent with every request.
+    client_id: String,
+
+    #[getset(get_copy)]
+    /// The required acknowledgements of the produce requests, where `0` does not wait for any.
+    acks: i16,
+
+    /// The TLS configuration, if the connections are encrypted.
+    tls: Option<Arc<ClientConfig>>,
+
+    /// The SASL PLAIN username and password, if the connections are authenticated.
+    sasl: Option<(String, String)>,
+
+    /// Connection to the leader of the container partition.
+    connection: Option<Connection>,
+
+    #[getset(get_copy)]
+    /// The partition of the container, known after the first connection.
+    partition: Option<i32>,
+
+    /// The backoff of connection attempts after the connection got lost.
+    backoff: Backoff,
+}
+
+#[derive(Debug)]
+/// A connection to a single broker.
+struct Connection {
+    /// The `host:port` address of the broker.
+    address: String,
+
+    /// The underlying stream.
+    stream: Stream,
+
+    /// The correlation ID of the next request.
+    correlation_id: i32,
+}
+
+#[derive(Debug)]
+enum Stream {
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+#[derive(Debug)]
+/// A single log line to be published.
+struct Record {
+    pipe: Pipe,
+    timestamp: i64,
+    line: Vec<u8>,
+}
+
+impl KafkaLogger {
+    const ERR_UNINITIALIZED: &'static str = "logger not initialized";
+
+    /// The maximum amount of buffered records, further records get dropped.
+    const BUFFER_SIZE: usize = 10 * Producer::BATCH_SIZE;
+
+    /// Create a new Kafka logger instance for the comma separated bootstrap brokers in
+    /// `address`, which is prefixed by either `tcp://` or `tls://`. The `kafka-topic` option is
+    /// required, further supported options are `kafka-acks` (`0`, `1` or `all`),
+    /// `kafka-client-id`, `kafka-capath` to trust a custom CA instead of the system roots and
+    /// `kafka-sasl-username` together with `kafka-sasl-password` for SASL PLAIN authentication.
+    pub fn new(
+        container_id: &str,
+        address: &str,
+        options: &[(String, String)],
+    ) -> Result<KafkaLogger> {
+        let (encrypted, address) = if let Some(address) = address.strip_prefix("tcp://") {
+            (false, address)
+        } else if let Some(address) = address.strip_prefix("tls://") {
+            (true, address)
+        } else {
+            bail!("unsupported Kafka address: {}", address)
+        };
+        let brokers = address
+            .split(',')
+            .map(|broker| match broker.trim().rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                    Ok(broker.trim().to_string())
+                }
+                _ => bail!("Kafka broker address requires host and port: {}", broker),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut topic = None;
+        let mut acks = 1;
+        let mut client_id = Producer::DEFAULT_CLIENT_ID.to_string();
+        let mut ca_path = None;
+        let mut username = None;
+        let mut password = None;
+        for (key, value) in options {
+            match key.as_str() {
+                "kafka-topic" => topic = Some(value.clone()),
+                "kafka-acks" => {
+                    acks = match value.as_str() {
+                        "0" => 0,
+                        "1" => 1,
+                        "all" | "-1" => -1,
+                        x => bail!("unsupported Kafka acks: {}", x),
+                    }
+                }
+                "kafka-client-id" => client_id = value.clone(),
+                "kafka-capath" => ca_path = Some(value.as_str()),
+                "kafka-sasl-username" => username = Some(value.clone()),
+                "kafka-sasl-password" => password = Some(value.clone()),
+                x => bail!("unsupported Kafka option: {}", x),
+            }
+        }
+
+        let topic = match topic {
+            Some(topic) if !topic.is_empty() => topic,
+            _ => bail!("Kafka log driver requires the kafka-topic option"),
+        };
+        let sasl = match (username, password) {
+            (Some(username), Some(password)) => Some((username, password)),
+            (None, None) => None,
+            _ => bail!("Kafka SASL authentication requires both username and password"),
+        };
+        let tls = if encrypted {
+            Some(Arc::new(Producer::tls_config(ca_path)?))
+        } else if ca_path.is_some() {
+            bail!("Kafka option kafka-capath requires a tls:// address")
+        } else {
+            None
+        };
+
+        Ok(Self {
+            producer: Some(Producer {
+                brokers,
+                topic,
+                container_id: container_id.into(),
+                client_id,
+                acks,
+                tls,
+                sasl,
+                connection: None,
+                partition: None,
+                backoff: Backoff::default(),
+            }),
+            sender: None,
+            task: None,
+            dropped: 0,
+        })
+    }
+
+    /// Asynchronously initialize the Kafka logger by connecting to the leader of the container
+    /// partition and starting the batching task.
+    pub async fn init(&mut self) -> Result<()> {
+        debug!("Initializing Kafka logger");
+        self.connect().await?;
+        self.start()
+    }
+
+    /// Connect the producer to the leader of the container partition.
+    async fn connect(&mut self) -> Result<()> {
+        let producer = self
+            .producer
+            .as_mut()
+            .context("Kafka batching task already running")?;
+        producer.connection = Some(producer.connect().await?);
+        producer.backoff.connected("Kafka records");
+        Ok(())
+    }
+
+    /// Start the batching task, which owns the producer until the logger gets closed.
+    fn start(&mut self) -> Result<()> {
+        let producer = self.producer.take().context(Self::ERR_UNINITIALIZED)?;
+        debug!("Publishing records to Kafka topic {}", producer.topic());
+        let (sender, receiver) = mpsc::channel(Self::BUFFER_SIZE);
+        self.sender = Some(sender);
+        self.task = Some(task::spawn(producer.publish_records(receiver)));
+        Ok(())
+    }
+
+    /// Write the contents of the provided reader as records, one per line. Records get dropped
+    /// if the buffer is full, for example because Kafka is unreachable.
+    pub async fn write<T>(&mut self, pipe: Pipe, bytes: T) -> Result<()>
+    where
+        T: AsyncBufRead + Unpin,
+    {
+        let timestamp = i64::try_from(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .context("get time since unix epoch")?
+                .as_millis(),
+        )
+        .context("convert timestamp")?;
+        let mut reader = BufReader::new(bytes);
+        loop {
+            let mut line = vec![];
+            let read = reader
+                .read_until(b'\n', &mut line)
+                .await
+                .context("read log line")?;
+            if read == 0 {
+                break;
+            }
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+
+            let len = line.len();
+            let record = Record {
+                pipe,
+                timestamp,
+                line,
+            };
+            match self
+                .sender
+                .as_ref()
+                .context(Self::ERR_UNINITIALIZED)?
+                .try_send(record)
+            {
+                Ok(()) => trace!("Buffered Kafka record of length {}", len),
+                Err(TrySendError::Full(_)) => {
+                    if self.dropped == 0 {
+                        warn!("Kafka record buffer is full, dropping records");
+                    }
+                    self.dropped += 1;
+                }
+                Err(TrySendError::Closed(_)) => bail!("Kafka batching task stopped"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Publish all buffered records and reconnect to the leader of the container partition,
+    /// which may have changed.
+    pub async fn reopen(&mut self) -> Result<()> {
+        debug!("Reopen Kafka logger");
+        self.close().await?;
+
+        // Records get published with backoff if the leader is not reachable right now.
+        let connected = self.connect().await;
+        self.start()?;
+        connected
+    }
+
+    /// Publish all buffered records and stop the batching task.
+    pub async fn close(&mut self) -> Result<()> {
+        self.sender = None;
+        if let Some(task) = self.task.take() {
+            self.producer = Some(task.await.context("wait for Kafka batching task")?);
+        }
+        Ok(())
+    }
+}
+
+impl Producer {
+    /// The timeout of a single request including the connection setup.
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// The default client ID.
+    const DEFAULT_CLIENT_ID: &'static str = "conmon-rs";
+
+    /// The produce API key and the used version.
+    const API_PRODUCE: (i16, i16) = (0, 3);
+
+    /// The metadata API key and the used version.
+    const API_METADATA: (i16, i16) = (3, 1);
+
+    /// The SASL handshake API key and the used version.
+    const API_SASL_HANDSHAKE: (i16, i16) = (17, 1);
+
+    /// The SASL authenticate API key and the used version.
+    const API_SASL_AUTHENTICATE: (i16, i16) = (36, 0);
+
+    /// The maximum amount of records per record batch.
+    const BATCH_SIZE: usize = 1000;
+
+    /// The interval in which buffered records get published.
+    const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Batch the received records and publish them once the batch is full, the publish interval
+    /// elapsed or the sender got dropped, which returns the producer for a later restart.
+    async fn publish_records(mut self, mut receiver: mpsc::Receiver<Record>) -> Self {
+        let mut batch = vec![];
+        let mut interval = time::interval(Self::PUBLISH_INTERVAL);
+        loop {
+            let closed = tokio::select! {
+                record = receiver.recv() => match record {
+                    Some(record) => {
+                        batch.push(record);
+                        if batch.len() < Self::BATCH_SIZE {
+                            continue;
+                        }
+                        false
+                    }
+                    None => true,
+                },
+                _ = interval.tick() => false,
+            };
+            if !batch.is_empty() {
+                self.publish(&batch).await;
+                batch.clear();
+            }
+            if closed {
+                return self;
+            }
+        }
+    }
+
+    /// Publish the records as a single record batch. If the connection is lost, then it gets
+    /// reestablished with exponential backoff, while the records are dropped in the meantime.
+    async fn publish(&mut self, records: &[Record]) {
+        let count = records.len() as u64;
+        if self.connection.is_none() && !self.reconnect().await {
+            self.backoff.drop_records(count);
+            return;
+        }
+        let batch = match self.record_batch(records) {
+            Ok(batch) => batch,
+            Err(e) => {
+                error!("Unable to encode Kafka records: {:#}", e);
+                self.backoff.drop_records(count);
+                return;
+            }
+        };
+        if let Err(e) = self.produce(&batch).await {
+            warn!("Unable to publish records to Kafka: {:#}", e);
+            self.connection = None;
+            if !self.reconnect().await || self.produce(&batch).await.is_err() {
+                self.connection = None;
+                self.backoff.drop_records(count);
+                return;
+            }
+        }
+        trace!("Published {} Kafka records", count);
+    }
+
+    /// Try to reconnect if the backoff delay elapsed and returns whether it succeeded.
+    async fn reconnect(&mut self) -> bool {
+        if !self.backoff.ready() {
+            return false;
+        }
+        match self.connect().await {
+            Ok(connection) => {
+                debug!("Reconnected to Kafka broker {}", connection.address);
+                self.backoff.connected("Kafka records");
+                self.connection = Some(connection);
+                true
+            }
+            Err(e) => {
+                self.backoff.failed("Kafka", &e);
+                false
+            }
+        }
+    }
+
+    /// Connect to the first available bootstrap broker, look up the leader of the container
+    /// partition and connect to it.
+    async fn connect(&mut self) -> Result<Connection> {
+        let mut last_error = None;
+        for broker in self.brokers().clone() {
+            match self.connect_leader(&broker).await {
+                Ok(connection) => return Ok(connection),
+                Err(e) => {
+                    debug!("Unable to use Kafka broker {}: {:#}", broker, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow!("no Kafka brokers")))
+    }
+
+    async fn connect_leader(&mut self, broker: &str) -> Result<Connection> {
+        let mut connection = self.open(broker).await?;
+        let (partitions, leaders) = self.metadata(&mut connection).await?;
+        let partition = Self::select_partition(self.container_id(), partitions);
+        let leader = leaders
+            .into_iter()
+            .find(|(x, _)| *x == partition)
+            .and_then(|(_, leader)| leader)
+            .context(format!("no leader for partition {}", partition))?;
+        self.partition = Some(partition);
+        if leader == connection.address {
+            return Ok(connection);
+        }
+        self.open(&leader).await
+    }
+
+    /// Open an authenticated connection to the broker at `address`.
+    async fn open(&self, address: &str) -> Result<Connection> {
+        let stream = time::timeout(Self::REQUEST_TIMEOUT, TcpStream::connect(address))
+            .await
+            .context("timeout")
+            .and_then(|x| x.map_err(Into::into))
+            .context(format!("connect to Kafka broker {}", address))?;
+        let stream = match &self.tls {
+            None => Stream::Tcp(stream),
+            Some(config) => {
+                let host = address
+                    .rsplit_once(':')
+                    .map(|(host, _)| host)
+                    .unwrap_or(address)
+                    .trim_start_matches('[')
+                    .trim_end_matches(']');
+                let server_name = ServerName::try_from(host)
+                    .context(format!("invalid TLS server name '{}'", host))?;
+                Stream::Tls(Box::new(
+                    TlsConnector::from(config.clone())
+                        .connect(server_name, stream)
+                        .await
+                        .context("establish TLS connection")?,
+                ))
+            }
+        };
+        let mut connection = Connection {
+            address: address.into(),
+            stream,
+            correlation_id: 0,
+        };
+        if let Some((username, password)) = &self.sasl {
+            self.authenticate(&mut connection, username, password)
+                .await
+                .context("SASL authentication")?;
+        }
+        Ok(connection)
+    }
+
+    /// Authenticate via SASL PLAIN.
+    async fn authenticate(
+        &self,
+        connection: &mut Connection,
+        username: &str,
+        password: &str,
+    ) -> Result<()> {
+        let mut body = vec![];
+        body.put_string("PLAIN")?;
+        let response = self
+            .request(connection, Self::API_SASL_HANDSHAKE, &body)
+            .await?
+            .context("no handshake response")?;
+        let mut decoder = Decoder::new(&response);
+        Self::check_error(decoder.i16()?).context("SASL handshake")?;
+
+        let mut body = vec![];
+        body.put_bytes(format!("\0{}\0{}", username, password).as_bytes())?;
+        let response = self
+            .request(connection, Self::API_SASL_AUTHENTICATE, &body)
+            .await?
+            .context("no authenticate response")?;
+        let mut decoder = Decoder::new(&response);
+        let error_code = decoder.i16()?;
+        if error_code != 0 {
+            bail!(
+                "Kafka error code {}: {}",
+                error_code,
+                decoder.nullable_string()?.unwrap_or_default()
+            )
+        }
+        Ok(())
+    }
+
+    /// Request the partitions of the topic, which returns their count and leader addresses.
+    async fn metadata(
+        &self,
+        connection: &mut Connection,
+    ) -> Result<(i32, Vec<(i32, Option<String>)>)> {
+        let mut body = vec![];
+        body.put_i32(1);
+        body.put_string(self.topic())?;
+        let response = self
+            .request(connection, Self::API_METADATA, &body)
+            .await?
+            .context("no metadata response")?;
+
+        let mut decoder = Decoder::new(&response);
+        let mut brokers = vec![];
+        for _ in 0..decoder.array_len()? {
+            let node_id = decoder.i32()?;
+            let host = decoder.string()?;
+            let port = decoder.i32()?;
+            decoder.nullable_string()?;
+            brokers.push((node_id, format!("{}:{}", host, port)));
+        }
+        decoder.i32()?;
+
+        for _ in 0..decoder.array_len()? {
+            let error_code = decoder.i16()?;
+            let name = decoder.string()?;
+            decoder.i8()?;
+            let mut partitions = vec![];
+            for _ in 0..decoder.array_len()? {
+                decoder.i16()?;
+                let index = decoder.i32()?;
+                let leader_id = decoder.i32()?;
+                for _ in 0..decoder.array_len()? {
+                    decoder.i32()?;
+                }
+                for _ in 0..decoder.array_len()? {
+                    decoder.i32()?;
+                }
+                let leader = brokers
+                    .iter()
+                    .find(|(node_id, _)| *node_id == leader_id)
+                    .map(|(_, address)| address.clone());
+                partitions.push((index, leader));
+            }
+            if name != *self.topic() {
+                continue;
+            }
+            Self::check_error(error_code).context(format!("get topic '{}'", name))?;
+            if partitions.is_empty() {
+                bail!("topic '{}' has no partitions", name)
+            }
+            let count = i32::try_from(partitions.len()).context("convert partition count")?;
+            return Ok((count, partitions));
+        }
+        bail!("topic '{}' not found", self.topic())
+    }
+
+    /// Publish the record batch to the container partition.
+    async fn produce(&mut self, batch: &[u8]) -> Result<()> {
+        let partition = self.partition().context("no partition")?;
+        let mut body = vec![];
+        body.put_i16(-1);
+        body.put_i16(self.acks());
+        body.put_i32(Self::REQUEST_TIMEOUT.as_millis() as i32);
+        body.put_i32(1);
+        body.put_string(self.topic())?;
+        body.put_i32(1);
+        body.put_i32(partition);
+        body.put_bytes(batch)?;
+
+        let mut connection = self.connection.take().context("not connected")?;
+        let response = self
+            .request(&mut connection, Self::API_PRODUCE, &body)
+            .await?;
+        self.connection = Some(connection);
+        let response = match response {
+            Some(response) => response,
+            None => return Ok(()),
+        };
+
+        let mut decoder = Decoder::new(&response);
+        for _ in 0..decoder.array_len()? {
+            decoder.string()?;
+            for _ in 0..decoder.array_len()? {
+                let index = decoder.i32()?;
+                let error_code = decoder.i16()?;
+                decoder.i64()?;
+                decoder.i64()?;
+                if index == partition {
+                    return Self::check_error(error_code).context("produce records");
+                }
+            }
+        }
+        bail!("no produce response for partition {}", partition)
+    }
+
+    /// Send a request and return the response body, which is `None` for produce requests
+    /// without acknowledgements.
+    async fn request(
+        &self,
+        connection: &mut Connection,
+        (api_key, api_version): (i16, i16),
+        body: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let correlation_id = connection.correlation_id;
+        connection.correlation_id = connection.correlation_id.wrapping_add(1);
+
+        let mut request = vec![0; 4];
+        request.put_i16(api_key);
+        request.put_i16(api_version);
+        request.put_i32(correlation_id);
+        request.put_string(self.client_id())?;
+        request.extend_from_slice(body);
+        let len = i32::try_from(request.len() - 4).context("Kafka request too large")?;
+        request[..4].copy_from_slice(&len.to_be_bytes());
+
+        let expect_response = (api_key, api_version) != Self::API_PRODUCE || self.acks() != 0;
+        time::timeout(
+            Self::REQUEST_TIMEOUT,
+            connection.round_trip(&request, expect_response),
+        )
+        .await
+        .context("timeout")?
+        .map(|response| response.map(|x| x[4..].to_vec()))
+        .context(format!(
+            "Kafka request {} to {}",
+            api_key, connection.address
+        ))
+    }
+
+    /// Serialize the records into a version 2 record batch.
+    fn record_batch(&self, records: &[Record]) -> Result<Vec<u8>> {
+        let first_timestamp = records.first().map(|x| x.timestamp).unwrap_or_default();
+        let max_timestamp = records
+            .iter()
+            .map(|x| x.timestamp)
+            .max()
+            .unwrap_or_default();
+
+        let mut body = vec![];
+        for (offset_delta, record) in records.iter().enumerate() {
+            let mut encoded = vec![0];
+            encoded.put_varint(record.timestamp - first_timestamp);
+            encoded.put_varint(offset_delta as i64);
+            encoded.put_varint(self.container_id().len() as i64);
+            encoded.extend_from_slice(self.container_id().as_bytes());
+            encoded.put_varint(record.line.len() as i64);
+            encoded.extend_from_slice(&record.line);
+            encoded.put_varint(1);
+            encoded.put_varint(6);
+            encoded.extend_from_slice(b"stream");
+            let stream = record.pipe.to_string();
+            encoded.put_varint(stream.len() as i64);
+            encoded.extend_from_slice(stream.as_bytes());
+
+            body.put_varint(encoded.len() as i64);
+            body.extend_from_slice(&encoded);
+        }
+
+        // The part of the batch covered by the checksum, which starts at the attributes
+        let mut checked = vec![];
+        checked.put_i16(0);
+        checked.put_i32(i32::try_from(records.len()).context("too many records")? - 1);
+        checked.put_i64(first_timestamp);
+        checked.put_i64(max_timestamp);
+        checked.put_i64(-1);
+        checked.put_i16(-1);
+        checked.put_i32(-1);
+        checked.put_i32(records.len() as i32);
+        checked.extend_from_slice(&body);
+
+        let mut batch = vec![];
+        batch.put_i64(0);
+        batch.put_i32(i32::try_from(checked.len() + 9).context("record batch too large")?);
+        batch.put_i32(-1);
+        batch.push(2);
+        batch.extend_from_slice(&crc32c(&checked).to_be_bytes());
+        batch.extend_from_slice(&checked);
+        Ok(batch)
+    }
+
+    /// Select the partition for the key like the Kafka default partitioner.
+    fn select_partition(key: &str, partitions: i32) -> i32 {
+        ((murmur2(key.as_bytes()) & 0x7fff_ffff) % partitions as u32) as i32
+    }
+
+    fn check_error(error_code: i16) -> Result<()> {
+        if error_code != 0 {
+            bail!("Kafka error code {}", error_code)
+        }
+        Ok(())
+    }
+
+    /// Build the TLS client configuration, which trusts either the CA at `ca_path` or the
+    /// bundled web PKI roots.
+    fn tls_config(ca_path: Option<&str>) -> Result<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        match ca_path {
+            Some(path) => {
+                let mut reader = StdBufReader::new(
+                    File::open(path).context(format!("open Kafka CA '{}'", path))?,
+                );
+                let certs = rustls_pemfile::certs(&mut reader).context("parse Kafka CA")?;
+                if certs.is_empty() {
+                    bail!("no certificates found in Kafka CA '{}'", path)
+                }
+                for cert in certs {
+                    roots.add(&Certificate(cert)).context("add Kafka CA")?;
+                }
+            }
+            None => roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|x| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    x.subject,
+                    x.spki,
+                    x.name_constraints,
+                )
+            })),
+        }
+        Ok(rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth())
+    }
+}
+
+impl Connection {
+    /// Write the request and read the response including its verified correlation ID, if
+    /// expected.
+    async fn round_trip(&mut self, request: &[u8], response: bool) -> Result<Option<Vec<u8>>> {
+        match &mut self.stream {
+            Stream::Tcp(stream) => Self::exchange(stream, request, response).await,
+            Stream::Tls(stream) => Self::exchange(stream, request, response).await,
+        }
+    }
+
+    async fn exchange<T>(stream: &mut T, request: &[u8], response: bool) -> Result<Option<Vec<u8>>>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        stream.write_all(request).await?;
+        stream.flush().await?;
+        if !response {
+            return Ok(None);
+        }
+        let mut len = [0; 4];
+        stream.read_exact(&mut len).await?;
+        let len = usize::try_from(i32::from_be_bytes(len)).context("invalid response length")?;
+        let mut response = vec![0; len];
+        stream.read_exact(&mut response).await?;
+        if response.get(..4) != request.get(8..12) {
+            bail!("unexpected correlation ID")
+        }
+        Ok(Some(response))
+    }
+}
+
+/// Encoding of the Kafka protocol primitives.
+trait Encoder {
+    fn put_i16(&mut self, value: i16);
+    fn put_i32(&mut self, value: i32);
+    fn put_i64(&mut self, value: i64);
+    fn put_string(&mut self, value: &str) -> Result<()>;
+    fn put_bytes(&mut self, value: &[u8]) -> Result<()>;
+    fn put_varint(&mut self, value: i64);
+}
+
+impl Encoder for Vec<u8> {
+    fn put_i16(&mut self, value: i16) {
+        self.extend_from_slice(&value.to_be_bytes())
+    }
+
+    fn put_i32(&mut self, value: i32) {
+        self.extend_from_slice(&value.to_be_bytes())
+    }
+
+    fn put_i64(&mut self, value: i64) {
+        self.extend_from_slice(&value.to_be_bytes())
+    }
+
+    fn put_string(&mut self, value: &str) -> Result<()> {
+        self.put_i16(i16::try_from(value.len()).context("string too long")?);
+        self.extend_from_slice(value.as_bytes());
+        Ok(())
+    }
+
+    fn put_bytes(&mut self, value: &[u8]) -> Result<()> {
+        self.put_i32(i32::try_from(value.len()).context("bytes too long")?);
+        self.extend_from_slice(value);
+        Ok(())
+    }
+
+    /// Zigzag encoded variable length integer.
+    fn put_varint(&mut self, value: i64) {
+        let mut value = ((value << 1) ^ (value >> 63)) as u64;
+        while value >= 0x80 {
+            self.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        self.push(value as u8);
+    }
+}
+
+/// Decoding of the Kafka protocol primitives.
+struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.buf.len() < len {
+            bail!("truncated Kafka response")
+        }
+        let (value, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(value)
+    }
+
+    fn i8(&mut self) -> Result<i8> {
+        Ok(self.take(1)?[0] as i8)
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(i16::from_be_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn i64(&mut self) -> Result<i64> {
+        Ok(i64::from_be_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn array_len(&mut self) -> Result<usize> {
+        Ok(usize::try_from(self.i32()?).unwrap_or_default())
+    }
+
+    fn nullable_string(&mut self) -> Result<Option<String>> {
+        let len = match usize::try_from(self.i16()?) {
+            Ok(len) => len,
+            Err(_) => return Ok(None),
+        };
+        Ok(Some(String::from_utf8_lossy(self.take(len)?).into()))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.nullable_string()?.context("unexpected null string")
+    }
+}
+
+/// The murmur2 hash as used by the Kafka default partitioner.
+fn murmur2(data: &[u8]) -> u32 {
+    const SEED: u32 = 0x9747_b28c;
+    const M: u32 = 0x5bd1_e995;
+    const R: u32 = 24;
+
+    let mut h = SEED ^ data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M);
+        h ^= k;
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, byte) in rest.iter().enumerate().rev() {
+            h ^= u32::from(*byte) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h
+}
+
+/// The CRC-32C (Castagnoli) checksum of record batches.
+fn crc32c(data: &[u8]) -> u32 {
+    const POLYNOMIAL: u32 = 0x82f6_3b78;
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const ID: &str = "0123456789abcdef";
+    const TOPIC: &str = "logs";
+
+    /// A decoded record consisting of the key, value and stream header.
+    type DecodedRecord = (String, String, String);
+
+    fn get_varint(decoder: &mut Decoder) -> Result<i64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = decoder.take(1)?[0];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn get_varbytes(decoder: &mut Decoder) -> Result<String> {
+        let len = get_varint(decoder)? as usize;
+        Ok(String::from_utf8(decoder.take(len)?.to_vec())?)
+    }
+
+    /// Decode the records of a record batch after verifying its checksum.
+    fn decode_batch(batch: &[u8]) -> Result<Vec<DecodedRecord>> {
+        let mut decoder = Decoder::new(batch);
+        assert_eq!(decoder.i64()?, 0);
+        assert_eq!(decoder.i32()? as usize, batch.len() - 12);
+        decoder.i32()?;
+        assert_eq!(decoder.i8()?, 2);
+        let crc = decoder.i32()? as u32;
+        assert_eq!(crc32c(decoder.buf), crc);
+        decoder.take(36)?;
+        let mut records = vec![];
+        for _ in 0..decoder.i32()? {
+            get_varint(&mut decoder)?;
+            decoder.i8()?;
+            get_varint(&mut decoder)?;
+            get_varint(&mut decoder)?;
+            let key = get_varbytes(&mut decoder)?;
+            let value = get_varbytes(&mut decoder)?;
+            assert_eq!(get_varint(&mut decoder)?, 1);
+            assert_eq!(get_varbytes(&mut decoder)?, "stream");
+            let stream = get_varbytes(&mut decoder)?;
+            records.push((key, value, stream));
+        }
+        Ok(records)
+    }
+
+    /// Serve a single connection of a broker with three partitions, which answers metadata,
+    /// SASL and produce requests and returns the published records per partition.
+    async fn serve(listener: TcpListener) -> Result<Vec<(i32, Vec<DecodedRecord>)>> {
+        let address = listener.local_addr()?;
+        let (mut stream, _) = listener.accept().await?;
+        let mut produced = vec![];
+        loop {
+            let mut len = [0; 4];
+            if stream.read_exact(&mut len).await.is_err() {
+                return Ok(produced);
+            }
+            let mut request = vec![0; i32::from_be_bytes(len) as usize];
+            stream.read_exact(&mut request).await?;
+            let mut decoder = Decoder::new(&request);
+            let api_key = decoder.i16()?;
+            decoder.i16()?;
+            let correlation_id = decoder.i32()?;
+            decoder.string()?;
+
+            let mut response = vec![];
+            response.put_i32(correlation_id);
+            match api_key {
+                3 => {
+                    response.put_i32(1);
+                    response.put_i32(0);
+                    response.put_string(&address.ip().to_string())?;
+                    response.put_i32(address.port().into());
+                    response.put_i16(-1);
+                    response.put_i32(0);
+                    response.put_i32(1);
+                    response.put_i16(0);
+                    response.put_string(TOPIC)?;
+                    response.push(0);
+                    response.put_i32(3);
+                    for partition in 0..3 {
+                        response.put_i16(0);
+                        response.put_i32(partition);
+                        response.put_i32(0);
+                        response.put_i32(0);
+                        response.put_i32(0);
+                    }
+                }
+                17 => {
+                    assert_eq!(decoder.string()?, "PLAIN");
+                    response.put_i16(0);
+                    response.put_i32(0);
+                }
+                36 => {
+                    let len = decoder.i32()? as usize;
+                    let error_code = if decoder.take(len)? == b"\0user\0pass" {
+                        0
+                    } else {
+                        58
+                    };
+                    response.put_i16(error_code);
+                    response.put_i16(-1);
+                    response.put_i32(0);
+                }
+                0 => {
+                    decoder.i16()?;
+                    decoder.i16()?;
+                    decoder.i32()?;
+                    decoder.i32()?;
+                    assert_eq!(decoder.string()?, TOPIC);
+                    decoder.i32()?;
+                    let partition = decoder.i32()?;
+                    let len = decoder.i32()? as usize;
+                    produced.push((partition, decode_batch(decoder.take(len)?)?));
+                    response.put_i32(1);
+                    response.put_string(TOPIC)?;
+                    response.put_i32(1);
+                    response.put_i32(partition);
+                    response.put_i16(0);
+                    response.put_i64(0);
+                    response.put_i64(-1);
+                    response.put_i32(0);
+                }
+                x => bail!("unexpected API key {}", x),
+            }
+            let mut framed = vec![];
+            framed.put_bytes(&response)?;
+            stream.write_all(&framed).await?;
+        }
+    }
+
+    fn options(extra: &[(&str, &str)]) -> Vec<(String, String)> {
+        [("kafka-topic", TOPIC)]
+            .iter()
+            .chain(extra)
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn write() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = format!("tcp://{}", listener.local_addr()?);
+        let server = tokio::spawn(serve(listener));
+        let mut sut = KafkaLogger::new(ID, &address, &options(&[]))?;
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, "a\nb\n".as_bytes()).await?;
+        sut.write(Pipe::StdErr, "c".as_bytes()).await?;
+        sut.close().await?;
+        drop(sut);
+
+        // The batching task may publish the records in one or more batches.
+        let produced = server.await??;
+        let partition = Producer::select_partition(ID, 3);
+        assert!(produced.iter().all(|(x, _)| *x == partition));
+        assert_eq!(
+            produced
+                .into_iter()
+                .flat_map(|(_, records)| records)
+                .collect::<Vec<_>>(),
+            vec![
+                (ID.into(), "a".into(), "stdout".into()),
+                (ID.into(), "b".into(), "stdout".into()),
+                (ID.into(), "c".into(), "stderr".into()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_sasl() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = format!("tcp://{}", listener.local_addr()?);
+        let server = tokio::spawn(serve(listener));
+        let options = options(&[
+            ("kafka-sasl-username", "user"),
+            ("kafka-sasl-password", "pass"),
+        ]);
+        let mut sut = KafkaLogger::new(ID, &address, &options)?;
+        sut.init().await?;
+        sut.write(Pipe::StdOut, "a\n".as_bytes()).await?;
+        sut.close().await?;
+        drop(sut);
+
+        assert_eq!(server.await??.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sasl_failure() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = format!("tcp://{}", listener.local_addr()?);
+        let server = tokio::spawn(serve(listener));
+        let options = options(&[
+            ("kafka-sasl-username", "user"),
+            ("kafka-sasl-password", "wrong"),
+        ]);
+        let mut sut = KafkaLogger::new(ID, &address, &options)?;
+        assert!(sut.init().await.is_err());
+        drop(sut);
+        assert!(server.await??.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reconnect() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let local_addr = listener.local_addr()?;
+        let server = tokio::spawn(serve(listener));
+        let mut sut = KafkaLogger::new(ID, &format!("tcp://{}", local_addr), &options(&[]))?
+            .producer
+            .context("no producer")?;
+        sut.connection = Some(sut.connect().await?);
+        let record = |line: &str| Record {
+            pipe: Pipe::StdOut,
+            timestamp: 0,
+            line: line.into(),
+        };
+
+        // The broker is unavailable, which drops the records
+        server.abort();
+        sut.connection = None;
+        sut.publish(&[record("a")]).await;
+        assert_eq!(sut.backoff.dropped(), 1);
+        assert!(!sut.backoff.ready());
+
+        let server = tokio::spawn(serve(TcpListener::bind(local_addr).await?));
+        sut.backoff.expire();
+        sut.publish(&[record("b")]).await;
+        drop(sut);
+
+        let produced = server.await??;
+        assert_eq!(produced.len(), 1);
+        assert_eq!(produced[0].1[0].1, "b");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_buffer_full() -> Result<()> {
+        let mut sut = KafkaLogger::new(ID, "tcp://localhost:9092", &options(&[]))?;
+        assert!(sut.write(Pipe::StdOut, "a\n".as_bytes()).await.is_err());
+
+        let (sender, mut receiver) = mpsc::channel(1);
+        sut.sender = Some(sender);
+        sut.write(Pipe::StdOut, "a\nb\n".as_bytes()).await?;
+        assert_eq!(sut.dropped(), 1);
+        assert_eq!(receiver.recv().await.map(|x| x.line), Some(b"a".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn murmur2_vectors() {
+        // Test vectors of the Kafka default partitioner
+        for (data, expected) in [
+            ("21", -973932308),
+            ("foobar", -790332482),
+            ("a-little-bit-long-string", -985981536),
+            ("a-little-bit-longer-string", -1486304829),
+            (
+                "lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8",
+                -58897971,
+            ),
+            ("abc", 479470107),
+        ] {
+            assert_eq!(murmur2(data.as_bytes()) as i32, expected);
+        }
+    }
+
+    #[test]
+    fn crc32c_checksum() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+    }
+
+    #[test]
+    fn varint() {
+        for (value, expected) in [
+            (0, vec![0]),
+            (-1, vec![1]),
+            (1, vec![2]),
+            (64, vec![0x80, 1]),
+            (-65, vec![0x81, 1]),
+        ] {
+            let mut buf = vec![];
+            buf.put_varint(value);
+            assert_eq!(buf, expected);
+            assert_eq!(get_varint(&mut Decoder::new(&buf)).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn invalid_config() {
+        for (address, options) in [
+            ("localhost:9092", options(&[])),
+            ("tcp://localhost", options(&[])),
+            ("tcp://localhost:9092,:9092", options(&[])),
+            ("tcp://localhost:9092", vec![]),
+            ("tcp://localhost:9092", options(&[("kafka-acks", "2")])),
+            ("tcp://localhost:9092", options(&[("kafka-unknown", "")])),
+            (
+                "tcp://localhost:9092",
+                options(&[("kafka-capath", "/ca.pem")]),
+            ),
+            (
+                "tcp://localhost:9092",
+                options(&[("kafka-sasl-username", "user")]),
+            ),
+            (
+                "tls://localhost:9092",
+                options(&[("kafka-capath", "/no/ca.pem")]),
+            ),
+        ] {
+            assert!(KafkaLogger::new(ID, address, &options).is_err());
+        }
+    }
+
+    #[test]
+    fn brokers() -> Result<()> {
+        let sut = KafkaLogger::new(ID, "tls://a:9093, b:9093", &options(&[]))?
+            .producer
+            .context("no producer")?;
+        assert_eq!(sut.brokers(), &["a:9093", "b:9093"]);
+        Ok(())
+    }
+}
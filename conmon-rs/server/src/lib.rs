@@ -1,18 +1,74 @@
 pub use server::Server;
 pub use version::Version;
 
+mod admission;
 mod attach;
+mod audit_log;
+mod auth;
+mod capacity;
+mod capnp_bridge;
+mod capabilities;
+mod cgroup_stats;
 mod child;
 mod child_reaper;
 mod config;
+mod container_filter;
 mod container_io;
+mod container_labels;
 mod container_log;
 mod cri_logger;
+mod events;
+mod extra_fd;
+mod fd_socket;
+mod file_ownership;
+mod flush_policy;
+mod gelf_logger;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod http_api;
+mod idempotency;
 mod init;
+mod journald_logger;
+mod json_file_logger;
+mod kafka_logger;
+mod lifecycle_event;
 mod listener;
+mod log_compression;
+mod log_filter;
+mod log_quota;
+mod log_sync;
+mod log_timestamp;
+mod log_transport;
+mod loki_logger;
+mod method_filter;
+mod metrics;
+mod null_logger;
 mod oom_watcher;
+mod output_buffer;
+mod passthrough_logger;
+mod plugin_logger;
+mod rate_limiter;
+mod recorder;
+mod redaction;
+mod remote_logger;
+mod restart_policy;
+mod rotation_schedule;
 mod rpc;
 mod server;
+mod splunk_logger;
+mod status;
 mod streams;
+mod syslog_logger;
+mod tag_template;
 mod terminal;
+mod terminal_mode;
+mod tls_listener;
+#[cfg(feature = "ttrpc")]
+mod ttrpc;
+#[cfg(feature = "io-uring")]
+mod uring;
+mod utf8_boundary;
 mod version;
+mod vsock_listener;
+mod worker_pool;
+mod worker_router;
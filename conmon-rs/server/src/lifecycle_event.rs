@@ -0,0 +1,72 @@
+//! Container lifecycle events, which can be recorded as marker lines in the container log.
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Available lifecycle events of a container.
+pub enum LifecycleEvent {
+    /// The container got created by the runtime.
+    Created,
+
+    /// The container got killed because it ran out of memory.
+    OomKilled,
+
+    /// The container exited with the provided exit code.
+    Exited(i32),
+
+    /// The container log got rotated.
+    Rotated,
+
+    /// A removed container log file got recreated.
+    LogRecreated,
+
+    /// The container got restarted by its restart policy for the contained amount of times.
+    Restarted(u32),
+}
+
+impl LifecycleEvent {
+    /// The prefix of every marker line, which distinguishes it from the container output.
+    const PREFIX: &'static str = "conmon-rs:";
+}
+
+impl fmt::Display for LifecycleEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} event=", Self::PREFIX)?;
+        match self {
+            Self::Created => write!(f, "created"),
+            Self::OomKilled => write!(f, "oom_killed"),
+            Self::Exited(exit_code) => write!(f, "exited exit_code={}", exit_code),
+            Self::Rotated => write!(f, "rotated"),
+            Self::LogRecreated => write!(f, "log_recreated"),
+            Self::Restarted(restarts) => write!(f, "restarted restart_count={}", restarts),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        for (event, expected) in [
+            (LifecycleEvent::Created, "conmon-rs: event=created"),
+            (LifecycleEvent::OomKilled, "conmon-rs: event=oom_killed"),
+            (
+                LifecycleEvent::Exited(137),
+                "conmon-rs: event=exited exit_code=137",
+            ),
+            (LifecycleEvent::Rotated, "conmon-rs: event=rotated"),
+            (
+                LifecycleEvent::LogRecreated,
+                "conmon-rs: event=log_recreated",
+            ),
+            (
+                LifecycleEvent::Restarted(2),
+                "conmon-rs: event=restarted restart_count=2",
+            ),
+        ] {
+            assert_eq!(event.to_string(), expected);
+        }
+    }
+}
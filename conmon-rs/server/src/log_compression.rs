@@ -0,0 +1,194 @@
+//! Compression of rotated container logs.
+
+use crate::file_ownership::FileOwnership;
+use anyhow::{Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use getset::CopyGetters;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+use tokio::task::{self, JoinHandle};
+use tracing::{debug, error};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Available compression codecs.
+pub enum Codec {
+    Gzip,
+    Zstd,
+}
+
+impl Codec {
+    /// All available codecs.
+    pub const ALL: &'static [Codec] = &[Codec::Gzip, Codec::Zstd];
+
+    /// The file extension of the codec.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Codec::Gzip => "gz",
+            Codec::Zstd => "zst",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, CopyGetters)]
+#[getset(get_copy = "pub")]
+/// The compression applied to rotated log files.
+pub struct LogCompression {
+    /// The used codec.
+    codec: Codec,
+
+    /// The compression level, where 0 selects the default of the codec.
+    level: i32,
+}
+
+impl LogCompression {
+    /// Create a new log compression for the provided codec and level.
+    pub fn new(codec: Codec, level: i32) -> Self {
+        Self { codec, level }
+    }
+
+    /// Asynchronously compress the file at `path` into a file with the extension of the codec
+    /// appended and remove the original afterwards. Errors are logged, because the compression
+    /// does not affect the container output.
+    pub fn spawn(self, path: PathBuf) -> JoinHandle<()> {
+        task::spawn_blocking(move || {
+            if let Err(e) = self.compress(&path) {
+                error!("Unable to compress log file {}: {:#}", path.display(), e)
+            }
+        })
+    }
+
+    /// Compress the file at `path` and remove it afterwards.
+    fn compress(&self, path: &Path) -> Result<()> {
+        let target = Self::compressed_path(path, self.codec());
+        debug!("Compressing log {} to {}", path.display(), target.display());
+
+        let mut reader = BufReader::new(
+            File::open(path).context(format!("open log file '{}'", path.display()))?,
+        );
+        let writer = BufWriter::new(
+            File::create(&target).context(format!("create log file '{}'", target.display()))?,
+        );
+        FileOwnership::copy(reader.get_ref(), writer.get_ref())
+            .context("copy log file ownership")?;
+
+        match self.codec() {
+            Codec::Gzip => {
+                let level = match self.level() {
+                    0 => Compression::default(),
+                    x => Compression::new(x.clamp(1, 9) as u32),
+                };
+                let mut encoder = GzEncoder::new(writer, level);
+                io::copy(&mut reader, &mut encoder).context("gzip log file")?;
+                encoder.finish()?.flush()?;
+            }
+            Codec::Zstd => {
+                let mut encoder =
+                    zstd::Encoder::new(writer, self.level()).context("create zstd encoder")?;
+                io::copy(&mut reader, &mut encoder).context("zstd compress log file")?;
+                encoder.finish()?.flush()?;
+            }
+        }
+
+        fs::remove_file(path).context(format!("remove log file '{}'", path.display()))
+    }
+
+    /// Read the whole decompressed content of the file at `path`, which got compressed by the
+    /// provided codec.
+    pub fn decompress(path: &Path, codec: Codec) -> Result<Vec<u8>> {
+        let reader = BufReader::new(
+            File::open(path).context(format!("open log file '{}'", path.display()))?,
+        );
+        let mut content = vec![];
+        match codec {
+            Codec::Gzip => {
+                GzDecoder::new(reader)
+                    .read_to_end(&mut content)
+                    .context("gunzip log file")?;
+            }
+            Codec::Zstd => {
+                content = zstd::decode_all(reader).context("zstd decompress log file")?;
+            }
+        }
+        Ok(content)
+    }
+
+    /// Returns the path of the compressed variant of `path`.
+    pub fn compressed_path(path: &Path, codec: Codec) -> PathBuf {
+        let mut path = path.as_os_str().to_owned();
+        path.push(".");
+        path.push(codec.extension());
+        path.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const CONTENT: &str = "line 1\nline 2\n";
+
+    async fn compress(codec: Codec, level: i32) -> Result<Vec<u8>> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log.1");
+        fs::write(&path, CONTENT)?;
+
+        LogCompression::new(codec, level)
+            .spawn(path.clone())
+            .await?;
+
+        assert!(!path.exists());
+        Ok(fs::read(LogCompression::compressed_path(&path, codec))?)
+    }
+
+    #[tokio::test]
+    async fn gzip() -> Result<()> {
+        for level in &[0, 1, 9] {
+            let compressed = compress(Codec::Gzip, *level).await?;
+            let mut res = String::new();
+            GzDecoder::new(&compressed[..]).read_to_string(&mut res)?;
+            assert_eq!(res, CONTENT);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn zstd() -> Result<()> {
+        for level in &[0, 1, 19] {
+            let compressed = compress(Codec::Zstd, *level).await?;
+            assert_eq!(zstd::decode_all(&compressed[..])?, CONTENT.as_bytes());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn decompress() -> Result<()> {
+        for codec in Codec::ALL {
+            let dir = tempdir()?;
+            let path = dir.path().join("log.1");
+            fs::write(&path, CONTENT)?;
+            LogCompression::new(*codec, 0).spawn(path.clone()).await?;
+
+            let compressed = LogCompression::compressed_path(&path, *codec);
+            assert_eq!(
+                LogCompression::decompress(&compressed, *codec)?,
+                CONTENT.as_bytes()
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn missing_file() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log.1");
+        LogCompression::new(Codec::Gzip, 0)
+            .spawn(path.clone())
+            .await?;
+        assert!(!LogCompression::compressed_path(&path, Codec::Gzip).exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+//! The filter of the server logs, which can be changed at runtime.
+
+use anyhow::{format_err, Context, Result};
+use std::{fmt, str::FromStr, sync::Mutex};
+use tracing_subscriber::{filter::Targets, reload};
+
+macro_rules! lock {
+    ($x:expr) => {
+        $x.lock().map_err(|e| format_err!("{:#}", e))?
+    };
+}
+
+/// The reloadable filter of the server logs.
+///
+/// Filters consist of comma separated directives, where every directive is either a level like
+/// `info` or a target prefix followed by a level like `conmonrs::attach=debug`.
+pub struct LogFilter {
+    reload: Box<dyn Fn(Targets) -> Result<(), reload::Error> + Send + Sync>,
+    filter: Mutex<String>,
+}
+
+impl fmt::Debug for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogFilter")
+            .field("filter", &self.filter)
+            .finish()
+    }
+}
+
+impl LogFilter {
+    /// Create a new log filter from the provided directives, which returns the filter to be
+    /// applied to the log layer as well.
+    pub fn new<S>(filter: &str) -> Result<(Self, reload::Layer<Targets, S>)>
+    where
+        S: 'static,
+    {
+        let (layer, handle) = reload::Layer::new(Self::parse(filter)?);
+        Ok((
+            Self {
+                reload: Box::new(move |targets| handle.reload(targets)),
+                filter: Mutex::new(filter.into()),
+            },
+            layer,
+        ))
+    }
+
+    /// The directives of the current filter.
+    pub fn filter(&self) -> Result<String> {
+        Ok(lock!(self.filter).clone())
+    }
+
+    /// Replace the filter by the provided directives and return the previous ones.
+    pub fn set(&self, filter: &str) -> Result<String> {
+        let targets = Self::parse(filter)?;
+        let mut current = lock!(self.filter);
+        (self.reload)(targets).context("reload log filter")?;
+        Ok(std::mem::replace(&mut current, filter.into()))
+    }
+
+    fn parse(filter: &str) -> Result<Targets> {
+        Targets::from_str(filter).context(format!("parse log filter '{}'", filter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tracing::{debug, info, Event, Subscriber};
+    use tracing_subscriber::{
+        layer::{Context as LayerContext, Layer},
+        prelude::*,
+    };
+
+    #[derive(Clone, Default)]
+    struct Counter(Arc<AtomicUsize>);
+
+    impl<S: Subscriber> Layer<S> for Counter {
+        fn on_event(&self, _: &Event<'_>, _: LayerContext<'_, S>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn set() -> Result<()> {
+        let (sut, filter) = LogFilter::new("info")?;
+        let counter = Counter::default();
+        let subscriber = tracing_subscriber::registry().with(counter.clone().with_filter(filter));
+
+        tracing::subscriber::with_default(subscriber, || -> Result<()> {
+            debug!("filtered");
+            info!("logged");
+            assert_eq!(counter.0.load(Ordering::Relaxed), 1);
+
+            assert!(sut.set("invalid=level=debug").is_err());
+            assert_eq!(sut.filter()?, "info");
+
+            assert_eq!(sut.set(&format!("warn,{}=debug", module_path!()))?, "info");
+            debug!("logged");
+            assert_eq!(counter.0.load(Ordering::Relaxed), 2);
+            Ok(())
+        })?;
+
+        assert_eq!(sut.filter()?, format!("warn,{}=debug", module_path!()));
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+//! Global disk usage quota for all container logs managed by the server.
+
+use crate::{config::LogQuotaPolicy, container_log::ContainerLog};
+use anyhow::{format_err, Result};
+use getset::CopyGetters;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{fs, task, time};
+use tracing::{debug, error, info, warn};
+
+macro_rules! lock {
+    ($x:expr) => {
+        $x.lock().map_err(|e| format_err!("{:#}", e))?
+    };
+}
+
+/// A shareable log quota.
+pub type SharedLogQuota = Arc<LogQuota>;
+
+#[derive(Debug, CopyGetters)]
+/// Tracks the disk usage of all registered container log files against a global budget. The
+/// usage gets updated periodically, while the loggers enforce the quota policy if the budget is
+/// exceeded.
+pub struct LogQuota {
+    #[getset(get_copy = "pub")]
+    /// The maximum amount of bytes of all log files, 0 means unlimited.
+    limit: u64,
+
+    #[getset(get_copy = "pub")]
+    /// The action taken if the limit is exceeded.
+    policy: LogQuotaPolicy,
+
+    /// The registered log files and their maximum amount of files including rotated ones.
+    files: Mutex<HashMap<PathBuf, usize>>,
+
+    /// The disk usage of the last update.
+    used: AtomicU64,
+
+    /// Whether the last update exceeded the limit.
+    exceeded: AtomicBool,
+
+    /// Incremented on every update exceeding the limit, which allows loggers to act once per
+    /// update.
+    generation: AtomicU64,
+
+    /// The amount of log writes dropped since the limit got exceeded.
+    dropped: AtomicU64,
+}
+
+impl LogQuota {
+    /// The interval of disk usage updates.
+    const UPDATE_INTERVAL: Duration = Duration::from_secs(10);
+
+    /// Create a new shared log quota.
+    pub fn new(limit: u64, policy: LogQuotaPolicy) -> SharedLogQuota {
+        Arc::new(Self {
+            limit,
+            policy,
+            files: Mutex::new(HashMap::new()),
+            used: AtomicU64::new(0),
+            exceeded: AtomicBool::new(false),
+            generation: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Start updating the disk usage periodically, if the quota is limited.
+    pub fn start(self: &Arc<Self>) {
+        if self.limit() == 0 {
+            return;
+        }
+        let quota = self.clone();
+        task::spawn(async move {
+            loop {
+                if let Err(e) = quota.update().await {
+                    error!("Unable to update log quota: {:#}", e);
+                }
+                time::sleep(Self::UPDATE_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Register a log file and its rotated variants.
+    pub fn register(&self, path: &Path, max_files: usize) -> Result<()> {
+        lock!(self.files).insert(path.into(), max_files);
+        Ok(())
+    }
+
+    /// Unregister a log file, for example if the container log got dropped.
+    pub fn unregister(&self, path: &Path) -> Result<()> {
+        lock!(self.files).remove(path);
+        Ok(())
+    }
+
+    /// The disk usage in bytes of the last update.
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Whether the budget is currently exceeded.
+    pub fn exceeded(&self) -> bool {
+        self.exceeded.load(Ordering::Relaxed)
+    }
+
+    /// The generation of the last update exceeding the limit.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    /// Record a log write dropped because of the exceeded quota.
+    pub fn record_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Update the disk usage of all registered log files.
+    pub async fn update(&self) -> Result<()> {
+        let files = lock!(self.files).clone();
+        let mut used = 0;
+        for (path, max_files) in files {
+            let mut paths = vec![path.clone()];
+            for i in 1..max_files.max(1) {
+                paths.extend(ContainerLog::file_variants(&ContainerLog::rotated_path(
+                    &path, i,
+                )));
+            }
+            for path in paths {
+                if let Ok(metadata) = fs::metadata(&path).await {
+                    used += metadata.len();
+                }
+            }
+        }
+        self.used.store(used, Ordering::Relaxed);
+        debug!("Log quota usage: {} of {} bytes", used, self.limit());
+
+        let exceeded = self.limit() > 0 && used > self.limit();
+        let was_exceeded = self.exceeded.swap(exceeded, Ordering::Relaxed);
+        if exceeded {
+            self.generation.fetch_add(1, Ordering::Relaxed);
+            if !was_exceeded {
+                warn!(
+                    "Log quota exceeded: {} of {} bytes used, applying policy {:?}",
+                    used,
+                    self.limit(),
+                    self.policy()
+                );
+            }
+        } else if was_exceeded {
+            info!(
+                "Log quota recovered: {} of {} bytes used, dropped {} writes",
+                used,
+                self.limit(),
+                self.dropped.swap(0, Ordering::Relaxed)
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn update() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log");
+        fs::write(&path, "a".repeat(10))?;
+        fs::write(ContainerLog::rotated_path(&path, 1), "a".repeat(20))?;
+        fs::write(dir.path().join("log.2.gz"), "a".repeat(30))?;
+        fs::write(ContainerLog::rotated_path(&path, 3), "a".repeat(40))?;
+
+        let sut = LogQuota::new(50, LogQuotaPolicy::Rotate);
+        sut.register(&path, 3)?;
+        sut.update().await?;
+        assert_eq!(sut.used(), 60);
+        assert!(sut.exceeded());
+        assert_eq!(sut.generation(), 1);
+
+        sut.update().await?;
+        assert_eq!(sut.generation(), 2);
+
+        fs::remove_file(dir.path().join("log.2.gz"))?;
+        sut.update().await?;
+        assert_eq!(sut.used(), 30);
+        assert!(!sut.exceeded());
+        assert_eq!(sut.generation(), 2);
+
+        sut.unregister(&path)?;
+        sut.update().await?;
+        assert_eq!(sut.used(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unlimited() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log");
+        fs::write(&path, "a")?;
+
+        let sut = LogQuota::new(0, LogQuotaPolicy::Drop);
+        sut.register(&path, 1)?;
+        sut.update().await?;
+        assert_eq!(sut.used(), 1);
+        assert!(!sut.exceeded());
+        Ok(())
+    }
+}
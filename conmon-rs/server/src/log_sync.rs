@@ -0,0 +1,113 @@
+//! Durability of the file based container logs.
+
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// The available policies of syncing written log entries to disk.
+pub enum SyncPolicy {
+    #[default]
+    /// Never sync explicitly and rely on the kernel to write back the page cache.
+    Never,
+
+    /// Sync once the unsynced bytes exceed `bytes` or the last sync is older than `interval`,
+    /// which is checked on every write. Zero values disable the corresponding limit.
+    Periodic { bytes: usize, interval: Duration },
+
+    /// Sync after every written log entry.
+    Line,
+}
+
+#[derive(Debug)]
+/// The state of a sync policy for a single log file.
+pub struct LogSync {
+    /// The applied policy.
+    policy: SyncPolicy,
+
+    /// The amount of bytes written since the last sync.
+    unsynced: usize,
+
+    /// The time of the last sync.
+    last_sync: Instant,
+}
+
+impl Default for LogSync {
+    fn default() -> Self {
+        Self::new(SyncPolicy::default())
+    }
+}
+
+impl LogSync {
+    /// Create a new log sync state for the provided policy.
+    pub fn new(policy: SyncPolicy) -> Self {
+        Self {
+            policy,
+            unsynced: 0,
+            last_sync: Instant::now(),
+        }
+    }
+
+    /// Account a written log entry of `bytes` length and return whether the file has to be
+    /// synced now.
+    pub fn record(&mut self, bytes: usize) -> bool {
+        self.unsynced = self.unsynced.saturating_add(bytes);
+        match self.policy {
+            SyncPolicy::Never => false,
+            SyncPolicy::Periodic { bytes, interval } => {
+                (bytes > 0 && self.unsynced >= bytes)
+                    || (!interval.is_zero() && self.last_sync.elapsed() >= interval)
+            }
+            SyncPolicy::Line => true,
+        }
+    }
+
+    /// Reset the state after the file got synced.
+    pub fn synced(&mut self) {
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn never() {
+        let mut sut = LogSync::default();
+        assert!(!sut.record(usize::MAX));
+        assert!(!sut.record(1));
+    }
+
+    #[test]
+    fn periodic_bytes() {
+        let mut sut = LogSync::new(SyncPolicy::Periodic {
+            bytes: 10,
+            interval: Duration::ZERO,
+        });
+        assert!(!sut.record(5));
+        assert!(sut.record(5));
+        sut.synced();
+        assert!(!sut.record(9));
+    }
+
+    #[test]
+    fn periodic_interval() {
+        let mut sut = LogSync::new(SyncPolicy::Periodic {
+            bytes: 0,
+            interval: Duration::from_millis(10),
+        });
+        assert!(!sut.record(1000));
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(sut.record(1));
+        sut.synced();
+        assert!(!sut.record(1));
+    }
+
+    #[test]
+    fn line() {
+        let mut sut = LogSync::new(SyncPolicy::Line);
+        assert!(sut.record(1));
+        sut.synced();
+        assert!(sut.record(0));
+    }
+}
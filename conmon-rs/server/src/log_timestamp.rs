@@ -0,0 +1,92 @@
+//! Timestamps of the file based log entries.
+
+use anyhow::{Context, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tz::{DateTime, TimeZone, UtcDateTime};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// The available formats of the log entry timestamps.
+pub enum TimestampFormat {
+    #[default]
+    /// RFC3339 with nanosecond precision, like `2022-10-11T08:01:02.123456789+02:00`.
+    Rfc3339Nano,
+
+    /// Nanoseconds since the unix epoch, like `1665468062123456789`.
+    UnixNano,
+
+    /// Omit the timestamp, for consumers which do their own timestamping.
+    None,
+}
+
+impl TimestampFormat {
+    /// Format the current time in the local timezone, or `None` if timestamps are omitted.
+    pub fn local(self) -> Result<Option<String>> {
+        match self {
+            Self::Rfc3339Nano => {
+                let local_tz = TimeZone::local().context("get local timezone")?;
+                Ok(Some(
+                    DateTime::now(local_tz.as_ref())
+                        .context("get local datetime")?
+                        .to_string(),
+                ))
+            }
+            Self::UnixNano => Self::unix_nano().map(Some),
+            Self::None => Ok(None),
+        }
+    }
+
+    /// Format the current time in UTC, or `None` if timestamps are omitted.
+    pub fn utc(self) -> Result<Option<String>> {
+        match self {
+            Self::Rfc3339Nano => Ok(Some(
+                UtcDateTime::now().context("get UTC datetime")?.to_string(),
+            )),
+            Self::UnixNano => Self::unix_nano().map(Some),
+            Self::None => Ok(None),
+        }
+    }
+
+    /// The current nanoseconds since the unix epoch.
+    fn unix_nano() -> Result<String> {
+        Ok(SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("get time since unix epoch")?
+            .as_nanos()
+            .to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+    #[test]
+    fn rfc3339_nano() -> Result<()> {
+        let local = TimestampFormat::Rfc3339Nano
+            .local()?
+            .context("no timestamp")?;
+        OffsetDateTime::parse(&local, &Rfc3339)?;
+
+        let utc = TimestampFormat::Rfc3339Nano
+            .utc()?
+            .context("no timestamp")?;
+        assert!(utc.ends_with('Z'));
+        OffsetDateTime::parse(&utc, &Rfc3339)?;
+        Ok(())
+    }
+
+    #[test]
+    fn unix_nano() -> Result<()> {
+        let timestamp = TimestampFormat::UnixNano.local()?.context("no timestamp")?;
+        assert!(timestamp.parse::<u128>()? > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn none() -> Result<()> {
+        assert!(TimestampFormat::None.local()?.is_none());
+        assert!(TimestampFormat::None.utc()?.is_none());
+        Ok(())
+    }
+}
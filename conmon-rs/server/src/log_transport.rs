@@ -0,0 +1,89 @@
+//! Transports of the log drivers sending container output to a log server.
+
+use anyhow::{bail, Result};
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Available transports to a log server.
+pub enum Transport {
+    /// A local unix datagram socket.
+    UnixDatagram(PathBuf),
+
+    /// A remote UDP address.
+    Udp(String),
+
+    /// A remote TCP address.
+    Tcp(String),
+}
+
+impl Transport {
+    /// Parse a `scheme://target` address into a transport, where the scheme has to be one of
+    /// `schemes`. Supported are `unixgram://` and `unix://` paths to unix datagram sockets as
+    /// well as `udp://` and `tcp://` addresses, which use `default_port` if they have none.
+    pub fn parse(address: &str, schemes: &[&str], default_port: u16) -> Result<Self> {
+        let (scheme, target) = match address.split_once("://") {
+            Some((scheme, target)) if schemes.contains(&scheme) => (scheme, target),
+            _ => bail!("unsupported address: {}", address),
+        };
+        let with_port = || {
+            if target.ends_with(']') || !target.contains(':') {
+                format!("{}:{}", target, default_port)
+            } else {
+                target.into()
+            }
+        };
+
+        Ok(match scheme {
+            "unixgram" | "unix" => Self::UnixDatagram(target.into()),
+            "udp" => Self::Udp(with_port()),
+            "tcp" => Self::Tcp(with_port()),
+            x => bail!("unsupported address scheme: {}", x),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMES: &[&str] = &["unix", "udp", "tcp"];
+
+    #[test]
+    fn parse() -> Result<()> {
+        assert_eq!(
+            Transport::parse("unix:///run/log", SCHEMES, 514)?,
+            Transport::UnixDatagram("/run/log".into())
+        );
+        assert_eq!(
+            Transport::parse("udp://localhost", SCHEMES, 514)?,
+            Transport::Udp("localhost:514".into())
+        );
+        assert_eq!(
+            Transport::parse("udp://localhost:1234", SCHEMES, 514)?,
+            Transport::Udp("localhost:1234".into())
+        );
+        assert_eq!(
+            Transport::parse("tcp://[::1]", SCHEMES, 514)?,
+            Transport::Tcp("[::1]:514".into())
+        );
+        assert_eq!(
+            Transport::parse("tcp://[::1]:601", SCHEMES, 514)?,
+            Transport::Tcp("[::1]:601".into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_unsupported() {
+        for address in &[
+            "",
+            "localhost",
+            "/run/log",
+            "http://localhost",
+            "unixgram:///log",
+        ] {
+            assert!(Transport::parse(address, SCHEMES, 514).is_err());
+        }
+        assert!(Transport::parse("ftp://localhost", &["ftp"], 21).is_err());
+    }
+}
@@ -0,0 +1,564 @@
+//! Grafana Loki push API logging functionalities.
+
+use crate::container_io::Pipe;
+use anyhow::{bail, Context, Result};
+use getset::{CopyGetters, Getters, Setters};
+use reqwest::{
+    header::{HeaderMap, HeaderValue},
+    Certificate, Client, Url,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    marker::Unpin,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, BufReader},
+    sync::mpsc::{self, error::TrySendError},
+    task::{self, JoinHandle},
+    time,
+};
+use tracing::{debug, error, trace, warn};
+
+#[derive(Debug, CopyGetters, Getters, Setters)]
+/// The structure used for pushing container output to Grafana Loki.
+pub struct LokiLogger {
+    #[getset(get)]
+    /// The push endpoint of Loki.
+    url: Url,
+
+    /// The HTTP client including the tenant header and TLS configuration.
+    client: Client,
+
+    #[getset(get)]
+    /// The labels of every pushed stream, except the `stream` label itself.
+    labels: BTreeMap<String, String>,
+
+    /// Sender of entries to the batching task.
+    sender: Option<mpsc::Sender<Entry>>,
+
+    /// The batching task pushing the entries to Loki.
+    task: Option<JoinHandle<()>>,
+
+    #[getset(get_copy, set)]
+    /// The initial delay between retries of failed pushes, which doubles on every retry.
+    retry_delay: Duration,
+
+    #[getset(get_copy)]
+    /// The amount of entries dropped because the buffer was full.
+    dropped: u64,
+}
+
+#[derive(Debug)]
+/// A single log line to be pushed.
+struct Entry {
+    pipe: Pipe,
+    time: String,
+    line: String,
+}
+
+impl LokiLogger {
+    const ERR_UNINITIALIZED: &'static str = "logger not initialized";
+
+    /// The path of the push endpoint relative to the Loki URL.
+    const PUSH_PATH: &'static str = "loki/api/v1/push";
+
+    /// The maximum amount of entries per push.
+    const BATCH_SIZE: usize = 1000;
+
+    /// The maximum amount of buffered entries, further entries get dropped.
+    const BUFFER_SIZE: usize = 10 * Self::BATCH_SIZE;
+
+    /// The interval in which buffered entries get pushed.
+    const PUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// The timeout of a single push.
+    const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// The maximum amount of push attempts before the entries get dropped.
+    const MAX_ATTEMPTS: usize = 5;
+
+    /// The initial delay between retries.
+    const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+    /// The maximum delay between retries.
+    const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+    /// Create a new Loki logger instance for the Loki instance at `address`. The container
+    /// metadata is added as labels with snake case names, like `pod_name` for `PodName`.
+    /// Supported options are `loki-tenant-id`, `loki-external-labels` as comma separated
+    /// `key=value` pairs, `loki-capath` and `loki-insecureskipverify`.
+    pub fn new(
+        container_name: &str,
+        address: &str,
+        tag: &str,
+        metadata: &HashMap<String, String>,
+        options: &[(String, String)],
+    ) -> Result<LokiLogger> {
+        let mut url = Url::parse(address).context(format!("parse Loki URL '{}'", address))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            bail!("unsupported Loki URL scheme: {}", url.scheme())
+        }
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+        let url = url.join(Self::PUSH_PATH).context("build Loki push URL")?;
+
+        let mut labels = BTreeMap::new();
+        for (key, value) in metadata {
+            labels.insert(Self::label_name(key), value.clone());
+        }
+        if !container_name.is_empty() {
+            labels.insert("container_name".into(), container_name.into());
+        }
+        if !tag.is_empty() {
+            labels.insert("tag".into(), tag.into());
+        }
+
+        let mut headers = HeaderMap::new();
+        let mut builder = Client::builder().timeout(Self::PUSH_TIMEOUT);
+        for (key, value) in options {
+            match key.as_str() {
+                "loki-tenant-id" => {
+                    headers.insert(
+                        "X-Scope-OrgID",
+                        HeaderValue::from_str(value).context("invalid Loki tenant ID")?,
+                    );
+                }
+                "loki-external-labels" => {
+                    for label in value.split(',').filter(|x| !x.is_empty()) {
+                        let (name, value) = label
+                            .split_once('=')
+                            .context(format!("invalid Loki external label '{}'", label))?;
+                        labels.insert(Self::label_name(name.trim()), value.trim().into());
+                    }
+                }
+                "loki-capath" => {
+                    let pem = fs::read(value).context(format!("read Loki CA '{}'", value))?;
+                    builder = builder.add_root_certificate(
+                        Certificate::from_pem(&pem).context("parse Loki CA")?,
+                    );
+                }
+                "loki-insecureskipverify" => {
+                    builder = builder.danger_accept_invalid_certs(
+                        value
+                            .parse()
+                            .context(format!("parse Loki option {}", key))?,
+                    );
+                }
+                x => bail!("unsupported Loki option: {}", x),
+            }
+        }
+
+        Ok(Self {
+            url,
+            client: builder
+                .default_headers(headers)
+                .build()
+                .context("build Loki HTTP client")?,
+            labels,
+            sender: None,
+            task: None,
+            retry_delay: Self::RETRY_DELAY,
+            dropped: 0,
+        })
+    }
+
+    /// Asynchronously initialize the Loki logger by starting the batching task.
+    pub async fn init(&mut self) -> Result<()> {
+        debug!("Initializing Loki logger for {}", self.url());
+        let (sender, receiver) = mpsc::channel(Self::BUFFER_SIZE);
+        self.sender = Some(sender);
+        self.task = Some(task::spawn(Self::push_entries(
+            self.client.clone(),
+            self.url().clone(),
+            self.labels().clone(),
+            receiver,
+            self.retry_delay(),
+        )));
+        Ok(())
+    }
+
+    /// Write the contents of the provided reader as entries, one per line. Entries get dropped
+    /// if the buffer is full, for example because Loki is unreachable.
+    pub async fn write<T>(&mut self, pipe: Pipe, bytes: T) -> Result<()>
+    where
+        T: AsyncBufRead + Unpin,
+    {
+        let mut reader = BufReader::new(bytes);
+        loop {
+            let mut line = vec![];
+            let read = reader
+                .read_until(b'\n', &mut line)
+                .await
+                .context("read log line")?;
+            if read == 0 {
+                break;
+            }
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+
+            let entry = Entry {
+                pipe,
+                time: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .context("get time since unix epoch")?
+                    .as_nanos()
+                    .to_string(),
+                line: String::from_utf8_lossy(&line).into_owned(),
+            };
+            match self
+                .sender
+                .as_ref()
+                .context(Self::ERR_UNINITIALIZED)?
+                .try_send(entry)
+            {
+                Ok(()) => trace!("Buffered Loki entry of length {}", line.len()),
+                Err(TrySendError::Full(_)) => {
+                    if self.dropped == 0 {
+                        warn!("Loki entry buffer is full, dropping entries");
+                    }
+                    self.dropped += 1;
+                }
+                Err(TrySendError::Closed(_)) => bail!("Loki batching task stopped"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Push all buffered entries and restart the batching task.
+    pub async fn reopen(&mut self) -> Result<()> {
+        debug!("Reopen Loki logger");
+        self.close().await?;
+        self.init().await
+    }
+
+    /// Push all buffered entries and stop the batching task.
+    pub async fn close(&mut self) -> Result<()> {
+        self.sender = None;
+        if let Some(task) = self.task.take() {
+            task.await.context("wait for Loki batching task")?;
+        }
+        Ok(())
+    }
+
+    /// Convert the provided name into a valid label name in snake case.
+    fn label_name(name: &str) -> String {
+        let mut label = String::with_capacity(name.len() + 4);
+        let mut previous: Option<char> = None;
+        for c in name.chars() {
+            if c.is_ascii_uppercase() {
+                if matches!(previous, Some(x) if x.is_ascii_lowercase() || x.is_ascii_digit()) {
+                    label.push('_');
+                }
+                label.push(c.to_ascii_lowercase());
+            } else if c.is_ascii_alphanumeric() {
+                label.push(c);
+            } else {
+                label.push('_');
+            }
+            previous = Some(c);
+        }
+        if label.is_empty() || label.starts_with(|c: char| c.is_ascii_digit()) {
+            label.insert(0, '_');
+        }
+        label
+    }
+
+    /// Batch the received entries and push them once the batch is full, the push interval
+    /// elapsed or the sender got dropped.
+    async fn push_entries(
+        client: Client,
+        url: Url,
+        labels: BTreeMap<String, String>,
+        mut receiver: mpsc::Receiver<Entry>,
+        retry_delay: Duration,
+    ) {
+        let mut batch = vec![];
+        let mut interval = time::interval(Self::PUSH_INTERVAL);
+        loop {
+            let closed = tokio::select! {
+                entry = receiver.recv() => match entry {
+                    Some(entry) => {
+                        batch.push(entry);
+                        if batch.len() < Self::BATCH_SIZE {
+                            continue;
+                        }
+                        false
+                    }
+                    None => true,
+                },
+                _ = interval.tick() => false,
+            };
+            if !batch.is_empty() {
+                let body = Self::body(&labels, &batch);
+                Self::push(&client, &url, body, batch.len(), retry_delay).await;
+                batch.clear();
+            }
+            if closed {
+                return;
+            }
+        }
+    }
+
+    /// Build the push request body, which contains one stream per pipe.
+    fn body(labels: &BTreeMap<String, String>, entries: &[Entry]) -> Value {
+        let streams = [Pipe::StdOut, Pipe::StdErr]
+            .iter()
+            .filter_map(|pipe| {
+                let values = entries
+                    .iter()
+                    .filter(|entry| entry.pipe == *pipe)
+                    .map(|entry| json!([entry.time, entry.line]))
+                    .collect::<Vec<_>>();
+                if values.is_empty() {
+                    return None;
+                }
+                let mut labels = labels.clone();
+                labels.insert("stream".into(), pipe.to_string());
+                Some(json!({ "stream": labels, "values": values }))
+            })
+            .collect::<Vec<_>>();
+        json!({ "streams": streams })
+    }
+
+    /// Push the body with exponential backoff, where it gets dropped after the maximum amount
+    /// of attempts.
+    async fn push(client: &Client, url: &Url, body: Value, len: usize, retry_delay: Duration) {
+        let body = body.to_string();
+        let mut delay = retry_delay;
+        for attempt in 1..=Self::MAX_ATTEMPTS {
+            match Self::send(client, url, body.clone()).await {
+                Ok(()) => {
+                    trace!("Pushed {} entries to Loki", len);
+                    return;
+                }
+                Err(e) if attempt < Self::MAX_ATTEMPTS => {
+                    warn!(
+                        "Unable to push entries to Loki (attempt {}/{}), retrying in {:?}: {:#}",
+                        attempt,
+                        Self::MAX_ATTEMPTS,
+                        delay,
+                        e
+                    );
+                    time::sleep(delay).await;
+                    delay = (delay * 2).min(Self::MAX_RETRY_DELAY);
+                }
+                Err(e) => error!(
+                    "Dropping {} entries after {} failed pushes to Loki: {:#}",
+                    len, attempt, e
+                ),
+            }
+        }
+    }
+
+    async fn send(client: &Client, url: &Url, body: String) -> Result<()> {
+        let response = client
+            .post(url.clone())
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await
+            .context("send entries")?;
+        let status = response.status();
+        if !status.is_success() {
+            bail!(
+                "unexpected status {}: {}",
+                status,
+                response.text().await.unwrap_or_default()
+            )
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// A received request consisting of the header and the body.
+    type Request = (String, String);
+
+    /// Serve one request per provided status code.
+    async fn serve(listener: TcpListener, statuses: Vec<u16>) -> Result<Vec<Request>> {
+        let mut requests = vec![];
+        let mut stream = None;
+        for status in statuses {
+            loop {
+                if stream.is_none() {
+                    stream = Some(BufReader::new(listener.accept().await?.0));
+                }
+                let reader = stream.as_mut().context("no stream")?;
+
+                let mut header = String::new();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).await? == 0 {
+                        break;
+                    }
+                    header.push_str(&line);
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                if header.is_empty() {
+                    stream = None;
+                    continue;
+                }
+
+                let len = header
+                    .lines()
+                    .find_map(|x| {
+                        x.to_lowercase()
+                            .strip_prefix("content-length: ")
+                            .map(String::from)
+                    })
+                    .context("no content length")?
+                    .parse::<usize>()?;
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).await?;
+                reader
+                    .get_mut()
+                    .write_all(
+                        format!("HTTP/1.1 {} Status\r\nContent-Length: 0\r\n\r\n", status)
+                            .as_bytes(),
+                    )
+                    .await?;
+                requests.push((header, String::from_utf8(body)?));
+                break;
+            }
+        }
+        Ok(requests)
+    }
+
+    async fn logger(listener: &TcpListener) -> Result<LokiLogger> {
+        let mut metadata = HashMap::new();
+        metadata.insert("PodName".to_string(), "pod".to_string());
+        let mut sut = LokiLogger::new(
+            "name",
+            &format!("http://{}", listener.local_addr()?),
+            "",
+            &metadata,
+            &[
+                ("loki-tenant-id".into(), "tenant".into()),
+                ("loki-external-labels".into(), "env=prod,team=a".into()),
+            ],
+        )?;
+        sut.set_retry_delay(Duration::from_millis(10));
+        sut.init().await?;
+        Ok(sut)
+    }
+
+    #[tokio::test]
+    async fn write() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut sut = logger(&listener).await?;
+        let server = task::spawn(serve(listener, vec![204]));
+
+        sut.write(Pipe::StdOut, "a\nb\n".as_bytes()).await?;
+        sut.write(Pipe::StdErr, "c\n".as_bytes()).await?;
+        sut.close().await?;
+
+        let requests = server.await??;
+        assert_eq!(requests.len(), 1);
+        let (header, body) = &requests[0];
+        assert!(header.starts_with("POST /loki/api/v1/push "));
+        assert!(header.to_lowercase().contains("x-scope-orgid: tenant"));
+
+        let body: Value = serde_json::from_str(body)?;
+        let streams = body["streams"].as_array().context("no streams")?;
+        assert_eq!(streams.len(), 2);
+        for (stream, (name, lines)) in streams
+            .iter()
+            .zip(&[("stdout", vec!["a", "b"]), ("stderr", vec!["c"])])
+        {
+            assert_eq!(
+                stream["stream"],
+                json!({
+                    "container_name": "name",
+                    "env": "prod",
+                    "pod_name": "pod",
+                    "stream": name,
+                    "team": "a",
+                })
+            );
+            let values = stream["values"].as_array().context("no values")?;
+            assert_eq!(values.len(), lines.len());
+            for (value, line) in values.iter().zip(lines) {
+                let time = value[0].as_str().context("no time")?;
+                assert!(time.parse::<u128>()? > 0);
+                assert_eq!(value[1], *line);
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_retry() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut sut = logger(&listener).await?;
+        let server = task::spawn(serve(listener, vec![429, 204]));
+
+        sut.write(Pipe::StdOut, "a\n".as_bytes()).await?;
+        sut.close().await?;
+
+        let requests = server.await??;
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].1, requests[1].1);
+        Ok(())
+    }
+
+    #[test]
+    fn label_name() {
+        for (name, expected) in &[
+            ("PodName", "pod_name"),
+            ("pod_namespace", "pod_namespace"),
+            ("io.kubernetes.pod", "io_kubernetes_pod"),
+            ("ID", "id"),
+            ("FullID", "full_id"),
+            ("1st", "_1st"),
+            ("", "_"),
+        ] {
+            assert_eq!(LokiLogger::label_name(name), *expected);
+        }
+    }
+
+    #[test]
+    fn url() -> Result<()> {
+        for (address, expected) in &[
+            ("http://loki:3100", "http://loki:3100/loki/api/v1/push"),
+            (
+                "https://loki/prefix",
+                "https://loki/prefix/loki/api/v1/push",
+            ),
+        ] {
+            let sut = LokiLogger::new("", address, "", &HashMap::new(), &[])?;
+            assert_eq!(sut.url().as_str(), *expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_config() {
+        for (address, options) in [
+            ("ftp://loki", vec![]),
+            ("loki", vec![]),
+            ("http://loki", vec![("loki-unknown", "")]),
+            ("http://loki", vec![("loki-external-labels", "invalid")]),
+            ("http://loki", vec![("loki-insecureskipverify", "yes")]),
+        ] {
+            let options = options
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>();
+            assert!(LokiLogger::new("", address, "", &HashMap::new(), &options).is_err());
+        }
+    }
+}
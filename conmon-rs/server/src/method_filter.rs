@@ -0,0 +1,240 @@
+//! Restrict the methods of the `Conmon` interface, which can be called over a listener.
+
+use anyhow::{bail, Result};
+use capnp::{
+    any_pointer,
+    capability::{self, FromServer, Params, Promise, Results},
+    Error,
+};
+use capnp_rpc::pry;
+use conmon_common::conmon_capnp::conmon;
+use std::{
+    collections::HashSet,
+    ops::{Deref, DerefMut},
+};
+
+macro_rules! request_id {
+    ($params:expr, $module:ident) => {
+        $params
+            .get_as::<conmon::$module::Reader>()?
+            .get_request()?
+            .get_id()?
+            .to_string()
+    };
+}
+
+/// The method names of the `Conmon` interface, indexed by their ordinal.
+pub const METHODS: &[&str] = &[
+    "version",
+    "createContainer",
+    "execSyncContainer",
+    "attachContainer",
+    "reopenLogContainer",
+    "setWindowSizeContainer",
+    "attachStatsContainer",
+    "logStatsContainer",
+    "updateLogConfigContainer",
+    "tailLogContainer",
+    "setWindowSizeExecSession",
+    "setTerminalModeContainer",
+    "execStreamContainer",
+    "readRecentOutputContainer",
+    "ioStatsContainer",
+    "setOutputPausedContainer",
+    "sendTerminalMasterContainer",
+    "listContainers",
+    "containerStats",
+    "status",
+    "setLogFilter",
+    "reloadConfig",
+    "shutdown",
+    "subscribeEvents",
+    "createContainers",
+    "capabilities",
+    "removeContainer",
+    "containerStatus",
+    "listExecSessions",
+    "inspectExecSession",
+    "killExecSession",
+    "stopContainer",
+];
+
+/// The ID of the container the method operates on, which is `None` for the methods not related
+/// to a single container.
+pub fn container_id(
+    method: &str,
+    params: any_pointer::Reader<'_>,
+) -> capnp::Result<Option<String>> {
+    Ok(Some(match method {
+        "createContainer" => request_id!(params, create_container_params),
+        "execSyncContainer" => request_id!(params, exec_sync_container_params),
+        "attachContainer" => request_id!(params, attach_container_params),
+        "reopenLogContainer" => request_id!(params, reopen_log_container_params),
+        "setWindowSizeContainer" => request_id!(params, set_window_size_container_params),
+        "attachStatsContainer" => request_id!(params, attach_stats_container_params),
+        "logStatsContainer" => request_id!(params, log_stats_container_params),
+        "updateLogConfigContainer" => {
+            request_id!(params, update_log_config_container_params)
+        }
+        "tailLogContainer" => request_id!(params, tail_log_container_params),
+        "setWindowSizeExecSession" => {
+            request_id!(params, set_window_size_exec_session_params)
+        }
+        "setTerminalModeContainer" => {
+            request_id!(params, set_terminal_mode_container_params)
+        }
+        "execStreamContainer" => request_id!(params, exec_stream_container_params),
+        "readRecentOutputContainer" => {
+            request_id!(params, read_recent_output_container_params)
+        }
+        "ioStatsContainer" => request_id!(params, io_stats_container_params),
+        "setOutputPausedContainer" => {
+            request_id!(params, set_output_paused_container_params)
+        }
+        "sendTerminalMasterContainer" => {
+            request_id!(params, send_terminal_master_container_params)
+        }
+        "containerStats" => request_id!(params, container_stats_params),
+        "removeContainer" => request_id!(params, remove_container_params),
+        "containerStatus" => request_id!(params, container_status_params),
+        "listExecSessions" => request_id!(params, list_exec_sessions_params),
+        "inspectExecSession" => request_id!(params, inspect_exec_session_params),
+        "killExecSession" => request_id!(params, kill_exec_session_params),
+        "stopContainer" => request_id!(params, stop_container_params),
+        _ => return Ok(None),
+    }))
+}
+
+/// A server forwarding only the allowed methods to the `Conmon` client.
+pub struct MethodFilter {
+    client: conmon::Client,
+    allowed: HashSet<u16>,
+}
+
+/// Restrict the client to the methods named like in the schema, where no methods allow all of
+/// them.
+pub fn restrict<T: AsRef<str>>(client: &conmon::Client, methods: &[T]) -> Result<conmon::Client> {
+    if methods.is_empty() {
+        return Ok(client.clone());
+    }
+    Ok(capnp_rpc::new_client(MethodFilter {
+        client: client.clone(),
+        allowed: method_ids(methods)?,
+    }))
+}
+
+/// Validate the method names, returning their ordinals.
+pub fn method_ids<T: AsRef<str>>(methods: &[T]) -> Result<HashSet<u16>> {
+    methods
+        .iter()
+        .map(
+            |method| match METHODS.iter().position(|x| *x == method.as_ref()) {
+                Some(id) => Ok(id as u16),
+                None => bail!("unknown RPC method '{}'", method.as_ref()),
+            },
+        )
+        .collect()
+}
+
+impl capability::Server for MethodFilter {
+    fn dispatch_call(
+        &mut self,
+        interface_id: u64,
+        method_id: u16,
+        params: Params<any_pointer::Owned>,
+        mut results: Results<any_pointer::Owned>,
+    ) -> Promise<(), Error> {
+        if interface_id != conmon::_private::TYPE_ID || !self.allowed.contains(&method_id) {
+            return Promise::err(Error::failed(format!(
+                "method {} is not permitted on this listener",
+                METHODS.get(method_id as usize).unwrap_or(&"unknown")
+            )));
+        }
+
+        let mut request = self
+            .client
+            .client
+            .new_call::<any_pointer::Owned, any_pointer::Owned>(interface_id, method_id, None);
+        pry!(request.get().set_as(pry!(params.get())));
+        Promise::from_future(async move {
+            let response = request.send().promise.await?;
+            results.get().set_as(response.get()?)
+        })
+    }
+}
+
+/// The dispatch of the filter, which is required to create a typed `Conmon` client from it.
+pub struct MethodFilterDispatch(MethodFilter);
+
+impl Deref for MethodFilterDispatch {
+    type Target = MethodFilter;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for MethodFilterDispatch {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl capability::Server for MethodFilterDispatch {
+    fn dispatch_call(
+        &mut self,
+        interface_id: u64,
+        method_id: u16,
+        params: Params<any_pointer::Owned>,
+        results: Results<any_pointer::Owned>,
+    ) -> Promise<(), Error> {
+        self.0
+            .dispatch_call(interface_id, method_id, params, results)
+    }
+}
+
+impl FromServer<MethodFilter> for conmon::Client {
+    type Dispatch = MethodFilterDispatch;
+
+    fn from_server(s: MethodFilter) -> Self::Dispatch {
+        MethodFilterDispatch(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn container_id_of_methods() {
+        let mut message = capnp::message::Builder::new_default();
+        let mut root = message.init_root::<any_pointer::Builder>();
+        root.reborrow()
+            .init_as::<conmon::container_status_params::Builder>()
+            .init_request()
+            .set_id("id");
+        let params = root.into_reader();
+
+        assert_eq!(
+            container_id("containerStatus", params).unwrap().as_deref(),
+            Some("id")
+        );
+        assert_eq!(container_id("version", params).unwrap(), None);
+    }
+
+    #[test]
+    fn valid_method_ids() -> Result<()> {
+        assert_eq!(
+            method_ids(&["version", "containerStats", "shutdown", "killExecSession"])?,
+            [0, 18, 22, 30].iter().copied().collect()
+        );
+        assert!(method_ids::<&str>(&[])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_method_ids() {
+        assert!(method_ids(&["version", "createcontainer"]).is_err());
+        assert!(method_ids(&[""]).is_err());
+    }
+}
@@ -0,0 +1,539 @@
+//! Prometheus metrics of the server, which get served in the text exposition format.
+
+use crate::{
+    child_reaper::ChildReaper,
+    container_io::Pipe,
+    listener::{DefaultListener, Listener},
+};
+use anyhow::{format_err, Context, Result};
+use lazy_static::lazy_static;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{
+        AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+        BufReader,
+    },
+    net::TcpListener,
+    task, time,
+};
+use tracing::{
+    debug, debug_span, error,
+    span::{Attributes, Id},
+    Instrument, Level, Metadata, Subscriber,
+};
+use tracing_subscriber::{
+    filter::Targets,
+    layer::{Context as LayerContext, Layer},
+    registry::LookupSpan,
+};
+
+macro_rules! lock {
+    ($x:expr) => {
+        $x.lock().map_err(|e| format_err!("{:#}", e))?
+    };
+}
+
+lazy_static! {
+    /// The metrics of the server process.
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+#[derive(Debug, Default)]
+/// The counters and histograms exposed to Prometheus.
+pub struct Metrics {
+    rpc_durations: Mutex<BTreeMap<&'static str, Histogram>>,
+    stdin_bytes: AtomicU64,
+    stdout_bytes: AtomicU64,
+    stderr_bytes: AtomicU64,
+    reaped_children: AtomicU64,
+    oom_events: AtomicU64,
+    capacity_rejections: AtomicU64,
+    admission_queued: Mutex<BTreeMap<&'static str, u64>>,
+    admission_waits: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+impl Metrics {
+    /// Account the duration of a finished RPC.
+    pub fn record_rpc(&self, method: &'static str, duration: Duration) {
+        if let Ok(mut rpc_durations) = self.rpc_durations.lock() {
+            rpc_durations
+                .entry(method)
+                .or_default()
+                .observe(duration.as_secs_f64());
+        }
+    }
+
+    /// Account input written to a container.
+    pub fn record_input(&self, len: usize) {
+        self.stdin_bytes.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// Account output read from a container.
+    pub fn record_output(&self, pipe: Pipe, len: usize) {
+        let counter = match pipe {
+            Pipe::StdOut => &self.stdout_bytes,
+            Pipe::StdErr => &self.stderr_bytes,
+        };
+        counter.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// Account a reaped child, which may have been killed by the OOM killer.
+    pub fn record_reaped(&self, oomed: bool) {
+        self.reaped_children.fetch_add(1, Ordering::Relaxed);
+        if oomed {
+            self.oom_events.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Account a create rejected because the maximum amount of containers is tracked.
+    pub fn record_capacity_rejection(&self) {
+        self.capacity_rejections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account a RPC waiting for its admission.
+    pub fn record_queued(&self, method: &'static str) {
+        if let Ok(mut admission_queued) = self.admission_queued.lock() {
+            *admission_queued.entry(method).or_default() += 1;
+        }
+    }
+
+    /// Account a RPC which stopped waiting for its admission.
+    pub fn record_dequeued(&self, method: &'static str) {
+        if let Ok(mut admission_queued) = self.admission_queued.lock() {
+            let queued = admission_queued.entry(method).or_default();
+            *queued = queued.saturating_sub(1);
+        }
+    }
+
+    /// Account the time a RPC waited for its admission.
+    pub fn record_admission_wait(&self, method: &'static str, duration: Duration) {
+        if let Ok(mut admission_waits) = self.admission_waits.lock() {
+            admission_waits
+                .entry(method)
+                .or_default()
+                .observe(duration.as_secs_f64());
+        }
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn render(&self, attach_clients: u64, tracked_containers: u64) -> Result<String> {
+        let mut out = String::new();
+
+        Self::header(
+            &mut out,
+            "conmonrs_rpc_duration_seconds",
+            "histogram",
+            "The duration of the container RPCs.",
+        );
+        for (method, histogram) in lock!(self.rpc_durations).iter() {
+            histogram.render(&mut out, "conmonrs_rpc_duration_seconds", method);
+        }
+
+        Self::header(
+            &mut out,
+            "conmonrs_admission_queue_depth",
+            "gauge",
+            "The amount of RPCs waiting for their admission.",
+        );
+        for (method, queued) in lock!(self.admission_queued).iter() {
+            let _ = writeln!(
+                out,
+                "conmonrs_admission_queue_depth{{method=\"{}\"}} {}",
+                method, queued
+            );
+        }
+
+        Self::header(
+            &mut out,
+            "conmonrs_admission_wait_seconds",
+            "histogram",
+            "The time RPCs waited for their admission.",
+        );
+        for (method, histogram) in lock!(self.admission_waits).iter() {
+            histogram.render(&mut out, "conmonrs_admission_wait_seconds", method);
+        }
+
+        Self::header(
+            &mut out,
+            "conmonrs_io_bytes_total",
+            "counter",
+            "The bytes written to and read from the containers.",
+        );
+        for (pipe, counter) in [
+            ("stdin", &self.stdin_bytes),
+            ("stdout", &self.stdout_bytes),
+            ("stderr", &self.stderr_bytes),
+        ] {
+            let _ = writeln!(
+                out,
+                "conmonrs_io_bytes_total{{pipe=\"{}\"}} {}",
+                pipe,
+                counter.load(Ordering::Relaxed)
+            );
+        }
+
+        for (name, typ, help, value) in [
+            (
+                "conmonrs_attach_clients",
+                "gauge",
+                "The amount of connected attach clients.",
+                attach_clients,
+            ),
+            (
+                "conmonrs_tracked_containers",
+                "gauge",
+                "The amount of tracked containers, without their exec sessions.",
+                tracked_containers,
+            ),
+            (
+                "conmonrs_capacity_rejections_total",
+                "counter",
+                "The amount of creates rejected because of the maximum tracked containers.",
+                self.capacity_rejections.load(Ordering::Relaxed),
+            ),
+            (
+                "conmonrs_reaped_children_total",
+                "counter",
+                "The amount of containers and exec sessions which exited.",
+                self.reaped_children.load(Ordering::Relaxed),
+            ),
+            (
+                "conmonrs_oom_events_total",
+                "counter",
+                "The amount of containers and exec sessions killed by the OOM killer.",
+                self.oom_events.load(Ordering::Relaxed),
+            ),
+        ] {
+            Self::header(&mut out, name, typ, help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        Ok(out)
+    }
+
+    fn header(out: &mut String, name: &str, typ: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, typ);
+    }
+}
+
+/// The upper bounds of the histogram buckets in seconds.
+const BUCKETS: [f64; 10] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0, 10.0,
+];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// A histogram with cumulative bucket counts.
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+
+    fn render(&self, out: &mut String, name: &str, method: &str) {
+        for (bucket, bound) in self.buckets.iter().zip(BUCKETS) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{method=\"{}\",le=\"{}\"}} {}",
+                name, method, bound, bucket
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{method=\"{}\",le=\"+Inf\"}} {}",
+            name, method, self.count
+        );
+        let _ = writeln!(out, "{}_sum{{method=\"{}\"}} {}", name, method, self.sum);
+        let _ = writeln!(
+            out,
+            "{}_count{{method=\"{}\"}} {}",
+            name, method, self.count
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+/// A tracing layer measuring the duration of container RPCs, which lasts from creating their
+/// root span until the span and all of its children are closed.
+pub struct RpcDurationLayer;
+
+/// The time at which a RPC span got created.
+struct Started(Instant);
+
+impl RpcDurationLayer {
+    const TARGET: &'static str = "conmonrs::rpc";
+
+    /// The filter enabling the RPC spans regardless of the log level.
+    pub fn filter() -> Targets {
+        Targets::new().with_target(Self::TARGET, Level::DEBUG)
+    }
+
+    /// RPC root spans are the only ones carrying the container ID.
+    fn is_rpc(metadata: &Metadata<'_>) -> bool {
+        metadata.is_span()
+            && metadata.target() == Self::TARGET
+            && metadata.fields().field("container_id").is_some()
+    }
+}
+
+impl<S> Layer<S> for RpcDurationLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: LayerContext<'_, S>) {
+        if !Self::is_rpc(attrs.metadata()) {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Started(Instant::now()));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: LayerContext<'_, S>) {
+        if let Some(span) = ctx.span(&id) {
+            if let Some(started) = span.extensions().get::<Started>() {
+                METRICS.record_rpc(span.name(), started.0.elapsed());
+            }
+        }
+    }
+}
+
+/// The maximum size of a request, which consists only of the request line and headers.
+const MAX_REQUEST_SIZE: u64 = 8192;
+
+/// The time a client has to send the request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serve the metrics via HTTP on the provided address, which is either the path of a unix domain
+/// socket or a TCP socket address.
+pub async fn serve(address: &str, reaper: Arc<ChildReaper>) -> Result<()> {
+    if is_socket_path(address) {
+        let listener = Listener::<DefaultListener>::default()
+            .bind_long_path(address)
+            .context("bind metrics socket")?;
+        debug!("Serving metrics on {}", address);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            spawn_handler(stream, reaper.clone());
+        }
+    }
+
+    let address: SocketAddr = address
+        .parse()
+        .context(format!("parse metrics address '{}'", address))?;
+    let listener = TcpListener::bind(address)
+        .await
+        .context("bind metrics address")?;
+    debug!("Serving metrics on {}", address);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        spawn_handler(stream, reaper.clone());
+    }
+}
+
+/// Returns whether the metrics address refers to a unix domain socket.
+pub fn is_socket_path(address: &str) -> bool {
+    Path::new(address).is_absolute()
+}
+
+fn spawn_handler<T>(stream: T, reaper: Arc<ChildReaper>)
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    task::spawn(
+        async move {
+            if let Err(e) = handle(stream, &reaper).await {
+                error!("Unable to serve metrics request: {:#}", e);
+            }
+        }
+        .instrument(debug_span!("metrics")),
+    );
+}
+
+async fn handle<T>(stream: T, reaper: &ChildReaper) -> Result<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_SIZE));
+    let request_line = time::timeout(REQUEST_TIMEOUT, read_head(&mut reader))
+        .await
+        .context("read request timed out")??;
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) if path.split('?').next() == Some("/metrics") => {
+            let mut attach_clients = 0;
+            for (_, child) in reaper.list()? {
+                attach_clients += child.io().attach().await.stats()?.active_clients();
+            }
+            let tracked_containers = reaper.containers()? as u64;
+            (
+                "200 OK",
+                METRICS.render(attach_clients, tracked_containers)?,
+            )
+        }
+        (Some("GET"), _) => ("404 Not Found", "Not Found\n".into()),
+        _ => ("405 Method Not Allowed", "Method Not Allowed\n".into()),
+    };
+
+    let stream = reader.get_mut().get_mut();
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 {}\r\n\
+                Content-Type: text/plain; version=0.0.4\r\n\
+                Content-Length: {}\r\n\
+                Connection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        )
+        .await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Read the request line and skip all headers.
+async fn read_head<T>(reader: &mut T) -> Result<String>
+where
+    T: AsyncBufRead + Unpin,
+{
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 || header.trim_end().is_empty() {
+            return Ok(request_line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn histogram() {
+        let mut sut = Histogram::default();
+        sut.observe(0.003);
+        sut.observe(20.0);
+
+        let mut out = String::new();
+        sut.render(&mut out, "rpc", "test");
+        assert!(out.contains("rpc_bucket{method=\"test\",le=\"0.0025\"} 0\n"));
+        assert!(out.contains("rpc_bucket{method=\"test\",le=\"0.005\"} 1\n"));
+        assert!(out.contains("rpc_bucket{method=\"test\",le=\"10\"} 1\n"));
+        assert!(out.contains("rpc_bucket{method=\"test\",le=\"+Inf\"} 2\n"));
+        assert!(out.contains("rpc_sum{method=\"test\"} 20.003\n"));
+        assert!(out.contains("rpc_count{method=\"test\"} 2\n"));
+    }
+
+    #[test]
+    fn render() -> Result<()> {
+        let sut = Metrics::default();
+        sut.record_input(1);
+        sut.record_output(Pipe::StdOut, 2);
+        sut.record_output(Pipe::StdErr, 3);
+        sut.record_reaped(false);
+        sut.record_reaped(true);
+        sut.record_rpc("create_container", Duration::from_millis(20));
+        sut.record_queued("createContainer");
+        sut.record_queued("createContainer");
+        sut.record_dequeued("createContainer");
+        sut.record_admission_wait("createContainer", Duration::from_millis(5));
+        sut.record_capacity_rejection();
+
+        let out = sut.render(4, 2)?;
+        assert!(out.contains("# TYPE conmonrs_rpc_duration_seconds histogram\n"));
+        assert!(
+            out.contains("conmonrs_rpc_duration_seconds_count{method=\"create_container\"} 1\n")
+        );
+        assert!(out.contains("conmonrs_io_bytes_total{pipe=\"stdin\"} 1\n"));
+        assert!(out.contains("conmonrs_io_bytes_total{pipe=\"stdout\"} 2\n"));
+        assert!(out.contains("conmonrs_io_bytes_total{pipe=\"stderr\"} 3\n"));
+        assert!(out.contains("conmonrs_attach_clients 4\n"));
+        assert!(out.contains("conmonrs_tracked_containers 2\n"));
+        assert!(out.contains("conmonrs_capacity_rejections_total 1\n"));
+        assert!(out.contains("conmonrs_reaped_children_total 2\n"));
+        assert!(out.contains("conmonrs_oom_events_total 1\n"));
+        assert!(out.contains("conmonrs_admission_queue_depth{method=\"createContainer\"} 1\n"));
+        assert!(
+            out.contains("conmonrs_admission_wait_seconds_count{method=\"createContainer\"} 1\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rpc_duration_layer() -> Result<()> {
+        let subscriber = tracing_subscriber::registry()
+            .with(RpcDurationLayer.with_filter(RpcDurationLayer::filter()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span =
+                debug_span!(target: "conmonrs::rpc", "rpc_duration_layer", container_id = "id");
+            let child = span.in_scope(|| debug_span!(target: "conmonrs::rpc", "promise"));
+            drop(span);
+            std::thread::sleep(Duration::from_millis(10));
+            drop(child);
+            debug_span!(target: "conmonrs::rpc", "other").in_scope(|| {});
+        });
+
+        let rpc_durations = lock!(METRICS.rpc_durations);
+        let histogram = rpc_durations
+            .get("rpc_duration_layer")
+            .context("no histogram")?;
+        assert_eq!(histogram.count, 1);
+        assert!(histogram.sum >= 0.01);
+        assert!(!rpc_durations.contains_key("promise"));
+        assert!(!rpc_durations.contains_key("other"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handle_request() -> Result<()> {
+        let reaper = ChildReaper::default();
+        for (request, status) in [
+            ("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n", "200 OK"),
+            ("GET /metrics?x=y HTTP/1.1\r\n\r\n", "200 OK"),
+            ("GET / HTTP/1.1\r\n\r\n", "404 Not Found"),
+            ("POST /metrics HTTP/1.1\r\n\r\n", "405 Method Not Allowed"),
+        ] {
+            let (mut client, server) = tokio::io::duplex(1024 * 1024);
+            client.write_all(request.as_bytes()).await?;
+            handle(server, &reaper).await?;
+
+            let mut response = String::new();
+            client.read_to_string(&mut response).await?;
+            assert!(response.starts_with(&format!("HTTP/1.1 {}\r\n", status)));
+            if status == "200 OK" {
+                assert!(response.contains("\r\n\r\n# HELP conmonrs_rpc_duration_seconds"));
+                assert!(response.contains("conmonrs_attach_clients 0\n"));
+            }
+        }
+        Ok(())
+    }
+}
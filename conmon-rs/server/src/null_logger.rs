@@ -0,0 +1,71 @@
+//! Discarding log driver without any IO.
+
+use crate::container_io::Pipe;
+use anyhow::Result;
+use getset::CopyGetters;
+use tracing::debug;
+
+#[derive(Debug, Default, CopyGetters)]
+/// The structure used for discarding container output, which only counts the drained bytes.
+pub struct NullLogger {
+    #[getset(get_copy = "pub")]
+    /// Discarded bytes of stdout.
+    stdout_bytes: u64,
+
+    #[getset(get_copy = "pub")]
+    /// Discarded bytes of stderr.
+    stderr_bytes: u64,
+}
+
+impl NullLogger {
+    /// Create a new null logger instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Initialize the null logger, which is a no-op.
+    pub async fn init(&mut self) -> Result<()> {
+        debug!("Initializing null logger");
+        Ok(())
+    }
+
+    /// Discard the provided bytes.
+    pub async fn write(&mut self, pipe: Pipe, bytes: &[u8]) -> Result<()> {
+        match pipe {
+            Pipe::StdOut => self.stdout_bytes += bytes.len() as u64,
+            Pipe::StdErr => self.stderr_bytes += bytes.len() as u64,
+        }
+        Ok(())
+    }
+
+    /// Reopen the null logger, which is a no-op.
+    pub async fn reopen(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// The total amount of discarded bytes.
+    pub fn discarded_bytes(&self) -> u64 {
+        self.stdout_bytes() + self.stderr_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn write() -> Result<()> {
+        let mut sut = NullLogger::new();
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, b"abc\n").await?;
+        sut.write(Pipe::StdErr, b"de").await?;
+        sut.reopen().await?;
+        sut.write(Pipe::StdOut, b"f").await?;
+
+        assert_eq!(sut.stdout_bytes(), 5);
+        assert_eq!(sut.stderr_bytes(), 2);
+        assert_eq!(sut.discarded_bytes(), 7);
+        Ok(())
+    }
+}
@@ -38,7 +38,7 @@ pub const CGROUP2_SUPER_MAGIC: FsType = FsType(libc::CGROUP2_SUPER_MAGIC as i64)
 static CGROUP_ROOT: &str = "/sys/fs/cgroup";
 
 lazy_static! {
-    static ref IS_CGROUP_V2: bool = {
+    pub static ref IS_CGROUP_V2: bool = {
         if let Ok(sts) = statfs(CGROUP_ROOT) {
             return sts.filesystem_type() == CGROUP2_SUPER_MAGIC;
         }
@@ -337,7 +337,8 @@ impl OOMWatcher {
         Ok(())
     }
 
-    async fn process_cgroup_subsystem_path_cgroup_v1(
+    /// The cgroup v1 path of the provided subsystem for the process, if it still exists.
+    pub async fn process_cgroup_subsystem_path_cgroup_v1(
         pid: u32,
         subsystem: &str,
     ) -> Result<Option<PathBuf>> {
@@ -371,7 +372,8 @@ impl OOMWatcher {
         Ok(None)
     }
 
-    async fn process_cgroup_subsystem_path_cgroup_v2(pid: u32) -> Result<Option<PathBuf>> {
+    /// The cgroup v2 path of the process, if it still exists.
+    pub async fn process_cgroup_subsystem_path_cgroup_v2(pid: u32) -> Result<Option<PathBuf>> {
         lazy_static! {
             static ref RE: Regex = Regex::new(".*:.*:/(.*)").expect("could not compile regex");
         }
@@ -0,0 +1,83 @@
+//! Bounded in-memory buffer of the most recent container output.
+
+use crate::container_io::Pipe;
+use std::collections::VecDeque;
+
+#[derive(Debug)]
+/// The most recent container output up to a capacity in bytes, where the oldest output gets
+/// dropped first.
+pub struct OutputBuffer {
+    chunks: VecDeque<(Pipe, Vec<u8>)>,
+    len: usize,
+    capacity: usize,
+}
+
+impl OutputBuffer {
+    /// Create a new output buffer holding up to `capacity` bytes.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            chunks: VecDeque::new(),
+            len: 0,
+            capacity,
+        }
+    }
+
+    /// All buffered chunks, oldest first.
+    pub fn chunks(&self) -> impl Iterator<Item = &(Pipe, Vec<u8>)> {
+        self.chunks.iter()
+    }
+
+    /// Append the data and drop the oldest output exceeding the capacity. Consecutive data of
+    /// the same pipe is merged into a single chunk.
+    pub fn push(&mut self, pipe: Pipe, buf: &[u8]) {
+        let buf = &buf[buf.len().saturating_sub(self.capacity)..];
+        match self.chunks.back_mut() {
+            Some((p, data)) if *p == pipe => data.extend_from_slice(buf),
+            _ => self.chunks.push_back((pipe, buf.to_vec())),
+        }
+        self.len += buf.len();
+
+        while self.len > self.capacity {
+            let excess = self.len - self.capacity;
+            match self.chunks.front_mut() {
+                Some((_, data)) if data.len() > excess => {
+                    data.drain(..excess);
+                    self.len -= excess;
+                }
+                Some(_) => {
+                    if let Some((_, data)) = self.chunks.pop_front() {
+                        self.len -= data.len();
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push() {
+        let mut sut = OutputBuffer::new(8);
+
+        sut.push(Pipe::StdOut, b"abc");
+        sut.push(Pipe::StdOut, b"def");
+        sut.push(Pipe::StdErr, b"ghi");
+        assert_eq!(
+            sut.chunks().cloned().collect::<Vec<_>>(),
+            vec![
+                (Pipe::StdOut, b"bcdef".to_vec()),
+                (Pipe::StdErr, b"ghi".to_vec())
+            ]
+        );
+
+        sut.push(Pipe::StdOut, b"0123456789");
+        assert_eq!(
+            sut.chunks().cloned().collect::<Vec<_>>(),
+            vec![(Pipe::StdOut, b"23456789".to_vec())]
+        );
+    }
+}
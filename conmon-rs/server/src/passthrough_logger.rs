@@ -0,0 +1,100 @@
+//! Passthrough of the container output to caller provided file descriptors.
+
+use crate::container_io::Pipe;
+use anyhow::{Context, Result};
+use std::os::unix::io::{AsRawFd, RawFd};
+use tokio::{fs::File, io::AsyncWriteExt};
+use tracing::debug;
+
+#[derive(Debug)]
+/// The structure used for copying container output to file descriptors of the caller, without
+/// any file management like rotation.
+pub struct PassthroughLogger {
+    /// The destination of stdout.
+    stdout: File,
+
+    /// The destination of stderr, which is stdout if `None`.
+    stderr: Option<File>,
+}
+
+impl PassthroughLogger {
+    /// Create a new passthrough logger instance, which writes stderr to `stdout` if no separate
+    /// `stderr` is provided.
+    pub fn new(stdout: std::fs::File, stderr: Option<std::fs::File>) -> Self {
+        Self {
+            stdout: stdout.into(),
+            stderr: stderr.map(Into::into),
+        }
+    }
+
+    /// Initialize the passthrough logger, which is a no-op because the file descriptors are
+    /// already open.
+    pub async fn init(&mut self) -> Result<()> {
+        debug!("Initializing passthrough logger");
+        Ok(())
+    }
+
+    /// Copy the provided bytes unmodified to the file descriptor of the pipe.
+    pub async fn write(&mut self, pipe: Pipe, bytes: &[u8]) -> Result<()> {
+        let file = match (pipe, self.stderr.as_mut()) {
+            (Pipe::StdErr, Some(stderr)) => stderr,
+            _ => &mut self.stdout,
+        };
+        file.write_all(bytes)
+            .await
+            .context(format!("write {} passthrough", pipe))?;
+        file.flush()
+            .await
+            .context(format!("flush {} passthrough", pipe))
+    }
+
+    /// Returns the file descriptor the output of the pipe gets written to.
+    pub fn fd(&self, pipe: Pipe) -> RawFd {
+        match (pipe, self.stderr.as_ref()) {
+            (Pipe::StdErr, Some(stderr)) => stderr.as_raw_fd(),
+            _ => self.stdout.as_raw_fd(),
+        }
+    }
+
+    /// Reopen the passthrough logger, which is a no-op because the file descriptors are owned
+    /// by the caller.
+    pub async fn reopen(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::NamedTempFile;
+
+    #[tokio::test]
+    async fn write() -> Result<()> {
+        let stdout = NamedTempFile::new()?;
+        let stderr = NamedTempFile::new()?;
+        let mut sut = PassthroughLogger::new(stdout.reopen()?, Some(stderr.reopen()?));
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, b"a\nb").await?;
+        sut.write(Pipe::StdErr, b"c\n").await?;
+        sut.write(Pipe::StdOut, b"\n").await?;
+
+        assert_eq!(fs::read_to_string(stdout.path())?, "a\nb\n");
+        assert_eq!(fs::read_to_string(stderr.path())?, "c\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_combined() -> Result<()> {
+        let stdout = NamedTempFile::new()?;
+        let mut sut = PassthroughLogger::new(stdout.reopen()?, None);
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, b"a\n").await?;
+        sut.write(Pipe::StdErr, b"b\n").await?;
+
+        assert_eq!(fs::read_to_string(stdout.path())?, "a\nb\n");
+        Ok(())
+    }
+}
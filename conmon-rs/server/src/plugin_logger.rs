@@ -0,0 +1,323 @@
+//! Logging to external plugins over a unix socket.
+//!
+//! Every log line is sent as a single record, which consists of a big endian `u32` length of
+//! the following fields:
+//!
+//! - the stream as `u8`, where `1` is stdout and `2` is stderr,
+//! - the timestamp as big endian `u64` nanoseconds since the unix epoch,
+//! - the payload, which is the log line without its trailing newline.
+
+use crate::{container_io::Pipe, remote_logger::Backoff};
+use anyhow::{bail, Context, Result};
+use getset::{CopyGetters, Getters};
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    marker::Unpin,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::UnixStream,
+};
+use tracing::{debug, trace, warn};
+
+#[derive(Debug, CopyGetters, Getters)]
+/// The structure used for streaming container output to an external plugin.
+pub struct PluginLogger {
+    #[getset(get)]
+    /// Path to the unix socket of the plugin.
+    socket_path: PathBuf,
+
+    /// Connection to the plugin.
+    connection: Option<UnixStream>,
+
+    /// Records which are not yet sent to the plugin, oldest first.
+    spill: VecDeque<Vec<u8>>,
+
+    #[getset(get_copy)]
+    /// The total length of the spilled records.
+    spill_size: usize,
+
+    #[getset(get_copy)]
+    /// The maximum total length of the spilled records, where the oldest ones get dropped if
+    /// it is exceeded (`plugin-spill-size`).
+    max_spill_size: usize,
+
+    /// The backoff of connection attempts, which also counts the records dropped because the
+    /// spill buffer was full.
+    backoff: Backoff,
+}
+
+impl PluginLogger {
+    /// The default maximum total length of the spilled records.
+    const DEFAULT_MAX_SPILL_SIZE: usize = 1024 * 1024;
+
+    /// The stream identifier of stdout records.
+    const STREAM_STDOUT: u8 = 1;
+
+    /// The stream identifier of stderr records.
+    const STREAM_STDERR: u8 = 2;
+
+    /// Create a new plugin logger instance for the unix socket at `socket_path`. The only
+    /// supported option is `plugin-spill-size`, which limits the amount of bytes buffered
+    /// while the plugin is unavailable.
+    pub fn new<T: AsRef<Path>>(socket_path: T, options: &[(String, String)]) -> Result<Self> {
+        if socket_path.as_ref().as_os_str().is_empty() {
+            bail!("plugin log driver requires a socket path")
+        }
+        let mut max_spill_size = Self::DEFAULT_MAX_SPILL_SIZE;
+        for (key, value) in options {
+            match key.as_str() {
+                "plugin-spill-size" => {
+                    max_spill_size = value
+                        .parse()
+                        .context(format!("invalid plugin spill size '{}'", value))?
+                }
+                x => bail!("unsupported plugin log option: {}", x),
+            }
+        }
+        Ok(Self {
+            socket_path: socket_path.as_ref().into(),
+            connection: None,
+            spill: VecDeque::new(),
+            spill_size: 0,
+            max_spill_size,
+            backoff: Backoff::default(),
+        })
+    }
+
+    /// Asynchronously initialize the plugin logger by connecting to the plugin.
+    pub async fn init(&mut self) -> Result<()> {
+        debug!(
+            "Initializing plugin logger for socket {}",
+            self.socket_path().display()
+        );
+        self.connection = Some(self.connect().await?);
+        self.backoff.connected("plugin log records");
+        Ok(())
+    }
+
+    /// Write the contents of the provided reader to the plugin, one record per line. If the
+    /// plugin is unavailable, then the records are spilled into a bounded buffer and sent once
+    /// the connection got reestablished with exponential backoff.
+    pub async fn write<T>(&mut self, pipe: Pipe, bytes: T) -> Result<()>
+    where
+        T: AsyncBufRead + Unpin,
+    {
+        let mut reader = BufReader::new(bytes);
+        loop {
+            let mut line = vec![];
+            let read = reader
+                .read_until(b'\n', &mut line)
+                .await
+                .context("read log line")?;
+            if read == 0 {
+                break;
+            }
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            let record = Self::record(pipe, &line)?;
+            self.push(record);
+        }
+        self.send_spilled().await;
+        Ok(())
+    }
+
+    /// Reconnect to the plugin.
+    pub async fn reopen(&mut self) -> Result<()> {
+        debug!("Reopen plugin logger");
+        self.init().await?;
+        self.send_spilled().await;
+        Ok(())
+    }
+
+    /// Serialize a single log line into a length prefixed record.
+    fn record(pipe: Pipe, line: &[u8]) -> Result<Vec<u8>> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("get time since unix epoch")?
+            .as_nanos() as u64;
+        let len = u32::try_from(line.len() + 9).context("plugin log record too long")?;
+
+        let mut record = Vec::with_capacity(line.len() + 13);
+        record.extend_from_slice(&len.to_be_bytes());
+        record.push(match pipe {
+            Pipe::StdOut => Self::STREAM_STDOUT,
+            Pipe::StdErr => Self::STREAM_STDERR,
+        });
+        record.extend_from_slice(&timestamp.to_be_bytes());
+        record.extend_from_slice(line);
+        Ok(record)
+    }
+
+    /// Append a record to the spill buffer, which drops the oldest records if it gets full.
+    fn push(&mut self, record: Vec<u8>) {
+        if record.len() > self.max_spill_size() {
+            self.backoff.drop_records(1);
+            return;
+        }
+        while self.spill_size() + record.len() > self.max_spill_size() {
+            match self.spill.pop_front() {
+                Some(dropped) => {
+                    self.spill_size -= dropped.len();
+                    self.backoff.drop_records(1);
+                }
+                None => break,
+            }
+        }
+        self.spill_size += record.len();
+        self.spill.push_back(record);
+    }
+
+    /// Send all spilled records to the plugin, where they are kept if the plugin is
+    /// unavailable.
+    async fn send_spilled(&mut self) {
+        if self.spill.is_empty() || (self.connection.is_none() && !self.reconnect().await) {
+            return;
+        }
+        let records = self.spill.iter().flatten().copied().collect::<Vec<_>>();
+        if let Err(e) = self.send(&records).await {
+            warn!("Lost connection to log plugin: {:#}", e);
+            self.connection = None;
+            return;
+        }
+        trace!("Wrote {} plugin log records", self.spill.len());
+        self.spill.clear();
+        self.spill_size = 0;
+    }
+
+    /// Try to reconnect if the backoff delay elapsed and returns whether it succeeded.
+    async fn reconnect(&mut self) -> bool {
+        if !self.backoff.ready() {
+            return false;
+        }
+        match self.connect().await {
+            Ok(connection) => {
+                debug!("Reconnected to log plugin {}", self.socket_path().display());
+                self.backoff.connected("plugin log records");
+                self.connection = Some(connection);
+                true
+            }
+            Err(e) => {
+                self.backoff.failed("log plugin", &e);
+                false
+            }
+        }
+    }
+
+    async fn connect(&self) -> Result<UnixStream> {
+        UnixStream::connect(self.socket_path())
+            .await
+            .context(format!(
+                "connect to log plugin socket '{}'",
+                self.socket_path().display()
+            ))
+    }
+
+    async fn send(&mut self, records: &[u8]) -> Result<()> {
+        let connection = self.connection.as_mut().context("not connected")?;
+        connection.write_all(records).await?;
+        connection.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+    use tempfile::tempdir;
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt},
+        net::UnixListener,
+    };
+
+    async fn read_records<T: AsyncRead + Unpin>(mut stream: T) -> Result<Vec<(u8, Vec<u8>)>> {
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await?;
+        let mut records = vec![];
+        let mut rest = &buf[..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into()?) as usize;
+            let (record, next) = rest[4..].split_at(len);
+            let timestamp = u64::from_be_bytes(record[1..9].try_into()?);
+            assert!(timestamp > 0);
+            records.push((record[0], record[9..].to_vec()));
+            rest = next;
+        }
+        Ok(records)
+    }
+
+    #[tokio::test]
+    async fn write() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("plugin.sock");
+        let server = UnixListener::bind(&path)?;
+        let mut sut = PluginLogger::new(&path, &[])?;
+        sut.init().await?;
+        let (stream, _) = server.accept().await?;
+
+        sut.write(Pipe::StdOut, "a\nb\n".as_bytes()).await?;
+        sut.write(Pipe::StdErr, "c".as_bytes()).await?;
+        drop(sut);
+
+        assert_eq!(
+            read_records(stream).await?,
+            vec![(1, b"a".to_vec()), (1, b"b".to_vec()), (2, b"c".to_vec())]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn spill_on_outage() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("plugin.sock");
+        let server = UnixListener::bind(&path)?;
+        let options = [("plugin-spill-size".into(), "30".into())];
+        let mut sut = PluginLogger::new(&path, &options)?;
+        sut.init().await?;
+        server.accept().await?;
+
+        // The plugin is unavailable, which spills the records
+        drop(server);
+        std::fs::remove_file(&path)?;
+        sut.connection = None;
+        sut.write(Pipe::StdOut, "a\n".as_bytes()).await?;
+        assert_eq!(sut.spill_size(), 14);
+        assert!(!sut.backoff.ready());
+
+        // The oldest record gets dropped if the spill buffer is full
+        sut.write(Pipe::StdOut, "b\nc\n".as_bytes()).await?;
+        assert_eq!(sut.spill_size(), 28);
+        assert_eq!(sut.backoff.dropped(), 1);
+
+        let server = UnixListener::bind(&path)?;
+        sut.backoff.expire();
+        sut.write(Pipe::StdErr, "d\n".as_bytes()).await?;
+        let (stream, _) = server.accept().await?;
+        assert_eq!(sut.spill_size(), 0);
+        assert_eq!(sut.backoff.dropped(), 0);
+        drop(sut);
+
+        assert_eq!(
+            read_records(stream).await?,
+            vec![(1, b"c".to_vec()), (2, b"d".to_vec())]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(PluginLogger::new("", &[]).is_err());
+        assert!(
+            PluginLogger::new("/plugin.sock", &[("plugin-unknown".into(), "".into())]).is_err()
+        );
+        assert!(
+            PluginLogger::new("/plugin.sock", &[("plugin-spill-size".into(), "-1".into())])
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Token bucket rate limiting of container log output.
+
+use getset::CopyGetters;
+use std::time::{Duration, Instant};
+use tokio::time;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The behavior if the rate limit is exceeded.
+pub enum RateLimitMode {
+    /// Drop the output exceeding the limit.
+    Drop,
+
+    /// Wait until the output fits into the limit, which applies backpressure to the container.
+    Throttle,
+}
+
+#[derive(Debug, CopyGetters)]
+/// A token bucket limiting the amount of bytes per second.
+pub struct RateLimiter {
+    #[getset(get_copy = "pub")]
+    /// The amount of bytes per second.
+    rate: u64,
+
+    #[getset(get_copy = "pub")]
+    /// The maximum amount of bytes allowed at once.
+    burst: u64,
+
+    #[getset(get_copy = "pub")]
+    /// The behavior if the limit is exceeded.
+    mode: RateLimitMode,
+
+    /// The currently available bytes, which may be negative if throttled.
+    tokens: f64,
+
+    /// The last time the tokens got refilled.
+    last_refill: Instant,
+
+    #[getset(get_copy = "pub")]
+    /// The amount of messages dropped since the last suppression report.
+    suppressed: u64,
+
+    /// The last time suppressed messages got reported.
+    last_report: Instant,
+}
+
+impl RateLimiter {
+    /// The minimum interval between two suppression reports.
+    const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Create a new rate limiter with a full bucket. A `burst` of zero defaults to `rate`.
+    pub fn new(rate: u64, burst: u64, mode: RateLimitMode) -> Self {
+        let burst = if burst == 0 { rate } else { burst };
+        let now = Instant::now();
+        Self {
+            rate,
+            burst,
+            mode,
+            tokens: burst as f64,
+            last_refill: now,
+            suppressed: 0,
+            last_report: now,
+        }
+    }
+
+    /// Acquire the provided amount of bytes. Returns `false` if the message has to be dropped.
+    /// Throttling rate limiters wait until the bytes are available and always return `true`.
+    pub async fn acquire(&mut self, len: usize) -> bool {
+        let now = Instant::now();
+        match self.mode() {
+            RateLimitMode::Drop => self.try_acquire_at(len, now),
+            RateLimitMode::Throttle => {
+                let wait = self.acquire_debt_at(len, now);
+                if !wait.is_zero() {
+                    time::sleep(wait).await;
+                }
+                true
+            }
+        }
+    }
+
+    /// Returns the amount of suppressed messages if they should be reported, which happens at
+    /// most once per report interval.
+    pub fn take_suppressed(&mut self) -> Option<u64> {
+        self.take_suppressed_at(Instant::now())
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.rate() as f64).min(self.burst() as f64);
+        self.last_refill = now;
+    }
+
+    /// Take the bytes if available, where messages larger than the burst only require a full
+    /// bucket.
+    fn try_acquire_at(&mut self, len: usize, now: Instant) -> bool {
+        self.refill(now);
+        let needed = (len as u64).min(self.burst()) as f64;
+        if self.tokens >= needed {
+            self.tokens -= needed;
+            true
+        } else {
+            self.suppressed += 1;
+            false
+        }
+    }
+
+    /// Take the bytes in any case and return the time to wait until the debt is paid.
+    fn acquire_debt_at(&mut self, len: usize, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= len as f64;
+        if self.tokens >= 0. || self.rate() == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate() as f64)
+        }
+    }
+
+    fn take_suppressed_at(&mut self, now: Instant) -> Option<u64> {
+        if self.suppressed == 0
+            || now.saturating_duration_since(self.last_report) < Self::REPORT_INTERVAL
+        {
+            return None;
+        }
+        self.last_report = now;
+        Some(std::mem::take(&mut self.suppressed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_and_refill() {
+        let mut sut = RateLimiter::new(100, 200, RateLimitMode::Drop);
+        let now = Instant::now();
+
+        assert!(sut.try_acquire_at(150, now));
+        assert!(!sut.try_acquire_at(100, now));
+        assert!(sut.try_acquire_at(50, now));
+        assert!(!sut.try_acquire_at(1, now));
+        assert_eq!(sut.suppressed(), 2);
+
+        let later = now + Duration::from_millis(500);
+        assert!(sut.try_acquire_at(50, later));
+        assert!(!sut.try_acquire_at(1, later));
+
+        // The bucket never exceeds the burst
+        let much_later = now + Duration::from_secs(60);
+        assert!(sut.try_acquire_at(200, much_later));
+        assert!(!sut.try_acquire_at(1, much_later));
+    }
+
+    #[test]
+    fn drop_larger_than_burst() {
+        let mut sut = RateLimiter::new(100, 0, RateLimitMode::Drop);
+        assert_eq!(sut.burst(), 100);
+
+        let now = Instant::now();
+        assert!(sut.try_acquire_at(1000, now));
+        assert!(!sut.try_acquire_at(1000, now));
+    }
+
+    #[test]
+    fn throttle() {
+        let mut sut = RateLimiter::new(100, 100, RateLimitMode::Throttle);
+        let now = Instant::now();
+
+        assert_eq!(sut.acquire_debt_at(100, now), Duration::ZERO);
+        assert_eq!(sut.acquire_debt_at(50, now), Duration::from_millis(500));
+        assert_eq!(
+            sut.acquire_debt_at(50, now + Duration::from_millis(500)),
+            Duration::from_millis(500)
+        );
+        assert_eq!(sut.suppressed(), 0);
+    }
+
+    #[test]
+    fn suppression_report() {
+        let mut sut = RateLimiter::new(1, 1, RateLimitMode::Drop);
+        let now = Instant::now();
+        assert_eq!(sut.take_suppressed_at(now), None);
+
+        assert!(sut.try_acquire_at(1, now));
+        assert!(!sut.try_acquire_at(1, now));
+        assert!(!sut.try_acquire_at(1, now));
+        assert_eq!(sut.take_suppressed_at(now), None);
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(sut.take_suppressed_at(later), Some(2));
+        assert_eq!(sut.take_suppressed_at(later), None);
+        assert_eq!(sut.suppressed(), 0);
+    }
+
+    #[tokio::test]
+    async fn acquire() {
+        let mut sut = RateLimiter::new(1000, 10, RateLimitMode::Throttle);
+        let start = Instant::now();
+        assert!(sut.acquire(10).await);
+        assert!(sut.acquire(20).await);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,141 @@
+//! Attach session recording into asciicast v2 files.
+
+use anyhow::{format_err, Context, Result};
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+#[derive(Debug)]
+/// Records the IO of a single attach session into an asciicast v2 file. Every event gets flushed
+/// immediately, which keeps the recording usable if the session ends unexpectedly.
+pub struct Recorder {
+    writer: Mutex<BufWriter<File>>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// The terminal size used if the real one is unknown.
+    const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+    /// Create a new recording at the provided path for a terminal of the provided size.
+    pub fn new(path: &Path, size: Option<(u16, u16)>) -> Result<Self> {
+        let (width, height) = size.unwrap_or(Self::DEFAULT_SIZE);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("get current time")?
+            .as_secs();
+
+        let file =
+            File::create(path).with_context(|| format!("create recording {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        writeln!(
+            writer,
+            r#"{{"version": 2, "width": {}, "height": {}, "timestamp": {}}}"#,
+            width, height, timestamp
+        )
+        .context("write recording header")?;
+        writer.flush().context("flush recording header")?;
+
+        Ok(Self {
+            writer: Mutex::new(writer),
+            start: Instant::now(),
+        })
+    }
+
+    /// Record output written to the client.
+    pub fn output(&self, data: &[u8]) {
+        self.event("o", &escape(data))
+    }
+
+    /// Record input read from the client.
+    pub fn input(&self, data: &[u8]) {
+        self.event("i", &escape(data))
+    }
+
+    /// Record a resize of the terminal.
+    pub fn resize(&self, width: u16, height: u16) {
+        self.event("r", &format!("{}x{}", width, height))
+    }
+
+    fn event(&self, code: &str, data: &str) {
+        if let Err(e) = self.write_event(code, data) {
+            warn!("Unable to write attach recording event: {:#}", e)
+        }
+    }
+
+    fn write_event(&self, code: &str, data: &str) -> Result<()> {
+        let mut writer = self.writer.lock().map_err(|e| format_err!("{:#}", e))?;
+        writeln!(
+            writer,
+            r#"[{:.6}, "{}", "{}"]"#,
+            self.start.elapsed().as_secs_f64(),
+            code,
+            data
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Escape the data to be used within a JSON string. Invalid UTF-8, for example a multibyte
+/// character split between two chunks, gets replaced.
+fn escape(data: &[u8]) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    for c in String::from_utf8_lossy(data).chars() {
+        match c {
+            '"' => escaped.push_str(r#"\""#),
+            '\\' => escaped.push_str(r"\\"),
+            '\n' => escaped.push_str(r"\n"),
+            '\r' => escaped.push_str(r"\r"),
+            '\t' => escaped.push_str(r"\t"),
+            c if c.is_control() => {
+                let _ = write!(escaped, r"\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn escape_data() {
+        assert_eq!(escape(b"hello"), "hello");
+        assert_eq!(escape(b"a\"b\\c"), r#"a\"b\\c"#);
+        assert_eq!(escape(b"\r\n\t"), r"\r\n\t");
+        assert_eq!(escape(b"\x1b[0m"), r"\u001b[0m");
+        assert_eq!(escape("ä".as_bytes()), "ä");
+        assert_eq!(escape(&[0xff]), "\u{fffd}");
+    }
+
+    #[test]
+    fn record() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("session.cast");
+
+        let sut = Recorder::new(&path, Some((100, 50)))?;
+        sut.output(b"$ ");
+        sut.input(b"ls\r");
+        sut.resize(120, 40);
+
+        let content = fs::read_to_string(&path)?;
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with(r#"{"version": 2, "width": 100, "height": 50, "timestamp": "#));
+        assert!(lines[1].ends_with(r#", "o", "$ "]"#));
+        assert!(lines[2].ends_with(r#", "i", "ls\r"]"#));
+        assert!(lines[3].ends_with(r#", "r", "120x40"]"#));
+        Ok(())
+    }
+}
@@ -0,0 +1,561 @@
+//! Remote logging of newline delimited JSON records over TCP or TLS.
+
+use crate::container_io::Pipe;
+use anyhow::{bail, Context, Result};
+use getset::{CopyGetters, Getters};
+use serde::Serialize;
+use std::{
+    convert::TryFrom,
+    fs::File,
+    io::BufReader as StdBufReader,
+    marker::Unpin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{self, Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName},
+    TlsConnector,
+};
+use tracing::{debug, trace, warn};
+use tz::UtcDateTime;
+
+#[derive(Debug, Getters)]
+/// The structure used for streaming container output to a remote collector.
+pub struct RemoteLogger {
+    #[getset(get)]
+    /// The `host:port` address of the collector.
+    address: String,
+
+    /// The TLS configuration, if the connection is encrypted.
+    tls: Option<Tls>,
+
+    /// Connection to the collector.
+    connection: Option<Connection>,
+
+    #[getset(get)]
+    /// The full container identifier.
+    container_id: String,
+
+    #[getset(get)]
+    /// The container name, omitted if empty.
+    container_name: String,
+
+    #[getset(get)]
+    /// The tag added to every record, omitted if empty.
+    tag: String,
+
+    /// The backoff of connection attempts after the connection got lost.
+    backoff: Backoff,
+}
+
+#[derive(Clone)]
+/// The TLS configuration of the connection.
+struct Tls {
+    config: Arc<ClientConfig>,
+    server_name: ServerName,
+}
+
+impl std::fmt::Debug for Tls {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tls")
+            .field("server_name", &self.server_name)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+enum Connection {
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+#[derive(Debug, Serialize)]
+/// A single record sent to the collector.
+struct Record<'a> {
+    time: String,
+    stream: String,
+    log: &'a str,
+    container_id: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    container_name: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    tag: &'a str,
+}
+
+impl RemoteLogger {
+    /// Create a new remote logger instance for the collector at `address`, which is either a
+    /// `tcp://host:port` or `tls://host:port` address. Supported options are `remote-capath`
+    /// to trust a custom CA instead of the system roots and `remote-servername` to verify a
+    /// different name than the host.
+    pub fn new(
+        container_id: &str,
+        container_name: &str,
+        address: &str,
+        tag: &str,
+        options: &[(String, String)],
+    ) -> Result<RemoteLogger> {
+        let (encrypted, address) = if let Some(address) = address.strip_prefix("tcp://") {
+            (false, address)
+        } else if let Some(address) = address.strip_prefix("tls://") {
+            (true, address)
+        } else {
+            bail!("unsupported remote log address: {}", address)
+        };
+        let host = match address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                host.trim_start_matches('[').trim_end_matches(']')
+            }
+            _ => bail!("remote log address requires host and port: {}", address),
+        };
+
+        let mut ca_path = None;
+        let mut server_name = host;
+        for (key, value) in options {
+            match key.as_str() {
+                "remote-capath" => ca_path = Some(value.as_str()),
+                "remote-servername" => server_name = value,
+                x => bail!("unsupported remote log option: {}", x),
+            }
+        }
+
+        let tls = if encrypted {
+            Some(Tls {
+                config: Arc::new(Self::tls_config(ca_path)?),
+                server_name: ServerName::try_from(server_name)
+                    .context(format!("invalid TLS server name '{}'", server_name))?,
+            })
+        } else if ca_path.is_some() {
+            bail!("remote log option remote-capath requires a tls:// address")
+        } else {
+            None
+        };
+
+        Ok(Self {
+            address: address.into(),
+            tls,
+            connection: None,
+            container_id: container_id.into(),
+            container_name: container_name.into(),
+            tag: tag.into(),
+            backoff: Backoff::default(),
+        })
+    }
+
+    /// Asynchronously initialize the remote logger by connecting to the collector.
+    pub async fn init(&mut self) -> Result<()> {
+        debug!("Initializing remote logger for {}", self.address());
+        self.connection = Some(self.connect().await?);
+        self.backoff.connected("remote log records");
+        Ok(())
+    }
+
+    /// Write the contents of the provided reader to the collector, one record per line. If the
+    /// connection is lost, then it gets reestablished with exponential backoff, while the
+    /// records are dropped in the meantime.
+    pub async fn write<T>(&mut self, pipe: Pipe, bytes: T) -> Result<()>
+    where
+        T: AsyncBufRead + Unpin,
+    {
+        let mut reader = BufReader::new(bytes);
+        let mut records = vec![];
+        let mut count = 0;
+        loop {
+            let mut line = vec![];
+            let read = reader
+                .read_until(b'\n', &mut line)
+                .await
+                .context("read log line")?;
+            if read == 0 {
+                break;
+            }
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            self.record(pipe, &line, &mut records)?;
+            count += 1;
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        if self.connection.is_none() && !self.reconnect().await {
+            self.backoff.drop_records(count);
+            return Ok(());
+        }
+        if let Err(e) = self.send(&records).await {
+            warn!("Lost connection to remote log collector: {:#}", e);
+            self.connection = None;
+            if !self.reconnect().await || self.send(&records).await.is_err() {
+                self.connection = None;
+                self.backoff.drop_records(count);
+                return Ok(());
+            }
+        }
+        trace!("Wrote {} remote log records", count);
+        Ok(())
+    }
+
+    /// Reconnect to the collector.
+    pub async fn reopen(&mut self) -> Result<()> {
+        debug!("Reopen remote logger");
+        self.init().await
+    }
+
+    /// Append the serialized record of the log line to `records`.
+    fn record(&self, pipe: Pipe, line: &[u8], records: &mut Vec<u8>) -> Result<()> {
+        let now = UtcDateTime::now().context("get UTC datetime")?;
+        let record = Record {
+            time: format!(
+                "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+                now.year(),
+                now.month(),
+                now.month_day(),
+                now.hour(),
+                now.minute(),
+                now.second(),
+                now.nanoseconds()
+            ),
+            stream: pipe.to_string(),
+            log: &String::from_utf8_lossy(line),
+            container_id: self.container_id(),
+            container_name: self.container_name(),
+            tag: self.tag(),
+        };
+        serde_json::to_writer(&mut *records, &record).context("serialize remote log record")?;
+        records.push(b'\n');
+        Ok(())
+    }
+
+    /// Try to reconnect if the backoff delay elapsed and returns whether it succeeded.
+    async fn reconnect(&mut self) -> bool {
+        if !self.backoff.ready() {
+            return false;
+        }
+        match self.connect().await {
+            Ok(connection) => {
+                debug!("Reconnected to remote log collector {}", self.address());
+                self.backoff.connected("remote log records");
+                self.connection = Some(connection);
+                true
+            }
+            Err(e) => {
+                self.backoff.failed("remote log collector", &e);
+                false
+            }
+        }
+    }
+
+    async fn connect(&self) -> Result<Connection> {
+        let stream = TcpStream::connect(self.address())
+            .await
+            .context(format!("connect to remote log address {}", self.address()))?;
+        Ok(match &self.tls {
+            None => Connection::Tcp(stream),
+            Some(tls) => Connection::Tls(Box::new(
+                TlsConnector::from(tls.config.clone())
+                    .connect(tls.server_name.clone(), stream)
+                    .await
+                    .context("establish TLS connection")?,
+            )),
+        })
+    }
+
+    async fn send(&mut self, records: &[u8]) -> Result<()> {
+        match self.connection.as_mut().context("not connected")? {
+            Connection::Tcp(stream) => {
+                stream.write_all(records).await?;
+                stream.flush().await?;
+            }
+            Connection::Tls(stream) => {
+                stream.write_all(records).await?;
+                stream.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the TLS client configuration, which trusts either the CA at `ca_path` or the
+    /// bundled web PKI roots.
+    fn tls_config(ca_path: Option<&str>) -> Result<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        match ca_path {
+            Some(path) => {
+                let mut reader = StdBufReader::new(
+                    File::open(path).context(format!("open remote log CA '{}'", path))?,
+                );
+                let certs = rustls_pemfile::certs(&mut reader).context("parse remote log CA")?;
+                if certs.is_empty() {
+                    bail!("no certificates found in remote log CA '{}'", path)
+                }
+                for cert in certs {
+                    roots.add(&Certificate(cert)).context("add remote log CA")?;
+                }
+            }
+            None => roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|x| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    x.subject,
+                    x.spki,
+                    x.name_constraints,
+                )
+            })),
+        }
+        Ok(rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth())
+    }
+}
+
+#[derive(Debug, CopyGetters)]
+/// The exponential backoff of connection attempts shared by the network log drivers, which also
+/// counts the records dropped while disconnected.
+pub struct Backoff {
+    /// The earliest time of the next connection attempt after a failed one.
+    next_attempt: Option<Instant>,
+
+    /// The delay until the next connection attempt, which doubles on every failed one.
+    delay: Duration,
+
+    #[getset(get_copy = "pub")]
+    /// The amount of records dropped since the last successful connection.
+    dropped: u64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            next_attempt: None,
+            delay: Self::INITIAL_DELAY,
+            dropped: 0,
+        }
+    }
+}
+
+impl Backoff {
+    /// The initial delay between connection attempts.
+    const INITIAL_DELAY: Duration = Duration::from_millis(500);
+
+    /// The maximum delay between connection attempts.
+    const MAX_DELAY: Duration = Duration::from_secs(30);
+
+    /// Returns whether the delay after the last failed connection attempt elapsed.
+    pub fn ready(&self) -> bool {
+        !matches!(self.next_attempt, Some(x) if Instant::now() < x)
+    }
+
+    /// Count records which got dropped while disconnected.
+    pub fn drop_records(&mut self, count: u64) {
+        self.dropped += count;
+    }
+
+    /// Reset the backoff after a successful connection and report the amount of dropped
+    /// `records`.
+    pub fn connected(&mut self, records: &str) {
+        if self.dropped > 0 {
+            warn!("Dropped {} {} while disconnected", self.dropped, records);
+        }
+        self.next_attempt = None;
+        self.delay = Self::INITIAL_DELAY;
+        self.dropped = 0;
+    }
+
+    /// Delay the next connection attempt to `target` after a failed one.
+    pub fn failed(&mut self, target: &str, err: &anyhow::Error) {
+        warn!(
+            "Unable to reconnect to {}, retrying in {:?}: {:#}",
+            target, self.delay, err
+        );
+        self.next_attempt = Some(Instant::now() + self.delay);
+        self.delay = (self.delay * 2).min(Self::MAX_DELAY);
+    }
+
+    #[cfg(test)]
+    /// Allow the next connection attempt immediately.
+    pub fn expire(&mut self) {
+        self.next_attempt = Some(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::fs;
+    use tempfile::tempdir;
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt},
+        net::TcpListener,
+    };
+    use tokio_rustls::{rustls::PrivateKey, TlsAcceptor};
+
+    const ID: &str = "0123456789abcdef";
+
+    async fn read_records<T: AsyncRead + Unpin>(mut stream: T) -> Result<Vec<Value>> {
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await?;
+        Ok(res
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?)
+    }
+
+    #[tokio::test]
+    async fn write_tcp() -> Result<()> {
+        let server = TcpListener::bind("127.0.0.1:0").await?;
+        let address = format!("tcp://{}", server.local_addr()?);
+        let mut sut = RemoteLogger::new(ID, "name", &address, "tag", &[])?;
+        sut.init().await?;
+        let (stream, _) = server.accept().await?;
+
+        sut.write(Pipe::StdOut, "a\nb\n".as_bytes()).await?;
+        sut.write(Pipe::StdErr, "c".as_bytes()).await?;
+        drop(sut);
+
+        let records = read_records(stream).await?;
+        assert_eq!(records.len(), 3);
+        for (record, (log, stream)) in
+            records
+                .iter()
+                .zip(&[("a", "stdout"), ("b", "stdout"), ("c", "stderr")])
+        {
+            assert_eq!(record["log"], *log);
+            assert_eq!(record["stream"], *stream);
+            assert_eq!(record["container_id"], ID);
+            assert_eq!(record["container_name"], "name");
+            assert_eq!(record["tag"], "tag");
+            assert!(record["time"].as_str().context("no time")?.ends_with('Z'));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reconnect() -> Result<()> {
+        let server = TcpListener::bind("127.0.0.1:0").await?;
+        let local_addr = server.local_addr()?;
+        let mut sut = RemoteLogger::new(ID, "", &format!("tcp://{}", local_addr), "", &[])?;
+        sut.init().await?;
+        server.accept().await?;
+
+        // The collector is unavailable, which drops the records
+        drop(server);
+        sut.connection = None;
+        sut.write(Pipe::StdOut, "a\n".as_bytes()).await?;
+        assert_eq!(sut.backoff.dropped(), 1);
+        assert!(!sut.backoff.ready());
+
+        // Records are dropped until the backoff delay elapsed
+        let server = TcpListener::bind(local_addr).await?;
+        sut.write(Pipe::StdOut, "b\n".as_bytes()).await?;
+        assert_eq!(sut.backoff.dropped(), 2);
+
+        sut.backoff.expire();
+        sut.write(Pipe::StdOut, "c\n".as_bytes()).await?;
+        let (stream, _) = server.accept().await?;
+        assert_eq!(sut.backoff.dropped(), 0);
+        drop(sut);
+
+        let records = read_records(stream).await?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["log"], "c");
+        assert!(records[0].get("container_name").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_tls() -> Result<()> {
+        let dir = tempdir()?;
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+        let ca_path = dir.path().join("ca.pem");
+        fs::write(&ca_path, cert.serialize_pem()?)?;
+
+        let server_config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(cert.serialize_der()?)],
+                PrivateKey(cert.serialize_private_key_der()),
+            )?;
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+        let server = TcpListener::bind("127.0.0.1:0").await?;
+        let address = format!("tls://{}", server.local_addr()?);
+
+        let options = [
+            ("remote-capath".into(), ca_path.display().to_string()),
+            ("remote-servername".into(), "localhost".into()),
+        ];
+        let mut sut = RemoteLogger::new(ID, "", &address, "", &options)?;
+        let (init, accepted) = tokio::join!(sut.init(), async {
+            let (stream, _) = server.accept().await?;
+            Ok::<_, anyhow::Error>(acceptor.accept(stream).await?)
+        });
+        init?;
+        let stream = accepted?;
+
+        sut.write(Pipe::StdOut, "secret\n".as_bytes()).await?;
+
+        // The connection is not closed gracefully, so read the single record without EOF
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await?;
+        let record: Value = serde_json::from_str(&line)?;
+        assert_eq!(record["log"], "secret");
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_config() {
+        for (address, options) in [
+            ("localhost:1234", vec![]),
+            ("udp://localhost:1234", vec![]),
+            ("tcp://localhost", vec![]),
+            ("tcp://:1234", vec![]),
+            ("tcp://localhost:1234", vec![("remote-capath", "/ca.pem")]),
+            ("tls://localhost:1234", vec![("remote-unknown", "")]),
+            (
+                "tls://localhost:1234",
+                vec![("remote-capath", "/no/ca.pem")],
+            ),
+        ] {
+            let options = options
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>();
+            assert!(RemoteLogger::new(ID, "", address, "", &options).is_err());
+        }
+    }
+
+    #[test]
+    fn backoff() {
+        let mut sut = Backoff::default();
+        assert!(sut.ready());
+
+        let err = anyhow::format_err!("unavailable");
+        sut.failed("collector", &err);
+        assert!(!sut.ready());
+        assert_eq!(sut.delay, Backoff::INITIAL_DELAY * 2);
+        for _ in 0..10 {
+            sut.failed("collector", &err);
+        }
+        assert_eq!(sut.delay, Backoff::MAX_DELAY);
+
+        sut.drop_records(3);
+        sut.drop_records(2);
+        assert_eq!(sut.dropped(), 5);
+
+        sut.connected("records");
+        assert!(sut.ready());
+        assert_eq!(sut.delay, Backoff::INITIAL_DELAY);
+        assert_eq!(sut.dropped(), 0);
+    }
+
+    #[test]
+    fn ipv6_address() -> Result<()> {
+        let sut = RemoteLogger::new(ID, "", "tls://[::1]:1234", "", &[])?;
+        assert_eq!(sut.address(), "[::1]:1234");
+        Ok(())
+    }
+}
@@ -0,0 +1,365 @@
+//! Restart policies of containers, which let the server create and start a container again
+//! after it exited, like the restart policies of podman.
+
+use crate::{
+    attach::SharedContainerAttach,
+    child::Child,
+    child_reaper::{kill_grandchild, ChildReaper, ExitChannelData, ReapableChild},
+    container_io::{BufferSizes, ContainerIO, ContainerIOType, SharedContainerIO},
+    container_labels::ContainerLabels,
+    container_log::SharedContainerLog,
+    events::{EventKind, EVENTS},
+    flush_policy::FlushPolicy,
+    lifecycle_event::LifecycleEvent,
+};
+use anyhow::{bail, Context, Result};
+use getset::Setters;
+use nix::sys::signal::Signal;
+use std::{fmt, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+use tokio::{
+    process::Command,
+    sync::broadcast::Receiver,
+    time::{self, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// The policy of restarting a container after it exited.
+pub enum RestartPolicy {
+    /// Never restart the container.
+    No,
+
+    /// Restart the container if it exited with a non-zero code, at most the contained amount of
+    /// times where 0 means unlimited.
+    OnFailure(u32),
+
+    /// Always restart the container, which only stops for good once it got removed or the
+    /// server shuts down.
+    Always,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self::No
+    }
+}
+
+impl FromStr for RestartPolicy {
+    type Err = anyhow::Error;
+
+    /// Parse `no`, `always`, `on-failure` or `on-failure:<max restarts>`, where an empty string
+    /// is `no`.
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "" | "no" => Self::No,
+            "always" => Self::Always,
+            "on-failure" => Self::OnFailure(0),
+            x => match x.strip_prefix("on-failure:") {
+                Some(max) => Self::OnFailure(
+                    max.parse()
+                        .context(format!("parse maximum restarts of '{}'", s))?,
+                ),
+                None => bail!("unknown restart policy '{}'", s),
+            },
+        })
+    }
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::No => write!(f, "no"),
+            Self::OnFailure(0) => write!(f, "on-failure"),
+            Self::OnFailure(max) => write!(f, "on-failure:{}", max),
+            Self::Always => write!(f, "always"),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Whether the container gets restarted after exiting with the code, where `restarts` is
+    /// the amount of its previous restarts.
+    pub fn should_restart(self, exit_code: i32, restarts: u32) -> bool {
+        match self {
+            Self::No => false,
+            Self::OnFailure(max) => exit_code != 0 && (max == 0 || restarts < max),
+            Self::Always => true,
+        }
+    }
+}
+
+#[derive(Debug, Default, Setters)]
+#[getset(set = "pub")]
+/// Restarts a container according to its policy, which takes over writing the exit paths and
+/// running the cleanup command once the container exited for good.
+pub struct Supervisor {
+    policy: RestartPolicy,
+    id: String,
+    runtime: PathBuf,
+
+    /// The arguments of the runtime `create`, whose console socket gets replaced on restarts.
+    args: Vec<String>,
+    pidfile: PathBuf,
+    exit_paths: Vec<PathBuf>,
+    oom_exit_paths: Vec<PathBuf>,
+    cleanup_cmd: Vec<String>,
+    labels: Arc<ContainerLabels>,
+
+    /// The settings of the container IO, which gets created again on every restart, where the
+    /// log drivers and attach clients stay connected.
+    terminal: bool,
+    stdin: bool,
+    merge_stderr: bool,
+    flush_policy: FlushPolicy,
+    buffer_sizes: BufferSizes,
+    logger: SharedContainerLog,
+    attach: SharedContainerAttach,
+
+    reaper: Arc<ChildReaper>,
+
+    /// Cancelled once the server started draining, which stops restarting the container.
+    draining: CancellationToken,
+
+    /// The time after which the container gets removed once it exited for good.
+    exited_ttl: Duration,
+}
+
+impl Supervisor {
+    /// The delay before the first restart, which doubles with every consecutive restart.
+    const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+    /// The maximum delay between restarts.
+    const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+    /// Containers running at least this long before exiting restart after the initial delay.
+    const BACKOFF_RESET: Duration = Duration::from_secs(10);
+
+    /// The prefix of the runtime argument referencing the console socket.
+    const CONSOLE_SOCKET_ARG: &'static str = "--console-socket=";
+
+    /// The delay before the next restart after the amount of consecutive restarts.
+    fn backoff(consecutive: u32) -> Duration {
+        Self::INITIAL_BACKOFF
+            .checked_mul(2u32.saturating_pow(consecutive))
+            .map_or(Self::MAX_BACKOFF, |x| x.min(Self::MAX_BACKOFF))
+    }
+
+    /// Watch the exits of the container with the provided PID and restart it until the policy
+    /// does not allow it anymore, the container got stopped or removed or the server started
+    /// draining.
+    pub async fn supervise(self, mut pid: u32, mut exit_rx: Receiver<ExitChannelData>) {
+        let mut restarts = 0;
+        let mut consecutive = 0;
+        let mut started = Instant::now();
+        loop {
+            let exit_code = match exit_rx.recv().await {
+                Ok(exit) => *exit.exit_code(),
+                Err(e) => {
+                    error!("Unable to receive exit of container {}: {}", self.id, e);
+                    return;
+                }
+            };
+            if !self.policy.should_restart(exit_code, restarts) || !self.tracked(pid) {
+                return self.finish(pid, exit_code).await;
+            }
+
+            if started.elapsed() >= Self::BACKOFF_RESET {
+                consecutive = 0;
+            }
+            let delay = Self::backoff(consecutive);
+            debug!(
+                "Restarting container {} exited with {} in {:?}",
+                self.id, exit_code, delay
+            );
+            tokio::select! {
+                _ = time::sleep(delay) => {}
+                _ = self.draining.cancelled() => {}
+            }
+            if self.draining.is_cancelled() || !self.tracked(pid) {
+                debug!("Stopping to restart container {}", self.id);
+                return self.finish(pid, exit_code).await;
+            }
+
+            restarts += 1;
+            consecutive += 1;
+            match self.restart(restarts).await {
+                Ok((restarted_pid, rx)) => {
+                    info!(
+                        "Restarted container {} with PID {} after {} restarts",
+                        self.id, restarted_pid, restarts
+                    );
+                    pid = restarted_pid;
+                    exit_rx = rx;
+                    started = Instant::now();
+                }
+                Err(e) => {
+                    error!("Unable to restart container {}: {:#}", self.id, e);
+                    return self.finish(pid, exit_code).await;
+                }
+            }
+        }
+    }
+
+    /// Whether the container process is still the tracked one, which is not the case once the
+    /// container got removed. Stopped containers are not tracked anymore either.
+    fn tracked(&self, pid: u32) -> bool {
+        self.reaper
+            .get(&self.id)
+            .map_or(false, |child| child.pid() == pid && !child.stopped())
+    }
+
+    /// Delete the exited container and create and start it again, which resolves to its new PID
+    /// and exit receiver.
+    async fn restart(&self, restarts: u32) -> Result<(u32, Receiver<ExitChannelData>)> {
+        self.run_runtime(&["delete", "--force"])
+            .await
+            .context("delete exited container")?;
+
+        let mut container_io = ContainerIO::new(
+            self.terminal,
+            self.logger.clone(),
+            self.attach.clone(),
+            self.buffer_sizes,
+        )?;
+        container_io.set_stdin(self.stdin);
+        container_io.set_merge_stderr(self.merge_stderr);
+        container_io.set_flush_policy(self.flush_policy);
+
+        let args = self.args(&container_io);
+        let (pid, token) = self
+            .reaper
+            .create_child(&self.runtime, &args, &mut container_io, &self.pidfile)
+            .await
+            .context("create container")?;
+        if let Err(e) = self.run_runtime(&["start"]).await {
+            kill_grandchild(pid, Signal::SIGKILL);
+            return Err(e.context("start container"));
+        }
+
+        let mut child = Child::new(
+            self.id.clone(),
+            pid,
+            vec![],
+            self.oom_exit_paths.clone(),
+            None,
+            SharedContainerIO::new(container_io),
+            vec![],
+            token,
+        );
+        child.set_labels(self.labels.clone());
+        child.set_restarts(restarts);
+        let exit_rx = match self.reaper.watch_restarted(child) {
+            Ok(exit_rx) => exit_rx,
+            Err(e) => {
+                kill_grandchild(pid, Signal::SIGKILL);
+                return Err(e);
+            }
+        };
+
+        EVENTS.publish(&self.id, EventKind::Restarted(restarts));
+        if let Err(e) = self
+            .logger
+            .write()
+            .await
+            .write_event(LifecycleEvent::Restarted(restarts))
+            .await
+        {
+            error!("Unable to write restart event: {:#}", e);
+        }
+        Ok((pid, exit_rx))
+    }
+
+    /// The runtime `create` arguments referencing the console socket of the container IO.
+    fn args(&self, container_io: &ContainerIO) -> Vec<String> {
+        let mut args = self.args.clone();
+        if let ContainerIOType::Terminal(terminal) = container_io.typ() {
+            for arg in args
+                .iter_mut()
+                .filter(|x| x.starts_with(Self::CONSOLE_SOCKET_ARG))
+            {
+                *arg = format!("{}{}", Self::CONSOLE_SOCKET_ARG, terminal.path().display());
+            }
+        }
+        args
+    }
+
+    /// Run the runtime subcommand for the container, using the global arguments of the create.
+    async fn run_runtime(&self, subcommand: &[&str]) -> Result<()> {
+        let global_args = self
+            .args
+            .iter()
+            .take_while(|x| *x != "create")
+            .collect::<Vec<_>>();
+        let output = Command::new(&self.runtime)
+            .args(global_args)
+            .args(subcommand)
+            .arg(&self.id)
+            .output()
+            .await
+            .context(format!("run runtime {}", subcommand.join(" ")))?;
+        if !output.status.success() {
+            bail!(
+                "runtime {} failed with {}: {}",
+                subcommand.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        }
+        Ok(())
+    }
+
+    /// Write the exit paths and run the cleanup command for the final exit of the container,
+    /// which gets removed once its time to live elapsed.
+    async fn finish(mut self, pid: u32, exit_code: i32) {
+        if let Err(e) = ReapableChild::write_to_exit_paths(exit_code, &self.exit_paths).await {
+            warn!("Could not write exit paths of {}: {:#}", self.id, e);
+        }
+        if !self.cleanup_cmd.is_empty() {
+            ReapableChild::spawn_cleanup_process(&mut self.cleanup_cmd).await;
+        }
+        self.reaper.expire(&self.id, pid, self.exited_ttl).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<()> {
+        for (s, expected) in [
+            ("", RestartPolicy::No),
+            ("no", RestartPolicy::No),
+            ("always", RestartPolicy::Always),
+            ("on-failure", RestartPolicy::OnFailure(0)),
+            ("on-failure:3", RestartPolicy::OnFailure(3)),
+        ] {
+            assert_eq!(s.parse::<RestartPolicy>()?, expected);
+        }
+        for s in ["never", "on-failure:", "on-failure:-1", "always:3"] {
+            assert!(s.parse::<RestartPolicy>().is_err(), "{}", s);
+        }
+        assert_eq!(RestartPolicy::OnFailure(3).to_string(), "on-failure:3");
+        assert_eq!(RestartPolicy::OnFailure(0).to_string(), "on-failure");
+        Ok(())
+    }
+
+    #[test]
+    fn should_restart() {
+        assert!(!RestartPolicy::No.should_restart(1, 0));
+        assert!(RestartPolicy::Always.should_restart(0, 100));
+        assert!(RestartPolicy::OnFailure(0).should_restart(1, 100));
+        assert!(!RestartPolicy::OnFailure(0).should_restart(0, 0));
+        assert!(RestartPolicy::OnFailure(2).should_restart(137, 1));
+        assert!(!RestartPolicy::OnFailure(2).should_restart(137, 2));
+    }
+
+    #[test]
+    fn backoff() {
+        assert_eq!(Supervisor::backoff(0), Duration::from_millis(100));
+        assert_eq!(Supervisor::backoff(3), Duration::from_millis(800));
+        assert_eq!(Supervisor::backoff(20), Supervisor::MAX_BACKOFF);
+        assert_eq!(Supervisor::backoff(u32::MAX), Supervisor::MAX_BACKOFF);
+    }
+}
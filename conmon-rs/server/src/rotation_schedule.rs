@@ -0,0 +1,215 @@
+//! Time based log rotation schedules.
+
+use anyhow::{bail, format_err, Context, Result};
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tz::{DateTime, TimeZone, TimeZoneRef};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A cron like schedule with minute resolution, evaluated in local time.
+pub struct RotationSchedule {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    month_days: Vec<bool>,
+    months: Vec<bool>,
+    week_days: Vec<bool>,
+
+    /// Whether the day of month or the day of week is unrestricted, which follows the cron
+    /// semantics of matching either day field if both are restricted.
+    any_month_day: bool,
+    any_week_day: bool,
+}
+
+impl FromStr for RotationSchedule {
+    type Err = anyhow::Error;
+
+    /// Parse `hourly`, `daily`, `weekly` or a five field cron expression of the form
+    /// `minute hour day-of-month month day-of-week`. Fields support `*`, values, ranges (`a-b`),
+    /// steps (`*/n`, `a-b/n`) and comma separated lists of them.
+    fn from_str(s: &str) -> Result<Self> {
+        let expression = match s.trim() {
+            "hourly" => "0 * * * *",
+            "daily" => "0 0 * * *",
+            "weekly" => "0 0 * * 0",
+            x => x,
+        };
+
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            bail!(
+                "rotation schedule '{}' needs five fields, got {}",
+                s,
+                fields.len()
+            )
+        }
+
+        let mut week_days = Self::parse_field(fields[4], 0, 7).context("parse day of week")?;
+        // Both 0 and 7 are Sunday
+        if week_days.pop() == Some(true) {
+            week_days[0] = true;
+        }
+
+        Ok(Self {
+            minutes: Self::parse_field(fields[0], 0, 59).context("parse minute")?,
+            hours: Self::parse_field(fields[1], 0, 23).context("parse hour")?,
+            month_days: Self::parse_field(fields[2], 1, 31).context("parse day of month")?,
+            months: Self::parse_field(fields[3], 1, 12).context("parse month")?,
+            week_days,
+            any_month_day: fields[2] == "*",
+            any_week_day: fields[4] == "*",
+        })
+    }
+}
+
+impl RotationSchedule {
+    /// The maximum time span searched for the next match, which covers leap days.
+    const MAX_SEARCH_MINUTES: i64 = 4 * 366 * 24 * 60;
+
+    /// Returns the time until the next scheduled rotation in local time.
+    pub fn next_delay(&self) -> Result<Duration> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("get current time")?;
+        let local_tz = TimeZone::local().context("get local timezone")?;
+        let next = self.next_after(now.as_secs() as i64, local_tz.as_ref())?;
+        Ok(Duration::from_secs(next as u64).saturating_sub(now))
+    }
+
+    /// Returns the first unix time strictly after `unix_time` that matches the schedule.
+    fn next_after(&self, unix_time: i64, time_zone: TimeZoneRef) -> Result<i64> {
+        let start = (unix_time.div_euclid(60) + 1) * 60;
+        for minute in 0..Self::MAX_SEARCH_MINUTES {
+            let time = start + minute * 60;
+            let date_time = DateTime::from_timespec(time, 0, time_zone)
+                .context("convert unix time to local time")?;
+            if self.matches(&date_time) {
+                return Ok(time);
+            }
+        }
+        Err(format_err!("rotation schedule never matches"))
+    }
+
+    fn matches(&self, date_time: &DateTime) -> bool {
+        let month_day = self.month_days[date_time.month_day() as usize - 1];
+        let week_day = self.week_days[date_time.week_day() as usize];
+        let day = match (self.any_month_day, self.any_week_day) {
+            (false, false) => month_day || week_day,
+            _ => month_day && week_day,
+        };
+
+        day && self.minutes[date_time.minute() as usize]
+            && self.hours[date_time.hour() as usize]
+            && self.months[date_time.month() as usize - 1]
+    }
+
+    /// Parse a single cron field into a set of the matching values in `min..=max`.
+    fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>> {
+        let mut values = vec![false; (max - min + 1) as usize];
+        for item in field.split(',') {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>().context("parse step")?),
+                None => (item, 1),
+            };
+            if step == 0 {
+                bail!("step of '{}' must not be zero", item)
+            }
+
+            let (start, end) = match range.split_once('-') {
+                _ if range == "*" => (min, max),
+                Some((start, end)) => (
+                    start.parse().context("parse range start")?,
+                    end.parse().context("parse range end")?,
+                ),
+                None => {
+                    let value = range.parse().context("parse value")?;
+                    (value, value)
+                }
+            };
+            if start < min || end > max || start > end {
+                bail!("'{}' is out of range {}-{}", item, min, max)
+            }
+
+            for value in (start..=end).step_by(step as usize) {
+                values[(value - min) as usize] = true;
+            }
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2022-01-01T00:00:00Z, a Saturday
+    const NEW_YEAR: i64 = 1_640_995_200;
+
+    fn next(schedule: &str, unix_time: i64) -> Result<i64> {
+        schedule
+            .parse::<RotationSchedule>()?
+            .next_after(unix_time, TimeZoneRef::utc())
+    }
+
+    #[test]
+    fn hourly() -> Result<()> {
+        assert_eq!(next("hourly", NEW_YEAR)?, NEW_YEAR + 3600);
+        assert_eq!(next("hourly", NEW_YEAR + 1)?, NEW_YEAR + 3600);
+        assert_eq!(next("hourly", NEW_YEAR - 1)?, NEW_YEAR);
+        Ok(())
+    }
+
+    #[test]
+    fn daily_weekly() -> Result<()> {
+        assert_eq!(next("daily", NEW_YEAR)?, NEW_YEAR + 86400);
+        assert_eq!(next("weekly", NEW_YEAR)?, NEW_YEAR + 86400);
+        assert_eq!(next("0 0 * * 7", NEW_YEAR)?, NEW_YEAR + 86400);
+        Ok(())
+    }
+
+    #[test]
+    fn cron_expression() -> Result<()> {
+        assert_eq!(next("*/15 * * * *", NEW_YEAR)?, NEW_YEAR + 15 * 60);
+        assert_eq!(
+            next("30 2-4 * * *", NEW_YEAR)?,
+            NEW_YEAR + 2 * 3600 + 30 * 60
+        );
+        assert_eq!(
+            next("0 12 15 1 *", NEW_YEAR)?,
+            NEW_YEAR + 14 * 86400 + 12 * 3600
+        );
+        assert_eq!(next("0 0 3,5 * *", NEW_YEAR)?, NEW_YEAR + 2 * 86400);
+        // Either the day of month or the day of week has to match
+        assert_eq!(next("0 0 15 * 1", NEW_YEAR)?, NEW_YEAR + 2 * 86400);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        for schedule in &[
+            "",
+            "monthly",
+            "* * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(
+                schedule.parse::<RotationSchedule>().is_err(),
+                "{}",
+                schedule
+            );
+        }
+    }
+
+    #[test]
+    fn never_matches() {
+        assert!(next("0 0 31 2 *", NEW_YEAR).is_err());
+    }
+}
@@ -1,22 +1,55 @@
 use crate::{
+    attach::{AttachOptions, SharedContainerAttach},
+    capabilities::Capabilities,
+    cgroup_stats::{CgroupStats, StatsGroup},
     child::Child,
-    container_io::{ContainerIO, SharedContainerIO},
+    child_reaper::{kill_grandchild, ChildState, ReapableChild},
+    container_filter::ContainerFilter,
+    container_io::{BufferSizes, ContainerIO, Pipe, SharedContainerIO},
+    container_labels::ContainerLabels,
     container_log::ContainerLog,
+    events::{Event, EventKind, EVENTS},
+    extra_fd::{ExtraFd, ExtraFdSink},
+    flush_policy::FlushPolicy,
+    lifecycle_event::LifecycleEvent,
+    log_timestamp::TimestampFormat,
+    passthrough_logger::PassthroughLogger,
+    rate_limiter::{RateLimitMode, RateLimiter},
+    redaction::{RedactionRule, Redactor},
+    restart_policy::{RestartPolicy, Supervisor},
     server::Server,
+    status::ProcessStatus,
+    terminal_mode::TerminalModeChange,
     version::Version,
 };
-use anyhow::format_err;
+use anyhow::{format_err, Context};
 use capnp::{capability::Promise, Error};
 use capnp_rpc::pry;
-use conmon_common::conmon_capnp::conmon;
+use conmon_common::conmon_capnp::conmon::{
+    self, event, ContainerInfoField, ContainerState, ContainerStatsGroup, ExecStreamPipe,
+    LogRateLimitMode, LogTimestampFormat,
+};
+use futures::{stream, StreamExt};
+use nix::sys::signal::Signal;
 use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs::File,
+    future::Future,
+    mem,
+    os::unix::io::RawFd,
     path::{Path, PathBuf},
     str,
-    time::Duration,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::broadcast::error::RecvError,
+    task,
+    time::{self, Instant},
 };
-use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, debug_span, error, Instrument};
+use tracing::{debug, debug_span, error, info, Instrument};
 use uuid::Uuid;
 
 macro_rules! pry_err {
@@ -59,34 +92,179 @@ macro_rules! capnp_vec {
     };
 }
 
-impl conmon::Server for Server {
-    /// Retrieve version information from the server.
-    fn version(
+macro_rules! set_labels {
+    ($builder:expr, $labels:expr) => {
+        ContainerLabels::set_entries(
+            $builder
+                .reborrow()
+                .init_labels($labels.labels().len() as u32),
+            $labels.labels(),
+        );
+        ContainerLabels::set_entries(
+            $builder
+                .reborrow()
+                .init_annotations($labels.annotations().len() as u32),
+            $labels.annotations(),
+        );
+    };
+}
+
+/// The description of the `overloaded` errors of requests cancelled at their deadline.
+pub(crate) const DEADLINE_EXCEEDED: &str = "deadline exceeded";
+
+/// Create the promise of a request, whose future gets dropped to cancel the request once the
+/// deadline elapsed.
+fn promise_until<F, T>(deadline: Option<Instant>, future: F) -> Promise<T, Error>
+where
+    F: Future<Output = Result<T, Error>> + 'static,
+    T: 'static,
+{
+    match deadline {
+        Some(deadline) => Promise::from_future(async move {
+            time::timeout_at(deadline, future)
+                .await
+                .unwrap_or_else(|_| Err(Error::overloaded(DEADLINE_EXCEEDED.into())))
+        }),
+        None => Promise::from_future(future),
+    }
+}
+
+/// The time to kill an executed command, which is the earlier one of its timeout in seconds
+/// and the request deadline.
+fn command_deadline(timeout: u64, deadline: Option<Instant>) -> Option<Instant> {
+    let timeout = (timeout > 0).then(|| Instant::now() + Duration::from_secs(timeout));
+    match (timeout, deadline) {
+        (Some(timeout), Some(deadline)) => Some(timeout.min(deadline)),
+        (timeout, deadline) => timeout.or(deadline),
+    }
+}
+
+/// The Cap'n Proto state of the tracked child state.
+fn container_state(state: ChildState) -> ContainerState {
+    match state {
+        ChildState::Created => ContainerState::Created,
+        ChildState::Running => ContainerState::Running,
+        ChildState::Exited => ContainerState::Exited,
+    }
+}
+
+/// The nanoseconds since the epoch of the time, 0 if it is unset.
+fn unix_nano(time: Option<SystemTime>) -> i64 {
+    time.and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |x| x.as_nanos() as i64)
+}
+
+/// Fill the Cap'n Proto event from the published one and the labels of its container.
+fn set_event(mut builder: event::Builder<'_>, event: &Event, labels: &ContainerLabels) {
+    set_labels!(builder, labels);
+    builder.set_id(event.container_id());
+    builder.set_exec_session_id(event.exec_session_id());
+    builder.set_timestamp_unix_nano(
+        event
+            .timestamp()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as i64,
+    );
+    builder.set_type(match event.kind() {
+        EventKind::Exited(exit_code) => {
+            builder.set_exit_code(exit_code);
+            event::Type::Exited
+        }
+        EventKind::OomKilled => event::Type::OomKilled,
+        EventKind::LogRotated => event::Type::LogRotated,
+        EventKind::AttachConnected => event::Type::AttachConnected,
+        EventKind::AttachDisconnected => event::Type::AttachDisconnected,
+        EventKind::Restarted(restarts) => {
+            builder.set_restart_count(restarts);
+            event::Type::Restarted
+        }
+    });
+}
+
+/// Fill the Cap'n Proto exec session info from the tracked exec session.
+fn set_exec_session_info(
+    mut builder: conmon::exec_session_info::Builder<'_>,
+    child: &ReapableChild,
+    runtime: &Path,
+) -> anyhow::Result<()> {
+    builder.set_exec_session_id(child.exec_session_id().as_deref().unwrap_or_default());
+    builder.set_pid(child.pid());
+    builder.set_state(container_state(child.state(runtime)?));
+    if let Some(exit_code) = child.exit_code()? {
+        builder.set_exit_code(exit_code);
+    }
+    builder.set_created_unix_nano(unix_nano(Some(child.created())));
+    builder.set_timeout_unix_nano(unix_nano(
+        child
+            .timeout()
+            .map(|x| SystemTime::now() + x.saturating_duration_since(Instant::now())),
+    ));
+    let mut command = builder.init_command(child.command().len() as u32);
+    for (i, arg) in child.command().iter().enumerate() {
+        command.set(i as u32, arg);
+    }
+    Ok(())
+}
+
+impl Server {
+    /// The ID of a new exec session of the container, which gets generated if none was
+    /// requested. Fails if the container has an active exec session with the requested ID.
+    fn exec_session_id(&self, id: &str, requested: &str) -> anyhow::Result<String> {
+        if requested.is_empty() {
+            return Ok(Uuid::new_v4().to_string());
+        }
+        if self.reaper().get_exec_session(id, requested).is_ok() {
+            return Err(format_err!(
+                "exec session {} of container {} already exists",
+                requested,
+                id
+            ));
+        }
+        Ok(requested.into())
+    }
+
+    /// The labels and annotations of the tracked container, which are empty for unknown ones.
+    fn container_labels(&self, id: &str) -> Arc<ContainerLabels> {
+        self.reaper()
+            .get(id)
+            .map(|x| x.labels().clone())
+            .unwrap_or_default()
+    }
+
+    /// Create a new container for the provided request, resolving to the PID of the container.
+    /// Requests with an idempotency key replay the result of a previous create using the key.
+    fn create(
         &mut self,
-        _: conmon::VersionParams,
-        mut results: conmon::VersionResults,
-    ) -> Promise<(), capnp::Error> {
-        debug!("Got a version request");
-        let mut response = results.get().init_response();
-        let version = Version::new();
-        response.set_version(version.version());
-        response.set_tag(version.tag());
-        response.set_commit(version.commit());
-        response.set_build_date(version.build_date());
-        response.set_rust_version(version.rust_version());
-        response.set_process_id(std::process::id());
-        Promise::ok(())
+        req: conmon::create_container_request::Reader<'_>,
+        deadline: Option<Instant>,
+    ) -> Promise<u32, Error> {
+        let key = pry!(req.get_idempotency_key());
+        let window = self.config().idempotency_window();
+        if key.is_empty() || window == 0 {
+            return self.create_now(req, deadline);
+        }
+
+        let id = pry!(req.get_id());
+        let cache = self.idempotency().clone();
+        let pending = pry_err!(cache.claim(key, id, Duration::from_secs(window), || {
+            let create = self.create_now(req, None);
+            async move { create.await.map_err(|e| e.description) }
+        }));
+        promise_until(
+            deadline,
+            async move { capnp_err!(pending.result().await) }.instrument(debug_span!("promise")),
+        )
     }
 
-    /// Create a new container for the provided parameters.
-    fn create_container(
+    /// Create a new container without considering the idempotency key of the request.
+    fn create_now(
         &mut self,
-        params: conmon::CreateContainerParams,
-        mut results: conmon::CreateContainerResults,
-    ) -> Promise<(), capnp::Error> {
-        let req = pry!(pry!(params.get()).get_request());
+        req: conmon::create_container_request::Reader<'_>,
+        deadline: Option<Instant>,
+    ) -> Promise<u32, Error> {
         let id = pry!(req.get_id()).to_string();
-        let cleanup_cmd: Vec<String> = pry!(pry!(req.get_cleanup_cmd())
+        let mut cleanup_cmd: Vec<String> = pry!(pry!(req.get_cleanup_cmd())
             .iter()
             .map(|s| s.map(String::from))
             .collect());
@@ -95,11 +273,173 @@ impl conmon::Server for Server {
         let _enter = span.enter();
 
         debug!("Got a create container request");
+        pry_err!(self.ensure_accepting());
+        pry_err!(self.reaper().ensure_replaceable(&id));
+        let restart_policy: RestartPolicy = pry_err!(pry!(req.get_restart_policy()).parse());
+        if restart_policy != RestartPolicy::No
+            && (!pry!(req.get_stdin_path()).is_empty()
+                || req.get_stdin_fd() != 0
+                || req.get_stdout_fd() != 0
+                || req.get_stderr_fd() != 0
+                || !pry!(req.get_extra_fds()).is_empty())
+        {
+            pry_err!(Err(format_err!(
+                "restart policy {} does not support stdin, stdout and stderr files or extra fds",
+                restart_policy
+            )))
+        }
+        let stop_signal = match req.get_stop_signal() {
+            0 => Signal::SIGTERM,
+            x => pry_err!(Signal::try_from(x).context(format!("invalid stop signal {}", x))),
+        };
+        let attach_replay_size = req.get_attach_replay_size() as usize;
+        if attach_replay_size > self.config().max_attach_replay_size() {
+            pry_err!(Err(format_err!(
+                "attach replay size {} exceeds the maximum of {} bytes",
+                attach_replay_size,
+                self.config().max_attach_replay_size()
+            )))
+        }
+        let recent_output_size = req.get_recent_output_size() as usize;
+        if recent_output_size > self.config().max_recent_output_size() {
+            pry_err!(Err(format_err!(
+                "recent output size {} exceeds the maximum of {} bytes",
+                recent_output_size,
+                self.config().max_recent_output_size()
+            )))
+        }
+        let reservation = pry!(self
+            .capacity()
+            .reserve(pry_err!(self.reaper().running_containers()))
+            .map_err(|e| Error::overloaded(e.to_string())));
 
         let log_drivers = pry!(req.get_log_drivers());
-        let container_log = pry_err!(ContainerLog::from(log_drivers));
-        let mut container_io =
-            pry_err!(ContainerIO::new(req.get_terminal(), container_log.clone()));
+        let rate_limiter = match req.get_log_rate_limit() {
+            0 => None,
+            rate => Some(RateLimiter::new(
+                rate,
+                req.get_log_rate_burst(),
+                match pry!(req.get_log_rate_limit_mode()) {
+                    LogRateLimitMode::Drop => RateLimitMode::Drop,
+                    LogRateLimitMode::Throttle => RateLimitMode::Throttle,
+                },
+            )),
+        };
+        let mut redaction_rules = vec![];
+        for redaction in pry!(req.get_log_redactions()).iter() {
+            redaction_rules.push(pry_err!(RedactionRule::new(
+                pry!(redaction.get_pattern()),
+                pry!(redaction.get_replacement())
+            )));
+        }
+        let mut metadata = HashMap::new();
+        for entry in pry!(req.get_metadata()).iter() {
+            metadata.insert(
+                pry!(entry.get_key()).to_string(),
+                pry!(entry.get_value()).to_string(),
+            );
+        }
+        let labels = Arc::new(pry!(ContainerLabels::from_lists(
+            pry!(req.get_labels()),
+            pry!(req.get_annotations())
+        )));
+        let container_log = pry_err!(ContainerLog::from(
+            &id,
+            log_drivers,
+            self.log_quota().clone(),
+            rate_limiter,
+            Redactor::new(redaction_rules),
+            &metadata,
+            &labels,
+            match pry!(req.get_log_timestamp_format()) {
+                LogTimestampFormat::Rfc3339Nano => TimestampFormat::Rfc3339Nano,
+                LogTimestampFormat::UnixNano => TimestampFormat::UnixNano,
+                LogTimestampFormat::None => TimestampFormat::None,
+            },
+            self.fd_socket(),
+        ));
+        let defaults = pry_err!(self.config().buffer_sizes());
+        let buffer_sizes = pry_err!(BufferSizes::new(
+            match req.get_stdin_buffer_size() {
+                0 => defaults.stdin(),
+                x => x as usize,
+            },
+            match req.get_output_buffer_size() {
+                0 => defaults.output(),
+                x => x as usize,
+            },
+            match req.get_attach_packet_size() {
+                0 => defaults.attach_packet(),
+                x => x as usize,
+            },
+        ));
+        let attach = SharedContainerAttach::new(attach_replay_size).with_container_id(&id);
+        let mut container_io = pry_err!(ContainerIO::new(
+            req.get_terminal(),
+            container_log.clone(),
+            attach,
+            buffer_sizes
+        ));
+        container_io.set_merge_stderr(req.get_merge_stderr());
+        container_io.set_stdin(req.get_stdin());
+        let mut extra_fds: Vec<ExtraFd> = vec![];
+        for extra_fd in pry!(req.get_extra_fds()).iter() {
+            let fd = extra_fd.get_fd() as RawFd;
+            if extra_fds.iter().any(|x| x.fd() == fd) {
+                pry_err!(Err(format_err!("duplicate extra fd {}", fd)))
+            }
+            let path = PathBuf::from(pry!(extra_fd.get_path()));
+            extra_fds.push(pry_err!(ExtraFd::new(
+                fd,
+                match pry!(extra_fd.get_type()) {
+                    conmon::extra_fd::Type::File => ExtraFdSink::File(path),
+                    conmon::extra_fd::Type::Socket => ExtraFdSink::Socket(path),
+                }
+            )));
+        }
+        container_io.set_extra_fds(extra_fds);
+        let flush_size = match req.get_flush_size() {
+            0 => FlushPolicy::DEFAULT_SIZE,
+            x => x as usize,
+        };
+        let flush_max_delay = Duration::from_millis(req.get_flush_max_delay_ms());
+        let flush_policy = match pry!(req.get_flush_policy()) {
+            conmon::FlushPolicy::Immediate => FlushPolicy::Immediate,
+            conmon::FlushPolicy::Line => FlushPolicy::Line {
+                size: flush_size,
+                max_delay: flush_max_delay,
+            },
+            conmon::FlushPolicy::Block => FlushPolicy::Block {
+                size: flush_size,
+                max_delay: flush_max_delay,
+            },
+        };
+        container_io.set_flush_policy(flush_policy);
+        container_io.set_stdin_file(match (pry!(req.get_stdin_path()), req.get_stdin_fd()) {
+            ("", 0) => None,
+            _ if !req.get_stdin() => {
+                pry_err!(Err(format_err!("stdin path and stdin fd require stdin")))
+            }
+            (path, 0) => Some(pry_err!(
+                File::open(path).context(format!("open stdin file {}", path))
+            )),
+            ("", slot) => Some(pry_err!(self.fd_socket().take(slot))),
+            _ => pry_err!(Err(format_err!(
+                "stdin path and stdin fd are mutually exclusive"
+            ))),
+        });
+        container_io.set_stdout_file(match req.get_stdout_fd() {
+            0 => None,
+            slot => Some(pry_err!(self.fd_socket().take(slot).context("get stdout"))),
+        });
+        container_io.set_stderr_file(match req.get_stderr_fd() {
+            0 => None,
+            slot => Some(pry_err!(self.fd_socket().take(slot).context("get stderr"))),
+        });
+        let (width, height) = (req.get_terminal_width(), req.get_terminal_height());
+        if width > 0 && height > 0 {
+            pry_err!(container_io.set_initial_window_size(width, height));
+        }
 
         let bundle_path = Path::new(pry!(req.get_bundle_path()));
         let pidfile = bundle_path.join("pidfile");
@@ -117,13 +457,70 @@ impl conmon::Server for Server {
             command_args
         ));
         let runtime = self.config().runtime().clone();
-        let exit_paths = capnp_vec_path!(req.get_exit_paths());
+        let mut exit_paths = capnp_vec_path!(req.get_exit_paths());
         let oom_exit_paths = capnp_vec_path!(req.get_oom_exit_paths());
+        let exited_ttl = Duration::from_secs(self.config().exited_container_ttl());
+        let supervisor = match restart_policy {
+            RestartPolicy::No => None,
+            policy => {
+                // The supervisor takes over the exit paths and the cleanup command, which are
+                // only used once the container exited for good.
+                let mut supervisor = Supervisor::default();
+                supervisor
+                    .set_policy(policy)
+                    .set_id(id.clone())
+                    .set_runtime(runtime.clone())
+                    .set_args(args.clone())
+                    .set_pidfile(pidfile.clone())
+                    .set_exit_paths(mem::take(&mut exit_paths))
+                    .set_oom_exit_paths(oom_exit_paths.clone())
+                    .set_cleanup_cmd(mem::take(&mut cleanup_cmd))
+                    .set_labels(labels.clone())
+                    .set_terminal(req.get_terminal())
+                    .set_stdin(container_io.stdin())
+                    .set_merge_stderr(container_io.merge_stderr())
+                    .set_flush_policy(flush_policy)
+                    .set_buffer_sizes(container_io.buffer_sizes())
+                    .set_logger(container_log.clone())
+                    .set_attach(container_io.attach().clone())
+                    .set_reaper(self.reaper().clone())
+                    .set_draining(self.draining().clone())
+                    .set_exited_ttl(exited_ttl);
+                Some(supervisor)
+            }
+        };
+        let lifecycle_events = req.get_log_lifecycle_events();
+        let tee = match (req.get_tee_stdout_fd(), req.get_tee_stderr_fd()) {
+            (0, 0) => None,
+            (0, _) => pry_err!(Err(format_err!("tee stderr fd requires a tee stdout fd"))),
+            (stdout, stderr) => Some(PassthroughLogger::new(
+                pry_err!(self.fd_socket().take(stdout).context("get tee stdout")),
+                match stderr {
+                    0 => None,
+                    slot => Some(pry_err!(self
+                        .fd_socket()
+                        .take(slot)
+                        .context("get tee stderr"))),
+                },
+            )),
+        };
 
-        Promise::from_future(
+        let admission = self.admission().clone();
+        promise_until(
+            deadline,
             async move {
-                capnp_err!(container_log.write().await.init().await)?;
+                // Keep the slot until the container is tracked or its create failed.
+                let _reservation = reservation;
+                capnp_err!(child_reaper.replace(&id).await)?;
+                {
+                    let mut locked_log = container_log.write().await;
+                    locked_log.set_lifecycle_events(lifecycle_events);
+                    locked_log.set_tee(tee);
+                    locked_log.set_recent_output_size(recent_output_size);
+                    capnp_err!(locked_log.init().await)?;
+                }
 
+                let permit = capnp_err!(admission.acquire("createContainer").await)?;
                 let (grandchild_pid, token) = capnp_err!(match child_reaper
                     .create_child(runtime, args, &mut container_io, &pidfile)
                     .await
@@ -144,11 +541,19 @@ impl conmon::Server for Server {
                     }
                     res => res,
                 })?;
+                drop(permit);
+                capnp_err!(
+                    container_log
+                        .write()
+                        .await
+                        .write_event(LifecycleEvent::Created)
+                        .await
+                )?;
 
                 // register grandchild with server
                 let io = SharedContainerIO::new(container_io);
-                let child = Child::new(
-                    id,
+                let mut child = Child::new(
+                    id.clone(),
                     grandchild_pid,
                     exit_paths,
                     oom_exit_paths,
@@ -157,17 +562,83 @@ impl conmon::Server for Server {
                     cleanup_cmd,
                     token,
                 );
-                capnp_err!(child_reaper.watch_grandchild(child))?;
-
-                results
-                    .get()
-                    .init_response()
-                    .set_container_pid(grandchild_pid);
-                Ok(())
+                child.set_labels(labels);
+                child.set_stop_signal(stop_signal);
+                let mut exit_rx = capnp_err!(child_reaper.watch_grandchild(child))?;
+                match supervisor {
+                    Some(supervisor) => task::spawn_local(
+                        supervisor
+                            .supervise(grandchild_pid, exit_rx)
+                            .instrument(debug_span!("supervise")),
+                    ),
+                    None => task::spawn_local(
+                        async move {
+                            if exit_rx.recv().await.is_ok() {
+                                child_reaper.expire(&id, grandchild_pid, exited_ttl).await
+                            }
+                        }
+                        .instrument(debug_span!("expire")),
+                    ),
+                };
+                Ok(grandchild_pid)
             }
             .instrument(debug_span!("promise")),
         )
     }
+}
+
+impl conmon::Server for Server {
+    /// Retrieve version information from the server.
+    fn version(
+        &mut self,
+        _: conmon::VersionParams,
+        mut results: conmon::VersionResults,
+    ) -> Promise<(), capnp::Error> {
+        debug!("Got a version request");
+        let mut response = results.get().init_response();
+        let version = Version::new();
+        response.set_version(version.version());
+        response.set_tag(version.tag());
+        response.set_commit(version.commit());
+        response.set_build_date(version.build_date());
+        response.set_rust_version(version.rust_version());
+        response.set_process_id(std::process::id());
+        response.set_build_target(version.build_target());
+        response.set_build_profile(version.build_profile());
+        response.set_protocol_version(Capabilities::PROTOCOL_VERSION);
+        response.set_min_protocol_version(Capabilities::MIN_PROTOCOL_VERSION);
+
+        let features = Capabilities::enabled_features();
+        let mut list = response.reborrow().init_features(features.len() as u32);
+        for (i, feature) in features.iter().enumerate() {
+            list.set(i as u32, feature);
+        }
+        let log_drivers = Capabilities::supported_log_drivers();
+        let mut list = response.init_log_drivers(log_drivers.len() as u32);
+        for (i, log_driver) in log_drivers.iter().enumerate() {
+            list.set(i as u32, *log_driver);
+        }
+        Promise::ok(())
+    }
+
+    /// Create a new container for the provided parameters.
+    fn create_container(
+        &mut self,
+        params: conmon::CreateContainerParams,
+        mut results: conmon::CreateContainerResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let create = self.create(req, deadline);
+        Promise::from_future(async move {
+            let container_pid = create.await?;
+            results
+                .get()
+                .init_response()
+                .set_container_pid(container_pid);
+            Ok(())
+        })
+    }
 
     /// Execute a command in sync inside of a container.
     fn exec_sync_container(
@@ -176,6 +647,7 @@ impl conmon::Server for Server {
         mut results: conmon::ExecSyncContainerResults,
     ) -> Promise<(), capnp::Error> {
         let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
         let id = pry!(req.get_id()).to_string();
         let timeout = req.get_timeout_sec();
 
@@ -189,33 +661,42 @@ impl conmon::Server for Server {
         let _enter = span.enter();
 
         debug!("Got exec sync container request with timeout {}", timeout);
+        pry_err!(self.ensure_accepting());
+        let exec_session_id = pry_err!(self.exec_session_id(&id, pry!(req.get_exec_session_id())));
 
         let runtime = self.config().runtime().clone();
         let child_reaper = self.reaper().clone();
+        let labels = self.container_labels(&id);
 
         let logger = ContainerLog::new();
-        let mut container_io = pry_err!(ContainerIO::new(req.get_terminal(), logger));
+        let mut container_io = pry_err!(ContainerIO::new(
+            req.get_terminal(),
+            logger,
+            SharedContainerAttach::default(),
+            pry_err!(self.config().buffer_sizes())
+        ));
 
         let command = pry!(req.get_command());
         let args = pry_err!(self.generate_exec_sync_args(&id, &pidfile, &container_io, &command));
+        let command = capnp_vec_str!(req.get_command());
+        let admission = self.admission().clone();
 
-        Promise::from_future(
+        promise_until(
+            deadline,
             async move {
-                match child_reaper
+                let permit = capnp_err!(admission.acquire("execSyncContainer").await)?;
+                let created = child_reaper
                     .create_child(&runtime, &args, &mut container_io, &pidfile)
-                    .await
-                {
+                    .await;
+                drop(permit);
+                match created {
                     Ok((grandchild_pid, token)) => {
-                        let time_to_timeout = if timeout > 0 {
-                            Some(Instant::now() + Duration::from_secs(timeout))
-                        } else {
-                            None
-                        };
+                        let time_to_timeout = command_deadline(timeout, deadline);
                         let mut resp = results.get().init_response();
                         // register grandchild with server
                         let io = SharedContainerIO::new(container_io);
                         let io_clone = io.clone();
-                        let child = Child::new(
+                        let mut child = Child::new(
                             id,
                             grandchild_pid,
                             vec![],
@@ -225,6 +706,10 @@ impl conmon::Server for Server {
                             vec![],
                             token.clone(),
                         );
+                        resp.set_exec_session_id(&exec_session_id);
+                        child.set_exec_session_id(Some(exec_session_id));
+                        child.set_command(command);
+                        child.set_labels(labels);
 
                         let mut exit_rx = capnp_err!(child_reaper.watch_grandchild(child))?;
 
@@ -242,6 +727,7 @@ impl conmon::Server for Server {
                     Err(e) => {
                         error!("Unable to create child: {:#}", e);
                         let mut resp = results.get().init_response();
+                        resp.set_exec_session_id(&exec_session_id);
                         resp.set_exit_code(-2);
                     }
                 }
@@ -251,6 +737,121 @@ impl conmon::Server for Server {
         )
     }
 
+    /// Execute a command in a running container and push its output chunks to the listener
+    /// as they arrive, followed by the exit code.
+    fn exec_stream_container(
+        &mut self,
+        params: conmon::ExecStreamContainerParams,
+        mut results: conmon::ExecStreamContainerResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let id = pry!(req.get_id()).to_string();
+        let timeout = req.get_timeout_sec();
+        let listener = pry!(req.get_listener());
+
+        let pidfile = pry_err!(ContainerIO::temp_file_name(
+            Some(self.config().runtime_dir()),
+            "exec_stream",
+            "pid"
+        ));
+
+        let span = new_root_span!("exec_stream_container", id.as_str());
+        let _enter = span.enter();
+
+        debug!("Got exec stream container request with timeout {}", timeout);
+        pry_err!(self.ensure_accepting());
+        let exec_session_id = pry_err!(self.exec_session_id(&id, pry!(req.get_exec_session_id())));
+
+        let runtime = self.config().runtime().clone();
+        let child_reaper = self.reaper().clone();
+        let labels = self.container_labels(&id);
+
+        let logger = ContainerLog::new();
+        let mut container_io = pry_err!(ContainerIO::new(
+            req.get_terminal(),
+            logger,
+            SharedContainerAttach::default(),
+            pry_err!(self.config().buffer_sizes())
+        ));
+
+        let command = pry!(req.get_command());
+        let args = pry_err!(self.generate_exec_sync_args(&id, &pidfile, &container_io, &command));
+        let command = capnp_vec_str!(req.get_command());
+        let admission = self.admission().clone();
+
+        promise_until(
+            deadline,
+            async move {
+                let permit = capnp_err!(admission.acquire("execStreamContainer").await)?;
+                let (grandchild_pid, token) = capnp_err!(child_reaper
+                    .create_child(&runtime, &args, &mut container_io, &pidfile)
+                    .await
+                    .context("create child"))?;
+                drop(permit);
+                let time_to_timeout = command_deadline(timeout, deadline);
+                // register grandchild with server
+                let io = SharedContainerIO::new(container_io);
+                let mut output = capnp_err!(io.output_receiver().await)?;
+                let mut child = Child::new(
+                    id,
+                    grandchild_pid,
+                    vec![],
+                    vec![],
+                    time_to_timeout,
+                    io,
+                    vec![],
+                    token,
+                );
+                results
+                    .get()
+                    .init_response()
+                    .set_exec_session_id(&exec_session_id);
+                child.set_exec_session_id(Some(exec_session_id));
+                child.set_command(command);
+                child.set_labels(labels);
+
+                let mut exit_rx = capnp_err!(child_reaper.watch_grandchild(child))?;
+
+                // Every chunk waits for the previous one to be received by the listener.
+                let mut timed_out = false;
+                loop {
+                    let chunk = match time_to_timeout {
+                        Some(deadline) => match time::timeout_at(deadline, output.recv()).await {
+                            Ok(chunk) => chunk,
+                            Err(_) => {
+                                timed_out = true;
+                                None
+                            }
+                        },
+                        None => output.recv().await,
+                    };
+                    let (pipe, data) = match chunk {
+                        Some(chunk) => chunk,
+                        None => break,
+                    };
+                    let mut request = listener.output_request();
+                    request.get().set_pipe(match pipe {
+                        Pipe::StdOut => ExecStreamPipe::Stdout,
+                        Pipe::StdErr => ExecStreamPipe::Stderr,
+                    });
+                    request.get().set_data(&data);
+                    request.send().promise.await?;
+                }
+
+                let exit_data = capnp_err!(exit_rx.recv().await)?;
+                let mut request = listener.exit_request();
+                request.get().set_exit_code(*exit_data.exit_code());
+                request
+                    .get()
+                    .set_timed_out(timed_out || exit_data.timed_out);
+                request.send().promise.await?;
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
     /// Attach to a running container.
     fn attach_container(
         &mut self,
@@ -258,6 +859,7 @@ impl conmon::Server for Server {
         _: conmon::AttachContainerResults,
     ) -> Promise<(), capnp::Error> {
         let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
         let container_id = pry_err!(req.get_id());
 
         let span = new_root_span!("attach_container", container_id);
@@ -271,18 +873,53 @@ impl conmon::Server for Server {
         }
 
         let socket_path = pry!(req.get_socket_path()).to_string();
+        let vsock_port = req.get_vsock_port();
+        let abstract_socket = req.get_abstract_socket();
+        let websocket = req.get_websocket();
+        let mut options = pry_err!(AttachOptions::from(req));
+        if options.idle_timeout().is_none() && self.config().attach_idle_timeout() > 0 {
+            options.set_idle_timeout(Some(Duration::from_secs(
+                self.config().attach_idle_timeout(),
+            )));
+        }
+        if options.max_clients().is_none() && self.config().attach_max_clients() > 0 {
+            options.set_max_clients(Some(self.config().attach_max_clients() as usize));
+        }
+        let default_packet_size = req.get_packet_size() == 0;
         let child = pry_err!(self.reaper().get(container_id));
+        if req.get_allow_signals() {
+            options.set_signal_pid(Some(child.pid()));
+        }
 
-        Promise::from_future(
+        promise_until(
+            deadline,
             async move {
-                capnp_err!(
-                    child
-                        .io()
-                        .attach()
-                        .await
-                        .add(&socket_path, child.token().clone())
-                        .await
-                )
+                let buffer_sizes = child.io().buffer_sizes().await;
+                if default_packet_size {
+                    options.set_packet_size(buffer_sizes.attach_packet());
+                }
+                options.set_stdin_buffer_size(buffer_sizes.stdin());
+                if !child.io().stdin().await {
+                    options.set_read_only(true);
+                }
+                let mut attach = child.io().attach().await;
+                let token = child.token().clone();
+                if vsock_port > 0 {
+                    debug!("Using vsock port {}", vsock_port);
+                    capnp_err!(attach.add_vsock(vsock_port, options, token).await)
+                } else if abstract_socket {
+                    debug!("Using abstract socket {}", socket_path);
+                    capnp_err!(attach.add_abstract(&socket_path, options, token).await)
+                } else if websocket {
+                    debug!("Using WebSocket");
+                    capnp_err!(
+                        attach
+                            .add_websocket(Path::new(&socket_path), options, token)
+                            .await
+                    )
+                } else {
+                    capnp_err!(attach.add(&socket_path, options, token).await)
+                }
             }
             .instrument(debug_span!("promise")),
         )
@@ -295,6 +932,7 @@ impl conmon::Server for Server {
         _: conmon::ReopenLogContainerResults,
     ) -> Promise<(), capnp::Error> {
         let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
         let container_id = pry_err!(req.get_id());
 
         let span = new_root_span!("reopen_log_container", container_id);
@@ -304,7 +942,8 @@ impl conmon::Server for Server {
 
         let child = pry_err!(self.reaper().get(container_id));
 
-        Promise::from_future(
+        promise_until(
+            deadline,
             async move { capnp_err!(child.io().logger().await.write().await.reopen().await) }
                 .instrument(debug_span!("promise")),
         )
@@ -317,6 +956,7 @@ impl conmon::Server for Server {
         _: conmon::SetWindowSizeContainerResults,
     ) -> Promise<(), capnp::Error> {
         let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
         let container_id = pry_err!(req.get_id());
 
         let span = new_root_span!("set_window_size_container", container_id);
@@ -328,9 +968,1011 @@ impl conmon::Server for Server {
         let width = req.get_width();
         let height = req.get_height();
 
-        Promise::from_future(
+        promise_until(
+            deadline,
+            async move { capnp_err!(child.io().resize(width, height).await) }
+                .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Adjust the window size of an exec session running inside of a terminal.
+    fn set_window_size_exec_session(
+        &mut self,
+        params: conmon::SetWindowSizeExecSessionParams,
+        _: conmon::SetWindowSizeExecSessionResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let container_id = pry_err!(req.get_id());
+        let exec_session_id = pry_err!(req.get_exec_session_id());
+
+        let span = new_root_span!("set_window_size_exec_session", container_id);
+        let _enter = span.enter();
+
+        debug!(
+            "Got a set window size request for exec session {}",
+            exec_session_id
+        );
+
+        let child = pry_err!(self
+            .reaper()
+            .get_exec_session(container_id, exec_session_id));
+        let width = req.get_width();
+        let height = req.get_height();
+
+        promise_until(
+            deadline,
             async move { capnp_err!(child.io().resize(width, height).await) }
                 .instrument(debug_span!("promise")),
         )
     }
+
+    /// Change the terminal mode of a container or exec session, like disabling echo for
+    /// password prompts.
+    fn set_terminal_mode_container(
+        &mut self,
+        params: conmon::SetTerminalModeContainerParams,
+        mut results: conmon::SetTerminalModeContainerResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let container_id = pry_err!(req.get_id());
+        let exec_session_id = pry_err!(req.get_exec_session_id());
+
+        let span = new_root_span!("set_terminal_mode_container", container_id);
+        let _enter = span.enter();
+
+        debug!("Got a set terminal mode container request");
+
+        let child = pry_err!(match exec_session_id {
+            "" => self.reaper().get(container_id),
+            x => self.reaper().get_exec_session(container_id, x),
+        });
+        let change = pry_err!(TerminalModeChange::from(req));
+
+        promise_until(
+            deadline,
+            async move {
+                let mode = capnp_err!(child.io().set_terminal_mode(change).await)?;
+                let mut response = results.get().init_response();
+                response.set_echo(mode.echo());
+                response.set_canonical(mode.canonical());
+                response.set_signals(mode.signals());
+                response.set_cr_to_nl(mode.cr_to_nl());
+                response.set_nl_to_cr_nl(mode.nl_to_cr_nl());
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Retrieve the attach usage statistics of a container.
+    fn attach_stats_container(
+        &mut self,
+        params: conmon::AttachStatsContainerParams,
+        mut results: conmon::AttachStatsContainerResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let container_id = pry_err!(req.get_id());
+
+        let span = new_root_span!("attach_stats_container", container_id);
+        let _enter = span.enter();
+
+        debug!("Got an attach stats container request");
+
+        let child = pry_err!(self.reaper().get(container_id));
+
+        promise_until(
+            deadline,
+            async move {
+                let stats = capnp_err!(child.io().attach().await.stats())?;
+                let mut response = results.get().init_response();
+                response.set_active_clients(stats.active_clients());
+                response.set_bytes_in(stats.bytes_in());
+                response.set_bytes_out(stats.bytes_out());
+                response.set_dropped_messages(stats.dropped_messages());
+                response.set_disconnects(stats.disconnects());
+                response.set_queued_messages(stats.queued_messages());
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    fn log_stats_container(
+        &mut self,
+        params: conmon::LogStatsContainerParams,
+        mut results: conmon::LogStatsContainerResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let container_id = pry_err!(req.get_id());
+
+        let span = new_root_span!("log_stats_container", container_id);
+        let _enter = span.enter();
+
+        debug!("Got a log stats container request");
+
+        let child = pry_err!(self.reaper().get(container_id));
+
+        promise_until(
+            deadline,
+            async move {
+                let logger = child.io().logger().await;
+                let locked_logger = logger.read().await;
+                let rules = locked_logger.redactor().rules();
+                let mut response = results.get().init_response();
+                response.set_discarded_bytes(locked_logger.discarded_bytes());
+                let mut redactions = response.init_redactions(rules.len() as u32);
+                for (i, rule) in rules.iter().enumerate() {
+                    let mut redaction = redactions.reborrow().get(i as u32);
+                    redaction.set_pattern(rule.pattern().as_str());
+                    redaction.set_hits(rule.hits());
+                }
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Replace the log drivers of a running container.
+    fn update_log_config_container(
+        &mut self,
+        params: conmon::UpdateLogConfigContainerParams,
+        _: conmon::UpdateLogConfigContainerResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let container_id = pry_err!(req.get_id());
+
+        let span = new_root_span!("update_log_config_container", container_id);
+        let _enter = span.enter();
+
+        debug!("Got an update log config container request");
+
+        let child = pry_err!(self.reaper().get(container_id));
+        let fd_socket = self.fd_socket().clone();
+
+        promise_until(
+            deadline,
+            async move {
+                let log_drivers = params.get()?.get_request()?.get_log_drivers()?;
+                let logger = child.io().logger().await;
+                capnp_err!(ContainerLog::update(&logger, log_drivers, &fd_socket).await)
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Read the last lines of a container log.
+    fn tail_log_container(
+        &mut self,
+        params: conmon::TailLogContainerParams,
+        mut results: conmon::TailLogContainerResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let container_id = pry_err!(req.get_id());
+
+        let span = new_root_span!("tail_log_container", container_id);
+        let _enter = span.enter();
+
+        debug!("Got a tail log container request");
+
+        let child = pry_err!(self.reaper().get(container_id));
+        let lines = req.get_lines() as usize;
+        let follow_rotations = req.get_follow_rotations();
+
+        promise_until(
+            deadline,
+            async move {
+                let logger = child.io().logger().await;
+                let tail = capnp_err!(logger.write().await.tail(lines, follow_rotations).await)?;
+                let mut response = results.get().init_response().init_lines(tail.len() as u32);
+                for (i, line) in tail.iter().enumerate() {
+                    response.set(i as u32, line);
+                }
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Read the in-memory buffer of the most recent container output.
+    fn read_recent_output_container(
+        &mut self,
+        params: conmon::ReadRecentOutputContainerParams,
+        mut results: conmon::ReadRecentOutputContainerResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let container_id = pry_err!(req.get_id());
+
+        let span = new_root_span!("read_recent_output_container", container_id);
+        let _enter = span.enter();
+
+        debug!("Got a read recent output container request");
+
+        let child = pry_err!(self.reaper().get(container_id));
+
+        promise_until(
+            deadline,
+            async move {
+                let logger = child.io().logger().await;
+                let recent_output = logger.read().await.recent_output();
+                let mut chunks = results
+                    .get()
+                    .init_response()
+                    .init_chunks(recent_output.len() as u32);
+                for (i, (pipe, data)) in recent_output.iter().enumerate() {
+                    let mut chunk = chunks.reborrow().get(i as u32);
+                    chunk.set_pipe(match pipe {
+                        Pipe::StdOut => ExecStreamPipe::Stdout,
+                        Pipe::StdErr => ExecStreamPipe::Stderr,
+                    });
+                    chunk.set_data(data);
+                }
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Retrieve the IO throughput statistics of a container.
+    fn io_stats_container(
+        &mut self,
+        params: conmon::IoStatsContainerParams,
+        mut results: conmon::IoStatsContainerResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let container_id = pry_err!(req.get_id());
+
+        let span = new_root_span!("io_stats_container", container_id);
+        let _enter = span.enter();
+
+        debug!("Got an IO stats container request");
+
+        let child = pry_err!(self.reaper().get(container_id));
+
+        promise_until(
+            deadline,
+            async move {
+                let stats = capnp_err!(child.io().stats().await)?;
+                let mut response = results.get().init_response();
+                response.set_stdin_bytes(stats.stdin_bytes());
+                let mut stdout = response.reborrow().init_stdout();
+                stdout.set_bytes(stats.stdout().bytes());
+                stdout.set_lines(stats.stdout().lines());
+                stdout.set_partial_lines(stats.stdout().partial_lines());
+                stdout.set_dropped_bytes(stats.stdout().dropped_bytes());
+                let mut stderr = response.reborrow().init_stderr();
+                stderr.set_bytes(stats.stderr().bytes());
+                stderr.set_lines(stats.stderr().lines());
+                stderr.set_partial_lines(stats.stderr().partial_lines());
+                stderr.set_dropped_bytes(stats.stderr().dropped_bytes());
+                let mut extra_fds = response.init_extra_fds(stats.extra_fds().len() as u32);
+                for (i, (fd, bytes)) in stats.extra_fds().iter().enumerate() {
+                    let mut extra_fd = extra_fds.reborrow().get(i as u32);
+                    extra_fd.set_fd(*fd as u32);
+                    extra_fd.set_bytes(*bytes);
+                }
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    fn set_output_paused_container(
+        &mut self,
+        params: conmon::SetOutputPausedContainerParams,
+        mut results: conmon::SetOutputPausedContainerResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let container_id = pry_err!(req.get_id());
+
+        let span = new_root_span!("set_output_paused_container", container_id);
+        let _enter = span.enter();
+
+        debug!("Got a set output paused container request");
+
+        let child = pry_err!(self.reaper().get(container_id));
+        let paused = req.get_paused();
+
+        promise_until(
+            deadline,
+            async move {
+                let was_paused = child.io().set_output_paused(paused).await;
+                results.get().init_response().set_was_paused(was_paused);
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Hand the terminal master of a container over to the caller, which can drive the terminal
+    /// directly instead of attaching.
+    fn send_terminal_master_container(
+        &mut self,
+        params: conmon::SendTerminalMasterContainerParams,
+        _: conmon::SendTerminalMasterContainerResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let container_id = pry_err!(req.get_id());
+
+        let span = new_root_span!("send_terminal_master_container", container_id);
+        let _enter = span.enter();
+
+        debug!("Got a send terminal master container request");
+
+        let child = pry_err!(self.reaper().get(container_id));
+        let socket_path = PathBuf::from(pry!(req.get_socket_path()));
+
+        promise_until(
+            deadline,
+            async move { capnp_err!(child.io().send_terminal_master(&socket_path).await) }
+                .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// List all containers and exec sessions tracked by the server.
+    fn list_containers(
+        &mut self,
+        params: conmon::ListContainersParams,
+        mut results: conmon::ListContainersResults,
+    ) -> Promise<(), capnp::Error> {
+        debug!("Got a list containers request");
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let children = pry_err!(self.reaper().list());
+        let runtime = self.config().runtime().clone();
+
+        let filter = pry!(req.get_filter());
+        let mut container_filter = ContainerFilter::default();
+        container_filter
+            .set_ids(capnp_vec_str!(filter.get_ids()))
+            .set_pod_id(pry!(filter.get_pod_id()).to_string())
+            .set_exclude_exec_sessions(filter.get_exclude_exec_sessions());
+        let mut states = vec![];
+        for state in pry!(filter.get_states()).iter() {
+            states.push(pry!(state));
+        }
+        container_filter.set_states(states);
+        let mut labels = HashMap::new();
+        for entry in pry!(filter.get_labels()).iter() {
+            labels.insert(
+                pry!(entry.get_key()).to_string(),
+                pry!(entry.get_value()).to_string(),
+            );
+        }
+        container_filter.set_labels(labels);
+        let mut fields = vec![];
+        for field in pry!(req.get_fields()).iter() {
+            fields.push(pry!(field));
+        }
+        let wanted = move |field: ContainerInfoField| fields.is_empty() || fields.contains(&field);
+
+        promise_until(
+            deadline,
+            async move {
+                let mut infos = vec![];
+                for (id, child) in children {
+                    let exit_code = capnp_err!(child.exit_code())?;
+                    let state = container_state(capnp_err!(child.state(&runtime))?);
+                    let logger = child.io().logger().await;
+                    let locked_logger = logger.read().await;
+                    if !container_filter.matches(
+                        &id,
+                        child.exec_session_id().is_some(),
+                        state,
+                        locked_logger.metadata(),
+                        child.labels().labels(),
+                    ) {
+                        continue;
+                    }
+                    let log_paths = if wanted(ContainerInfoField::LogPaths) {
+                        locked_logger.paths()
+                    } else {
+                        vec![]
+                    };
+                    drop(locked_logger);
+                    let attach_socket_paths = if wanted(ContainerInfoField::AttachSocketPaths) {
+                        capnp_err!(child.io().attach().await.socket_paths())?
+                    } else {
+                        vec![]
+                    };
+                    infos.push((id, child, state, exit_code, log_paths, attach_socket_paths));
+                }
+
+                let mut containers = results
+                    .get()
+                    .init_response()
+                    .init_containers(infos.len() as u32);
+                for (i, (id, child, state, exit_code, log_paths, attach_socket_paths)) in
+                    infos.iter().enumerate()
+                {
+                    let mut container = containers.reborrow().get(i as u32);
+                    container.set_id(id);
+                    if wanted(ContainerInfoField::ExecSessionId) {
+                        container
+                            .set_exec_session_id(child.exec_session_id().as_deref().unwrap_or(""));
+                    }
+                    if wanted(ContainerInfoField::Pid) {
+                        container.set_pid(child.pid());
+                    }
+                    if wanted(ContainerInfoField::State) {
+                        container.set_state(*state);
+                        if let Some(exit_code) = exit_code {
+                            container.set_exit_code(*exit_code);
+                        }
+                    }
+                    if wanted(ContainerInfoField::Created) {
+                        container.set_created_unix_nano(
+                            child
+                                .created()
+                                .duration_since(UNIX_EPOCH)
+                                .map_or(0, |x| x.as_nanos() as i64),
+                        );
+                    }
+                    if wanted(ContainerInfoField::Labels) {
+                        let labels = child.labels().labels();
+                        ContainerLabels::set_entries(
+                            container.reborrow().init_labels(labels.len() as u32),
+                            labels,
+                        );
+                    }
+                    if wanted(ContainerInfoField::Annotations) {
+                        let annotations = child.labels().annotations();
+                        ContainerLabels::set_entries(
+                            container
+                                .reborrow()
+                                .init_annotations(annotations.len() as u32),
+                            annotations,
+                        );
+                    }
+                    let mut paths = container.reborrow().init_log_paths(log_paths.len() as u32);
+                    for (i, path) in log_paths.iter().enumerate() {
+                        paths.set(i as u32, &path.to_string_lossy());
+                    }
+                    let mut paths =
+                        container.init_attach_socket_paths(attach_socket_paths.len() as u32);
+                    for (i, path) in attach_socket_paths.iter().enumerate() {
+                        paths.set(i as u32, &path.to_string_lossy());
+                    }
+                }
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Retrieve the resource usage of a container from its cgroup.
+    fn container_stats(
+        &mut self,
+        params: conmon::ContainerStatsParams,
+        mut results: conmon::ContainerStatsResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let container_id = pry_err!(req.get_id());
+
+        let span = new_root_span!("container_stats", container_id);
+        let _enter = span.enter();
+
+        debug!("Got a container stats request");
+
+        let pid = pry_err!(self.reaper().get(container_id)).pid();
+        let mut groups = vec![];
+        for group in pry!(req.get_groups()).iter() {
+            groups.push(match pry!(group) {
+                ContainerStatsGroup::Cpu => StatsGroup::Cpu,
+                ContainerStatsGroup::Memory => StatsGroup::Memory,
+                ContainerStatsGroup::Pids => StatsGroup::Pids,
+                ContainerStatsGroup::Io => StatsGroup::Io,
+            });
+        }
+        if groups.is_empty() {
+            groups.extend(StatsGroup::ALL);
+        }
+
+        promise_until(
+            deadline,
+            async move {
+                let stats = capnp_err!(CgroupStats::read(pid, &groups).await)?;
+                let mut response = results.get().init_response();
+                response.set_cpu_usage_usec(stats.cpu_usage_usec());
+                response.set_cpu_user_usec(stats.cpu_user_usec());
+                response.set_cpu_system_usec(stats.cpu_system_usec());
+                response.set_memory_usage_bytes(stats.memory_usage_bytes());
+                response.set_memory_limit_bytes(stats.memory_limit_bytes());
+                response.set_pids_current(stats.pids_current());
+                response.set_pids_limit(stats.pids_limit());
+                response.set_io_read_bytes(stats.io_read_bytes());
+                response.set_io_write_bytes(stats.io_write_bytes());
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Report the runtime diagnostics of the server.
+    fn status(
+        &mut self,
+        params: conmon::StatusParams,
+        mut results: conmon::StatusResults,
+    ) -> Promise<(), capnp::Error> {
+        debug!("Got a status request");
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let children = pry_err!(self.reaper().list());
+        let tracked_containers = pry_err!(self.reaper().containers());
+        let max_containers = self.capacity().max();
+        let uptime = self.started().elapsed();
+        let logged_errors = pry_err!(self.error_log().errors());
+        let process = pry_err!(ProcessStatus::read());
+
+        promise_until(
+            deadline,
+            async move {
+                let mut attach_clients = 0;
+                for (_, child) in &children {
+                    attach_clients +=
+                        capnp_err!(child.io().attach().await.stats())?.active_clients();
+                }
+
+                let mut response = results.get().init_response();
+                response.set_uptime_ms(uptime.as_millis() as u64);
+                response.set_containers(children.len() as u64);
+                response.set_attach_clients(attach_clients);
+                response.set_tasks(process.tasks());
+                response.set_open_fds(process.open_fds());
+                response.set_max_fds(process.max_fds());
+                response.set_tracked_containers(tracked_containers as u64);
+                response.set_max_containers(max_containers as u64);
+                let mut errors = response.init_errors(logged_errors.len() as u32);
+                for (i, logged_error) in logged_errors.iter().enumerate() {
+                    let mut error = errors.reborrow().get(i as u32);
+                    error.set_unix_nano(
+                        logged_error
+                            .time()
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |x| x.as_nanos() as i64),
+                    );
+                    error.set_target(logged_error.target());
+                    error.set_message(logged_error.message());
+                }
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Change the filter of the server logs at runtime.
+    fn set_log_filter(
+        &mut self,
+        params: conmon::SetLogFilterParams,
+        mut results: conmon::SetLogFilterResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let filter = pry!(req.get_filter());
+        debug!("Got a set log filter request: {}", filter);
+
+        let log_filter = pry_err!(self
+            .log_filter()
+            .as_ref()
+            .context("logging not initialized"));
+        let previous_filter = pry_err!(log_filter.set(filter));
+        info!(
+            "Changed log filter from '{}' to '{}'",
+            previous_filter, filter
+        );
+
+        results
+            .get()
+            .init_response()
+            .set_previous_filter(&previous_filter);
+        Promise::ok(())
+    }
+
+    /// Re-read the configuration file of the server.
+    fn reload_config(
+        &mut self,
+        _: conmon::ReloadConfigParams,
+        _: conmon::ReloadConfigResults,
+    ) -> Promise<(), capnp::Error> {
+        debug!("Got a reload config request");
+        pry_err!(self.reload_config_file());
+        Promise::ok(())
+    }
+
+    /// Drain all containers and stop the server afterwards.
+    fn shutdown(
+        &mut self,
+        params: conmon::ShutdownParams,
+        mut results: conmon::ShutdownResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        debug!("Got a shutdown request");
+        pry_err!(self.ensure_accepting());
+        let signal = match req.get_signal() {
+            0 => Signal::SIGTERM,
+            x => pry_err!(Signal::try_from(x).context(format!("invalid signal {}", x))),
+        };
+        self.draining().cancel();
+
+        let timeout = Duration::from_secs(match req.get_timeout_sec() {
+            0 => self.config().shutdown_timeout(),
+            x => x,
+        });
+        let reaper = self.reaper().clone();
+        let shutdown_requested = self.shutdown_requested().clone();
+        Promise::from_future(
+            async move {
+                let summary = capnp_err!(reaper.drain(timeout, signal).await)?;
+                info!(
+                    "Drained containers, {} exited and {} got killed",
+                    summary.exited(),
+                    summary.killed()
+                );
+                let mut response = results.get().init_response();
+                response.set_exited_containers(summary.exited() as u32);
+                response.set_killed_containers(summary.killed() as u32);
+                shutdown_requested.cancel();
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Create multiple containers, where up to the parallelism of them get created concurrently.
+    fn create_containers(
+        &mut self,
+        params: conmon::CreateContainersParams,
+        mut results: conmon::CreateContainersResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        debug!("Got a create containers request");
+        let parallelism = match req.get_parallelism() {
+            0 => self.config().create_parallelism(),
+            x => x as usize,
+        };
+
+        let mut ids = vec![];
+        let mut creates = vec![];
+        for request in pry!(req.get_requests()).iter() {
+            ids.push(pry!(request.get_id()).to_string());
+            let deadline = self.deadline(request.get_rpc_timeout_ms());
+            creates.push(self.create(request, deadline));
+        }
+
+        promise_until(
+            deadline,
+            async move {
+                let outcomes = stream::iter(creates)
+                    .buffered(parallelism)
+                    .collect::<Vec<_>>()
+                    .await;
+
+                let mut list = results.get().init_response().init_results(ids.len() as u32);
+                for (i, (id, outcome)) in ids.iter().zip(outcomes).enumerate() {
+                    let mut result = list.reborrow().get(i as u32);
+                    result.set_id(id);
+                    match outcome {
+                        Ok(container_pid) => result.set_container_pid(container_pid),
+                        Err(e) => result.set_error(&e.description),
+                    }
+                }
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Report the supported features, log drivers, transports and methods.
+    fn capabilities(
+        &mut self,
+        _: conmon::CapabilitiesParams,
+        mut results: conmon::CapabilitiesResults,
+    ) -> Promise<(), capnp::Error> {
+        debug!("Got a capabilities request");
+        let capabilities = Capabilities::new(&self.config());
+        let mut response = results.get().init_response();
+        response.set_protocol_version(capabilities.protocol_version());
+
+        let features = capabilities.features();
+        let mut list = response.reborrow().init_features(features.len() as u32);
+        for (i, feature) in features.iter().enumerate() {
+            list.set(i as u32, feature);
+        }
+
+        let log_drivers = capabilities.log_drivers();
+        let mut list = response
+            .reborrow()
+            .init_log_drivers(log_drivers.len() as u32);
+        for (i, log_driver) in log_drivers.iter().enumerate() {
+            list.set(i as u32, *log_driver);
+        }
+
+        let transports = capabilities.transports();
+        let mut list = response.reborrow().init_transports(transports.len() as u32);
+        for (i, transport) in transports.iter().enumerate() {
+            list.set(i as u32, *transport);
+        }
+
+        let methods = capabilities.methods();
+        let mut list = response.init_methods(methods.len() as u32);
+        for (i, method) in methods.iter().enumerate() {
+            list.set(i as u32, method);
+        }
+        Promise::ok(())
+    }
+
+    /// Remove the container and release all of its resources.
+    fn remove_container(
+        &mut self,
+        params: conmon::RemoveContainerParams,
+        mut results: conmon::RemoveContainerResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let id = pry!(req.get_id()).to_string();
+
+        let span = new_root_span!("remove_container", id.as_str());
+        let _enter = span.enter();
+
+        debug!("Got a remove container request");
+        let force = req.get_force();
+        let child_reaper = self.reaper().clone();
+
+        promise_until(
+            deadline,
+            async move {
+                let killed = capnp_err!(child_reaper.remove(&id, force).await)?;
+                results
+                    .get()
+                    .init_response()
+                    .set_killed_processes(killed as u32);
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Retrieve the lifecycle state of a container from the child reaper.
+    fn container_status(
+        &mut self,
+        params: conmon::ContainerStatusParams,
+        mut results: conmon::ContainerStatusResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let container_id = pry_err!(req.get_id());
+
+        let span = new_root_span!("container_status", container_id);
+        let _enter = span.enter();
+
+        debug!("Got a container status request");
+        let child = pry_err!(self.reaper().get(container_id));
+        let runtime = self.config().runtime().clone();
+
+        promise_until(
+            deadline,
+            async move {
+                let state = capnp_err!(child.state(&runtime))?;
+                let exit = capnp_err!(child.exit())?;
+                let mut response = results.get().init_response();
+                response.set_state(container_state(state));
+                response.set_pid(child.pid());
+                response.set_created_unix_nano(unix_nano(Some(child.created())));
+                response.set_started_unix_nano(unix_nano(capnp_err!(child.started())?));
+                if let Some(exit) = exit {
+                    response.set_exit_code(exit.code());
+                    response.set_signal(exit.signal().map_or(0, |x| x as i32));
+                    response.set_oom_killed(exit.oom_killed());
+                    response.set_finished_unix_nano(unix_nano(Some(exit.finished())));
+                }
+                set_labels!(response, child.labels());
+                response.set_restart_count(child.restarts());
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// List the active exec sessions of a container.
+    fn list_exec_sessions(
+        &mut self,
+        params: conmon::ListExecSessionsParams,
+        mut results: conmon::ListExecSessionsResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let container_id = pry_err!(req.get_id());
+
+        let span = new_root_span!("list_exec_sessions", container_id);
+        let _enter = span.enter();
+
+        debug!("Got a list exec sessions request");
+        let exec_sessions = pry_err!(self.reaper().exec_sessions(container_id));
+        let runtime = self.config().runtime().clone();
+
+        promise_until(
+            deadline,
+            async move {
+                let mut list = results
+                    .get()
+                    .init_response()
+                    .init_exec_sessions(exec_sessions.len() as u32);
+                for (i, child) in exec_sessions.iter().enumerate() {
+                    capnp_err!(set_exec_session_info(
+                        list.reborrow().get(i as u32),
+                        child,
+                        &runtime
+                    ))?;
+                }
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Retrieve the state of an active exec session of a container.
+    fn inspect_exec_session(
+        &mut self,
+        params: conmon::InspectExecSessionParams,
+        mut results: conmon::InspectExecSessionResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let container_id = pry_err!(req.get_id());
+        let exec_session_id = pry_err!(req.get_exec_session_id());
+
+        let span = new_root_span!("inspect_exec_session", container_id);
+        let _enter = span.enter();
+
+        debug!(
+            "Got an inspect exec session request for {}",
+            exec_session_id
+        );
+        let child = pry_err!(self
+            .reaper()
+            .get_exec_session(container_id, exec_session_id));
+        let runtime = self.config().runtime().clone();
+
+        promise_until(
+            deadline,
+            async move {
+                capnp_err!(set_exec_session_info(
+                    results.get().init_response().init_exec_session(),
+                    &child,
+                    &runtime
+                ))
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Send a signal to an active exec session of a container.
+    fn kill_exec_session(
+        &mut self,
+        params: conmon::KillExecSessionParams,
+        _: conmon::KillExecSessionResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let container_id = pry_err!(req.get_id());
+        let exec_session_id = pry_err!(req.get_exec_session_id());
+
+        let span = new_root_span!("kill_exec_session", container_id);
+        let _enter = span.enter();
+
+        let signal = match req.get_signal() {
+            0 => Signal::SIGKILL,
+            x => pry_err!(Signal::try_from(x).context(format!("invalid signal {}", x))),
+        };
+        debug!(
+            "Got a kill exec session request for {} with {}",
+            exec_session_id, signal
+        );
+        let child = pry_err!(self
+            .reaper()
+            .get_exec_session(container_id, exec_session_id));
+        promise_until(
+            deadline,
+            async move {
+                if !matches!(child.exit_code(), Ok(Some(_))) {
+                    kill_grandchild(child.pid(), signal);
+                }
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Stop the container gracefully, killing it once the timeout elapsed.
+    fn stop_container(
+        &mut self,
+        params: conmon::StopContainerParams,
+        mut results: conmon::StopContainerResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        let deadline = self.deadline(req.get_rpc_timeout_ms());
+        let id = pry!(req.get_id()).to_string();
+
+        let span = new_root_span!("stop_container", id.as_str());
+        let _enter = span.enter();
+
+        let timeout = Duration::from_secs(req.get_timeout_sec());
+        debug!("Got a stop container request with timeout {:?}", timeout);
+        let signal = match req.get_signal() {
+            0 => pry_err!(self.reaper().get(&id)).stop_signal(),
+            x => pry_err!(Signal::try_from(x).context(format!("invalid signal {}", x))),
+        };
+        let child_reaper = self.reaper().clone();
+
+        promise_until(
+            deadline,
+            async move {
+                let (exit, killed) = capnp_err!(child_reaper.stop(&id, signal, timeout).await)?;
+                let mut response = results.get().init_response();
+                response.set_exit_code(exit.code());
+                response.set_signal(exit.signal().map_or(0, |x| x as i32));
+                response.set_oom_killed(exit.oom_killed());
+                response.set_killed(killed);
+                response.set_finished_unix_nano(unix_nano(Some(exit.finished())));
+                Ok(())
+            }
+            .instrument(debug_span!("promise")),
+        )
+    }
+
+    /// Push the events of the containers to the listener until it goes away.
+    fn subscribe_events(
+        &mut self,
+        params: conmon::SubscribeEventsParams,
+        _: conmon::SubscribeEventsResults,
+    ) -> Promise<(), capnp::Error> {
+        let req = pry!(pry!(params.get()).get_request());
+        debug!("Got a subscribe events request");
+        let listener = pry!(req.get_listener());
+        let id = pry!(req.get_id()).to_string();
+
+        // Subscribe before returning, to not miss any event happening afterwards.
+        let mut events = EVENTS.subscribe();
+        let reaper = self.reaper().clone();
+        task::spawn_local(
+            async move {
+                loop {
+                    let mut request = listener.event_request();
+                    let mut builder = request.get().init_event();
+                    match events.recv().await {
+                        Ok(event) if id.is_empty() || event.container_id() == &id => {
+                            let labels = reaper
+                                .get(event.container_id())
+                                .map(|x| x.labels().clone())
+                                .unwrap_or_default();
+                            set_event(builder, &event, &labels)
+                        }
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(dropped)) => {
+                            builder.set_type(event::Type::Lagged);
+                            builder.set_dropped_events(dropped);
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+                    // Every event waits for the previous one to be received by the listener.
+                    if let Err(e) = request.send().promise.await {
+                        debug!("Stopping event subscription: {}", e);
+                        break;
+                    }
+                }
+            }
+            .instrument(debug_span!("subscribe_events")),
+        );
+        Promise::ok(())
+    }
 }
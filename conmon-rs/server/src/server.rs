@@ -1,18 +1,39 @@
 #![deny(missing_docs)]
 
+#[cfg(feature = "grpc")]
+use crate::grpc::GrpcService;
+#[cfg(feature = "ttrpc")]
+use crate::ttrpc::TtrpcService;
 use crate::{
+    admission::Admission,
+    audit_log::{AuditLog, Caller},
+    auth::{self, Access, Authenticator},
+    capacity::ContainerCapacity,
+    capnp_bridge::CapnpBridge,
     child_reaper::ChildReaper,
-    config::{CgroupManager, Config, LogDriver},
+    config::{CgroupManager, Config, Isolation, LogDriver},
     container_io::{ContainerIO, ContainerIOType},
+    fd_socket::FdSocket,
+    http_api::HttpApi,
+    idempotency::IdempotencyCache,
     init::{DefaultInit, Init},
     listener::{DefaultListener, Listener},
+    log_filter::LogFilter,
+    log_quota::{LogQuota, SharedLogQuota},
+    method_filter,
+    metrics::{self, RpcDurationLayer},
+    status::ErrorLog,
+    tls_listener::TlsListener,
     version::Version,
+    vsock_listener::VsockListener,
+    worker_pool::WorkerPool,
+    worker_router::WorkerRouter,
 };
-use anyhow::{format_err, Context, Result};
+use anyhow::{bail, format_err, Context, Result};
 use capnp::text_list::Reader;
 use capnp_rpc::{rpc_twoparty_capnp::Side, twoparty, RpcSystem};
 use conmon_common::conmon_capnp::conmon;
-use futures::{AsyncReadExt, FutureExt};
+use futures::AsyncReadExt;
 use getset::Getters;
 use nix::{
     errno,
@@ -20,37 +41,99 @@ use nix::{
     sys::signal::Signal,
     unistd::{fork, ForkResult},
 };
-use std::{fs::File, io::Write, path::Path, process, str::FromStr, sync::Arc};
+use std::{
+    fs::File,
+    io::Write,
+    net::SocketAddr,
+    path::Path,
+    process,
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard},
+    time::{Duration, Instant},
+};
 use tokio::{
     fs,
+    io::{AsyncRead, AsyncWrite, BufReader},
+    net::TcpStream,
     runtime::{Builder, Handle},
     signal::unix::{signal, SignalKind},
     sync::oneshot,
     task::{self, LocalSet},
+    time,
 };
-use tokio_util::compat::TokioAsyncReadCompatExt;
-use tracing::{debug, debug_span, info, Instrument};
+use tokio_util::{compat::TokioAsyncReadCompatExt, sync::CancellationToken};
+use tokio_vsock::VsockStream;
+use tracing::{debug, debug_span, error, info, warn, Instrument};
 use tracing_subscriber::{filter::LevelFilter, prelude::*};
 use twoparty::VatNetwork;
 
 #[derive(Debug, Getters)]
 /// The main server structure.
 pub struct Server {
-    /// Server configuration.
+    /// Server configuration, which gets replaced once the config file got reloaded.
+    config: Arc<RwLock<Config>>,
+
+    /// Concurrency limits of the RPCs invoking the runtime.
     #[getset(get = "pub(crate)")]
-    config: Config,
+    admission: Arc<Admission>,
 
     /// Child reaper instance.
     #[getset(get = "pub(crate)")]
     reaper: Arc<ChildReaper>,
+
+    /// The creates of the recently used idempotency keys.
+    #[getset(get = "pub(crate)")]
+    idempotency: IdempotencyCache,
+
+    /// The maximum amount of tracked containers.
+    #[getset(get = "pub(crate)")]
+    capacity: ContainerCapacity,
+
+    /// Global quota of all container logs.
+    #[getset(get = "pub(crate)")]
+    log_quota: SharedLogQuota,
+
+    /// File descriptors received from clients.
+    #[getset(get = "pub(crate)")]
+    fd_socket: Arc<FdSocket>,
+
+    /// The time the server got created.
+    #[getset(get = "pub(crate)")]
+    started: Instant,
+
+    /// The most recent errors logged by the server.
+    #[getset(get = "pub(crate)")]
+    error_log: ErrorLog,
+
+    /// The filter of the server logs, which is set once logging got initialized.
+    #[getset(get = "pub(crate)")]
+    log_filter: Option<Arc<LogFilter>>,
+
+    /// Cancelled once the server started draining the containers, which rejects new ones.
+    #[getset(get = "pub(crate)")]
+    draining: CancellationToken,
+
+    /// Cancelled once a shutdown request drained the containers, which stops the server.
+    #[getset(get = "pub(crate)")]
+    shutdown_requested: CancellationToken,
 }
 
 impl Server {
     /// Create a new `Server` instance.
     pub fn new() -> Result<Self> {
-        let server = Self {
-            config: Default::default(),
+        let config = Config::default();
+        let mut server = Self {
+            log_quota: LogQuota::new(config.log_quota(), config.log_quota_policy()),
+            capacity: ContainerCapacity::new(config.max_containers()),
+            config: Arc::new(RwLock::new(config)),
+            admission: Default::default(),
             reaper: Default::default(),
+            idempotency: Default::default(),
+            fd_socket: Default::default(),
+            started: Instant::now(),
+            error_log: Default::default(),
+            log_filter: None,
+            draining: Default::default(),
+            shutdown_requested: Default::default(),
         };
 
         if server.config().version() {
@@ -58,13 +141,70 @@ impl Server {
             process::exit(0);
         }
 
+        let config = server.config().reloaded().context("load config file")?;
+        server.config = Arc::new(RwLock::new(config));
         server.init_logging().context("set log verbosity")?;
         server.config().validate().context("validate config")?;
+        let admission = {
+            let config = server.config();
+            Admission::new(config.max_concurrent_rpcs(), config.rpc_method_limits())?
+        };
+        server.admission = Arc::new(admission);
 
         Self::init().context("init self")?;
         Ok(server)
     }
 
+    /// The current server configuration.
+    pub(crate) fn config(&self) -> RwLockReadGuard<'_, Config> {
+        Self::read_config(&self.config)
+    }
+
+    fn read_config(config: &RwLock<Config>) -> RwLockReadGuard<'_, Config> {
+        // The configuration gets only replaced as a whole, which keeps it consistent.
+        config.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Fails if the server is draining, which means that no new containers get accepted.
+    pub(crate) fn ensure_accepting(&self) -> Result<()> {
+        if self.draining().is_cancelled() {
+            bail!("server is shutting down");
+        }
+        Ok(())
+    }
+
+    /// The deadline of a request, which sets its own timeout in milliseconds or uses the
+    /// configured default if 0. Returns `None` if neither is set.
+    pub(crate) fn deadline(&self, timeout_ms: u64) -> Option<time::Instant> {
+        let timeout = match timeout_ms {
+            0 => Duration::from_secs(self.config().rpc_timeout()),
+            ms => Duration::from_millis(ms),
+        };
+        (!timeout.is_zero()).then(|| time::Instant::now() + timeout)
+    }
+
+    /// Re-read the config file, where the log level gets applied to the running server and the
+    /// other settings to new containers.
+    pub(crate) fn reload_config_file(&self) -> Result<()> {
+        Self::reload(&self.config, self.log_filter().as_deref())
+    }
+
+    fn reload(config: &RwLock<Config>, log_filter: Option<&LogFilter>) -> Result<()> {
+        let current = Self::read_config(config).clone();
+        let reloaded = current.reloaded().context("reload config file")?;
+        if let Some(log_filter) = log_filter {
+            // Keep filters set at runtime unless the configured log level changed.
+            if reloaded.log_level() != current.log_level() {
+                log_filter
+                    .set(reloaded.log_level())
+                    .context("set log level")?;
+            }
+        }
+        *config.write().unwrap_or_else(PoisonError::into_inner) = reloaded;
+        info!("Reloaded config file");
+        Ok(())
+    }
+
     /// Start the `Server` instance and consume it.
     pub fn start(self) -> Result<()> {
         // We need to fork as early as possible, especially before setting up tokio.
@@ -103,17 +243,24 @@ impl Server {
         init.set_oom_score("-1000")
     }
 
-    fn init_logging(&self) -> Result<()> {
-        let level =
-            LevelFilter::from_str(self.config().log_level()).context("convert log level filter")?;
-        let registry = tracing_subscriber::registry();
+    fn init_logging(&mut self) -> Result<()> {
+        let (log_filter, filter) =
+            LogFilter::new(self.config().log_level()).context("create log filter")?;
+        let rpc_durations = self
+            .config()
+            .metrics_address()
+            .is_some()
+            .then(|| RpcDurationLayer.with_filter(RpcDurationLayer::filter()));
+        let registry = tracing_subscriber::registry()
+            .with(self.error_log().clone().with_filter(LevelFilter::ERROR))
+            .with(rpc_durations);
 
         match self.config().log_driver() {
             LogDriver::Stdout => {
                 let layer = tracing_subscriber::fmt::layer()
                     .with_target(true)
                     .with_line_number(true)
-                    .with_filter(level);
+                    .with_filter(filter);
                 registry
                     .with(layer)
                     .try_init()
@@ -123,7 +270,7 @@ impl Server {
             LogDriver::Systemd => {
                 let layer = tracing_journald::layer()
                     .context("unable to connect to journald")?
-                    .with_filter(level);
+                    .with_filter(filter);
                 registry
                     .with(layer)
                     .try_init()
@@ -132,17 +279,40 @@ impl Server {
             }
         }
         info!("Set log level to: {}", self.config().log_level());
+        self.log_filter = Some(Arc::new(log_filter));
         Ok(())
     }
 
     /// Spwans all required tokio tasks.
     async fn spawn_tasks(self) -> Result<()> {
         let (shutdown_tx, shutdown_rx) = oneshot::channel();
-        let socket = self.config().socket();
         let reaper = self.reaper.clone();
+        let config = self.config.clone();
+        let log_filter = self.log_filter().clone();
+        let draining = self.draining().clone();
+        let shutdown_requested = self.shutdown_requested().clone();
+        self.log_quota().start();
+        if let Some(address) = self.config().metrics_address().clone() {
+            let reaper = self.reaper.clone();
+            task::spawn(
+                async move {
+                    if let Err(e) = metrics::serve(&address, reaper).await {
+                        error!("Unable to serve metrics: {:#}", e);
+                    }
+                }
+                .instrument(debug_span!("metrics")),
+            );
+        }
         task::spawn(
-            Self::start_signal_handler(reaper, socket, shutdown_tx)
-                .instrument(debug_span!("signal_handler")),
+            Self::start_signal_handler(
+                reaper,
+                config,
+                log_filter,
+                draining,
+                shutdown_requested,
+                shutdown_tx,
+            )
+            .instrument(debug_span!("signal_handler")),
         );
 
         task::spawn_blocking(move || {
@@ -158,69 +328,423 @@ impl Server {
         .await?
     }
 
-    async fn start_signal_handler<T: AsRef<Path>>(
+    async fn start_signal_handler(
         reaper: Arc<ChildReaper>,
-        socket: T,
-        shutdown_tx: oneshot::Sender<()>,
+        config: Arc<RwLock<Config>>,
+        log_filter: Option<Arc<LogFilter>>,
+        draining: CancellationToken,
+        shutdown_requested: CancellationToken,
+        shutdown_tx: oneshot::Sender<Signal>,
     ) -> Result<()> {
         let mut sigterm = signal(SignalKind::terminate())?;
         let mut sigint = signal(SignalKind::interrupt())?;
-        let handled_sig: Signal;
+        let mut sighup = signal(SignalKind::hangup())?;
 
-        tokio::select! {
-            _ = sigterm.recv() => {
-                info!("Received SIGTERM");
-                handled_sig = Signal::SIGTERM;
-            }
-            _ = sigint.recv() => {
-                info!("Received SIGINT");
-                handled_sig = Signal::SIGINT;
+        let handled_sig = loop {
+            tokio::select! {
+                _ = sigterm.recv() => {
+                    info!("Received SIGTERM");
+                    break Some(Signal::SIGTERM);
+                }
+                _ = sigint.recv() => {
+                    info!("Received SIGINT");
+                    break Some(Signal::SIGINT);
+                }
+                _ = sighup.recv() => {
+                    info!("Received SIGHUP");
+                    if let Err(e) = Self::reload(&config, log_filter.as_deref()) {
+                        error!("Unable to reload config: {:#}", e);
+                    }
+                }
+                _ = shutdown_requested.cancelled() => {
+                    info!("Received shutdown request");
+                    // Allow the response of the shutdown request to be sent.
+                    time::sleep(Self::SHUTDOWN_RESPONSE_DELAY).await;
+                    break None;
+                }
             }
         };
 
-        debug!("Starting grandchildren cleanup task");
-        reaper
-            .kill_grandchildren(handled_sig)
-            .context("unable to kill grandchildren")?;
+        // The shutdown request already drained the grandchildren.
+        if let Some(handled_sig) = handled_sig {
+            draining.cancel();
+            let timeout = Duration::from_secs(Self::read_config(&config).shutdown_timeout());
+            debug!("Draining grandchildren within {:?}", timeout);
+            let summary = reaper
+                .drain(timeout, handled_sig)
+                .await
+                .context("unable to drain grandchildren")?;
+            info!(
+                "Drained containers, {} exited and {} got killed",
+                summary.exited(),
+                summary.killed()
+            );
+        }
+
+        let (socket, fd_socket, grpc_socket, ttrpc_socket) = {
+            let config = Self::read_config(&config);
+            (
+                config.socket(),
+                config.fd_socket(),
+                config.grpc_socket().clone(),
+                config.ttrpc_socket().clone(),
+            )
+        };
 
         debug!("Sending shutdown message");
         shutdown_tx
-            .send(())
+            .send(handled_sig.unwrap_or(Signal::SIGTERM))
             .map_err(|_| format_err!("unable to send shutdown message"))?;
 
-        debug!("Removing socket file {}", socket.as_ref().display());
+        debug!("Removing fd socket file {}", fd_socket.display());
+        fs::remove_file(fd_socket)
+            .await
+            .context("remove existing fd socket file")?;
+
+        if let Some(grpc_socket) = grpc_socket {
+            debug!("Removing gRPC socket file {}", grpc_socket.display());
+            fs::remove_file(grpc_socket)
+                .await
+                .context("remove existing gRPC socket file")?;
+        }
+
+        if let Some(ttrpc_socket) = ttrpc_socket {
+            debug!("Removing ttrpc socket file {}", ttrpc_socket.display());
+            fs::remove_file(ttrpc_socket)
+                .await
+                .context("remove existing ttrpc socket file")?;
+        }
+
+        debug!("Removing socket file {}", socket.display());
         fs::remove_file(socket)
             .await
             .context("remove existing socket file")
     }
 
-    async fn start_backend(self, mut shutdown_rx: oneshot::Receiver<()>) -> Result<()> {
+    async fn start_backend(self, mut shutdown_rx: oneshot::Receiver<Signal>) -> Result<()> {
         let listener =
             Listener::<DefaultListener>::default().bind_long_path(&self.config().socket())?;
-        let client: conmon::Client = capnp_rpc::new_client(self);
+        let fd_listener =
+            Listener::<DefaultListener>::default().bind_long_path(&self.config().fd_socket())?;
+        let fd_socket = self.fd_socket().clone();
+        task::spawn(
+            async move {
+                if let Err(e) = fd_socket.serve(fd_listener).await {
+                    error!("Unable to serve fd socket: {:#}", e);
+                }
+            }
+            .instrument(debug_span!("fd_socket")),
+        );
+        #[cfg(feature = "grpc")]
+        let grpc_listener = match self.config().grpc_socket() {
+            Some(path) => Some(Listener::<DefaultListener>::default().bind_long_path(path)?),
+            None => None,
+        };
+        #[cfg(feature = "ttrpc")]
+        let ttrpc_listener = match self.config().ttrpc_socket() {
+            Some(path) => Some(Listener::<DefaultListener>::default().bind_long_path(path)?),
+            None => None,
+        };
+        let tls_address = self.config().tls_address();
+        let tls_listener = match tls_address {
+            Some(address) => Some(self.bind_tls_listener(address).await?),
+            None => None,
+        };
+        let http_address = self.config().http_address().clone();
+        let mut vsock_listener = match self.config().vsock_port() {
+            Some(port) => {
+                debug!("Serving RPC API on vsock port {}", port);
+                Some(VsockListener::bind(port)?)
+            }
+            None => None,
+        };
+        let (
+            allowed_methods,
+            tls_allowed_methods,
+            vsock_allowed_methods,
+            allowed_uids,
+            allowed_gids,
+            authenticator,
+            worker_pool,
+            create_parallelism,
+            audit_log,
+            shutdown_timeout,
+        ) = {
+            let config = self.config();
+            (
+                config.allowed_methods().clone(),
+                config.tls_allowed_methods().clone(),
+                config.vsock_allowed_methods().clone(),
+                config.allowed_uids().clone(),
+                config.allowed_gids().clone(),
+                match config.auth_token_file() {
+                    Some(path) => Some(Arc::new(Authenticator::load(path)?)),
+                    None => None,
+                },
+                match config.isolation() {
+                    Isolation::None => None,
+                    _ => Some(WorkerPool::new(&config)?),
+                },
+                config.create_parallelism(),
+                match config.audit_log() {
+                    Some(destination) => Some(AuditLog::open(destination)?),
+                    None => None,
+                },
+                Duration::from_secs(config.shutdown_timeout()),
+            )
+        };
+        let mut client: conmon::Client = capnp_rpc::new_client(self);
+        if let Some(pool) = worker_pool.clone() {
+            debug!("Forwarding container RPCs to isolated workers");
+            client = capnp_rpc::new_client(WorkerRouter::new(client, pool, create_parallelism));
+        }
+        let socket_client = method_filter::restrict(&client, &allowed_methods)?;
+        let tls_client = method_filter::restrict(&client, &tls_allowed_methods)?;
+        let vsock_client = method_filter::restrict(&client, &vsock_allowed_methods)?;
+
+        // The bridged APIs do not expose the credentials of their callers, which is why their
+        // audit records only contain the transport. They share the restrictions of the unix
+        // domain socket, including its allowed methods, peers and token.
+        let new_bridge = |transport| {
+            let client = match &audit_log {
+                Some(audit_log) => audit_log.audit(socket_client.clone(), Caller::new(transport)),
+                None => socket_client.clone(),
+            };
+            let (bridge, calls) = CapnpBridge::new();
+            task::spawn_local(CapnpBridge::run_calls(client, calls));
+            bridge
+        };
+        let access = Access::new(
+            authenticator.clone(),
+            allowed_uids.clone(),
+            allowed_gids.clone(),
+        );
+
+        if let Some(address) = http_address {
+            let api = HttpApi::new(new_bridge("http"), access.clone());
+            task::spawn(
+                async move {
+                    if let Err(e) = api.serve(&address).await {
+                        error!("Unable to serve HTTP API: {:#}", e);
+                    }
+                }
+                .instrument(debug_span!("http_api")),
+            );
+        }
+
+        #[cfg(feature = "grpc")]
+        if let Some(grpc_listener) = grpc_listener {
+            let service = GrpcService::new(new_bridge("grpc"), access.clone());
+            task::spawn(
+                async move {
+                    if let Err(e) = service.serve(grpc_listener).await {
+                        error!("Unable to serve gRPC API: {:#}", e);
+                    }
+                }
+                .instrument(debug_span!("grpc")),
+            );
+        }
+
+        #[cfg(feature = "ttrpc")]
+        if let Some(ttrpc_listener) = ttrpc_listener {
+            let service = TtrpcService::new(new_bridge("ttrpc"), access.clone());
+            task::spawn(
+                async move {
+                    if let Err(e) = service.serve(ttrpc_listener).await {
+                        error!("Unable to serve ttrpc API: {:#}", e);
+                    }
+                }
+                .instrument(debug_span!("ttrpc")),
+            );
+        }
 
         loop {
-            let stream = tokio::select! {
-                _ = &mut shutdown_rx => {
+            tokio::select! {
+                signal = &mut shutdown_rx => {
                     debug!("Received shutdown message");
+                    if let (Some(pool), Ok(signal)) = (&worker_pool, signal) {
+                        debug!("Shutting down workers within {:?}", shutdown_timeout);
+                        pool.shutdown(signal, shutdown_timeout).await;
+                    }
                     return Ok(())
                 }
                 stream = listener.accept() => {
-                    stream?.0
+                    let stream = stream?.0;
+                    let cred = match stream.peer_cred() {
+                        Ok(cred) => cred,
+                        Err(e) => {
+                            error!("Unable to get peer credentials: {:#}", e);
+                            continue;
+                        }
+                    };
+                    if !auth::peer_allowed(&cred, &allowed_uids, &allowed_gids) {
+                        warn!(
+                            "Rejecting connection of peer with UID {} and GID {}",
+                            cred.uid(),
+                            cred.gid()
+                        );
+                        continue;
+                    }
+                    let mut caller = Caller::new("unix");
+                    caller
+                        .set_uid(Some(cred.uid()))
+                        .set_gid(Some(cred.gid()))
+                        .set_pid(cred.pid());
+                    task::spawn_local(Self::serve_connection(
+                        stream,
+                        socket_client.clone(),
+                        authenticator.clone(),
+                        audit_log.clone(),
+                        caller,
+                    ));
                 },
-            };
-            let (reader, writer) = TokioAsyncReadCompatExt::compat(stream).split();
-            let network = Box::new(VatNetwork::new(
-                reader,
-                writer,
-                Side::Server,
-                Default::default(),
-            ));
-            let rpc_system = RpcSystem::new(network, Some(client.clone().client));
-            task::spawn_local(Box::pin(rpc_system.map(|_| ())));
+                stream = Self::accept_tls(tls_listener.as_ref()) => {
+                    let (stream, address) = match stream {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            error!("Unable to accept TLS connection: {:#}", e);
+                            continue;
+                        }
+                    };
+                    // The listener has to exist for a connection to be accepted.
+                    let acceptor = tls_listener
+                        .as_ref()
+                        .context("no TLS listener")?
+                        .acceptor()
+                        .clone();
+                    let client = tls_client.clone();
+                    let authenticator = authenticator.clone();
+                    let audit_log = audit_log.clone();
+                    let mut caller = Caller::new("tls");
+                    caller.set_address(Some(address.to_string()));
+                    task::spawn_local(
+                        async move {
+                            match acceptor.accept(stream).await {
+                                Ok(stream) => {
+                                    Self::serve_connection(
+                                        stream,
+                                        client,
+                                        authenticator,
+                                        audit_log,
+                                        caller,
+                                    )
+                                    .await
+                                }
+                                Err(e) => error!("TLS handshake with {} failed: {:#}", address, e),
+                            }
+                        }
+                        .instrument(debug_span!("tls", address = %address)),
+                    );
+                },
+                stream = Self::accept_vsock(vsock_listener.as_mut()) => {
+                    let mut stream = match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            error!("Unable to accept vsock connection: {:#}", e);
+                            continue;
+                        }
+                    };
+                    let client = vsock_client.clone();
+                    let authenticator = authenticator.clone();
+                    let audit_log = audit_log.clone();
+                    task::spawn_local(
+                        async move {
+                            match VsockListener::negotiate(&mut stream).await {
+                                Ok(version) => {
+                                    debug!("Negotiated protocol version {}", version);
+                                    Self::serve_connection(
+                                        stream,
+                                        client,
+                                        authenticator,
+                                        audit_log,
+                                        Caller::new("vsock"),
+                                    )
+                                    .await
+                                }
+                                Err(e) => error!("Version negotiation failed: {:#}", e),
+                            }
+                        }
+                        .instrument(debug_span!("vsock")),
+                    );
+                },
+            }
+        }
+    }
+
+    async fn bind_tls_listener(&self, address: SocketAddr) -> Result<TlsListener> {
+        let (cert, key, client_ca) = {
+            let config = self.config();
+            (
+                config.tls_cert().clone().context("no TLS certificate")?,
+                config.tls_key().clone().context("no TLS key")?,
+                config.tls_client_ca().clone().context("no TLS client CA")?,
+            )
+        };
+        debug!("Serving RPC API on TLS address {}", address);
+        TlsListener::bind(address, &cert, &key, &client_ca).await
+    }
+
+    /// Accept the next TLS connection, or wait forever if no TLS listener exists.
+    async fn accept_tls(listener: Option<&TlsListener>) -> Result<(TcpStream, SocketAddr)> {
+        match listener {
+            Some(listener) => listener.accept().await,
+            None => futures::future::pending().await,
         }
     }
 
+    /// Accept the next vsock connection, or wait forever if no vsock listener exists.
+    async fn accept_vsock(listener: Option<&mut VsockListener>) -> Result<VsockStream> {
+        match listener {
+            Some(listener) => listener.accept().await,
+            None => futures::future::pending().await,
+        }
+    }
+
+    /// Serve the RPC API on the connection, after authenticating it if a token file is
+    /// configured, and record its calls if an audit log is configured.
+    async fn serve_connection<T>(
+        stream: T,
+        client: conmon::Client,
+        authenticator: Option<Arc<Authenticator>>,
+        audit_log: Option<AuditLog>,
+        mut caller: Caller,
+    ) where
+        T: AsyncRead + AsyncWrite + Unpin + 'static,
+    {
+        // Keep the bytes buffered beyond the token for the RPC system.
+        let mut stream = BufReader::new(stream);
+        if let Some(authenticator) = authenticator {
+            match authenticator.authenticate(&mut stream).await {
+                Ok(name) => {
+                    debug!("Authenticated RPC caller {}", name);
+                    caller.set_token(Some(name.into()));
+                }
+                Err(e) => {
+                    warn!("Rejecting unauthenticated connection: {:#}", e);
+                    return;
+                }
+            }
+        }
+
+        let client = match audit_log {
+            Some(audit_log) => audit_log.audit(client, caller),
+            None => client,
+        };
+
+        let (reader, writer) = TokioAsyncReadCompatExt::compat(stream).split();
+        let network = Box::new(VatNetwork::new(
+            reader,
+            writer,
+            Side::Server,
+            Default::default(),
+        ));
+        if let Err(e) = RpcSystem::new(network, Some(client.client)).await {
+            debug!("RPC connection failed: {:#}", e);
+        }
+    }
+
+    const SHUTDOWN_RESPONSE_DELAY: Duration = Duration::from_millis(100);
+
     const SYSTEMD_CGROUP_ARG: &'static str = "--systemd-cgroup";
 
     /// Generate the OCI runtime CLI arguments from the provided parameters.
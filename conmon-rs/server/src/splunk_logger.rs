@@ -0,0 +1,527 @@
+//! Splunk HTTP Event Collector (HEC) logging functionalities.
+
+use crate::container_io::Pipe;
+use anyhow::{bail, Context, Result};
+use getset::{CopyGetters, Getters, Setters};
+use nix::unistd::gethostname;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    Certificate, Client, Url,
+};
+use serde_json::{Map, Value};
+use std::{fs, marker::Unpin, time::Duration};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, BufReader},
+    sync::mpsc::{self, error::TrySendError},
+    task::{self, JoinHandle},
+    time,
+};
+use tracing::{debug, error, trace, warn};
+use tz::UtcDateTime;
+
+#[derive(Debug, CopyGetters, Getters, Setters)]
+/// The structure used for posting container output to a Splunk HTTP Event Collector.
+pub struct SplunkLogger {
+    #[getset(get)]
+    /// The event endpoint of the collector.
+    url: Url,
+
+    /// The HTTP client including the token authentication and TLS configuration.
+    client: Client,
+
+    #[getset(get)]
+    /// The metadata added to every event, like `host`, `index` or the indexed `fields`.
+    metadata: Map<String, Value>,
+
+    #[getset(get)]
+    /// The tag added to every event, omitted if empty.
+    tag: String,
+
+    /// Sender of events to the batching task.
+    sender: Option<mpsc::Sender<Value>>,
+
+    /// The batching task posting the events to the collector.
+    task: Option<JoinHandle<()>>,
+
+    #[getset(get_copy, set)]
+    /// The initial delay between retries of failed posts, which doubles on every retry.
+    retry_delay: Duration,
+
+    #[getset(get_copy)]
+    /// The amount of events dropped because the buffer was full.
+    dropped: u64,
+}
+
+impl SplunkLogger {
+    const ERR_UNINITIALIZED: &'static str = "logger not initialized";
+
+    /// The path of the event endpoint relative to the collector URL.
+    const EVENT_PATH: &'static str = "services/collector/event/1.0";
+
+    /// The maximum amount of events per post.
+    const BATCH_SIZE: usize = 1000;
+
+    /// The maximum amount of buffered events, further events get dropped.
+    const BUFFER_SIZE: usize = 10 * Self::BATCH_SIZE;
+
+    /// The interval in which buffered events get posted.
+    const POST_INTERVAL: Duration = Duration::from_secs(5);
+
+    /// The timeout of a single post.
+    const POST_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// The maximum amount of post attempts before the events get dropped.
+    const MAX_ATTEMPTS: usize = 5;
+
+    /// The initial delay between retries.
+    const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+    /// The maximum delay between retries.
+    const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+    /// Create a new Splunk logger instance for the collector at `address`. Supported options are
+    /// `splunk-token` (required), `splunk-index`, `splunk-source`, `splunk-sourcetype`,
+    /// `splunk-capath` and `splunk-insecureskipverify`, like in the Docker Splunk driver.
+    pub fn new(
+        container_id: &str,
+        container_name: &str,
+        address: &str,
+        hostname: &str,
+        tag: &str,
+        options: &[(String, String)],
+    ) -> Result<SplunkLogger> {
+        let mut url = Url::parse(address).context(format!("parse Splunk URL '{}'", address))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            bail!("unsupported Splunk URL scheme: {}", url.scheme())
+        }
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+        let url = url
+            .join(Self::EVENT_PATH)
+            .context("build Splunk event URL")?;
+
+        let hostname = if hostname.is_empty() {
+            gethostname()
+                .context("get hostname")?
+                .to_string_lossy()
+                .into_owned()
+        } else {
+            hostname.into()
+        };
+        let mut fields = Map::new();
+        fields.insert("container_id".into(), container_id.into());
+        if !container_name.is_empty() {
+            fields.insert("container_name".into(), container_name.into());
+        }
+        let mut metadata = Map::new();
+        metadata.insert("host".into(), hostname.into());
+        metadata.insert("fields".into(), fields.into());
+
+        let mut token = None;
+        let mut builder = Client::builder().timeout(Self::POST_TIMEOUT);
+        for (key, value) in options {
+            match key.as_str() {
+                "splunk-token" => token = Some(value.as_str()),
+                "splunk-index" | "splunk-source" | "splunk-sourcetype" => {
+                    metadata.insert(
+                        key.trim_start_matches("splunk-").into(),
+                        value.as_str().into(),
+                    );
+                }
+                "splunk-capath" => {
+                    let pem = fs::read(value).context(format!("read Splunk CA '{}'", value))?;
+                    builder = builder.add_root_certificate(
+                        Certificate::from_pem(&pem).context("parse Splunk CA")?,
+                    );
+                }
+                "splunk-insecureskipverify" => {
+                    builder = builder.danger_accept_invalid_certs(
+                        value
+                            .parse()
+                            .context(format!("parse Splunk option {}", key))?,
+                    );
+                }
+                x => bail!("unsupported Splunk option: {}", x),
+            }
+        }
+
+        let mut authorization = HeaderValue::from_str(&format!(
+            "Splunk {}",
+            token.context("no Splunk token provided")?
+        ))
+        .context("invalid Splunk token")?;
+        authorization.set_sensitive(true);
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, authorization);
+
+        let tag = if tag.is_empty() { container_name } else { tag };
+
+        Ok(Self {
+            url,
+            client: builder
+                .default_headers(headers)
+                .build()
+                .context("build Splunk HTTP client")?,
+            metadata,
+            tag: tag.into(),
+            sender: None,
+            task: None,
+            retry_delay: Self::RETRY_DELAY,
+            dropped: 0,
+        })
+    }
+
+    /// Asynchronously initialize the Splunk logger by starting the batching task.
+    pub async fn init(&mut self) -> Result<()> {
+        debug!("Initializing Splunk logger for {}", self.url());
+        let (sender, receiver) = mpsc::channel(Self::BUFFER_SIZE);
+        self.sender = Some(sender);
+        self.task = Some(task::spawn(Self::post_events(
+            self.client.clone(),
+            self.url().clone(),
+            receiver,
+            self.retry_delay(),
+        )));
+        Ok(())
+    }
+
+    /// Write the contents of the provided reader as events, one per line. Events get dropped if
+    /// the buffer is full, for example because the collector is unreachable.
+    pub async fn write<T>(&mut self, pipe: Pipe, bytes: T) -> Result<()>
+    where
+        T: AsyncBufRead + Unpin,
+    {
+        let mut reader = BufReader::new(bytes);
+        loop {
+            let mut line = vec![];
+            let read = reader
+                .read_until(b'\n', &mut line)
+                .await
+                .context("read log line")?;
+            if read == 0 {
+                break;
+            }
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+
+            let event = self.event(pipe, &line)?;
+            match self
+                .sender
+                .as_ref()
+                .context(Self::ERR_UNINITIALIZED)?
+                .try_send(event)
+            {
+                Ok(()) => trace!("Buffered Splunk event of length {}", line.len()),
+                Err(TrySendError::Full(_)) => {
+                    if self.dropped == 0 {
+                        warn!("Splunk event buffer is full, dropping events");
+                    }
+                    self.dropped += 1;
+                }
+                Err(TrySendError::Closed(_)) => bail!("Splunk batching task stopped"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Post all buffered events and restart the batching task.
+    pub async fn reopen(&mut self) -> Result<()> {
+        debug!("Reopen Splunk logger");
+        self.close().await?;
+        self.init().await
+    }
+
+    /// Post all buffered events and stop the batching task.
+    pub async fn close(&mut self) -> Result<()> {
+        self.sender = None;
+        if let Some(task) = self.task.take() {
+            task.await.context("wait for Splunk batching task")?;
+        }
+        Ok(())
+    }
+
+    /// Build the event for the provided log line.
+    fn event(&self, pipe: Pipe, line: &[u8]) -> Result<Value> {
+        let now = UtcDateTime::now().context("get UTC datetime")?;
+        let mut event = Map::new();
+        event.insert(
+            "line".into(),
+            String::from_utf8_lossy(line).into_owned().into(),
+        );
+        event.insert("source".into(), pipe.to_string().into());
+        if !self.tag().is_empty() {
+            event.insert("tag".into(), self.tag().as_str().into());
+        }
+
+        let mut message = self.metadata().clone();
+        message.insert(
+            "time".into(),
+            format!("{}.{:06}", now.unix_time(), now.nanoseconds() / 1000).into(),
+        );
+        message.insert("event".into(), event.into());
+        Ok(message.into())
+    }
+
+    /// Batch the received events and post them once the batch is full, the post interval
+    /// elapsed or the sender got dropped.
+    async fn post_events(
+        client: Client,
+        url: Url,
+        mut receiver: mpsc::Receiver<Value>,
+        retry_delay: Duration,
+    ) {
+        let mut batch = vec![];
+        let mut interval = time::interval(Self::POST_INTERVAL);
+        loop {
+            let closed = tokio::select! {
+                event = receiver.recv() => match event {
+                    Some(event) => {
+                        batch.push(event);
+                        if batch.len() < Self::BATCH_SIZE {
+                            continue;
+                        }
+                        false
+                    }
+                    None => true,
+                },
+                _ = interval.tick() => false,
+            };
+            if !batch.is_empty() {
+                Self::post(&client, &url, std::mem::take(&mut batch), retry_delay).await;
+            }
+            if closed {
+                return;
+            }
+        }
+    }
+
+    /// Post the events with exponential backoff, where they get dropped after the maximum
+    /// amount of attempts.
+    async fn post(client: &Client, url: &Url, events: Vec<Value>, retry_delay: Duration) {
+        let mut body = vec![];
+        for event in &events {
+            body.extend_from_slice(event.to_string().as_bytes());
+            body.push(b'\n');
+        }
+
+        let mut delay = retry_delay;
+        for attempt in 1..=Self::MAX_ATTEMPTS {
+            match Self::send(client, url, body.clone()).await {
+                Ok(()) => {
+                    trace!("Posted {} events to Splunk", events.len());
+                    return;
+                }
+                Err(e) if attempt < Self::MAX_ATTEMPTS => {
+                    warn!(
+                        "Unable to post events to Splunk (attempt {}/{}), retrying in {:?}: {:#}",
+                        attempt,
+                        Self::MAX_ATTEMPTS,
+                        delay,
+                        e
+                    );
+                    time::sleep(delay).await;
+                    delay = (delay * 2).min(Self::MAX_RETRY_DELAY);
+                }
+                Err(e) => error!(
+                    "Dropping {} events after {} failed posts to Splunk: {:#}",
+                    events.len(),
+                    attempt,
+                    e
+                ),
+            }
+        }
+    }
+
+    async fn send(client: &Client, url: &Url, body: Vec<u8>) -> Result<()> {
+        let response = client
+            .post(url.clone())
+            .body(body)
+            .send()
+            .await
+            .context("send events")?;
+        let status = response.status();
+        if !status.is_success() {
+            bail!(
+                "unexpected status {}: {}",
+                status,
+                response.text().await.unwrap_or_default()
+            )
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    const ID: &str = "0123456789abcdef";
+
+    /// A received request consisting of the header and the body.
+    type Request = (String, String);
+
+    /// Serve one request per provided status code.
+    async fn serve(listener: TcpListener, statuses: Vec<u16>) -> Result<Vec<Request>> {
+        let mut requests = vec![];
+        let mut stream = None;
+        for status in statuses {
+            loop {
+                if stream.is_none() {
+                    stream = Some(BufReader::new(listener.accept().await?.0));
+                }
+                let reader = stream.as_mut().context("no stream")?;
+
+                let mut header = String::new();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).await? == 0 {
+                        break;
+                    }
+                    header.push_str(&line);
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                if header.is_empty() {
+                    stream = None;
+                    continue;
+                }
+
+                let len = header
+                    .lines()
+                    .find_map(|x| {
+                        x.to_lowercase()
+                            .strip_prefix("content-length: ")
+                            .map(String::from)
+                    })
+                    .context("no content length")?
+                    .parse::<usize>()?;
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).await?;
+                reader
+                    .get_mut()
+                    .write_all(
+                        format!("HTTP/1.1 {} Status\r\nContent-Length: 0\r\n\r\n", status)
+                            .as_bytes(),
+                    )
+                    .await?;
+                requests.push((header, String::from_utf8(body)?));
+                break;
+            }
+        }
+        Ok(requests)
+    }
+
+    async fn logger(listener: &TcpListener) -> Result<SplunkLogger> {
+        let mut sut = SplunkLogger::new(
+            ID,
+            "name",
+            &format!("http://{}/", listener.local_addr()?),
+            "host",
+            "",
+            &[
+                ("splunk-token".into(), "secret".into()),
+                ("splunk-index".into(), "main".into()),
+            ],
+        )?;
+        sut.set_retry_delay(Duration::from_millis(10));
+        sut.init().await?;
+        Ok(sut)
+    }
+
+    #[tokio::test]
+    async fn write() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut sut = logger(&listener).await?;
+        let server = task::spawn(serve(listener, vec![200]));
+
+        sut.write(Pipe::StdOut, "a\n".as_bytes()).await?;
+        sut.write(Pipe::StdErr, "b\n".as_bytes()).await?;
+        sut.close().await?;
+
+        let requests = server.await??;
+        assert_eq!(requests.len(), 1);
+        let (header, body) = &requests[0];
+        assert!(header.starts_with("POST /services/collector/event/1.0 "));
+        assert!(header
+            .to_lowercase()
+            .contains("authorization: splunk secret"));
+
+        let events = body
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<Value>, _>>()?;
+        assert_eq!(events.len(), 2);
+        for (event, (line, source)) in events.iter().zip(&[("a", "stdout"), ("b", "stderr")]) {
+            assert_eq!(event["host"], "host");
+            assert_eq!(event["index"], "main");
+            assert_eq!(event["fields"]["container_id"], ID);
+            assert_eq!(event["fields"]["container_name"], "name");
+            assert!(event["time"].as_str().context("no time")?.contains('.'));
+            assert_eq!(event["event"]["line"], *line);
+            assert_eq!(event["event"]["source"], *source);
+            assert_eq!(event["event"]["tag"], "name");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_retry() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut sut = logger(&listener).await?;
+        let server = task::spawn(serve(listener, vec![503, 200]));
+
+        sut.write(Pipe::StdOut, "a\n".as_bytes()).await?;
+        sut.close().await?;
+
+        let requests = server.await??;
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].1, requests[1].1);
+        Ok(())
+    }
+
+    #[test]
+    fn url() -> Result<()> {
+        let options = [("splunk-token".to_string(), "secret".to_string())];
+        for (address, expected) in &[
+            (
+                "https://splunk:8088",
+                "https://splunk:8088/services/collector/event/1.0",
+            ),
+            (
+                "http://splunk/prefix",
+                "http://splunk/prefix/services/collector/event/1.0",
+            ),
+        ] {
+            let sut = SplunkLogger::new(ID, "", address, "", "", &options)?;
+            assert_eq!(sut.url().as_str(), *expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_config() {
+        let token = ("splunk-token".to_string(), "secret".to_string());
+        for (address, options) in [
+            ("https://splunk", vec![]),
+            ("ftp://splunk", vec![token.clone()]),
+            ("splunk", vec![token.clone()]),
+            (
+                "https://splunk",
+                vec![token.clone(), ("splunk-unknown".into(), "".into())],
+            ),
+            (
+                "https://splunk",
+                vec![token, ("splunk-insecureskipverify".into(), "yes".into())],
+            ),
+        ] {
+            assert!(SplunkLogger::new(ID, "", address, "", "", &options).is_err());
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! Runtime diagnostics of the server process reported by the status RPC.
+
+use anyhow::{format_err, Context, Result};
+use getset::{CopyGetters, Getters};
+use nix::sys::resource::{getrlimit, Resource};
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write},
+    fs,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::layer::{Context as LayerContext, Layer};
+
+macro_rules! lock {
+    ($x:expr) => {
+        $x.lock().map_err(|e| format_err!("{:#}", e))?
+    };
+}
+
+#[derive(Clone, Debug, Default)]
+/// A tracing layer keeping the most recent errors logged by the server.
+pub struct ErrorLog(Arc<Mutex<VecDeque<LoggedError>>>);
+
+#[derive(Clone, Debug, Eq, Getters, PartialEq)]
+#[getset(get = "pub")]
+/// A single error logged by the server.
+pub struct LoggedError {
+    /// The time the error got logged.
+    time: SystemTime,
+
+    /// The target of the event, which is usually the module path.
+    target: String,
+
+    /// The message along with all other fields of the event.
+    message: String,
+}
+
+impl ErrorLog {
+    /// The maximum amount of kept errors, where the oldest ones get dropped first.
+    const CAPACITY: usize = 16;
+
+    /// The kept errors, oldest first.
+    pub fn errors(&self) -> Result<Vec<LoggedError>> {
+        Ok(lock!(self.0).iter().cloned().collect())
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ErrorLog {
+    fn on_event(&self, event: &Event<'_>, _: LayerContext<'_, S>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        // Logging errors of the layer itself would recurse.
+        if let Ok(mut errors) = self.0.lock() {
+            if errors.len() == Self::CAPACITY {
+                errors.pop_front();
+            }
+            errors.push_back(LoggedError {
+                time: SystemTime::now(),
+                target: event.metadata().target().into(),
+                message: visitor.finish(),
+            });
+        }
+    }
+}
+
+#[derive(Default)]
+/// Collects the message of an event and its other fields as `name=value`.
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl MessageVisitor {
+    /// The message followed by the other fields.
+    fn finish(self) -> String {
+        match (self.message.is_empty(), self.fields.is_empty()) {
+            (_, true) => self.message,
+            (true, false) => self.fields,
+            (false, false) => format!("{} {}", self.message, self.fields),
+        }
+    }
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+            return;
+        }
+        if !self.fields.is_empty() {
+            self.fields.push(' ');
+        }
+        let _ = write!(self.fields, "{}={:?}", field.name(), value);
+    }
+}
+
+#[derive(Clone, Copy, CopyGetters, Debug, Eq, PartialEq)]
+#[getset(get_copy = "pub")]
+/// The resource usage of the server process.
+pub struct ProcessStatus {
+    /// The amount of kernel tasks, which are the threads of the process.
+    tasks: u64,
+
+    /// The amount of open file descriptors.
+    open_fds: u64,
+
+    /// The soft limit of open file descriptors.
+    max_fds: u64,
+}
+
+impl ProcessStatus {
+    /// Read the resource usage of the current process.
+    pub fn read() -> Result<Self> {
+        let count = |path| -> Result<u64> {
+            Ok(fs::read_dir(path)
+                .context(format!("read {}", path))?
+                .count() as u64)
+        };
+        let (max_fds, _) = getrlimit(Resource::RLIMIT_NOFILE).context("get fd limit")?;
+        Ok(Self {
+            tasks: count("/proc/self/task")?,
+            // The directory itself is open while reading it.
+            open_fds: count("/proc/self/fd")?.saturating_sub(1),
+            max_fds,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::{error, warn};
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn error_log() -> Result<()> {
+        let sut = ErrorLog::default();
+        let subscriber = tracing_subscriber::registry().with(sut.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            warn!("not recorded");
+            for i in 0..=ErrorLog::CAPACITY {
+                error!(pid = 1, "failure {}", i);
+            }
+        });
+
+        let errors = sut.errors()?;
+        assert_eq!(errors.len(), ErrorLog::CAPACITY);
+        assert_eq!(errors[0].message(), "failure 1 pid=1");
+        assert_eq!(
+            errors[ErrorLog::CAPACITY - 1].message(),
+            &format!("failure {} pid=1", ErrorLog::CAPACITY)
+        );
+        assert_eq!(errors[0].target(), module_path!());
+        Ok(())
+    }
+
+    #[test]
+    fn process_status() -> Result<()> {
+        let sut = ProcessStatus::read()?;
+        assert!(sut.tasks() > 0);
+        assert!(sut.open_fds() > 0);
+        assert!(sut.max_fds() >= sut.open_fds());
+        Ok(())
+    }
+}
@@ -1,22 +1,28 @@
 //! Pseudo terminal implementation.
 
+#[cfg(feature = "io-uring")]
+use crate::uring::UringIo;
 use crate::{
     attach::SharedContainerAttach,
-    container_io::{ContainerIO, Message, Pipe},
+    container_io::{ContainerIO, IoMetrics, Message, Pipe},
     container_log::SharedContainerLog,
+    flush_policy::FlushPolicy,
 };
 use anyhow::Result;
-use getset::Getters;
-use std::os::unix::io::AsRawFd;
+use getset::{Getters, Setters};
+use std::sync::Arc;
 use tokio::{
     process::{ChildStderr, ChildStdin, ChildStdout},
-    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        watch,
+    },
     task,
 };
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, debug_span, error, Instrument};
 
-#[derive(Debug, Getters)]
+#[derive(Debug, Getters, Setters)]
 pub struct Streams {
     #[getset(get = "pub")]
     logger: SharedContainerLog,
@@ -24,6 +30,19 @@ pub struct Streams {
     #[getset(get = "pub")]
     attach: SharedContainerAttach,
 
+    /// The IO counters of the container.
+    metrics: Arc<IoMetrics>,
+
+    /// Whether consuming the output is paused.
+    paused: watch::Receiver<bool>,
+
+    /// The size of the buffer for reading stdout and stderr.
+    buf_size: usize,
+
+    #[getset(set = "pub")]
+    /// The policy of forwarding the read output.
+    flush_policy: FlushPolicy,
+
     pub message_rx_stdout: UnboundedReceiver<Message>,
 
     #[getset(get = "pub")]
@@ -36,8 +55,14 @@ pub struct Streams {
 }
 
 impl Streams {
-    /// Create a new Streams instance.
-    pub fn new(logger: SharedContainerLog, attach: SharedContainerAttach) -> Result<Self> {
+    /// Create a new Streams instance, which reads stdout and stderr in chunks of `buf_size`.
+    pub fn new(
+        logger: SharedContainerLog,
+        attach: SharedContainerAttach,
+        metrics: Arc<IoMetrics>,
+        paused: watch::Receiver<bool>,
+        buf_size: usize,
+    ) -> Result<Self> {
         debug!("Creating new IO streams");
 
         let (message_tx_stdout, message_rx_stdout) = mpsc::unbounded_channel();
@@ -46,6 +71,10 @@ impl Streams {
         Ok(Self {
             logger,
             attach,
+            metrics,
+            paused,
+            buf_size,
+            flush_policy: FlushPolicy::default(),
             message_rx_stdout,
             message_tx_stdout,
             message_rx_stderr,
@@ -61,16 +90,28 @@ impl Streams {
         token: CancellationToken,
     ) {
         debug!("Start reading from IO streams");
+        #[cfg(feature = "io-uring")]
+        let (stdin, stdout, stderr) = (
+            stdin.map(UringIo::new),
+            stdout.map(UringIo::new),
+            stderr.map(UringIo::new),
+        );
+
+        let buf_size = self.buf_size;
+        let flush_policy = self.flush_policy;
         let logger = self.logger().clone();
-        let attach = self.attach().clone();
+        let mut attach = self.attach().clone();
+        let metrics = self.metrics.clone();
         let message_tx = self.message_tx_stdout().clone();
 
         let token_clone = token.clone();
         if let Some(stdin) = stdin {
             task::spawn(
                 async move {
+                    // Dropping stdin at the end of the loop closes the pipe of the container.
                     if let Err(e) =
-                        ContainerIO::read_loop_stdin(stdin.as_raw_fd(), attach, token_clone).await
+                        ContainerIO::read_loop_stdin(stdin, &mut attach, &metrics, token_clone)
+                            .await
                     {
                         error!("Stdin read loop failure: {:#}", e);
                     }
@@ -80,16 +121,22 @@ impl Streams {
         }
 
         let attach = self.attach().clone();
+        let metrics = self.metrics.clone();
+        let paused = self.paused.clone();
         let token_clone = token.clone();
         if let Some(stdout) = stdout {
             task::spawn(
                 async move {
                     if let Err(e) = ContainerIO::read_loop(
                         stdout,
+                        buf_size,
                         Pipe::StdOut,
                         logger,
                         message_tx,
                         attach,
+                        metrics,
+                        paused,
+                        flush_policy,
                         token_clone,
                     )
                     .await
@@ -103,16 +150,22 @@ impl Streams {
 
         let logger = self.logger().clone();
         let attach = self.attach().clone();
+        let metrics = self.metrics.clone();
+        let paused = self.paused.clone();
         let message_tx = self.message_tx_stderr().clone();
         if let Some(stderr) = stderr {
             task::spawn(
                 async move {
                     if let Err(e) = ContainerIO::read_loop(
                         stderr,
+                        buf_size,
                         Pipe::StdErr,
                         logger,
                         message_tx,
                         attach,
+                        metrics,
+                        paused,
+                        flush_policy,
                         token,
                     )
                     .await
@@ -129,9 +182,15 @@ impl Streams {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{attach::SharedContainerAttach, container_log::ContainerLog};
+    use crate::{
+        attach::SharedContainerAttach, container_io::BufferSizes, container_log::ContainerLog,
+    };
     use anyhow::{bail, Context};
-    use std::{process::Stdio, str::from_utf8};
+    use std::{
+        process::Stdio,
+        str::from_utf8,
+        time::{Duration, Instant},
+    };
     use tokio::process::Command;
 
     fn msg_string(message: Message) -> Result<String> {
@@ -147,7 +206,13 @@ mod tests {
         let attach = SharedContainerAttach::default();
         let token = CancellationToken::new();
 
-        let mut sut = Streams::new(logger, attach)?;
+        let mut sut = Streams::new(
+            logger,
+            attach,
+            Arc::default(),
+            watch::channel(false).1,
+            BufferSizes::default().output(),
+        )?;
 
         let expected = "hello world";
         let mut child = Command::new("echo")
@@ -195,4 +260,97 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn flush_policy_line() -> Result<()> {
+        let logger = ContainerLog::new();
+        let attach = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+
+        let mut sut = Streams::new(
+            logger,
+            attach,
+            Arc::default(),
+            watch::channel(false).1,
+            BufferSizes::default().output(),
+        )?;
+        sut.set_flush_policy(FlushPolicy::Line {
+            size: 64,
+            max_delay: Duration::from_millis(100),
+        });
+
+        let mut child = Command::new("printf")
+            .arg("a\\nb")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        sut.handle_stdio_receive(
+            None,
+            child.stdout.take(),
+            child.stderr.take(),
+            token.clone(),
+        );
+
+        // The trailing partial line gets forwarded once the deadline elapsed
+        let msg = sut.message_rx_stdout.recv().await.context("no line")?;
+        assert_eq!(msg_string(msg)?, "a\n");
+        let start = Instant::now();
+        let msg = sut
+            .message_rx_stdout
+            .recv()
+            .await
+            .context("no partial line")?;
+        assert_eq!(msg_string(msg)?, "b");
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        token.cancel();
+        child.wait().await?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn paused() -> Result<()> {
+        let logger = ContainerLog::new();
+        let attach = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let (paused_tx, paused_rx) = watch::channel(true);
+
+        let mut sut = Streams::new(
+            logger,
+            attach,
+            Arc::default(),
+            paused_rx,
+            BufferSizes::default().output(),
+        )?;
+
+        let mut child = Command::new("echo")
+            .arg("-n")
+            .arg("hello")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        sut.handle_stdio_receive(
+            None,
+            child.stdout.take(),
+            child.stderr.take(),
+            token.clone(),
+        );
+
+        // Nothing gets read until resuming
+        child.wait().await?;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), sut.message_rx_stdout.recv())
+                .await
+                .is_err()
+        );
+
+        paused_tx.send(false)?;
+        let msg = sut.message_rx_stdout.recv().await.context("no message")?;
+        assert_eq!(msg_string(msg)?, "hello");
+
+        token.cancel();
+        Ok(())
+    }
 }
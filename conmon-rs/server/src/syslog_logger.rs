@@ -0,0 +1,416 @@
+//! RFC 5424 syslog logging functionalities.
+
+use crate::{container_io::Pipe, log_transport::Transport};
+use anyhow::{bail, Context, Result};
+use getset::{CopyGetters, Getters};
+use memchr::memchr;
+use nix::unistd::gethostname;
+use std::marker::Unpin;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpStream, UdpSocket, UnixDatagram},
+};
+use tracing::{debug, trace};
+use tz::UtcDateTime;
+
+#[derive(Debug, CopyGetters, Getters)]
+/// The structure used for logging container output to a syslog server.
+pub struct SyslogLogger {
+    #[getset(get)]
+    /// The transport used to reach the syslog server.
+    transport: Transport,
+
+    /// Connection to the syslog server.
+    connection: Option<Connection>,
+
+    #[getset(get_copy)]
+    /// The syslog facility code.
+    facility: u8,
+
+    #[getset(get)]
+    /// The APP-NAME of the messages.
+    tag: String,
+
+    #[getset(get)]
+    /// The HOSTNAME of the messages.
+    hostname: String,
+}
+
+#[derive(Debug)]
+enum Connection {
+    UnixDatagram(UnixDatagram),
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+impl SyslogLogger {
+    const ERR_UNINITIALIZED: &'static str = "logger not initialized";
+
+    /// The local syslog socket used if no address is provided.
+    const DEFAULT_SOCKET: &'static str = "/dev/log";
+
+    /// The port used if the address does not contain one.
+    const DEFAULT_PORT: u16 = 514;
+
+    /// The facility used if none is provided.
+    const DEFAULT_FACILITY: &'static str = "daemon";
+
+    /// The maximum length of the APP-NAME as defined by RFC 5424.
+    const MAX_TAG_LEN: usize = 48;
+
+    /// The maximum length of the HOSTNAME as defined by RFC 5424.
+    const MAX_HOSTNAME_LEN: usize = 255;
+
+    /// The length of the shortened container identifier used as default tag.
+    const SHORT_ID_LEN: usize = 12;
+
+    /// Severity of stdout messages (informational).
+    const SEVERITY_STDOUT: u8 = 6;
+
+    /// Severity of stderr messages (error).
+    const SEVERITY_STDERR: u8 = 3;
+
+    /// Create a new syslog logger instance. Empty values fall back to the defaults, which are
+    /// the local syslog socket, the daemon facility, the short container ID as tag and the
+    /// hostname of the system.
+    pub fn new(
+        container_id: &str,
+        address: &str,
+        facility: &str,
+        tag: &str,
+        hostname: &str,
+    ) -> Result<SyslogLogger> {
+        let tag = if tag.is_empty() {
+            container_id
+                .get(..Self::SHORT_ID_LEN)
+                .unwrap_or(container_id)
+        } else {
+            tag
+        };
+
+        let hostname = if hostname.is_empty() {
+            gethostname()
+                .context("get hostname")?
+                .to_string_lossy()
+                .into_owned()
+        } else {
+            hostname.into()
+        };
+
+        Ok(Self {
+            transport: Self::parse_address(address)?,
+            connection: None,
+            facility: Self::parse_facility(facility)?,
+            tag: Self::sanitize(tag, Self::MAX_TAG_LEN),
+            hostname: Self::sanitize(&hostname, Self::MAX_HOSTNAME_LEN),
+        })
+    }
+
+    /// Asynchronously initialize the syslog logger.
+    pub async fn init(&mut self) -> Result<()> {
+        debug!("Initializing syslog logger for {:?}", self.transport());
+        self.connection = Some(match self.transport() {
+            Transport::UnixDatagram(path) => {
+                let socket = UnixDatagram::unbound().context("create syslog socket")?;
+                socket
+                    .connect(path)
+                    .context(format!("connect to syslog socket '{}'", path.display()))?;
+                Connection::UnixDatagram(socket)
+            }
+            Transport::Udp(address) => {
+                let socket = UdpSocket::bind(if address.starts_with('[') {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                })
+                .await
+                .context("bind UDP socket")?;
+                socket
+                    .connect(address)
+                    .await
+                    .context(format!("connect to syslog address {}", address))?;
+                Connection::Udp(socket)
+            }
+            Transport::Tcp(address) => Connection::Tcp(
+                TcpStream::connect(address)
+                    .await
+                    .context(format!("connect to syslog address {}", address))?,
+            ),
+        });
+        Ok(())
+    }
+
+    /// Write the contents of the provided reader to the syslog server, one message per line.
+    pub async fn write<T>(&mut self, pipe: Pipe, bytes: T) -> Result<()>
+    where
+        T: AsyncBufRead + Unpin,
+    {
+        let mut reader = BufReader::new(bytes);
+        let severity = match pipe {
+            Pipe::StdOut => Self::SEVERITY_STDOUT,
+            Pipe::StdErr => Self::SEVERITY_STDERR,
+        };
+
+        loop {
+            let mut line_buf = vec![];
+            let read = Self::read_line(&mut reader, &mut line_buf).await?;
+            if read == 0 {
+                break;
+            }
+            if line_buf.last() == Some(&b'\n') {
+                line_buf.pop();
+            }
+
+            let message = self.message(severity, &line_buf)?;
+            match self.connection.as_mut().context(Self::ERR_UNINITIALIZED)? {
+                Connection::UnixDatagram(socket) => {
+                    socket.send(&message).await?;
+                }
+                Connection::Udp(socket) => {
+                    socket.send(&message).await?;
+                }
+                Connection::Tcp(stream) => {
+                    stream
+                        .write_all(format!("{} ", message.len()).as_bytes())
+                        .await?;
+                    stream.write_all(&message).await?;
+                }
+            }
+            trace!("Wrote syslog message of length {}", message.len());
+        }
+        Ok(())
+    }
+
+    /// Reconnect to the syslog server.
+    pub async fn reopen(&mut self) -> Result<()> {
+        debug!("Reopen syslog logger");
+        self.init().await
+    }
+
+    /// Serialize a log line into an RFC 5424 message without structured data.
+    fn message(&self, severity: u8, line: &[u8]) -> Result<Vec<u8>> {
+        let now = UtcDateTime::now().context("get UTC datetime")?;
+        let mut message = format!(
+            "<{}>1 {}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z {} {} - - - ",
+            self.facility() * 8 + severity,
+            now.year(),
+            now.month(),
+            now.month_day(),
+            now.hour(),
+            now.minute(),
+            now.second(),
+            now.nanoseconds() / 1000,
+            self.hostname(),
+            self.tag(),
+        )
+        .into_bytes();
+        message.extend_from_slice(line);
+        Ok(message)
+    }
+
+    /// Parse the address into a transport. Supported are paths to unix datagram sockets,
+    /// optionally prefixed by `unixgram://` or `unix://`, as well as `udp://` and `tcp://`
+    /// addresses. TCP messages use octet counting framing.
+    fn parse_address(address: &str) -> Result<Transport> {
+        Ok(if address.is_empty() {
+            Transport::UnixDatagram(Self::DEFAULT_SOCKET.into())
+        } else if address.starts_with('/') {
+            Transport::UnixDatagram(address.into())
+        } else {
+            Transport::parse(
+                address,
+                &["unixgram", "unix", "udp", "tcp"],
+                Self::DEFAULT_PORT,
+            )
+            .context("invalid syslog address")?
+        })
+    }
+
+    /// Parse the facility name into its code.
+    fn parse_facility(facility: &str) -> Result<u8> {
+        let facility = if facility.is_empty() {
+            Self::DEFAULT_FACILITY
+        } else {
+            facility
+        };
+        Ok(match facility {
+            "kern" => 0,
+            "user" => 1,
+            "mail" => 2,
+            "daemon" => 3,
+            "auth" => 4,
+            "syslog" => 5,
+            "lpr" => 6,
+            "news" => 7,
+            "uucp" => 8,
+            "cron" => 9,
+            "authpriv" => 10,
+            "ftp" => 11,
+            "local0" => 16,
+            "local1" => 17,
+            "local2" => 18,
+            "local3" => 19,
+            "local4" => 20,
+            "local5" => 21,
+            "local6" => 22,
+            "local7" => 23,
+            x => bail!("unsupported syslog facility: {}", x),
+        })
+    }
+
+    /// Restrict the header field to printable ASCII without spaces as required by RFC 5424.
+    fn sanitize(value: &str, max_len: usize) -> String {
+        let sanitized = value
+            .chars()
+            .filter(|c| c.is_ascii_graphic())
+            .take(max_len)
+            .collect::<String>();
+        if sanitized.is_empty() {
+            "-".into()
+        } else {
+            sanitized
+        }
+    }
+
+    async fn read_line<T>(r: &mut BufReader<T>, buf: &mut Vec<u8>) -> Result<usize>
+    where
+        T: AsyncBufRead + Unpin,
+    {
+        let read = {
+            let available = r.fill_buf().await?;
+            match memchr(b'\n', available) {
+                Some(i) => {
+                    buf.extend_from_slice(&available[..=i]);
+                    i + 1
+                }
+                None => {
+                    buf.extend_from_slice(available);
+                    available.len()
+                }
+            }
+        };
+        r.consume(read);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    const ID: &str = "0123456789abcdef";
+
+    fn assert_message(message: &[u8], pri: u8, msg: &str) {
+        let message = String::from_utf8_lossy(message);
+        let parts = message.splitn(8, ' ').collect::<Vec<_>>();
+        assert_eq!(parts[0], format!("<{}>1", pri));
+        assert!(parts[1].ends_with('Z'));
+        assert_eq!(parts[2], "host");
+        assert_eq!(parts[3], "tag");
+        assert_eq!(&parts[4..7], &["-", "-", "-"]);
+        assert_eq!(parts[7], msg);
+    }
+
+    #[tokio::test]
+    async fn write_unix_datagram() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log");
+        let server = UnixDatagram::bind(&path)?;
+
+        let address = format!("unixgram://{}", path.display());
+        let mut sut = SyslogLogger::new(ID, &address, "local0", "tag", "host")?;
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, "a\n".as_bytes()).await?;
+        sut.write(Pipe::StdErr, "b".as_bytes()).await?;
+
+        let mut buf = vec![0; 1024];
+        let n = server.recv(&mut buf).await?;
+        assert_message(&buf[..n], 16 * 8 + 6, "a");
+        let n = server.recv(&mut buf).await?;
+        assert_message(&buf[..n], 16 * 8 + 3, "b");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_udp() -> Result<()> {
+        let server = UdpSocket::bind("127.0.0.1:0").await?;
+        let address = format!("udp://{}", server.local_addr()?);
+        let mut sut = SyslogLogger::new(ID, &address, "", "tag", "host")?;
+        sut.init().await?;
+
+        sut.write(Pipe::StdOut, "hello world\n".as_bytes()).await?;
+
+        let mut buf = vec![0; 1024];
+        let n = server.recv(&mut buf).await?;
+        assert_message(&buf[..n], 3 * 8 + 6, "hello world");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_tcp() -> Result<()> {
+        let server = TcpListener::bind("127.0.0.1:0").await?;
+        let address = format!("tcp://{}", server.local_addr()?);
+        let mut sut = SyslogLogger::new(ID, &address, "user", "tag", "host")?;
+        sut.init().await?;
+        let (mut stream, _) = server.accept().await?;
+
+        sut.write(Pipe::StdErr, "a\nb\n".as_bytes()).await?;
+        drop(sut);
+
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await?;
+        for (msg, expected) in [(&res[..], "a"), (&res[res.len() / 2..], "b")] {
+            let (len, rest) = msg.split_once(' ').context("no length")?;
+            let len = len.parse::<usize>()?;
+            assert_message(&rest.as_bytes()[..len], 8 + 3, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn defaults() -> Result<()> {
+        let sut = SyslogLogger::new(ID, "", "", "", "")?;
+        assert_eq!(sut.transport(), &Transport::UnixDatagram("/dev/log".into()));
+        assert_eq!(sut.facility(), 3);
+        assert_eq!(sut.tag(), "0123456789ab");
+        assert!(!sut.hostname().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_address() -> Result<()> {
+        assert_eq!(
+            SyslogLogger::parse_address("/run/log")?,
+            Transport::UnixDatagram("/run/log".into())
+        );
+        assert_eq!(
+            SyslogLogger::parse_address("udp://localhost")?,
+            Transport::Udp("localhost:514".into())
+        );
+        assert_eq!(
+            SyslogLogger::parse_address("tcp://[::1]")?,
+            Transport::Tcp("[::1]:514".into())
+        );
+        assert_eq!(
+            SyslogLogger::parse_address("tcp://[::1]:601")?,
+            Transport::Tcp("[::1]:601".into())
+        );
+        assert!(SyslogLogger::parse_address("http://localhost").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_facility() {
+        assert!(SyslogLogger::new(ID, "", "wrong", "", "").is_err());
+    }
+
+    #[test]
+    fn sanitize() {
+        assert_eq!(SyslogLogger::sanitize("a b\tc", 48), "abc");
+        assert_eq!(SyslogLogger::sanitize("abc", 2), "ab");
+        assert_eq!(SyslogLogger::sanitize(" ", 48), "-");
+    }
+}
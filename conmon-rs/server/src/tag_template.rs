@@ -0,0 +1,130 @@
+//! Log tag templates referencing container metadata.
+
+use crate::container_labels::ContainerLabels;
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+
+/// Renders templates like `{{.PodName}}/{{.ContainerName}}`, where the fields are provided by
+/// the container metadata. The fields `ID` (short container ID) and `FullID` are always
+/// available, while labels and annotations are referenced like `{{.Labels.app}}`.
+pub struct TagTemplate;
+
+impl TagTemplate {
+    /// The length of the short container ID.
+    const SHORT_ID_LEN: usize = 12;
+
+    /// Render the template for the provided container ID, metadata and labels. Unknown fields
+    /// and unterminated actions result in an error.
+    pub fn render(
+        template: &str,
+        id: &str,
+        metadata: &HashMap<String, String>,
+        labels: &ContainerLabels,
+    ) -> Result<String> {
+        let mut rendered = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            rendered.push_str(&rest[..start]);
+            let action = &rest[start + 2..];
+            let end = action
+                .find("}}")
+                .with_context(|| format!("unterminated action in tag template '{}'", template))?;
+
+            let field = action[..end].trim();
+            let name = match field.strip_prefix('.') {
+                Some(name) if !name.is_empty() => name,
+                _ => bail!("invalid field '{}' in tag template '{}'", field, template),
+            };
+            match name {
+                "ID" => rendered.push_str(id.get(..Self::SHORT_ID_LEN).unwrap_or(id)),
+                "FullID" => rendered.push_str(id),
+                name => rendered.push_str(
+                    metadata
+                        .get(name)
+                        .map(String::as_str)
+                        .or_else(|| labels.field(name))
+                        .with_context(|| format!("unknown field '{}' in tag template", name))?,
+                ),
+            }
+            rest = &action[end + 2..];
+        }
+        rendered.push_str(rest);
+        Ok(rendered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "0123456789abcdef";
+
+    fn metadata() -> HashMap<String, String> {
+        vec![
+            ("PodName".to_string(), "pod".to_string()),
+            ("ContainerName".to_string(), "ctr".to_string()),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    fn labels() -> ContainerLabels {
+        ContainerLabels::new(
+            vec![("app".to_string(), "web".to_string())]
+                .into_iter()
+                .collect(),
+            vec![("example.com/owner".to_string(), "team".to_string())]
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn render() -> Result<()> {
+        assert_eq!(
+            TagTemplate::render(
+                "{{.PodName}}/{{ .ContainerName }}",
+                ID,
+                &metadata(),
+                &labels()
+            )?,
+            "pod/ctr"
+        );
+        assert_eq!(
+            TagTemplate::render("k8s-{{.ID}}-{{.FullID}}", ID, &metadata(), &labels())?,
+            "k8s-0123456789ab-0123456789abcdef"
+        );
+        assert_eq!(
+            TagTemplate::render(
+                "{{.Labels.app}}-{{.Annotations.example.com/owner}}",
+                ID,
+                &metadata(),
+                &labels()
+            )?,
+            "web-team"
+        );
+        assert_eq!(
+            TagTemplate::render("plain", ID, &metadata(), &labels())?,
+            "plain"
+        );
+        assert_eq!(TagTemplate::render("", ID, &metadata(), &labels())?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn render_failure() {
+        for template in &[
+            "{{.Unknown}}",
+            "{{.Labels.tier}}",
+            "{{.PodName",
+            "{{PodName}}",
+            "{{.}}",
+        ] {
+            assert!(
+                TagTemplate::render(template, ID, &metadata(), &labels()).is_err(),
+                "{}",
+                template
+            );
+        }
+    }
+}
@@ -2,27 +2,41 @@
 
 use crate::{
     attach::SharedContainerAttach,
-    container_io::{ContainerIO, Message, Pipe},
+    container_io::{ContainerIO, IoMetrics, Message, Pipe},
     container_log::SharedContainerLog,
+    flush_policy::FlushPolicy,
     listener::{DefaultListener, Listener},
+    terminal_mode::{TerminalMode, TerminalModeChange},
 };
 use anyhow::{bail, format_err, Context, Result};
 use getset::{Getters, MutGetters, Setters};
 use libc::{self, winsize, TIOCSWINSZ};
-use nix::sys::termios::{self, OutputFlags, SetArg};
-use sendfd::RecvWithFd;
+use nix::{
+    sys::{
+        signal::{self, Signal},
+        termios::{self, OutputFlags, SetArg, SpecialCharacterIndices},
+    },
+    unistd,
+};
+use sendfd::{RecvWithFd, SendWithFd};
 use std::{
     convert::TryFrom,
     io::{Error as IOError, ErrorKind},
-    os::unix::{fs::PermissionsExt, io::RawFd},
-    path::PathBuf,
-    sync::mpsc::Sender as StdSender,
+    os::unix::{
+        fs::PermissionsExt,
+        io::{FromRawFd, RawFd},
+    },
+    path::{Path, PathBuf},
+    sync::{mpsc::Sender as StdSender, Arc},
 };
 use tokio::{
     fs,
     io::{AsyncWriteExt, Interest},
     net::UnixStream,
-    sync::mpsc::{self, Receiver, Sender, UnboundedReceiver},
+    sync::{
+        mpsc::{self, Receiver, Sender, UnboundedReceiver},
+        watch,
+    },
     task,
 };
 use tokio_fd::AsyncFd;
@@ -44,6 +58,27 @@ pub struct Terminal {
 
     logger: SharedContainerLog,
     attach: SharedContainerAttach,
+
+    /// The IO counters of the container.
+    metrics: Arc<IoMetrics>,
+
+    /// Whether consuming the output is paused.
+    paused: watch::Receiver<bool>,
+
+    /// The size of the buffer for reading the terminal output.
+    buf_size: usize,
+
+    #[getset(set = "pub")]
+    /// The window size applied once the terminal got connected, as width and height.
+    initial_window_size: Option<(u16, u16)>,
+
+    #[getset(set = "pub")]
+    /// Whether the input of the attach clients gets forwarded into the terminal.
+    stdin: bool,
+
+    #[getset(set = "pub")]
+    /// The policy of forwarding the read output.
+    flush_policy: FlushPolicy,
 }
 
 #[derive(Debug, Getters)]
@@ -59,8 +94,14 @@ struct Config {
 }
 
 impl Terminal {
-    /// Setup a new terminal instance.
-    pub fn new(logger: SharedContainerLog, attach: SharedContainerAttach) -> Result<Self> {
+    /// Setup a new terminal instance, which reads the output in chunks of `buf_size`.
+    pub fn new(
+        logger: SharedContainerLog,
+        attach: SharedContainerAttach,
+        metrics: Arc<IoMetrics>,
+        paused: watch::Receiver<bool>,
+        buf_size: usize,
+    ) -> Result<Self> {
         debug!("Creating new terminal");
         let path = ContainerIO::temp_file_name(None, "conmon-term-", ".sock")?;
         let path_clone = path.clone();
@@ -91,6 +132,12 @@ impl Terminal {
             tty: None,
             logger,
             attach,
+            metrics,
+            paused,
+            buf_size,
+            initial_window_size: None,
+            stdin: true,
+            flush_policy: FlushPolicy::default(),
         })
     }
 
@@ -108,23 +155,35 @@ impl Terminal {
         let mut term = termios::tcgetattr(fd)?;
         term.output_flags |= OutputFlags::ONLCR;
         termios::tcsetattr(fd, SetArg::TCSANOW, &term)?;
+        if let Some((width, height)) = self.initial_window_size {
+            self.resize(width, height)
+                .context("apply initial window size")?;
+        }
 
         let stdio = AsyncFd::try_from(fd)?;
 
         let attach_clone = self.attach.clone();
         let logger_clone = self.logger.clone();
+        let metrics_clone = self.metrics.clone();
+        let paused_clone = self.paused.clone();
         let (message_tx, message_rx) = mpsc::unbounded_channel();
         self.message_rx = Some(message_rx);
         let token_clone = token.clone();
+        let buf_size = self.buf_size;
+        let flush_policy = self.flush_policy;
 
         task::spawn(
             async move {
                 if let Err(e) = ContainerIO::read_loop(
                     stdio,
+                    buf_size,
                     Pipe::StdOut,
                     logger_clone,
                     message_tx,
                     attach_clone,
+                    metrics_clone,
+                    paused_clone,
+                    flush_policy,
                     token_clone,
                 )
                 .await
@@ -136,11 +195,37 @@ impl Terminal {
             .instrument(debug_span!("read_loop")),
         );
 
-        let attach_clone = self.attach.clone();
+        if !self.stdin {
+            debug!("Not forwarding input because the container has no stdin");
+            return Ok(());
+        }
+
+        let mut attach_clone = self.attach.clone();
+        let metrics_clone = self.metrics.clone();
         task::spawn(
             async move {
-                if let Err(e) = ContainerIO::read_loop_stdin(fd, attach_clone, token).await {
-                    error!("Stdin read loop failure: {:#}", e);
+                let mut writer = unsafe { fs::File::from_raw_fd(fd) };
+                loop {
+                    if let Err(e) = ContainerIO::read_loop_stdin(
+                        &mut writer,
+                        &mut attach_clone,
+                        &metrics_clone,
+                        token.clone(),
+                    )
+                    .await
+                    {
+                        error!("Stdin read loop failure: {:#}", e);
+                        break;
+                    }
+                    if token.is_cancelled() {
+                        break;
+                    }
+                    // Closing the terminal would hang up the container, so we send the end of
+                    // file character instead and keep the terminal usable for new clients.
+                    if let Err(e) = Self::write_eof(fd, &mut writer).await {
+                        error!("Unable to write EOF to terminal: {:#}", e);
+                        break;
+                    }
                 }
             }
             .instrument(debug_span!("read_loop_stdin")),
@@ -149,7 +234,15 @@ impl Terminal {
         Ok(())
     }
 
-    /// Resize the terminal width and height.
+    /// Write the end of file control character of the terminal.
+    async fn write_eof(fd: RawFd, writer: &mut fs::File) -> Result<()> {
+        let term = termios::tcgetattr(fd).context("get terminal attributes")?;
+        let eof = term.control_chars[SpecialCharacterIndices::VEOF as usize];
+        debug!("Writing EOF character to terminal");
+        writer.write_all(&[eof]).await.context("write EOF")
+    }
+
+    /// Resize the terminal width and height and notify its foreground process group.
     pub fn resize(&self, width: u16, height: u16) -> Result<()> {
         debug!("Resizing terminal to width {} and height {}", width, height);
         let ws = winsize {
@@ -158,15 +251,52 @@ impl Terminal {
             ws_xpixel: 0,
             ws_ypixel: 0,
         };
-        match unsafe {
-            libc::ioctl(
-                self.tty().context("terminal not connected")?,
-                TIOCSWINSZ,
-                &ws,
-            )
-        } {
-            0 => Ok(()),
-            _ => Err(IOError::last_os_error().into()),
+        let fd = self.tty().context("terminal not connected")?;
+        if unsafe { libc::ioctl(fd, TIOCSWINSZ, &ws) } != 0 {
+            return Err(IOError::last_os_error().into());
+        }
+        Self::signal_window_change(fd);
+        Ok(())
+    }
+
+    /// Send SIGWINCH to the foreground process group of the terminal. The kernel signals only
+    /// actual size changes, which is why full-screen applications may not redraw otherwise.
+    fn signal_window_change(fd: RawFd) {
+        match unistd::tcgetpgrp(fd) {
+            // A zero process group means that no process has the terminal as controlling one.
+            Ok(pgrp) if pgrp.as_raw() > 0 => {
+                if let Err(e) = signal::killpg(pgrp, Signal::SIGWINCH) {
+                    debug!("Unable to signal window change to {}: {}", pgrp, e);
+                }
+            }
+            Ok(_) => debug!("No foreground process group to signal window change"),
+            Err(e) => debug!("Unable to get foreground process group: {}", e),
+        }
+    }
+
+    /// Change the mode of the terminal and return the resulting one.
+    pub fn set_mode(&self, change: &TerminalModeChange) -> Result<TerminalMode> {
+        change.apply(self.tty().context("terminal not connected")?)
+    }
+
+    /// Send the terminal master to the unix socket at the provided path via `SCM_RIGHTS`, which
+    /// duplicates it into the receiving process. The message consists of a single zero byte.
+    pub async fn send_master(&self, path: &Path) -> Result<()> {
+        let fd = self.tty().context("terminal not connected")?;
+        debug!("Sending terminal master to {}", path.display());
+        let stream = UnixStream::connect(path)
+            .await
+            .context(format!("connect socket '{}'", path.display()))?;
+        loop {
+            stream
+                .writable()
+                .await
+                .context("wait for writable socket")?;
+            match stream.send_with_fd(&[0], &[fd]) {
+                Ok(_) => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e).context("send terminal master"),
+            }
         }
     }
 
@@ -255,10 +385,31 @@ impl Drop for Terminal {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{attach::SharedContainerAttach, container_log::ContainerLog};
-    use nix::pty;
-    use sendfd::SendWithFd;
-    use std::os::unix::io::FromRawFd;
+    use crate::{
+        attach::SharedContainerAttach, container_io::BufferSizes, container_log::ContainerLog,
+    };
+    use nix::{pty, sys::stat};
+    use std::{
+        os::unix::process::CommandExt,
+        process::{Command, Stdio},
+        time::Duration,
+    };
+    use tempfile::tempdir;
+    use tokio::{net::UnixListener, time};
+
+    async fn wait_output(
+        message_rx: &mut UnboundedReceiver<Message>,
+        output: &mut String,
+        expected: &str,
+    ) -> Result<()> {
+        while !output.contains(expected) {
+            match time::timeout(Duration::from_secs(5), message_rx.recv()).await? {
+                Some(Message::Data(data)) => output.push_str(std::str::from_utf8(&data)?),
+                message => bail!("unexpected message {:?}", message),
+            }
+        }
+        Ok(())
+    }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn new_success() -> Result<()> {
@@ -266,7 +417,13 @@ mod tests {
         let attach = SharedContainerAttach::default();
         let token = CancellationToken::new();
 
-        let mut sut = Terminal::new(logger, attach)?;
+        let mut sut = Terminal::new(
+            logger,
+            attach,
+            Arc::default(),
+            watch::channel(false).1,
+            BufferSizes::default().output(),
+        )?;
         assert!(sut.path().exists());
 
         let res = pty::openpty(None, None)?;
@@ -292,4 +449,134 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn initial_window_size() -> Result<()> {
+        let logger = ContainerLog::new();
+        let attach = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+
+        let mut sut = Terminal::new(
+            logger,
+            attach,
+            Arc::default(),
+            watch::channel(false).1,
+            BufferSizes::default().output(),
+        )?;
+        sut.set_initial_window_size(Some((120, 40)));
+
+        let res = pty::openpty(None, None)?;
+        let stream = UnixStream::connect(sut.path()).await?;
+        stream.writable().await?;
+        stream.send_with_fd(b"test", &[res.master])?;
+        sut.wait_connected(token.clone()).await?;
+
+        let mut ws = winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        assert_eq!(
+            unsafe { libc::ioctl(res.slave, libc::TIOCGWINSZ, &mut ws) },
+            0
+        );
+        assert_eq!((ws.ws_col, ws.ws_row), (120, 40));
+
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resize_signals_foreground() -> Result<()> {
+        let logger = ContainerLog::new();
+        let attach = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+
+        let mut sut = Terminal::new(
+            logger,
+            attach,
+            Arc::default(),
+            watch::channel(false).1,
+            BufferSizes::default().output(),
+        )?;
+        let res = pty::openpty(None, None)?;
+        let stream = UnixStream::connect(sut.path()).await?;
+        stream.writable().await?;
+        stream.send_with_fd(b"test", &[res.master])?;
+        sut.wait_connected(token.clone()).await?;
+
+        // The shell becomes the foreground process group of its controlling terminal
+        let mut cmd = Command::new("sh");
+        cmd.args([
+            "-c",
+            "trap 'echo winch' WINCH; echo ready; while :; do sleep 0.1; done",
+        ])
+        .stdin(unsafe { Stdio::from_raw_fd(res.slave) })
+        .stdout(unsafe { Stdio::from_raw_fd(unistd::dup(res.slave)?) })
+        .stderr(Stdio::null());
+        unsafe {
+            cmd.pre_exec(|| {
+                unistd::setsid()?;
+                if libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY, 0) != 0 {
+                    return Err(IOError::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let mut child = cmd.spawn()?;
+
+        let mut message_rx = sut.message_rx_mut().take().context("no message receiver")?;
+        let mut output = String::new();
+        wait_output(&mut message_rx, &mut output, "ready").await?;
+
+        // The size is unchanged, which means that the kernel does not signal the change itself
+        sut.resize(0, 0)?;
+        wait_output(&mut message_rx, &mut output, "winch").await?;
+
+        child.kill()?;
+        child.wait()?;
+        token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn send_master() -> Result<()> {
+        let logger = ContainerLog::new();
+        let attach = SharedContainerAttach::default();
+        let token = CancellationToken::new();
+        let dir = tempdir()?;
+        let path = dir.path().join("master.sock");
+        let listener = UnixListener::bind(&path)?;
+
+        let mut sut = Terminal::new(
+            logger,
+            attach,
+            Arc::default(),
+            watch::channel(false).1,
+            BufferSizes::default().output(),
+        )?;
+        assert!(sut.send_master(&path).await.is_err());
+
+        let res = pty::openpty(None, None)?;
+        let stream = UnixStream::connect(sut.path()).await?;
+        stream.writable().await?;
+        stream.send_with_fd(b"test", &[res.master])?;
+        sut.wait_connected(token.clone()).await?;
+
+        sut.send_master(&path).await?;
+        let (stream, _) = listener.accept().await?;
+        stream.readable().await?;
+        let (mut buf, mut fds) = ([0; 8], [0; 2]);
+        assert_eq!(stream.recv_with_fd(&mut buf, &mut fds)?, (1, 1));
+        assert_ne!(fds[0], res.master);
+        assert_eq!(
+            stat::fstat(fds[0])?.st_rdev,
+            stat::fstat(res.master)?.st_rdev
+        );
+        unistd::close(fds[0])?;
+
+        token.cancel();
+        Ok(())
+    }
 }
@@ -0,0 +1,148 @@
+//! Control of the terminal modes like echo and canonical input, which allows callers to
+//! implement password prompts and other non-echoing interactive flows.
+
+use anyhow::{Context, Result};
+use conmon_common::conmon_capnp::conmon::{set_terminal_mode_request, TerminalFlag};
+use getset::{CopyGetters, Setters};
+use nix::sys::termios::{self, InputFlags, LocalFlags, OutputFlags, SetArg, Termios};
+use std::os::unix::io::RawFd;
+use tracing::debug;
+
+#[derive(Clone, Copy, CopyGetters, Debug, Default, Eq, PartialEq, Setters)]
+#[getset(get_copy = "pub", set = "pub")]
+/// The changes of a terminal mode, where unset flags keep their current value.
+pub struct TerminalModeChange {
+    /// Whether the terminal is set into raw mode before the flags get applied.
+    raw: bool,
+
+    /// Whether input characters are echoed.
+    echo: Option<bool>,
+
+    /// Whether input is made available line by line.
+    canonical: Option<bool>,
+
+    /// Whether the interrupt, quit and suspend characters generate signals.
+    signals: Option<bool>,
+
+    /// Whether carriage returns of the input are translated into newlines.
+    cr_to_nl: Option<bool>,
+
+    /// Whether newlines of the output are translated into carriage return and newline.
+    nl_to_cr_nl: Option<bool>,
+}
+
+impl TerminalModeChange {
+    /// Create the terminal mode change from the provided capnp request.
+    pub fn from(req: set_terminal_mode_request::Reader) -> Result<Self> {
+        Ok(Self {
+            raw: req.get_raw(),
+            echo: Self::flag(req.get_echo()?),
+            canonical: Self::flag(req.get_canonical()?),
+            signals: Self::flag(req.get_signals()?),
+            cr_to_nl: Self::flag(req.get_cr_to_nl()?),
+            nl_to_cr_nl: Self::flag(req.get_nl_to_cr_nl()?),
+        })
+    }
+
+    fn flag(flag: TerminalFlag) -> Option<bool> {
+        match flag {
+            TerminalFlag::Unchanged => None,
+            TerminalFlag::Enable => Some(true),
+            TerminalFlag::Disable => Some(false),
+        }
+    }
+
+    /// Apply the changes to the terminal of the file descriptor and return its resulting mode.
+    pub fn apply(&self, fd: RawFd) -> Result<TerminalMode> {
+        debug!("Changing terminal mode: {:?}", self);
+        let mut term = termios::tcgetattr(fd).context("get terminal attributes")?;
+        if self.raw {
+            termios::cfmakeraw(&mut term);
+        }
+        if let Some(x) = self.echo {
+            term.local_flags.set(LocalFlags::ECHO, x);
+        }
+        if let Some(x) = self.canonical {
+            term.local_flags.set(LocalFlags::ICANON, x);
+        }
+        if let Some(x) = self.signals {
+            term.local_flags.set(LocalFlags::ISIG, x);
+        }
+        if let Some(x) = self.cr_to_nl {
+            term.input_flags.set(InputFlags::ICRNL, x);
+        }
+        if let Some(x) = self.nl_to_cr_nl {
+            term.output_flags.set(OutputFlags::ONLCR, x);
+        }
+        termios::tcsetattr(fd, SetArg::TCSANOW, &term).context("set terminal attributes")?;
+        Ok(TerminalMode::from(&term))
+    }
+}
+
+#[derive(Clone, Copy, CopyGetters, Debug, Eq, PartialEq)]
+#[getset(get_copy = "pub")]
+/// The mode of a terminal.
+pub struct TerminalMode {
+    /// Whether input characters are echoed.
+    echo: bool,
+
+    /// Whether input is made available line by line.
+    canonical: bool,
+
+    /// Whether the interrupt, quit and suspend characters generate signals.
+    signals: bool,
+
+    /// Whether carriage returns of the input are translated into newlines.
+    cr_to_nl: bool,
+
+    /// Whether newlines of the output are translated into carriage return and newline.
+    nl_to_cr_nl: bool,
+}
+
+impl From<&Termios> for TerminalMode {
+    fn from(term: &Termios) -> Self {
+        Self {
+            echo: term.local_flags.contains(LocalFlags::ECHO),
+            canonical: term.local_flags.contains(LocalFlags::ICANON),
+            signals: term.local_flags.contains(LocalFlags::ISIG),
+            cr_to_nl: term.input_flags.contains(InputFlags::ICRNL),
+            nl_to_cr_nl: term.output_flags.contains(OutputFlags::ONLCR),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::{pty, unistd};
+
+    #[test]
+    fn apply() -> Result<()> {
+        let res = pty::openpty(None, None)?;
+
+        let mut sut = TerminalModeChange::default();
+        sut.set_echo(Some(false));
+        let mode = sut.apply(res.master)?;
+        assert!(!mode.echo());
+        assert!(mode.canonical());
+        assert!(!TerminalMode::from(&termios::tcgetattr(res.slave)?).echo());
+
+        // Raw mode clears the input processing flags, explicit flags are applied afterwards
+        let mut sut = TerminalModeChange::default();
+        sut.set_raw(true);
+        sut.set_signals(Some(true));
+        let mode = sut.apply(res.master)?;
+        assert!(!mode.canonical());
+        assert!(!mode.cr_to_nl());
+        assert!(mode.signals());
+
+        // Unchanged flags are kept
+        let mode = TerminalModeChange::default().apply(res.master)?;
+        assert!(!mode.canonical());
+        assert!(mode.signals());
+
+        unistd::close(res.master)?;
+        unistd::close(res.slave)?;
+        Ok(())
+    }
+}
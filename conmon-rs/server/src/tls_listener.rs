@@ -0,0 +1,210 @@
+//! The optional TCP listener of the RPC server, which requires TLS with client certificates.
+
+use anyhow::{bail, Context, Result};
+use getset::Getters;
+use std::{fs::File, io::BufReader, net::SocketAddr, path::Path, sync::Arc};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{
+    rustls::{
+        server::AllowAnyAuthenticatedClient, Certificate, PrivateKey, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+#[derive(Getters)]
+/// A TCP listener along with the TLS acceptor for its connections.
+pub struct TlsListener {
+    listener: TcpListener,
+
+    #[getset(get = "pub")]
+    /// The acceptor doing the TLS handshake, which verifies the client certificate.
+    acceptor: TlsAcceptor,
+}
+
+impl TlsListener {
+    /// Bind the listener to the address, serving the certificate chain of `cert_path` and only
+    /// accepting clients with a certificate issued by the CA of `client_ca_path`.
+    pub async fn bind(
+        address: SocketAddr,
+        cert_path: &Path,
+        key_path: &Path,
+        client_ca_path: &Path,
+    ) -> Result<Self> {
+        let config = Self::server_config(cert_path, key_path, client_ca_path)?;
+        let listener = TcpListener::bind(address)
+            .await
+            .context(format!("bind TLS listener to {}", address))?;
+        Ok(Self {
+            listener,
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+        })
+    }
+
+    /// Accept the next TCP connection, which still requires the TLS handshake of the acceptor.
+    pub async fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
+        self.listener
+            .accept()
+            .await
+            .context("accept TLS connection")
+    }
+
+    fn server_config(
+        cert_path: &Path,
+        key_path: &Path,
+        client_ca_path: &Path,
+    ) -> Result<ServerConfig> {
+        let mut roots = RootCertStore::empty();
+        for cert in Self::certs(client_ca_path)? {
+            roots.add(&cert).context("add TLS client CA")?;
+        }
+
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+            .with_single_cert(Self::certs(cert_path)?, Self::private_key(key_path)?)
+            .context("build TLS server config")
+    }
+
+    fn certs(path: &Path) -> Result<Vec<Certificate>> {
+        let mut reader = BufReader::new(
+            File::open(path).context(format!("open certificates '{}'", path.display()))?,
+        );
+        let certs = rustls_pemfile::certs(&mut reader)
+            .context(format!("parse certificates '{}'", path.display()))?;
+        if certs.is_empty() {
+            bail!("no certificates found in '{}'", path.display())
+        }
+        Ok(certs.into_iter().map(Certificate).collect())
+    }
+
+    fn private_key(path: &Path) -> Result<PrivateKey> {
+        let mut reader = BufReader::new(
+            File::open(path).context(format!("open private key '{}'", path.display()))?,
+        );
+        while let Some(item) = rustls_pemfile::read_one(&mut reader)
+            .context(format!("parse private key '{}'", path.display()))?
+        {
+            match item {
+                rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+                _ => {}
+            }
+        }
+        bail!("no private key found in '{}'", path.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, Certificate as CertGen, CertificateParams, IsCa};
+    use std::{convert::TryFrom, fs, path::PathBuf};
+    use tempfile::{tempdir, TempDir};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{
+        rustls::{ClientConfig, ServerName},
+        TlsConnector,
+    };
+
+    struct Pki {
+        dir: TempDir,
+        ca: CertGen,
+    }
+
+    impl Pki {
+        fn new() -> Result<Self> {
+            let mut params = CertificateParams::new(vec![]);
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            Ok(Self {
+                dir: tempdir()?,
+                ca: CertGen::from_params(params)?,
+            })
+        }
+
+        /// Issue a certificate for localhost, returning the paths of the certificate and its key.
+        fn issue(&self, name: &str) -> Result<(PathBuf, PathBuf)> {
+            let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+            let cert_path = self.dir.path().join(format!("{}.pem", name));
+            let key_path = self.dir.path().join(format!("{}.key", name));
+            fs::write(&cert_path, cert.serialize_pem_with_signer(&self.ca)?)?;
+            fs::write(&key_path, cert.serialize_private_key_pem())?;
+            Ok((cert_path, key_path))
+        }
+
+        fn ca_path(&self) -> Result<PathBuf> {
+            let path = self.dir.path().join("ca.pem");
+            fs::write(&path, self.ca.serialize_pem()?)?;
+            Ok(path)
+        }
+
+        fn client_config(&self, client: Option<(&Path, &Path)>) -> Result<ClientConfig> {
+            let mut roots = RootCertStore::empty();
+            roots.add(&Certificate(self.ca.serialize_der()?))?;
+            let builder = ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots);
+            Ok(match client {
+                Some((cert, key)) => builder.with_client_auth_cert(
+                    TlsListener::certs(cert)?,
+                    TlsListener::private_key(key)?,
+                )?,
+                None => builder.with_no_client_auth(),
+            })
+        }
+    }
+
+    async fn connect(pki: &Pki, client: Option<(&Path, &Path)>) -> Result<()> {
+        let (cert, key) = pki.issue("server")?;
+        let sut = TlsListener::bind("127.0.0.1:0".parse()?, &cert, &key, &pki.ca_path()?).await?;
+        let address = sut.listener.local_addr()?;
+        let connector = TlsConnector::from(Arc::new(pki.client_config(client)?));
+
+        let (server, client) = tokio::join!(
+            async {
+                let (stream, _) = sut.accept().await?;
+                let mut stream = sut.acceptor().accept(stream).await?;
+                stream.write_all(b"ok").await?;
+                stream.shutdown().await?;
+                Ok::<_, anyhow::Error>(())
+            },
+            async {
+                let stream = TcpStream::connect(address).await?;
+                let mut stream = connector
+                    .connect(ServerName::try_from("localhost")?, stream)
+                    .await?;
+                let mut res = String::new();
+                stream.read_to_string(&mut res).await?;
+                assert_eq!(res, "ok");
+                Ok::<_, anyhow::Error>(())
+            }
+        );
+        server.and(client)
+    }
+
+    #[tokio::test]
+    async fn accept_client_certificate() -> Result<()> {
+        let pki = Pki::new()?;
+        let (cert, key) = pki.issue("client")?;
+        connect(&pki, Some((&cert, &key))).await
+    }
+
+    #[tokio::test]
+    async fn reject_missing_client_certificate() -> Result<()> {
+        let pki = Pki::new()?;
+        assert!(connect(&pki, None).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_files() -> Result<()> {
+        let pki = Pki::new()?;
+        let (cert, key) = pki.issue("server")?;
+        let ca = pki.ca_path()?;
+        assert!(TlsListener::server_config(&cert, &key, &ca).is_ok());
+        assert!(TlsListener::server_config(&key, &key, &ca).is_err());
+        assert!(TlsListener::server_config(&cert, &cert, &ca).is_err());
+        assert!(TlsListener::server_config(&cert, &key, Path::new("/none")).is_err());
+        Ok(())
+    }
+}
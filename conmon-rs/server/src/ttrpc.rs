@@ -0,0 +1,457 @@
+//! The ttrpc API of the server, which is enabled by the `ttrpc` feature.
+//!
+//! The ttrpc service serves the same `proto/conmon.proto` as the gRPC one for containerd shim
+//! style deployments, and forwards every call through the `CapnpBridge` to the Cap'n Proto
+//! service.
+
+use crate::{
+    auth::Access, capacity::CapacityExceeded, capnp_bridge::CapnpBridge,
+    container_labels::ContainerLabels, rpc::DEADLINE_EXCEEDED,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use capnp::{text_list, ErrorKind};
+use conmon_common::conmon_capnp::conmon;
+use futures::future;
+use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+use std::{convert::TryFrom, future::Future, os::unix::io::IntoRawFd, sync::Arc};
+use tokio::net::UnixListener;
+use tracing::{debug, warn};
+use ttrpc::{
+    asynchronous::{Server, TtrpcContext},
+    get_status, Code,
+};
+
+#[allow(clippy::all)]
+mod proto {
+    pub mod conmon {
+        include!(concat!(env!("OUT_DIR"), "/ttrpc/conmon.rs"));
+    }
+
+    pub mod conmon_ttrpc {
+        include!(concat!(env!("OUT_DIR"), "/ttrpc/conmon_ttrpc.rs"));
+    }
+}
+
+use proto::{
+    conmon::{
+        AttachRequest, AttachResponse, ContainerStatsRequest, ContainerStatsResponse,
+        CreateContainerRequest, CreateContainerResponse, ExecSyncContainerRequest,
+        ExecSyncContainerResponse, ExtraFdStats, IoStatsRequest, IoStatsResponse, IoStreamStats,
+        LogDriver_Type, ReopenLogRequest, ReopenLogResponse, SetOutputPausedRequest,
+        SetOutputPausedResponse, SetWindowSizeRequest, SetWindowSizeResponse, VersionRequest,
+        VersionResponse,
+    },
+    conmon_ttrpc::{create_conmon, Conmon},
+};
+
+#[derive(Clone, Debug)]
+/// The ttrpc service, which forwards all calls to the Cap'n Proto service.
+pub struct TtrpcService {
+    bridge: CapnpBridge,
+
+    /// The token and peer checks, where the token is sent by the `authorization` metadata.
+    access: Access,
+}
+
+impl TtrpcService {
+    /// Create a new service forwarding its calls through the bridge.
+    pub fn new(bridge: CapnpBridge, access: Access) -> Self {
+        Self { bridge, access }
+    }
+
+    /// Serve the ttrpc API on the unix domain socket listener.
+    pub async fn serve(self, listener: UnixListener) -> Result<()> {
+        debug!("Serving ttrpc API");
+        let fd = listener
+            .into_std()
+            .context("convert ttrpc listener")?
+            .into_raw_fd();
+        let service: Box<dyn Conmon + Send + Sync> = Box::new(self);
+        let mut server = Server::new()
+            .add_listener(fd)
+            .context("add ttrpc listener")?
+            .set_domain_unix()
+            .register_service(create_conmon(Arc::new(service)));
+        server.start().await.context("start ttrpc server")?;
+
+        // The server stops accepting connections once dropped.
+        future::pending().await
+    }
+
+    /// Verify that the peer of the connection is allowed and that the call carries a valid token.
+    fn authorize(&self, ctx: &TtrpcContext) -> ttrpc::Result<()> {
+        let status =
+            |code, message: String| Err(ttrpc::Error::RpcStatus(get_status(code, message)));
+        let cred = match getsockopt(ctx.fd, PeerCredentials) {
+            Ok(cred) => cred,
+            Err(e) => return status(Code::INTERNAL, format!("get peer credentials: {}", e)),
+        };
+        if !self.access.peer_allowed(cred.uid(), cred.gid()) {
+            warn!(
+                "Rejecting ttrpc call of peer with UID {} and GID {}",
+                cred.uid(),
+                cred.gid()
+            );
+            return status(Code::PERMISSION_DENIED, "peer not allowed".into());
+        }
+
+        let authorization = ctx
+            .metadata
+            .get("authorization")
+            .and_then(|x| x.first())
+            .map(String::as_str);
+        match self.access.authorize(authorization) {
+            Ok(Some(name)) => debug!("Authenticated ttrpc caller {}", name),
+            Ok(None) => {}
+            Err(e) => return status(Code::UNAUTHENTICATED, e.to_string()),
+        }
+        Ok(())
+    }
+
+    /// Authorize the call, forward it to the Cap'n Proto service and wait for its response.
+    async fn call<F, R, T>(&self, ctx: &TtrpcContext, f: F) -> ttrpc::Result<T>
+    where
+        F: FnOnce(conmon::Client) -> R + Send + 'static,
+        R: Future<Output = Result<T, capnp::Error>> + 'static,
+        T: Send + 'static,
+    {
+        self.authorize(ctx)?;
+        self.bridge.call(f).await.map_err(|e| {
+            let code = match e.kind {
+                _ if CapacityExceeded::matches(&e.description) => Code::RESOURCE_EXHAUSTED,
+                ErrorKind::Overloaded if e.description.ends_with(DEADLINE_EXCEEDED) => {
+                    Code::DEADLINE_EXCEEDED
+                }
+                ErrorKind::Overloaded => Code::RESOURCE_EXHAUSTED,
+                ErrorKind::Disconnected => Code::UNAVAILABLE,
+                _ => Code::INTERNAL,
+            };
+            ttrpc::Error::RpcStatus(get_status(code, e.description))
+        })
+    }
+}
+
+/// Convert the type of a ttrpc log driver into its Cap'n Proto counterpart.
+fn log_driver_type(typ: LogDriver_Type) -> conmon::log_driver::Type {
+    use conmon::log_driver::Type;
+    match typ {
+        LogDriver_Type::CONTAINER_RUNTIME_INTERFACE => Type::ContainerRuntimeInterface,
+        LogDriver_Type::JOURNALD => Type::Journald,
+        LogDriver_Type::JSON_FILE => Type::JsonFile,
+        LogDriver_Type::NONE => Type::None,
+    }
+}
+
+/// Convert a terminal dimension, which is limited to 16 bits.
+fn window_size(value: u32) -> ttrpc::Result<u16> {
+    u16::try_from(value).map_err(|_| {
+        ttrpc::Error::RpcStatus(get_status(
+            Code::INVALID_ARGUMENT,
+            format!("window size {} out of range", value),
+        ))
+    })
+}
+
+fn set_texts(mut builder: text_list::Builder<'_>, values: &[String]) {
+    for (i, value) in values.iter().enumerate() {
+        builder.set(i as u32, value);
+    }
+}
+
+fn stream_stats(reader: conmon::io_stream_stats::Reader<'_>) -> IoStreamStats {
+    let mut stats = IoStreamStats::new();
+    stats.bytes = reader.get_bytes();
+    stats.lines = reader.get_lines();
+    stats.partial_lines = reader.get_partial_lines();
+    stats.dropped_bytes = reader.get_dropped_bytes();
+    stats
+}
+
+#[async_trait]
+impl Conmon for TtrpcService {
+    async fn version(
+        &self,
+        ctx: &TtrpcContext,
+        _: VersionRequest,
+    ) -> ttrpc::Result<VersionResponse> {
+        self.call(ctx, |client| async move {
+            let response = client.version_request().send().promise.await?;
+            let response = response.get()?.get_response()?;
+            let mut version = VersionResponse::new();
+            version.version = response.get_version()?.into();
+            version.tag = response.get_tag()?.into();
+            version.commit = response.get_commit()?.into();
+            version.build_date = response.get_build_date()?.into();
+            version.rust_version = response.get_rust_version()?.into();
+            version.process_id = response.get_process_id();
+            version.build_target = response.get_build_target()?.into();
+            version.build_profile = response.get_build_profile()?.into();
+            for feature in response.get_features()?.iter() {
+                version.features.push(feature?.into());
+            }
+            version.protocol_version = response.get_protocol_version();
+            version.min_protocol_version = response.get_min_protocol_version();
+            Ok(version)
+        })
+        .await
+    }
+
+    async fn create_container(
+        &self,
+        ctx: &TtrpcContext,
+        request: CreateContainerRequest,
+    ) -> ttrpc::Result<CreateContainerResponse> {
+        self.call(ctx, move |client| async move {
+            let mut call = client.create_container_request();
+            let mut req = call.get().init_request();
+            req.set_id(&request.id);
+            req.set_bundle_path(&request.bundle_path);
+            req.set_terminal(request.terminal);
+            req.set_stdin(request.stdin);
+            req.set_restart_policy(&request.restart_policy);
+            req.set_stop_signal(request.stop_signal);
+            set_texts(
+                req.reborrow()
+                    .init_exit_paths(request.exit_paths.len() as u32),
+                request.exit_paths.as_slice(),
+            );
+            set_texts(
+                req.reborrow()
+                    .init_oom_exit_paths(request.oom_exit_paths.len() as u32),
+                request.oom_exit_paths.as_slice(),
+            );
+            set_texts(
+                req.reborrow()
+                    .init_cleanup_cmd(request.cleanup_cmd.len() as u32),
+                request.cleanup_cmd.as_slice(),
+            );
+            set_texts(
+                req.reborrow()
+                    .init_global_args(request.global_args.len() as u32),
+                request.global_args.as_slice(),
+            );
+            set_texts(
+                req.reborrow()
+                    .init_command_args(request.command_args.len() as u32),
+                request.command_args.as_slice(),
+            );
+
+            let mut log_drivers = req
+                .reborrow()
+                .init_log_drivers(request.log_drivers.len() as u32);
+            for (i, driver) in request.log_drivers.iter().enumerate() {
+                let mut log_driver = log_drivers.reborrow().get(i as u32);
+                log_driver.set_type(log_driver_type(driver.field_type));
+                log_driver.set_path(&driver.path);
+                log_driver.set_max_size(driver.max_size);
+                log_driver.set_max_files(driver.max_files);
+            }
+
+            ContainerLabels::set_entries(
+                req.reborrow().init_metadata(request.metadata.len() as u32),
+                &request.metadata,
+            );
+            ContainerLabels::set_entries(
+                req.reborrow().init_labels(request.labels.len() as u32),
+                &request.labels,
+            );
+            ContainerLabels::set_entries(
+                req.init_annotations(request.annotations.len() as u32),
+                &request.annotations,
+            );
+
+            let response = call.send().promise.await?;
+            let mut create = CreateContainerResponse::new();
+            create.container_pid = response.get()?.get_response()?.get_container_pid();
+            Ok(create)
+        })
+        .await
+    }
+
+    async fn exec_sync_container(
+        &self,
+        ctx: &TtrpcContext,
+        request: ExecSyncContainerRequest,
+    ) -> ttrpc::Result<ExecSyncContainerResponse> {
+        self.call(ctx, move |client| async move {
+            let mut call = client.exec_sync_container_request();
+            let mut req = call.get().init_request();
+            req.set_id(&request.id);
+            req.set_timeout_sec(request.timeout_sec);
+            req.set_terminal(request.terminal);
+            req.set_exec_session_id(&request.exec_session_id);
+            set_texts(
+                req.init_command(request.command.len() as u32),
+                request.command.as_slice(),
+            );
+
+            let response = call.send().promise.await?;
+            let response = response.get()?.get_response()?;
+            let mut exec = ExecSyncContainerResponse::new();
+            exec.exit_code = response.get_exit_code();
+            exec.stdout = response.get_stdout()?.to_vec();
+            exec.stderr = response.get_stderr()?.to_vec();
+            exec.timed_out = response.get_timed_out();
+            exec.exec_session_id = response.get_exec_session_id()?.into();
+            Ok(exec)
+        })
+        .await
+    }
+
+    async fn set_window_size_container(
+        &self,
+        ctx: &TtrpcContext,
+        request: SetWindowSizeRequest,
+    ) -> ttrpc::Result<SetWindowSizeResponse> {
+        let (width, height) = (window_size(request.width)?, window_size(request.height)?);
+        self.call(ctx, move |client| async move {
+            let mut call = client.set_window_size_container_request();
+            let mut req = call.get().init_request();
+            req.set_id(&request.id);
+            req.set_width(width);
+            req.set_height(height);
+            call.send().promise.await?;
+            Ok(SetWindowSizeResponse::new())
+        })
+        .await
+    }
+
+    async fn set_output_paused_container(
+        &self,
+        ctx: &TtrpcContext,
+        request: SetOutputPausedRequest,
+    ) -> ttrpc::Result<SetOutputPausedResponse> {
+        self.call(ctx, move |client| async move {
+            let mut call = client.set_output_paused_container_request();
+            let mut req = call.get().init_request();
+            req.set_id(&request.id);
+            req.set_paused(request.paused);
+
+            let response = call.send().promise.await?;
+            let mut paused = SetOutputPausedResponse::new();
+            paused.was_paused = response.get()?.get_response()?.get_was_paused();
+            Ok(paused)
+        })
+        .await
+    }
+
+    async fn attach_container(
+        &self,
+        ctx: &TtrpcContext,
+        request: AttachRequest,
+    ) -> ttrpc::Result<AttachResponse> {
+        self.call(ctx, move |client| async move {
+            let mut call = client.attach_container_request();
+            let mut req = call.get().init_request();
+            req.set_id(&request.id);
+            req.set_socket_path(&request.socket_path);
+            req.set_exec_session_id(&request.exec_session_id);
+            req.set_read_only(request.read_only);
+            req.set_idle_timeout_sec(request.idle_timeout_sec);
+            req.set_max_clients(request.max_clients);
+            req.set_socket_mode(request.socket_mode);
+            req.set_stream_socket(request.stream_socket);
+
+            call.send().promise.await?;
+            Ok(AttachResponse::new())
+        })
+        .await
+    }
+
+    async fn reopen_log_container(
+        &self,
+        ctx: &TtrpcContext,
+        request: ReopenLogRequest,
+    ) -> ttrpc::Result<ReopenLogResponse> {
+        self.call(ctx, move |client| async move {
+            let mut call = client.reopen_log_container_request();
+            call.get().init_request().set_id(&request.id);
+
+            call.send().promise.await?;
+            Ok(ReopenLogResponse::new())
+        })
+        .await
+    }
+
+    async fn io_stats_container(
+        &self,
+        ctx: &TtrpcContext,
+        request: IoStatsRequest,
+    ) -> ttrpc::Result<IoStatsResponse> {
+        self.call(ctx, move |client| async move {
+            let mut call = client.io_stats_container_request();
+            call.get().init_request().set_id(&request.id);
+
+            let response = call.send().promise.await?;
+            let response = response.get()?.get_response()?;
+            let mut stats = IoStatsResponse::new();
+            stats.stdin_bytes = response.get_stdin_bytes();
+            stats.set_stdout(stream_stats(response.get_stdout()?));
+            stats.set_stderr(stream_stats(response.get_stderr()?));
+            stats.extra_fds = response
+                .get_extra_fds()?
+                .iter()
+                .map(|x| {
+                    let mut extra_fd = ExtraFdStats::new();
+                    extra_fd.fd = x.get_fd();
+                    extra_fd.bytes = x.get_bytes();
+                    extra_fd
+                })
+                .collect::<Vec<_>>()
+                .into();
+            Ok(stats)
+        })
+        .await
+    }
+
+    async fn container_stats(
+        &self,
+        ctx: &TtrpcContext,
+        request: ContainerStatsRequest,
+    ) -> ttrpc::Result<ContainerStatsResponse> {
+        self.call(ctx, move |client| async move {
+            let mut call = client.container_stats_request();
+            call.get().init_request().set_id(&request.id);
+
+            let response = call.send().promise.await?;
+            let response = response.get()?.get_response()?;
+            let mut stats = ContainerStatsResponse::new();
+            stats.cpu_usage_usec = response.get_cpu_usage_usec();
+            stats.cpu_user_usec = response.get_cpu_user_usec();
+            stats.cpu_system_usec = response.get_cpu_system_usec();
+            stats.memory_usage_bytes = response.get_memory_usage_bytes();
+            stats.memory_limit_bytes = response.get_memory_limit_bytes();
+            stats.pids_current = response.get_pids_current();
+            stats.pids_limit = response.get_pids_limit();
+            stats.io_read_bytes = response.get_io_read_bytes();
+            stats.io_write_bytes = response.get_io_write_bytes();
+            Ok(stats)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_log_driver_type() {
+        assert_eq!(
+            log_driver_type(LogDriver_Type::JSON_FILE),
+            conmon::log_driver::Type::JsonFile
+        );
+        assert_eq!(
+            log_driver_type(LogDriver_Type::NONE),
+            conmon::log_driver::Type::None
+        );
+    }
+
+    #[test]
+    fn convert_window_size() -> ttrpc::Result<()> {
+        assert_eq!(window_size(80)?, 80);
+        assert!(window_size(1 << 16).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,417 @@
+//! Container IO based on io_uring, which is enabled by the `io-uring` feature.
+//!
+//! A single driver thread owns the ring and completes the reads and writes of all containers,
+//! which saves the readiness notifications and separate syscalls per chunk of the epoll based
+//! IO. The epoll based IO is still used if the ring cannot be set up, for example because
+//! io_uring is disabled by the kernel or a seccomp profile.
+
+use anyhow::{Context as _, Result};
+use futures::{channel::oneshot, ready};
+use io_uring::{opcode, squeue, types, IoUring};
+use lazy_static::lazy_static;
+use nix::{
+    fcntl::{self, FcntlArg, OFlag},
+    sys::eventfd::{eventfd, EfdFlags},
+    unistd,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    future::Future,
+    io::{self, ErrorKind},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Mutex,
+    },
+    task::{Context, Poll},
+    thread,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::{debug, error, info, warn};
+
+lazy_static! {
+    /// The driver shared by all containers, which is `None` if io_uring is unavailable.
+    static ref DRIVER: Option<Driver> = match Driver::new() {
+        Ok(driver) => {
+            info!("Using io_uring for container IO");
+            Some(driver)
+        }
+        Err(e) => {
+            warn!("Unable to set up io_uring, using epoll for container IO: {:#}", e);
+            None
+        }
+    };
+}
+
+/// The buffer of a completed operation together with the amount of transferred bytes.
+type Completion = (Vec<u8>, io::Result<usize>);
+
+#[derive(Debug)]
+/// The reads and writes of a file descriptor, which get completed by io_uring if available.
+pub struct UringIo<T> {
+    inner: T,
+    driver: Option<&'static Driver>,
+    read: Option<Operation>,
+    write: Option<Operation>,
+
+    /// Read data which did not fit into the buffer of the caller.
+    buffered: Vec<u8>,
+}
+
+impl<T> UringIo<T>
+where
+    T: AsRawFd,
+{
+    /// Wrap the provided file descriptor, which gets switched to blocking mode if io_uring is
+    /// available, because the kernel would otherwise complete reads of empty pipes by EAGAIN.
+    pub fn new(inner: T) -> Self {
+        let driver = DRIVER.as_ref().filter(|_| {
+            Self::set_blocking(inner.as_raw_fd())
+                .map_err(|e| warn!("Unable to use io_uring for file descriptor: {:#}", e))
+                .is_ok()
+        });
+        Self {
+            inner,
+            driver,
+            read: None,
+            write: None,
+            buffered: vec![],
+        }
+    }
+
+    fn set_blocking(fd: RawFd) -> Result<()> {
+        let flags = OFlag::from_bits_truncate(
+            fcntl::fcntl(fd, FcntlArg::F_GETFL).context("get file status flags")?,
+        );
+        fcntl::fcntl(fd, FcntlArg::F_SETFL(flags - OFlag::O_NONBLOCK))
+            .context("set file status flags")?;
+        Ok(())
+    }
+}
+
+impl<T> AsRawFd for UringIo<T>
+where
+    T: AsRawFd,
+{
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl<T> AsyncRead for UringIo<T>
+where
+    T: AsyncRead + AsRawFd + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let driver = match this.driver {
+            Some(driver) => driver,
+            None => return Pin::new(&mut this.inner).poll_read(cx, buf),
+        };
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        if this.buffered.is_empty() {
+            let fd = this.inner.as_raw_fd();
+            let operation = match this.read.take() {
+                Some(operation) => operation,
+                None => driver.submit(Kind::Read, fd, vec![0; buf.remaining()])?,
+            };
+            let (mut data, result) = ready!(Pin::new(this.read.insert(operation)).poll(cx))?;
+            this.read = None;
+            data.truncate(result?);
+            this.buffered = data;
+        }
+
+        let n = this.buffered.len().min(buf.remaining());
+        buf.put_slice(&this.buffered[..n]);
+        this.buffered.drain(..n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> AsyncWrite for UringIo<T>
+where
+    T: AsyncWrite + AsRawFd + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let driver = match this.driver {
+            Some(driver) => driver,
+            None => return Pin::new(&mut this.inner).poll_write(cx, buf),
+        };
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let fd = this.inner.as_raw_fd();
+        let operation = match this.write.take() {
+            Some(operation) => operation,
+            None => driver.submit(Kind::Write, fd, buf.to_vec())?,
+        };
+        let (_, result) = ready!(Pin::new(this.write.insert(operation)).poll(cx))?;
+        this.write = None;
+        Poll::Ready(result)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.driver {
+            Some(_) => Poll::Ready(Ok(())),
+            None => Pin::new(&mut this.inner).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.driver {
+            Some(_) => Poll::Ready(Ok(())),
+            None => Pin::new(&mut this.inner).poll_shutdown(cx),
+        }
+    }
+}
+
+impl<T> Drop for UringIo<T> {
+    fn drop(&mut self) {
+        if let Some(driver) = self.driver {
+            for operation in self.read.iter().chain(self.write.iter()) {
+                driver.cancel(operation.key);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A submitted read or write, which completes once the driver received its completion.
+struct Operation {
+    key: u64,
+    rx: oneshot::Receiver<Completion>,
+}
+
+impl Future for Operation {
+    type Output = io::Result<Completion>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.rx)
+            .poll(cx)
+            .map_err(|_| Driver::stopped())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    Read,
+    Write,
+}
+
+#[derive(Debug)]
+enum Request {
+    /// A read or write, where the buffer is owned by the driver until it got completed.
+    Io {
+        kind: Kind,
+        fd: RawFd,
+        buf: Vec<u8>,
+        tx: oneshot::Sender<Completion>,
+    },
+
+    /// The cancellation of the read or write with the provided key.
+    Cancel(u64),
+
+    /// The read of the eventfd, which wakes up the driver on new requests.
+    Wake,
+}
+
+#[derive(Debug)]
+/// The submitting side of the driver thread.
+struct Driver {
+    requests: Mutex<mpsc::Sender<(u64, Request)>>,
+    last_key: AtomicU64,
+    wake: File,
+}
+
+impl Driver {
+    /// The amount of submission queue entries.
+    const ENTRIES: u32 = 256;
+
+    /// The key of the eventfd read.
+    const WAKE_KEY: u64 = 0;
+
+    fn new() -> Result<Self> {
+        let ring = IoUring::new(Self::ENTRIES).context("create ring")?;
+        let fd = eventfd(0, EfdFlags::EFD_CLOEXEC).context("create eventfd")?;
+        // SAFETY: the eventfd got created above and is owned exclusively.
+        let wake = unsafe { File::from_raw_fd(fd) };
+        let (tx, rx) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("io-uring".into())
+            .spawn(move || {
+                if let Err(e) = Self::run(ring, fd, rx) {
+                    error!("io_uring driver failed: {:#}", e);
+                }
+            })
+            .context("spawn io_uring driver thread")?;
+
+        Ok(Self {
+            requests: Mutex::new(tx),
+            last_key: AtomicU64::new(Self::WAKE_KEY),
+            wake,
+        })
+    }
+
+    /// Submit a read or write of the file descriptor.
+    fn submit(&self, kind: Kind, fd: RawFd, buf: Vec<u8>) -> io::Result<Operation> {
+        let (tx, rx) = oneshot::channel();
+        let key = self.last_key.fetch_add(1, Ordering::Relaxed) + 1;
+        self.send(key, Request::Io { kind, fd, buf, tx })?;
+        Ok(Operation { key, rx })
+    }
+
+    /// Cancel the read or write with the provided key, if not already completed.
+    fn cancel(&self, key: u64) {
+        let cancel_key = self.last_key.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Err(e) = self.send(cancel_key, Request::Cancel(key)) {
+            debug!("Unable to cancel io_uring operation: {}", e);
+        }
+    }
+
+    fn send(&self, key: u64, request: Request) -> io::Result<()> {
+        self.requests
+            .lock()
+            .map_err(|_| Self::stopped())?
+            .send((key, request))
+            .map_err(|_| Self::stopped())?;
+        unistd::write(self.wake.as_raw_fd(), &1u64.to_ne_bytes())?;
+        Ok(())
+    }
+
+    fn stopped() -> io::Error {
+        io::Error::new(ErrorKind::BrokenPipe, "io_uring driver stopped")
+    }
+
+    /// Push the requests into the ring and complete the operations until the process exits.
+    fn run(mut ring: IoUring, wake: RawFd, requests: mpsc::Receiver<(u64, Request)>) -> Result<()> {
+        let mut counter = [0u8; 8];
+        let mut backlog = VecDeque::from(vec![(Self::WAKE_KEY, Request::Wake)]);
+        let mut pending = HashMap::new();
+
+        loop {
+            backlog.extend(requests.try_iter());
+            while let Some((key, request)) = backlog.pop_front() {
+                let entry = match &request {
+                    Request::Io { kind, fd, buf, .. } => Self::entry(*kind, *fd, buf),
+                    Request::Cancel(target) => opcode::AsyncCancel::new(*target).build(),
+                    Request::Wake => {
+                        opcode::Read::new(types::Fd(wake), counter.as_mut_ptr(), 8).build()
+                    }
+                };
+                // SAFETY: the buffers are kept in `pending` or `counter` until completed.
+                if unsafe { ring.submission().push(&entry.user_data(key)) }.is_err() {
+                    backlog.push_front((key, request));
+                    break;
+                }
+                if let Request::Io { buf, tx, .. } = request {
+                    pending.insert(key, (buf, tx));
+                }
+            }
+
+            let want = if backlog.is_empty() { 1 } else { 0 };
+            match ring.submit_and_wait(want) {
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                result => {
+                    result.context("submit to ring")?;
+                }
+            }
+
+            for entry in ring.completion() {
+                let key = entry.user_data();
+                if key == Self::WAKE_KEY {
+                    backlog.push_back((key, Request::Wake));
+                } else if let Some((buf, tx)) = pending.remove(&key) {
+                    let result = entry.result();
+                    let result = if result < 0 {
+                        Err(io::Error::from_raw_os_error(-result))
+                    } else {
+                        Ok(result as usize)
+                    };
+                    tx.send((buf, result)).ok();
+                }
+            }
+        }
+    }
+
+    fn entry(kind: Kind, fd: RawFd, buf: &[u8]) -> squeue::Entry {
+        match kind {
+            Kind::Read => {
+                opcode::Read::new(types::Fd(fd), buf.as_ptr() as *mut u8, buf.len() as u32).build()
+            }
+            Kind::Write => {
+                opcode::Write::new(types::Fd(fd), buf.as_ptr(), buf.len() as u32).build()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Stdio;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        process::Command,
+    };
+
+    #[tokio::test]
+    async fn read_write() -> Result<()> {
+        let mut child = Command::new("cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdin = UringIo::new(child.stdin.take().context("no stdin")?);
+        let mut stdout = UringIo::new(child.stdout.take().context("no stdout")?);
+        assert_eq!(stdin.driver.is_some(), DRIVER.is_some());
+
+        stdin.write_all(b"hello").await?;
+        let mut buf = [0; 3];
+        stdout.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hel");
+
+        stdin.write_all(b" world").await?;
+        drop(stdin);
+        let mut rest = String::new();
+        stdout.read_to_string(&mut rest).await?;
+        assert_eq!(rest, "lo world");
+
+        child.wait().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cancel_on_drop() -> Result<()> {
+        let mut child = Command::new("sleep")
+            .arg("10")
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdout = UringIo::new(child.stdout.take().context("no stdout")?);
+
+        let mut buf = [0; 8];
+        let read =
+            tokio::time::timeout(std::time::Duration::from_millis(50), stdout.read(&mut buf)).await;
+        assert!(read.is_err());
+        drop(stdout);
+
+        child.kill().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+//! Detection of UTF-8 code points split at the end of a buffer.
+
+/// Returns the length of `buf` without a trailing incomplete UTF-8 sequence, which allows
+/// splitting the output without breaking a code point. Invalid UTF-8 is not treated specially
+/// and therefore never shortened.
+pub fn complete_len(buf: &[u8]) -> usize {
+    // A sequence is at most four bytes long, which means that only the last three bytes can
+    // belong to an incomplete one.
+    for (n, byte) in buf.iter().rev().take(3).enumerate() {
+        let width = match byte {
+            0b1000_0000..=0b1011_1111 => continue,
+            0b1100_0000..=0b1101_1111 => 2,
+            0b1110_0000..=0b1110_1111 => 3,
+            0b1111_0000..=0b1111_0111 => 4,
+            _ => return buf.len(),
+        };
+        return if n + 1 < width {
+            buf.len() - n - 1
+        } else {
+            buf.len()
+        };
+    }
+    buf.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complete_len_split() {
+        let text = "aä€😀".as_bytes();
+        assert_eq!(complete_len(text), text.len());
+        assert_eq!(complete_len(&text[..2]), 1);
+        assert_eq!(complete_len(&text[..3]), 3);
+        assert_eq!(complete_len(&text[..4]), 3);
+        assert_eq!(complete_len(&text[..5]), 3);
+        assert_eq!(complete_len(&text[..6]), 6);
+        assert_eq!(complete_len(&text[..9]), 6);
+        assert_eq!(complete_len(&[]), 0);
+    }
+
+    #[test]
+    fn complete_len_invalid() {
+        assert_eq!(complete_len(b"a\x80\x80\x80"), 4);
+        assert_eq!(complete_len(b"a\xff"), 2);
+    }
+}
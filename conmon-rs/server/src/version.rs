@@ -1,5 +1,6 @@
 //! Generic version information for conmon
 
+use crate::capabilities::Capabilities;
 use getset::CopyGetters;
 use shadow_rs::shadow;
 
@@ -23,6 +24,12 @@ pub struct Version {
 
     /// The used Rust version.
     rust_version: &'static str,
+
+    /// The target triple the binary got built for.
+    build_target: &'static str,
+
+    /// The cargo profile of the build, like `debug` or `release`.
+    build_profile: &'static str,
 }
 
 impl Version {
@@ -34,6 +41,8 @@ impl Version {
             commit: build::COMMIT_HASH,
             build_date: build::BUILD_TIME,
             rust_version: build::RUST_VERSION,
+            build_target: build::BUILD_TARGET,
+            build_profile: build::BUILD_RUST_CHANNEL,
         }
     }
 
@@ -51,6 +60,15 @@ impl Version {
         println!("commit: {}", build::COMMIT_HASH);
         println!("build: {}", build::BUILD_TIME);
         println!("{}", build::RUST_VERSION);
+        println!("target: {}", build::BUILD_TARGET);
+        println!("profile: {}", build::BUILD_RUST_CHANNEL);
+        println!(
+            "features: {}",
+            match Capabilities::enabled_features() {
+                x if x.is_empty() => "none".into(),
+                x => x.join(","),
+            }
+        );
     }
 }
 
@@ -66,6 +84,8 @@ mod tests {
         assert_eq!(v.commit(), build::COMMIT_HASH);
         assert_eq!(v.build_date(), build::BUILD_TIME);
         assert_eq!(v.rust_version(), build::RUST_VERSION);
+        assert_eq!(v.build_target(), build::BUILD_TARGET);
+        assert_eq!(v.build_profile(), build::BUILD_RUST_CHANNEL);
 
         v.print();
     }
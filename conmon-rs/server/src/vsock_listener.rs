@@ -0,0 +1,118 @@
+//! The optional vsock listener of the RPC server, which allows a manager on the host to reach a
+//! server running inside of a virtual machine.
+
+use crate::capabilities::Capabilities;
+use anyhow::{bail, Context, Result};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time,
+};
+use tokio_vsock::VsockStream;
+
+/// Bind to any context ID of the virtual machine.
+const VMADDR_CID_ANY: u32 = u32::MAX;
+
+/// A vsock listener, whose connections negotiate the protocol version before any Cap'n Proto
+/// message.
+pub struct VsockListener {
+    listener: tokio_vsock::VsockListener,
+}
+
+impl VsockListener {
+    /// The bytes starting both negotiation messages.
+    const MAGIC: &'static [u8; 4] = b"CMRS";
+
+    /// The time a client has to send its protocol version.
+    const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Bind the listener to the port of any context ID.
+    pub fn bind(port: u32) -> Result<Self> {
+        let listener = tokio_vsock::VsockListener::bind(VMADDR_CID_ANY, port)
+            .context(format!("bind vsock listener to port {}", port))?;
+        Ok(Self { listener })
+    }
+
+    /// Accept the next vsock connection, which still requires the version negotiation.
+    pub async fn accept(&mut self) -> Result<VsockStream> {
+        let (stream, _) = self
+            .listener
+            .accept()
+            .await
+            .context("accept vsock connection")?;
+        Ok(stream)
+    }
+
+    /// Negotiate the protocol version with the client, which sends `CMRS` followed by the newest
+    /// version it speaks as big endian `u32`. The server answers the same way with the version
+    /// used for the connection, which is 0 if the client is too old.
+    pub async fn negotiate<T>(stream: &mut T) -> Result<u32>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut request = [0; 8];
+        time::timeout(Self::NEGOTIATION_TIMEOUT, stream.read_exact(&mut request))
+            .await
+            .context("wait for protocol version")?
+            .context("read protocol version")?;
+        if &request[..4] != Self::MAGIC {
+            bail!("invalid protocol negotiation")
+        }
+
+        let client_version = u32::from_be_bytes([request[4], request[5], request[6], request[7]]);
+        let version = match client_version.min(Capabilities::PROTOCOL_VERSION) {
+            x if x < Capabilities::MIN_PROTOCOL_VERSION => 0,
+            x => x,
+        };
+        let mut response = Self::MAGIC.to_vec();
+        response.extend_from_slice(&version.to_be_bytes());
+        stream
+            .write_all(&response)
+            .await
+            .context("write protocol version")?;
+
+        if version == 0 {
+            bail!("unsupported protocol version {}", client_version)
+        }
+        Ok(version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::duplex;
+
+    async fn negotiate(request: &[u8]) -> (Result<u32>, Vec<u8>) {
+        let (mut client, mut server) = duplex(64);
+        client.write_all(request).await.unwrap();
+        let result = VsockListener::negotiate(&mut server).await;
+        drop(server);
+        let mut response = vec![];
+        client.read_to_end(&mut response).await.unwrap();
+        (result, response)
+    }
+
+    #[tokio::test]
+    async fn negotiate_version() {
+        let (result, response) = negotiate(b"CMRS\0\0\0\x01").await;
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(response, b"CMRS\0\0\0\x01");
+
+        // Newer clients fall back to the version of the server.
+        let (result, response) = negotiate(b"CMRS\0\0\x01\0").await;
+        assert_eq!(result.unwrap(), Capabilities::PROTOCOL_VERSION);
+        assert_eq!(response[4..], Capabilities::PROTOCOL_VERSION.to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn negotiate_failure() {
+        let (result, response) = negotiate(b"CMRS\0\0\0\0").await;
+        assert!(result.is_err());
+        assert_eq!(response, b"CMRS\0\0\0\0");
+
+        let (result, response) = negotiate(b"HTTP/1.1").await;
+        assert!(result.is_err());
+        assert!(response.is_empty());
+    }
+}
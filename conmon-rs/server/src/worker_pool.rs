@@ -0,0 +1,544 @@
+//! The workers of the isolation mode, which are dedicated server processes per pod or container
+//! that the main server forwards the RPCs to.
+
+use crate::{
+    auth::Authenticator,
+    config::{Config, Isolation},
+    container_filter::ContainerFilter,
+};
+use anyhow::{bail, format_err, Context, Result};
+use capnp_rpc::{rpc_twoparty_capnp::Side, twoparty, RpcSystem};
+use conmon_common::conmon_capnp::conmon;
+use futures::{future, AsyncReadExt, FutureExt};
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::{getuid, Pid},
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    process::Stdio,
+    rc::Rc,
+    time::Duration,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    net::UnixStream,
+    process::Command,
+    sync::Mutex,
+    task,
+    time::{self, Instant},
+};
+use tokio_util::{compat::TokioAsyncReadCompatExt, sync::CancellationToken};
+use tracing::{debug, debug_span, error, info, warn, Instrument};
+use twoparty::VatNetwork;
+
+#[derive(Clone)]
+/// The running workers and the containers they are responsible for.
+pub struct WorkerPool {
+    state: Rc<RefCell<PoolState>>,
+
+    /// Serializes spawning workers, so that concurrent creates of a pod share one worker.
+    spawn_lock: Rc<Mutex<()>>,
+
+    settings: Rc<WorkerSettings>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    /// The workers by their key.
+    workers: HashMap<String, Worker>,
+
+    /// The worker keys by container ID.
+    routes: HashMap<String, String>,
+
+    /// The event listeners, which get subscribed to every new worker.
+    listeners: Vec<(conmon::event_listener::Client, String)>,
+
+    /// The index of the next worker, used for its runtime directory.
+    next_index: u64,
+}
+
+#[derive(Clone)]
+struct Worker {
+    client: conmon::Client,
+    pid: u32,
+
+    /// Cancelled once the worker process exited.
+    exited: CancellationToken,
+}
+
+#[derive(Debug)]
+struct WorkerSettings {
+    isolation: Isolation,
+    program: PathBuf,
+    args: Vec<String>,
+    runtime_dir: PathBuf,
+
+    /// The token sent to the workers if they require authentication.
+    token: Option<Vec<u8>>,
+}
+
+impl WorkerPool {
+    /// The time a new worker has to serve its socket.
+    const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// The interval of checking if a new worker serves its socket.
+    const STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+    /// The time workers get to exit on shutdown in addition to draining their containers.
+    const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+    /// The methods the pool calls on the workers on its own, which the workers have to allow.
+    const POOL_METHODS: &'static [&'static str] = &["shutdown", "subscribeEvents"];
+
+    /// Create a new pool, whose workers run the current executable with the settings of the
+    /// config.
+    pub fn new(config: &Config) -> Result<Self> {
+        let program = env::current_exe().context("get path of current executable")?;
+        let token = match config.auth_token_file() {
+            Some(path) => Authenticator::load(path)?.token().map(<[u8]>::to_vec),
+            None => None,
+        };
+        Ok(Self {
+            state: Default::default(),
+            spawn_lock: Default::default(),
+            settings: Rc::new(WorkerSettings {
+                isolation: config.isolation(),
+                program,
+                args: Self::worker_args(config),
+                runtime_dir: config.runtime_dir().join("workers"),
+                token,
+            }),
+        })
+    }
+
+    /// The arguments shared by all workers, which get their runtime directory on spawn.
+    fn worker_args(config: &Config) -> Vec<String> {
+        let mut args = vec![
+            "--skip-fork".to_string(),
+            format!("--isolation={}", <&str>::from(Isolation::None)),
+            format!("--runtime={}", config.runtime().display()),
+            format!("--log-level={}", config.log_level()),
+            format!("--log-driver={}", <&str>::from(config.log_driver())),
+            format!("--cgroup-manager={}", <&str>::from(config.cgroup_manager())),
+            format!("--attach-idle-timeout={}", config.attach_idle_timeout()),
+            format!("--attach-max-clients={}", config.attach_max_clients()),
+            format!("--attach-packet-size={}", config.attach_packet_size()),
+            format!(
+                "--max-attach-replay-size={}",
+                config.max_attach_replay_size()
+            ),
+            format!(
+                "--max-recent-output-size={}",
+                config.max_recent_output_size()
+            ),
+            format!("--stdin-buffer-size={}", config.stdin_buffer_size()),
+            format!("--output-buffer-size={}", config.output_buffer_size()),
+            format!("--log-quota={}", config.log_quota()),
+            format!(
+                "--log-quota-policy={}",
+                <&str>::from(config.log_quota_policy())
+            ),
+            format!("--shutdown-timeout={}", config.shutdown_timeout()),
+            format!("--rpc-timeout={}", config.rpc_timeout()),
+            format!("--create-parallelism={}", config.create_parallelism()),
+            format!("--idempotency-window={}", config.idempotency_window()),
+            format!("--max-concurrent-rpcs={}", config.max_concurrent_rpcs()),
+            format!("--max-containers={}", config.max_containers()),
+            format!("--exited-container-ttl={}", config.exited_container_ttl()),
+        ];
+        if let Some(root) = config.runtime_root() {
+            args.push(format!("--runtime-root={}", root.display()));
+        }
+        if !config.rpc_method_limits().is_empty() {
+            args.push(format!(
+                "--rpc-method-limits={}",
+                config.rpc_method_limits().join(",")
+            ));
+        }
+        if let Some(file) = config.config_file() {
+            args.push(format!("--config-file={}", file.display()));
+        }
+        if let Some(file) = config.auth_token_file() {
+            args.push(format!("--auth-token-file={}", file.display()));
+        }
+
+        // The workers have to accept the main server as peer, which runs as the same user.
+        if !config.allowed_uids().is_empty() || !config.allowed_gids().is_empty() {
+            let uids = config
+                .allowed_uids()
+                .iter()
+                .copied()
+                .chain(Some(getuid().as_raw()))
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>();
+            args.push(format!("--allowed-uids={}", uids.join(",")));
+        }
+        if !config.allowed_gids().is_empty() {
+            let gids = config
+                .allowed_gids()
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>();
+            args.push(format!("--allowed-gids={}", gids.join(",")));
+        }
+
+        let methods = Self::worker_methods(config);
+        if !methods.is_empty() {
+            args.push(format!("--allowed-methods={}", methods.join(",")));
+        }
+        args
+    }
+
+    /// The methods the workers allow, which are the ones allowed on any listener of the main
+    /// server along with the ones called by the pool. All methods are allowed if empty.
+    fn worker_methods(config: &Config) -> Vec<String> {
+        let listeners = [
+            (true, config.allowed_methods()),
+            (config.tls_address().is_some(), config.tls_allowed_methods()),
+            (
+                config.vsock_port().is_some(),
+                config.vsock_allowed_methods(),
+            ),
+        ];
+        let mut methods = vec![];
+        for (_, allowed) in listeners.iter().filter(|(enabled, _)| *enabled) {
+            if allowed.is_empty() {
+                return vec![];
+            }
+            methods.extend(allowed.iter().cloned());
+        }
+        methods.extend(Self::POOL_METHODS.iter().map(|x| x.to_string()));
+        methods.sort();
+        methods.dedup();
+        methods
+    }
+
+    /// The key of the worker responsible for the container, which is its pod ID in pod
+    /// isolation, if available.
+    pub fn key(&self, id: &str, metadata: &HashMap<String, String>) -> String {
+        match self.settings.isolation {
+            Isolation::Pod => match metadata.get(ContainerFilter::POD_ID) {
+                Some(pod_id) if !pod_id.is_empty() => format!("pod-{}", pod_id),
+                _ => format!("container-{}", id),
+            },
+            _ => format!("container-{}", id),
+        }
+    }
+
+    /// The client of the worker for the key, which gets spawned if not running yet.
+    pub async fn worker(&self, key: &str) -> Result<conmon::Client> {
+        let _guard = self.spawn_lock.lock().await;
+        if let Some(worker) = self.state.borrow().workers.get(key) {
+            return Ok(worker.client.clone());
+        }
+
+        let worker = self.spawn(key).await?;
+        let listeners = self.state.borrow().listeners.clone();
+        for (listener, id) in listeners {
+            if let Err(e) = Self::subscribe(&worker.client, listener, &id).await {
+                error!("Unable to subscribe listener to worker {}: {:#}", key, e);
+            }
+        }
+        self.state
+            .borrow_mut()
+            .workers
+            .insert(key.into(), worker.clone());
+        Ok(worker.client)
+    }
+
+    /// Spawn a new worker and connect to it once it serves its socket.
+    async fn spawn(&self, key: &str) -> Result<Worker> {
+        let runtime_dir = {
+            let mut state = self.state.borrow_mut();
+            state.next_index += 1;
+            self.settings.runtime_dir.join(state.next_index.to_string())
+        };
+        fs::create_dir_all(&runtime_dir).context(format!(
+            "create worker runtime dir {}",
+            runtime_dir.display()
+        ))?;
+
+        let mut command = Command::new(&self.settings.program);
+        command
+            .args(&self.settings.args)
+            .arg(format!("--runtime-dir={}", runtime_dir.display()))
+            .stdin(Stdio::null());
+        // Workers must not serve the listeners configured by the environment of the main server,
+        // which is why they get all of their settings as arguments.
+        for (name, _) in env::vars_os() {
+            if name.to_string_lossy().starts_with("CONMON_") {
+                command.env_remove(name);
+            }
+        }
+        let mut child = command.spawn().context(format!("spawn worker {}", key))?;
+        let pid = child
+            .id()
+            .ok_or_else(|| format_err!("worker {} exited immediately", key))?;
+        info!("Spawned worker {} with PID {}", key, pid);
+
+        let state = self.state.clone();
+        let worker_key = key.to_string();
+        let exited = CancellationToken::new();
+        let worker_exited = exited.clone();
+        task::spawn_local(
+            async move {
+                match child.wait().await {
+                    Ok(status) if status.success() => debug!("Worker {} exited", worker_key),
+                    Ok(status) => error!("Worker {} exited with {}", worker_key, status),
+                    Err(e) => error!("Unable to wait for worker {}: {:#}", worker_key, e),
+                }
+                worker_exited.cancel();
+                let mut state = state.borrow_mut();
+                if matches!(state.workers.get(&worker_key), Some(x) if x.pid == pid) {
+                    state.workers.remove(&worker_key);
+                }
+            }
+            .instrument(debug_span!("worker")),
+        );
+
+        let client = self
+            .connect(&runtime_dir.join("conmon.sock"))
+            .await
+            .context(format!("connect to worker {}", key))?;
+        Ok(Worker {
+            client,
+            pid,
+            exited,
+        })
+    }
+
+    /// Connect to the socket of a worker, waiting for it to show up, and authenticate if
+    /// required.
+    async fn connect(&self, socket: &Path) -> Result<conmon::Client> {
+        let deadline = Instant::now() + Self::STARTUP_TIMEOUT;
+        let mut stream = loop {
+            match UnixStream::connect(socket).await {
+                Ok(stream) => break stream,
+                Err(e) if Instant::now() >= deadline => {
+                    bail!("worker socket {} not ready: {}", socket.display(), e)
+                }
+                Err(_) => time::sleep(Self::STARTUP_POLL_INTERVAL).await,
+            }
+        };
+        if let Some(token) = &self.settings.token {
+            stream
+                .write_all(&[token.as_slice(), b"\n"].concat())
+                .await
+                .context("send token to worker")?;
+        }
+
+        let (reader, writer) = TokioAsyncReadCompatExt::compat(stream).split();
+        let network = Box::new(VatNetwork::new(
+            reader,
+            writer,
+            Side::Client,
+            Default::default(),
+        ));
+        let mut rpc_system = RpcSystem::new(network, None);
+        let client: conmon::Client = rpc_system.bootstrap(Side::Server);
+        task::spawn_local(rpc_system.map(|result| {
+            if let Err(e) = result {
+                debug!("Worker connection failed: {:#}", e);
+            }
+        }));
+        Ok(client)
+    }
+
+    /// Subscribe the event listener to a worker.
+    async fn subscribe(
+        worker: &conmon::Client,
+        listener: conmon::event_listener::Client,
+        id: &str,
+    ) -> Result<()> {
+        let mut request = worker.subscribe_events_request();
+        let mut req = request.get().init_request();
+        req.set_listener(listener);
+        req.set_id(id);
+        request.send().promise.await?;
+        Ok(())
+    }
+
+    /// Subscribe the event listener to all running and future workers.
+    pub async fn add_listener(
+        &self,
+        listener: conmon::event_listener::Client,
+        id: &str,
+    ) -> Result<()> {
+        let _guard = self.spawn_lock.lock().await;
+        self.state
+            .borrow_mut()
+            .listeners
+            .push((listener.clone(), id.into()));
+        for worker in self.workers() {
+            Self::subscribe(&worker, listener.clone(), id).await?;
+        }
+        Ok(())
+    }
+
+    /// Remember the worker responsible for the container.
+    pub fn add_route(&self, id: &str, key: &str) {
+        self.state.borrow_mut().routes.insert(id.into(), key.into());
+    }
+
+    /// The key of the worker responsible for the container.
+    pub fn route_key(&self, id: &str) -> Option<String> {
+        self.state.borrow().routes.get(id).cloned()
+    }
+
+    /// The client of the worker responsible for the container.
+    pub fn route(&self, id: &str) -> Result<conmon::Client> {
+        let key = self
+            .route_key(id)
+            .ok_or_else(|| format_err!("container with ID {} not found", id))?;
+        match self.state.borrow().workers.get(&key) {
+            Some(worker) => Ok(worker.client.clone()),
+            None => bail!("worker {} of container {} is not running", key, id),
+        }
+    }
+
+    /// Forget the container, and shut down its worker if it was the last container of it.
+    pub fn remove_route(&self, id: &str) {
+        let mut state = self.state.borrow_mut();
+        let key = match state.routes.remove(id) {
+            Some(key) => key,
+            None => return,
+        };
+        if state.routes.values().any(|x| x == &key) {
+            return;
+        }
+        if let Some(worker) = state.workers.remove(&key) {
+            debug!("Shutting down worker {} without containers", key);
+            task::spawn_local(
+                async move {
+                    if let Err(e) = worker.client.shutdown_request().send().promise.await {
+                        debug!("Shutdown of worker {} failed: {}", key, e);
+                    }
+                }
+                .instrument(debug_span!("worker")),
+            );
+        }
+    }
+
+    /// Send the signal to all running workers, which drain their containers within the timeout,
+    /// and kill the workers which did not exit after it.
+    pub async fn shutdown(&self, signal: Signal, timeout: Duration) {
+        let workers = self
+            .state
+            .borrow()
+            .workers
+            .iter()
+            .map(|(key, worker)| (key.clone(), worker.clone()))
+            .collect::<Vec<_>>();
+        for (key, worker) in &workers {
+            debug!("Sending {} to worker {}", signal, key);
+            if let Err(e) = kill(Pid::from_raw(worker.pid as i32), signal) {
+                error!("Unable to send {} to worker {}: {:#}", signal, key, e);
+            }
+        }
+
+        let exits = future::join_all(workers.iter().map(|(_, x)| x.exited.cancelled()));
+        if time::timeout(timeout + Self::SHUTDOWN_GRACE_PERIOD, exits)
+            .await
+            .is_ok()
+        {
+            return;
+        }
+        for (key, worker) in workers.iter().filter(|(_, x)| !x.exited.is_cancelled()) {
+            warn!("Killing worker {} which did not exit in time", key);
+            if let Err(e) = kill(Pid::from_raw(worker.pid as i32), Signal::SIGKILL) {
+                error!("Unable to kill worker {}: {:#}", key, e);
+            }
+        }
+    }
+
+    /// The clients of all running workers.
+    pub fn workers(&self) -> Vec<conmon::Client> {
+        self.state
+            .borrow()
+            .workers
+            .values()
+            .map(|x| x.client.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn pool(isolation: &str) -> WorkerPool {
+        let config = Config::parse_from([
+            "conmonrs",
+            "--runtime=/bin/true",
+            "--runtime-dir=/tmp",
+            &format!("--isolation={}", isolation),
+        ]);
+        WorkerPool::new(&config).unwrap()
+    }
+
+    #[test]
+    fn key() {
+        let metadata = HashMap::from([(ContainerFilter::POD_ID.to_string(), "pod".to_string())]);
+
+        let sut = pool("pod");
+        assert_eq!(sut.key("id", &metadata), "pod-pod");
+        assert_eq!(sut.key("id", &HashMap::new()), "container-id");
+
+        let sut = pool("container");
+        assert_eq!(sut.key("id", &metadata), "container-id");
+    }
+
+    #[test]
+    fn worker_args() {
+        let sut = pool("pod");
+        let args = &sut.settings.args;
+        assert!(args.contains(&"--skip-fork".to_string()));
+        assert!(args.contains(&"--isolation=none".to_string()));
+        assert!(args.contains(&"--runtime=/bin/true".to_string()));
+        assert!(!args.iter().any(|x| x.starts_with("--runtime-dir")));
+
+        let config = Config::try_parse_from(
+            ["conmonrs", "--runtime-dir=/tmp/workers/1"]
+                .iter()
+                .map(|x| x.to_string())
+                .chain(args.iter().cloned()),
+        )
+        .unwrap();
+        assert_eq!(config.isolation(), Isolation::None);
+        assert!(config.skip_fork());
+    }
+
+    #[test]
+    fn worker_args_access() {
+        let config = Config::parse_from([
+            "conmonrs",
+            "--runtime=/bin/true",
+            "--isolation=pod",
+            "--allowed-gids=20",
+            "--allowed-methods=version,attachContainer",
+            "--vsock-port=1024",
+            "--vsock-allowed-methods=execSyncContainer",
+        ]);
+        let args = WorkerPool::worker_args(&config);
+        assert!(args.contains(&format!("--allowed-uids={}", getuid())));
+        assert!(args.contains(&"--allowed-gids=20".to_string()));
+        assert!(args.contains(
+            &"--allowed-methods=attachContainer,execSyncContainer,shutdown,subscribeEvents,version"
+                .to_string()
+        ));
+
+        let config = Config::parse_from([
+            "conmonrs",
+            "--runtime=/bin/true",
+            "--allowed-methods=version",
+            "--vsock-port=1024",
+        ]);
+        let args = WorkerPool::worker_args(&config);
+        assert!(!args.iter().any(|x| x.starts_with("--allowed-")));
+    }
+}
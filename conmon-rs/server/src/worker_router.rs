@@ -0,0 +1,418 @@
+//! Forward the RPCs of the isolation mode to the workers responsible for the containers, while
+//! the main server only answers the RPCs not related to any container.
+//!
+//! File descriptors passed via the fd socket are not forwarded to the workers, so that they
+//! cannot be used by create and exec requests in isolation mode.
+
+use crate::{
+    method_filter::{self, METHODS},
+    worker_pool::WorkerPool,
+};
+use capnp::{
+    any_pointer,
+    capability::{self, FromServer, Params, Promise, Request, Response, Results},
+    Error,
+};
+use capnp_rpc::pry;
+use conmon_common::conmon_capnp::conmon;
+use futures::{future, stream, StreamExt};
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+};
+use tracing::{debug, error};
+
+macro_rules! capnp_err {
+    ($x:expr) => {
+        $x.map_err(|e| Error::failed(format!("{:#}", e)))
+    };
+}
+
+/// A server forwarding the RPCs to the workers of the pool.
+pub struct WorkerRouter {
+    /// The main server.
+    local: conmon::Client,
+    pool: WorkerPool,
+    create_parallelism: usize,
+}
+
+impl WorkerRouter {
+    /// Create a new router, which answers the RPCs not related to any container by the main
+    /// server.
+    pub fn new(local: conmon::Client, pool: WorkerPool, create_parallelism: usize) -> Self {
+        Self {
+            local,
+            pool,
+            create_parallelism,
+        }
+    }
+
+    /// A request calling the method of the client with the params.
+    fn request(
+        client: &conmon::Client,
+        method_id: u16,
+        params: any_pointer::Reader<'_>,
+    ) -> capnp::Result<Request<any_pointer::Owned, any_pointer::Owned>> {
+        let mut request = client
+            .client
+            .new_call::<any_pointer::Owned, any_pointer::Owned>(
+                conmon::_private::TYPE_ID,
+                method_id,
+                None,
+            );
+        request.get().set_as(params)?;
+        Ok(request)
+    }
+
+    /// Forward the method to the main server.
+    fn forward_local(
+        &self,
+        method_id: u16,
+        params: Params<any_pointer::Owned>,
+        mut results: Results<any_pointer::Owned>,
+    ) -> Promise<(), Error> {
+        let request = pry!(Self::request(&self.local, method_id, pry!(params.get())));
+        Promise::from_future(async move {
+            let response = request.send().promise.await?;
+            results.get().set_as(response.get()?)
+        })
+    }
+
+    /// Forward the method to the worker of the container it operates on. Removed containers
+    /// release their worker.
+    fn forward_container(
+        &self,
+        method: &'static str,
+        method_id: u16,
+        params: Params<any_pointer::Owned>,
+        mut results: Results<any_pointer::Owned>,
+    ) -> Promise<(), Error> {
+        let params = pry!(params.get());
+        let id = pry!(
+            pry!(method_filter::container_id(method, params)).ok_or_else(|| {
+                Error::unimplemented(format!("method {} cannot be routed to a worker", method))
+            })
+        );
+        let worker = pry!(capnp_err!(self.pool.route(&id)));
+        let request = pry!(Self::request(&worker, method_id, params));
+        let pool = self.pool.clone();
+        Promise::from_future(async move {
+            let response = request.send().promise.await?;
+            if method == "removeContainer" {
+                pool.remove_route(&id);
+            }
+            results.get().set_as(response.get()?)
+        })
+    }
+
+    /// Apply the method to the main server and all workers, answering with the response of the
+    /// main server.
+    fn broadcast(
+        &self,
+        method_id: u16,
+        params: Params<any_pointer::Owned>,
+        mut results: Results<any_pointer::Owned>,
+    ) -> Promise<(), Error> {
+        let params = pry!(params.get());
+        let request = pry!(Self::request(&self.local, method_id, params));
+        let mut worker_requests = vec![];
+        for worker in self.pool.workers() {
+            worker_requests.push(pry!(Self::request(&worker, method_id, params)));
+        }
+        Promise::from_future(async move {
+            let response = request.send().promise.await?;
+            for result in
+                future::join_all(worker_requests.into_iter().map(|x| x.send().promise)).await
+            {
+                result?;
+            }
+            results.get().set_as(response.get()?)
+        })
+    }
+
+    /// Create the container in its worker, which gets spawned if required.
+    fn create_container(
+        &self,
+        method_id: u16,
+        params: Params<any_pointer::Owned>,
+        mut results: Results<any_pointer::Owned>,
+    ) -> Promise<(), Error> {
+        let pool = self.pool.clone();
+        Promise::from_future(async move {
+            let params = params.get()?;
+            let req = params
+                .get_as::<conmon::create_container_params::Reader>()?
+                .get_request()?;
+            let (id, key) = Self::worker_key(&pool, req)?;
+            let worker = capnp_err!(pool.worker(&key).await)?;
+            let response = Self::request(&worker, method_id, params)?
+                .send()
+                .promise
+                .await?;
+            pool.add_route(&id, &key);
+            results.get().set_as(response.get()?)
+        })
+    }
+
+    /// The ID of the container and the key of its worker, where retries of a create stick to
+    /// the worker of the first attempt.
+    fn worker_key(
+        pool: &WorkerPool,
+        req: conmon::create_container_request::Reader<'_>,
+    ) -> capnp::Result<(String, String)> {
+        let id = req.get_id()?.to_string();
+        if let Some(key) = pool.route_key(&id) {
+            return Ok((id, key));
+        }
+        let mut metadata = HashMap::new();
+        for entry in req.get_metadata()?.iter() {
+            metadata.insert(entry.get_key()?.to_string(), entry.get_value()?.to_string());
+        }
+        let key = pool.key(&id, &metadata);
+        Ok((id, key))
+    }
+
+    /// Create the containers in their workers, where up to the parallelism of them get created
+    /// concurrently.
+    fn create_containers(
+        &self,
+        params: Params<any_pointer::Owned>,
+        mut results: Results<any_pointer::Owned>,
+    ) -> Promise<(), Error> {
+        let pool = self.pool.clone();
+        let create_parallelism = self.create_parallelism;
+        Promise::from_future(async move {
+            let req = params
+                .get()?
+                .get_as::<conmon::create_containers_params::Reader>()?
+                .get_request()?;
+            let parallelism = match req.get_parallelism() {
+                0 => create_parallelism,
+                x => x as usize,
+            };
+
+            let mut creates = vec![];
+            for request in req.get_requests()?.iter() {
+                let (id, key) = Self::worker_key(&pool, request)?;
+                let create = match pool.worker(&key).await {
+                    Ok(worker) => {
+                        let mut create = worker.create_container_request();
+                        create.get().set_request(request)?;
+                        Ok(create)
+                    }
+                    Err(e) => Err(e),
+                };
+                creates.push((id, key, create));
+            }
+
+            let outcomes = stream::iter(creates.into_iter().map(|(id, key, create)| async move {
+                let outcome = match create {
+                    Ok(create) => match create.send().promise.await {
+                        Ok(response) => response
+                            .get()
+                            .and_then(|x| x.get_response())
+                            .map(|x| x.get_container_pid()),
+                        Err(e) => Err(e),
+                    },
+                    Err(e) => Err(Error::failed(format!("{:#}", e))),
+                };
+                (id, key, outcome)
+            }))
+            .buffered(parallelism)
+            .collect::<Vec<_>>()
+            .await;
+
+            let mut list = results
+                .get()
+                .init_as::<conmon::create_containers_results::Builder>()
+                .init_response()
+                .init_results(outcomes.len() as u32);
+            for (i, (id, key, outcome)) in outcomes.iter().enumerate() {
+                let mut result = list.reborrow().get(i as u32);
+                result.set_id(id);
+                match outcome {
+                    Ok(container_pid) => {
+                        pool.add_route(id, key);
+                        result.set_container_pid(*container_pid);
+                    }
+                    Err(e) => result.set_error(&e.description),
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// List the containers of all workers, skipping the ones which failed to answer.
+    fn list_containers(
+        &self,
+        method_id: u16,
+        params: Params<any_pointer::Owned>,
+        mut results: Results<any_pointer::Owned>,
+    ) -> Promise<(), Error> {
+        let params = pry!(params.get());
+        let mut requests = vec![];
+        for worker in self.pool.workers() {
+            requests.push(pry!(Self::request(&worker, method_id, params)));
+        }
+        Promise::from_future(async move {
+            let mut responses = vec![];
+            for result in future::join_all(requests.into_iter().map(|x| x.send().promise)).await {
+                match result {
+                    Ok(response) => responses.push(response),
+                    Err(e) => error!("Unable to list containers of worker: {}", e),
+                }
+            }
+            let mut containers = vec![];
+            for response in &responses {
+                let response = Self::typed::<conmon::list_containers_results::Reader>(response)?;
+                containers.extend(response.get_response()?.get_containers()?.iter());
+            }
+
+            let list = results
+                .get()
+                .init_as::<conmon::list_containers_results::Builder>()
+                .init_response()
+                .init_containers(containers.len() as u32);
+            for (i, container) in containers.into_iter().enumerate() {
+                list.set_with_caveats(i as u32, container)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Subscribe the event listener to all running and future workers.
+    fn subscribe_events(&self, params: Params<any_pointer::Owned>) -> Promise<(), Error> {
+        let pool = self.pool.clone();
+        Promise::from_future(async move {
+            let req = params
+                .get()?
+                .get_as::<conmon::subscribe_events_params::Reader>()?
+                .get_request()?;
+            capnp_err!(pool.add_listener(req.get_listener()?, req.get_id()?).await)
+        })
+    }
+
+    /// Shut down all workers before the main server, summing up their drained containers.
+    fn shutdown(
+        &self,
+        method_id: u16,
+        params: Params<any_pointer::Owned>,
+        mut results: Results<any_pointer::Owned>,
+    ) -> Promise<(), Error> {
+        let params = pry!(params.get());
+        let request = pry!(Self::request(&self.local, method_id, params));
+        let mut worker_requests = vec![];
+        for worker in self.pool.workers() {
+            worker_requests.push(pry!(Self::request(&worker, method_id, params)));
+        }
+        Promise::from_future(async move {
+            let mut responses = vec![];
+            for result in
+                future::join_all(worker_requests.into_iter().map(|x| x.send().promise)).await
+            {
+                match result {
+                    Ok(response) => responses.push(response),
+                    Err(e) => error!("Unable to shut down worker: {}", e),
+                }
+            }
+            responses.push(request.send().promise.await?);
+
+            let (mut exited, mut killed) = (0, 0);
+            for response in &responses {
+                let response = Self::typed::<conmon::shutdown_results::Reader>(response)?;
+                let response = response.get_response()?;
+                exited += response.get_exited_containers();
+                killed += response.get_killed_containers();
+            }
+            debug!("Shut down workers and main server");
+            let mut response = results
+                .get()
+                .init_as::<conmon::shutdown_results::Builder>()
+                .init_response();
+            response.set_exited_containers(exited);
+            response.set_killed_containers(killed);
+            Ok(())
+        })
+    }
+
+    /// The typed results of a forwarded call.
+    fn typed<'a, T>(response: &'a Response<any_pointer::Owned>) -> capnp::Result<T>
+    where
+        T: capnp::traits::FromPointerReader<'a>,
+    {
+        response.get()?.get_as()
+    }
+}
+
+impl capability::Server for WorkerRouter {
+    fn dispatch_call(
+        &mut self,
+        interface_id: u64,
+        method_id: u16,
+        params: Params<any_pointer::Owned>,
+        results: Results<any_pointer::Owned>,
+    ) -> Promise<(), Error> {
+        if interface_id != conmon::_private::TYPE_ID {
+            return Promise::err(Error::unimplemented(format!(
+                "interface {} is not implemented",
+                interface_id
+            )));
+        }
+        match METHODS.get(method_id as usize).copied() {
+            Some("version") | Some("status") | Some("capabilities") => {
+                self.forward_local(method_id, params, results)
+            }
+            Some("setLogFilter") | Some("reloadConfig") => {
+                self.broadcast(method_id, params, results)
+            }
+            Some("createContainer") => self.create_container(method_id, params, results),
+            Some("createContainers") => self.create_containers(params, results),
+            Some("listContainers") => self.list_containers(method_id, params, results),
+            Some("subscribeEvents") => self.subscribe_events(params),
+            Some("shutdown") => self.shutdown(method_id, params, results),
+            Some(method) => self.forward_container(method, method_id, params, results),
+            None => Promise::err(Error::unimplemented(format!(
+                "method {} is not implemented",
+                method_id
+            ))),
+        }
+    }
+}
+
+/// The dispatch of the router, which is required to create a typed `Conmon` client from it.
+pub struct WorkerRouterDispatch(WorkerRouter);
+
+impl Deref for WorkerRouterDispatch {
+    type Target = WorkerRouter;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for WorkerRouterDispatch {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl capability::Server for WorkerRouterDispatch {
+    fn dispatch_call(
+        &mut self,
+        interface_id: u64,
+        method_id: u16,
+        params: Params<any_pointer::Owned>,
+        results: Results<any_pointer::Owned>,
+    ) -> Promise<(), Error> {
+        self.0
+            .dispatch_call(interface_id, method_id, params, results)
+    }
+}
+
+impl FromServer<WorkerRouter> for conmon::Client {
+    type Dispatch = WorkerRouterDispatch;
+
+    fn from_server(s: WorkerRouter) -> Self::Dispatch {
+        WorkerRouterDispatch(s)
+    }
+}
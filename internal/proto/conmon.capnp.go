@@ -1209,12 +1209,12 @@ type Conmon_AttachRequest capnp.Struct
 const Conmon_AttachRequest_TypeID = 0xdf703ca0befc3afc
 
 func NewConmon_AttachRequest(s *capnp.Segment) (Conmon_AttachRequest, error) {
-	st, err := capnp.NewStruct(s, capnp.ObjectSize{DataSize: 0, PointerCount: 3})
+	st, err := capnp.NewStruct(s, capnp.ObjectSize{DataSize: 8, PointerCount: 3})
 	return Conmon_AttachRequest(st), err
 }
 
 func NewRootConmon_AttachRequest(s *capnp.Segment) (Conmon_AttachRequest, error) {
-	st, err := capnp.NewRootStruct(s, capnp.ObjectSize{DataSize: 0, PointerCount: 3})
+	st, err := capnp.NewRootStruct(s, capnp.ObjectSize{DataSize: 8, PointerCount: 3})
 	return Conmon_AttachRequest(st), err
 }
 
@@ -1304,12 +1304,20 @@ func (s Conmon_AttachRequest) SetExecSessionId(v string) error {
 	return capnp.Struct(s).SetText(2, v)
 }
 
+func (s Conmon_AttachRequest) OverflowPolicy() Conmon_AttachRequest_OverflowPolicy {
+	return Conmon_AttachRequest_OverflowPolicy(capnp.Struct(s).Uint16(0))
+}
+
+func (s Conmon_AttachRequest) SetOverflowPolicy(v Conmon_AttachRequest_OverflowPolicy) {
+	capnp.Struct(s).SetUint16(0, uint16(v))
+}
+
 // Conmon_AttachRequest_List is a list of Conmon_AttachRequest.
 type Conmon_AttachRequest_List = capnp.StructList[Conmon_AttachRequest]
 
 // NewConmon_AttachRequest creates a new list of Conmon_AttachRequest.
 func NewConmon_AttachRequest_List(s *capnp.Segment, sz int32) (Conmon_AttachRequest_List, error) {
-	l, err := capnp.NewCompositeList(s, capnp.ObjectSize{DataSize: 0, PointerCount: 3}, sz)
+	l, err := capnp.NewCompositeList(s, capnp.ObjectSize{DataSize: 8, PointerCount: 3}, sz)
 	return capnp.StructList[Conmon_AttachRequest](l), err
 }
 
@@ -1321,6 +1329,55 @@ func (p Conmon_AttachRequest_Future) Struct() (Conmon_AttachRequest, error) {
 	return Conmon_AttachRequest(s), err
 }
 
+type Conmon_AttachRequest_OverflowPolicy uint16
+
+// Conmon_AttachRequest_OverflowPolicy_TypeID is the unique identifier for the type Conmon_AttachRequest_OverflowPolicy.
+const Conmon_AttachRequest_OverflowPolicy_TypeID = 0xbc373f523b97b726
+
+// Values of Conmon_AttachRequest_OverflowPolicy.
+const (
+	Conmon_AttachRequest_OverflowPolicy_dropOldest Conmon_AttachRequest_OverflowPolicy = 0
+	Conmon_AttachRequest_OverflowPolicy_block      Conmon_AttachRequest_OverflowPolicy = 1
+	Conmon_AttachRequest_OverflowPolicy_disconnect Conmon_AttachRequest_OverflowPolicy = 2
+)
+
+// String returns the enum's constant name.
+func (c Conmon_AttachRequest_OverflowPolicy) String() string {
+	switch c {
+	case Conmon_AttachRequest_OverflowPolicy_dropOldest:
+		return "dropOldest"
+	case Conmon_AttachRequest_OverflowPolicy_block:
+		return "block"
+	case Conmon_AttachRequest_OverflowPolicy_disconnect:
+		return "disconnect"
+
+	default:
+		return ""
+	}
+}
+
+// Conmon_AttachRequest_OverflowPolicyFromString returns the enum value with a name,
+// or the zero value if there's no such value.
+func Conmon_AttachRequest_OverflowPolicyFromString(c string) Conmon_AttachRequest_OverflowPolicy {
+	switch c {
+	case "dropOldest":
+		return Conmon_AttachRequest_OverflowPolicy_dropOldest
+	case "block":
+		return Conmon_AttachRequest_OverflowPolicy_block
+	case "disconnect":
+		return Conmon_AttachRequest_OverflowPolicy_disconnect
+
+	default:
+		return 0
+	}
+}
+
+type Conmon_AttachRequest_OverflowPolicy_List = capnp.EnumList[Conmon_AttachRequest_OverflowPolicy]
+
+func NewConmon_AttachRequest_OverflowPolicy_List(s *capnp.Segment, sz int32) (Conmon_AttachRequest_OverflowPolicy_List, error) {
+	return capnp.NewEnumList[Conmon_AttachRequest_OverflowPolicy](s, sz)
+}
+
 type Conmon_AttachResponse capnp.Struct
 
 // Conmon_AttachResponse_TypeID is the unique identifier for the type Conmon_AttachResponse.
@@ -2773,136 +2830,143 @@ func (p Conmon_setWindowSizeContainer_Results_Future) Response() Conmon_SetWindo
 	return Conmon_SetWindowSizeResponse_Future{Future: p.Future.Field(0, nil)}
 }
 
-const schema_ffaaf7385bc4adad = "x\xda\xacX}l\x14\xd7\x11\x9fy\xef\xd6k\xc2\x9d" +
-	"\xd7\xcb:*\xb1\x8a\xdc \x88\x14Pc\x82\xe9G\x10" +
-	"\x91\x0d\xc6\"NI{\xef\x0cE\xe1K,w/\xf6" +
-	"\x91\xbb\xdd\xf3\xee\x1e\xb6I\x10\x90\x14UJ\xdb\xb4\x89" +
-	"\x88Z\xa3\"\xe16\x89\x82\x0b%\x1f\x85&\xb4\x8a\xda" +
-	"&(\x0d-mm\xa9\xad\x1a)\xfd\xa24\x04\xb5i" +
-	"\xa2\"\xd5T\xa8[\xbd\xdd\xdb\x8f;\xbb\x8a?\xfa\x9f" +
-	"o\xf6\xb7\xf3f\xe6\xcd\xfc~\xb3^\xf1\xa2\xd4\x91\xb8" +
-	"3\xb5\xe5& lP\xaas\xed?\x0dY\xcf\x1e\xdb" +
-	"\xf0(\xa8\xcb\x11@B\x19\xa0M\x95\x16\x13@\xedv" +
-	"\xa9\x1d\xd0=\xfc\xeeg\xcfn~\xf4\x1f\xc7\xe3\x80n" +
-	"i\xa5\x00\xe8\x1e`x\xdb\x95\x07\xbb\xba\x95o\x09\x80" +
-	"\xfbA\xdb\x03o\x0f_\xf9\xd4\xf7!!p\x87\xa5\xab" +
-	"\xa8\x8dH2Pw\xc9\xe9\xd7\xc6\x1e[\xd3:\x1aw" +
-	"\xb3_Z \xdc<\xe5\xb9\x19\xd8\xf5\xd3\xd3\xfb\xd8\xe5" +
-	"\x93S\xb89#\x8d\xa36\xe6\xb9Y5\xf2\xd2\xd9\xc7" +
-	"\xdf\x1b\xfc.\xb0\xe5H\"\x98\xef\xef\x944\x8a\xday" +
-	"\xe9#\x00\xdaE\xe94\xa0\xfb\xd0\xd8\xd5\xe7\x1e\xff\xd2" +
-	"\xda3\x02\x8d\xb5h^G\x88v\xa8N\x06\xd0\xf6\xd7" +
-	"\x09t\xf8\\]B\xddS\xa7^\xdf\xf6\xe9\x7f\x8d\xba" +
-	"\x00\xd8v\xb3\xbc\x15\xb5\xdb\xe57\x00\xdaR\xf5_D" +
-	"m\xd1<\x19\xc0\xbdp\xf6\xc4\xea\x7f_\x1a8W\xeb" +
-	"\xbc^8\x97\xe6- \xdaR\x81k\xbbu\x9e\x8b\x80" +
-	"n\xe3\xb6_\xde\xfd\xb7\x9d\x7f=\x1f/\xc0\xc8\xfcf" +
-	"Q\x80W\xe6\x8b\x02\xbc\xa3\xff\x80t],\xbc\x11\x07" +
-	"\xbc=\xff^\x01\xb8\xee\x03\xfe\xf2\x9f=\xbd\xa5\xd6\x9f" +
-	"\xfb\x00\xaf2\xb7$\xc7\x11\x12\xee\xc4\xcd\xaf~\xbdy" +
-	"\xcd\xb9_\xc4_M%=\xdfK\x93\xe2\xd5\xe6\xb5c" +
-	"\xab\x14c\xc3\xaf\xaa\x8b[\xb9\xcc\xe4\x9fQ\xe3IQ" +
-	"\x07\xdd\x03\xdf8\xbc\xe6\xe0\xa2E\xbf\xf9]mbD" +
-	"\xa0\x0f%\x97\x11m\xc4C\x1fK\xbe\x03\xe8\x1e]>" +
-	"P\xda\xb9{\xf5\xefk\xd0^xC\xa9f\xa2\x0d\xa7" +
-	"\x04\xf8\xa9\x94\xe7z\xf5\x8dW\x8f\xaf)\xfd\xa1&\x0e" +
-	"*\xc0\xaf\xa4.\xa0\xf6k\x0f<\x96\x12\xf7\xb1\xb9\xb4" +
-	"A\xbd-\xd3\xf0\xc7xV\xfd\x0d\x19\x91\xd5W\x1a\x84" +
-	"\xb7\x15\x0fm8\xb13\xaf]\x8a\x03^hx\x0b\x01" +
-	"\xb5\xf3\x1e\xe0\x93\xdak\xcf\x1bO\\\xbd\x1c\x07\xbc\xdb" +
-	"\xb0Lx@E\x00~\xbc\xad-\xfd\xdbK\xb7\xbd\x0f" +
-	"\xea'H\xd4[\x80m\xb7*\xe3\xa8\xdd\xad\x88`\xee" +
-	"RZ\x00\xdd\xb1\xf7ZN\xfe\xec\xf2g\xfeY[\x14" +
-	"I\xf8\xbcKy\x0b\xb5\xcd\x02\xdd\xc6\x94-\xe2\xb6\x9f" +
-	"\xed\xff\xf6\xd7&\x16\xab\xd7j\xfb\xd4\xab\xe1\x89\xc6\xc5" +
-	"D{\xb3Q8?\xdf(j\xf8\xf2\xd1#_}}" +
-	"\xe5\x86kU\x89\xa8\xdep\xbc\xa9\x8a8\x7f\x88\xa3\xf3" +
-	"\xb7\xef\xb92Q\x95\x88\xea'\xb2@\x00&F\xbe\xd3" +
-	"v\xf0\xe2K\xd7\xa7\x98\x9e\xa5\x0bn\"Z\xd7\x02\x19" +
-	"Z\xdd\xaci\x14M\xe3\xe3\x96l\xb7f\xcdb\xd14" +
-	"ZK\x96\xe9\x98\xad\xbe\xfd\x8e\xac^2J\xab;\xfd" +
-	"\x1f|\x90g{\x86\x8cl\xa7i8z\xde\xe0\xd6\x92" +
-	"\xb4n\xc9z\xd1f\x09\x9a\x00H \x80\x9aZ\x07\xc0" +
-	"\xea)\xb2&\x82\x07,\xde_\xe6\xb6\x83\x8dQ\xf2\x80" +
-	"\xd8\x088\xa3c-n\x96\xb8\xb1\xd1\xec\x8d\xce\xcd\xf0" +
-	"\x16\xbb\\p\xaa\x0e\xbe\x17\x80%)\xb2\x85\x04]\x8b" +
-	"\xdb%\xd3\xb09\x00`cDG5\x87\xd7M\xe3\xf0" +
-	"Lpx\x86\xdb%E\xf8L\xe3\xcc\xc2\xd7\x1dG\xcf" +
-	"\xf6U\xd5L/\xe24j\x16N\xc6,\xc2^\xeb\x1d" +
-	"\x9a\xf1\xcb\x80U1K\xd3x}\xa3\xd9\xbb\xdeR\xf2" +
-	"{\xb9\xc5\x12\x18\x9f\x09\\\xa6l\x1a*q\x96\x0c\x83" +
-	"\xefZ\x06\xc0:(\xb2\x8d\x04\x11\x9bP\xd8\xba\x85m" +
-	"=E\x96&\xa8\x12lB\x02\xa0\xde'\xb2\xbc\x87\"" +
-	"\xdbDPq\x86J\x1c\x95\xc81 *\x80JIw" +
-	"\xfa0\x09\x04\x93\x80\x07\x8a\xfa`O~\x1f\xc7y@" +
-	"p\xde\x0c{\xa6\x87;[\xf2F\xce\x1c\x10\x1e2~" +
-	"I!\x8d\x18\x0f\xbcy\x8a\xc0WN\x15\xf8\xea(p" +
-	"\x9a\xcf\x05\xf1\xb5\x0c\xe4sN\x1f\xca@P\x06l\xef" +
-	"\xe3\xf9\xde>'\xf8\x19\x06\x9b\xf8\xb0`\xa9i\xb0\x15" +
-	"\x18c\x13\xf5\xfeG\"!Q\xef?\x17\x91\x90\xba#" +
-	"\x13q\xab\xba\xe3'\xd1T\xa9\xfa\x85\x88\xa3\xd5\xfcx" +
-	"\x8cU\xfb\xad\x98\x8e\xf6\xef\x8b\xf1~\xffc1\xa1." +
-	"?\x19I\xa3:4\x1ac\x8f\xfd/\xba\x9f\xe7\x96\x9d" +
-	"7\x8d\x0c\x0d\xc6\xaa\xd3\xe2\xba\xc3\xc3\x9e\xce\xb4\xfb\x15" +
-	"v\xbd\xbe\xc9\xef\xe5\x80\x96\x1b`\xa4\x00\x14\xbc\xdcU" +
-	"K#\xc1\xfd\x80\x1b<\"\xb1g\x95\x16v\x83\x96\x86" +
-	"\x16\xff\xac\xf0w\xbb\xef\xd7\x0dF\x15{#\x87q[" +
-	"\xe0(\xe8\x0d\x0c\x9aC\xf1\xfc\xd5\x9a\xed\x16\xcf-[" +
-	"H%\x80Pi1P\x18\xf5\x85u@\xd4gd\x8c" +
-	"\xb8\x1a\x03\xd1U\x87\x1f\x01\xa2>!#\x09\x97)\x0c" +
-	"\xf8Z=\xfc$\x10\xf5\x90\x8c\xd1\x02\x84\xc1\"\xa0\x96" +
-	"\xc5{E\x19\x13\xa1Na\xb0l\xa9\xfaQ \xea\x0e" +
-	"\x19\xa5p-\xc0@\x0eUv\x0e\x88z\x9f|`\xaf" +
-	"\x7fQ\x1d\xe8f+\xd5\xc7J\x1d\xa1\x03\xdd\x80\xbf1" +
-	"\xa8.Z\x1d\xe8\x06\xfc\x14GZa\xd9*P\xca\x05" +
-	"\xd4\xae*Q\xa7i\xb4\xfb\xaft\xe0LI\xb1\xb6\x81" +
-	"\xbc\x0bCGL\xe8\x92pB\xff.&\xf4\x0aEv" +
-	"\x8d\xa0\x1a\x8c\xe8\x07[\x01\xd8\xfb\x14\xd9\x0d\x82H\xfc" +
-	"\x09\xbd.\xb8\x7f\x82bO\x02\x09\xaa\x944!\x05\xd0" +
-	"\x103\x00\x19\xa4\xd8\xf3QaN\xd0&L\x00h\xb7" +
-	"\xe0\x1e\x80\x9e\x85\xc2\xbeJ\xd8\xa5D\x13J\x00\xda\x9d" +
-	"\xb8\x15\xa0g\x85\xb0o\x14\xf6:\xa9\x09\xeb\x00\xb4n" +
-	"\xcf~\x8f\xb0\xe7\x84]\xaekBo3\xf2\xec\xbb\x84" +
-	"\xfdaa\xaf\x97\x9b\xb0\x1e@\x1b\xc2\xdd\x00=\x83\xc2" +
-	"~\x04\xab\x18\xc3\xdd]6r\x05\x9e\xd6\x81F4\xe7" +
-	":\xdc*\xe6\x0d\xbd \xb4\x0a\x81\xa0\xd8\x15\xf8`\xde" +
-	"I\xebN\x1f\xa0\x8d\x0d\x80i\x8a\x1e\xbc\x01\xd05\xcd" +
-	"b\x97x\x0a\x8a\xee\xf4MzZ\x08\x06\x90Z\xe1\xb3" +
-	"\xc6\xf8\x1a\xe3\xa1\xb2\x05\xae\x1b\xe5R'\xd0b\xae\xd6" +
-	"Co\xc1\xdc\xad\x17\xd6Z@{'y\x17\xd7\xaa\x1b" +
-	"\xb9\xb5 [S=\x9c\xbd&f\xb8].\xd0\xe9\xea" +
-	"y\xc8g5\xc2X?\x8d\x93\xed\xb80\xd4\x88\xb2\x0d" +
-	"\xf0\xe1\xaa\x1c\xb2\xe4,T\xb92\x9e\xc1\x0a0\xa3\x92" +
-	"e\xab'f\x86%\x0becn+P\x7fY\xe6\xb6" +
-	"7\xa5\xb1S\x9b\xa3:\xc5\x9b}&\xc9M!\x09\xd1" +
-	"\xb6\xc5\x1a\xc3\xb3t\x91\xe1.\x8a\xac\x10iv^\xc8" +
-	"s\x8e\"+\xc54\xbb(\x8c}\x14\x99#\x08\xe1c" +
-	"\x1e!\xa8\xfd\xe2\xed\x12E\xf60\xf1G\xac\xd3\xccy" +
-	"\xf5I\x00\xc1\x04`\xbb\xed\xe4\xcc\xb2\x83) \x98\xf2" +
-	"\x7fr\xcb\x0a~\xbaN\xbe\xc8s\x9f+;\xf1A\x9d" +
-	"\x13\xe7\x89\x0b\xa2~\x8a\xb1r\xee\x89]b\xb6\x02\x06" +
-	"\xc5J\xe7sX\x0f\x04\xebg\xb9\x09V\x14\xf1\x7f\xef" +
-	"@!\xc3vo\x8d\xf6\x1d\x95T(\x96Y\x00,M" +
-	"\x91m\xaf\xbee\xdb\xcc>\xc8\x9d\x1aJ\xf3\x84\x86\xdb" +
-	"6\xb4\xe4M\xa3{rK\xccaP\xbd\xb6wp\x9a" +
-	"m\x1fn1s\x18\xd6\x99\x0dZ\xb8\xc6\xfd\x7f>t" +
-	"\xd2\xbabM\xeb\x03+\\\xebf\x91i\xb0\xb5Yw" +
-	"l\x1a*\xa1\xdf\x8f\xde\xa5K\xe3\x00a\x0f\x12+S" +
-	"6\xc4\x0ct\x1b\x0e\xb7\x1e\xd0\xb3\xc8gtJ\xb0D" +
-	"\xc6\xdb~a\x98\xd6\xb0H\xeb\x08Ev<\xd6\x89\xc7" +
-	"\x16\x03\xb0oPdO\xc7:qD\x8c\xf67)\xb2" +
-	"\xe7\xc4hS\x7f\xb4\x9f\xc9\x00\xb0\xa7)\xb2\xe7\x85\xd2" +
-	"'<\xa5WO\xed\x06`')\xb2\x97\x09\xa2\xe4\xa9" +
-	"\xbczF\x00\xbfG\x91\xfd\x88`\xb0.\x05\xfd);" +
-	"zo\xf0w\xbb\xc8'\xef\xc4\xa4;_\xc8\xad\xd7\x1d" +
-	"@\x1e\xda\xac\xb2\xed\x88\xac@\x8e9qK\x96\x99\xe5" +
-	"\xb6\xdd\x0d8y`g\xc9\x85\x11\xed\xc6\xa8P\x8c\xee" +
-	"v\x8a\xac/\xa2B\xbeu**\\W\xa1\xc2/\x88" +
-	"zu\xf8\xf5:$:\xf8 E\xf6\xe5\xeaq\x16\xf7" +
-	"k\x96\x9d\x1e\xa0<\x1b|x\x1d\xa8\xc8~\xad\xe0O" +
-	"\xb5\xb8\xccE\xd1\xa6\xfda\x1c~\x1d\xcdb\xc6&\xff" +
-	"\x0f#\xc3me\xfa\xffK\x08\xbf\xb2fqv\xcdG" +
-	"i\xe06\x8d\xf8\xdf\x00\x00\x00\xff\xffg\x85U\x84"
+const schema_ffaaf7385bc4adad = "x\xda\xadX\x7fl\xd4\xf6\x15\xff>\xfb\x1c'\xe5\x12" +
+	"\xc7q\xa6\xd1hQ&\xc4\xf8\x034\x02\x84\xad#\xa3" +
+	"J\x02Di:(w\x97\xb2\xaa\xd0 \x9c;\x93\x1c" +
+	"\xbd\xb3/\xb6\xaf\xf9Q\x10\xb4\x1d\x9a\xd6m\xdd\xa8\x90" +
+	":\xd0\x90\xa0k\xab\x92\x91\xd1\xae\x83V\xac\xaa\xd6\xb5" +
+	"U76\xb6\x81\xb4ML\xea\xb6\x8e\xb1R4\xba\xa2" +
+	"!-\x99\xe8\xbc\xf7\xb5\xefk\xfb\x8e\x93z\x97\xf4\x8f" +
+	"H\xe7\xcf\xf7\xf9\xfd\xfa\xbe\xf7y\xcfYqU\xe8\x8e" +
+	"\xac\xac?y\x1b\xe1\xe2\xbb\x85\x1a\xc7zw\xc2|\xee" +
+	"H\xdfcD^\x06\x84\x08 \x12\xd2q\xa7\xb0\x88#" +
+	"\xa0l\x11\xba\x088\xfb\xdf\xbf\xe7\xf4\x96\xc7\xfeu4" +
+	",\x90\x17VQ\x81o\xbb\x02\x87\xb6]y\xb0\xb7_" +
+	"z\x9a\x0a8\xd7;v\xbes\xe8\xca\x1d/\x93\x08\x95" +
+	"{Q\xb8\x0a\xca9A$\xbc\xb3\xf8\xe4\x1b\xe7\x1f_" +
+	"\xdb>\x15Vs\\h\xa2j^w\xd5\x8c\xed\xf8\xc5" +
+	"\xc9\xc9\xf8\xe5\x13e\xd4\xbc+\\\x00\xe5#W\xcd\xea" +
+	"c/\x9d~\xe2\x83\xf1\x1f\x91\xf82\xe0\x021O\xdf" +
+	"Ea\x0a\x94\xeb\xc2\xa7\x09Qf\x85\x93\xa8\xf3\xe1\xf3" +
+	"W\x9f\x7f\xe2\x9b=\xa7\xa84\x94J\x1f\xa8\xe18e" +
+	"\xba\x06\x7f*\xc7k\xa8\xb4\x7f./\xe6\x9d\xe9\xe97" +
+	"\xb7}\xe9?S\x0e!\xd0\xd1#n\xc5l\x88o\xe3" +
+	"Kkj\xbf\x0eJ\x7f\x1d\xbe\xe4\x9c=}\xbc\xf3\xbf" +
+	"\x97\xc6\xce\x94*\xaf\xa5\xcaW\xd65qJ\x9c\xcau" +
+	"l\xaas\x00\xb5/y\xf9\xa9/'\xba\xeex\x95\xc8" +
+	"k8\xe7f\xe7\xcd\xd7\x8e\xae\xcd\xfd\x85j?\xb7`" +
+	"\x1d\xa7\\[@\xfdx\x7f\xc1f\x94l\xdc\xf6\xdb;" +
+	"\xff\xb9\xfd\x1fo\x85S%D[h\xaaZ\xa34U" +
+	"\xef\xa9?\xe5z\xcfe\xde\x0e\x0b\xf4D\xef\xa6\x02\x83" +
+	"\x9e\xc0\xdf\xff\xb7k8\xd7\xfekO\xc0\xcd\xe1\x9e\xe8" +
+	"\x05 \x11g\xe6S\xaf=\xd5\xb2\xf6\xcco\xc2\xaf\x8e" +
+	"z\xba\xbf\xe1\xbe\xda\xd2s~\xb5\xa4\xf7\xfd\xae\xf8\x1a" +
+	"<\xc1\xe9\xe8\xdf@\xf9e\x94z\xfa\x96+|s\xff" +
+	"\xda}\xad\xad\x7f\xb8X\x9a\x02\x8eJ_\x8e.\xe5\x14" +
+	"\xa1\x9eJC\xfd{(}x\xd9Xn\xfbP\xe7\x9f" +
+	"K\xa4]\xf7\xde\xa9o\xe1\x94\x8f\\\xe1\xd9zW5" +
+	"\xcbP\x89j\x9eJ\xb76\x9c\x05eM\x03\xbd\xe8\xde" +
+	"\x06\xaazK\xaeO^\x92h\xf8k8\xac\xcfI\x09" +
+	"\x1aV\x8fD\xd5\xadx\xb8\xef\xf8\xf6\xb4r),\xa0" +
+	"I\x7f\xc2\x8bQ&\\\x81/*o\xbc\xa0\x1f\xb8z" +
+	"9,pDZJ5\x9cr\x05^\xdf\xd6\x11\xfb\xe3" +
+	"\xa5%\x1f\x12\xf9\x0b\\P\x86x\x7f\xbf\x97\xb0:\xaf" +
+	"K\xd4\xf5kR\x1bJ\x9e\xff\xa0\xed\xc4\xaf.\x7f\xe5" +
+	"\xdf\xa5\xae\x0bT\xe754\xaa\xd45\xbaw\xdax\x1f" +
+	"-\x8c\xe7F\x7f\xf0\xdd\x99E\xf2\x8d\xd2\x92v\x938" +
+	"(/\xe2\x94=2U>!\xd3H_9|\xf0;" +
+	"o\xae\xea\xbbQ\x14H\x93\xdbG{\x9a\xa8\x9f\xaf\xc2" +
+	"\xd4\x82\x07v]\x99)\x0a\xa4\xc9\x0b\xc4\x15\x989\xf6" +
+	"\xc3\x8e}\xe7^\x9a-\xd3h\x17\x9bn\xe3\x94\xd9&" +
+	"\x91\xb4;IC\xcf\x1a\xfa\xe7M\xd1jO\x1aY\xfc" +
+	"\xd9\x9e3\x0d\xdbh\xf7\xf0\xe5I5\xa7\xe7:\xd7{" +
+	"\x0f\xda\xb8\x96\x1c\x98\xd0\x93\xf8h\xabi]3\x17\xc7" +
+	"TST\xb3V<\xc2G\xb0\xfe\xd0\x0f\xb9~\x1d!" +
+	"\xf1Z\x1e\xe2\xcd\x1c\xec5\xb5\xd1\xbcf\xd9\xd0\x18\x04" +
+	"O\x00\x1a\xd1\xb9j\xcc\x9a\x9a\x91\xd3\xf4\x8d\xc6p`" +
+	"7\xa1\xb5Y\xf9\x8c]d\xf8n4\x1cE\xc3\x0b9" +
+	"pL\xcd\xca\x19\xba\xa5\x11\xb4\xd7\x180W\x89\xf1\x9a" +
+	"\x0a\x8c'\x98\xf1\x04\xaa\x94\xa8\xce\x18T\xe7\xbej\xdb" +
+	"jr\xa4(gj\x16*\xc8Y\x88<\xaav\xbb\xc7" +
+	"5\x9a\xf0\xd2\x00E>\x0b\x15\xbc\x8e\xf1n0\xa5\xf4" +
+	"C\x9a\x19\x8f@\xb8'`\xa9t\xefDN\xc3D3" +
+	"\xe7{\x97\xa2\xf3\xdd\xe8\xfcF\x0e\x00\x9a\x81b\xfd\x14" +
+	"\xdb\x80X\x8c\x03\x99C\x90Cp\x13\x8d\xf2.\x04\xef" +
+	"\xe5@\xb2Q\x09H\x81b\x0cQ\" \xe5T{\x04" +
+	"\xa2\x84\xc3?\xd8\x9bU\xc7\x07\xd2\x93\x1a\xd4\xe1s]" +
+	"\x9553\xa0\xd9\xf7\xa5\xf5\x941F5$\xbc\x94\x12" +
+	"\xccB\xd8\xf1\x962\x8e\xaf*\xe7xg\xe08\x9fN" +
+	"1\xff\xda\xc6\xd2)\xf4V\xc4'\x91@\xd7\x88\x96\x1e" +
+	"\x1e\xb1\xd9\xa3\xefl\xe4\xe3\x9c\xe5\x0d=\xbe\x02Bl" +
+	"\"\xdf\xffh0s\xe4\xfb\xcf\x04$$\x0f&\x02r" +
+	"\x95\x07\x7f\x1et\x95\xac\x9e\x0dHZN_\x08jG" +
+	"\x1e5C#wt2D\xfc\xa3\x8f\x87fz\xfe\xc9" +
+	"`\x8a\xca\x13S!\xf6\xd8\xf3c\xe7\xab\x9ai\xa5\x0d" +
+	"=\xc1\xb3\xb6Zoj\xaa\xad\xf95\x9d\xe8\xf22\xec" +
+	"\xb8u\x83eC\xc0t\x98\x8c\xc0\x84\xd8\xcb\xbd\xa54" +
+	"\xc2\xee\x878\xec\x88\x0b\x9d\x15J\xd8a%M\xda<" +
+	"[\xfes\x97\xa7\xd7a\xad\x0a\xc3\x81\xc20\xc6\x14\xb1" +
+	"\xda\x00V\x1c\x92\xab\xaf\x14\xb6\xda\\\xb5\xf1\x85\xbc\x80" +
+	"\x8a\xd8\xa8\x056a\xe4\x17\xd7\x11N~V\x84\x80\xab" +
+	"\x81M]\xf9\xd0\xa3xv@D\x9eg{\x170\xbe" +
+	"\x96\xf7?\x89g\x8f\x88\x10\xecJ\xc06\x019O\xdf" +
+	"\xcb\x8a\x10\xf1\xe7\x14\xb0\xbdLV\x0f\xe3\xd9\xa0\x08\x82" +
+	"\xbf\x17\x00\x1b\x87r\xfc\x0c\x9em\x12\xf7>\xe4]T" +
+	"7\xd6_!\xfbP\xc8#A\x8c\xf17\xb0\xec\x82\x89" +
+	"(\xe3\xa7\xb0\xa4\xe9\xa7\xad \xcakT\xd4*J\x11" +
+	"\x1euy\xaftC\xb5\xa4XZ@\xee\x85\x81M;" +
+	"t\xb1\xdf\xa1\xd7h\x87^\xc1\xc6\xbb\x81\xdd\xc8Z\xf4" +
+	"\xfaV\x04?D\xf0&\xb6-\xe7u\xe8,\xe5\xfe\x19" +
+	"\x1e\x06\"\x80\x92<\xa2<\xddF AH\x02\x10\xfe" +
+	"\x0c\x85#|3\xa0f\xe5v\xd8E\xc8\xc0B\x8a\xaf" +
+	"\xa6\xb8\x10i\x06\xbcae%\xa0\xe6\x81\x15\x14\xdfH" +
+	"\xf1\x1a\xa1\x19j\x10\xefw\xf1\xbb(\x9e\xa2\xb8X\xd3" +
+	"L\x87\xad\xa2\xba\xf8\x0e\x8a\xef\xa6x\xad\xd8\x0c\xb5t" +
+	"~\xc3\x10\xe2\xe3\x14?\x08E\x8c\xe1\x0c\xe5\xf5TF" +
+	"\x8b\xa9\x84\x0fh\xce\xb153\x9b\xd6\xd5\x0c\x9dU\x80" +
+	"\x18\xdd\x15\xb4\xf1\xb4\x1dC.$`A\x03\x81\x18\x0f" +
+	"\xae8\xfet\x0c#\xdbKO\x89\x84\xe7\xb7\x9cfX" +
+	"\x03\xf2\xa6\x7f\xd6\x18^c\\\xa9dFS\xf5|n" +
+	"=\xe1\xb3\xa9R\x0d\xc3\x19cH\xcd\xf4\x98\x84\x1f\xbe" +
+	"E;\xbdVUO\xf5\x10\xd1,w\xe8]\x7fm\x15" +
+	"\xe3\xc9m\xd3\xe5\x9b\xb1lwf\x8c\xb1\x98\x91I'" +
+	"'<\x9av\xef\xb5u+\xf5X\xbe\x1dY\x198Y" +
+	"\xc6''e\x1a\xb9\xcd\x99\x14\x06h\xd9mC\x19#" +
+	"\xf9\xa0\x93J[hCG(i\xcfk2c\xcf\xe7" +
+	"3|\xa5[\x85\xcf\xaa%\xe3\xb9\x92\xf8\xad\xf0x*" +
+	"Y\x0d,\xb4\xf8\xb1\xbb\x81\xcf\xd5s\xd8\x0d\x0a$\xc1" +
+	"\x16\x91\xaaR\x96,\xee\xdb*S\xe6\x0f\xaf\xf9-b" +
+	"\xa3y\x11\xb3@\xcb$d\xb5%\xc8S\xb8\xe5\xaa\x09" +
+	"\xae\xcc`\x0av\xbex\xa3oK\xa5\x11\xee@[\x99" +
+	"`sH\xd3%!\x85X.\xb49d)8\x82\xa0" +
+	"Mi\xe9\xb3.-\xc9\xa3\xf4\xed\x1c\x82\xbb9\xaf\xd1" +
+	"\xd7\x1b)7?\x11t9\x82\x9b\x84e\xa7\x8c\xbc\x0d" +
+	"\xf5\xf8X\xef=j\xa6\xc9\x1e\x1d;\x9d\xd5R\x9b\xf3" +
+	"v\x98.\xe6\xc5\xbc\xf4\x82x/\xc4P:w\x85." +
+	"1Y\x10&\x92\x19\xc3\xd4\xd6\xa2\xd5\xda9\xee\xa3\x85" +
+	"\xb9\xecn\x95\xc1\x972L:\x8c\x03H\x97\xc7\x02\xe1" +
+	"t\xd3\xab}\x00}\x19\x09\x8d\x01mk8\xdf\x859" +
+	"\x905\x11\xcc 8\x8ey\xe1\xbdt\xe7'\x11\xb3\x11" +
+	"\xdbW\\\x18\x16R\x87f\x97p\xb1;!5\xcb\"" +
+	"m\xd8 \xfd\x81\xb0Q\xe2\x1d\xae\xae\xbe\xf7\xde\xea\xfa" +
+	"I\xb4\xbf\xdbL6T\xd8L\xfe\x866\x0f\x0a\xa8\xae" +
+	"}\xfd\x15\xf5\x93\xf9\x88\x8b\xa9\x92Y\xd1\xc7\xa3\xbf\xb2" +
+	"\xce!R\xb6\x91\x9a\xcb\xf1\xbb\x05\xbc*wkE\xb8" +
+	"\x80\xd3\x84U6g&\xf2:\xed\xac~\x1d\xc7\xf1N" +
+	"5\x89kb5V\xd8\x82\x1cn\xa6\x85~X\x87h" +
+	"X\x071\xac\xa3\xa1\x02>\xb2\x08\xc1\xef!\xf8L\xa8" +
+	"\x80\x8fQ\xc2\xf8>\x82\xcfS\xc2(T\xf0\xb3\xb8\xc6" +
+	"\xc4\x9fA\xf0\x05\xba\xc5D\xdc-F\x9e\xc6%#~" +
+	"\x02\xc1W\xb0\xd4\x05w\x83\x91OQ\xc1\x9f \xf63" +
+	"L_\xe1\x8aY\x09\x8b\xb6:\xcc~w\xd1x\xd2v" +
+	"h-IgR\x1bT\x9b\x80\xe6cf\xde\xb2iT" +
+	"D\x0c)q0\x03Il\x8f~\x02\xb7\xd2\xc0\x1c\x19" +
+	"6 \xf3\xf2\x1d_\xbe\xe1\x19\xc1\xae+\x10\xec\xd7h" +
+	"\xbe\xba\xbd|=B+x\x1f\x82\xdf*\xeexz\xbf" +
+	"H\xac\x03\x84\xd7\x92\xec\xa3roa\xa5)]f\xca" +
+	"-e\xf3\x99\x93\x15\x7f\xf4\xfb_~s\xe8\xb1[\xff" +
+	"?\x83\x05)U\xfe\x7f\x12\xff\x0br\x0e\xb6K>\xb8" +
+	"\x99Z\xbc\xd7\xff\x03\x88}\xafx"
 
 func init() {
 	schemas.Register(schema_ffaaf7385bc4adad,
@@ -2915,6 +2979,7 @@ func init() {
 		0xb5418b8ea8ead17b,
 		0xb737e899dd6633f1,
 		0xba77e3fa3aa9b6ca,
+		0xbc373f523b97b726,
 		0xc5e65eec3dcf5b10,
 		0xc76ccd4502bb61e7,
 		0xcc2f70676afee4e7,
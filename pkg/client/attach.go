@@ -85,8 +85,28 @@ type AttachConfig struct {
 
 	// The keys that indicate the attach session should be detached.
 	DetachKeys []byte
+
+	// The policy applied by the server if the output queue of this client is
+	// full.
+	OverflowPolicy AttachOverflowPolicy
 }
 
+// AttachOverflowPolicy specifies how the server handles a slow attach client.
+type AttachOverflowPolicy int
+
+const (
+	// AttachOverflowPolicyDropOldest drops the oldest queued output of the
+	// client.
+	AttachOverflowPolicyDropOldest AttachOverflowPolicy = iota
+
+	// AttachOverflowPolicyBlock waits for the client, which applies
+	// backpressure to the container output.
+	AttachOverflowPolicyBlock
+
+	// AttachOverflowPolicyDisconnect disconnects the client.
+	AttachOverflowPolicyDisconnect
+)
+
 // AttachContainer can be used to attach to a running container.
 func (c *ConmonClient) AttachContainer(ctx context.Context, cfg *AttachConfig) error {
 	conn, err := c.newRPCConn()
@@ -114,6 +134,17 @@ func (c *ConmonClient) AttachContainer(ctx context.Context, cfg *AttachConfig) e
 			return fmt.Errorf("set socket path: %w", err)
 		}
 
+		switch cfg.OverflowPolicy {
+		case AttachOverflowPolicyDropOldest:
+			req.SetOverflowPolicy(proto.Conmon_AttachRequest_OverflowPolicy_dropOldest)
+		case AttachOverflowPolicyBlock:
+			req.SetOverflowPolicy(proto.Conmon_AttachRequest_OverflowPolicy_block)
+		case AttachOverflowPolicyDisconnect:
+			req.SetOverflowPolicy(proto.Conmon_AttachRequest_OverflowPolicy_disconnect)
+		default:
+			return fmt.Errorf("unknown attach overflow policy: %d", cfg.OverflowPolicy)
+		}
+
 		// TODO: add exec session
 		return nil
 	})